@@ -1,4 +1,13 @@
+use std::env;
+
 pub fn main() {
     println!("cargo:rerun-if-changed=.cargo/layout.ld");
     println!("cargo:rerun-if-env-changed=VERBOSE_BUILD");
+
+    // the USB host controller driver and its environment, built from
+    // ext/uspi by `make build-all`
+    if env::var_os("CARGO_FEATURE_USB").is_some() {
+        println!("cargo:rustc-link-lib=static=uspi");
+        println!("cargo:rustc-link-lib=static=uspienv");
+    }
 }
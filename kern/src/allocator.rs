@@ -3,8 +3,18 @@ mod util;
 
 mod bin;
 mod bump;
+mod stats;
+#[cfg(any(test, feature = "heap-guard"))]
+mod guard;
+mod zeroize;
 
+pub use self::stats::{Class, Stats, SIZE_CLASSES};
+pub use self::zeroize::{poison, zeroize, Zeroizing};
+
+#[cfg(not(feature = "heap-guard"))]
 type AllocatorImpl = bin::Allocator;
+#[cfg(feature = "heap-guard")]
+type AllocatorImpl = guard::Guard<bin::Allocator>;
 
 #[cfg(test)]
 mod tests;
@@ -17,16 +27,38 @@ use crate::mutex::Mutex;
 use pi::atags::Atags;
 
 use crate::console::{kprint, kprintln, CONSOLE};
+use crate::dma;
 
 /// `LocalAlloc` is an analogous trait to the standard library's `GlobalAlloc`,
 /// but it takes `&mut self` in `alloc()` and `dealloc()`.
 pub trait LocalAlloc {
     unsafe fn alloc(&mut self, layout: Layout) -> *mut u8;
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// Like `alloc()`, with the memory zeroed.
+    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc(layout);
+        if !ptr.is_null() {
+            core::ptr::write_bytes(ptr, 0, layout.size());
+        }
+        ptr
+    }
+
+    /// Resizes the allocation at `ptr` to `new_size` bytes, moving it if
+    /// needed, as `GlobalAlloc::realloc()` does.
+    unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.alloc(Layout::from_size_align_unchecked(new_size, layout.align()));
+        if !new_ptr.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
 }
 
-/// Thread-safe (locking) wrapper around a particular memory allocator.
-pub struct Allocator(Mutex<Option<AllocatorImpl>>);
+/// Thread-safe (locking) wrapper around a particular memory allocator,
+/// which counts the heap usage of what it hands out.
+pub struct Allocator(Mutex<Option<AllocatorImpl>>, Mutex<Stats>);
 
 impl Allocator {
     /// Returns an uninitialized `Allocator`.
@@ -34,7 +66,7 @@ impl Allocator {
     /// The allocator must be initialized by calling `initialize()` before the
     /// first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        Allocator(Mutex::new(None))
+        Allocator(Mutex::new(None), Mutex::new(Stats::new()))
     }
 
     /// Initializes the memory allocator.
@@ -45,19 +77,74 @@ impl Allocator {
     ///
     /// Panics if the system's memory map could not be retrieved.
     pub unsafe fn initialize(&self) {
-        let (start, end) = memory_map().expect("failed to find memory map");
+        let (start, _) = memory_map().expect("failed to find memory map");
+        let (end, _) = dma::zone().expect("failed to find memory map");
         info!("heap beg: {:x}, end: {:x}", start, end);
         *self.0.lock() = Some(AllocatorImpl::new(start, end));
     }
+
+    /// Returns the start and end address of the heap, `None` if the memory
+    /// map could not be determined.
+    pub fn bounds(&self) -> Option<(usize, usize)> {
+        Some((memory_map()?.0, dma::zone()?.0))
+    }
+
+    /// Returns the heap usage so far.
+    pub fn stats(&self) -> Stats {
+        *self.1.lock()
+    }
+
+    /// Counts a request that returned `ptr` with `f`, or as a failure if it
+    /// returned null.
+    fn record<F: FnOnce(&mut Stats)>(&self, ptr: *mut u8, f: F) {
+        let mut stats = self.1.lock();
+        match ptr.is_null() {
+            true => stats.record_failure(),
+            false => f(&mut stats),
+        }
+    }
+}
+
+#[cfg(feature = "heap-guard")]
+impl Allocator {
+    /// Prints every live heap allocation with its size and call sites.
+    pub fn dump_live(&self) {
+        let heap = self.0.lock();
+        let heap = heap.as_ref().expect("allocator uninitialized");
+        kprintln!("{} live allocations", heap.live());
+        heap.for_each_live(|allocation| {
+            kprint!("{:#x} {:8} bytes from", allocation.ptr, allocation.size);
+            for address in allocation.backtrace.iter().take_while(|address| **address != 0) {
+                kprint!(" {:#x}", address);
+            }
+            kprintln!();
+        });
+    }
+
+    /// Checks the redzones of every live heap allocation and prints the
+    /// first corrupted one. Returns `true` if none are corrupted.
+    pub fn check_live(&self) -> bool {
+        let heap = self.0.lock();
+        match heap.as_ref().expect("allocator uninitialized").check() {
+            Some((allocation, offset)) => {
+                kprintln!("redzone of {:#x} ({} bytes) overwritten at offset {}, allocated at {:x?}",
+                          allocation.ptr, allocation.size, offset, allocation.backtrace);
+                false
+            }
+            None => true,
+        }
+    }
 }
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.0
+        let ptr = self.0
             .lock()
             .as_mut()
             .expect("allocator uninitialized")
-            .alloc(layout)
+            .alloc(layout);
+        self.record(ptr, |stats| stats.record_alloc(layout.size()));
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
@@ -66,6 +153,27 @@ unsafe impl GlobalAlloc for Allocator {
             .as_mut()
             .expect("allocator uninitialized")
             .dealloc(ptr, layout);
+        self.1.lock().record_dealloc(layout.size());
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.0
+            .lock()
+            .as_mut()
+            .expect("allocator uninitialized")
+            .alloc_zeroed(layout);
+        self.record(ptr, |stats| stats.record_alloc(layout.size()));
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.0
+            .lock()
+            .as_mut()
+            .expect("allocator uninitialized")
+            .realloc(ptr, layout, new_size);
+        self.record(new_ptr, |stats| stats.record_realloc(layout.size(), new_size));
+        new_ptr
     }
 }
 
@@ -76,24 +184,23 @@ extern "C" {
 /// Returns the (start address, end address) of the available memory on this
 /// system if it can be determined. If it cannot, `None` is returned.
 ///
-/// This function is expected to return `Some` under all normal cirumstances.
+/// The device tree names the memory if the firmware passed one; otherwise
+/// it comes from the ATAGs. This function is expected to return `Some`
+/// under all normal cirumstances.
 pub fn memory_map() -> Option<(usize, usize)> {
 
-    let binary_end = unsafe { (&__text_end as *const u8) as usize };
-
-    for atag in Atags::get() {
-	match atag.mem() {
-	    Some(mem) => {
-		let start_addr: usize = cmp::max(binary_end, mem.start as usize);
-		let end_addr: usize = mem.start as usize + mem.size as usize;
-		assert!(start_addr < end_addr);
-		return Some((start_addr, end_addr));
-	    },
-	    _ => continue,
-		
-	}
-    }
-    None
+    let binary_end = crate::kaslr::phys(unsafe { (&__text_end as *const u8) as usize });
+
+    let (start, end) = match crate::dtb::memory() {
+	Some(range) => range,
+	None => {
+	    let mem = Atags::get().find_map(|atag| atag.mem())?;
+	    (mem.start as usize, mem.start as usize + mem.size as usize)
+	},
+    };
+    let start_addr: usize = cmp::max(binary_end, start);
+    assert!(start_addr < end);
+    Some((start_addr, end))
 }
 
 impl fmt::Debug for Allocator {
@@ -1,6 +1,6 @@
 use core::alloc::Layout;
 use core::ptr;
-use core::cmp::max;
+use core::cmp::{self, max};
 
 use crate::allocator::linked_list::LinkedList;
 use crate::allocator::util::*;
@@ -10,6 +10,9 @@ const ALLOC_BOUND: usize = 64 - 3;
 const MIN_BIN_SIZE: usize = 8;
 const INDEX_OFFSET: usize = 3; // i.e. [n] -> 2^(n+3)
 
+/// granularity fresh memory is zeroed in as the bump pointer reaches it
+const ZERO_CHUNK: usize = 4096;
+
 /// returns index such that Allocator.align[X][index] is tightest bounded block on the size requirement
 /// size is a byte value, the hash returns an index into an element of an align member of an Allocator struct
 fn get_bin (size: usize) -> usize {
@@ -53,6 +56,8 @@ fn bump(current: usize, end: usize, size: usize, align: usize) -> Option<usize>
 pub struct Allocator {
     current: usize,
     end: usize,
+    /// memory from `current` up to here is known to be zero
+    zeroed: usize,
     bins: [LinkedList; ALLOC_BOUND],
     unused: LinkedList,
     frag_count: usize,
@@ -65,6 +70,7 @@ impl Allocator {
 	Allocator {
 	    current: start,
 	    end: end,
+	    zeroed: start,
 	    bins: [LinkedList::new(); ALLOC_BOUND],
 	    unused: LinkedList::new(),
 	    frag_count: 0,
@@ -108,6 +114,50 @@ impl Allocator {
 	return block;
     }
 
+    /// carves a block out of the memory no block was carved from yet, zeroing
+    /// that memory a chunk at a time as the bump pointer reaches it, so that
+    /// the block comes out zeroed
+    fn bump_block (&mut self, size: usize, align: usize) -> Option<usize> {
+	let addr = bump(self.current, self.end, size, align)?;
+	self.save_external_frag(self.current, addr - self.current);
+	self.bump_to(addr + size);
+	Some(addr)
+    }
+
+    /// moves the bump pointer up to `current`, zeroing fresh memory on the way
+    fn bump_to (&mut self, current: usize) {
+	if current > self.zeroed {
+	    let zeroed = cmp::min(align_up(current, ZERO_CHUNK), self.end);
+	    unsafe { ptr::write_bytes(self.zeroed as *mut u8, 0, zeroed - self.zeroed) };
+	    self.zeroed = zeroed;
+	}
+	self.current = current;
+    }
+
+    /// takes a block to allocate `layout` in, and returns it with whether it
+    /// came out of fresh, zeroed memory
+    fn alloc_block (&mut self, layout: Layout) -> Option<(*mut u8, bool)> {
+	let size = max(layout.size(), layout.align());
+	let bin_index = get_bin(size);
+	let bin_size = get_bin_size(size);
+
+	// search for reusable block
+	let ref mut bin = self.bins[bin_index];
+	for block in bin.iter_mut() {
+	    if is_align(block.value() as usize, layout.align()) {
+		return Some((block.pop() as *mut u8, false));
+	    }
+	}
+
+	// search for block in externally fragmented memory
+	if let Some(addr) = self.find_block_external_frag(bin_size, layout.align()) {
+	    return Some((addr, false));
+	}
+
+	// if no block bump allocate more memory
+	self.bump_block(bin_size, layout.align()).map(|addr| (addr as *mut u8, true))
+    }
+
     /// saves reference to region lost due to alignment constraints on allocation of new blocks
     /// these unused regions are check in the future as a last effort before allocating new memory
     fn save_external_frag (&mut self, start: usize, size: usize) {
@@ -154,33 +204,66 @@ impl LocalAlloc for Allocator {
     /// or `layout` does not meet this allocator's
     /// size or alignment constraints.
     unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+	match self.alloc_block(layout) {
+	    Some((ptr, _)) => ptr,
+	    // exhausted
+	    None => ptr::null_mut(),
+	}
+    }
 
-	let size = max(layout.size(), layout.align());
-	let bin_index = get_bin(size);
-	let bin_size = get_bin_size(size);
+    /// Like `alloc()`, with the block zeroed. Blocks carved out of fresh
+    /// memory are zeroed already and skip the memset.
+    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> *mut u8 {
+	match self.alloc_block(layout) {
+	    Some((ptr, true)) => ptr,
+	    Some((ptr, false)) => {
+		ptr::write_bytes(ptr, 0, layout.size());
+		ptr
+	    },
+	    None => ptr::null_mut(),
+	}
+    }
 
-	// search for reusable block
-	let ref mut bin = self.bins[bin_index];
-	for block in bin.iter_mut() {
-	    if is_align(block.value() as usize, layout.align()) {
-		return block.pop() as *mut u8;
+    /// Resizes the block at `ptr` to `new_size` bytes. The block stays where
+    /// it is if the new size falls in the same bin, when shrinking, where the
+    /// tail goes back to the bins, and when growing if the block is the last
+    /// one bumped or the block of its size right after it is free.
+    unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+	let old_size = get_bin_size(max(layout.size(), layout.align()));
+	let new_size_bin = get_bin_size(max(new_size, layout.align()));
+	let addr = ptr as usize;
+
+	if new_size_bin <= old_size {
+	    // the tail splits into blocks of new_size_bin, 2 * new_size_bin, ...
+	    let mut tail = new_size_bin;
+	    while tail < old_size {
+		self.bins[get_bin(tail)].push((addr + tail) as *mut usize);
+		tail *= 2;
 	    }
+	    return ptr;
 	}
 
-	// search for block in externally fragmented memory
-	if let Some(addr) = self.find_block_external_frag(bin_size, layout.align()) {
-	    return addr as *mut u8;
+	if addr + old_size == self.current && addr + new_size_bin <= self.end {
+	    self.bump_to(addr + new_size_bin);
+	    return ptr;
 	}
 
-	// if no block bump allocate more memory
-	if let Some(addr) = bump(self.current, self.end, bin_size, layout.align()) {
-	    self.save_external_frag(self.current, addr - self.current);
-	    self.current = addr + bin_size;
-	    return addr as *mut u8;
+	if new_size_bin == 2 * old_size {
+	    let next = addr + old_size;
+	    for block in self.bins[get_bin(old_size)].iter_mut() {
+		if block.value() as usize == next {
+		    block.pop();
+		    return ptr;
+		}
+	    }
 	}
 
-	// exhausted
-	ptr::null_mut()
+	let new_ptr = self.alloc(Layout::from_size_align_unchecked(new_size, layout.align()));
+	if !new_ptr.is_null() {
+	    ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size));
+	    self.dealloc(ptr, layout);
+	}
+	new_ptr
     }
 
     /// Deallocates the memory referenced by `ptr`.
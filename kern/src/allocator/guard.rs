@@ -0,0 +1,254 @@
+use core::alloc::Layout;
+use core::cmp::max;
+use core::mem::{align_of, size_of};
+use core::ptr;
+
+use crate::allocator::bin;
+use crate::allocator::util::align_up;
+use crate::allocator::LocalAlloc;
+
+/// Bytes of redzone after every allocation. The redzone before an allocation
+/// is at least as large.
+pub const REDZONE: usize = 32;
+
+/// Number of return addresses recorded per allocation.
+pub const BACKTRACE_DEPTH: usize = 6;
+
+/// Fill pattern of the redzones.
+const REDZONE_BYTE: u8 = 0xFB;
+
+/// Fill pattern of freed memory, to make use after free visible.
+const FREED_BYTE: u8 = 0xFD;
+
+/// Marks the header of a live allocation.
+const MAGIC: usize = 0x6175_7264_6c69_7665;
+
+/// Bookkeeping stored in front of every allocation. Live allocations are
+/// linked into a list so they can be dumped.
+#[repr(C)]
+struct Header {
+    prev: *mut Header,
+    next: *mut Header,
+    size: usize,
+    align: usize,
+    backtrace: [usize; BACKTRACE_DEPTH],
+    magic: usize,
+}
+
+/// A live allocation, as reported by `Guard::for_each_live()`.
+#[derive(Debug, Copy, Clone)]
+pub struct Allocation {
+    pub ptr: usize,
+    pub size: usize,
+    /// return addresses of the allocating call stack, innermost first; zero
+    /// past the end of the recorded stack
+    pub backtrace: [usize; BACKTRACE_DEPTH],
+}
+
+/// A debugging allocator that wraps another one, surrounds every allocation
+/// with redzones, and records where it was allocated.
+///
+/// The redzones are checked when the allocation is freed, and `check()` checks
+/// those of all live allocations. A corrupted redzone, a double free, or a
+/// free with the wrong layout panics with the allocation's call site.
+///
+/// Call sites are found by walking frame records, which needs the kernel to be
+/// built with `-C force-frame-pointers=yes`; otherwise the backtraces are
+/// incomplete.
+pub struct Guard<A: LocalAlloc> {
+    inner: A,
+    live: *mut Header,
+    count: usize,
+}
+
+// The list only links blocks owned by the guard, which is always accessed
+// under the global allocator's lock.
+unsafe impl<A: LocalAlloc + Send> Send for Guard<A> {}
+
+impl Guard<bin::Allocator> {
+    /// Returns a guarded bin allocator managing the memory in `[start, end)`.
+    pub fn new(start: usize, end: usize) -> Guard<bin::Allocator> {
+	Guard::wrap(bin::Allocator::new(start, end))
+    }
+}
+
+impl<A: LocalAlloc> Guard<A> {
+    /// Returns a guard around `inner`.
+    pub fn wrap(inner: A) -> Guard<A> {
+	Guard { inner: inner, live: ptr::null_mut(), count: 0 }
+    }
+
+    /// Number of live allocations.
+    pub fn live(&self) -> usize {
+	self.count
+    }
+
+    /// Calls `f` with every live allocation, most recent first.
+    pub fn for_each_live<F: FnMut(&Allocation)>(&self, mut f: F) {
+	let mut header = self.live;
+	while !header.is_null() {
+	    unsafe {
+		f(&Allocation {
+		    ptr: Guard::<A>::user_ptr(header) as usize,
+		    size: (*header).size,
+		    backtrace: (*header).backtrace,
+		});
+		header = (*header).next;
+	    }
+	}
+    }
+
+    /// Checks the redzones of every live allocation and returns the first
+    /// one with a corrupted redzone, together with the offset of the first
+    /// overwritten byte relative to the allocation.
+    pub fn check(&self) -> Option<(Allocation, isize)> {
+	let mut header = self.live;
+	while !header.is_null() {
+	    unsafe {
+		if let Some(offset) = Guard::<A>::corruption(header) {
+		    let allocation = Allocation {
+			ptr: Guard::<A>::user_ptr(header) as usize,
+			size: (*header).size,
+			backtrace: (*header).backtrace,
+		    };
+		    return Some((allocation, offset));
+		}
+		header = (*header).next;
+	    }
+	}
+	None
+    }
+
+    /// Offset of the allocation from the start of its block.
+    fn front(align: usize) -> usize {
+	align_up(size_of::<Header>() + REDZONE, align)
+    }
+
+    /// Layout of the block holding the header, the redzones and an
+    /// allocation with `layout`.
+    fn block_layout(layout: Layout) -> Option<Layout> {
+	let align = max(layout.align(), align_of::<Header>());
+	let size = Guard::<A>::front(align).checked_add(layout.size())?.checked_add(REDZONE)?;
+	Layout::from_size_align(size, align).ok()
+    }
+
+    unsafe fn user_ptr(header: *mut Header) -> *mut u8 {
+	let align = max((*header).align, align_of::<Header>());
+	(header as *mut u8).add(Guard::<A>::front(align))
+    }
+
+    /// Returns the offset relative to the allocation of the first byte in
+    /// either redzone that was overwritten.
+    unsafe fn corruption(header: *mut Header) -> Option<isize> {
+	let ptr = Guard::<A>::user_ptr(header);
+	let front = ptr as usize - header as usize - size_of::<Header>();
+	for i in (1..=front).rev() {
+	    if *ptr.sub(i) != REDZONE_BYTE {
+		return Some(-(i as isize));
+	    }
+	}
+	let size = (*header).size;
+	for i in 0..REDZONE {
+	    if *ptr.add(size + i) != REDZONE_BYTE {
+		return Some((size + i) as isize);
+	    }
+	}
+	None
+    }
+}
+
+impl<A: LocalAlloc> LocalAlloc for Guard<A> {
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+	let block_layout = match Guard::<A>::block_layout(layout) {
+	    Some(block_layout) => block_layout,
+	    None => return ptr::null_mut(),
+	};
+	let header = self.inner.alloc(block_layout) as *mut Header;
+	if header.is_null() {
+	    return ptr::null_mut();
+	}
+
+	header.write(Header {
+	    prev: ptr::null_mut(),
+	    next: self.live,
+	    size: layout.size(),
+	    align: layout.align(),
+	    backtrace: backtrace(),
+	    magic: MAGIC,
+	});
+	if !self.live.is_null() {
+	    (*self.live).prev = header;
+	}
+	self.live = header;
+	self.count += 1;
+
+	let ptr = Guard::<A>::user_ptr(header);
+	let redzone = header.add(1) as *mut u8;
+	ptr::write_bytes(redzone, REDZONE_BYTE, ptr as usize - redzone as usize);
+	ptr::write_bytes(ptr.add(layout.size()), REDZONE_BYTE, REDZONE);
+	ptr
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+	let block_layout = Guard::<A>::block_layout(layout).expect("heap guard: invalid layout");
+	let header = ptr.sub(Guard::<A>::front(block_layout.align())) as *mut Header;
+
+	if (*header).magic != MAGIC {
+	    panic!("heap guard: free of {:p} ({} bytes), which is not allocated", ptr, layout.size());
+	}
+	if (*header).size != layout.size() || (*header).align != layout.align() {
+	    panic!("heap guard: {:p} freed as {} bytes aligned to {}, allocated as {} bytes aligned to {} at {:x?}",
+		   ptr, layout.size(), layout.align(), (*header).size, (*header).align, (*header).backtrace);
+	}
+	if let Some(offset) = Guard::<A>::corruption(header) {
+	    panic!("heap guard: redzone of {:p} ({} bytes) overwritten at offset {}, allocated at {:x?}",
+		   ptr, layout.size(), offset, (*header).backtrace);
+	}
+
+	if (*header).prev.is_null() {
+	    self.live = (*header).next;
+	}
+	else {
+	    (*(*header).prev).next = (*header).next;
+	}
+	if !(*header).next.is_null() {
+	    (*(*header).next).prev = (*header).prev;
+	}
+	self.count -= 1;
+
+	(*header).magic = 0;
+	ptr::write_bytes(ptr, FREED_BYTE, layout.size());
+	self.inner.dealloc(header as *mut u8, block_layout);
+    }
+}
+
+/// Returns the return addresses of the current call stack by following the
+/// frame records chained through `x29`.
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+fn backtrace() -> [usize; BACKTRACE_DEPTH] {
+    let mut addresses = [0; BACKTRACE_DEPTH];
+    let mut fp: usize;
+    unsafe {
+	asm!("mov $0, x29" : "=r"(fp) ::: "volatile");
+    }
+    for address in addresses.iter_mut() {
+	// frame records are 16 byte aligned and callers' records sit higher on
+	// the stack; anything else means the chain ended or is not there
+	if fp == 0 || fp % 16 != 0 {
+	    break;
+	}
+	let (next, lr) = unsafe { (*(fp as *const usize), *((fp + 8) as *const usize)) };
+	*address = lr;
+	if next <= fp {
+	    break;
+	}
+	fp = next;
+    }
+    addresses
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn backtrace() -> [usize; BACKTRACE_DEPTH] {
+    [0; BACKTRACE_DEPTH]
+}
@@ -0,0 +1,86 @@
+/// Number of size classes heap usage is counted in: powers of two from 8
+/// bytes up to 1 GiB, the last also counting anything larger.
+pub const SIZE_CLASSES: usize = 28;
+
+/// Smallest size class, as a power of two
+const MIN_CLASS_SHIFT: u32 = 3;
+
+/// Allocations of one size class
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Class {
+    /// allocations made in the class since boot
+    pub allocs: u64,
+    /// allocations of the class not freed yet
+    pub live: u64,
+}
+
+/// Heap usage in the bytes asked for, not counting what the allocator
+/// rounds them up to.
+#[derive(Debug, Copy, Clone)]
+pub struct Stats {
+    pub live_bytes: usize,
+    /// most bytes that were live at once
+    pub peak_bytes: usize,
+    pub allocs: u64,
+    pub frees: u64,
+    /// requests the allocator could not satisfy
+    pub failures: u64,
+    pub classes: [Class; SIZE_CLASSES],
+}
+
+impl Stats {
+    pub const fn new() -> Stats {
+        Stats {
+            live_bytes: 0,
+            peak_bytes: 0,
+            allocs: 0,
+            frees: 0,
+            failures: 0,
+            classes: [Class { allocs: 0, live: 0 }; SIZE_CLASSES],
+        }
+    }
+
+    /// Returns the index of the size class of `size` bytes.
+    pub fn class(size: usize) -> usize {
+        let shift = size.next_power_of_two().trailing_zeros();
+        core::cmp::min(shift.saturating_sub(MIN_CLASS_SHIFT) as usize, SIZE_CLASSES - 1)
+    }
+
+    /// Returns the largest size, in bytes, of the size class `index`.
+    pub fn class_size(index: usize) -> usize {
+        1 << (index as u32 + MIN_CLASS_SHIFT)
+    }
+
+    pub fn record_alloc(&mut self, size: usize) {
+        self.live_bytes += size;
+        self.peak_bytes = core::cmp::max(self.peak_bytes, self.live_bytes);
+        self.allocs += 1;
+        let class = &mut self.classes[Stats::class(size)];
+        class.allocs += 1;
+        class.live += 1;
+    }
+
+    pub fn record_dealloc(&mut self, size: usize) {
+        self.live_bytes -= size;
+        self.frees += 1;
+        self.classes[Stats::class(size)].live -= 1;
+    }
+
+    /// Records the move of an allocation of `old_size` bytes to `new_size`,
+    /// which counts as freeing the old and allocating the new one only if
+    /// they are of different classes.
+    pub fn record_realloc(&mut self, old_size: usize, new_size: usize) {
+        if Stats::class(old_size) == Stats::class(new_size) {
+            self.live_bytes = self.live_bytes - old_size + new_size;
+            self.peak_bytes = core::cmp::max(self.peak_bytes, self.live_bytes);
+        }
+        else {
+            self.record_dealloc(old_size);
+            self.record_alloc(new_size);
+        }
+    }
+
+    pub fn record_failure(&mut self) {
+        self.failures += 1;
+    }
+}
@@ -289,6 +289,172 @@ mod allocator {
             }
         }
     });
+
+    test_allocators!(bin_realloc, bump_realloc, 65536, |(_, _, mut a)| {
+        let ptr = a.alloc(layout!(24, 8));
+        for i in 0..24 {
+            *ptr.add(i) = i as u8;
+        }
+
+        let grown = a.realloc(ptr, layout!(24, 8), 1000);
+        assert!(!grown.is_null());
+        assert!((0..24).all(|i| *grown.add(i) == i as u8));
+        scribble(grown.add(24), 1000 - 24);
+
+        let shrunk = a.realloc(grown, layout!(1000, 8), 10);
+        assert!(!shrunk.is_null());
+        assert!((0..10).all(|i| *shrunk.add(i) == i as u8));
+        a.dealloc(shrunk, layout!(10, 8));
+    });
+
+    test_allocators!(@bin, bin_realloc_in_place, 65536, |(_, _, mut a)| {
+        // the block bumped last grows into fresh memory
+        let last = a.alloc(layout!(64, 8));
+        assert_eq!(a.realloc(last, layout!(64, 8), 1000), last);
+
+        // a block grows into the free block of its size after it
+        let first = a.alloc(layout!(64, 8));
+        let second = a.alloc(layout!(64, 8));
+        let third = a.alloc(layout!(64, 8));
+        assert_eq!(second as usize, first as usize + 64);
+        a.dealloc(second, layout!(64, 8));
+        assert_eq!(a.realloc(first, layout!(64, 8), 128), first);
+
+        // and gives the tail back when it shrinks
+        assert_eq!(a.realloc(first, layout!(128, 8), 64), first);
+        assert_eq!(a.alloc(layout!(64, 8)), second);
+        a.dealloc(third, layout!(64, 8));
+    });
+
+    test_allocators!(bin_alloc_zeroed, bump_alloc_zeroed, 65536, |(_, _, mut a)| {
+        let ptr = a.alloc(layout!(256, 8));
+        scribble(ptr, 256);
+        a.dealloc(ptr, layout!(256, 8));
+
+        // a recycled block, then one from fresh memory
+        for _ in 0..2 {
+            let ptr = a.alloc_zeroed(layout!(256, 8));
+            assert!(!ptr.is_null());
+            assert!((0..256).all(|i| *ptr.add(i) == 0));
+            scribble(ptr, 256);
+        }
+    });
+}
+
+mod guard {
+    extern crate alloc;
+    use alloc::raw_vec::RawVec;
+
+    use core::alloc::Layout;
+
+    use crate::allocator::guard::{Guard, REDZONE};
+    use crate::allocator::{bin, LocalAlloc};
+
+    macro test_guard(|$a:pat| $block:expr) {
+        let mem: RawVec<u8> = RawVec::with_capacity(65536);
+        let start = mem.ptr() as usize;
+        let $a = Guard::wrap(bin::Allocator::new(start, start + 65536));
+
+        #[allow(unused_unsafe)]
+        unsafe {
+            $block
+        }
+    }
+
+    macro layout($size:expr, $align:expr) {
+        Layout::from_size_align($size, $align).unwrap()
+    }
+
+    #[test]
+    fn guard_alloc_dealloc() {
+        test_guard!(|mut a| {
+            let layouts = [layout!(1, 1), layout!(24, 8), layout!(100, 256), layout!(4096, 4096)];
+            let mut pointers = vec![];
+            for layout in &layouts {
+                let ptr = a.alloc(layout.clone());
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % layout.align(), 0);
+                ::core::ptr::write_bytes(ptr, 0xAF, layout.size());
+                pointers.push(ptr);
+            }
+            assert_eq!(a.live(), layouts.len());
+            assert!(a.check().is_none());
+
+            for (ptr, layout) in pointers.into_iter().zip(layouts.iter()) {
+                a.dealloc(ptr, layout.clone());
+            }
+            assert_eq!(a.live(), 0);
+        });
+    }
+
+    #[test]
+    fn guard_live_allocations() {
+        test_guard!(|mut a| {
+            let x = a.alloc(layout!(16, 8));
+            let y = a.alloc(layout!(48, 16));
+            let z = a.alloc(layout!(80, 8));
+            a.dealloc(y, layout!(48, 16));
+
+            let mut live = vec![];
+            a.for_each_live(|allocation| live.push((allocation.ptr, allocation.size)));
+            assert_eq!(live, vec![(z as usize, 80), (x as usize, 16)]);
+        });
+    }
+
+    #[test]
+    fn guard_check_finds_overflow() {
+        test_guard!(|mut a| {
+            let x = a.alloc(layout!(16, 8));
+            let y = a.alloc(layout!(20, 4));
+            *y.add(20 + REDZONE - 1) = 0;
+
+            let (allocation, offset) = a.check().expect("corruption not found");
+            assert_eq!(allocation.ptr, y as usize);
+            assert_eq!(offset, (20 + REDZONE - 1) as isize);
+            a.dealloc(x, layout!(16, 8));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "redzone")]
+    fn guard_overflow_panics() {
+        test_guard!(|mut a| {
+            let ptr = a.alloc(layout!(32, 8));
+            *ptr.add(32) = 0;
+            a.dealloc(ptr, layout!(32, 8));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "redzone")]
+    fn guard_underflow_panics() {
+        test_guard!(|mut a| {
+            let ptr = a.alloc(layout!(32, 64));
+            *ptr.sub(1) = 0;
+            a.dealloc(ptr, layout!(32, 64));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "not allocated")]
+    fn guard_double_free_panics() {
+        test_guard!(|mut a| {
+            let keep = a.alloc(layout!(8, 8));
+            let ptr = a.alloc(layout!(64, 8));
+            a.dealloc(ptr, layout!(64, 8));
+            a.dealloc(ptr, layout!(64, 8));
+            a.dealloc(keep, layout!(8, 8));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "freed as")]
+    fn guard_layout_mismatch_panics() {
+        test_guard!(|mut a| {
+            let ptr = a.alloc(layout!(64, 8));
+            a.dealloc(ptr, layout!(32, 8));
+        });
+    }
 }
 
 mod linked_list {
@@ -383,3 +549,79 @@ mod linked_list {
         assert_eq!(iter.next(), None);
     }
 }
+
+mod zeroize {
+    use crate::allocator::{poison, zeroize, Zeroizing};
+
+    #[test]
+    fn zeroize_clears_range() {
+        let mut buf = [0xAAu8; 64];
+        unsafe { zeroize(buf[8..].as_mut_ptr(), 48) };
+        assert!(buf[..8].iter().all(|b| *b == 0xAA));
+        assert!(buf[8..56].iter().all(|b| *b == 0));
+        assert!(buf[56..].iter().all(|b| *b == 0xAA));
+    }
+
+    #[test]
+    fn poison_fills_range() {
+        let mut buf = [0u8; 32];
+        unsafe { poison(buf[4..].as_mut_ptr(), 24, 0x6b) };
+        assert!(buf[..4].iter().all(|b| *b == 0));
+        assert!(buf[4..28].iter().all(|b| *b == 0x6b));
+        assert!(buf[28..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn zeroizing_holds_data() {
+        let mut key = Zeroizing::from_slice(b"secret key");
+        assert_eq!(&key[..], b"secret key");
+        key[0] = b'S';
+        assert_eq!(&key[..6], b"Secret");
+
+        let zeroed = Zeroizing::zeroed(32);
+        assert_eq!(zeroed.len(), 32);
+        assert!(zeroed.iter().all(|b| *b == 0));
+
+        let value = Zeroizing::new([7u64; 4]);
+        assert_eq!(*value, [7; 4]);
+    }
+}
+
+mod stats {
+    use crate::allocator::{Stats, SIZE_CLASSES};
+
+    #[test]
+    fn size_classes() {
+        assert_eq!(Stats::class(0), 0);
+        assert_eq!(Stats::class(8), 0);
+        assert_eq!(Stats::class(9), 1);
+        assert_eq!(Stats::class(4096), 9);
+        assert_eq!(Stats::class(1 << 40), SIZE_CLASSES - 1);
+        assert_eq!(Stats::class_size(Stats::class(100)), 128);
+    }
+
+    #[test]
+    fn counts_live_and_peak() {
+        let mut stats = Stats::new();
+        stats.record_alloc(100);
+        stats.record_alloc(4000);
+        stats.record_dealloc(100);
+        assert_eq!(stats.live_bytes, 4000);
+        assert_eq!(stats.peak_bytes, 4100);
+        assert_eq!((stats.allocs, stats.frees), (2, 1));
+        assert_eq!(stats.classes[Stats::class(100)].live, 0);
+        assert_eq!(stats.classes[Stats::class(100)].allocs, 1);
+        assert_eq!(stats.classes[Stats::class(4000)].live, 1);
+
+        stats.record_realloc(4000, 4090);
+        assert_eq!(stats.live_bytes, 4090);
+        assert_eq!(stats.allocs, 2);
+        stats.record_realloc(4090, 10000);
+        assert_eq!(stats.classes[Stats::class(4000)].live, 0);
+        assert_eq!(stats.classes[Stats::class(10000)].live, 1);
+        assert_eq!(stats.peak_bytes, 10000);
+
+        stats.record_failure();
+        assert_eq!(stats.failures, 1);
+    }
+}
@@ -0,0 +1,84 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use core::fmt;
+use core::mem::size_of_val;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// Overwrites `len` bytes at `ptr` with zeros. Unlike `write_bytes`, the
+/// stores are volatile, so they are not elided for memory about to be freed.
+///
+/// # Safety
+///
+/// `ptr` must be valid for writes of `len` bytes.
+pub unsafe fn zeroize(ptr: *mut u8, len: usize) {
+    poison(ptr, len, 0)
+}
+
+/// Like `zeroize()`, with `byte` instead of zero, so that reads of freed
+/// memory stand out.
+///
+/// # Safety
+///
+/// `ptr` must be valid for writes of `len` bytes.
+pub unsafe fn poison(ptr: *mut u8, len: usize, byte: u8) {
+    for i in 0..len {
+	ptr::write_volatile(ptr.add(i), byte);
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// A heap allocation that is zeroed before it is freed, for key material and
+/// other data that must not linger in reused memory.
+///
+/// Only plain data can be stored: a value of a `Copy` type or a byte slice.
+/// Copies made out of the allocation are not tracked.
+pub struct Zeroizing<T: ?Sized>(Box<T>);
+
+impl<T: Copy> Zeroizing<T> {
+    pub fn new(value: T) -> Zeroizing<T> {
+	Zeroizing(Box::new(value))
+    }
+}
+
+impl Zeroizing<[u8]> {
+    /// Returns `len` zeroed bytes.
+    pub fn zeroed(len: usize) -> Zeroizing<[u8]> {
+	Zeroizing(vec![0u8; len].into_boxed_slice())
+    }
+
+    /// Returns a copy of `bytes`.
+    pub fn from_slice(bytes: &[u8]) -> Zeroizing<[u8]> {
+	Zeroizing(Box::from(bytes))
+    }
+}
+
+impl<T: ?Sized> Deref for Zeroizing<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+	&self.0
+    }
+}
+
+impl<T: ?Sized> DerefMut for Zeroizing<T> {
+    fn deref_mut(&mut self) -> &mut T {
+	&mut self.0
+    }
+}
+
+impl<T: ?Sized> Drop for Zeroizing<T> {
+    fn drop(&mut self) {
+	let len = size_of_val(&*self.0);
+	unsafe {
+	    zeroize(&mut *self.0 as *mut T as *mut u8, len);
+	}
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Zeroizing<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f, "Zeroizing({} bytes)", size_of_val(&*self.0))
+    }
+}
@@ -0,0 +1,26 @@
+use pi::atags::Atags;
+
+use crate::dtb;
+
+/// Returns the value of `key` in the command line `cmd`.
+fn find(cmd: &'static str, key: &str) -> Option<&'static str> {
+    for arg in cmd.split_whitespace() {
+	let mut parts = arg.splitn(2, '=');
+	if parts.next() == Some(key) {
+	    return Some(parts.next().unwrap_or(""));
+	}
+    }
+    None
+}
+
+/// Returns the value of `key` on the kernel command line (`cmdline.txt`),
+/// as the device tree or, without one, the ATAGs hold it.
+///
+/// Arguments are whitespace separated `key=value` pairs; a bare `key` yields
+/// `Some("")`. Returns `None` if `key` is not present.
+pub fn get(key: &str) -> Option<&'static str> {
+    match dtb::cmdline() {
+	Some(cmd) => find(cmd, key),
+	None => Atags::get().filter_map(|atag| atag.cmd()).find_map(|cmd| find(cmd, key)),
+    }
+}
@@ -0,0 +1,131 @@
+//! When boot reached each of its phases, and how large the sections of the
+//! kernel image are, both printed by the `bootstat` shell command so that
+//! boot time and footprint show how they change as subsystems grow.
+//!
+//! Times are those of the system timer, which counts from power-on, so the
+//! first phase includes the firmware. A phase is stamped the first time it
+//! is reached; marking it again does nothing.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use pi::timer::current_time;
+
+use crate::kaslr;
+use crate::percore::is_mmu_ready;
+
+/// The phases of boot, in the order they are reached.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Phase {
+    /// `kmain()` was entered
+    Kernel = 0,
+    Allocator = 1,
+    VirtualMemory = 2,
+    FileSystem = 3,
+    Scheduler = 4,
+    /// a process was switched to for the first time
+    FirstProcess = 5,
+    /// the shell printed its first prompt
+    FirstPrompt = 6,
+}
+
+const PHASES: usize = 7;
+
+impl Phase {
+    pub const ALL: [Phase; PHASES] = [
+	Phase::Kernel,
+	Phase::Allocator,
+	Phase::VirtualMemory,
+	Phase::FileSystem,
+	Phase::Scheduler,
+	Phase::FirstProcess,
+	Phase::FirstPrompt,
+    ];
+
+    pub fn name(self) -> &'static str {
+	match self {
+	    Phase::Kernel => "kernel",
+	    Phase::Allocator => "allocator",
+	    Phase::FileSystem => "file system",
+	    Phase::VirtualMemory => "virtual memory",
+	    Phase::Scheduler => "scheduler",
+	    Phase::FirstProcess => "first process",
+	    Phase::FirstPrompt => "first prompt",
+	}
+    }
+}
+
+/// Microseconds since power-on each phase was reached at, 0 until then
+static STAMPS: [AtomicU64; PHASES] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Notes that boot reached `phase`, unless it did before.
+///
+/// The early phases are marked before the MMU is on, when exclusive
+/// load/store instructions may never succeed; only one core runs then, so a
+/// plain load and store is enough.
+pub fn mark(phase: Phase) {
+    let stamp = &STAMPS[phase as usize];
+    let now = current_time().as_micros() as u64;
+    if is_mmu_ready() {
+	stamp.compare_and_swap(0, now, Ordering::Relaxed);
+    }
+    else if stamp.load(Ordering::Relaxed) == 0 {
+	stamp.store(now, Ordering::Relaxed);
+    }
+}
+
+/// Returns the time since power-on `phase` was reached at, `None` if it was
+/// not yet.
+pub fn reached(phase: Phase) -> Option<Duration> {
+    match STAMPS[phase as usize].load(Ordering::Relaxed) {
+	0 => None,
+	micros => Some(Duration::from_micros(micros)),
+    }
+}
+
+/// A section of the kernel image, as laid out by `layout.ld`
+#[derive(Copy, Clone, Debug)]
+pub struct Section {
+    pub name: &'static str,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Section {
+    pub fn size(&self) -> usize {
+	self.end - self.start
+    }
+}
+
+extern "C" {
+    static __text_beg: u8;
+    static __code_end: u8;
+    static __rodata_beg: u8;
+    static __rodata_end: u8;
+    static __data_beg: u8;
+    static __data_end: u8;
+    static __bss_beg: u8;
+    static __bss_end: u8;
+}
+
+/// Returns the physical extent of the sections of the kernel image, in
+/// address order. The gaps between them are alignment padding.
+pub fn sections() -> [Section; 4] {
+    let addr = |symbol: &u8| kaslr::phys(symbol as *const u8 as usize);
+    unsafe {
+	[
+	    Section { name: ".text", start: addr(&__text_beg), end: addr(&__code_end) },
+	    Section { name: ".rodata", start: addr(&__rodata_beg), end: addr(&__rodata_end) },
+	    Section { name: ".data", start: addr(&__data_beg), end: addr(&__data_end) },
+	    Section { name: ".bss", start: addr(&__bss_beg), end: addr(&__bss_end) },
+	]
+    }
+}
@@ -0,0 +1,147 @@
+use core::cmp::min;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use pi::interrupt::{Controller, Interrupt};
+use pi::timer::{current_time, spin_sleep, Timer};
+
+use crate::bootargs;
+use crate::console::CONSOLE;
+use crate::param::{HZ, HZ_MAX, HZ_MIN};
+
+/// Timer interrupts per second
+static RATE: AtomicU64 = AtomicU64::new(HZ);
+
+/// Timer interrupts since boot
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Seconds since the Unix epoch at boot, 0 while the time is not known. The
+/// Pi has no real-time clock, so it is given by the `time=` boot argument or
+/// set later on.
+static BOOT_TIME: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the rate from the `hz=` boot argument and the time from `time=`, if
+/// there are valid ones.
+pub fn initialize() {
+    if let Some(arg) = bootargs::get("time") {
+	match arg.parse::<u64>() {
+	    Ok(secs) if secs > 0 => set_unix_time(secs),
+	    _ => warn!("clock: ignoring time={}, not seconds since the epoch", arg),
+	}
+    }
+    if let Some(arg) = bootargs::get("hz") {
+	match arg.parse::<u64>().ok().filter(|&hz| set_hz(hz)) {
+	    Some(hz) => info!("clock: {} Hz", hz),
+	    None => warn!("clock: ignoring hz={}, not within {}..={}", arg, HZ_MIN, HZ_MAX),
+	}
+    }
+}
+
+/// Returns the number of timer interrupts per second.
+pub fn hz() -> u64 {
+    RATE.load(Ordering::Relaxed)
+}
+
+/// Changes the number of timer interrupts per second, from the next tick on.
+/// Returns false and leaves the rate alone if `hz` is not within
+/// `HZ_MIN..=HZ_MAX`.
+pub fn set_hz(hz: u64) -> bool {
+    if hz < HZ_MIN || hz > HZ_MAX {
+	return false;
+    }
+    RATE.store(hz, Ordering::Relaxed);
+    true
+}
+
+/// Returns the time between two timer interrupts.
+pub fn tick() -> Duration {
+    ticks_to_duration(1)
+}
+
+/// Returns the number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Counts a timer interrupt. Called by the timer interrupt handler only.
+pub fn count_tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns how long `ticks` timer interrupts take at the current rate.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let hz = hz();
+    Duration::new(ticks / hz, ((ticks % hz) * 1_000_000_000 / hz) as u32)
+}
+
+/// Returns the number of timer interrupts that cover `duration` at the
+/// current rate, rounded up.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let nanos = duration.as_nanos() * hz() as u128;
+    ((nanos + 999_999_999) / 1_000_000_000) as u64
+}
+
+/// Shortest wait `wait()` sleeps in `wfi` for; a timer match set closer to
+/// now could pass before it is written.
+const WAIT_MIN: Duration = Duration::from_micros(100);
+
+/// How long `wait()` spins at a time while another interrupt is pending
+const WAIT_POLL: Duration = Duration::from_millis(1);
+
+/// Waits for `duration`, for kernel code that runs outside of any process,
+/// like the shell, and cannot wait on the scheduler. Returns `false` if the
+/// console has a byte to read before the time is up.
+///
+/// IRQs stay masked on the core, but the interrupts enabled at the
+/// controller stay so, and are taken once the wait is over. Timer 3, which
+/// wakes the core at the end, and the console, whose input ends the wait
+/// early, are enabled as well. The core sleeps in `wfi` while none of the
+/// others is pending, and polls the timer otherwise, as `wfi` would return
+/// at once.
+pub fn wait(duration: Duration) -> bool {
+    let deadline = current_time() + duration;
+    let mut controller = Controller::new();
+    let enabled = controller.save();
+    controller.enable(Interrupt::Timer3);
+    controller.enable(Interrupt::Aux);
+
+    let mut timer = Timer::new();
+    let done = loop {
+	if CONSOLE.lock().has_byte() {
+	    break false;
+	}
+	let now = current_time();
+	if now >= deadline {
+	    break true;
+	}
+	let other_pending = Interrupt::iter()
+	    .any(|int| int != Interrupt::Timer3 && int != Interrupt::Aux && controller.is_pending(int));
+	if other_pending {
+	    spin_sleep(min(deadline - now, WAIT_POLL));
+	    continue;
+	}
+	if deadline - now < WAIT_MIN {
+	    spin_sleep(deadline - now);
+	    continue;
+	}
+	timer.wake_in(deadline - now);
+	aarch64::wfi();
+    };
+    timer.clear_wake();
+    controller.restore(enabled);
+    done
+}
+
+/// Returns the seconds since the Unix epoch, or `None` if the time was never
+/// set.
+pub fn unix_time() -> Option<u64> {
+    match BOOT_TIME.load(Ordering::Relaxed) {
+	0 => None,
+	boot => Some(boot + current_time().as_secs()),
+    }
+}
+
+/// Sets the time to `secs` seconds since the Unix epoch.
+pub fn set_unix_time(secs: u64) {
+    BOOT_TIME.store(secs.saturating_sub(current_time().as_secs()).max(1), Ordering::Relaxed);
+}
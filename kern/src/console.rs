@@ -1,24 +1,73 @@
+use alloc::boxed::Box;
 use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use pi::interrupt::{Controller, Interrupt};
+use pi::timer::current_time;
 use pi::uart::MiniUart;
 use shim::io;
 
+use crate::fs::vfs::Handle;
 use crate::mutex::Mutex;
+use crate::param::{CONSOLE_INPUT_SIZE, LOG_RATELIMIT_BURST, LOG_RATELIMIT_INTERVAL};
+use crate::percore;
+use crate::process::Id;
+use crate::traps::irq::IrqHandlerRegistry;
+use crate::traps::TrapFrame;
+use crate::{GLOBAL_IRQ, SCHEDULER};
+
+/// The interrupt character, Ctrl-C, which stops the foreground process
+pub const INTR: u8 = 0x03;
+
+/// Bytes received from the UART that nobody has read yet
+struct Input {
+    buf: [u8; CONSOLE_INPUT_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl Input {
+    const fn new() -> Input {
+        Input { buf: [0; CONSOLE_INPUT_SIZE], head: 0, len: 0 }
+    }
+
+    /// Queues `byte`, dropping it if the buffer is full.
+    fn push(&mut self, byte: u8) {
+        if self.len < CONSOLE_INPUT_SIZE {
+            self.buf[(self.head + self.len) % CONSOLE_INPUT_SIZE] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % CONSOLE_INPUT_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
 
 /// A global singleton allowing read/write access to the console.
 pub struct Console {
     inner: Option<MiniUart>,
+    /// file that output goes to instead of the UART
+    redirect: Option<Box<dyn Handle>>,
+    input: Input,
 }
 
 impl Console {
     /// Creates a new instance of `Console`.
     const fn new() -> Console {
-        Console { inner: None }
+        Console { inner: None, redirect: None, input: Input::new() }
     }
 
     /// Initializes the console if it's not already initialized.
     #[inline]
     fn initialize(&mut self) {
 	self.inner = Some(MiniUart::new());
+	UART_READY.store(true, Ordering::Relaxed);
     }
 
     /// Returns a mutable borrow to the inner `MiniUart`, initializing it as
@@ -30,15 +79,51 @@ impl Console {
 	self.inner.as_mut().unwrap()
     }
 
-    /// Reads a byte from the UART device, blocking until a byte is available.
+    /// Reads a byte, from those `receive()` queued first and then from the
+    /// UART device, blocking until a byte is available.
     pub fn read_byte(&mut self) -> u8 {
-        self.inner().read_byte()
+        match self.input.pop() {
+            Some(byte) => byte,
+            None => self.inner().read_byte(),
+        }
     }
 
     /// Writes the byte `byte` to the UART device.
     pub fn write_byte(&mut self, byte: u8) {
         self.inner().write_byte(byte);
     }
+
+    /// Returns true if a byte can be read without blocking.
+    pub fn has_byte(&mut self) -> bool {
+        self.input.len > 0 || self.inner().has_byte()
+    }
+
+    /// Queues the bytes the UART holds for `read_byte()`, taking `INTR` out
+    /// of them as a request to interrupt the foreground process, if there is
+    /// one. Bytes that find the queue full are dropped.
+    pub fn receive(&mut self) {
+        while self.inner().has_byte() {
+            let byte = self.inner().read_byte();
+            if byte == INTR && FOREGROUND.load(Ordering::Relaxed) != 0 {
+                INTERRUPTED.store(true, Ordering::Relaxed);
+            }
+            else {
+                self.input.push(byte);
+            }
+        }
+    }
+
+    /// Returns the UART device itself, which output is never redirected from.
+    pub fn device(&mut self) -> &mut MiniUart {
+        self.inner()
+    }
+
+    /// Sends what is written to the console to `file` instead of the UART,
+    /// or to the UART again if `None`, and returns the previous redirection.
+    /// Single bytes written with `write_byte()` still go to the UART.
+    pub fn redirect(&mut self, file: Option<Box<dyn Handle>>) -> Option<Box<dyn Handle>> {
+        core::mem::replace(&mut self.redirect, file)
+    }
 }
 
 impl io::Read for Console {
@@ -49,7 +134,10 @@ impl io::Read for Console {
 
 impl io::Write for Console {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner().write(buf)
+        match self.redirect.as_mut() {
+            Some(file) => io::Write::write(file, buf),
+            None => self.inner().write(buf),
+        }
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -59,21 +147,277 @@ impl io::Write for Console {
 
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.inner().write_str(s)
+        match self.redirect.as_mut() {
+            Some(file) => io::Write::write_all(file, s.as_bytes()).map_err(|_| fmt::Error),
+            None => self.inner().write_str(s),
+        }
+    }
+}
+
+/// The console as a file, the standard input, output and error of every
+/// process. Reads return the bytes that have arrived and wait while there
+/// are none. Writes go to the UART even while the shell redirects its own
+/// output: a program started with its output redirected has the file open
+/// as its descriptors 1 and 2 instead, see `FdTable::with_output()`.
+pub struct ConsoleFile;
+
+impl io::Read for ConsoleFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut console = CONSOLE.lock();
+        console.receive();
+        let mut read = 0;
+        while read < buf.len() && console.has_byte() {
+            buf[read] = console.read_byte();
+            read += 1;
+        }
+        match read {
+            0 if !buf.is_empty() => Err(io::Error::new(io::ErrorKind::WouldBlock, "no input")),
+            _ => Ok(read),
+        }
+    }
+}
+
+impl io::Write for ConsoleFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        CONSOLE.lock().device().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for ConsoleFile {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "the console cannot seek"))
+    }
+}
+
+impl Handle for ConsoleFile {
+    fn size(&self) -> u64 {
+        0
+    }
+
+    fn blocking(&self) -> bool {
+        true
     }
 }
 
 /// Global `Console` singleton.
 pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
 
-/// Internal function called by the `kprint[ln]!` macros.
+/// Process that `INTR` interrupts, 0 if none
+static FOREGROUND: AtomicU64 = AtomicU64::new(0);
+
+/// Whether `INTR` arrived since the foreground process was last interrupted
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes `pid` the process that `INTR` kills, or no process if it is 0.
+/// The console goes back to no process once `pid` exits, so that `INTR`
+/// reaches the shell again.
+pub fn set_foreground(pid: Id) {
+    FOREGROUND.store(pid, Ordering::Relaxed);
+    if pid != 0 {
+	// nothing to undo if `pid` is gone already
+	let _ = SCHEDULER.on_exit(pid, "console", Box::new(move || {
+	    FOREGROUND.compare_and_swap(pid, 0, Ordering::Relaxed);
+	}));
+    }
+}
+
+pub fn foreground() -> Id {
+    FOREGROUND.load(Ordering::Relaxed)
+}
+
+/// Whether the UART interrupt is masked until the console lock is free
+static READ_DEFERRED: AtomicBool = AtomicBool::new(false);
+
+/// Queues the bytes the UART holds from an interrupt handler, unless the
+/// console is locked. Returns whether it was not.
+fn receive_from_handler() -> bool {
+    if CONSOLE.is_locked() {
+        return false;
+    }
+    match CONSOLE.try_lock() {
+        Some(mut console) => {
+            console.receive();
+            true
+        },
+        None => false,
+    }
+}
+
+/// Has the UART raise an interrupt for every byte it receives, so that
+/// `INTR` reaches a process that never reads the console.
+pub fn enable_read_interrupt() {
+    GLOBAL_IRQ.register(Interrupt::Aux, Box::new(|tf: &mut TrapFrame| {
+        // The console lock leaves interrupts enabled, so it may be held by
+        // the thread this interrupted, which locking here would reenter, or
+        // by another core, which a handler must not wait for. The UART keeps
+        // the interrupt raised until it is drained, so it is masked instead
+        // and `deliver_interrupt()` drains the UART on a later tick.
+        if !receive_from_handler() {
+            Controller::new().disable(Interrupt::Aux);
+            READ_DEFERRED.store(true, Ordering::Relaxed);
+            return;
+        }
+        deliver_interrupt(tf);
+    }));
+    CONSOLE.lock().device().set_read_interrupt(true);
+    Controller::new().enable(Interrupt::Aux);
+}
+
+/// Kills the foreground process if `INTR` arrived for it, switching `tf`
+/// to another process if it was the one running. Called from interrupt
+/// handlers, on every tick among them, which also unmasks the UART
+/// interrupt once the console it was masked for is free.
+pub fn deliver_interrupt(tf: &mut TrapFrame) {
+    if READ_DEFERRED.load(Ordering::Relaxed) && receive_from_handler() {
+        READ_DEFERRED.store(false, Ordering::Relaxed);
+        Controller::new().enable(Interrupt::Aux);
+    }
+    if !INTERRUPTED.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let pid = FOREGROUND.swap(0, Ordering::Relaxed);
+    if pid != 0 && SCHEDULER.kill_process(pid, tf) {
+        print_raw("^C\n");
+    }
+}
+
+/// Times a handler polls for room in the UART's output FIFO before it drops
+/// a byte, so that a stalled UART cannot hang the handler
+const HANDLER_SPINS: usize = 10_000;
+
+/// Whether the UART has been set up, so handlers may attach to it
+static UART_READY: AtomicBool = AtomicBool::new(false);
+
+/// Bytes printed by handlers that the UART had no room for, reported by the
+/// next print outside of a handler
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes to the UART for interrupt and fault handlers: bytes that find the
+/// output FIFO full for too long are dropped instead of waited for.
+struct HandlerWriter<'a>(&'a mut MiniUart);
+
+impl<'a> HandlerWriter<'a> {
+    fn put(&mut self, byte: u8) {
+        for _ in 0..HANDLER_SPINS {
+            if self.0.try_write_byte(byte) {
+                return;
+            }
+        }
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<'a> fmt::Write for HandlerWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.put(b'\r');
+            }
+            self.put(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Calls `f` with a writer to the UART that does not wait on the console
+/// lock, which the interrupted thread may hold, and does not go through a
+/// redirection, which takes file system locks. When the console is free it
+/// is locked as usual; otherwise the UART is written to directly and the
+/// output may interleave with the holder's.
+fn with_handler_writer<F: FnOnce(&mut HandlerWriter)>(f: F) {
+    if !CONSOLE.is_locked() {
+        if let Some(mut console) = CONSOLE.try_lock() {
+            f(&mut HandlerWriter(console.device()));
+            return;
+        }
+    }
+    if UART_READY.load(Ordering::Relaxed) {
+        let mut uart = unsafe { MiniUart::attach() };
+        f(&mut HandlerWriter(&mut uart));
+    }
+}
+
+/// Prints from an interrupt or fault handler, see `with_handler_writer()`.
+fn print_from_handler(args: fmt::Arguments) {
+    use core::fmt::Write;
+    with_handler_writer(|out| {
+        let _ = out.write_fmt(args);
+    });
+}
+
+/// Prints `s` to the UART the way handlers do, without formatting and
+/// without touching the heap, for the allocation error handler and others
+/// that must not fail on a broken allocator. See `rawfmt`; the panic handler
+/// has `PanicConsole` of its own.
+pub fn print_raw(s: &str) {
+    use core::fmt::Write;
+    with_handler_writer(|out| {
+        let _ = out.write_str(s);
+    });
+}
+
+/// The UART as the panic handler writes to it. It never takes the console
+/// lock, not even a free one, since the panic may come from code that left
+/// the console half updated, and it sets the UART up itself if the panic
+/// comes before the console did. Like handlers, it drops bytes rather than
+/// wait on a stalled UART.
+pub struct PanicConsole(MiniUart);
+
+impl PanicConsole {
+    pub fn get() -> PanicConsole {
+        let uart = match UART_READY.swap(true, Ordering::Relaxed) {
+            true => unsafe { MiniUart::attach() },
+            false => MiniUart::new(),
+        };
+        PanicConsole(uart)
+    }
+}
+
+impl fmt::Write for PanicConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        fmt::Write::write_str(&mut HandlerWriter(&mut self.0), s)
+    }
+}
+
+/// Notes the bytes handlers dropped since the last call on `out`.
+fn report_dropped<W: fmt::Write>(out: &mut W) {
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        let _ = write!(out, "[console] {} bytes printed by handlers were dropped\n", dropped);
+    }
+}
+
+/// Prints `args` to the UART even while the console is redirected. Safe to
+/// call from interrupt and fault handlers.
+pub fn print_device(args: fmt::Arguments) {
+    use core::fmt::Write;
+    if percore::in_handler() {
+        print_from_handler(args);
+        return;
+    }
+    let mut console = CONSOLE.lock();
+    report_dropped(console.device());
+    let _ = console.device().write_fmt(args);
+}
+
+/// Internal function called by the `kprint[ln]!` macros. Handlers print to
+/// the UART only, see `print_device()`.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     #[cfg(not(test))]
     {
         use core::fmt::Write;
+        if percore::in_handler() {
+            print_from_handler(args);
+            return;
+        }
         let mut console = CONSOLE.lock();
-        console.write_fmt(args).unwrap();
+        report_dropped(console.device());
+        let _ = console.write_fmt(args);
     }
 
     #[cfg(test)]
@@ -93,3 +437,51 @@ pub macro kprintln {
 pub macro kprint($($arg:tt)*) {
     _print(format_args!($($arg)*))
 }
+
+/// The state of one `kprintln_ratelimited!` call site: at most
+/// `LOG_RATELIMIT_BURST` messages are printed per `LOG_RATELIMIT_INTERVAL`,
+/// and the number of messages held back is printed when the next interval
+/// starts.
+pub struct RateLimit {
+    /// start of the current interval in microseconds since boot
+    start: AtomicU64,
+    printed: AtomicU64,
+    suppressed: AtomicU64,
+}
+
+impl RateLimit {
+    pub const fn new() -> RateLimit {
+        RateLimit { start: AtomicU64::new(0), printed: AtomicU64::new(0), suppressed: AtomicU64::new(0) }
+    }
+
+    /// Returns true if one more message may be printed now.
+    pub fn allow(&self) -> bool {
+        let now = current_time().as_micros() as u64;
+        let start = self.start.load(Ordering::Relaxed);
+        if now.saturating_sub(start) >= LOG_RATELIMIT_INTERVAL.as_micros() as u64 {
+            self.start.store(now, Ordering::Relaxed);
+            self.printed.store(0, Ordering::Relaxed);
+            let suppressed = self.suppressed.swap(0, Ordering::Relaxed);
+            if suppressed > 0 {
+                kprintln!("[{} messages suppressed]", suppressed);
+            }
+        }
+        if self.printed.fetch_add(1, Ordering::Relaxed) < LOG_RATELIMIT_BURST {
+            true
+        }
+        else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+}
+
+/// Like `kprintln!`, for messages printed from a path that may run over and
+/// over, such as a fault handler. Each call site is limited on its own, see
+/// `RateLimit`.
+pub macro kprintln_ratelimited($($arg:tt)*) {{
+    static LIMIT: RateLimit = RateLimit::new();
+    if LIMIT.allow() {
+        kprintln!($($arg)*);
+    }
+}}
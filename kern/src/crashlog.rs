@@ -0,0 +1,96 @@
+use core::fmt;
+use core::slice;
+use core::str;
+
+use crate::allocator::memory_map;
+use crate::console::kprintln;
+use crate::param::{CRASHLOG_SIZE, PAGE_MASK};
+
+/// Marks a crash log that holds a report.
+const MAGIC: u32 = 0x4b43_4150; // "PACK"
+
+#[repr(C)]
+struct Header {
+    magic: u32,
+    len: u32,
+}
+
+/// A report kept in a region at the top of RAM that is excluded from the
+/// heap, so it survives a watchdog reset and can be printed on the next boot.
+pub struct CrashLog {
+    header: &'static mut Header,
+    data: &'static mut [u8],
+}
+
+impl CrashLog {
+    /// Returns the physical address of the reserved region, or `None` if the
+    /// memory map could not be determined.
+    pub fn base() -> Option<usize> {
+	memory_map().map(|(_, end)| (end - CRASHLOG_SIZE) & PAGE_MASK)
+    }
+
+    /// Returns the crash log stored in the reserved region.
+    pub fn get() -> Option<CrashLog> {
+	let base = CrashLog::base()?;
+	let header_size = core::mem::size_of::<Header>();
+	unsafe {
+	    Some(CrashLog {
+		header: &mut *(base as *mut Header),
+		data: slice::from_raw_parts_mut((base + header_size) as *mut u8, CRASHLOG_SIZE - header_size),
+	    })
+	}
+    }
+
+    /// Returns the stored report, if there is one.
+    pub fn contents(&self) -> Option<&str> {
+	if self.header.magic != MAGIC {
+	    return None;
+	}
+	let len = core::cmp::min(self.header.len as usize, self.data.len());
+	Some(str::from_utf8(&self.data[..len]).unwrap_or("<corrupted report>"))
+    }
+
+    /// Starts a new, empty report.
+    pub fn reset(&mut self) {
+	self.header.magic = MAGIC;
+	self.header.len = 0;
+    }
+
+    /// Discards the stored report.
+    pub fn clear(&mut self) {
+	self.header.magic = 0;
+	self.header.len = 0;
+    }
+
+    /// Writes the report back to RAM so it is not lost in the data cache
+    /// when the board resets.
+    pub fn persist(&self) {
+	unsafe {
+	    aarch64::clean_dcache_range(&*self.header as *const Header as usize, CRASHLOG_SIZE);
+	}
+    }
+}
+
+impl fmt::Write for CrashLog {
+    /// Appends `s` to the report, silently truncating once the region is full.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+	let len = self.header.len as usize;
+	let count = core::cmp::min(s.len(), self.data.len() - len);
+	self.data[len..len + count].copy_from_slice(&s.as_bytes()[..count]);
+	self.header.len += count as u32;
+	Ok(())
+    }
+}
+
+/// Prints the report left behind by a panic in the previous boot, if any,
+/// then discards it.
+pub fn replay() {
+    if let Some(mut log) = CrashLog::get() {
+	if let Some(report) = log.contents() {
+	    kprintln!("---- panic report from previous boot ----");
+	    kprintln!("{}", report);
+	    kprintln!("-----------------------------------------");
+	}
+	log.clear();
+    }
+}
@@ -0,0 +1,51 @@
+//! Work that interrupt handlers hand off because it takes locks a handler
+//! must not wait for, or blocks: file system I/O, firmware calls. Handlers
+//! `schedule()` it, and it runs once the exception that is returning to a
+//! thread is done, with no lock held on the core, as a system call would.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::percore;
+
+/// Kinds of work, each a bit of `PENDING`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Work {
+    /// append the kernel log to its file, see `klog::tick()`
+    #[cfg(feature = "klog")]
+    KlogFlush = 1 << 0,
+    /// move the ACT LED on to the current step, see `led::tick()`
+    Led = 1 << 1,
+    /// drop the threads killed inside a handler, see `scheduler::reap()`
+    Reap = 1 << 2,
+}
+
+/// Work scheduled and not yet run
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// Has `work` run on the next return to a thread, on whichever core comes
+/// first. Scheduling it again before then runs it once. Safe to call from
+/// exception handlers.
+pub fn schedule(work: Work) {
+    PENDING.fetch_or(work as u64, Ordering::Relaxed);
+}
+
+/// Runs the work scheduled so far. Called by the exception vector on the
+/// way back to a thread; does nothing inside a handler.
+pub fn run() {
+    if percore::in_handler() || PENDING.load(Ordering::Relaxed) == 0 {
+	return;
+    }
+    let pending = PENDING.swap(0, Ordering::Relaxed);
+    #[cfg(feature = "klog")]
+    {
+	if pending & Work::KlogFlush as u64 != 0 {
+	    crate::klog::flush_due();
+	}
+    }
+    if pending & Work::Led as u64 != 0 {
+	crate::led::update();
+    }
+    if pending & Work::Reap as u64 != 0 {
+	crate::process::reap_deferred();
+    }
+}
@@ -0,0 +1,121 @@
+//! The DMA zone: a region of `DMA_ZONE_SIZE` bytes between the heap and the
+//! kernel log that buffers shared with DMA engines are allocated from. The
+//! kernel page table maps it non-cacheable, so that neither the CPU nor a
+//! device reads what the other wrote from a stale cache line, and a buffer
+//! is a run of physically contiguous pages. All of RAM is within reach of
+//! the DMA engines of the BCM2837, so the zone takes no care of that.
+
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+use crate::dmesg::Dmesg;
+use crate::mutex::Mutex;
+use crate::param::{DMA_ZONE_SIZE, PAGE_SIZE};
+use shim::const_assert_eq;
+
+/// Pages the zone holds
+const ZONE_PAGES: usize = DMA_ZONE_SIZE / PAGE_SIZE;
+const_assert_eq!(DMA_ZONE_SIZE % PAGE_SIZE, 0);
+const_assert_eq!((ZONE_PAGES <= 64), true);
+
+/// Offset of the alias of RAM that bypasses the VideoCore's L2 cache, as
+/// DMA engines see it
+const BUS_UNCACHED: usize = 0xC000_0000;
+
+/// Bit `n` is set while page `n` of the zone is part of a buffer.
+static USED: Mutex<u64> = Mutex::new(0);
+
+/// Returns the start and end physical address of the zone, or `None` if the
+/// memory map could not be determined.
+pub fn zone() -> Option<(usize, usize)> {
+    let end = Dmesg::base()?;
+    Some((end - DMA_ZONE_SIZE, end))
+}
+
+/// Returns the bits of `USED` of a buffer of `pages` pages starting at the
+/// first page of the zone.
+fn mask(pages: usize) -> u64 {
+    match pages {
+	64 => core::u64::MAX,
+	_ => (1 << pages) - 1,
+    }
+}
+
+/// A zeroed run of physically contiguous pages of the DMA zone, returned to
+/// the zone when dropped.
+pub struct DmaBuffer {
+    /// number of the first page in the zone
+    first: usize,
+    pages: usize,
+}
+
+impl DmaBuffer {
+    /// Allocates a buffer of at least `len` bytes, rounded up to whole pages.
+    /// Returns `None` if `len` is zero or there is no run of free pages long
+    /// enough.
+    pub fn new(len: usize) -> Option<DmaBuffer> {
+	let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+	if pages == 0 || pages > ZONE_PAGES {
+	    return None;
+	}
+	let bits = mask(pages);
+	let mut used = USED.lock();
+	let first = (0..=ZONE_PAGES - pages).find(|&first| *used & bits << first == 0)?;
+	*used |= bits << first;
+	drop(used);
+
+	let mut buffer = DmaBuffer { first, pages };
+	for byte in buffer.iter_mut() {
+	    *byte = 0;
+	}
+	Some(buffer)
+    }
+
+    /// Physical address of the buffer, as the CPU addresses it
+    pub fn phys_addr(&self) -> usize {
+	let (start, _) = zone().expect("DMA zone without memory map");
+	start + self.first * PAGE_SIZE
+    }
+
+    /// Address of the buffer as DMA engines address it
+    pub fn bus_addr(&self) -> u32 {
+	(self.phys_addr() | BUS_UNCACHED) as u32
+    }
+
+    /// Length of the buffer in bytes
+    pub fn len(&self) -> usize {
+	self.pages * PAGE_SIZE
+    }
+}
+
+impl Deref for DmaBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+	unsafe { core::slice::from_raw_parts(self.phys_addr() as *const u8, self.len()) }
+    }
+}
+
+impl DerefMut for DmaBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+	unsafe { core::slice::from_raw_parts_mut(self.phys_addr() as *mut u8, self.len()) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+	*USED.lock() &= !(mask(self.pages) << self.first);
+    }
+}
+
+impl fmt::Debug for DmaBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f, "DmaBuffer({:#x}, {} pages)", self.phys_addr(), self.pages)
+    }
+}
+
+/// Returns the bytes of the zone held by buffers and the size of the zone,
+/// as listed in `/proc/meminfo`.
+pub fn stats() -> (usize, usize) {
+    (USED.lock().count_ones() as usize * PAGE_SIZE, DMA_ZONE_SIZE)
+}
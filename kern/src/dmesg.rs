@@ -0,0 +1,198 @@
+use core::fmt;
+use core::mem::size_of;
+use core::slice;
+
+use filexfer::crc32;
+
+use crate::crashlog::CrashLog;
+use crate::mutex::Mutex;
+use crate::param::DMESG_SIZE;
+
+/// Marks an initialized log ring.
+const MAGIC: u32 = 0x474d_5344; // "DSMG"
+
+/// The kernel log of this boot and of the previous one.
+pub static DMESG: Mutex<Option<Dmesg>> = Mutex::new(None);
+
+#[repr(C)]
+struct Header {
+    magic: u32,
+    /// checksum of `magic`, `head` and `len`
+    crc: u32,
+    /// offset where the next byte is written
+    head: u32,
+    /// number of valid bytes, at most the ring's capacity
+    len: u32,
+}
+
+/// A byte ring that lives in reserved RAM and survives a warm reset.
+pub struct Ring {
+    header: &'static mut Header,
+    data: &'static mut [u8],
+}
+
+impl Ring {
+    unsafe fn at(base: usize, size: usize) -> Ring {
+	Ring {
+	    header: &mut *(base as *mut Header),
+	    data: slice::from_raw_parts_mut((base + size_of::<Header>()) as *mut u8, size - size_of::<Header>()),
+	}
+    }
+
+    fn checksum(&self) -> u32 {
+	let mut fields = [0u8; 12];
+	fields[0..4].copy_from_slice(&self.header.magic.to_le_bytes());
+	fields[4..8].copy_from_slice(&self.header.head.to_le_bytes());
+	fields[8..12].copy_from_slice(&self.header.len.to_le_bytes());
+	crc32(&fields)
+    }
+
+    /// Returns `true` if the ring holds a log with a valid magic and checksum.
+    fn is_valid(&self) -> bool {
+	self.header.magic == MAGIC
+	    && self.header.crc == self.checksum()
+	    && self.header.head as usize <= self.data.len()
+	    && self.header.len as usize <= self.data.len()
+    }
+
+    fn seal(&mut self) {
+	self.header.crc = self.checksum();
+	unsafe {
+	    aarch64::clean_dcache_range(&*self.header as *const Header as usize, size_of::<Header>());
+	}
+    }
+
+    fn reset(&mut self) {
+	self.header.magic = MAGIC;
+	self.header.head = 0;
+	self.header.len = 0;
+	self.seal();
+    }
+
+    fn invalidate(&mut self) {
+	self.header.magic = 0;
+	self.seal();
+    }
+
+    fn copy_from(&mut self, other: &Ring) {
+	self.data.copy_from_slice(other.data);
+	self.header.magic = MAGIC;
+	self.header.head = other.header.head;
+	self.header.len = other.header.len;
+	self.seal();
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+	let start = self.header.head as usize;
+	for byte in bytes {
+	    let head = self.header.head as usize;
+	    self.data[head] = *byte;
+	    self.header.head = ((head + 1) % self.data.len()) as u32;
+	    if (self.header.len as usize) < self.data.len() {
+		self.header.len += 1;
+	    }
+	}
+	let (clean_start, clean_len) = match start + bytes.len() <= self.data.len() {
+	    true => (start, bytes.len()),
+	    false => (0, self.data.len()),
+	};
+	unsafe {
+	    aarch64::clean_dcache_range(self.data.as_ptr() as usize + clean_start, clean_len);
+	}
+	self.seal();
+    }
+
+    /// Returns the contents of the ring, oldest first, as two slices.
+    pub fn contents(&self) -> (&[u8], &[u8]) {
+	let head = self.header.head as usize;
+	let len = self.header.len as usize;
+	if len < self.data.len() {
+	    (&self.data[..len], &self.data[..0])
+	}
+	else {
+	    (&self.data[head..], &self.data[..head])
+	}
+    }
+
+    /// Returns the newest `n` bytes of the ring, or all of them if it holds
+    /// fewer, oldest first, as two slices.
+    pub fn last(&self, n: usize) -> (&[u8], &[u8]) {
+	let (first, second) = self.contents();
+	let skip = (first.len() + second.len()).saturating_sub(n);
+	match skip <= first.len() {
+	    true => (&first[skip..], second),
+	    false => (&second[skip - first.len()..], &second[..0]),
+	}
+    }
+}
+
+/// The log ring of this boot and, if a warm reset left one behind, the log
+/// ring of the previous boot.
+pub struct Dmesg {
+    current: Ring,
+    previous: Ring,
+    has_previous: bool,
+    /// bytes logged since boot, including those the ring dropped
+    written: u64,
+}
+
+impl Dmesg {
+    /// Returns the physical address of the reserved region. It sits right
+    /// below the crash log, and the DMA zone right below it.
+    pub fn base() -> Option<usize> {
+	Some(CrashLog::base()? - DMESG_SIZE)
+    }
+
+    pub fn current(&self) -> &Ring {
+	&self.current
+    }
+
+    /// Returns the number of bytes logged since boot, including those the
+    /// ring has dropped since.
+    pub fn written(&self) -> u64 {
+	self.written
+    }
+
+    /// The log of the previous boot if it survived the reset.
+    pub fn previous(&self) -> Option<&Ring> {
+	match self.has_previous {
+	    true => Some(&self.previous),
+	    false => None,
+	}
+    }
+}
+
+impl fmt::Write for Dmesg {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+	self.current.push(s.as_bytes());
+	self.written += s.len() as u64;
+	Ok(())
+    }
+}
+
+/// Saves a log left behind by the previous boot and starts a fresh log.
+pub fn initialize() {
+    let base = match Dmesg::base() {
+	Some(base) => base,
+	None => return,
+    };
+    let (mut current, mut previous) = unsafe {
+	(Ring::at(base, DMESG_SIZE / 2), Ring::at(base + DMESG_SIZE / 2, DMESG_SIZE / 2))
+    };
+
+    let has_previous = current.is_valid();
+    if has_previous {
+	previous.copy_from(&current);
+    }
+    else {
+	previous.invalidate();
+    }
+    current.reset();
+
+    *DMESG.lock() = Some(Dmesg {
+	current: current,
+	previous: previous,
+	has_previous: has_previous,
+	written: 0,
+    });
+}
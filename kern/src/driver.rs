@@ -0,0 +1,59 @@
+//! The registry of the optional drivers. The cargo feature that builds a
+//! driver in adds it to `DRIVERS`, and `initialize()` starts them in order
+//! at boot; a driver that fails to start is logged and left out, and the
+//! kernel goes on without it. `drivers` in the shell lists them.
+//!
+//! The USB host controller of `usb` and the ethernet driver of `net` are
+//! registered too, but refuse to start for now: their USPi glue and
+//! ethernet driver are still the unimplemented stubs of lab 5, which would
+//! panic at boot.
+
+use alloc::vec::Vec;
+
+use crate::mutex::Mutex;
+
+/// A driver built into the kernel.
+pub struct Driver {
+    /// name the shell lists it under
+    pub name: &'static str,
+    /// cargo feature that builds it in
+    pub feature: &'static str,
+    /// starts the driver, or returns why it cannot
+    init: fn() -> Result<(), &'static str>,
+}
+
+static DRIVERS: &[Driver] = &[
+    #[cfg(feature = "usb")]
+    Driver { name: "usb", feature: "usb", init: crate::usb::initialize },
+    #[cfg(feature = "net")]
+    Driver { name: "ethernet", feature: "net", init: crate::net::initialize },
+    #[cfg(feature = "gfx")]
+    Driver { name: "framebuffer", feature: "gfx", init: crate::gfx::initialize },
+    #[cfg(feature = "sound")]
+    Driver { name: "pwm-audio", feature: "sound", init: crate::sound::initialize },
+];
+
+/// How each of `DRIVERS` started, once `initialize()` ran
+static STATUS: Mutex<Option<Vec<Result<(), &'static str>>>> = Mutex::new(None);
+
+/// Starts each registered driver.
+pub fn initialize() {
+    let status = DRIVERS.iter().map(|driver| {
+	let result = (driver.init)();
+	match result {
+	    Ok(()) => info!("driver {} ({}) started", driver.name, driver.feature),
+	    Err(reason) => warn!("driver {} ({}) not started: {}", driver.name, driver.feature, reason),
+	}
+	result
+    }).collect();
+    *STATUS.lock() = Some(status);
+}
+
+/// Returns the registered drivers with how each started, `None` for those
+/// not started yet.
+pub fn drivers() -> Vec<(&'static Driver, Option<Result<(), &'static str>>)> {
+    let status = STATUS.lock();
+    DRIVERS.iter().enumerate()
+	.map(|(i, driver)| (driver, status.as_ref().map(|status| status[i])))
+	.collect()
+}
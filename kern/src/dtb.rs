@@ -0,0 +1,123 @@
+//! What the kernel takes from the device tree newer firmware passes instead
+//! of ATAGs: the RAM, the window the peripherals are mapped at and the
+//! command line. `initialize()` copies them out of the blob before the heap
+//! can reuse its memory. Each accessor returns `None` when the firmware
+//! passed ATAGs, and its caller falls back to those.
+//!
+//! The drivers in `pi` still address the peripherals at the compile-time
+//! `IO_BASE`; only what the kernel maps comes from here.
+
+use core::str;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use pi::dtb::{read_cells, Fdt};
+
+use crate::param::{CMDLINE_SIZE, PAGE_MASK, PAGE_SIZE};
+
+/// Address of the blob the firmware passed in `x0`, 0 for none
+static BLOB: AtomicUsize = AtomicUsize::new(0);
+
+/// RAM, 0 to 0 if the tree names none
+static MEM_START: AtomicUsize = AtomicUsize::new(0);
+static MEM_END: AtomicUsize = AtomicUsize::new(0);
+
+/// Peripherals, 0 to 0 if the tree names none
+static IO_START: AtomicUsize = AtomicUsize::new(0);
+static IO_END: AtomicUsize = AtomicUsize::new(0);
+
+/// `/chosen/bootargs`, written once by `initialize()`
+static mut CMDLINE: [u8; CMDLINE_SIZE] = [0; CMDLINE_SIZE];
+static CMDLINE_LEN: AtomicUsize = AtomicUsize::new(0);
+static HAS_CMDLINE: AtomicBool = AtomicBool::new(false);
+
+/// Records the address of the blob from `x0` at entry. It is not read yet:
+/// this runs before the switch to EL1 and must not touch the stack.
+#[inline(always)]
+pub fn set_blob(addr: usize) {
+    BLOB.store(addr, Ordering::Relaxed);
+}
+
+/// Reads the first `(address, size)` pair of the `reg` property of `path`.
+fn reg(fdt: &Fdt, path: &str, address_cells: u32, size_cells: u32) -> Option<(usize, usize)> {
+    let reg = fdt.property(path, "reg")?;
+    let (address, rest) = read_cells(reg, address_cells)?;
+    let (size, _) = read_cells(rest, size_cells)?;
+    Some((address as usize, address.checked_add(size)? as usize))
+}
+
+/// Returns the lowest and highest addresses `/soc` translates its children
+/// to, rounded out to whole pages.
+fn soc_window(fdt: &Fdt, parent_cells: u32) -> Option<(usize, usize)> {
+    let child_cells = fdt.u32_property("/soc", "#address-cells").unwrap_or(2);
+    let size_cells = fdt.u32_property("/soc", "#size-cells").unwrap_or(1);
+    let mut ranges = fdt.property("/soc", "ranges")?;
+    let (mut start, mut end) = (usize::max_value(), 0);
+    while !ranges.is_empty() {
+	let (_, rest) = read_cells(ranges, child_cells)?;
+	let (parent, rest) = read_cells(rest, parent_cells)?;
+	let (size, rest) = read_cells(rest, size_cells)?;
+	start = core::cmp::min(start, parent as usize);
+	end = core::cmp::max(end, parent.checked_add(size)? as usize);
+	ranges = rest;
+    }
+    match start < end {
+	true => Some((start & PAGE_MASK, (end + PAGE_SIZE - 1) & PAGE_MASK)),
+	false => None,
+    }
+}
+
+/// Parses the blob `set_blob()` recorded, if it is a device tree. Must run
+/// before anything allocates or reserves memory.
+pub unsafe fn initialize() {
+    let fdt = match Fdt::from_addr(BLOB.load(Ordering::Relaxed)) {
+	Some(fdt) => fdt,
+	None => return,
+    };
+    // the defaults the specification gives nodes without the properties
+    let address_cells = fdt.u32_property("/", "#address-cells").unwrap_or(2);
+    let size_cells = fdt.u32_property("/", "#size-cells").unwrap_or(1);
+
+    if let Some((start, end)) = reg(&fdt, "/memory", address_cells, size_cells) {
+	MEM_START.store(start, Ordering::Relaxed);
+	MEM_END.store(end, Ordering::Relaxed);
+    }
+    if let Some((start, end)) = soc_window(&fdt, address_cells) {
+	IO_START.store(start, Ordering::Relaxed);
+	IO_END.store(end, Ordering::Relaxed);
+    }
+    if let Some(args) = fdt.str_property("/chosen", "bootargs") {
+	let mut len = core::cmp::min(args.len(), CMDLINE_SIZE);
+	while !args.is_char_boundary(len) {
+	    len -= 1;
+	}
+	CMDLINE[..len].copy_from_slice(&args.as_bytes()[..len]);
+	CMDLINE_LEN.store(len, Ordering::Relaxed);
+	HAS_CMDLINE.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Returns the (start address, end address) of RAM the device tree names.
+pub fn memory() -> Option<(usize, usize)> {
+    match MEM_END.load(Ordering::Relaxed) {
+	0 => None,
+	end => Some((MEM_START.load(Ordering::Relaxed), end)),
+    }
+}
+
+/// Returns the (start address, end address) of the peripherals as the ARM
+/// cores see them.
+pub fn peripherals() -> Option<(usize, usize)> {
+    match IO_END.load(Ordering::Relaxed) {
+	0 => None,
+	end => Some((IO_START.load(Ordering::Relaxed), end)),
+    }
+}
+
+/// Returns the kernel command line the device tree holds.
+pub fn cmdline() -> Option<&'static str> {
+    if !HAS_CMDLINE.load(Ordering::Relaxed) {
+	return None;
+    }
+    // written only by `initialize()`, which cut it at a char boundary
+    unsafe { Some(str::from_utf8_unchecked(&CMDLINE[..CMDLINE_LEN.load(Ordering::Relaxed)])) }
+}
@@ -0,0 +1,185 @@
+//! Serves files over the console to `rustos-dev ls/get/put`. The shell
+//! hands the console over when it reads the first byte of
+//! `filexfer::ESCAPE`, and takes it back once the host quits or goes quiet.
+//! See the `filexfer` crate for the protocol.
+
+use alloc::format;
+use alloc::vec::Vec;
+use core::str;
+use core::time::Duration;
+
+use filexfer::{read_frame, write_frame, Op, ESCAPE, MAX_PAYLOAD};
+use log::LevelFilter;
+use pi::timer::current_time;
+use pi::uart::MiniUart;
+use shim::io::{self, Read, Write};
+use shim::path::{Path, PathBuf};
+
+use crate::console::CONSOLE;
+use crate::logger;
+use crate::FILESYSTEM;
+
+/// How long the rest of the escape sequence may take to arrive
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long the server waits for the host before giving the console back
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The UART, with reads that give up after a while so that a host that
+/// goes away does not keep the console from the shell.
+struct Link<'a> {
+    uart: &'a mut MiniUart,
+    timeout: Duration,
+}
+
+impl<'a> io::Read for Link<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	let deadline = current_time() + self.timeout;
+	while !self.uart.has_byte() {
+	    if current_time() > deadline {
+		return Err(io::Error::new(io::ErrorKind::TimedOut, "host went quiet"));
+	    }
+	}
+	let mut read = 0;
+	while read < buf.len() && self.uart.has_byte() {
+	    buf[read] = self.uart.read_byte();
+	    read += 1;
+	}
+	Ok(read)
+    }
+}
+
+impl<'a> io::Write for Link<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	self.uart.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	Ok(())
+    }
+}
+
+/// Serves requests from the host until it quits, resolving relative paths
+/// against `pwd`. Called once the first byte of `ESCAPE` has been read;
+/// returns `false` without serving if the rest of it does not follow.
+pub fn serve(pwd: &Path) -> bool {
+    let mut console = CONSOLE.lock();
+    let mut link = Link { uart: console.device(), timeout: ESCAPE_TIMEOUT };
+    let mut rest = [0u8; 3];
+    if link.read_exact(&mut rest).is_err() || rest != ESCAPE[1..] {
+	return false;
+    }
+    link.timeout = IDLE_TIMEOUT;
+
+    // log lines would land in the middle of frames
+    let serial = logger::sinks().into_iter().find(|attached| attached.name == "serial");
+    if let Some(serial) = serial {
+	logger::configure(serial.name, LevelFilter::Off, serial.format);
+    }
+
+    let mut buf = [0u8; MAX_PAYLOAD];
+    loop {
+	let result = match read_frame(&mut link, &mut buf) {
+	    Ok((Op::Quit, _)) => {
+		let _ = write_frame(&mut link, Op::Ok, b"");
+		break;
+	    },
+	    Ok((op, len)) => request(&mut link, pwd, op, len, &mut buf),
+	    Err(e) => Err(e),
+	};
+	if let Err(e) = result {
+	    if e.kind() == io::ErrorKind::TimedOut {
+		break;
+	    }
+	    let _ = write_frame(&mut link, Op::Error, format!("{:?}", e).as_bytes());
+	}
+    }
+
+    if let Some(serial) = serial {
+	logger::configure(serial.name, serial.level, serial.format);
+    }
+    true
+}
+
+/// Answers the request `op` with a path of `len` bytes in `buf`.
+fn request(link: &mut Link, pwd: &Path, op: Op, len: usize, buf: &mut [u8; MAX_PAYLOAD]) -> io::Result<()> {
+    let path = str::from_utf8(&buf[..len])
+	.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "path is not UTF-8"))?;
+    let path = fat32::path::resolve(pwd, path)?;
+    match op {
+	Op::List => list(link, &path),
+	Op::Get => get(link, &path, buf),
+	Op::Put => put(link, &path, buf),
+	_ => Err(io::Error::new(io::ErrorKind::InvalidInput, "not a request")),
+    }
+}
+
+/// Sends the entries of the directory `path`, a line each, packed into as
+/// few frames as fit.
+fn list(link: &mut Link, path: &PathBuf) -> io::Result<()> {
+    let entries = FILESYSTEM.open(path)?.entries()?;
+    let mut out = Vec::with_capacity(MAX_PAYLOAD);
+    for entry in entries.iter() {
+	let line = match entry.attr().directory {
+	    true => format!("d {}\n", entry.name()),
+	    false => format!("f {} {}\n", entry.attr().size, entry.name()),
+	};
+	if out.len() + line.len() > MAX_PAYLOAD {
+	    write_frame(link, Op::Data, &out)?;
+	    out.clear();
+	}
+	out.extend_from_slice(line.as_bytes());
+    }
+    if !out.is_empty() {
+	write_frame(link, Op::Data, &out)?;
+    }
+    write_frame(link, Op::End, b"")
+}
+
+/// Sends the file `path`.
+fn get(link: &mut Link, path: &PathBuf, buf: &mut [u8; MAX_PAYLOAD]) -> io::Result<()> {
+    let mut file = FILESYSTEM.open_file(path)?;
+    loop {
+	match file.read(buf)? {
+	    0 => break,
+	    read => write_frame(link, Op::Data, &buf[..read])?,
+	}
+    }
+    write_frame(link, Op::End, b"")
+}
+
+/// Replaces the file `path`, creating it if needed, with what the host
+/// sends. Once writing fails the rest of the file is still read, so that
+/// the host hears of the failure after `End` as it expects.
+fn put(link: &mut Link, path: &PathBuf, buf: &mut [u8; MAX_PAYLOAD]) -> io::Result<()> {
+    let mut file = match FILESYSTEM.open_file(path) {
+	Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+	    FILESYSTEM.create(path, false)?;
+	    FILESYSTEM.open_file(path)?
+	},
+	result => result?,
+    };
+    file.truncate()?;
+    write_frame(link, Op::Ok, b"")?;
+
+    let mut result = Ok(());
+    loop {
+	match read_frame(link, buf) {
+	    Ok((Op::Data, len)) => {
+		if result.is_ok() {
+		    result = file.write_all(&buf[..len]);
+		}
+	    },
+	    Ok((Op::End, _)) => break,
+	    Ok(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "expected Data or End")),
+	    Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+		result = Err(io::Error::new(io::ErrorKind::InvalidData, "a frame of the file was corrupt"));
+	    },
+	    Err(e) => return Err(e),
+	}
+    }
+    result?;
+    file.flush()?;
+    file.sync()?;
+    write_frame(link, Op::Ok, b"")
+}
@@ -1,26 +1,56 @@
+pub mod dcache;
+pub mod dev;
+pub mod devfs;
+pub mod fat;
+pub mod flock;
+pub mod guard;
+pub mod iostat;
+pub mod notify;
+pub mod pagecache;
+pub mod pipe;
+pub mod procfs;
 pub mod sd;
+pub mod temp;
+pub mod tmpfs;
+pub mod vfs;
+pub mod walk;
 
-use alloc::rc::Rc;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::fmt::{self, Debug};
-use shim::io;
-use shim::path::Path;
+use core::sync::atomic::{AtomicBool, Ordering};
+use shim::io::{self, Write};
+use shim::path::{Path, PathBuf};
 
 pub use fat32::traits;
-use fat32::vfat::{Dir, Entry, File, VFat, VFatHandle};
+use fat32::traits::BlockDevice;
+use fat32::vfat::{BiosParameterBlock, Error, FormatParams, LookupMode, MountReport, TimeUpdate, VFat, VFatHandle};
+use kernel_api::{OsError, IN_CREATE, IN_DELETE};
 
+use self::dev::{LoopDevice, RamDisk};
+use self::devfs::DevFs;
+use self::fat::FatFs;
+use self::flock::Locking;
+use self::guard::Guarded;
+use self::notify::{Notifying, Watch};
+use self::pagecache::Cached;
+use self::procfs::ProcFs;
 use self::sd::Sd;
+use self::temp::Temp;
+use self::tmpfs::TmpFs;
+use self::vfs::{Handle, Vfs, Vnode};
+use crate::bootargs;
+use crate::clock;
+use crate::console::kprint;
 use crate::mutex::Mutex;
 
+/// The volume of the boot partition, shared by every file open on it and
+/// used from every core. `FileSystem::initialize()` runs after the MMU is on,
+/// as the atomics of the `Arc` need it.
 #[derive(Clone)]
-pub struct PiVFatHandle(Rc<Mutex<VFat<Self>>>);
-
-// These impls are *unsound*. We should use `Arc` instead of `Rc` to implement
-// `Sync` and `Send` trait for `PiVFatHandle`. However, `Arc` uses atomic memory
-// access, which requires MMU to be initialized on ARM architecture. Since we
-// have enabled only one core of the board, these unsound impls will not cause
-// any immediate harm for now. We will fix this in the future.
-unsafe impl Send for PiVFatHandle {}
-unsafe impl Sync for PiVFatHandle {}
+pub struct PiVFatHandle(Arc<VFat<Self>>);
 
 impl Debug for PiVFatHandle {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -29,15 +59,88 @@ impl Debug for PiVFatHandle {
 }
 
 impl VFatHandle for PiVFatHandle {
+    type Lock = Mutex<()>;
+
     fn new(val: VFat<PiVFatHandle>) -> Self {
-        PiVFatHandle(Rc::new(Mutex::new(val)))
+        PiVFatHandle(Arc::new(val))
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&VFat<PiVFatHandle>) -> R) -> R {
+        f(&self.0)
     }
+}
+
+/// Where a mounted volume is stored
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// SD card partition, 0 to 3
+    Partition(usize),
+    /// image file at an absolute path, through a loop device
+    Image(PathBuf),
+    /// RAM disk by number
+    Ram(usize),
+    /// none: the kernel makes up the file system, named by its type
+    Synthetic(&'static str),
+}
 
-    fn lock<R>(&self, f: impl FnOnce(&mut VFat<PiVFatHandle>) -> R) -> R {
-        f(&mut self.0.lock())
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    Source::Partition(partition) => write!(f, "sd{}", partition + 1),
+	    Source::Image(path) => write!(f, "{}", path.display()),
+	    Source::Ram(n) => write!(f, "ram{}", n),
+	    Source::Synthetic(name) => f.write_str(name),
+	}
     }
 }
-pub struct FileSystem(Mutex<Option<PiVFatHandle>>);
+
+/// A file system mounted in the tree.
+pub struct Mount {
+    /// absolute, normalized path of the mount point
+    pub path: PathBuf,
+    pub source: Source,
+    pub options: MountOptions,
+    fs: Box<dyn Vfs>,
+    /// `options.read_only`, shared with the files open on the mount
+    read_only: Arc<AtomicBool>,
+}
+
+impl Mount {
+    fn new(path: PathBuf, source: Source, options: MountOptions, fs: Box<dyn Vfs>) -> Mount {
+	let read_only = Arc::new(AtomicBool::new(options.read_only));
+	Mount { path, source, options, fs, read_only }
+    }
+
+    /// Name of the file system type, as listed by `mount`.
+    pub fn fs_type(&self) -> &'static str {
+	self.fs.fs_type()
+    }
+
+    /// Returns true if the volume refuses writes, because it was mounted
+    /// read only or cannot be written at all.
+    pub fn read_only(&self) -> bool {
+	self.read_only.load(Ordering::Acquire) || self.fs.read_only()
+    }
+}
+
+/// Options of `FileSystem::mount`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MountOptions {
+    /// refuse writes to the volume
+    pub read_only: bool,
+    /// read the sizes of files over 4 GiB stored with the FAT+ extension
+    pub fat_plus: bool,
+    /// when the access and modification times of files are updated
+    pub times: TimeUpdate,
+}
+
+/// The file system tree: the FAT32 root volume and the file systems mounted
+/// on its directories, which include the devices on `/dev`, the kernel
+/// state on `/proc` and the scratch space in memory on `/tmp`. A path is
+/// opened in the file system with the longest mount point that is a prefix
+/// of it, so a mount point need not exist as a directory of the file system
+/// below.
+pub struct FileSystem(Mutex<Option<Vec<Mount>>>);
 
 impl FileSystem {
     /// Returns an uninitialized `FileSystem`.
@@ -57,35 +160,432 @@ impl FileSystem {
     /// Panics if the underlying disk or file sytem failed to initialize.
     pub unsafe fn initialize(&self) {
 	let sd_device = Sd::new().expect("SD card controller failed");
-	let vfat = VFat::<PiVFatHandle>::from(sd_device).expect("failed to initialize VFAT from SD card controller");
-	*self.0.lock() = Some(vfat);
+	let (vfat, report) = VFat::<PiVFatHandle>::mount(sd_device, LookupMode::default())
+	    .expect("failed to initialize VFAT from SD card controller");
+	if report.primary_boot_corrupt {
+	    kprint!("(boot sector corrupt, mounted from backup) ");
+	}
+	if report.unclean {
+	    kprint!("(volume was not cleanly unmounted) ");
+	}
+	// `ro` on the command line keeps the boot partition from being written
+	let root = MountOptions { read_only: bootargs::get("ro").is_some(), ..MountOptions::default() };
+	vfat.with(|v| {
+	    v.set_clock(clock::unix_time);
+	    v.set_read_only(root.read_only);
+	});
+	let mut mounts = Vec::new();
+	mounts.push(Mount::new(PathBuf::from("/"), Source::Partition(0), root, Box::new(FatFs::new(vfat))));
+	mounts.push(Mount::new(PathBuf::from("/dev"), Source::Synthetic("devfs"), MountOptions::default(), Box::new(DevFs)));
+	mounts.push(Mount::new(PathBuf::from("/proc"), Source::Synthetic("proc"),
+			       MountOptions { read_only: true, ..MountOptions::default() }, Box::new(ProcFs)));
+	mounts.push(Mount::new(PathBuf::from("/tmp"), Source::Synthetic("tmpfs"), MountOptions::default(), Box::new(TmpFs::new())));
+	*self.0.lock() = Some(mounts);
     }
-}
 
-// FIXME: Implement `fat32::traits::FileSystem` for `&FileSystem`
-impl fat32::traits::FileSystem for &FileSystem {
-    /// The type of files in this file system.
-    type File = File<PiVFatHandle>;
+    /// Mounts the volume stored in `source` on the directory `path` with
+    /// `options`. An image file holds either a single volume or a disk with
+    /// the volume in its first partition.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if a path is not absolute, `path` is
+    /// already a mount point, or `source` is already mounted, and the errors
+    /// of opening `path` as a directory, of opening the image or of mounting
+    /// the volume.
+    pub fn mount(&self, source: Source, path: &Path, options: MountOptions) -> Result<MountReport, Error> {
+	let path = fat32::path::resolve("/", path)?;
+	let source = match source {
+	    Source::Image(image) => Source::Image(fat32::path::resolve("/", image)?),
+	    source => source,
+	};
+	let mut guard = self.0.lock();
+	let mounts = guard.as_mut().expect("file system is not initialized");
+	if mounts.iter().any(|m| m.path == path) {
+	    return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "already a mount point")));
+	}
+	if mounts.iter().any(|m| m.source == source) {
+	    return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "already mounted")));
+	}
+	let (mount, rest) = FileSystem::lookup(mounts, &path);
+	if !mount.fs.open(&rest).map(|node| node.attr().directory).unwrap_or(false) {
+	    return Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, "mount point is not a directory")));
+	}
 
-    /// The type of directories in this file system.
-    type Dir = Dir<PiVFatHandle>;
+	let (vfat, report) = match &source {
+	    // the controller was initialized when the root volume was mounted
+	    Source::Partition(partition) => VFat::<PiVFatHandle>::mount_partition(Sd, *partition, LookupMode::default())?,
+	    Source::Image(image) => {
+		let (mount, rest) = FileSystem::lookup(mounts, image);
+		mount_image(LoopDevice::new(mount.fs.open(&rest)?.open()?)?)?
+	    },
+	    Source::Ram(n) => match RamDisk::open(*n) {
+		Some(ram) => mount_image(ram)?,
+		None => return Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, "no such RAM disk"))),
+	    },
+	    Source::Synthetic(_) => return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "not a volume"))),
+	};
+	vfat.with(|v| {
+	    v.set_read_only(options.read_only);
+	    v.set_fat_plus(options.fat_plus);
+	    v.set_time_update(options.times);
+	    v.set_clock(clock::unix_time);
+	});
+	pagecache::invalidate_under(&path);
+	mounts.push(Mount::new(path, source, options, Box::new(FatFs::new(vfat))));
+	Ok(report)
+    }
+
+    /// Makes the file system mounted on `path` read only, or writable again
+    /// if `read_only` is false. Files already open on it refuse writes from
+    /// then on, or accept them again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `path` is not a mount point, and
+    /// the errors of `Vfs::set_read_only()`.
+    pub fn remount(&self, path: &Path, read_only: bool) -> io::Result<()> {
+	let path = fat32::path::resolve("/", path)?;
+	let mut guard = self.0.lock();
+	let mounts = guard.as_mut().expect("file system is not initialized");
+	let mount = match mounts.iter_mut().find(|m| m.path == path) {
+	    Some(mount) => mount,
+	    None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a mount point")),
+	};
+	// writes are refused before the volume is synced, and accepted only
+	// once the volume takes them
+	if read_only {
+	    mount.read_only.store(true, Ordering::Release);
+	}
+	if let Err(e) = mount.fs.set_read_only(read_only) {
+	    mount.read_only.store(mount.options.read_only, Ordering::Release);
+	    return Err(e);
+	}
+	mount.read_only.store(read_only, Ordering::Release);
+	mount.options.read_only = read_only;
+	Ok(())
+    }
+
+    /// Syncs and removes the file system mounted on `path`. Files that are
+    /// still open keep using it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `path` is not a mount point or is
+    /// the root, `Other` if another volume is mounted below it, and the error
+    /// of syncing the volume.
+    pub fn unmount(&self, path: &Path) -> io::Result<()> {
+	let path = fat32::path::resolve("/", path)?;
+	let mut guard = self.0.lock();
+	let mounts = guard.as_mut().expect("file system is not initialized");
+	let index = match mounts.iter().position(|m| m.path == path) {
+	    Some(0) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot unmount the root")),
+	    Some(index) => index,
+	    None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a mount point")),
+	};
+	if mounts.iter().any(|m| m.path != path && m.path.starts_with(&path)) {
+	    return Err(io::Error::new(io::ErrorKind::Other, "mount point is busy"));
+	}
+	mounts[index].fs.unmount()?;
+	mounts.remove(index);
+	pagecache::invalidate_under(&path);
+	Ok(())
+    }
 
-    /// The type of directory entries in this file system.
-    type Entry = Entry<PiVFatHandle>;
+    /// Returns true if a volume stored in `source` is mounted.
+    pub fn is_mounted(&self, source: &Source) -> bool {
+	let mut mounted = false;
+	self.for_each_mount(|m| mounted |= m.source == *source);
+	mounted
+    }
+
+    /// Calls `f` with every mounted volume, the root first.
+    pub fn for_each_mount<F: FnMut(&Mount)>(&self, mut f: F) {
+	if let Some(mounts) = self.0.lock().as_ref() {
+	    for mount in mounts.iter() {
+		f(mount);
+	    }
+	}
+    }
 
-    /// Opens the entry at `path`. `path` must be absolute.
+    /// Opens the vnode at `path`. `path` must be absolute.
     ///
     /// # Errors
     ///
     /// If `path` is not absolute, an error kind of `InvalidInput` is returned.
     ///
-    /// If any component but the last in `path` does not refer to an existing
-    /// directory, an error kind of `InvalidInput` is returned.
+    /// If there is no vnode at `path`, an error kind of `NotFound` is
+    /// returned. Other errors are those of the file system holding `path`.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Box<dyn Vnode>> {
+	if !path.as_ref().has_root() {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"));
+	}
+	let path = fat32::path::resolve("/", path)?;
+	let guard = self.0.lock();
+	let (mount, rest) = FileSystem::lookup(guard.as_ref().expect("file system is not initialized"), &path);
+	mount.fs.open(&rest)
+    }
+
+    /// Opens `path` relative to the absolute directory `cwd`.
+    pub fn open_at<P: AsRef<Path>, Q: AsRef<Path>>(&self, cwd: P, path: Q) -> io::Result<Box<dyn Vnode>> {
+	self.open(fat32::path::resolve(cwd, path)?)
+    }
+
+    /// Opens the regular file at the absolute `path`. The handle reports its
+    /// writes to watches and can take advisory locks on the file. Files of
+    /// volumes on block devices are read through the page cache.
+    ///
+    /// # Errors
     ///
-    /// If there is no entry at `path`, an error kind of `NotFound` is returned.
+    /// In addition to the errors of `open()`, returns an error of `Other` if
+    /// `path` is not a regular file.
+    pub fn open_file<P: AsRef<Path>>(&self, path: P) -> io::Result<Box<dyn Handle>> {
+	let path = FileSystem::absolute(path.as_ref())?;
+	let (handle, cached, read_only) = {
+	    let guard = self.0.lock();
+	    let (mount, rest) = FileSystem::lookup(guard.as_ref().expect("file system is not initialized"), &path);
+	    (mount.fs.open(&rest)?.open()?, mount.fs.page_cache(), mount.read_only.clone())
+	};
+	let handle: Box<dyn Handle> = match cached {
+	    true => Box::new(Cached::new(path.clone(), handle)),
+	    false => handle,
+	};
+	let handle = Box::new(Guarded::new(read_only, Box::new(Notifying::new(path.clone(), handle))));
+	Ok(Box::new(Locking::new(path, handle)))
+    }
+
+    /// Creates an empty regular file, or a directory if `directory`, at the
+    /// absolute `path`.
     ///
-    /// All other error values are implementation defined.
-    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-	self.0.lock().as_ref().unwrap().open(path)
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `path` is not absolute or is a
+    /// mount point, `OsError::ReadOnlyFs` if the volume holding it is read
+    /// only, and the errors of `Vfs::create()`.
+    pub fn create<P: AsRef<Path>>(&self, path: P, directory: bool) -> io::Result<()> {
+	let path = FileSystem::absolute(path.as_ref())?;
+	{
+	    let guard = self.0.lock();
+	    let mounts = guard.as_ref().expect("file system is not initialized");
+	    let (mount, rest) = FileSystem::writable_lookup(mounts, &path)?;
+	    mount.fs.create(&rest, directory)?;
+	}
+	notify::notify(&path, IN_CREATE);
+	Ok(())
+    }
+
+    /// Creates and opens an empty regular file in the absolute directory
+    /// `dir`, named `prefix` followed by random characters, and returns its
+    /// path and handle. The file is removed when the handle is dropped,
+    /// unless it was renamed away by then.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `prefix` holds a `/`,
+    /// `AlreadyExists` if `TEMP_ATTEMPTS` names in a row were taken, and the
+    /// errors of `create()` and `open_file()`.
+    pub fn create_temp<P: AsRef<Path>>(&self, dir: P, prefix: &str) -> io::Result<(PathBuf, Box<dyn Handle>)> {
+	if prefix.contains('/') {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "prefix holds a /"));
+	}
+	let dir = FileSystem::absolute(dir.as_ref())?;
+	for _ in 0..temp::TEMP_ATTEMPTS {
+	    let path = dir.join(temp::name(prefix));
+	    match self.create(&path, false) {
+		Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+		result => result?,
+	    }
+	    let handle = match self.open_file(&path) {
+		Ok(handle) => handle,
+		Err(e) => {
+		    let _ = self.remove(&path);
+		    return Err(e);
+		},
+	    };
+	    return Ok((path.clone(), Box::new(Temp::new(path, handle))));
+	}
+	Err(io::Error::new(io::ErrorKind::AlreadyExists, "no free temporary file name"))
+    }
+
+    /// Removes the regular file or empty directory at the absolute `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `path` is not absolute or is a
+    /// mount point, `OsError::ReadOnlyFs` if the volume holding it is read
+    /// only, and the errors of `Vfs::remove()`.
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+	let path = FileSystem::absolute(path.as_ref())?;
+	{
+	    let guard = self.0.lock();
+	    let mounts = guard.as_ref().expect("file system is not initialized");
+	    let (mount, rest) = FileSystem::writable_lookup(mounts, &path)?;
+	    mount.fs.remove(&rest)?;
+	}
+	pagecache::invalidate(&path);
+	notify::notify(&path, IN_DELETE);
+	Ok(())
+    }
+
+    /// Moves the regular file or directory at the absolute `from` to the
+    /// absolute `to`, replacing a file or an empty directory there.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if a path is not absolute, is a
+    /// mount point or the two are on different volumes, `OsError::ReadOnlyFs`
+    /// if the volume is read only, and the errors of `Vfs::rename()`.
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> io::Result<()> {
+	let from = FileSystem::absolute(from.as_ref())?;
+	let to = FileSystem::absolute(to.as_ref())?;
+	{
+	    let guard = self.0.lock();
+	    let mounts = guard.as_ref().expect("file system is not initialized");
+	    let (mount, from_rest) = FileSystem::writable_lookup(mounts, &from)?;
+	    let (to_mount, to_rest) = FileSystem::writable_lookup(mounts, &to)?;
+	    if mount.path != to_mount.path {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "paths are on different volumes"));
+	    }
+	    mount.fs.rename(&from_rest, &to_rest)?;
+	}
+	pagecache::invalidate(&from);
+	pagecache::invalidate(&to);
+	notify::notify(&from, IN_DELETE);
+	notify::notify(&to, IN_CREATE);
+	Ok(())
+    }
+
+    /// Replaces the file at the absolute `path` with `bytes`, so that it
+    /// holds either all of the old contents or all of the new ones, even if
+    /// power is lost meanwhile. The bytes go to a temporary file next to
+    /// `path`, which is synced and then renamed over it.
+    ///
+    /// A volume that cannot create or rename files, such as a FAT32 one, has
+    /// the existing file rewritten in place and synced instead, which is not
+    /// atomic: power lost meanwhile may leave it partly written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `path` has no file name, and
+    /// the errors of `create_temp()`, of writing the file and of `rename()`,
+    /// or, for a rewrite in place, those of `open_file()` and of writing.
+    pub fn write_atomic<P: AsRef<Path>>(&self, path: P, bytes: &[u8]) -> io::Result<()> {
+	let path = FileSystem::absolute(path.as_ref())?;
+	let (dir, name) = match (path.parent(), path.file_name().and_then(|name| name.to_str())) {
+	    (Some(dir), Some(name)) => (dir, name),
+	    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "path has no file name")),
+	};
+	// dropping the handle removes the file unless the rename moved it
+	let (temp, mut handle) = match self.create_temp(dir, &format!(".{}.", name)) {
+	    Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => return self.rewrite(&path, bytes),
+	    result => result?,
+	};
+	handle.write_all(bytes)?;
+	handle.flush()?;
+	handle.sync()?;
+	match self.rename(&temp, &path) {
+	    Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {
+		drop(handle);
+		self.rewrite(&path, bytes)
+	    },
+	    result => result,
+	}
+    }
+
+    /// Empties the file at the absolute `path`, writes `bytes` to it and
+    /// syncs it, for `write_atomic()` on a volume that cannot rename.
+    fn rewrite(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+	let mut file = self.open_file(path)?;
+	file.truncate()?;
+	file.write_all(bytes)?;
+	file.flush()?;
+	file.sync()
+    }
+
+    /// Returns the normalized `path`, which must be absolute.
+    fn absolute(path: &Path) -> io::Result<PathBuf> {
+	if !path.has_root() {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"));
+	}
+	fat32::path::resolve("/", path)
+    }
+
+    /// Like `lookup()`, for changing the directory entry at `path`.
+    fn writable_lookup<'a>(mounts: &'a [Mount], path: &Path) -> io::Result<(&'a Mount, PathBuf)> {
+	let (mount, rest) = FileSystem::lookup(mounts, path);
+	if mounts.iter().any(|m| m.path == path) {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is a mount point"));
+	}
+	if mount.read_only() {
+	    return Err(io::Error::from(OsError::ReadOnlyFs));
+	}
+	Ok((mount, rest))
+    }
+
+    /// Watches the directory at the absolute `path` for the events in `mask`,
+    /// a combination of `IN_CREATE`, `IN_MODIFY` and `IN_DELETE`.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors of `open()`, returns an error of
+    /// `InvalidInput` if `path` is not a directory.
+    pub fn watch<P: AsRef<Path>>(&self, path: P, mask: u64) -> io::Result<Box<dyn Handle>> {
+	if !self.open(path.as_ref())?.attr().directory {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a directory"));
+	}
+	let path = fat32::path::resolve("/", path)?;
+	Ok(Box::new(Watch::new(path, mask)))
+    }
+
+    /// Returns the mount holding the absolute, normalized `path`, and `path`
+    /// relative to the root of that volume.
+    fn lookup<'a>(mounts: &'a [Mount], path: &Path) -> (&'a Mount, PathBuf) {
+	let mount = mounts.iter()
+	    .filter(|m| path.starts_with(&m.path))
+	    .max_by_key(|m| m.path.components().count())
+	    .expect("root is mounted");
+	let rest = path.strip_prefix(&mount.path).expect("mount point is a prefix");
+	(mount, Path::new("/").join(rest))
+    }
+
+    /// Formats the partition of RAM disk `ram` as an empty FAT32 volume.
+    /// Partition `params.partition` of the SD card is named without `ram`,
+    /// but the SD card driver cannot write, so it is refused.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `PermissionDenied` for an SD card partition,
+    /// `InvalidInput` for a mounted RAM disk, `NotFound` for a RAM disk that
+    /// does not exist, or the error of `fat32::vfat::format()`.
+    pub fn format(&self, ram: Option<usize>, params: &FormatParams) -> Result<(), Error> {
+	let source = match ram {
+	    Some(n) => Source::Ram(n),
+	    None => Source::Partition(params.partition),
+	};
+	if self.is_mounted(&source) {
+	    return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "device is mounted")));
+	}
+	match ram {
+	    Some(n) => match RamDisk::open(n) {
+		Some(ram) => fat32::vfat::format(ram, params),
+		None => Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, "no such RAM disk"))),
+	    },
+	    // refused before anything is written, rather than failing on the
+	    // first sector with the volume half formatted
+	    None => Err(Error::Io(io::Error::new(io::ErrorKind::PermissionDenied, "the SD card driver cannot write"))),
+	}
+    }
+}
+
+/// Mounts the volume image on DEVICE: a single volume if the first sector is
+/// a FAT32 boot sector, or else the first partition of a disk image.
+fn mount_image<T: BlockDevice + 'static>(mut device: T) -> Result<(PiVFatHandle, MountReport), Error> {
+    let volume = match BiosParameterBlock::from(&mut device, 0) {
+	Ok(ebpb) => ebpb.signature(),
+	Err(_) => false,
+    };
+    match volume {
+	true => VFat::<PiVFatHandle>::mount_volume(device, 0, LookupMode::default()),
+	false => VFat::<PiVFatHandle>::mount_partition(device, 0, LookupMode::default()),
     }
 }
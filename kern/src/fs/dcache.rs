@@ -0,0 +1,127 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use shim::io;
+use shim::path::{Component, Path, PathBuf};
+
+use fat32::traits::Entry as _;
+use fat32::vfat::{Dir, Entry};
+
+use crate::fs::PiVFatHandle;
+use crate::param::DCACHE_ENTRIES;
+
+/// Lookups that found every component of the path cached, on any volume
+static HITS: AtomicU64 = AtomicU64::new(0);
+/// Lookups that read a directory for at least one component
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Counters of the directory entry caches, as listed in `/proc/meminfo`
+pub fn stats() -> (u64, u64) {
+    (HITS.load(Ordering::Relaxed), MISSES.load(Ordering::Relaxed))
+}
+
+struct Slot {
+    entry: Entry<PiVFatHandle>,
+    /// value of `DirCache::clock` when the entry was last looked up
+    used: u64,
+}
+
+/// The entries of a FAT volume found by recent lookups, by the path of their
+/// directory within the volume and the name they were looked up by. A cached
+/// entry is reread from its slot in the directory when it is used, a single
+/// entry rather than the whole directory, so that sizes and attributes
+/// changed through open files or removed entries are never served stale.
+pub struct DirCache {
+    entries: BTreeMap<(PathBuf, String), Slot>,
+    clock: u64,
+}
+
+impl DirCache {
+    pub fn new() -> DirCache {
+	DirCache { entries: BTreeMap::new(), clock: 0 }
+    }
+
+    /// Looks up the absolute, normalized `path` of the volume of `vfat`,
+    /// reading only the directories of components that are not cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `NotFound` if there is no entry at `path`,
+    /// `InvalidInput` if a component but the last is not a directory, and
+    /// the errors of reading the directories.
+    pub fn open(&mut self, vfat: &PiVFatHandle, path: &Path) -> io::Result<Entry<PiVFatHandle>> {
+	let mut entry = Dir::root(vfat);
+	let mut dir = PathBuf::from("/");
+	let mut missed = false;
+	for component in path.components() {
+	    let name = match component {
+		Component::RootDir => continue,
+		Component::Normal(name) => name.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid name"))?,
+		_ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not normalized")),
+	    };
+	    let key = (dir.clone(), String::from(name));
+	    entry = match self.lookup(&key) {
+		Some(cached) => cached,
+		None => {
+		    missed = true;
+		    let found = match entry.as_dir() {
+			Some(directory) => directory.find(name)?,
+			None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a directory")),
+		    };
+		    self.insert(key, found.clone());
+		    found
+		},
+	    };
+	    dir.push(name);
+	}
+	match missed {
+	    true => MISSES.fetch_add(1, Ordering::Relaxed),
+	    false => HITS.fetch_add(1, Ordering::Relaxed),
+	};
+	Ok(entry)
+    }
+
+    /// Returns a current copy of the cached entry at `key`, dropping it if
+    /// its slot no longer holds it.
+    fn lookup(&mut self, key: &(PathBuf, String)) -> Option<Entry<PiVFatHandle>> {
+	self.clock += 1;
+	let clock = self.clock;
+	let mut entry = {
+	    let slot = self.entries.get_mut(key)?;
+	    slot.used = clock;
+	    slot.entry.clone()
+	};
+	match entry.refresh() {
+	    Ok(()) => Some(entry),
+	    Err(_) => {
+		self.invalidate(&key.0.join(&key.1));
+		None
+	    },
+	}
+    }
+
+    fn insert(&mut self, key: (PathBuf, String), entry: Entry<PiVFatHandle>) {
+	if self.entries.len() >= DCACHE_ENTRIES && !self.entries.contains_key(&key) {
+	    let victim = self.entries.iter().min_by_key(|(_, slot)| slot.used).map(|(key, _)| key.clone());
+	    if let Some(victim) = victim {
+		self.entries.remove(&victim);
+	    }
+	}
+	let used = self.clock;
+	self.entries.insert(key, Slot { entry, used });
+    }
+
+    /// Drops the entry at the absolute, normalized `path` of the volume and
+    /// every entry below it, for when it is removed or replaced.
+    pub fn invalidate(&mut self, path: &Path) {
+	let stale: Vec<_> = self.entries.keys()
+	    .filter(|(dir, name)| dir.starts_with(path) || dir.join(name) == path)
+	    .cloned()
+	    .collect();
+	for key in stale {
+	    self.entries.remove(&key);
+	}
+    }
+}
@@ -0,0 +1,334 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::str::FromStr;
+
+use shim::io::{self, Read, Seek, SeekFrom, Write};
+
+use fat32::traits::BlockDevice;
+use fat32::MasterBootRecord;
+
+use crate::fs::iostat::{self, Device, Dir};
+use crate::fs::sd::Sd;
+use crate::mutex::Mutex;
+
+/// Sectors the SD card driver can address
+const SD_SECTORS: u64 = 0x8000_0000;
+
+/// Sectors `[start, start + sectors)` of a block device as a byte stream, so
+/// that devices can be copied like files. Partial sectors are read, patched
+/// and written back.
+pub struct BlockFile<T: BlockDevice> {
+    device: T,
+    start: u64,
+    sectors: u64,
+    position: u64,
+    buf: Vec<u8>,
+}
+
+impl<T: BlockDevice> BlockFile<T> {
+    pub fn new(device: T, start: u64, sectors: u64) -> BlockFile<T> {
+	let sector_size = device.sector_size() as usize;
+	BlockFile { device: device, start: start, sectors: sectors, position: 0, buf: vec![0; sector_size] }
+    }
+
+    /// Size in bytes
+    pub fn len(&self) -> u64 {
+	self.sectors * self.device.sector_size()
+    }
+
+    /// Sector holding the current position and the offset in it.
+    fn locate(&self) -> (u64, usize) {
+	let sector_size = self.device.sector_size();
+	(self.start + self.position / sector_size, (self.position % sector_size) as usize)
+    }
+}
+
+impl<T: BlockDevice> Read for BlockFile<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	if self.position >= self.len() || buf.is_empty() {
+	    return Ok(0);
+	}
+	let (sector, offset) = self.locate();
+	self.device.read_sector(sector, &mut self.buf)?;
+	let bytes = min(min(buf.len(), self.buf.len() - offset) as u64, self.len() - self.position) as usize;
+	buf[..bytes].copy_from_slice(&self.buf[offset..offset + bytes]);
+	self.position += bytes as u64;
+	Ok(bytes)
+    }
+}
+
+impl<T: BlockDevice> Write for BlockFile<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	if buf.is_empty() {
+	    return Ok(0);
+	}
+	if self.position >= self.len() {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "write past the end of the device"));
+	}
+	let (sector, offset) = self.locate();
+	let bytes = min(buf.len(), self.buf.len() - offset);
+	if bytes < self.buf.len() {
+	    self.device.read_sector(sector, &mut self.buf)?;
+	}
+	self.buf[offset..offset + bytes].copy_from_slice(&buf[..bytes]);
+	self.device.write_sector(sector, &self.buf)?;
+	self.position += bytes as u64;
+	Ok(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	self.device.flush()
+    }
+}
+
+impl<T: BlockDevice> Seek for BlockFile<T> {
+    /// Seeks like a file: positions past the end are invalid.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+	let position = match pos {
+	    SeekFrom::Start(offset) => Some(offset),
+	    SeekFrom::End(offset) if offset >= 0 => self.len().checked_add(offset as u64),
+	    SeekFrom::End(offset) => self.len().checked_sub(offset.wrapping_neg() as u64),
+	    SeekFrom::Current(offset) if offset >= 0 => self.position.checked_add(offset as u64),
+	    SeekFrom::Current(offset) => self.position.checked_sub(offset.wrapping_neg() as u64),
+	};
+	match position {
+	    Some(position) if position <= self.len() => {
+		self.position = position;
+		Ok(position)
+	    },
+	    _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek outside of the device")),
+	}
+    }
+}
+
+/// Sector size of loop devices
+const LOOP_SECTOR_SIZE: u64 = 512;
+
+/// A file presented as a block device of 512 byte sectors, so that the image
+/// of a volume stored in a file can be mounted. A partial sector at the end
+/// of the file is not part of the device.
+pub struct LoopDevice<F: Read + Write + Seek> {
+    file: F,
+    sectors: u64,
+}
+
+impl<F: Read + Write + Seek> LoopDevice<F> {
+    pub fn new(mut file: F) -> io::Result<LoopDevice<F>> {
+	let len = file.seek(SeekFrom::End(0))?;
+	Ok(LoopDevice { file: file, sectors: len / LOOP_SECTOR_SIZE })
+    }
+
+    fn seek_sector(&mut self, n: u64) -> io::Result<()> {
+	if n >= self.sectors {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "sector past the end of the image"));
+	}
+	self.file.seek(SeekFrom::Start(n * LOOP_SECTOR_SIZE))?;
+	Ok(())
+    }
+}
+
+impl<F: Read + Write + Seek + Send> BlockDevice for LoopDevice<F> {
+    fn sector_size(&self) -> u64 {
+	LOOP_SECTOR_SIZE
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+	let len = min(buf.len(), LOOP_SECTOR_SIZE as usize);
+	self.seek_sector(n)?;
+	self.file.read_exact(&mut buf[..len])?;
+	Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+	let len = min(buf.len(), LOOP_SECTOR_SIZE as usize);
+	self.seek_sector(n)?;
+	self.file.write_all(&buf[..len])?;
+	Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	self.file.flush()
+    }
+}
+
+/// Sector size of RAM disks
+const RAM_SECTOR_SIZE: u64 = 512;
+
+/// Number of RAM disks that can exist at once
+pub const MAX_RAM_DISKS: usize = 4;
+
+/// Sectors of the RAM disks, by number
+static RAM_DISKS: Mutex<[Option<Vec<u8>>; MAX_RAM_DISKS]> = Mutex::new([None, None, None, None]);
+
+/// A disk held in memory, to measure the file system without the latency of
+/// the SD card and for scratch volumes. Like `Sd`, a handle only names the
+/// disk: its sectors are kept in a table, so that `/dev/ramN` and a volume
+/// mounted from the disk share them.
+#[derive(Debug, Clone, Copy)]
+pub struct RamDisk(usize);
+
+impl RamDisk {
+    /// Creates a zeroed RAM disk of `sectors` sectors with the lowest free
+    /// number. Its MBR holds one partition of type FAT32 covering the rest of
+    /// the disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `sectors` is less than 2 or does
+    /// not fit an MBR, and `Other` if all RAM disks exist.
+    pub fn create(sectors: u64) -> io::Result<RamDisk> {
+	if sectors < 2 || sectors > u32::max_value() as u64 {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid RAM disk size"));
+	}
+	let mut disks = RAM_DISKS.lock();
+	let n = match disks.iter().position(|disk| disk.is_none()) {
+	    Some(n) => n,
+	    None => return Err(io::Error::new(io::ErrorKind::Other, "no free RAM disk")),
+	};
+	let mut bytes = vec![0u8; (sectors * RAM_SECTOR_SIZE) as usize];
+	let pte = &mut bytes[446..462];
+	pte[4] = 0x0C;
+	pte[8..12].copy_from_slice(&1u32.to_le_bytes());
+	pte[12..16].copy_from_slice(&(sectors as u32 - 1).to_le_bytes());
+	bytes[510] = 0x55;
+	bytes[511] = 0xAA;
+	disks[n] = Some(bytes);
+	iostat::reset_device(Device::Ram(n));
+	Ok(RamDisk(n))
+    }
+
+    /// Returns the RAM disk numbered `n` if it exists.
+    pub fn open(n: usize) -> Option<RamDisk> {
+	match RAM_DISKS.lock().get(n) {
+	    Some(Some(_)) => Some(RamDisk(n)),
+	    _ => None,
+	}
+    }
+
+    pub fn number(&self) -> usize {
+	self.0
+    }
+
+    /// Size in sectors, or 0 once the disk is destroyed
+    pub fn sectors(&self) -> u64 {
+	RAM_DISKS.lock()[self.0].as_ref().map_or(0, |disk| disk.len() as u64 / RAM_SECTOR_SIZE)
+    }
+
+    /// Frees the disk's memory. Other handles to the disk fail from then on,
+    /// so it must not be mounted.
+    pub fn destroy(self) {
+	RAM_DISKS.lock()[self.0] = None;
+    }
+
+    /// Calls `f` with sector `n` of the disk.
+    fn with_sector<R>(&self, n: u64, f: impl FnOnce(&mut [u8]) -> R) -> io::Result<R> {
+	let mut disks = RAM_DISKS.lock();
+	let disk = match disks[self.0].as_mut() {
+	    Some(disk) => disk,
+	    None => return Err(io::Error::new(io::ErrorKind::NotFound, "RAM disk was destroyed")),
+	};
+	if n >= disk.len() as u64 / RAM_SECTOR_SIZE {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "sector past the end of the RAM disk"));
+	}
+	let start = (n * RAM_SECTOR_SIZE) as usize;
+	Ok(f(&mut disk[start..start + RAM_SECTOR_SIZE as usize]))
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn sector_size(&self) -> u64 {
+	RAM_SECTOR_SIZE
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+	let len = min(buf.len(), RAM_SECTOR_SIZE as usize);
+	iostat::measure(Device::Ram(self.0), Dir::Read, || {
+	    self.with_sector(n, |sector| buf[..len].copy_from_slice(&sector[..len]))?;
+	    Ok(len)
+	})
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+	let len = min(buf.len(), RAM_SECTOR_SIZE as usize);
+	iostat::measure(Device::Ram(self.0), Dir::Write, || {
+	    self.with_sector(n, |sector| sector[..len].copy_from_slice(&buf[..len]))?;
+	    Ok(len)
+	})
+    }
+}
+
+/// A block device of the kernel, as named in `/dev`
+pub enum Disk {
+    Sd(Sd),
+    Ram(RamDisk),
+}
+
+impl BlockDevice for Disk {
+    fn sector_size(&self) -> u64 {
+	match self {
+	    Disk::Sd(sd) => sd.sector_size(),
+	    Disk::Ram(ram) => ram.sector_size(),
+	}
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+	match self {
+	    Disk::Sd(sd) => sd.read_sector(n, buf),
+	    Disk::Ram(ram) => ram.read_sector(n, buf),
+	}
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+	match self {
+	    Disk::Sd(sd) => sd.write_sector(n, buf),
+	    Disk::Ram(ram) => ram.write_sector(n, buf),
+	}
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	match self {
+	    Disk::Sd(sd) => sd.flush(),
+	    Disk::Ram(ram) => ram.flush(),
+	}
+    }
+}
+
+/// Opens the device at `path`: `/dev/sd` is the whole SD card, `/dev/sd1` to
+/// `/dev/sd4` its partitions and `/dev/ramN` RAM disk N. Returns `None` if
+/// `path` names no device.
+///
+/// # Errors
+///
+/// Returns an error of `NotFound` for a partition or RAM disk that does not
+/// exist, or the error of reading the MBR.
+pub fn open(path: &str) -> Option<io::Result<BlockFile<Disk>>> {
+    if !path.starts_with("/dev/") {
+	return None;
+    }
+    let name = &path["/dev/".len()..];
+    if name.starts_with("ram") {
+	let n = usize::from_str(&name["ram".len()..]).ok()?;
+	return Some(match RamDisk::open(n) {
+	    Some(ram) => Ok(BlockFile::new(Disk::Ram(ram), 0, ram.sectors())),
+	    None => Err(io::Error::new(io::ErrorKind::NotFound, "no such RAM disk")),
+	});
+    }
+    let partition = match name {
+	"sd" => return Some(Ok(BlockFile::new(Disk::Sd(Sd), 0, SD_SECTORS))),
+	"sd1" => 0,
+	"sd2" => 1,
+	"sd3" => 2,
+	"sd4" => 3,
+	_ => return None,
+    };
+    let pte = MasterBootRecord::from(Sd)
+	.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid MBR"))
+	.map(|mbr| mbr.pte(partition));
+    Some(match pte {
+	Ok(Some(pte)) if pte.num_sectors() > 0 =>
+	    Ok(BlockFile::new(Disk::Sd(Sd), pte.start_sector() as u64, pte.num_sectors() as u64)),
+	Ok(_) => Err(io::Error::new(io::ErrorKind::NotFound, "no such partition")),
+	Err(e) => Err(e),
+    })
+}
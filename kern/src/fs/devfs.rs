@@ -0,0 +1,117 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::path::Path;
+
+use fat32::traits::BlockDevice;
+
+use crate::fs::dev::{self, BlockFile, MAX_RAM_DISKS};
+use crate::fs::vfs::{Attr, Handle, Vfs, Vnode};
+
+/// The block devices of the kernel as files, mounted on `/dev`: `sd` is the
+/// whole SD card, `sd1` to `sd4` its partitions and `ramN` the RAM disks.
+pub struct DevFs;
+
+impl Vfs for DevFs {
+    fn fs_type(&self) -> &'static str {
+	"devfs"
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Vnode>> {
+	let name = match path.to_str() {
+	    Some("/") => return Ok(Box::new(DevDir)),
+	    Some(path) => &path[1..],
+	    None => return Err(io::Error::new(io::ErrorKind::NotFound, "no such device")),
+	};
+	Ok(Box::new(DevNode::open(name)?))
+    }
+
+    fn read_only(&self) -> bool {
+	false
+    }
+}
+
+/// The root of `/dev`
+struct DevDir;
+
+impl Vnode for DevDir {
+    fn name(&self) -> &str {
+	""
+    }
+
+    fn attr(&self) -> Attr {
+	Attr { directory: true, ..Attr::default() }
+    }
+
+    /// Lists the devices that exist: partitions missing from the MBR and
+    /// RAM disks not created are left out.
+    fn entries(&self) -> io::Result<Vec<Box<dyn Vnode>>> {
+	let mut names: Vec<String> = ["sd", "sd1", "sd2", "sd3", "sd4"].iter().map(|&name| String::from(name)).collect();
+	for n in 0..MAX_RAM_DISKS {
+	    let mut name = String::from("ram");
+	    name.push((b'0' + n as u8) as char);
+	    names.push(name);
+	}
+	Ok(names.iter()
+	   .filter_map(|name| DevNode::open(name).ok())
+	   .map(|node| Box::new(node) as Box<dyn Vnode>)
+	   .collect())
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Handle>> {
+	Err(io::Error::new(io::ErrorKind::Other, "not a regular file"))
+    }
+}
+
+/// A device in `/dev`
+struct DevNode {
+    name: String,
+    size: u64,
+}
+
+impl DevNode {
+    fn open(name: &str) -> io::Result<DevNode> {
+	let file = DevNode::device(name)?;
+	Ok(DevNode { name: String::from(name), size: file.len() })
+    }
+
+    fn device(name: &str) -> io::Result<BlockFile<dev::Disk>> {
+	let mut path = String::from("/dev/");
+	path.push_str(name);
+	match dev::open(&path) {
+	    Some(file) => file,
+	    None => Err(io::Error::new(io::ErrorKind::NotFound, "no such device")),
+	}
+    }
+}
+
+impl Vnode for DevNode {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn attr(&self) -> Attr {
+	Attr { size: self.size, system: true, ..Attr::default() }
+    }
+
+    fn entries(&self) -> io::Result<Vec<Box<dyn Vnode>>> {
+	Err(io::Error::new(io::ErrorKind::Other, "not a directory"))
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Handle>> {
+	Ok(Box::new(DevNode::device(&self.name)?))
+    }
+}
+
+impl<T: BlockDevice> Handle for BlockFile<T> {
+    fn size(&self) -> u64 {
+	self.len()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+	use shim::io::Write;
+	self.flush()
+    }
+}
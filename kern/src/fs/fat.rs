@@ -0,0 +1,124 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::path::Path;
+
+use fat32::traits::{self, Dir as _, Entry as _, Metadata as _};
+use fat32::vfat::{Entry, File, VFatHandle};
+
+use crate::fs::dcache::DirCache;
+use crate::fs::vfs::{Attr, Handle, Time, Vfs, Vnode};
+use crate::fs::PiVFatHandle;
+use crate::mutex::Mutex;
+
+/// A mounted FAT32 volume and the entries its recent lookups found
+pub struct FatFs(pub PiVFatHandle, Mutex<DirCache>);
+
+impl FatFs {
+    pub fn new(vfat: PiVFatHandle) -> FatFs {
+	FatFs(vfat, Mutex::new(DirCache::new()))
+    }
+}
+
+impl Vfs for FatFs {
+    fn fs_type(&self) -> &'static str {
+	"vfat"
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Vnode>> {
+	Ok(Box::new(FatNode(self.1.lock().open(&self.0, path)?)))
+    }
+
+    fn read_only(&self) -> bool {
+	self.0.with(|v| v.read_only())
+    }
+
+    fn set_read_only(&self, read_only: bool) -> io::Result<()> {
+	self.0.with(|v| {
+	    // syncing records the volume as clean, so that a read only volume
+	    // is not checked on the next mount
+	    v.set_read_only(read_only);
+	    match read_only {
+		true => v.sync().map_err(|e| {
+		    v.set_read_only(false);
+		    e
+		}),
+		false => Ok(()),
+	    }
+	})
+    }
+
+    fn page_cache(&self) -> bool {
+	true
+    }
+
+    fn unmount(&self) -> io::Result<()> {
+	self.0.with(|v| v.unmount())
+    }
+}
+
+/// A file or directory of a FAT32 volume
+struct FatNode(Entry<PiVFatHandle>);
+
+impl Vnode for FatNode {
+    fn name(&self) -> &str {
+	self.0.name()
+    }
+
+    fn attr(&self) -> Attr {
+	let metadata = self.0.metadata();
+	Attr {
+	    directory: self.0.is_dir(),
+	    // with FAT+ the size may not fit the directory entry's
+	    size: self.0.as_file().map_or(0, |file| traits::File::size(file)),
+	    read_only: metadata.read_only(),
+	    hidden: metadata.hidden(),
+	    system: metadata.system(),
+	    archive: metadata.archive(),
+	    created: Time::from(metadata.created()),
+	    modified: Time::from(metadata.modified()),
+	}
+    }
+
+    fn entries(&self) -> io::Result<Vec<Box<dyn Vnode>>> {
+	match self.0.as_dir() {
+	    Some(dir) => Ok(dir.entries()?.map(|entry| Box::new(FatNode(entry)) as Box<dyn Vnode>).collect()),
+	    None => Err(io::Error::new(io::ErrorKind::Other, "not a directory")),
+	}
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Handle>> {
+	match self.0.as_file() {
+	    Some(file) => Ok(Box::new(file.clone())),
+	    None => Err(io::Error::new(io::ErrorKind::Other, "not a regular file")),
+	}
+    }
+
+    fn set_read_only(&mut self, read_only: bool) -> io::Result<()> {
+	self.0.set_read_only(read_only)
+    }
+
+    fn set_hidden(&mut self, hidden: bool) -> io::Result<()> {
+	self.0.set_hidden(hidden)
+    }
+}
+
+impl Handle for File<PiVFatHandle> {
+    fn size(&self) -> u64 {
+	traits::File::size(self)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+	traits::File::sync(self)
+    }
+
+    fn truncate(&mut self) -> io::Result<()> {
+	File::truncate(self)
+    }
+
+    fn extents(&self) -> io::Result<Vec<(u64, u64)>> {
+	let extents = File::extents(self)?;
+	Ok(extents.iter().map(|&(start, len)| (start.number() as u64, len as u64)).collect())
+    }
+}
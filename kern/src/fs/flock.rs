@@ -0,0 +1,130 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use shim::io;
+use shim::path::{Path, PathBuf};
+
+use crate::fs::vfs::{Handle, LockKind};
+use crate::mutex::Mutex;
+
+/// The advisory lock on one file and the open files holding it
+#[derive(Debug)]
+struct FileLock {
+    /// absolute, normalized path of the file
+    path: PathBuf,
+    exclusive: bool,
+    holders: Vec<u64>,
+}
+
+/// Every file that is locked
+static LOCKS: Mutex<Option<Vec<FileLock>>> = Mutex::new(None);
+
+/// Source of the IDs of the open files that can hold locks
+static NEXT_HOLDER: AtomicU64 = AtomicU64::new(1);
+
+/// Takes or converts the lock of `holder` on the file at `path`.
+fn acquire(path: &Path, holder: u64, exclusive: bool) -> io::Result<()> {
+    let mut guard = LOCKS.lock();
+    let locks = guard.get_or_insert_with(Vec::new);
+    let lock = match locks.iter_mut().find(|lock| lock.path.as_path() == path) {
+	Some(lock) => lock,
+	None => {
+	    locks.push(FileLock { path: path.to_path_buf(), exclusive, holders: vec![holder] });
+	    return Ok(());
+	},
+    };
+    let others = lock.holders.iter().any(|&other| other != holder);
+    if others && (exclusive || lock.exclusive) {
+	return Err(io::Error::new(io::ErrorKind::WouldBlock, "file is locked"));
+    }
+    lock.exclusive = exclusive;
+    if !lock.holders.contains(&holder) {
+	lock.holders.push(holder);
+    }
+    Ok(())
+}
+
+/// Releases the lock of `holder` on the file at `path`, if it holds one.
+fn release(path: &Path, holder: u64) {
+    if let Some(locks) = LOCKS.lock().as_mut() {
+	for lock in locks.iter_mut().filter(|lock| lock.path.as_path() == path) {
+	    lock.holders.retain(|&other| other != holder);
+	}
+	locks.retain(|lock| !lock.holders.is_empty());
+    }
+}
+
+/// A regular file that can take an advisory lock on the file it was opened
+/// from, identified by its path. The lock is released when it is dropped.
+pub struct Locking {
+    path: PathBuf,
+    holder: u64,
+    handle: Box<dyn Handle>,
+}
+
+impl Locking {
+    /// Wraps `handle`, the file at the absolute, normalized `path`.
+    pub fn new(path: PathBuf, handle: Box<dyn Handle>) -> Locking {
+	let holder = NEXT_HOLDER.fetch_add(1, Ordering::Relaxed);
+	Locking { path, holder, handle }
+    }
+}
+
+impl io::Read for Locking {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	self.handle.read(buf)
+    }
+}
+
+impl io::Write for Locking {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	self.handle.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	self.handle.flush()
+    }
+}
+
+impl io::Seek for Locking {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+	self.handle.seek(pos)
+    }
+}
+
+impl Handle for Locking {
+    fn size(&self) -> u64 {
+	self.handle.size()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+	self.handle.sync()
+    }
+
+    fn truncate(&mut self) -> io::Result<()> {
+	self.handle.truncate()
+    }
+
+    fn lock(&mut self, kind: LockKind) -> io::Result<()> {
+	match kind {
+	    LockKind::Shared => acquire(&self.path, self.holder, false),
+	    LockKind::Exclusive => acquire(&self.path, self.holder, true),
+	    LockKind::Unlock => {
+		release(&self.path, self.holder);
+		Ok(())
+	    },
+	}
+    }
+
+    fn extents(&self) -> io::Result<Vec<(u64, u64)>> {
+	self.handle.extents()
+    }
+}
+
+impl Drop for Locking {
+    fn drop(&mut self) {
+	release(&self.path, self.holder);
+    }
+}
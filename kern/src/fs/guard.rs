@@ -0,0 +1,77 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use kernel_api::OsError;
+use shim::io;
+
+use crate::fs::vfs::{Handle, LockKind};
+
+/// An open file that refuses writes while the volume holding it is mounted
+/// read only. The flag is the mount's own, so a remount takes effect on
+/// files that are already open.
+pub struct Guarded {
+    read_only: Arc<AtomicBool>,
+    handle: Box<dyn Handle>,
+}
+
+impl Guarded {
+    /// Wraps `handle`, a file of the mount whose flag is `read_only`.
+    pub fn new(read_only: Arc<AtomicBool>, handle: Box<dyn Handle>) -> Guarded {
+	Guarded { read_only, handle }
+    }
+
+    fn check(&self) -> io::Result<()> {
+	match self.read_only.load(Ordering::Acquire) {
+	    true => Err(io::Error::from(OsError::ReadOnlyFs)),
+	    false => Ok(()),
+	}
+    }
+}
+
+impl io::Read for Guarded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	self.handle.read(buf)
+    }
+}
+
+impl io::Write for Guarded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	self.check()?;
+	self.handle.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	self.handle.flush()
+    }
+}
+
+impl io::Seek for Guarded {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+	self.handle.seek(pos)
+    }
+}
+
+impl Handle for Guarded {
+    fn size(&self) -> u64 {
+	self.handle.size()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+	self.handle.sync()
+    }
+
+    fn truncate(&mut self) -> io::Result<()> {
+	self.check()?;
+	self.handle.truncate()
+    }
+
+    fn lock(&mut self, kind: LockKind) -> io::Result<()> {
+	self.handle.lock(kind)
+    }
+
+    fn extents(&self) -> io::Result<Vec<(u64, u64)>> {
+	self.handle.extents()
+    }
+}
@@ -0,0 +1,185 @@
+use core::cmp::{max, min};
+use core::fmt;
+
+use pi::timer::current_time;
+use shim::io;
+
+use crate::fs::dev::MAX_RAM_DISKS;
+use crate::mutex::Mutex;
+
+/// Buckets of a latency histogram: bucket `i` counts transfers that took less
+/// than 2^i microseconds but not less than half of that, the last one also
+/// the slower ones.
+const BUCKETS: usize = 24;
+
+/// A block device whose transfers are counted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Device {
+    Sd,
+    Ram(usize),
+}
+
+impl Device {
+    fn index(&self) -> usize {
+	match self {
+	    Device::Sd => 0,
+	    Device::Ram(n) => 1 + n,
+	}
+    }
+
+    fn from_index(index: usize) -> Device {
+	match index {
+	    0 => Device::Sd,
+	    n => Device::Ram(n - 1),
+	}
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    Device::Sd => f.write_str("sd"),
+	    Device::Ram(n) => write!(f, "ram{}", n),
+	}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dir {
+    Read,
+    Write,
+}
+
+/// Latencies of transfers in power of two buckets of microseconds
+#[derive(Debug, Clone, Copy)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+}
+
+impl Histogram {
+    const fn new() -> Histogram {
+	Histogram { buckets: [0; BUCKETS] }
+    }
+
+    fn record(&mut self, micros: u64) {
+	let bucket = (64 - micros.leading_zeros()) as usize;
+	self.buckets[min(bucket, BUCKETS - 1)] += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+	self.buckets.iter().sum()
+    }
+
+    /// The latency in microseconds that `percent` percent of the transfers
+    /// took less than, rounded up to a power of two. `None` without
+    /// transfers.
+    pub fn percentile(&self, percent: u64) -> Option<u64> {
+	let count = self.count();
+	if count == 0 {
+	    return None;
+	}
+	let rank = max((count * percent + 99) / 100, 1);
+	let mut seen = 0;
+	for (i, n) in self.buckets.iter().enumerate() {
+	    seen += n;
+	    if seen >= rank {
+		return Some(1 << i);
+	    }
+	}
+	Some(1 << (BUCKETS - 1))
+    }
+}
+
+/// Transfers of a device since boot or the last `reset()`
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub read_bytes: u64,
+    pub written_bytes: u64,
+    /// failed transfers, which are in no other count
+    pub errors: u64,
+    /// transfers in progress
+    pub queue_depth: u32,
+    pub max_queue_depth: u32,
+    pub read_latency: Histogram,
+    pub write_latency: Histogram,
+}
+
+impl DeviceStats {
+    const fn new() -> DeviceStats {
+	DeviceStats {
+	    reads: 0,
+	    writes: 0,
+	    read_bytes: 0,
+	    written_bytes: 0,
+	    errors: 0,
+	    queue_depth: 0,
+	    max_queue_depth: 0,
+	    read_latency: Histogram::new(),
+	    write_latency: Histogram::new(),
+	}
+    }
+
+    /// Returns true if the device has not transferred anything.
+    pub fn is_idle(&self) -> bool {
+	self.reads == 0 && self.writes == 0 && self.errors == 0 && self.queue_depth == 0
+    }
+}
+
+/// Statistics of the SD card, then of each RAM disk
+static STATS: Mutex<[DeviceStats; 1 + MAX_RAM_DISKS]> = Mutex::new([DeviceStats::new(); 1 + MAX_RAM_DISKS]);
+
+/// Runs `f`, a transfer of `dir` on `device` returning the bytes transferred,
+/// and records it.
+pub fn measure(device: Device, dir: Dir, f: impl FnOnce() -> io::Result<usize>) -> io::Result<usize> {
+    {
+	let mut stats = STATS.lock();
+	let stats = &mut stats[device.index()];
+	stats.queue_depth += 1;
+	stats.max_queue_depth = max(stats.max_queue_depth, stats.queue_depth);
+    }
+    let start = current_time();
+    let result = f();
+    let micros = (current_time() - start).as_micros() as u64;
+
+    let mut stats = STATS.lock();
+    let stats = &mut stats[device.index()];
+    stats.queue_depth -= 1;
+    match (&result, dir) {
+	(Ok(bytes), Dir::Read) => {
+	    stats.reads += 1;
+	    stats.read_bytes += *bytes as u64;
+	    stats.read_latency.record(micros);
+	},
+	(Ok(bytes), Dir::Write) => {
+	    stats.writes += 1;
+	    stats.written_bytes += *bytes as u64;
+	    stats.write_latency.record(micros);
+	},
+	(Err(_), _) => stats.errors += 1,
+    }
+    result
+}
+
+/// Calls `f` with the statistics of every device, the SD card first.
+pub fn for_each<F: FnMut(Device, &DeviceStats)>(mut f: F) {
+    let stats = *STATS.lock();
+    for (i, stats) in stats.iter().enumerate() {
+	f(Device::from_index(i), stats);
+    }
+}
+
+/// Clears the statistics of all devices but their transfers in progress.
+pub fn reset() {
+    for i in 0..1 + MAX_RAM_DISKS {
+	reset_device(Device::from_index(i));
+    }
+}
+
+/// Clears the statistics of `device` but its transfers in progress.
+pub fn reset_device(device: Device) {
+    let mut stats = STATS.lock();
+    let stats = &mut stats[device.index()];
+    *stats = DeviceStats { queue_depth: stats.queue_depth, ..DeviceStats::new() };
+}
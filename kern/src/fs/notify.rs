@@ -0,0 +1,193 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::path::{Path, PathBuf};
+
+use kernel_api::{WatchEvent, IN_MODIFY, IN_Q_OVERFLOW};
+
+use crate::fs::vfs::{Handle, LockKind};
+use crate::mutex::Mutex;
+
+/// Events a watch queues before it reports an overflow and drops the rest
+const QUEUE_LEN: usize = 64;
+
+/// The pending events of one watch
+#[derive(Debug)]
+struct Queue {
+    /// absolute, normalized path of the watched directory
+    dir: PathBuf,
+    mask: u64,
+    events: VecDeque<(u64, String)>,
+}
+
+impl Queue {
+    /// Queues an event unless it repeats the last one. The last free slot
+    /// holds an overflow event.
+    fn push(&mut self, mask: u64, name: &str) {
+	if let Some((last, last_name)) = self.events.back() {
+	    if *last == mask && last_name == name {
+		return;
+	    }
+	}
+	if self.events.len() + 1 < QUEUE_LEN {
+	    self.events.push_back((mask, String::from(name)));
+	}
+	else if self.events.len() + 1 == QUEUE_LEN {
+	    self.events.push_back((IN_Q_OVERFLOW, String::new()));
+	}
+    }
+}
+
+/// Every watch, dropped from the list once its descriptor is closed
+static WATCHES: Mutex<Option<Vec<Weak<Mutex<Queue>>>>> = Mutex::new(None);
+
+/// Reports the event `mask` on the entry at the absolute, normalized `path` to
+/// the watches of its directory.
+pub fn notify(path: &Path, mask: u64) {
+    let (dir, name) = match (path.parent(), path.file_name().and_then(|name| name.to_str())) {
+	(Some(dir), Some(name)) => (dir, name),
+	_ => return,
+    };
+    if let Some(watches) = WATCHES.lock().as_mut() {
+	watches.retain(|watch| match watch.upgrade() {
+	    Some(queue) => {
+		let mut queue = queue.lock();
+		if queue.dir.as_path() == dir && queue.mask & mask != 0 {
+		    queue.push(mask, name);
+		}
+		true
+	    },
+	    None => false,
+	});
+    }
+}
+
+/// A descriptor receiving the events on the entries of a directory. Reads
+/// return whole events encoded by `WatchEvent::encode()`.
+pub struct Watch(Arc<Mutex<Queue>>);
+
+impl Watch {
+    /// Starts watching the absolute, normalized `dir` for the events in `mask`.
+    pub fn new(dir: PathBuf, mask: u64) -> Watch {
+	let queue = Arc::new(Mutex::new(Queue { dir, mask, events: VecDeque::new() }));
+	WATCHES.lock().get_or_insert_with(Vec::new).push(Arc::downgrade(&queue));
+	Watch(queue)
+    }
+}
+
+impl io::Read for Watch {
+    /// Moves as many queued events as fit into `buf`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `WouldBlock` if no event is queued and one of
+    /// `InvalidInput` if the next one does not fit into `buf`.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	let mut queue = self.0.lock();
+	if queue.events.is_empty() {
+	    return Err(io::Error::new(io::ErrorKind::WouldBlock, "no events"));
+	}
+	let mut read = 0;
+	while let Some((mask, name)) = queue.events.front() {
+	    match (WatchEvent { mask: *mask, name }).encode(&mut buf[read..]) {
+		Some(len) => read += len,
+		None => break,
+	    }
+	    queue.events.pop_front();
+	}
+	match read {
+	    0 => Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer too small for the next event")),
+	    _ => Ok(read),
+	}
+    }
+}
+
+impl io::Write for Watch {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+	Err(io::Error::new(io::ErrorKind::PermissionDenied, "watches cannot be written"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	Ok(())
+    }
+}
+
+impl io::Seek for Watch {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+	Err(io::Error::new(io::ErrorKind::InvalidInput, "watches cannot seek"))
+    }
+}
+
+impl Handle for Watch {
+    fn size(&self) -> u64 {
+	0
+    }
+}
+
+/// A regular file that reports its writes and truncation as `IN_MODIFY`
+/// events
+pub struct Notifying {
+    path: PathBuf,
+    handle: Box<dyn Handle>,
+}
+
+impl Notifying {
+    /// Wraps `handle`, the file at the absolute, normalized `path`.
+    pub fn new(path: PathBuf, handle: Box<dyn Handle>) -> Notifying {
+	Notifying { path, handle }
+    }
+}
+
+impl io::Read for Notifying {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	self.handle.read(buf)
+    }
+}
+
+impl io::Write for Notifying {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	let written = self.handle.write(buf)?;
+	if written > 0 {
+	    notify(&self.path, IN_MODIFY);
+	}
+	Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	self.handle.flush()
+    }
+}
+
+impl io::Seek for Notifying {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+	self.handle.seek(pos)
+    }
+}
+
+impl Handle for Notifying {
+    fn size(&self) -> u64 {
+	self.handle.size()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+	self.handle.sync()
+    }
+
+    fn truncate(&mut self) -> io::Result<()> {
+	self.handle.truncate()?;
+	notify(&self.path, IN_MODIFY);
+	Ok(())
+    }
+
+    fn lock(&mut self, kind: LockKind) -> io::Result<()> {
+	self.handle.lock(kind)
+    }
+
+    fn extents(&self) -> io::Result<Vec<(u64, u64)>> {
+	self.handle.extents()
+    }
+}
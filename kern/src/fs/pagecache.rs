@@ -0,0 +1,353 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use core::ops::{Deref, DerefMut};
+
+use shim::io;
+use shim::path::{Path, PathBuf};
+
+use crate::fs::vfs::{Handle, LockKind};
+use crate::mutex::Mutex;
+use crate::param::{PAGE_CACHE_PAGES, PAGE_SIZE};
+use crate::vm::{frame, PhysicalAddr};
+
+/// A page sized and aligned block of memory, so that the memory manager can
+/// map a cached page into a process as it is.
+struct Frame(*mut u8);
+
+// a frame is only reached through the lock of its page
+unsafe impl Send for Frame {}
+
+impl Frame {
+    /// Allocates a zeroed frame, `None` if memory is exhausted.
+    fn new() -> Option<Frame> {
+	Some(Frame(frame::alloc()?.as_mut_ptr()))
+    }
+}
+
+impl Deref for Frame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+	unsafe { core::slice::from_raw_parts(self.0, PAGE_SIZE) }
+    }
+}
+
+impl DerefMut for Frame {
+    fn deref_mut(&mut self) -> &mut [u8] {
+	unsafe { core::slice::from_raw_parts_mut(self.0, PAGE_SIZE) }
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+	frame::release(PhysicalAddr::from(self.0));
+    }
+}
+
+/// `PAGE_SIZE` bytes of a file starting at a multiple of `PAGE_SIZE`, of
+/// which the first `len` are in the file. The rest of the frame is zero.
+pub struct CachedPage {
+    frame: Frame,
+    len: usize,
+}
+
+/// The bytes of a cached page from an offset to the end of the file in it,
+/// read in place. The page is not locked, so writes to the file and
+/// processes mapping the page may change the bytes as they are read, as
+/// they would for a reader of the mapping.
+pub struct PageSlice {
+    /// holds the frame, `None` for the empty slice past the end of a file
+    _page: Option<Arc<Mutex<CachedPage>>>,
+    start: *const u8,
+    len: usize,
+}
+
+impl Deref for PageSlice {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+	unsafe { core::slice::from_raw_parts(self.start, self.len) }
+    }
+}
+
+struct Entry {
+    page: Arc<Mutex<CachedPage>>,
+    /// value of `PageCache::clock` when the page was last looked up
+    used: u64,
+}
+
+/// The cached pages of every file, by the absolute, normalized path of the
+/// file and the index of the page in it
+struct PageCache {
+    pages: BTreeMap<(PathBuf, u64), Entry>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+static PAGE_CACHE: Mutex<Option<PageCache>> = Mutex::new(None);
+
+/// Counters of the page cache, as listed in `/proc/meminfo`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    /// pages held
+    pub pages: usize,
+    /// lookups that found the page cached
+    pub hits: u64,
+    /// lookups that read the page from its file system
+    pub misses: u64,
+}
+
+pub fn stats() -> Stats {
+    match PAGE_CACHE.lock().as_ref() {
+	Some(cache) => Stats { pages: cache.pages.len(), hits: cache.hits, misses: cache.misses },
+	None => Stats::default(),
+    }
+}
+
+/// Returns page `index` of the file at `path`, reading it from `handle`, an
+/// open handle of the file of `size` bytes, if it is not cached or no longer
+/// matches the size. Pages found in use by a mapping or another reader are
+/// shared, not copied. A page that does not fit in the cache is returned all
+/// the same, without being cached.
+///
+/// # Errors
+///
+/// Returns an error of `Other` if there is no memory for the page, or the
+/// error of reading it.
+pub fn page(path: &Path, index: u64, size: u64, handle: &mut dyn Handle) -> io::Result<Arc<Mutex<CachedPage>>> {
+    let start = index * PAGE_SIZE as u64;
+    let expected = min(size.saturating_sub(start), PAGE_SIZE as u64) as usize;
+    {
+	let mut guard = PAGE_CACHE.lock();
+	let cache = guard.get_or_insert_with(|| PageCache { pages: BTreeMap::new(), clock: 0, hits: 0, misses: 0 });
+	cache.clock += 1;
+	let clock = cache.clock;
+	if let Some(entry) = cache.pages.get_mut(&(path.to_path_buf(), index)) {
+	    // a page shorter or longer than the file is from before it was
+	    // extended or truncated elsewhere
+	    if entry.page.lock().len == expected {
+		entry.used = clock;
+		cache.hits += 1;
+		return Ok(entry.page.clone());
+	    }
+	}
+	cache.misses += 1;
+    }
+
+    let page = Arc::new(Mutex::new(fill(handle, start, expected)?));
+    let mut guard = PAGE_CACHE.lock();
+    let cache = guard.as_mut().expect("page cache is initialized");
+    let key = (path.to_path_buf(), index);
+    if cache.pages.len() >= PAGE_CACHE_PAGES && !cache.pages.contains_key(&key) && !evict(cache) {
+	return Ok(page);
+    }
+    let used = cache.clock;
+    cache.pages.insert(key, Entry { page: page.clone(), used });
+    Ok(page)
+}
+
+/// Reads `len` bytes at `start` of the file of `handle` into a new page.
+fn fill(handle: &mut dyn Handle, start: u64, len: usize) -> io::Result<CachedPage> {
+    let mut frame = Frame::new();
+    if frame.is_none() && PAGE_CACHE.lock().as_mut().map_or(false, evict) {
+	frame = Frame::new();
+    }
+    let mut frame = frame.ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no memory for the page"))?;
+    handle.seek(io::SeekFrom::Start(start))?;
+    let mut read = 0;
+    while read < len {
+	match handle.read(&mut frame[read..len])? {
+	    0 => break,
+	    n => read += n,
+	}
+    }
+    Ok(CachedPage { frame, len: read })
+}
+
+/// Drops the least recently used page nobody else holds. Returns false if
+/// every page is in use.
+fn evict(cache: &mut PageCache) -> bool {
+    let victim = cache.pages.iter()
+	.filter(|(_, entry)| Arc::strong_count(&entry.page) == 1)
+	.min_by_key(|(_, entry)| entry.used)
+	.map(|(key, _)| key.clone());
+    match victim {
+	Some(key) => cache.pages.remove(&key).is_some(),
+	None => false,
+    }
+}
+
+/// Copies `data`, just written at `offset` of the file at `path`, into the
+/// pages of the file that are cached.
+fn update(path: &Path, offset: u64, data: &[u8]) {
+    if data.is_empty() {
+	return;
+    }
+    let first = offset / PAGE_SIZE as u64;
+    let last = (offset + data.len() as u64 - 1) / PAGE_SIZE as u64;
+    let guard = PAGE_CACHE.lock();
+    let cache = match guard.as_ref() {
+	Some(cache) => cache,
+	None => return,
+    };
+    for index in first..=last {
+	if let Some(entry) = cache.pages.get(&(path.to_path_buf(), index)) {
+	    let start = index * PAGE_SIZE as u64;
+	    let from = max(offset, start);
+	    let to = min(offset + data.len() as u64, start + PAGE_SIZE as u64);
+	    let mut page = entry.page.lock();
+	    let src = &data[(from - offset) as usize..(to - offset) as usize];
+	    page.frame[(from - start) as usize..(to - start) as usize].copy_from_slice(src);
+	    page.len = max(page.len, (to - start) as usize);
+	}
+    }
+}
+
+/// Drops the cached pages of the file at the absolute, normalized `path`,
+/// for when it is truncated or removed. Holders of a page keep their copy.
+pub fn invalidate(path: &Path) {
+    if let Some(cache) = PAGE_CACHE.lock().as_mut() {
+	let stale: Vec<_> = cache.pages.keys().filter(|(file, _)| file.as_path() == path).cloned().collect();
+	for key in stale {
+	    cache.pages.remove(&key);
+	}
+    }
+}
+
+/// Drops the cached pages of every file below the absolute, normalized
+/// `dir`, for when a volume is mounted on it or unmounted from it.
+pub fn invalidate_under(dir: &Path) {
+    if let Some(cache) = PAGE_CACHE.lock().as_mut() {
+	let stale: Vec<_> = cache.pages.keys().filter(|(file, _)| file.starts_with(dir)).cloned().collect();
+	for key in stale {
+	    cache.pages.remove(&key);
+	}
+    }
+}
+
+/// A regular file read through the page cache. Writes go to the file system
+/// right away and are copied into the cached pages, so every open file of
+/// the path reads the same bytes.
+pub struct Cached {
+    path: PathBuf,
+    handle: Box<dyn Handle>,
+    position: u64,
+}
+
+impl Cached {
+    /// Wraps `handle`, the file at the absolute, normalized `path`.
+    pub fn new(path: PathBuf, handle: Box<dyn Handle>) -> Cached {
+	Cached { path, handle, position: 0 }
+    }
+}
+
+impl io::Read for Cached {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	let size = self.handle.size();
+	let mut read = 0;
+	while read < buf.len() && self.position < size {
+	    let index = self.position / PAGE_SIZE as u64;
+	    let offset = (self.position % PAGE_SIZE as u64) as usize;
+	    let page = page(&self.path, index, size, &mut *self.handle)?;
+	    let page = page.lock();
+	    if offset >= page.len {
+		break;
+	    }
+	    let count = min(buf.len() - read, page.len - offset);
+	    buf[read..read + count].copy_from_slice(&page.frame[offset..offset + count]);
+	    read += count;
+	    self.position += count as u64;
+	}
+	Ok(read)
+    }
+}
+
+impl io::Write for Cached {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	self.handle.seek(io::SeekFrom::Start(self.position))?;
+	let written = self.handle.write(buf)?;
+	update(&self.path, self.position, &buf[..written]);
+	self.position += written as u64;
+	Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	self.handle.flush()
+    }
+}
+
+impl io::Seek for Cached {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+	let position = match pos {
+	    io::SeekFrom::Start(offset) => Some(offset),
+	    io::SeekFrom::Current(offset) => add_offset(self.position, offset),
+	    io::SeekFrom::End(offset) => add_offset(self.handle.size(), offset),
+	};
+	match position {
+	    Some(position) => {
+		self.position = position;
+		Ok(position)
+	    },
+	    None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file")),
+	}
+    }
+}
+
+fn add_offset(base: u64, offset: i64) -> Option<u64> {
+    match offset < 0 {
+	true => base.checked_sub(offset.wrapping_neg() as u64),
+	false => base.checked_add(offset as u64),
+    }
+}
+
+impl Handle for Cached {
+    fn size(&self) -> u64 {
+	self.handle.size()
+    }
+
+    fn page_cached(&self) -> bool {
+	true
+    }
+
+    fn cached_page(&mut self) -> Option<io::Result<PageSlice>> {
+	let size = self.handle.size();
+	if self.position >= size {
+	    return Some(Ok(PageSlice { _page: None, start: core::ptr::NonNull::dangling().as_ptr(), len: 0 }));
+	}
+	let index = self.position / PAGE_SIZE as u64;
+	let offset = (self.position % PAGE_SIZE as u64) as usize;
+	let page = match page(&self.path, index, size, &mut *self.handle) {
+	    Ok(page) => page,
+	    Err(e) => return Some(Err(e)),
+	};
+	let (start, len) = {
+	    let locked = page.lock();
+	    let len = locked.len.saturating_sub(offset);
+	    (unsafe { locked.frame.0.add(offset) } as *const u8, len)
+	};
+	Some(Ok(PageSlice { _page: Some(page), start, len }))
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+	self.handle.sync()
+    }
+
+    fn truncate(&mut self) -> io::Result<()> {
+	self.handle.truncate()?;
+	invalidate(&self.path);
+	self.position = 0;
+	Ok(())
+    }
+
+    fn lock(&mut self, kind: LockKind) -> io::Result<()> {
+	self.handle.lock(kind)
+    }
+
+    fn extents(&self) -> io::Result<Vec<(u64, u64)>> {
+	self.handle.extents()
+    }
+}
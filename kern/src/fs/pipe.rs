@@ -0,0 +1,133 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::cmp::min;
+
+use shim::io;
+
+use crate::fs::vfs::Handle;
+use crate::mutex::Mutex;
+use crate::param::PIPE_SIZE;
+
+/// The bytes in flight between the ends of a pipe
+#[derive(Debug, Default)]
+struct Pipe {
+    buffer: VecDeque<u8>,
+    /// the read end is still open
+    reader: bool,
+    /// the write end is still open
+    writer: bool,
+}
+
+/// Returns the two ends of a new pipe holding up to `PIPE_SIZE` bytes.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Mutex::new(Pipe { reader: true, writer: true, ..Pipe::default() }));
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+/// The end of a pipe bytes are read from. Reads return what is buffered,
+/// fail with `WouldBlock` while the pipe is empty, and return 0 once it is
+/// empty and the writer is gone.
+pub struct PipeReader(Arc<Mutex<Pipe>>);
+
+/// The end of a pipe bytes are written to. Writes take what fits, fail with
+/// `WouldBlock` while the pipe is full, and with `BrokenPipe` once the
+/// reader is gone.
+pub struct PipeWriter(Arc<Mutex<Pipe>>);
+
+impl io::Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	let mut pipe = self.0.lock();
+	if pipe.buffer.is_empty() {
+	    return match pipe.writer && !buf.is_empty() {
+		true => Err(io::Error::new(io::ErrorKind::WouldBlock, "pipe is empty")),
+		false => Ok(0),
+	    };
+	}
+	let count = min(buf.len(), pipe.buffer.len());
+	for (byte, value) in buf.iter_mut().zip(pipe.buffer.drain(..count)) {
+	    *byte = value;
+	}
+	Ok(count)
+    }
+}
+
+impl io::Write for PipeReader {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+	Err(io::Error::new(io::ErrorKind::PermissionDenied, "read end of a pipe"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	Ok(())
+    }
+}
+
+impl io::Read for PipeWriter {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+	Err(io::Error::new(io::ErrorKind::PermissionDenied, "write end of a pipe"))
+    }
+}
+
+impl io::Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	let mut pipe = self.0.lock();
+	if !pipe.reader {
+	    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "pipe has no reader"));
+	}
+	let count = min(buf.len(), PIPE_SIZE - pipe.buffer.len());
+	if count == 0 && !buf.is_empty() {
+	    return Err(io::Error::new(io::ErrorKind::WouldBlock, "pipe is full"));
+	}
+	pipe.buffer.extend(buf[..count].iter());
+	Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	Ok(())
+    }
+}
+
+impl io::Seek for PipeReader {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+	Err(io::Error::new(io::ErrorKind::InvalidInput, "pipes cannot seek"))
+    }
+}
+
+impl io::Seek for PipeWriter {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+	Err(io::Error::new(io::ErrorKind::InvalidInput, "pipes cannot seek"))
+    }
+}
+
+impl Handle for PipeReader {
+    /// Returns the number of bytes waiting to be read.
+    fn size(&self) -> u64 {
+	self.0.lock().buffer.len() as u64
+    }
+
+    fn blocking(&self) -> bool {
+	true
+    }
+}
+
+impl Handle for PipeWriter {
+    /// Returns the number of bytes waiting to be read.
+    fn size(&self) -> u64 {
+	self.0.lock().buffer.len() as u64
+    }
+
+    fn blocking(&self) -> bool {
+	true
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+	self.0.lock().reader = false;
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+	self.0.lock().writer = false;
+    }
+}
@@ -0,0 +1,275 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::fmt::Write as _;
+
+use kernel_api::OsError;
+use pi::timer::current_time;
+use shim::io;
+use shim::path::Path;
+
+use crate::fs::{dcache, pagecache};
+use crate::fs::vfs::{Attr, Handle, Vfs, Vnode};
+use crate::param::PAGE_SIZE;
+use crate::process::Id;
+use crate::vm::{frame, shm};
+use crate::{dma, ALLOCATOR, SCHEDULER, VMM};
+
+/// Kernel state as read only text files, mounted on `/proc`: `meminfo`,
+/// `uptime` and a directory per process holding `status`. The files are
+/// generated when they are opened.
+pub struct ProcFs;
+
+impl Vfs for ProcFs {
+    fn fs_type(&self) -> &'static str {
+	"proc"
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Vnode>> {
+	let path = path.to_str().ok_or_else(not_found)?;
+	let mut components = path.split('/').filter(|name| !name.is_empty());
+	match (components.next(), components.next(), components.next()) {
+	    (None, _, _) => Ok(Box::new(ProcDir::Root)),
+	    (Some("meminfo"), None, _) => Ok(Box::new(ProcFile::new("meminfo", meminfo()))),
+	    (Some("uptime"), None, _) => Ok(Box::new(ProcFile::new("uptime", uptime()))),
+	    (Some(pid), rest, None) => {
+		let pid = pid.parse::<Id>().ok().filter(|pid| pids().contains(pid)).ok_or_else(not_found)?;
+		match rest {
+		    None => Ok(Box::new(ProcDir::Process(pid))),
+		    Some("status") => Ok(Box::new(ProcFile::new("status", status(pid).ok_or_else(not_found)?))),
+		    Some(_) => Err(not_found()),
+		}
+	    },
+	    _ => Err(not_found()),
+	}
+    }
+
+    fn read_only(&self) -> bool {
+	true
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such file")
+}
+
+/// Returns the ID of every process, in queue order.
+fn pids() -> Vec<Id> {
+    let mut pids: Vec<Id> = Vec::new();
+    SCHEDULER.for_each_process(|process| pids.push(process.pid));
+    pids
+}
+
+fn meminfo() -> String {
+    let kib = |pages: usize| pages * PAGE_SIZE / 1024;
+    let stats = VMM.stats();
+    let mut text = String::new();
+    if let Some((start, end)) = ALLOCATOR.bounds() {
+	let _ = write!(text, "HeapTotal:   {:8} KiB\n", (end - start) / 1024);
+    }
+    let heap = ALLOCATOR.stats();
+    let _ = write!(text, "HeapLive:    {:8} KiB\n", heap.live_bytes / 1024);
+    let _ = write!(text, "HeapPeak:    {:8} KiB\n", heap.peak_bytes / 1024);
+    let _ = write!(text, "HeapAllocs:  {:8}\n", heap.allocs);
+    let _ = write!(text, "HeapFrees:   {:8}\n", heap.frees);
+    let _ = write!(text, "KernelPages: {:8} KiB\n", kib(stats.kernel_pages));
+    let _ = write!(text, "UserPages:   {:8} KiB\n", kib(stats.user_pages));
+    let _ = write!(text, "DevicePages: {:8} KiB\n", kib(stats.device_pages));
+    let _ = write!(text, "SharedPages: {:8} KiB\n", kib(stats.shared_pages));
+    let _ = write!(text, "CowPages:    {:8} KiB\n", kib(stats.cow_pages));
+    let frames = frame::stats();
+    let _ = write!(text, "Frames:      {:8} KiB\n", kib(frames.frames));
+    let _ = write!(text, "FramesShared:{:8} KiB\n", kib(frames.shared));
+    let _ = write!(text, "FrameRefs:   {:8}\n", frames.refs);
+    let _ = write!(text, "Shmem:       {:8} KiB\n", shm::stats() / 1024);
+    let (dma_used, dma_total) = dma::stats();
+    let _ = write!(text, "DmaTotal:    {:8} KiB\n", dma_total / 1024);
+    let _ = write!(text, "DmaFree:     {:8} KiB\n", (dma_total - dma_used) / 1024);
+    let _ = write!(text, "PageTables:  {:8} KiB\n", stats.table_bytes / 1024);
+    let cache = pagecache::stats();
+    let _ = write!(text, "PageCache:   {:8} KiB\n", kib(cache.pages));
+    let _ = write!(text, "CacheHits:   {:8}\n", cache.hits);
+    let _ = write!(text, "CacheMisses: {:8}\n", cache.misses);
+    let (hits, misses) = dcache::stats();
+    let _ = write!(text, "DirHits:     {:8}\n", hits);
+    let _ = write!(text, "DirMisses:   {:8}\n", misses);
+    text
+}
+
+fn uptime() -> String {
+    let time = current_time();
+    let mut text = String::new();
+    let _ = write!(text, "{}.{:02}\n", time.as_secs(), time.subsec_millis() / 10);
+    text
+}
+
+/// Returns the status of process `pid`, `None` if it does not exist.
+fn status(pid: Id) -> Option<String> {
+    let process = SCHEDULER.process(pid)?;
+    let mut text = String::new();
+    let _ = write!(text, "Pid:     {}\n", pid);
+    let _ = write!(text, "PPid:    {}\n", process.ppid);
+    let _ = write!(text, "Threads: {}\n", process.threads.len());
+    let _ = write!(text, "Nice:    {}\n", process.nice);
+    for thread in process.threads.iter() {
+	let _ = write!(text, "Thread:  {} {}\n", thread.tid, thread.state);
+    }
+    let _ = write!(text, "Memory:  {} KiB\n", process.vmap.lock().stats().user_pages * PAGE_SIZE / 1024);
+    let (mappings, mapped) = process.vmap.lock().anonymous();
+    let _ = write!(text, "Mapped:  {} KiB in {} mappings\n", mapped / 1024, mappings);
+    let _ = write!(text, "Files:   {}\n", process.files.lock().count());
+    Some(text)
+}
+
+/// A directory of `/proc`
+enum ProcDir {
+    Root,
+    Process(Id),
+}
+
+impl Vnode for ProcDir {
+    fn name(&self) -> &str {
+	""
+    }
+
+    fn attr(&self) -> Attr {
+	Attr { directory: true, read_only: true, ..Attr::default() }
+    }
+
+    /// Lists the files of the directory, which are generated on the way.
+    fn entries(&self) -> io::Result<Vec<Box<dyn Vnode>>> {
+	let mut entries: Vec<Box<dyn Vnode>> = Vec::new();
+	match self {
+	    ProcDir::Root => {
+		entries.push(Box::new(ProcFile::new("meminfo", meminfo())));
+		entries.push(Box::new(ProcFile::new("uptime", uptime())));
+		for pid in pids() {
+		    let mut name = String::new();
+		    let _ = write!(name, "{}", pid);
+		    entries.push(Box::new(ProcEntry { name: name, pid: pid }));
+		}
+	    },
+	    ProcDir::Process(pid) => {
+		if let Some(text) = status(*pid) {
+		    entries.push(Box::new(ProcFile::new("status", text)));
+		}
+	    },
+	}
+	Ok(entries)
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Handle>> {
+	Err(io::Error::new(io::ErrorKind::Other, "not a regular file"))
+    }
+}
+
+/// The directory of a process as listed in `/proc`
+struct ProcEntry {
+    name: String,
+    pid: Id,
+}
+
+impl Vnode for ProcEntry {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn attr(&self) -> Attr {
+	ProcDir::Process(self.pid).attr()
+    }
+
+    fn entries(&self) -> io::Result<Vec<Box<dyn Vnode>>> {
+	ProcDir::Process(self.pid).entries()
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Handle>> {
+	ProcDir::Process(self.pid).open()
+    }
+}
+
+/// A generated file and its contents
+struct ProcFile {
+    name: &'static str,
+    text: String,
+}
+
+impl ProcFile {
+    fn new(name: &'static str, text: String) -> ProcFile {
+	ProcFile { name, text }
+    }
+}
+
+impl Vnode for ProcFile {
+    fn name(&self) -> &str {
+	self.name
+    }
+
+    fn attr(&self) -> Attr {
+	Attr { size: self.text.len() as u64, read_only: true, ..Attr::default() }
+    }
+
+    fn entries(&self) -> io::Result<Vec<Box<dyn Vnode>>> {
+	Err(io::Error::new(io::ErrorKind::Other, "not a directory"))
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Handle>> {
+	Ok(Box::new(Contents { data: self.text.clone().into_bytes(), position: 0 }))
+    }
+}
+
+/// An open file of `/proc`, a snapshot taken when it was generated
+struct Contents {
+    data: Vec<u8>,
+    position: u64,
+}
+
+impl io::Read for Contents {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	let start = min(self.position, self.data.len() as u64) as usize;
+	let count = min(buf.len(), self.data.len() - start);
+	buf[..count].copy_from_slice(&self.data[start..start + count]);
+	self.position += count as u64;
+	Ok(count)
+    }
+}
+
+impl io::Write for Contents {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+	Err(io::Error::from(OsError::ReadOnlyFs))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	Ok(())
+    }
+}
+
+impl io::Seek for Contents {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+	let position = match pos {
+	    io::SeekFrom::Start(offset) => Some(offset),
+	    io::SeekFrom::Current(offset) => add_offset(self.position, offset),
+	    io::SeekFrom::End(offset) => add_offset(self.data.len() as u64, offset),
+	};
+	match position {
+	    Some(position) => {
+		self.position = position;
+		Ok(position)
+	    },
+	    None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file")),
+	}
+    }
+}
+
+fn add_offset(base: u64, offset: i64) -> Option<u64> {
+    match offset < 0 {
+	true => base.checked_sub(offset.wrapping_neg() as u64),
+	false => base.checked_add(offset as u64),
+    }
+}
+
+impl Handle for Contents {
+    fn size(&self) -> u64 {
+	self.data.len() as u64
+    }
+}
@@ -4,6 +4,8 @@ use pi::timer::spin_sleep;
 
 use fat32::traits::BlockDevice;
 
+use crate::fs::iostat::{self, Device, Dir};
+
 extern "C" {
     /// A global representing the last SD controller error that occured.
     static sd_err: i64;
@@ -78,39 +80,46 @@ impl BlockDevice for Sd {
     ///
     /// An error of kind `Other` is returned for all other errors.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
-	if (buf.len() as u64) < self.sector_size() {
-	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer too small to read sector"));
-	}
+	iostat::measure(Device::Sd, Dir::Read, || read(n, buf))
+    }
 
-	if n > 0x7FFFFFFF {
-	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "out of range sector requested for read"));
-	}
+    /// Fails with an error of kind `PermissionDenied`: `libsd` only reads,
+    /// so volumes on the SD card cannot be written or formatted.
+    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
+	iostat::measure(Device::Sd, Dir::Write, || Err(io::Error::new(io::ErrorKind::PermissionDenied, "SD card is read only")))
+    }
+}
 
-	let bytes_read = unsafe{
-	    sd_readsector(n as i32, buf.as_mut_ptr())
-	};
+/// Reads sector `n` from the SD card into `buf`, as `Sd::read_sector()`.
+fn read(n: u64, buf: &mut [u8]) -> io::Result<usize> {
+    if (buf.len() as u64) < 512 {
+	return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer too small to read sector"));
+    }
 
-	if bytes_read == 0 {
-	    // error occurred
-	    unsafe {
-		match sd_err {
-		    -1 => {return Err(io::Error::new(io::ErrorKind::TimedOut, "SD card controller timed out"));},
-		    -2 => {return Err(io::Error::new(io::ErrorKind::Other, "communication failed with SD card controller"));},
-		    _ => {return Err(io::Error::new(io::ErrorKind::Other, "an undefined error occured"));},
-		};
-	    }
-	}
+    if n > 0x7FFFFFFF {
+	return Err(io::Error::new(io::ErrorKind::InvalidInput, "out of range sector requested for read"));
+    }
 
-	if bytes_read < 0 {
-	    // undefined behaviour
-	    return Err(io::Error::new(io::ErrorKind::Other, "an undefined error occured"));
-	}
+    let bytes_read = unsafe{
+	sd_readsector(n as i32, buf.as_mut_ptr())
+    };
 
-	// successfull read
-	Ok(bytes_read as usize)
+    if bytes_read == 0 {
+	// error occurred
+	unsafe {
+	    match sd_err {
+		-1 => {return Err(io::Error::new(io::ErrorKind::TimedOut, "SD card controller timed out"));},
+		-2 => {return Err(io::Error::new(io::ErrorKind::Other, "communication failed with SD card controller"));},
+		_ => {return Err(io::Error::new(io::ErrorKind::Other, "an undefined error occured"));},
+	    };
+	}
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+    if bytes_read < 0 {
+	// undefined behaviour
+	return Err(io::Error::new(io::ErrorKind::Other, "an undefined error occured"));
     }
+
+    // successfull read
+    Ok(bytes_read as usize)
 }
@@ -0,0 +1,104 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem::ManuallyDrop;
+use shim::io;
+use shim::path::PathBuf;
+
+use crate::fs::vfs::{Handle, LockKind};
+use crate::rng;
+use crate::FILESYSTEM;
+
+/// Names `FileSystem::create_temp()` tries before giving up
+pub const TEMP_ATTEMPTS: usize = 16;
+
+/// Random characters after the prefix of a temporary file's name
+const TEMP_NAME_CHARS: usize = 8;
+
+/// Characters the random part of a name is made of, valid in long FAT names
+/// and free of case, which FAT ignores
+const TEMP_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Returns `prefix` followed by `TEMP_NAME_CHARS` random characters.
+pub fn name(prefix: &str) -> String {
+    let mut name = String::from(prefix);
+    for _ in 0..TEMP_NAME_CHARS {
+	name.push(TEMP_ALPHABET[rng::below(TEMP_ALPHABET.len() as u64) as usize] as char);
+    }
+    name
+}
+
+/// An open temporary file, which is removed once it is dropped unless it was
+/// renamed or removed before. Processes close their files when they exit, so
+/// their temporary files go with them.
+///
+/// The removal takes the file system lock and writes the disk, so a `Temp`
+/// is never dropped inside an exception handler: the threads a handler kills
+/// are dropped on the way back to a thread instead, see `scheduler::reap()`.
+pub struct Temp {
+    path: PathBuf,
+    handle: ManuallyDrop<Box<dyn Handle>>,
+}
+
+impl Temp {
+    /// Wraps `handle`, the file at the absolute, normalized `path`.
+    pub fn new(path: PathBuf, handle: Box<dyn Handle>) -> Temp {
+	Temp { path, handle: ManuallyDrop::new(handle) }
+    }
+}
+
+impl io::Read for Temp {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	self.handle.read(buf)
+    }
+}
+
+impl io::Write for Temp {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	self.handle.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	self.handle.flush()
+    }
+}
+
+impl io::Seek for Temp {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+	self.handle.seek(pos)
+    }
+}
+
+impl Handle for Temp {
+    fn size(&self) -> u64 {
+	self.handle.size()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+	self.handle.sync()
+    }
+
+    fn truncate(&mut self) -> io::Result<()> {
+	self.handle.truncate()
+    }
+
+    fn lock(&mut self, kind: LockKind) -> io::Result<()> {
+	self.handle.lock(kind)
+    }
+
+    fn extents(&self) -> io::Result<Vec<(u64, u64)>> {
+	self.handle.extents()
+    }
+}
+
+impl Drop for Temp {
+    fn drop(&mut self) {
+	// closed first, so that the file system is done with the file
+	unsafe { ManuallyDrop::drop(&mut self.handle) };
+	match FILESYSTEM.remove(&self.path) {
+	    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+	    Err(e) => warn!("temp: cannot remove {}: {:?}", self.path.display(), e),
+	    Ok(()) => {},
+	}
+    }
+}
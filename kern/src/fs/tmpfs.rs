@@ -0,0 +1,302 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::min;
+
+use shim::io;
+use shim::path::{Component, Path};
+
+use crate::fs::vfs::{Attr, Handle, Vfs, Vnode};
+use crate::mutex::Mutex;
+use crate::param::TMPFS_MAX_SIZE;
+
+type Dir = Arc<Mutex<BTreeMap<String, Node>>>;
+
+/// Contents of a regular file. Freed once the file is removed and the last
+/// handle to it is closed.
+struct Data {
+    bytes: Vec<u8>,
+    /// bytes held by all files of the file system
+    used: Arc<Mutex<usize>>,
+}
+
+impl Data {
+    /// Resizes the file to `len` bytes, zero filling it.
+    fn resize(&mut self, len: usize) -> io::Result<()> {
+	let mut used = self.used.lock();
+	let total = *used - self.bytes.len() + len;
+	if len > self.bytes.len() && total > TMPFS_MAX_SIZE {
+	    return Err(io::Error::new(io::ErrorKind::Other, "no space left on tmpfs"));
+	}
+	self.bytes.resize(len, 0);
+	*used = total;
+	Ok(())
+    }
+}
+
+impl Drop for Data {
+    fn drop(&mut self) {
+	*self.used.lock() -= self.bytes.len();
+    }
+}
+
+#[derive(Clone)]
+enum Node {
+    File(Arc<Mutex<Data>>),
+    Dir(Dir),
+}
+
+impl Node {
+    /// Returns true if `self` and `other` are the same file or directory.
+    fn same(&self, other: &Node) -> bool {
+	match (self, other) {
+	    (Node::File(a), Node::File(b)) => Arc::ptr_eq(a, b),
+	    (Node::Dir(a), Node::Dir(b)) => Arc::ptr_eq(a, b),
+	    _ => false,
+	}
+    }
+}
+
+/// A file system held in memory, mounted on `/tmp`. Everything is lost when
+/// the kernel stops; the files may hold `TMPFS_MAX_SIZE` bytes in total.
+pub struct TmpFs {
+    root: Dir,
+    used: Arc<Mutex<usize>>,
+}
+
+impl TmpFs {
+    pub fn new() -> TmpFs {
+	TmpFs { root: Arc::new(Mutex::new(BTreeMap::new())), used: Arc::new(Mutex::new(0)) }
+    }
+
+    /// Returns the directory holding the normalized `path` and the name of
+    /// the entry, `None` for the root.
+    fn parent(&self, path: &Path) -> io::Result<Option<(Dir, String)>> {
+	let names = names(path)?;
+	let (name, dirs) = match names.split_last() {
+	    Some(split) => split,
+	    None => return Ok(None),
+	};
+	let mut dir = self.root.clone();
+	for name in dirs {
+	    let next = match dir.lock().get(*name) {
+		Some(Node::Dir(next)) => next.clone(),
+		Some(Node::File(_)) => return Err(io::Error::new(io::ErrorKind::NotFound, "not a directory")),
+		None => return Err(not_found()),
+	    };
+	    dir = next;
+	}
+	Ok(Some((dir, String::from(*name))))
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such file or directory")
+}
+
+/// Splits the absolute, normalized `path` into the names along it.
+fn names(path: &Path) -> io::Result<Vec<&str>> {
+    let mut names = Vec::new();
+    for component in path.components() {
+	match component {
+	    Component::RootDir => {},
+	    Component::Normal(name) => names.push(name.to_str().ok_or_else(not_found)?),
+	    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not normalized")),
+	}
+    }
+    Ok(names)
+}
+
+impl Vfs for TmpFs {
+    fn fs_type(&self) -> &'static str {
+	"tmpfs"
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Vnode>> {
+	let (node, name) = match self.parent(path)? {
+	    None => (Node::Dir(self.root.clone()), String::new()),
+	    Some((dir, name)) => (dir.lock().get(&name).cloned().ok_or_else(not_found)?, name),
+	};
+	Ok(Box::new(TmpNode { name, node }))
+    }
+
+    fn read_only(&self) -> bool {
+	false
+    }
+
+    fn create(&self, path: &Path, directory: bool) -> io::Result<()> {
+	let (dir, name) = self.parent(path)?
+	    .ok_or_else(|| io::Error::new(io::ErrorKind::AlreadyExists, "file exists"))?;
+	let mut dir = dir.lock();
+	if dir.contains_key(&name) {
+	    return Err(io::Error::new(io::ErrorKind::AlreadyExists, "file exists"));
+	}
+	let node = match directory {
+	    true => Node::Dir(Arc::new(Mutex::new(BTreeMap::new()))),
+	    false => Node::File(Arc::new(Mutex::new(Data { bytes: Vec::new(), used: self.used.clone() }))),
+	};
+	dir.insert(name, node);
+	Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+	let (dir, name) = self.parent(path)?
+	    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "cannot remove the root"))?;
+	let mut dir = dir.lock();
+	match dir.get(&name) {
+	    Some(Node::Dir(entries)) if !entries.lock().is_empty() => {
+		Err(io::Error::new(io::ErrorKind::Other, "directory not empty"))
+	    },
+	    Some(_) => {
+		dir.remove(&name);
+		Ok(())
+	    },
+	    None => Err(not_found()),
+	}
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+	if from == to {
+	    return self.open(from).map(|_| ());
+	}
+	if to.starts_with(from) {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot move a directory into itself"));
+	}
+	let root = || io::Error::new(io::ErrorKind::InvalidInput, "cannot rename the root");
+	let (from_dir, from_name) = self.parent(from)?.ok_or_else(root)?;
+	let (to_dir, to_name) = self.parent(to)?.ok_or_else(root)?;
+	let node = from_dir.lock().get(&from_name).cloned().ok_or_else(not_found)?;
+	{
+	    let mut to_dir = to_dir.lock();
+	    match (&node, to_dir.get(&to_name)) {
+		(Node::File(_), Some(Node::Dir(_))) => {
+		    return Err(io::Error::new(io::ErrorKind::Other, "is a directory"));
+		},
+		(Node::Dir(_), Some(Node::File(_))) => {
+		    return Err(io::Error::new(io::ErrorKind::Other, "not a directory"));
+		},
+		(Node::Dir(_), Some(Node::Dir(entries))) if !entries.lock().is_empty() => {
+		    return Err(io::Error::new(io::ErrorKind::Other, "directory not empty"));
+		},
+		_ => {},
+	    }
+	    to_dir.insert(to_name, node.clone());
+	}
+	// unless something else took the name meanwhile
+	let mut from_dir = from_dir.lock();
+	if from_dir.get(&from_name).map_or(false, |current| current.same(&node)) {
+	    from_dir.remove(&from_name);
+	}
+	Ok(())
+    }
+}
+
+/// A file or directory of a `TmpFs`
+struct TmpNode {
+    name: String,
+    node: Node,
+}
+
+impl Vnode for TmpNode {
+    fn name(&self) -> &str {
+	&self.name
+    }
+
+    fn attr(&self) -> Attr {
+	match &self.node {
+	    Node::File(data) => Attr { size: data.lock().bytes.len() as u64, ..Attr::default() },
+	    Node::Dir(_) => Attr { directory: true, ..Attr::default() },
+	}
+    }
+
+    fn entries(&self) -> io::Result<Vec<Box<dyn Vnode>>> {
+	match &self.node {
+	    Node::Dir(dir) => Ok(dir.lock().iter()
+		.map(|(name, node)| Box::new(TmpNode { name: name.clone(), node: node.clone() }) as Box<dyn Vnode>)
+		.collect()),
+	    Node::File(_) => Err(io::Error::new(io::ErrorKind::Other, "not a directory")),
+	}
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Handle>> {
+	match &self.node {
+	    Node::File(data) => Ok(Box::new(TmpFile { data: data.clone(), position: 0 })),
+	    Node::Dir(_) => Err(io::Error::new(io::ErrorKind::Other, "not a regular file")),
+	}
+    }
+}
+
+/// An open regular file of a `TmpFs`
+struct TmpFile {
+    data: Arc<Mutex<Data>>,
+    position: u64,
+}
+
+impl io::Read for TmpFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	let data = self.data.lock();
+	let start = min(self.position, data.bytes.len() as u64) as usize;
+	let count = min(buf.len(), data.bytes.len() - start);
+	buf[..count].copy_from_slice(&data.bytes[start..start + count]);
+	self.position += count as u64;
+	Ok(count)
+    }
+}
+
+impl io::Write for TmpFile {
+    /// Writes `buf` at the current position, growing the file and zero
+    /// filling any gap before it.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	let mut data = self.data.lock();
+	let start = self.position as usize;
+	let end = start.checked_add(buf.len())
+	    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "file too large"))?;
+	if end > data.bytes.len() {
+	    data.resize(end)?;
+	}
+	data.bytes[start..end].copy_from_slice(buf);
+	self.position = end as u64;
+	Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	Ok(())
+    }
+}
+
+impl io::Seek for TmpFile {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+	let size = self.data.lock().bytes.len() as u64;
+	let position = match pos {
+	    io::SeekFrom::Start(offset) => Some(offset),
+	    io::SeekFrom::Current(offset) => add_offset(self.position, offset),
+	    io::SeekFrom::End(offset) => add_offset(size, offset),
+	};
+	match position {
+	    Some(position) => {
+		self.position = position;
+		Ok(position)
+	    },
+	    None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file")),
+	}
+    }
+}
+
+fn add_offset(base: u64, offset: i64) -> Option<u64> {
+    match offset < 0 {
+	true => base.checked_sub(offset.wrapping_neg() as u64),
+	false => base.checked_add(offset as u64),
+    }
+}
+
+impl Handle for TmpFile {
+    fn size(&self) -> u64 {
+	self.data.lock().bytes.len() as u64
+    }
+
+    fn truncate(&mut self) -> io::Result<()> {
+	self.data.lock().resize(0)
+    }
+}
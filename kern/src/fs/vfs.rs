@@ -0,0 +1,253 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::path::Path;
+
+use fat32::traits::Timestamp;
+use kernel_api::OsError;
+
+use crate::fs::pagecache::PageSlice;
+
+/// A date and time of a vnode
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Time {
+    pub year: usize,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Time {
+    pub fn from<T: Timestamp>(timestamp: T) -> Time {
+	Time {
+	    year: timestamp.year(),
+	    month: timestamp.month(),
+	    day: timestamp.day(),
+	    hour: timestamp.hour(),
+	    minute: timestamp.minute(),
+	    second: timestamp.second(),
+	}
+    }
+
+    /// Returns the time as seconds since the Unix epoch, or 0 for the
+    /// default time of a file system without times.
+    ///
+    /// The time is taken to be UTC. FAT stores local time with no zone, but
+    /// the kernel has no time zone setting and stamps files with
+    /// `Timestamp::from_unix` of its UTC clock, so its own files read back
+    /// right; files stamped by a host in local time are off by the host's
+    /// offset from UTC.
+    pub fn unix(&self) -> u64 {
+	if self.year == 0 {
+	    return 0;
+	}
+	let days = fat32::vfat::days_from_civil(self.year as i64, self.month as u32, self.day as u32);
+	let secs = days * 86400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+	core::cmp::max(secs, 0) as u64
+    }
+}
+
+/// Attributes of a vnode. File systems without one of them leave it at its
+/// default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Attr {
+    pub directory: bool,
+    /// size in bytes of a regular file
+    pub size: u64,
+    pub read_only: bool,
+    pub hidden: bool,
+    pub system: bool,
+    pub archive: bool,
+    pub created: Time,
+    pub modified: Time,
+}
+
+/// A file or directory of a mounted file system.
+pub trait Vnode {
+    /// Name in its directory; the root of a file system has an empty name.
+    fn name(&self) -> &str;
+
+    fn attr(&self) -> Attr;
+
+    /// Returns the entries of a directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if the vnode is not a directory.
+    fn entries(&self) -> io::Result<Vec<Box<dyn Vnode>>>;
+
+    /// Opens a regular file at its start.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if the vnode is not a regular file.
+    fn open(&self) -> io::Result<Box<dyn Handle>>;
+
+    /// Sets or clears the read only attribute.
+    fn set_read_only(&mut self, _read_only: bool) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::PermissionDenied, "attributes cannot be changed"))
+    }
+
+    /// Sets or clears the hidden attribute.
+    fn set_hidden(&mut self, _hidden: bool) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::PermissionDenied, "attributes cannot be changed"))
+    }
+}
+
+/// Advisory lock requests of `Handle::lock()`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockKind {
+    /// may be held by several open files at once
+    Shared,
+    /// held by one open file only
+    Exclusive,
+    /// releases the lock held
+    Unlock,
+}
+
+/// An open regular file
+pub trait Handle: io::Read + io::Write + io::Seek + Send {
+    /// Size in bytes
+    fn size(&self) -> u64;
+
+    /// Writes buffered data to the device.
+    fn sync(&mut self) -> io::Result<()> {
+	Ok(())
+    }
+
+    /// Returns true if reading or writing waits for the other end instead
+    /// of failing with `WouldBlock`, as for pipes.
+    fn blocking(&self) -> bool {
+	false
+    }
+
+    /// Returns true if the file is read through the page cache, and writes
+    /// to it update the cached pages.
+    fn page_cached(&self) -> bool {
+	false
+    }
+
+    /// Returns the bytes from the current position to the end of the page
+    /// of the page cache holding them, in place, empty at the end of the
+    /// file, or `None` if the file is not read through the page cache. The
+    /// position does not move.
+    fn cached_page(&mut self) -> Option<io::Result<PageSlice>> {
+	None
+    }
+
+    /// Empties the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `PermissionDenied` if the file cannot be resized.
+    fn truncate(&mut self) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::PermissionDenied, "file cannot be truncated"))
+    }
+
+    /// Takes, converts or releases the advisory lock of this open file on
+    /// the file. Locks only keep out other lockers, not readers or writers,
+    /// and are released when the file is closed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `WouldBlock` if another open file holds a lock
+    /// that conflicts, and `Other` if the file cannot be locked.
+    fn lock(&mut self, _kind: LockKind) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::Other, "file cannot be locked"))
+    }
+
+    /// Returns the runs of contiguous blocks holding the file, in file order,
+    /// as the first block of each run and its length in blocks. Blocks are
+    /// the file system's unit of allocation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if the file system does not store files in
+    /// blocks.
+    fn extents(&self) -> io::Result<Vec<(u64, u64)>> {
+	Err(io::Error::new(io::ErrorKind::Other, "file has no extents"))
+    }
+}
+
+/// A file system that can be mounted in the tree.
+pub trait Vfs: Send {
+    /// Name of the file system type, as listed by `mount`.
+    fn fs_type(&self) -> &'static str;
+
+    /// Opens the vnode at `path`, absolute and normalized within the file
+    /// system.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `NotFound` if there is no vnode at `path`.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Vnode>>;
+
+    fn read_only(&self) -> bool;
+
+    /// Makes the file system refuse or accept writes, as `remount` asks.
+    /// Making it read only writes back what it holds first.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::ReadOnlyFs` if the file system is always read only
+    /// and writes are asked for.
+    fn set_read_only(&self, read_only: bool) -> io::Result<()> {
+	match !read_only && self.read_only() {
+	    true => Err(io::Error::from(OsError::ReadOnlyFs)),
+	    false => Ok(()),
+	}
+    }
+
+    /// Creates an empty regular file, or a directory if `directory`, at
+    /// `path`, absolute and normalized within the file system.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `AlreadyExists` if there is a vnode at `path`,
+    /// `NotFound` if its directory does not exist, and `PermissionDenied` if
+    /// the file system cannot create files.
+    fn create(&self, _path: &Path, _directory: bool) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::PermissionDenied, "file system cannot create files"))
+    }
+
+    /// Removes the regular file or empty directory at `path`. Open handles
+    /// to a removed file keep working.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `NotFound` if there is no vnode at `path`, `Other`
+    /// if it is a directory with entries, and `PermissionDenied` if the file
+    /// system cannot remove files.
+    fn remove(&self, _path: &Path) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::PermissionDenied, "file system cannot remove files"))
+    }
+
+    /// Moves the vnode at `from` to `to`, both absolute and normalized within
+    /// the file system, replacing what is at `to` unless it is a directory
+    /// with entries or of the other kind. Whoever looks up `to` meanwhile
+    /// finds either the old vnode or the new one, never nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `NotFound` if there is no vnode at `from` or no
+    /// directory to hold `to`, `InvalidInput` if `to` is inside `from`,
+    /// `Other` if the vnode at `to` cannot be replaced, and
+    /// `PermissionDenied` if the file system cannot rename files.
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::PermissionDenied, "file system cannot rename files"))
+    }
+
+    /// Returns true if files are to be read through the page cache, which
+    /// is worth it for volumes on block devices only.
+    fn page_cache(&self) -> bool {
+	false
+    }
+
+    /// Writes everything back before the file system is unmounted.
+    fn unmount(&self) -> io::Result<()> {
+	Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::path::{Path, PathBuf};
+
+use crate::fs::vfs::Attr;
+use crate::FILESYSTEM;
+
+/// A file or directory found by `walk()`
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// path relative to the root of the walk
+    pub path: PathBuf,
+    pub attr: Attr,
+}
+
+/// Lists the tree under the absolute directory `root`, a directory before
+/// what it holds. The tree is taken as it is at the call: files created
+/// later are not listed, and the sizes are those of then.
+///
+/// The walk stays on the volume `root` is on: the file systems mounted
+/// below it are left out, with their mount points.
+///
+/// # Errors
+///
+/// Returns the errors of opening or listing a directory, `Other` if `root`
+/// is not a directory.
+pub fn walk<P: AsRef<Path>>(root: P) -> io::Result<Vec<Entry>> {
+    let root = root.as_ref();
+    let mut mounts = Vec::new();
+    FILESYSTEM.for_each_mount(|mount| {
+	if mount.path.starts_with(root) && mount.path != root {
+	    mounts.push(mount.path.clone());
+	}
+    });
+
+    let mut found = Vec::new();
+    visit(root, PathBuf::new(), &mounts, &mut found)?;
+    Ok(found)
+}
+
+/// Adds the entries of the directory `dir`, at `relative` in the walk, and
+/// those below them to `found`.
+fn visit(dir: &Path, relative: PathBuf, mounts: &[PathBuf], found: &mut Vec<Entry>) -> io::Result<()> {
+    for entry in FILESYSTEM.open(dir)?.entries()? {
+	let name = entry.name();
+	if name == "." || name == ".." {
+	    continue;
+	}
+	let path = dir.join(name);
+	if mounts.contains(&path) {
+	    continue;
+	}
+	let attr = entry.attr();
+	found.push(Entry { path: relative.join(name), attr });
+	if attr.directory {
+	    visit(&path, relative.join(name), mounts, found)?;
+	}
+    }
+    Ok(())
+}
@@ -0,0 +1,178 @@
+//! The framebuffer the firmware scans out to the HDMI output, built in by
+//! the `gfx` feature and started through the driver registry. The kernel
+//! fills rectangles of it and prints its log on it as text, through the
+//! `screen` log sink; the console stays on the serial port.
+
+mod font;
+
+use core::fmt::{self, Write};
+use core::ptr;
+
+use log::LevelFilter;
+use pi::mbox::{self, Framebuffer};
+
+use crate::logger::{self, Format, Sink};
+use crate::mutex::Mutex;
+use crate::param::{FB_DEPTH, FB_HEIGHT, FB_WIDTH};
+use crate::percore;
+use crate::VMM;
+
+/// The framebuffer, once `initialize()` allocated it
+pub static DISPLAY: Mutex<Option<Display>> = Mutex::new(None);
+
+/// Colors of text: the default one and those of the ANSI escape codes
+/// the log uses for levels, see `logger::Format::color`
+const TEXT: u32 = 0xaa_aaaa;
+const RED: u32 = 0xff_5555;
+const GREEN: u32 = 0x55_ff55;
+const YELLOW: u32 = 0xff_ff55;
+const GRAY: u32 = 0x55_5555;
+
+/// The most verbose level the `screen` sink starts out at
+const SCREEN_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// A framebuffer of 32 bit pixels, 0xRRGGBB.
+pub struct Display {
+    fb: Framebuffer,
+    /// the cell of the text cursor, in characters
+    col: u32,
+    row: u32,
+    /// the color of the text printed next
+    color: u32,
+    /// the parameter of the ANSI escape code being printed, if any
+    escape: Option<u32>,
+}
+
+/// Asks the firmware for a framebuffer of `FB_WIDTH` by `FB_HEIGHT`, maps it
+/// and clears it to black.
+pub fn initialize() -> Result<(), &'static str> {
+    let fb = mbox::allocate_framebuffer(FB_WIDTH, FB_HEIGHT, FB_DEPTH)
+	.ok_or("the firmware allocated no framebuffer")?;
+    if fb.depth != 32 {
+	return Err("the firmware picked pixels of other than 32 bits");
+    }
+    if !VMM.map_uncached(fb.addr, fb.addr + fb.size) {
+	return Err("the framebuffer overlaps memory the kernel maps");
+    }
+    let mut display = Display { fb, col: 0, row: 0, color: TEXT, escape: None };
+    display.fill(0, 0, fb.width, fb.height, 0);
+    *DISPLAY.lock() = Some(display);
+    logger::attach("screen", &Screen, SCREEN_LEVEL, Format { timestamp: true, color: true });
+    Ok(())
+}
+
+/// The framebuffer as a log sink, attached once there is one.
+struct Screen;
+
+impl Sink for Screen {
+    fn write(&self, line: fmt::Arguments) {
+	// a handler leaves the screen to the thread it interrupted
+	if percore::in_handler() && DISPLAY.is_locked() {
+	    return;
+	}
+	if let Some(display) = DISPLAY.lock().as_mut() {
+	    let _ = display.write_fmt(line);
+	}
+    }
+}
+
+impl Display {
+    /// Returns the geometry and address of the framebuffer.
+    pub fn info(&self) -> Framebuffer {
+	self.fb
+    }
+
+    /// Fills the `width` by `height` pixels at `x`, `y` with `color`,
+    /// 0xRRGGBB, clipped to the screen.
+    pub fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, color: u32) {
+	let fb = &self.fb;
+	let x_end = x.saturating_add(width).min(fb.width);
+	let y_end = y.saturating_add(height).min(fb.height);
+	for row in y..y_end {
+	    let line = fb.addr + row as usize * fb.pitch as usize;
+	    for col in x..x_end {
+		unsafe { ptr::write_volatile((line + col as usize * 4) as *mut u32, color) };
+	    }
+	}
+    }
+
+    /// Prints `byte` at the cursor and moves it on. Text wraps at the right
+    /// edge and, past the last row, starts over at the top rather than
+    /// scrolling: the framebuffer is uncached, too slow to move. The row the
+    /// cursor moves to is cleared. Understands `\n`, `\r` and the ANSI escape
+    /// codes that set the color.
+    pub fn write_byte(&mut self, byte: u8) {
+	if let Some(param) = self.escape {
+	    match byte {
+		b'[' => {},
+		b';' => self.escape = Some(0),
+		b'0'..=b'9' => self.escape = Some(param.saturating_mul(10).saturating_add((byte - b'0') as u32)),
+		_ => {
+		    self.color = match param {
+			31 => RED,
+			32 => GREEN,
+			33 => YELLOW,
+			90 => GRAY,
+			_ => TEXT,
+		    };
+		    self.escape = None;
+		},
+	    }
+	    return;
+	}
+	match byte {
+	    0x1b => self.escape = Some(0),
+	    b'\n' => self.newline(),
+	    b'\r' => self.col = 0,
+	    _ => {
+		if self.col == self.fb.width / font::WIDTH {
+		    self.newline();
+		}
+		self.draw(byte);
+		self.col += 1;
+	    },
+	}
+    }
+
+    /// Draws the glyph of `byte`, a box if it has none, in the cell of the
+    /// cursor.
+    fn draw(&mut self, byte: u8) {
+	let (x, y) = (self.col * font::WIDTH, self.row * font::HEIGHT);
+	self.fill(x, y, font::WIDTH, font::HEIGHT, 0);
+	let glyph = match byte {
+	    b' ' => return,
+	    font::FIRST..=font::LAST => &font::GLYPHS[(byte - font::FIRST) as usize],
+	    _ => {
+		self.fill(x + 1, y + 2, font::WIDTH - 2, font::HEIGHT - 4, GRAY);
+		return;
+	    },
+	};
+	for (dy, bits) in glyph.iter().enumerate() {
+	    for dx in 0..font::WIDTH {
+		if bits & 0x80 >> dx != 0 {
+		    self.fill(x + dx, y + dy as u32, 1, 1, self.color);
+		}
+	    }
+	}
+    }
+
+    /// Moves the cursor to the start of the next row and clears that row.
+    fn newline(&mut self) {
+	self.col = 0;
+	self.row += 1;
+	if self.row == self.fb.height / font::HEIGHT {
+	    self.row = 0;
+	}
+	let width = self.fb.width;
+	self.fill(0, self.row * font::HEIGHT, width, font::HEIGHT, 0);
+    }
+}
+
+impl fmt::Write for Display {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+	for byte in s.bytes() {
+	    self.write_byte(byte);
+	}
+	Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use aarch64::*;
 
 use core::mem::zeroed;
-use core::ptr::write_volatile;
+use core::ptr::{read_volatile, write_volatile};
 
 mod oom;
 mod panic;
@@ -22,12 +22,13 @@ global_asm!(include_str!("init/vectors.s"));
 // so, no debug build support!
 //
 
-/// Kernel entrypoint for core 0
+/// Kernel entrypoint for core 0. The firmware passes the address of the
+/// device tree in `dtb`, or 0 when it passes ATAGs.
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
+pub unsafe extern "C" fn _start(dtb: usize) -> ! {
     if MPIDR_EL1.get_value(MPIDR_EL1::Aff0) == 0 {
         SP.set(KERN_STACK_BASE);
-        kinit()
+        kinit(dtb)
     }
     unreachable!()
 }
@@ -107,8 +108,9 @@ unsafe fn switch_to_el1() {
 }
 
 #[no_mangle]
-unsafe fn kinit() -> ! {
+unsafe fn kinit(dtb: usize) -> ! {
     zeros_bss();
+    crate::dtb::set_blob(dtb);
     switch_to_el2();
     switch_to_el1();
     kmain();
@@ -117,8 +119,9 @@ unsafe fn kinit() -> ! {
 /// Kernel entrypoint for core 1, 2, and 3
 #[no_mangle]
 pub unsafe extern "C" fn start2() -> ! {
-    // Lab 5 1.A
-    unimplemented!("start2")
+    let core = MPIDR_EL1.get_value(MPIDR_EL1::Aff0) as usize;
+    SP.set(KERN_STACK_BASE - core * KERN_STACK_SIZE);
+    kinit2()
 }
 
 unsafe fn kinit2() -> ! {
@@ -128,13 +131,48 @@ unsafe fn kinit2() -> ! {
 }
 
 unsafe fn kmain2() -> ! {
-    // Lab 5 1.A
-    unimplemented!("kmain2")
+    // tells core 0 this core is up; its cache is off, so the write goes
+    // straight to memory
+    write_volatile(SPINNING_BASE.add(affinity()), 0);
+
+    VMM.wait();
+    crate::kaslr::relocate();
+    crate::vdso::setup();
+    crate::perf::setup();
+    crate::SCHEDULER.start()
+}
+
+/// Cleans and invalidates the cache line holding `addr` so that memory and
+/// a core with its cache off see the same value.
+unsafe fn sync_line(addr: *mut usize) {
+    asm!("dc civac, $0
+          dsb sy" :: "r"(addr) :: "volatile");
 }
 
 /// Wakes up each app core by writing the address of `init::start2`
 /// to their spinning base and send event with `sev()`.
+///
+/// The cores run from the physical address with their MMU off, and the
+/// firmware's spin loop reads the table from memory, past the cache of
+/// core 0. Returns once every core cleared its entry, which `kmain2()` does
+/// first thing.
 pub unsafe fn initialize_app_cores() {
-    // Lab 5 1.A
-    unimplemented!("initialize_app_cores")
+    let entry = crate::kaslr::phys(start2 as usize);
+    for core in 1..NCORES {
+        let spinning = SPINNING_BASE.add(core);
+        write_volatile(spinning, entry);
+        sync_line(spinning);
+    }
+    sev();
+
+    for core in 1..NCORES {
+        let spinning = SPINNING_BASE.add(core);
+        loop {
+            sync_line(spinning);
+            if read_volatile(spinning) == 0 {
+                break;
+            }
+        }
+    }
+    info!("app cores released");
 }
@@ -1,6 +1,42 @@
 use core::alloc::Layout;
 
+use crate::allocator::{Stats, SIZE_CLASSES};
+use crate::console::print_raw;
+use crate::rawfmt::StackBuf;
+use crate::ALLOCATOR;
+
+/// Reports the failed request and the heap usage before panicking. Nothing
+/// here may allocate, the heap being what ran out.
 #[alloc_error_handler]
-pub fn oom(_layout: Layout) -> ! {
+pub fn oom(layout: Layout) -> ! {
+    print_raw(StackBuf::new()
+	.push_str("out of memory allocating ").push_dec(layout.size() as u64)
+	.push_str(" bytes aligned to ").push_dec(layout.align() as u64)
+	.push_str("\n")
+	.as_str());
+
+    let stats = ALLOCATOR.stats();
+    let heap = ALLOCATOR.bounds().map(|(start, end)| end - start).unwrap_or(0);
+    print_raw(StackBuf::new()
+	.push_str("heap: ").push_dec(stats.live_bytes as u64)
+	.push_str(" bytes live, ").push_dec(stats.peak_bytes as u64)
+	.push_str(" peak, ").push_dec(heap as u64)
+	.push_str(" total; ").push_dec(stats.allocs)
+	.push_str(" allocs, ").push_dec(stats.frees)
+	.push_str(" frees, ").push_dec(stats.failures)
+	.push_str(" failures\n")
+	.as_str());
+    for index in 0..SIZE_CLASSES {
+	let class = stats.classes[index];
+	if class.live == 0 {
+	    continue;
+	}
+	print_raw(StackBuf::new()
+	    .push_str("  <= ").push_dec(Stats::class_size(index) as u64)
+	    .push_str(": ").push_dec(class.live)
+	    .push_str(" live of ").push_dec(class.allocs)
+	    .push_str("\n")
+	    .as_str());
+    }
     panic!("OOM");
 }
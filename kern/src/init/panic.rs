@@ -1,10 +1,37 @@
+use core::fmt::Write;
 use core::panic::PanicInfo;
-use crate::console::kprintln;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
 
-#[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
+use pi::pm::Watchdog;
+
+use crate::bootargs;
+use crate::console::PanicConsole;
+use crate::crashlog::CrashLog;
+use crate::led;
+use crate::morse;
+use crate::rawfmt::{self, StackBuf};
+
+/// Reads the `panic=N` boot argument. When `N` is a positive number of
+/// seconds, the watchdog is armed on panic and resets the board after `N`
+/// seconds. Otherwise the kernel halts forever.
+fn reboot_timeout() -> Option<Duration> {
+    match bootargs::get("panic")?.parse::<u64>() {
+	Ok(secs) if secs > 0 => Some(Duration::from_secs(secs)),
+	_ => None,
+    }
+}
 
-    kprintln!("
+/// Set by the first panic. A panic while reporting one, such as from the
+/// `Display` impl of a panic message, only prints where it happened.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// The report is built here rather than on the stack, which may be what
+/// overflowed. Only the first panic gets to use it.
+static mut REPORT: StackBuf = StackBuf::new();
+
+/// Printed first thing on panic
+const BANNER: &str = "
             (
        (      )     )
          )   (    (
@@ -15,13 +42,72 @@ fn panic(_info: &PanicInfo) -> ! {
      `================`
 
     The pi is overdone.
-");
+
+";
+
+/// Reports the panic without allocating, without the console lock and
+/// without going through the console's redirection, as the panic may come
+/// from the allocator, the console or the file system, which would fault
+/// again and again. Interrupts are masked first so that nothing is
+/// scheduled in the middle of the report.
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    aarch64::disable_irq_interrupt();
+    let mut console = PanicConsole::get();
+
+    if PANICKING.swap(true, Ordering::Relaxed) {
+	let _ = console.write_str("\npanicked while panicking");
+	if let Some(location) = _info.location() {
+	    let _ = console.write_str(" at ");
+	    let _ = console.write_str(location.file());
+	    let _ = console.write_str(":");
+	    let _ = console.write_str(rawfmt::dec(location.line() as u64, &mut [0; 20]));
+	}
+	let _ = console.write_str("\n");
+	loop {
+	    aarch64::wfe();
+	}
+    }
+
+    let _ = console.write_str(BANNER);
+
+    let report = unsafe { &mut REPORT };
     if let Some(location) = _info.location() {
-	kprintln!("FILE: {}\n LINE: {}\n COL: {}\n", location.file(), location.line(), location.column());
+	report.push_str("FILE: ").push_str(location.file())
+	    .push_str("\n LINE: ").push_dec(location.line() as u64)
+	    .push_str("\n COL: ").push_dec(location.column() as u64)
+	    .push_str("\n\n");
+    }
+    match _info.message() {
+	Some(message) => {
+	    let _ = report.write_fmt(*message);
+	},
+	None => {
+	    report.push_str(_info.payload().downcast_ref::<&str>().unwrap_or(&"panicked"));
+	},
     }
+    report.push_str("\n");
+    let _ = console.write_str(report.as_str());
 
-    kprintln!("{:?}", _info);
-    
-    
-    loop {}
+    // keep the report in reserved RAM so it is printed again after a reboot
+    if let Some(mut log) = CrashLog::get() {
+	log.reset();
+	let _ = log.write_str(report.as_str());
+	log.persist();
+    }
+
+    // a test run under QEMU fails instead of hanging
+    crate::qemu::test_exit(101);
+
+    if let Some(timeout) = reboot_timeout() {
+	let _ = console.write_str(StackBuf::new().push_str("rebooting in ").push_dec(timeout.as_secs()).push_str(" seconds\n").as_str());
+	Watchdog::new().start(timeout);
+    }
+
+    // a board without a console shows that it panicked, and why if it was
+    // still booting
+    if let Some(stage) = morse::failed_stage() {
+	morse::report(stage);
+    }
+    led::blink_panic()
 }
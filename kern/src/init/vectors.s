@@ -72,9 +72,9 @@ context_restore:
 	msr 	TTBR1_EL1, x1
 	msr 	TTBR0_EL1, x0
 
+	// translations of other address spaces stay in the TLB under their
+	// ASIDs; page table updates flush what they change
 	dsb     ishst
-	tlbi    vmalle1
-	dsb     ish
 	isb
 	
 	ldp 	q0, q1, [SP], #32
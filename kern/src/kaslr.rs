@@ -0,0 +1,168 @@
+//! Randomizes the virtual addresses the kernel runs at, given the `kaslr=on`
+//! boot argument. The kernel table maps the kernel image a second time, at
+//! a random page in the hole between the end of RAM and the peripherals,
+//! and `relocate()` moves execution there once the MMU is on.
+//!
+//! This is a lite version of KASLR: the code is not position independent,
+//! but the image is linked as a position independent executable, so the
+//! linker lists the absolute addresses in its data, like those of vtables
+//! and function pointers, in `.rela.dyn`. `relocate()` moves them to the
+//! alias too. Once every core runs there, `protect()` makes the identity
+//! mapping execute-never, so that only the alias holds kernel code. Which
+//! page the alias starts at is drawn from `rng`, so a fixed `rngseed` fixes
+//! it too.
+
+use core::mem::size_of;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use aarch64::{affinity, nop, VBAR_EL1};
+
+use crate::bootargs;
+use crate::param::{NCORES, PAGE_MASK, PAGE_SIZE};
+use crate::rng;
+use crate::VMM;
+
+extern "C" {
+    static __text_beg: u8;
+    static __text_end: u8;
+    static __rela_beg: u8;
+    static __rela_end: u8;
+
+    fn kaslr_jump(offset: usize);
+}
+
+global_asm!("
+.global kaslr_jump
+// Returns to the caller `x0` bytes further on than it was called from.
+kaslr_jump:
+    add     x30, x30, x0
+    ret
+");
+
+/// Distance from the kernel image to its alias, 0 without one
+static OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+/// Virtual addresses of the start and end of the alias
+static ALIAS_START: AtomicUsize = AtomicUsize::new(0);
+static ALIAS_END: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of cores running in the alias
+static RELOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// The only relocation a position independent kernel image has: the
+/// address `addend`, relative to where the image is loaded, is stored at
+/// `offset`
+const R_AARCH64_RELATIVE: u64 = 1027;
+
+/// An entry of `.rela.dyn`
+#[repr(C)]
+struct Rela {
+    offset: u64,
+    info: u64,
+    addend: u64,
+}
+
+/// Returns the physical start and end of the kernel image, rounded out to
+/// whole pages.
+pub fn image() -> (usize, usize) {
+    let start = phys(unsafe { &__text_beg as *const u8 as usize });
+    let end = phys(unsafe { &__text_end as *const u8 as usize });
+    (start & PAGE_MASK, (end + PAGE_SIZE - 1) & PAGE_MASK)
+}
+
+/// Returns the physical address of `addr`, an address in the kernel image
+/// or identity mapped. The address of a static the code takes is in the
+/// alias once the kernel runs there; hardware and page tables need the
+/// physical one.
+pub fn phys(addr: usize) -> usize {
+    match addr >= ALIAS_START.load(Ordering::Relaxed) && addr < ALIAS_END.load(Ordering::Relaxed) {
+	true => addr - OFFSET.load(Ordering::Relaxed),
+	false => addr,
+    }
+}
+
+/// Picks a random page in `hole_start..hole_end`, unmapped virtual address
+/// space, for the alias of the kernel image to start at if the `kaslr=on`
+/// boot argument asks for one and the hole fits the image. Returns the
+/// distance from the image to the alias, which `KernPageTable` maps.
+pub fn choose(hole_start: usize, hole_end: usize) -> Option<usize> {
+    match bootargs::get("kaslr") {
+	Some("on") => {},
+	None | Some("off") => return None,
+	Some(arg) => {
+	    warn!("kaslr: ignoring kaslr={}, not on or off", arg);
+	    return None;
+	},
+    }
+    let (start, end) = image();
+    if hole_end < hole_start + (end - start) {
+	warn!("kaslr: no room for the kernel image between RAM and the peripherals");
+	return None;
+    }
+    let slots = ((hole_end - hole_start - (end - start)) / PAGE_SIZE + 1) as u64;
+    let alias = hole_start + rng::below(slots) as usize * PAGE_SIZE;
+    ALIAS_START.store(alias, Ordering::Relaxed);
+    ALIAS_END.store(alias + (end - start), Ordering::Relaxed);
+    OFFSET.store(alias - start, Ordering::Relaxed);
+    debug!("kaslr: kernel image aliased at {:#x}, one of {} pages", alias, slots);
+    Some(alias - start)
+}
+
+/// Moves execution into the alias of the kernel image, exception vectors
+/// included, if there is one. The caller continues in the alias, but
+/// functions further up the stack return to the identity mapping, so it is
+/// called by `kmain()` and `kmain2()`, which never return. Inlined, so that
+/// the jump lands in the caller.
+///
+/// Core 0 first moves the absolute addresses in the image to the alias. It
+/// does so before it wakes up the other cores, which run from the identity
+/// mapping with their MMU off until they get here.
+#[inline(always)]
+pub unsafe fn relocate() {
+    let offset = OFFSET.load(Ordering::Relaxed);
+    if offset != 0 {
+	if affinity() == 0 {
+	    apply_relocations(offset);
+	}
+	VBAR_EL1.set(VBAR_EL1.get() + offset as u64);
+	kaslr_jump(offset);
+	RELOCATED.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// Adds `offset` to every absolute address `.rela.dyn` lists. The linker
+/// stored them for the address the image is loaded at, so the kernel runs
+/// from the identity mapping until then; both mappings are in place while
+/// they are rewritten.
+unsafe fn apply_relocations(offset: usize) {
+    let start = &__rela_beg as *const u8 as usize;
+    let end = &__rela_end as *const u8 as usize;
+    let relas = slice::from_raw_parts(start as *const Rela, (end - start) / size_of::<Rela>());
+    for rela in relas {
+	assert_eq!(rela.info, R_AARCH64_RELATIVE, "kaslr: unexpected relocation");
+	// written through the identity mapping, where the image is loaded
+	*(rela.offset as usize as *mut usize) = rela.addend as usize + offset;
+    }
+    // an address may have been in code, linked with text relocations
+    asm!("dsb ish
+	  ic iallu
+	  dsb ish
+	  isb" :::: "volatile");
+}
+
+/// Makes everything but the alias of the kernel image execute-never at EL1
+/// once every core runs in the alias, if there is one: the identity mapping
+/// of the image, and the rest of RAM and the peripherals mapped 1:1. Data
+/// stays readable and writable there. Called by `kmain()` after it woke up
+/// the other cores.
+pub fn protect() {
+    if OFFSET.load(Ordering::Relaxed) == 0 {
+	return;
+    }
+    while RELOCATED.load(Ordering::Acquire) < NCORES {
+	nop();
+    }
+    VMM.forbid_exec(ALIAS_START.load(Ordering::Relaxed), ALIAS_END.load(Ordering::Relaxed));
+    debug!("kaslr: identity mapping of the kernel image is execute-never");
+}
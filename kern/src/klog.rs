@@ -0,0 +1,142 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use pi::timer::current_time;
+use shim::io::{self, Seek, SeekFrom, Write};
+
+use crate::deferred::{self, Work};
+use crate::dmesg::DMESG;
+use crate::fs::vfs::{Handle, LockKind};
+use crate::mutex::Mutex;
+use crate::param::{KLOG_INTERVAL, KLOG_MAX_SIZE};
+use crate::FILESYSTEM;
+
+/// The file the kernel log is appended to. It is not created, so the service
+/// only runs on volumes that have it, and those must be writable: the SD card
+/// is not, since `libsd` only reads, so on a volume of the SD card the first
+/// append fails and the service stops. Hence the `klog` feature, off by
+/// default, until there is a writable block device to keep the log on.
+const LOG_PATH: &str = "/var/log/kernel.log";
+
+/// Where the log is moved once it grows past `KLOG_MAX_SIZE`. Without it
+/// the old log is dropped.
+const ROTATED_PATH: &str = "/var/log/kernel.log.1";
+
+struct Klog {
+    /// bytes of the kernel log, counted since boot, that were appended
+    flushed: u64,
+}
+
+static KLOG: Mutex<Option<Klog>> = Mutex::new(None);
+
+/// Microseconds since boot the log is next appended at, 0 while the service
+/// does not run
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// Starts appending the kernel log to `LOG_PATH` if the file exists.
+pub fn initialize() {
+    match FILESYSTEM.open_file(LOG_PATH) {
+	Ok(_) => {
+	    *KLOG.lock() = Some(Klog { flushed: 0 });
+	    NEXT.store(current_time().as_micros() as u64, Ordering::Relaxed);
+	},
+	Err(e) => info!("klog: not saving the kernel log to {}: {:?}", LOG_PATH, e),
+    }
+}
+
+/// Has the kernel log appended if `KLOG_INTERVAL` has passed since the last
+/// time. Called on every timer tick, so the file is written later, by
+/// `flush_due()`.
+pub fn tick() {
+    let next = NEXT.load(Ordering::Relaxed);
+    let now = current_time().as_micros() as u64;
+    if next != 0 && now >= next {
+	NEXT.store(now + KLOG_INTERVAL.as_micros() as u64, Ordering::Relaxed);
+	deferred::schedule(Work::KlogFlush);
+    }
+}
+
+/// Appends the kernel log for `tick()`. The service stops at the first
+/// error.
+pub fn flush_due() {
+    if let Err(e) = flush() {
+	KLOG.lock().take();
+	NEXT.store(0, Ordering::Relaxed);
+	warn!("klog: stopped saving the kernel log: {:?}", e);
+    }
+}
+
+/// Appends what was logged since the last call to `LOG_PATH`, rotating the
+/// file first if it would grow past `KLOG_MAX_SIZE`. Bytes the log ring
+/// dropped in between are noted in the file. Nothing is appended while
+/// another open file holds a lock on the log. Returns the number of bytes
+/// of the kernel log appended.
+pub fn flush() -> io::Result<usize> {
+    let mut guard = KLOG.lock();
+    let klog = match guard.as_mut() {
+	Some(klog) => klog,
+	None => return Ok(0),
+    };
+
+    let (written, pending, lost) = {
+	let dmesg = DMESG.lock();
+	let dmesg = match dmesg.as_ref() {
+	    Some(dmesg) => dmesg,
+	    None => return Ok(0),
+	};
+	let new = dmesg.written() - klog.flushed;
+	let (first, second) = dmesg.current().last(new as usize);
+	let mut pending = Vec::with_capacity(first.len() + second.len());
+	pending.extend_from_slice(first);
+	pending.extend_from_slice(second);
+	let lost = new - pending.len() as u64;
+	(dmesg.written(), pending, lost)
+    };
+    if written == klog.flushed {
+	return Ok(0);
+    }
+
+    let mut log = FILESYSTEM.open_file(LOG_PATH)?;
+    // a process holding the lock is reading or editing the log; try again
+    // next time
+    match log.lock(LockKind::Exclusive) {
+	Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(0),
+	result => result?,
+    }
+    if log.size() + pending.len() as u64 > KLOG_MAX_SIZE {
+	rotate(&mut *log)?;
+    }
+    log.seek(SeekFrom::End(0))?;
+    if lost > 0 {
+	let mut note = String::new();
+	let _ = write!(note, "[klog] {} bytes of the log were lost\n", lost);
+	log.write_all(note.as_bytes())?;
+    }
+    log.write_all(&pending)?;
+    log.sync()?;
+    klog.flushed = written;
+    Ok(pending.len())
+}
+
+/// Moves the contents of `log` to `ROTATED_PATH` and empties it.
+fn rotate(log: &mut dyn Handle) -> io::Result<()> {
+    match FILESYSTEM.open_file(ROTATED_PATH) {
+	Ok(mut rotated) => {
+	    rotated.truncate()?;
+	    log.seek(SeekFrom::Start(0))?;
+	    let mut buf = [0u8; 512];
+	    loop {
+		match log.read(&mut buf)? {
+		    0 => break,
+		    n => rotated.write_all(&buf[..n])?,
+		}
+	    }
+	    rotated.sync()?;
+	},
+	Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+	Err(e) => return Err(e),
+    }
+    log.truncate()
+}
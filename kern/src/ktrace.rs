@@ -0,0 +1,124 @@
+//! The trace buffer: a ring of the last `TRACE_EVENTS` thread switches,
+//! interrupts and system calls of every core, for `tracevis` to turn into a
+//! timeline. Tracing is off until `start()` is called, by the shell's
+//! `ktrace` command or at boot by the `trace=on` boot argument. The buffer
+//! is dumped in the format of the `trace` crate.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use pi::timer::current_time;
+use shim::io;
+use trace::{Event, Header, Kind};
+
+use crate::bootargs;
+use crate::mutex::Mutex;
+use crate::param::TRACE_EVENTS;
+use crate::percore;
+
+/// Whether events are recorded
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Events exception handlers skipped because the buffer was locked, since
+/// the buffer was last cleared. Events a full ring overwrote are not counted.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+static BUFFER: Mutex<Option<Ring>> = Mutex::new(None);
+
+/// The last `TRACE_EVENTS` events, overwriting the oldest once full.
+struct Ring {
+    events: Vec<Event>,
+    /// index the next event is written to once `events` is full
+    next: usize,
+}
+
+impl Ring {
+    fn push(&mut self, event: Event) {
+	if self.events.len() < TRACE_EVENTS {
+	    self.events.push(event);
+	}
+	else {
+	    self.events[self.next] = event;
+	    self.next = (self.next + 1) % TRACE_EVENTS;
+	}
+    }
+
+    /// Returns the events oldest first.
+    fn iter(&self) -> impl Iterator<Item = &Event> {
+	self.events[self.next..].iter().chain(self.events[..self.next].iter())
+    }
+}
+
+/// Starts tracing at boot if the `trace=on` boot argument is given.
+pub fn initialize() {
+    match bootargs::get("trace") {
+	Some("on") => start(),
+	Some(arg) => warn!("ktrace: ignoring trace={}, not on", arg),
+	None => {},
+    }
+}
+
+/// Records events from now on, after those recorded so far.
+pub fn start() {
+    let mut buffer = BUFFER.lock();
+    if buffer.is_none() {
+	*buffer = Some(Ring { events: Vec::with_capacity(TRACE_EVENTS), next: 0 });
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Stops recording events. Those recorded so far are kept for `dump()`.
+pub fn stop() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Forgets the events recorded so far and frees the buffer.
+pub fn clear() {
+    *BUFFER.lock() = None;
+    DROPPED.store(0, Ordering::Relaxed);
+}
+
+/// Returns whether events are recorded, the number of events in the buffer
+/// and the number of events dropped.
+pub fn status() -> (bool, usize, u32) {
+    let events = BUFFER.lock().as_ref().map_or(0, |ring| ring.events.len());
+    (ENABLED.load(Ordering::Relaxed), events, DROPPED.load(Ordering::Relaxed))
+}
+
+/// Records an event of `kind` on this core if tracing. Safe to call from
+/// exception handlers: one that interrupted the holder of the buffer drops
+/// its event instead.
+pub fn record(kind: Kind, arg: u32) {
+    if !ENABLED.load(Ordering::Relaxed) {
+	return;
+    }
+    if percore::in_handler() && BUFFER.is_locked() {
+	DROPPED.fetch_add(1, Ordering::Relaxed);
+	return;
+    }
+    let event = Event {
+	time: current_time().as_micros() as u64,
+	core: aarch64::affinity() as u8,
+	kind,
+	arg,
+    };
+    if let Some(ring) = BUFFER.lock().as_mut() {
+	ring.push(event);
+    }
+}
+
+/// Writes the recorded events to `w`, a `Header` followed by the events
+/// oldest first.
+pub fn dump<W: io::Write>(w: &mut W) -> io::Result<()> {
+    // copied, so that the buffer is not held while `w` writes
+    let events: Vec<Event> = match BUFFER.lock().as_ref() {
+	Some(ring) => ring.iter().cloned().collect(),
+	None => Vec::new(),
+    };
+    let header = Header { events: events.len() as u32, dropped: DROPPED.load(Ordering::Relaxed) };
+    w.write_all(&header.encode())?;
+    for event in events.iter() {
+	w.write_all(&event.encode())?;
+    }
+    Ok(())
+}
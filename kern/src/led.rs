@@ -0,0 +1,256 @@
+//! The board's activity LED as a status indicator for boards without a
+//! console attached. It blinks a heartbeat pattern while the kernel runs,
+//! a fault code for `LED_FAULT_TIME` after an exception the kernel could not
+//! handle, and a panic code once the kernel panicked. The state is kept in
+//! atomics, so that the fault and panic paths take no lock.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use pi::gpio::Gpio;
+use pi::mbox;
+use pi::timer::{current_time, spin_sleep};
+
+use crate::bootargs;
+use crate::deferred::{self, Work};
+use crate::param::{LED_FAULT_TIME, LED_STEP};
+
+/// Blinks of the code shown after an unhandled exception
+pub const FAULT_BLINKS: u64 = 2;
+/// Blinks of the code shown after a panic
+pub const PANIC_BLINKS: u64 = 3;
+
+/// Steps a blink code stays dark between repetitions
+const CODE_PAUSE: u64 = 6;
+
+/// Where the LED is wired to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Wiring {
+    None,
+    /// a pin of the SoC, like the ACT LED of the Raspberry Pi 3 Model B+
+    Gpio(u8),
+    /// a pin of the GPIO expander driven through the firmware, like the ACT
+    /// LED of the Raspberry Pi 3 Model B
+    Firmware(u32),
+}
+
+/// Bit of an encoded `Wiring` set for a firmware pin
+const FIRMWARE_PIN: u64 = 1 << 32;
+/// Encoded `Wiring::None`
+const NO_PIN: u64 = core::u64::MAX;
+
+impl Wiring {
+    /// Parses the value of the `led=` boot argument: `off`, `gpio:N` or
+    /// `fw:N`.
+    pub fn parse(s: &str) -> Option<Wiring> {
+	let mut parts = s.splitn(2, ':');
+	match (parts.next(), parts.next()) {
+	    (Some("off"), None) => Some(Wiring::None),
+	    (Some("gpio"), Some(pin)) => pin.parse::<u8>().ok().filter(|&pin| pin <= 53).map(Wiring::Gpio),
+	    (Some("fw"), Some(pin)) => pin.parse::<u32>().ok().map(Wiring::Firmware),
+	    _ => None,
+	}
+    }
+
+    fn encode(self) -> u64 {
+	match self {
+	    Wiring::None => NO_PIN,
+	    Wiring::Gpio(pin) => pin as u64,
+	    Wiring::Firmware(pin) => FIRMWARE_PIN | pin as u64,
+	}
+    }
+
+    fn decode(value: u64) -> Wiring {
+	match value {
+	    NO_PIN => Wiring::None,
+	    _ if value & FIRMWARE_PIN != 0 => Wiring::Firmware(value as u32),
+	    _ => Wiring::Gpio(value as u8),
+	}
+    }
+}
+
+impl fmt::Display for Wiring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    Wiring::None => write!(f, "off"),
+	    Wiring::Gpio(pin) => write!(f, "gpio:{}", pin),
+	    Wiring::Firmware(pin) => write!(f, "fw:{}", pin),
+	}
+    }
+}
+
+/// A sequence of up to `Pattern::MAX_STEPS` steps of `LED_STEP` each,
+/// repeated over and over, with the LED either lit or dark in each: bit `n`
+/// is set if it is lit in step `n`, and the top byte holds the number of
+/// steps.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pattern(u64);
+
+impl Pattern {
+    pub const MAX_STEPS: usize = 56;
+
+    /// Two short blinks a second, the default heartbeat
+    pub const HEARTBEAT: Pattern = Pattern(10 << Pattern::MAX_STEPS | 0b101);
+
+    /// Parses a pattern written as a `1` for every lit step and a `0` for
+    /// every dark one, as in `1010000000`.
+    pub fn parse(s: &str) -> Option<Pattern> {
+	if s.is_empty() || s.len() > Pattern::MAX_STEPS {
+	    return None;
+	}
+	let mut bits = 0;
+	for (step, c) in s.chars().enumerate() {
+	    match c {
+		'1' => bits |= 1 << step,
+		'0' => {},
+		_ => return None,
+	    }
+	}
+	Some(Pattern((s.len() as u64) << Pattern::MAX_STEPS | bits))
+    }
+
+    /// Returns the pattern of `blinks` short blinks and a pause.
+    fn code(blinks: u64) -> Pattern {
+	let bits = (0..blinks).fold(0, |bits, blink| bits | 1 << (2 * blink));
+	Pattern((2 * blinks + CODE_PAUSE) << Pattern::MAX_STEPS | bits)
+    }
+
+    fn steps(self) -> u64 {
+	self.0 >> Pattern::MAX_STEPS
+    }
+
+    fn is_lit(self, step: u64) -> bool {
+	self.0 & (1 << step) != 0
+    }
+
+    /// Returns whether the LED is lit at `time` since boot.
+    fn is_lit_at(self, time: Duration) -> bool {
+	let step = time.as_micros() as u64 / LED_STEP.as_micros() as u64;
+	self.is_lit(step % self.steps())
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	for step in 0..self.steps() {
+	    write!(f, "{}", if self.is_lit(step) { '1' } else { '0' })?;
+	}
+	Ok(())
+    }
+}
+
+/// The encoded `Wiring` of the LED, the ACT LED of the Raspberry Pi 3 Model
+/// B unless the `led=` boot argument says otherwise
+static WIRING: AtomicU64 = AtomicU64::new(FIRMWARE_PIN | 130);
+
+/// The heartbeat `Pattern`
+static HEARTBEAT: AtomicU64 = AtomicU64::new(Pattern::HEARTBEAT.0);
+
+/// Microseconds since boot until which the fault code is shown
+static FAULT_UNTIL: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the LED was last lit
+static LIT: AtomicBool = AtomicBool::new(false);
+
+/// Sets the wiring from the `led=` boot argument and the heartbeat from
+/// `heartbeat=`, if there are valid ones.
+pub fn initialize() {
+    if let Some(arg) = bootargs::get("led") {
+	match Wiring::parse(arg) {
+	    Some(wiring) => WIRING.store(wiring.encode(), Ordering::Relaxed),
+	    None => warn!("led: ignoring led={}, not off, gpio:N or fw:N", arg),
+	}
+    }
+    if let Some(arg) = bootargs::get("heartbeat") {
+	match Pattern::parse(arg) {
+	    Some(pattern) => set_heartbeat(pattern),
+	    None => warn!("led: ignoring heartbeat={}, not up to {} steps of 0 or 1", arg, Pattern::MAX_STEPS),
+	}
+    }
+    info!("led: {}, heartbeat {}", wiring(), heartbeat());
+    // the firmware may have left it lit
+    set(false);
+}
+
+pub fn wiring() -> Wiring {
+    Wiring::decode(WIRING.load(Ordering::Relaxed))
+}
+
+pub fn heartbeat() -> Pattern {
+    Pattern(HEARTBEAT.load(Ordering::Relaxed))
+}
+
+/// Blinks `pattern` from the next tick on.
+pub fn set_heartbeat(pattern: Pattern) {
+    HEARTBEAT.store(pattern.0, Ordering::Relaxed);
+}
+
+/// Lights the LED if `on` is set and darkens it otherwise. The heartbeat
+/// takes over again on the next tick that changes it.
+pub fn set(on: bool) {
+    match wiring() {
+	Wiring::None => {},
+	Wiring::Gpio(pin) => {
+	    let mut pin = Gpio::new(pin).into_output();
+	    match on {
+		true => pin.set(),
+		false => pin.clear(),
+	    }
+	},
+	Wiring::Firmware(pin) => {
+	    mbox::set_gpio_state(pin, on);
+	},
+    }
+    LIT.store(on, Ordering::Relaxed);
+}
+
+/// Returns whether the LED is lit at the current step of the heartbeat, or
+/// of the fault code while one is shown.
+fn lit_now() -> bool {
+    let now = current_time();
+    let pattern = match (now.as_micros() as u64) < FAULT_UNTIL.load(Ordering::Relaxed) {
+	true => Pattern::code(FAULT_BLINKS),
+	false => heartbeat(),
+    };
+    pattern.is_lit_at(now)
+}
+
+/// Moves the LED on to the current step. Called on every timer tick: a GPIO
+/// pin is written right away, while the blocking firmware call that drives
+/// the ACT LED is left to `update()`, after the interrupt.
+pub fn tick() {
+    let on = lit_now();
+    if on != LIT.load(Ordering::Relaxed) {
+	match wiring() {
+	    Wiring::Firmware(_) => deferred::schedule(Work::Led),
+	    _ => set(on),
+	}
+    }
+}
+
+/// Moves the LED on to the current step, for `tick()`.
+pub fn update() {
+    let on = lit_now();
+    if on != LIT.load(Ordering::Relaxed) {
+	set(on);
+    }
+}
+
+/// Shows the fault code for `LED_FAULT_TIME`. Safe to call from exception
+/// handlers.
+pub fn fault() {
+    FAULT_UNTIL.store((current_time() + LED_FAULT_TIME).as_micros() as u64, Ordering::Relaxed);
+}
+
+/// Blinks the panic code until the board is reset. For the panic handler,
+/// which runs with interrupts masked, so the timer no longer drives the LED.
+pub fn blink_panic() -> ! {
+    let pattern = Pattern::code(PANIC_BLINKS);
+    let mut step = 0;
+    loop {
+	set(pattern.is_lit(step));
+	spin_sleep(LED_STEP);
+	step = (step + 1) % pattern.steps();
+    }
+}
@@ -1,19 +1,270 @@
-use log::{LevelFilter, Metadata, Record};
+//! Fans kernel log messages out to sinks, each with its own level and
+//! format, attached and configured at runtime with `attach()` and
+//! `configure()` or the shell's `log` command.
+//!
+//! Two sinks are built in: the UART, with timestamps, and the ring `dmesg`
+//! reads. The `gfx` driver attaches a third, `screen`, that prints on the
+//! framebuffer in color. The network stack is not implemented yet (see
+//! `net`), so there is no netconsole sink; it attaches as a `Sink` once the
+//! driver exists.
 
-use crate::console::kprintln;
+use log::{Level, LevelFilter, Metadata, Record};
+
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use pi::timer::current_time;
+
+use crate::console::print_device;
+use crate::dmesg::DMESG;
+use crate::mutex::Mutex;
+use crate::param::LOG_RATELIMIT_INTERVAL;
+use crate::percore;
 
 struct KernelLogger;
 
 static LOGGER: KernelLogger = KernelLogger;
 
+/// Hash of the level and text of the last message logged
+static LAST: AtomicU64 = AtomicU64::new(0);
+
+/// Times the last message was logged again since it was last printed
+static REPEATS: AtomicU64 = AtomicU64::new(0);
+
+/// When the last message was printed, in microseconds since boot
+static PRINTED: AtomicU64 = AtomicU64::new(0);
+
+/// FNV-1a hash of formatted text, to recognize a message repeating without
+/// allocating a copy of it
+struct MessageHash(u64);
+
+impl fmt::Write for MessageHash {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100_0000_01b3);
+        }
+        Ok(())
+    }
+}
+
+/// A destination of kernel log messages, attached with `attach()`.
+pub trait Sink: Sync {
+    /// Writes `line`, a formatted message ending in a newline. May be called
+    /// from interrupt and fault handlers.
+    fn write(&self, line: fmt::Arguments);
+}
+
+/// The UART. Kernel messages reach it even while the shell redirects the
+/// console.
+struct Serial;
+
+impl Sink for Serial {
+    fn write(&self, line: fmt::Arguments) {
+        print_device(line);
+    }
+}
+
+/// The kernel log ring read by `dmesg`.
+struct Ring;
+
+impl Sink for Ring {
+    fn write(&self, line: fmt::Arguments) {
+        // a handler leaves the ring to the thread it interrupted
+        if percore::in_handler() && DMESG.is_locked() {
+            return;
+        }
+        if let Some(dmesg) = DMESG.lock().as_mut() {
+            let _ = dmesg.write_fmt(line);
+        }
+    }
+}
+
+/// How a sink's lines are decorated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Format {
+    /// prefix each line with the time since boot
+    pub timestamp: bool,
+    /// color each line by its level with ANSI escape codes
+    pub color: bool,
+}
+
+/// A sink with the most verbose level it takes and its format.
+#[derive(Copy, Clone)]
+pub struct Attached {
+    pub name: &'static str,
+    pub level: LevelFilter,
+    pub format: Format,
+    sink: &'static dyn Sink,
+}
+
+/// Sinks attached at once, including the built-in ones
+const MAX_SINKS: usize = 8;
+
+/// Level the built-in sinks start out at, unless the kernel was built with
+/// `VERBOSE_BUILD` set, which makes it `Trace`
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Debug;
+
+static SINKS: Mutex<[Option<Attached>; MAX_SINKS]> = Mutex::new([
+    Some(Attached { name: "serial", level: DEFAULT_LEVEL, format: Format { timestamp: true, color: false }, sink: &Serial }),
+    Some(Attached { name: "ring", level: DEFAULT_LEVEL, format: Format { timestamp: false, color: false }, sink: &Ring }),
+    None, None, None, None, None, None,
+]);
+
+/// Attaches `sink` as `name`, taking messages up to `level` formatted as
+/// `format`. Returns `false` if a sink of that name is attached already or
+/// `MAX_SINKS` are.
+pub fn attach(name: &'static str, sink: &'static dyn Sink, level: LevelFilter, format: Format) -> bool {
+    let mut sinks = SINKS.lock();
+    if sinks.iter().flatten().any(|attached| attached.name == name) {
+        return false;
+    }
+    match sinks.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => *slot = Some(Attached { name, level, format, sink }),
+        None => return false,
+    }
+    update_max_level(&sinks);
+    true
+}
+
+/// Detaches the sink `name`. Returns `false` if there is none.
+pub fn detach(name: &str) -> bool {
+    let mut sinks = SINKS.lock();
+    match sinks.iter_mut().find(|slot| slot.map_or(false, |attached| attached.name == name)) {
+        Some(slot) => *slot = None,
+        None => return false,
+    }
+    update_max_level(&sinks);
+    true
+}
+
+/// Changes the level and format of the sink `name`. Returns `false` if there
+/// is none.
+pub fn configure(name: &str, level: LevelFilter, format: Format) -> bool {
+    let mut sinks = SINKS.lock();
+    match sinks.iter_mut().flatten().find(|attached| attached.name == name) {
+        Some(attached) => {
+            attached.level = level;
+            attached.format = format;
+        },
+        None => return false,
+    }
+    update_max_level(&sinks);
+    true
+}
+
+/// Returns the attached sinks.
+pub fn sinks() -> Vec<Attached> {
+    SINKS.lock().iter().flatten().cloned().collect()
+}
+
+/// Lets through what the most verbose sink takes, so that messages no sink
+/// takes are not even formatted.
+fn update_max_level(sinks: &[Option<Attached>; MAX_SINKS]) {
+    let max = sinks.iter().flatten().map(|attached| attached.level).max().unwrap_or(LevelFilter::Off);
+    log::set_max_level(max);
+}
+
+/// A line of the log as a sink with `format` takes it.
+struct Line<'a> {
+    level: Level,
+    args: fmt::Arguments<'a>,
+    format: Format,
+}
+
+impl<'a> fmt::Display for Line<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.format.timestamp {
+            let now = current_time();
+            write!(f, "[{:5}.{:06}] ", now.as_secs(), now.subsec_micros())?;
+        }
+        if self.format.color {
+            let color = match self.level {
+                Level::Error => "31",
+                Level::Warn => "33",
+                Level::Info => "32",
+                Level::Debug => "39",
+                Level::Trace => "90",
+            };
+            write!(f, "\x1b[{}m{}\x1b[0m\n", color, self.args)
+        }
+        else {
+            write!(f, "{}\n", self.args)
+        }
+    }
+}
+
+/// Returns a copy of the attached sinks, or `None` in a handler while they
+/// are locked: the lock does not mask interrupts and is reentrant, so the
+/// handler may have interrupted this core in the middle of changing them.
+fn snapshot() -> Option<[Option<Attached>; MAX_SINKS]> {
+    if percore::in_handler() && SINKS.is_locked() {
+        return None;
+    }
+    Some(*SINKS.lock())
+}
+
+/// Writes `args` as a line to every sink taking messages of `level`. A
+/// handler that cannot look at the sinks writes it to the UART alone.
+fn emit(level: Level, args: fmt::Arguments) {
+    // copied, so that no lock is held while the sinks write
+    let sinks = match snapshot() {
+        Some(sinks) => sinks,
+        None => {
+            let format = Format { timestamp: true, color: false };
+            Serial.write(format_args!("{}", Line { level, args, format }));
+            return;
+        },
+    };
+    for attached in sinks.iter().flatten().filter(|attached| level <= attached.level) {
+        attached.sink.write(format_args!("{}", Line { level, args, format: attached.format }));
+    }
+}
+
 impl log::Log for KernelLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+    /// Whether a sink takes messages of the level of `metadata`. A handler
+    /// that cannot look at the sinks takes every message `log` lets through.
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match snapshot() {
+            Some(sinks) => sinks.iter().flatten().any(|attached| metadata.level() <= attached.level),
+            None => true,
+        }
     }
 
+    /// Prints the message unless it repeats the last one. Repeats are
+    /// counted and reported before the next different message, or with the
+    /// message again once per `LOG_RATELIMIT_INTERVAL`.
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            kprintln!("[{}] {}", record.level(), record.args());
+            let mut hash = MessageHash(0xcbf2_9ce4_8422_2325);
+            let _ = write!(hash, "{} {}", record.level(), record.args());
+            let now = current_time().as_micros() as u64;
+            let interval = LOG_RATELIMIT_INTERVAL.as_micros() as u64;
+            if LAST.load(Ordering::Relaxed) == hash.0
+                && now.saturating_sub(PRINTED.load(Ordering::Relaxed)) < interval {
+                // before the MMU is on, exclusives may never succeed; only
+                // one core runs then, so plain accesses are enough
+                if percore::is_mmu_ready() {
+                    REPEATS.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    REPEATS.store(REPEATS.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+                }
+                return;
+            }
+
+            let repeats = if percore::is_mmu_ready() {
+                REPEATS.swap(0, Ordering::Relaxed)
+            } else {
+                let repeats = REPEATS.load(Ordering::Relaxed);
+                REPEATS.store(0, Ordering::Relaxed);
+                repeats
+            };
+            if repeats > 0 {
+                emit(record.level(), format_args!("[last message repeated {} times]", repeats));
+            }
+            LAST.store(hash.0, Ordering::Relaxed);
+            PRINTED.store(now, Ordering::Relaxed);
+            emit(record.level(), format_args!("[{}] {}", record.level(), record.args()));
         }
     }
 
@@ -21,13 +272,12 @@ impl log::Log for KernelLogger {
 }
 
 pub unsafe fn init_logger() {
+    if option_env!("VERBOSE_BUILD").is_some() {
+        for attached in SINKS.lock().iter_mut().flatten() {
+            attached.level = LevelFilter::Trace;
+        }
+    }
     log::set_logger_racy(&LOGGER)
-        .map(|()| {
-            log::set_max_level(if let Some(_) = option_env!("VERBOSE_BUILD") {
-                LevelFilter::Trace
-            } else {
-                LevelFilter::Debug
-            })
-        })
+        .map(|()| update_max_level(&SINKS.lock()))
         .expect("Failed to initialize the logger");
 }
@@ -4,6 +4,7 @@
 #![feature(asm)]
 #![feature(global_asm)]
 #![feature(optin_builtin_traits)]
+#![feature(panic_info_message)]
 #![feature(ptr_internals)]
 #![feature(raw_vec_internals)]
 #![cfg_attr(not(test), no_std)]
@@ -16,16 +17,45 @@ extern crate alloc;
 extern crate log;
 
 pub mod allocator;
+pub mod bootargs;
+pub mod bootstat;
+pub mod clock;
 pub mod console;
+pub mod crashlog;
+pub mod deferred;
+pub mod dma;
+pub mod dmesg;
+pub mod driver;
+pub mod dtb;
+pub mod fileserver;
 pub mod fs;
+#[cfg(feature = "gfx")]
+pub mod gfx;
+pub mod kaslr;
+#[cfg(feature = "klog")]
+pub mod klog;
+pub mod ktrace;
+pub mod led;
 pub mod logger;
+pub mod morse;
 pub mod mutex;
+#[cfg(feature = "net")]
 pub mod net;
 pub mod param;
 pub mod percore;
+pub mod perf;
 pub mod process;
+pub mod qemu;
+pub mod rng;
+pub mod rawfmt;
 pub mod shell;
+#[cfg(feature = "sound")]
+pub mod sound;
+pub mod sysinfo;
 pub mod traps;
+#[cfg(feature = "usb")]
+pub mod usb;
+pub mod vdso;
 pub mod vm;
 
 use console::{kprint, kprintln, CONSOLE};
@@ -34,10 +64,12 @@ use pi::timer::spin_sleep;
 use pi::atags;
 use allocator::Allocator;
 use fs::FileSystem;
-use net::uspi::Usb;
+#[cfg(feature = "net")]
 use net::GlobalEthernetDriver;
 use process::GlobalScheduler;
 use traps::irq::{Fiq, GlobalIrq};
+#[cfg(feature = "usb")]
+use usb::Usb;
 use vm::VMManager;
 use aarch64::*;
 
@@ -46,9 +78,11 @@ pub static ALLOCATOR: Allocator = Allocator::uninitialized();
 pub static FILESYSTEM: FileSystem = FileSystem::uninitialized();
 pub static SCHEDULER: GlobalScheduler = GlobalScheduler::uninitialized();
 pub static VMM: VMManager = VMManager::uninitialized();
+#[cfg(feature = "usb")]
 pub static USB: Usb = Usb::uninitialized();
 pub static GLOBAL_IRQ: GlobalIrq = GlobalIrq::new();
 pub static FIQ: Fiq = Fiq::new();
+#[cfg(feature = "net")]
 pub static ETHERNET: GlobalEthernetDriver = GlobalEthernetDriver::uninitialized();
 
 extern "C" {
@@ -59,7 +93,12 @@ extern "C" {
 }
 
 unsafe fn kmain() -> ! {
+    bootstat::mark(bootstat::Phase::Kernel);
+    // the memory map comes from here, and the blob may be in the heap
+    crate::dtb::initialize();
+    crate::dmesg::initialize();
     crate::logger::init_logger();
+    crate::crashlog::replay();
 
     info!(
         "text beg: {:016x}, end: {:016x}",
@@ -69,6 +108,10 @@ unsafe fn kmain() -> ! {
         "bss  beg: {:016x}, end: {:016x}",
         &__bss_beg as *const _ as u64, &__bss_end as *const _ as u64
     );
+    match crate::dtb::memory() {
+	Some((start, end)) => info!("memory from device tree: {:08x}..{:08x}", start, end),
+	None => info!("no device tree, memory from ATAGs"),
+    }
     
     spin_sleep(Duration::from_secs(1));
     
@@ -79,23 +122,46 @@ unsafe fn kmain() -> ! {
     unsafe {
 	kprint!("initializing memory allocator... ");
 	ALLOCATOR.initialize();
+	bootstat::mark(bootstat::Phase::Allocator);
 	kprintln!("ready");
 
-	kprint!("initializing file system... ");
-        FILESYSTEM.initialize();
-	kprintln!("ready");
+	ktrace::initialize();
+	rng::initialize();
 
 	//kprint!("initializing irq handler... ");
 	//GLOBAL_IRQ.initialize();
 	//kprintln!("ready");
 
+	morse::booting(morse::Stage::VirtualMemory);
 	kprint!("initializing virtual memory manager... ");
 	VMM.initialize();
 	VMM.setup();
+	kaslr::relocate();
+	bootstat::mark(bootstat::Phase::VirtualMemory);
+	kprintln!("ready");
+
+	// the file system shares its volume through an `Arc`, whose atomics
+	// need the MMU on
+	morse::booting(morse::Stage::FileSystem);
+	kprint!("initializing file system... ");
+	FILESYSTEM.initialize();
+	bootstat::mark(bootstat::Phase::FileSystem);
 	kprintln!("ready");
 
+	#[cfg(feature = "klog")]
+	klog::initialize();
+
+	// processes map the vDSO page as soon as they are created
+	vdso::initialize();
+	perf::initialize();
+
+	clock::initialize();
+	led::initialize();
+	driver::initialize();
+	morse::booting(morse::Stage::Scheduler);
 	kprint!("initializing scheduler... ");
 	SCHEDULER.initialize();
+	bootstat::mark(bootstat::Phase::Scheduler);
 	kprintln!("ready\n\n");
 
 	kprintln!("
@@ -112,6 +178,12 @@ unsafe fn kmain() -> ! {
 Welcome to rustOS on Raspberry Pi!
 ");
 
+	morse::booted();
+	#[cfg(not(test))]
+	{
+	    init::initialize_app_cores();
+	    kaslr::protect();
+	}
 	SCHEDULER.start();
     }
 
@@ -0,0 +1,133 @@
+//! Reports a fatal error during boot as its number in Morse code, on a
+//! buzzer or LED wired to a GPIO pin, for boards with neither a display nor
+//! a serial console attached. The output is chosen by the `morse=` boot
+//! argument and is off without one:
+//!
+//! - `morse=gpio:N` keys pin N, for an LED or an active buzzer.
+//! - `morse=tone:N` plays a tone of `MORSE_TONE_HZ` on pin N, for a passive
+//!   buzzer.
+//! - `morse=led` keys the status LED, see `led`.
+//!
+//! The number is that of the `Stage` of boot that failed. Reporting it takes
+//! neither the heap nor a lock, so it works no matter how far boot got.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use pi::gpio::Gpio;
+use pi::timer::spin_sleep;
+
+use crate::bootargs;
+use crate::led;
+use crate::param::{MORSE_TONE_HZ, MORSE_UNIT};
+use crate::rawfmt;
+
+/// The steps of boot, numbered as reported when one of them fails.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stage {
+    Allocator = 1,
+    FileSystem = 2,
+    VirtualMemory = 3,
+    Scheduler = 4,
+}
+
+/// The `Stage` boot is in, 0 once it is done
+static STAGE: AtomicU64 = AtomicU64::new(Stage::Allocator as u64);
+
+/// Notes that boot moved on to `stage`.
+pub fn booting(stage: Stage) {
+    STAGE.store(stage as u64, Ordering::Relaxed);
+}
+
+/// Notes that boot is done, after which failures are not reported.
+pub fn booted() {
+    STAGE.store(0, Ordering::Relaxed);
+}
+
+/// Returns the number of the stage boot failed in if it is not done yet.
+pub fn failed_stage() -> Option<u64> {
+    match STAGE.load(Ordering::Relaxed) {
+	0 => None,
+	stage => Some(stage),
+    }
+}
+
+/// The output chosen by `morse=`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Output {
+    Gpio(u8),
+    Tone(u8),
+    Led,
+}
+
+impl Output {
+    fn get() -> Option<Output> {
+	let arg = bootargs::get("morse")?;
+	let mut parts = arg.splitn(2, ':');
+	let kind = parts.next()?;
+	let pin = parts.next().and_then(|pin| pin.parse::<u8>().ok()).filter(|&pin| pin <= 53);
+	match (kind, pin) {
+	    ("gpio", Some(pin)) => Some(Output::Gpio(pin)),
+	    ("tone", Some(pin)) => Some(Output::Tone(pin)),
+	    ("led", None) => Some(Output::Led),
+	    _ => None,
+	}
+    }
+
+    /// Sends a dot or dash of `units` units, followed by a unit of silence.
+    fn key(self, units: u32) {
+	let on = MORSE_UNIT * units;
+	match self {
+	    Output::Gpio(pin) => {
+		let mut pin = Gpio::new(pin).into_output();
+		pin.set();
+		spin_sleep(on);
+		pin.clear();
+	    },
+	    Output::Tone(pin) => {
+		let mut pin = Gpio::new(pin).into_output();
+		let half_period = Duration::from_micros(500_000 / MORSE_TONE_HZ);
+		for _ in 0..on.as_micros() as u64 / (2 * half_period.as_micros() as u64) {
+		    pin.set();
+		    spin_sleep(half_period);
+		    pin.clear();
+		    spin_sleep(half_period);
+		}
+	    },
+	    Output::Led => {
+		led::set(true);
+		spin_sleep(on);
+		led::set(false);
+	    },
+	}
+	spin_sleep(MORSE_UNIT);
+    }
+}
+
+/// Returns the Morse code of `digit` as dots and dashes.
+fn code(digit: u8) -> &'static str {
+    const DIGITS: [&str; 10] = ["-----", ".----", "..---", "...--", "....-", ".....", "-....", "--...", "---..", "----."];
+    DIGITS[(digit - b'0') as usize]
+}
+
+/// Sends `number` in Morse code over and over on the output `morse=` chose,
+/// until the board is reset. Returns right away if there is none.
+pub fn report(number: u64) {
+    let output = match Output::get() {
+	Some(output) => output,
+	None => return,
+    };
+    let mut buf = [0; 20];
+    let digits = rawfmt::dec(number, &mut buf);
+    loop {
+	for digit in digits.bytes() {
+	    for symbol in code(digit).bytes() {
+		output.key(if symbol == b'-' { 3 } else { 1 });
+	    }
+	    // three units between digits, one of them sent with the last symbol
+	    spin_sleep(MORSE_UNIT * 2);
+	}
+	// seven units before the number repeats
+	spin_sleep(MORSE_UNIT * 4);
+    }
+}
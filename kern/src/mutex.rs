@@ -3,11 +3,15 @@ use core::fmt;
 use core::ops::{Deref, DerefMut, Drop};
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+use crate::percore::is_mmu_ready;
+
 #[repr(align(32))]
 pub struct Mutex<T> {
     data: UnsafeCell<T>,
     lock: AtomicBool,
     owner: AtomicUsize,
+    /// guards the owner holds, touched by the owner only
+    depth: AtomicUsize,
 }
 
 unsafe impl<T: Send> Send for Mutex<T> {}
@@ -25,27 +29,48 @@ impl<T> Mutex<T> {
         Mutex {
             lock: AtomicBool::new(false),
             owner: AtomicUsize::new(usize::max_value()),
+            depth: AtomicUsize::new(0),
             data: UnsafeCell::new(val),
         }
     }
 }
 
 impl<T> Mutex<T> {
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
+    /// Takes the lock if it is free or already held by the current core.
+    ///
+    /// Exclusive loads and stores only work on cacheable memory, so a core
+    /// without its MMU set up takes the lock with plain ones. Only core 0
+    /// runs before its MMU is, and the other cores take no lock before
+    /// theirs is.
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        let this = 0;
-        if !self.lock.load(Ordering::Relaxed) || self.owner.load(Ordering::Relaxed) == this {
+        let this = aarch64::affinity();
+        if self.owner.load(Ordering::Relaxed) == this {
+            self.depth.store(self.depth.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+            return Some(MutexGuard { lock: &self });
+        }
+        let acquired = if is_mmu_ready() {
+            self.lock.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+        } else if !self.lock.load(Ordering::Relaxed) {
             self.lock.store(true, Ordering::Relaxed);
-            self.owner.store(this, Ordering::Relaxed);
-            Some(MutexGuard { lock: &self })
+            true
         } else {
-            None
+            false
+        };
+        if !acquired {
+            return None;
         }
+        self.owner.store(this, Ordering::Relaxed);
+        self.depth.store(1, Ordering::Relaxed);
+        Some(MutexGuard { lock: &self })
+    }
+
+    /// Returns true if a guard of this lock is alive anywhere. Locking is
+    /// reentrant, so code that may interrupt the holder checks this first:
+    /// it would otherwise run inside the holder's critical section.
+    pub fn is_locked(&self) -> bool {
+        self.lock.load(Ordering::Relaxed)
     }
 
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
     #[inline(never)]
     pub fn lock(&self) -> MutexGuard<T> {
         // Wait until we can "aquire" the lock, then "acquire" it.
@@ -57,8 +82,14 @@ impl<T> Mutex<T> {
         }
     }
 
+    /// Drops one guard of the current core, freeing the lock with the last.
     fn unlock(&self) {
-        self.lock.store(false, Ordering::Relaxed);
+        let depth = self.depth.load(Ordering::Relaxed) - 1;
+        self.depth.store(depth, Ordering::Relaxed);
+        if depth == 0 {
+            self.owner.store(usize::max_value(), Ordering::Relaxed);
+            self.lock.store(false, Ordering::Release);
+        }
     }
 }
 
@@ -82,6 +113,23 @@ impl<'a, T: 'a> Drop for MutexGuard<'a, T> {
     }
 }
 
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Mutex<T> {
+        Mutex::new(T::default())
+    }
+}
+
+// The locks inside the file system behave like every other kernel lock.
+impl fat32::vfat::RawLock for Mutex<()> {
+    fn lock(&self) {
+        core::mem::forget(Mutex::lock(self));
+    }
+
+    fn unlock(&self) {
+        Mutex::unlock(self);
+    }
+}
+
 impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.try_lock() {
@@ -1,5 +1,4 @@
-///! Network device that wraps USPi in smoltcp abstraction
-pub mod uspi;
+//! Network device that wraps USPi in smoltcp abstraction
 
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
@@ -217,6 +216,13 @@ impl EthernetDriver {
     }
 }
 
+/// Starts the ethernet driver, for the driver registry, once the `usb` one
+/// is up. Refused while `EthernetDriver` is the unimplemented stubs of lab 5,
+/// which would panic.
+pub fn initialize() -> Result<(), &'static str> {
+    Err("the ethernet driver of lab 5 is not implemented")
+}
+
 /// A thread-safe wrapper for `EthernetDriver`.
 pub struct GlobalEthernetDriver(Mutex<Option<EthernetDriver>>);
 
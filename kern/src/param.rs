@@ -20,15 +20,124 @@ const_assert_eq!(
 );
 pub const USER_STACK_BASE: usize = core::usize::MAX & PAGE_MASK; //0xffff_ffff_ffff_0000
 pub const USER_MAX_VM_SIZE: usize = 0x4000_0000;
+/// Maximum number of threads in a user process, including the main thread.
+pub const USER_MAX_THREADS: usize = 32;
+/// Maximum number of files a user process can have open at once.
+pub const USER_MAX_FILES: usize = 16;
+/// Pages each user stack grows to, counting the one it starts out with,
+/// before touching the guard page below kills the process.
+pub const USER_STACK_MAX_PAGES: usize = 8;
+/// Address space set aside for each thread's stack: the most it grows to and
+/// an unmapped guard page below.
+pub const USER_STACK_SPAN: usize = (USER_STACK_MAX_PAGES + 1) * PAGE_SIZE;
+/// Base of the read-only kernel data page mapped into every process, right
+/// below the guard page of the lowest possible thread stack.
+pub const USER_VDSO_BASE: usize = USER_STACK_BASE - USER_MAX_THREADS * USER_STACK_SPAN;
+const_assert_eq!(USER_VDSO_BASE, kernel_api::vdso::VDSO_BASE as usize);
 const_assert_eq!(USER_IMG_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
 
 pub const KERN_STACK_BASE: usize = 0x80_000;
 pub const KERN_STACK_ALIGN: usize = PAGE_ALIGN;
 pub const KERN_STACK_SIZE: usize = PAGE_SIZE;
 
-/// The `tick` time.
-// FIXME: When you're ready, change this to something more reasonable.
-pub const TICK: Duration = Duration::from_millis(10);
+/// Size of the region at the top of RAM reserved for the panic report.
+pub const CRASHLOG_SIZE: usize = PAGE_SIZE;
+
+/// Size of the region below the crash log holding this and the previous
+/// boot's kernel log.
+pub const DMESG_SIZE: usize = 2 * PAGE_SIZE;
+
+/// Most bytes of the device tree's `bootargs` the kernel keeps; the rest
+/// of a longer command line is ignored.
+pub const CMDLINE_SIZE: usize = 1024;
+
+/// Events the trace buffer holds before it overwrites the oldest.
+pub const TRACE_EVENTS: usize = 8192;
+
+/// Released frames debug builds hold back, poisoned and unmapped, before
+/// returning them to the heap.
+pub const FRAME_QUARANTINE: usize = 64;
+
+/// Size of the region below the kernel log set aside for DMA buffers, at
+/// most 64 pages.
+pub const DMA_ZONE_SIZE: usize = 16 * PAGE_SIZE;
+
+/// Bytes received on the console that are held until a process reads them
+pub const CONSOLE_INPUT_SIZE: usize = 256;
+
+/// Messages each `kprintln_ratelimited!` call site prints per
+/// `LOG_RATELIMIT_INTERVAL`. The logger also reports a message repeating
+/// without a break once per interval.
+pub const LOG_RATELIMIT_BURST: u64 = 10;
+pub const LOG_RATELIMIT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the kernel log is appended to `/var/log/kernel.log`.
+#[cfg(feature = "klog")]
+pub const KLOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Size past which `/var/log/kernel.log` is rotated.
+#[cfg(feature = "klog")]
+pub const KLOG_MAX_SIZE: u64 = 256 * 1024;
+
+/// Bytes the files of `/tmp` may hold in total.
+pub const TMPFS_MAX_SIZE: usize = 4 * 1024 * 1024;
+
+/// Pages of files the page cache holds before it drops the least recently
+/// used one.
+pub const PAGE_CACHE_PAGES: usize = 32;
+
+/// Directory entries each FAT volume keeps from its lookups before it drops
+/// the least recently used one.
+pub const DCACHE_ENTRIES: usize = 128;
+
+/// Timer interrupts per second the scheduler starts with. The `hz=` boot
+/// argument and `clock::set_hz()` change it within `HZ_MIN..=HZ_MAX`.
+pub const HZ: u64 = 100;
+pub const HZ_MIN: u64 = 10;
+pub const HZ_MAX: u64 = 1000;
+
+/// Timer interrupts a process runs for before the scheduler preempts it.
+/// The `quantum=` boot argument and `scheduler::set_quantum()` change it
+/// within `1..=QUANTUM_MAX`.
+pub const QUANTUM: u64 = 1;
+pub const QUANTUM_MAX: u64 = 100;
+
+/// Switches a ready thread is passed over for before its priority rises by
+/// one nice level, so that a busy process of a better level cannot starve
+/// it.
+pub const PRIORITY_AGING: u64 = 4;
+
+/// Bytes a pipe holds before its writers wait for the reader.
+pub const PIPE_SIZE: usize = 4096;
+
+/// Most bytes `sendfile` moves at a time, from a cached page or through its
+/// kernel buffer.
+pub const SENDFILE_CHUNK: usize = 4096;
+
+/// Time each step of an LED pattern lasts.
+pub const LED_STEP: Duration = Duration::from_millis(100);
+
+/// How long the LED blinks the fault code after an exception the kernel
+/// could not handle, before it goes back to the heartbeat.
+pub const LED_FAULT_TIME: Duration = Duration::from_secs(5);
+
+/// Length of a dot when `morse` reports a failed boot; a dash is three.
+pub const MORSE_UNIT: Duration = Duration::from_millis(150);
+
+/// Pitch of the tone `morse=tone:N` plays on a passive buzzer.
+pub const MORSE_TONE_HZ: u64 = 2000;
+
+/// Size and depth of the framebuffer the `gfx` feature asks the firmware for.
+pub const FB_WIDTH: u32 = 1024;
+pub const FB_HEIGHT: u32 = 768;
+pub const FB_DEPTH: u32 = 32;
+
+/// Pitch and length of the tone `beep` plays without arguments.
+pub const BEEP_HZ: u32 = 880;
+pub const BEEP_TIME: Duration = Duration::from_millis(200);
+
+/// The `tick` time at the default `HZ`; `clock::tick()` is the current one.
+pub const TICK: Duration = Duration::from_micros(1_000_000 / HZ);
 
 // Match this value with `HZ` in `timer.h`
 pub const USPI_TIMER_HZ: usize = 10;
@@ -10,6 +10,8 @@ pub struct PerCore {
     preemption: AtomicI64,
     /// Is MMU initialized for this core?
     mmu_ready: AtomicBool,
+    /// Number of nested interrupt and fault handlers running on this core
+    handlers: AtomicI64,
     /// Local IRQ handler registry
     irq: LocalIrq,
 }
@@ -18,21 +20,25 @@ static PER_CORE_DATA: [PerCore; NCORES] = [
     PerCore {
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
+        handlers: AtomicI64::new(0),
         irq: LocalIrq::new(),
     },
     PerCore {
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
+        handlers: AtomicI64::new(0),
         irq: LocalIrq::new(),
     },
     PerCore {
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
+        handlers: AtomicI64::new(0),
         irq: LocalIrq::new(),
     },
     PerCore {
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
+        handlers: AtomicI64::new(0),
         irq: LocalIrq::new(),
     },
 ];
@@ -74,6 +80,31 @@ pub unsafe fn set_mmu_ready() {
     PER_CORE_DATA[cpu].mmu_ready.store(true, Ordering::Relaxed);
 }
 
+/// Returns the number of cores that have set up their MMU.
+pub fn online_cores() -> usize {
+    PER_CORE_DATA.iter().filter(|core| core.mmu_ready.load(Ordering::Relaxed)).count()
+}
+
+/// Marks the start of an interrupt or fault handler on this core.
+pub fn enter_handler() {
+    let cpu = aarch64::affinity();
+    PER_CORE_DATA[cpu].handlers.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Marks the end of the handler started by the last `enter_handler()`.
+pub fn exit_handler() {
+    let cpu = aarch64::affinity();
+    let cnt = PER_CORE_DATA[cpu].handlers.fetch_sub(1, Ordering::Relaxed);
+    assert!(cnt > 0, "Handler count goes to negative!")
+}
+
+/// Returns true if an interrupt or fault handler is running on this core,
+/// as opposed to a thread or a system call made by one.
+pub fn in_handler() -> bool {
+    let cpu = aarch64::affinity();
+    PER_CORE_DATA[cpu].handlers.load(Ordering::Relaxed) > 0
+}
+
 /// Returns a reference to the local IRQ handler registry of the current core.
 pub fn local_irq() -> &'static LocalIrq {
     let cpu = aarch64::affinity();
@@ -0,0 +1,49 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aarch64::*;
+
+use crate::bootargs;
+
+/// Whether processes may ask for the cycle counter, set by `perf=user` on
+/// the kernel command line
+static USER_ALLOWED: AtomicBool = AtomicBool::new(false);
+
+/// Starts the cycle counter of this core and reads whether processes may
+/// be let in to it.
+pub unsafe fn initialize() {
+    setup();
+
+    if bootargs::get("perf") == Some("user") {
+	USER_ALLOWED.store(true, Ordering::Relaxed);
+	info!("perf: processes may read the cycle counter");
+    }
+}
+
+/// Starts the cycle counter of the current core, counting at EL0 and EL1,
+/// with EL0 reads trapped until a process is let in by `load()`. Every core
+/// calls this once.
+pub unsafe fn setup() {
+    PMCCFILTR_EL0.set(0);
+    PMCNTENSET_EL0.set(PMCNTENSET_EL0::C);
+    PMCR_EL0.set(PMCR_EL0.get() | PMCR_EL0::LC | PMCR_EL0::C | PMCR_EL0::E);
+    PMUSERENR_EL0.set(0);
+}
+
+/// Returns true if processes may be given the cycle counter.
+pub fn user_allowed() -> bool {
+    USER_ALLOWED.load(Ordering::Relaxed)
+}
+
+/// Lets EL0 read the cycle counter if `enabled`, or traps its reads. Called
+/// whenever a thread is switched to, with the setting of its process.
+pub fn load(enabled: bool) {
+    let value = match enabled {
+	true => PMUSERENR_EL0::CR,
+	false => 0,
+    };
+    unsafe {
+	if PMUSERENR_EL0.get() != value {
+	    PMUSERENR_EL0.set(value);
+	}
+    }
+}
@@ -1,10 +1,27 @@
+mod args;
+mod checkpoint;
+pub mod elf;
+mod exit;
+mod family;
+mod fd;
 mod process;
 mod scheduler;
+mod snapshot;
 mod stack;
 mod state;
+mod thread;
+mod tls;
+mod usage;
 
+pub use self::exit::{ExitHook, ExitHooks};
+pub use self::family::{Children, Family, INIT_PID};
+pub use self::fd::{FdTable, OpenFile};
 pub use self::process::{Id, Process};
-pub use self::scheduler::GlobalScheduler;
+pub use self::scheduler::{quantum, reap_deferred, set_quantum, GlobalScheduler};
+pub use self::snapshot::{ProcessInfo, ThreadInfo};
 pub use self::stack::Stack;
 pub use self::state::State;
+pub use self::thread::ThreadGroup;
+pub use self::tls::TlsTemplate;
+pub use self::usage::Usage;
 pub use crate::param::TICK;
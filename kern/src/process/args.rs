@@ -0,0 +1,72 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use kernel_api::{OsError, OsResult, ARG_MAX};
+
+use crate::process::Process;
+use crate::vm::VirtualAddr;
+
+/// Auxiliary vector entry that ends the vector
+const AT_NULL: u64 = 0;
+
+impl Process {
+    /// Copies the argument vector `argv` and the environment `envp`, each
+    /// string of the form `NAME=value`, onto the stack of the program, which
+    /// was just loaded, in the System V AArch64 layout: from the new stack
+    /// pointer up, `argc`, the `argv` pointers, a null pointer, the `envp`
+    /// pointers, a null pointer and an empty auxiliary vector, with the
+    /// NUL-terminated strings above them.
+    ///
+    /// The program also finds `argc`, `argv` and `envp` in `x0`, `x1` and
+    /// `x2`, so that its entry point may take them as arguments.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if a string holds a NUL byte, `NoMemory` if
+    /// the whole takes more than `ARG_MAX` bytes, and `BadAddress` if the
+    /// stack is not mapped.
+    pub fn set_args(&mut self, argv: &[&str], envp: &[&str]) -> OsResult<()> {
+	if argv.iter().chain(envp.iter()).any(|s| s.as_bytes().contains(&0)) {
+	    return Err(OsError::InvalidArgument);
+	}
+	let strings: usize = argv.iter().chain(envp.iter()).map(|s| s.len() + 1).sum();
+	let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2;
+	if strings + words * size_of::<u64>() + 16 > ARG_MAX {
+	    return Err(OsError::NoMemory);
+	}
+
+	let top = self.context.sp as usize;
+	let strings_at = (top - strings) & !(size_of::<u64>() - 1);
+	let sp = (strings_at - words * size_of::<u64>()) & !0xF;
+
+	let mut block = vec![0u8; top - sp];
+	let mut pointers: Vec<u64> = Vec::with_capacity(words);
+	pointers.push(argv.len() as u64);
+	let mut at = strings_at;
+	for list in [argv, envp].iter() {
+	    for s in list.iter() {
+		pointers.push(at as u64);
+		block[at - sp..at - sp + s.len()].copy_from_slice(s.as_bytes());
+		at += s.len() + 1;
+	    }
+	    pointers.push(0);
+	}
+	pointers.push(AT_NULL);
+	pointers.push(0);
+	for (i, pointer) in pointers.iter().enumerate() {
+	    let offset = i * size_of::<u64>();
+	    block[offset..offset + size_of::<u64>()].copy_from_slice(&pointer.to_le_bytes());
+	}
+
+	if !self.vmap.lock().copy_to(VirtualAddr::from(sp), &block) {
+	    return Err(OsError::BadAddress);
+	}
+	let argv_at = (sp + size_of::<u64>()) as u64;
+	self.context.sp = sp as u64;
+	self.context.x[0] = argv.len() as u64;
+	self.context.x[1] = argv_at;
+	self.context.x[2] = argv_at + (argv.len() + 1) as u64 * size_of::<u64>() as u64;
+	Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use core::mem::size_of;
+use core::slice;
+
+use shim::io;
+use shim::io::{Read, Write};
+use shim::path::Path;
+
+use kernel_api::{OsError, OsResult};
+
+use aarch64::SPSR_EL1;
+
+use crate::param::{PAGE_MASK, PAGE_SIZE, USER_IMG_BASE, USER_MAX_VM_SIZE};
+use crate::process::Process;
+use crate::traps::TrapFrame;
+use crate::vm::{PagePerm, Region, VirtualAddr};
+use crate::FILESYSTEM;
+
+/// Marks the start of a checkpoint image.
+const MAGIC: [u8; 8] = *b"RSOSCKPT";
+
+/// Bumped whenever the layout of a checkpoint image changes.
+const VERSION: u32 = 2;
+
+/// Bits of the permission word stored with each page.
+const PERM_WRITE: u32 = 1 << 0;
+const PERM_EXEC: u32 = 1 << 1;
+
+// Checkpoint image layout, all integers little endian:
+//
+//   magic       [u8; 8]
+//   version     u32
+//   frame size  u32   (size of `TrapFrame`)
+//   page size   u64
+//   trap frame  [u8; frame size]
+//   num pages   u64
+//   pages       num pages * (va: u64, perm: u32, data: [u8; page size])
+//
+// `perm` holds `PERM_WRITE` and `PERM_EXEC`; a page shared copy-on-write is
+// saved as writable if writing to it would copy it.
+//
+// The trap frame is stored raw, so an image can only be restored by the same
+// kernel build that wrote it.
+
+fn write_u32<W: Write>(w: &mut W, val: u32) -> io::Result<()> {
+    w.write_all(&val.to_le_bytes())
+}
+
+fn write_u64<W: Write>(w: &mut W, val: u64) -> io::Result<()> {
+    w.write_all(&val.to_le_bytes())
+}
+
+fn encode_perm(perm: PagePerm) -> u32 {
+    let mut bits = 0;
+    if perm.writable() {
+	bits |= PERM_WRITE;
+    }
+    if perm.executable() {
+	bits |= PERM_EXEC;
+    }
+    bits
+}
+
+fn decode_perm(bits: u32) -> Option<PagePerm> {
+    match bits & !(PERM_WRITE | PERM_EXEC) {
+	0 => Some(PagePerm::new(bits & PERM_WRITE != 0, bits & PERM_EXEC != 0)),
+	_ => None,
+    }
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+impl Process {
+    /// Serializes the registers and every mapped user page of `self` into `w`.
+    /// Only the registers of this thread are saved; the stacks of its sibling
+    /// threads are saved as plain pages. Open files are not saved; the
+    /// restored process starts without any.
+    ///
+    /// The process must not be running while it is checkpointed, otherwise the
+    /// saved trap frame does not match its memory.
+    ///
+    /// This facility is experimental.
+    pub fn checkpoint<W: Write>(&self, w: &mut W) -> io::Result<()> {
+	w.write_all(&MAGIC)?;
+	write_u32(w, VERSION)?;
+	write_u32(w, size_of::<TrapFrame>() as u32)?;
+	write_u64(w, PAGE_SIZE as u64)?;
+
+	let frame = unsafe {
+	    slice::from_raw_parts(&*self.context as *const TrapFrame as *const u8, size_of::<TrapFrame>())
+	};
+	w.write_all(frame)?;
+
+	// pages the process never touched are read in, so that the image does
+	// not depend on the program file
+	let mut vmap = self.vmap.lock();
+	if !vmap.populate_all() {
+	    return Err(io::Error::new(io::ErrorKind::Other, "cannot read in the pages of the process"));
+	}
+	write_u64(w, vmap.mapped().count() as u64)?;
+	for (va, pa, perm) in vmap.mapped() {
+	    write_u64(w, va.as_u64())?;
+	    write_u32(w, encode_perm(perm))?;
+	    let page = unsafe {
+		slice::from_raw_parts(pa.as_ptr(), PAGE_SIZE)
+	    };
+	    w.write_all(page)?;
+	}
+	w.flush()
+    }
+
+    /// Rebuilds a process from a checkpoint image written by `checkpoint()`.
+    /// The page table is freshly allocated, so `ttbr0` and `ttbr1` are reset;
+    /// the process ID is assigned when the process is added to the scheduler.
+    /// Each page is mapped with the permission it was saved with.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if the image was not written by this kernel
+    /// build, if it would resume the process outside of EL0 or user memory,
+    /// or if a page lies outside of user memory, is not page aligned, is saved
+    /// twice or has an unknown permission. Returns `IoError` if the image is
+    /// truncated.
+    pub fn restore<R: Read>(r: &mut R) -> OsResult<Process> {
+	use crate::VMM;
+
+	let mut magic = [0u8; 8];
+	r.read_exact(&mut magic)?;
+	if magic != MAGIC
+	    || read_u32(r)? != VERSION
+	    || read_u32(r)? != size_of::<TrapFrame>() as u32
+	    || read_u64(r)? != PAGE_SIZE as u64 {
+	    return Err(OsError::InvalidArgument);
+	}
+
+	let mut process = Process::new()?;
+	let frame = unsafe {
+	    slice::from_raw_parts_mut(&mut *process.context as *mut TrapFrame as *mut u8, size_of::<TrapFrame>())
+	};
+	r.read_exact(frame)?;
+	if process.context.spsr & (SPSR_EL1::M | SPSR_EL1::M4) != 0
+	    || (process.context.elr as usize) < USER_IMG_BASE {
+	    return Err(OsError::InvalidArgument);
+	}
+
+	let num_pages = read_u64(r)?;
+	if num_pages > (USER_MAX_VM_SIZE / PAGE_SIZE) as u64 {
+	    return Err(OsError::InvalidArgument);
+	}
+	{
+	    let mut vmap = process.vmap.lock();
+	    for _ in 0..num_pages {
+		let va = read_u64(r)? as usize;
+		let perm = decode_perm(read_u32(r)?).ok_or(OsError::InvalidArgument)?;
+		// the vDSO page is already mapped, so `is_valid()` refuses it
+		// along with the pages saved twice
+		if va < USER_IMG_BASE || va & !PAGE_MASK != 0 || vmap.is_valid(VirtualAddr::from(va)) {
+		    return Err(OsError::InvalidArgument);
+		}
+		let page = vmap.alloc(VirtualAddr::from(va), perm);
+		r.read_exact(page)?;
+	    }
+	    // so that the stack grows further than the pages it had
+	    vmap.reserve(Region::stack(Process::get_stack_base().as_usize()));
+	}
+
+	process.context.ttbr0 = VMM.get_baddr().as_u64();
+	process.context.ttbr1 = process.vmap.lock().get_baddr().as_u64();
+	Ok(process)
+    }
+
+    /// Restores a process from the checkpoint image stored at `path`.
+    pub fn restore_from<P: AsRef<Path>>(path: P) -> OsResult<Process> {
+	let mut file = FILESYSTEM.open_file(path)?;
+	Process::restore(&mut file)
+    }
+}
@@ -0,0 +1,244 @@
+use core::mem::size_of;
+use core::slice;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use shim::const_assert_size;
+use shim::io::{Read, Seek, SeekFrom};
+
+use kernel_api::{OsError, OsResult};
+
+use crate::fs::vfs::Handle;
+use crate::mutex::Mutex;
+use crate::param::{USER_IMG_BASE, USER_VDSO_BASE};
+use crate::process::TlsTemplate;
+use crate::vm::{Backing, PagePerm, Region, UserPageTable};
+
+/// The first four bytes of every ELF file.
+pub const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_AARCH64: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const PT_TLS: u32 = 7;
+
+/// Segment permission bits of `ProgramHeader::flags`
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+pub struct ElfHeader {
+    pub ident: [u8; 16],
+    pub kind: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u64,
+    pub phoff: u64,
+    pub shoff: u64,
+    pub flags: u32,
+    pub ehsize: u16,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+const_assert_size!(ElfHeader, 64);
+
+impl ElfHeader {
+    /// Name of the file type, `None` for unknown types.
+    pub fn kind_name(&self) -> Option<&'static str> {
+	match self.kind {
+	    1 => Some("REL"),
+	    ET_EXEC => Some("EXEC"),
+	    3 => Some("DYN"),
+	    4 => Some("CORE"),
+	    _ => None,
+	}
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+const_assert_size!(ProgramHeader, 56);
+
+impl ProgramHeader {
+    /// Name of the segment type, `None` for unknown types.
+    pub fn kind_name(&self) -> Option<&'static str> {
+	match self.kind {
+	    0 => Some("NULL"),
+	    PT_LOAD => Some("LOAD"),
+	    2 => Some("DYNAMIC"),
+	    3 => Some("INTERP"),
+	    4 => Some("NOTE"),
+	    6 => Some("PHDR"),
+	    PT_TLS => Some("TLS"),
+	    0x6474_e550 => Some("GNU_EH_FRAME"),
+	    0x6474_e551 => Some("GNU_STACK"),
+	    0x6474_e552 => Some("GNU_RELRO"),
+	    _ => None,
+	}
+    }
+
+    /// Returns why the loader rejects the segment, if it does.
+    fn problem(&self) -> Option<&'static str> {
+	if self.filesz > self.memsz {
+	    return Some("segment is larger in the file than in memory");
+	}
+	match self.kind {
+	    PT_LOAD => {
+		let start = self.vaddr as usize;
+		match start.checked_add(self.memsz as usize) {
+		    Some(end) if start >= USER_IMG_BASE && end <= USER_VDSO_BASE => None,
+		    _ => Some("segment lies outside of the user image"),
+		}
+	    },
+	    PT_TLS if !self.align.is_power_of_two() => Some("TLS alignment is not a power of two"),
+	    _ => None,
+	}
+    }
+}
+
+/// The headers of an ELF file
+pub struct Elf {
+    pub header: ElfHeader,
+    pub segments: Vec<ProgramHeader>,
+}
+
+impl Elf {
+    /// Returns why the loader rejects the file, `None` if it loads it.
+    pub fn problem(&self) -> Option<&'static str> {
+	if self.header.ident[4] != ELFCLASS64 {
+	    return Some("not a 64-bit ELF");
+	}
+	if self.header.ident[5] != ELFDATA2LSB {
+	    return Some("not little endian");
+	}
+	if self.header.kind != ET_EXEC {
+	    return Some("not a statically linked executable");
+	}
+	if self.header.machine != EM_AARCH64 {
+	    return Some("not an AArch64 program");
+	}
+	self.segments.iter().filter_map(|segment| segment.problem()).next()
+    }
+}
+
+/// Reads a plain-old-data structure from `r`.
+fn read_struct<T: Default + Copy, R: Read>(r: &mut R) -> OsResult<T> {
+    let mut val = T::default();
+    let bytes = unsafe {
+	slice::from_raw_parts_mut(&mut val as *mut T as *mut u8, size_of::<T>())
+    };
+    r.read_exact(bytes)?;
+    Ok(val)
+}
+
+/// A program loaded from an ELF image.
+pub struct Image {
+    /// address of the first instruction
+    pub entry: u64,
+    /// the thread local storage template, if the program has a `PT_TLS` segment
+    pub tls: Option<TlsTemplate>,
+}
+
+/// Reads the ELF header and the program headers of `file`.
+///
+/// # Errors
+///
+/// Returns `InvalidArgument` if `file` is not an ELF file or its program
+/// headers have an unexpected size.
+pub fn parse<R: Read + Seek>(file: &mut R) -> OsResult<Elf> {
+    file.seek(SeekFrom::Start(0))?;
+    let header: ElfHeader = read_struct(file)?;
+    if header.ident[0..4] != ELF_MAGIC
+	|| header.phentsize as usize != size_of::<ProgramHeader>() {
+	return Err(OsError::InvalidArgument);
+    }
+
+    let mut segments = Vec::with_capacity(header.phnum as usize);
+    file.seek(SeekFrom::Start(header.phoff))?;
+    for _ in 0..header.phnum {
+	segments.push(read_struct::<ProgramHeader, R>(file)?);
+    }
+    Ok(Elf { header: header, segments: segments })
+}
+
+/// Loads the statically linked AArch64 executable in `file` into `vmap`.
+///
+/// Every `PT_LOAD` segment is reserved in `vmap` rather than copied: a page
+/// of it is allocated and read from `file` when the program first touches
+/// it, and the part of a segment past its file size reads as zeros. Segments
+/// must lie between `USER_IMG_BASE` and the vDSO page, which sits below the
+/// thread stacks.
+///
+/// # Errors
+///
+/// Returns `InvalidArgument` if `file` is not such an executable, a segment
+/// does not fit in the user address space, `Elf::problem()` tells which, or
+/// a segment lies past the end of the file.
+pub fn load(file: &Arc<Mutex<Box<dyn Handle>>>, vmap: &mut UserPageTable) -> OsResult<Image> {
+    let (elf, size) = {
+	let mut file = file.lock();
+	(parse(&mut *file)?, file.size())
+    };
+    if elf.problem().is_some() {
+	return Err(OsError::InvalidArgument);
+    }
+
+    let mut tls = None;
+    for segment in elf.segments.iter() {
+	match segment.kind {
+	    PT_LOAD => {
+		// a page past the end would only fail when it is touched
+		match segment.offset.checked_add(segment.filesz) {
+		    Some(end) if end <= size => reserve_segment(file, vmap, segment),
+		    _ => return Err(OsError::InvalidArgument),
+		}
+	    },
+	    PT_TLS => {
+		tls = Some(TlsTemplate {
+		    vaddr: segment.vaddr,
+		    filesz: segment.filesz,
+		    memsz: segment.memsz,
+		    align: segment.align,
+		});
+	    },
+	    _ => {},
+	}
+    }
+
+    Ok(Image { entry: elf.header.entry, tls: tls })
+}
+
+/// Reserves `segment`, which `ProgramHeader::problem()` accepted, in `vmap`
+/// with the permissions of its flags.
+fn reserve_segment(file: &Arc<Mutex<Box<dyn Handle>>>, vmap: &mut UserPageTable, segment: &ProgramHeader) {
+    if segment.memsz == 0 {
+	return;
+    }
+    vmap.reserve(Region {
+	start: segment.vaddr as usize,
+	len: segment.memsz as usize,
+	perm: PagePerm::new(segment.flags & PF_W != 0, segment.flags & PF_X != 0),
+	backing: Backing::File { file: file.clone(), offset: segment.offset, filesz: segment.filesz },
+	growth: 0,
+    });
+}
@@ -0,0 +1,50 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Cleanup to do once a process is gone
+pub type ExitHook = Box<dyn FnOnce() + Send>;
+
+/// The cleanup subsystems registered for a process, shared by its threads,
+/// each under the name of the subsystem. The hooks run when the last thread
+/// is dropped, whether the process exited, was killed or faulted, the last
+/// registered first. They run with the scheduler unlocked, but may run on
+/// any core and must not block on the process.
+#[derive(Default)]
+pub struct ExitHooks(Vec<(&'static str, ExitHook)>);
+
+impl ExitHooks {
+    /// Registers `hook` under `name`, replacing the hook registered under it
+    /// before, which is dropped without running.
+    pub fn add(&mut self, name: &'static str, hook: ExitHook) {
+	match self.0.iter_mut().find(|(registered, _)| *registered == name) {
+	    Some(slot) => slot.1 = hook,
+	    None => self.0.push((name, hook)),
+	}
+    }
+
+    /// Drops the hooks registered so far without running them.
+    pub fn clear(&mut self) {
+	self.0.clear();
+    }
+
+    /// Runs the hooks registered so far and forgets them.
+    pub fn run(&mut self) {
+	while let Some((_, hook)) = self.0.pop() {
+	    hook();
+	}
+    }
+}
+
+impl Drop for ExitHooks {
+    fn drop(&mut self) {
+	self.run();
+    }
+}
+
+impl fmt::Debug for ExitHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	let names: Vec<&str> = self.0.iter().map(|(name, _)| *name).collect();
+	f.debug_struct("ExitHooks").field("hooks", &names).finish()
+    }
+}
@@ -0,0 +1,135 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::mutex::Mutex;
+use crate::process::Id;
+
+/// Process ID of the init process, the first one the scheduler starts, which
+/// inherits the children of every process that exits before them
+pub const INIT_PID: Id = 1;
+
+/// The children of a process: those still running, and the zombies, which
+/// exited and keep their exit code until the process waits for them.
+///
+/// Children adopted from a process that exited are only kept as zombies
+/// while a thread waits for a child: nothing else would ever reap them.
+#[derive(Debug, Default)]
+pub struct Children {
+    running: BTreeSet<Id>,
+    zombies: BTreeMap<Id, u64>,
+    /// children handed over by `append()`
+    adopted: BTreeSet<Id>,
+    /// threads waiting in `waitpid`, see `wait()`
+    waiters: usize,
+}
+
+impl Children {
+    /// Records the new child `pid`.
+    pub fn adopt(&mut self, pid: Id) {
+	self.running.insert(pid);
+    }
+
+    /// Turns the child `pid` into a zombie holding `code`.
+    pub fn exit(&mut self, pid: Id, code: u64) {
+	if self.running.remove(&pid) {
+	    self.zombies.insert(pid, code);
+	    self.forget_orphans();
+	}
+    }
+
+    /// Returns `true` if `pid` is a child, running or not reaped yet.
+    pub fn contains(&self, pid: Id) -> bool {
+	self.running.contains(&pid) || self.zombies.contains_key(&pid)
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.running.is_empty() && self.zombies.is_empty()
+    }
+
+    /// Returns `true` if there is a child `waitpid` may still return for
+    /// `pid`: the child `pid`, or any child for 0.
+    pub fn waitable(&self, pid: Id) -> bool {
+	match pid {
+	    0 => !self.is_empty(),
+	    pid => self.contains(pid),
+	}
+    }
+
+    /// Records that a thread waits for a child until `done_waiting()`.
+    pub fn wait(&mut self) {
+	self.waiters += 1;
+    }
+
+    /// Records that a thread is done waiting. The zombies adopted meanwhile
+    /// are dropped once no thread waits any longer.
+    pub fn done_waiting(&mut self) {
+	self.waiters -= 1;
+	self.forget_orphans();
+    }
+
+    /// Drops the adopted zombies if no thread waits for them.
+    fn forget_orphans(&mut self) {
+	if self.waiters > 0 {
+	    return;
+	}
+	let gone: Vec<Id> = self.adopted.iter().cloned().filter(|pid| self.zombies.contains_key(pid)).collect();
+	for pid in gone {
+	    self.adopted.remove(&pid);
+	    self.zombies.remove(&pid);
+	}
+    }
+
+    /// Removes the zombie `pid`, or any zombie for 0, and returns its ID and
+    /// exit code. Returns `None` if there is no such zombie.
+    pub fn reap(&mut self, pid: Id) -> Option<(Id, u64)> {
+	let pid = match pid {
+	    0 => *self.zombies.keys().next()?,
+	    pid => pid,
+	};
+	self.adopted.remove(&pid);
+	self.zombies.remove(&pid).map(|code| (pid, code))
+    }
+
+    /// Takes over every child of `orphans`, zombies included, which are
+    /// dropped right away unless a thread is waiting.
+    pub fn append(&mut self, orphans: &mut Children) {
+	self.adopted.extend(orphans.running.iter().chain(orphans.zombies.keys()));
+	self.adopted.append(&mut orphans.adopted);
+	self.running.append(&mut orphans.running);
+	self.zombies.append(&mut orphans.zombies);
+	self.forget_orphans();
+    }
+}
+
+/// The parent and the children of a process, shared by its threads.
+#[derive(Debug, Clone)]
+pub struct Family {
+    /// ID of the parent, 0 for a process without one
+    pub ppid: Id,
+    /// children of the parent, where the exit of the process is recorded
+    pub parent: Option<Arc<Mutex<Children>>>,
+    /// children of the process
+    pub children: Arc<Mutex<Children>>,
+}
+
+impl Family {
+    /// Returns the family of a process without a parent or children.
+    pub fn new() -> Family {
+	Family {
+	    ppid: 0,
+	    parent: None,
+	    children: Arc::new(Mutex::new(Children::default())),
+	}
+    }
+
+    /// Returns the family of a new child of `pid`, whose family is `self`.
+    /// The child is recorded in `self` once it has an ID.
+    pub fn child(&self, pid: Id) -> Family {
+	Family {
+	    ppid: pid,
+	    parent: Some(self.children.clone()),
+	    children: Arc::new(Mutex::new(Children::default())),
+	}
+    }
+}
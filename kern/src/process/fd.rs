@@ -0,0 +1,348 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::fmt;
+
+use shim::io::{Read, Seek, SeekFrom, Write};
+
+use kernel_api::{OsError, OsResult};
+
+use crate::console::ConsoleFile;
+use crate::fs::vfs::{Handle, LockKind};
+use crate::mutex::Mutex;
+use crate::param::{SENDFILE_CHUNK, USER_MAX_FILES};
+
+/// A file opened by a process with `open`, the object descriptors refer to.
+/// Clones made by `dup`, `dup2` and by inheriting a descriptor table share
+/// the handle and so the position.
+#[derive(Clone)]
+pub struct OpenFile {
+    handle: Arc<Mutex<Box<dyn Handle>>>,
+    readable: bool,
+    writable: bool,
+}
+
+impl OpenFile {
+    pub fn new(handle: Box<dyn Handle>, readable: bool, writable: bool) -> OpenFile {
+	OpenFile { handle: Arc::new(Mutex::new(handle)), readable, writable }
+    }
+
+    /// Reads from the current position into `buf`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoAccess` if the file was not opened for reading.
+    pub fn read(&mut self, buf: &mut [u8]) -> OsResult<usize> {
+	if !self.readable {
+	    return Err(OsError::NoAccess);
+	}
+	Ok(self.handle.lock().read(buf)?)
+    }
+
+    /// Writes `buf` at the current position.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoAccess` if the file was not opened for writing.
+    pub fn write(&mut self, buf: &[u8]) -> OsResult<usize> {
+	if !self.writable {
+	    return Err(OsError::NoAccess);
+	}
+	Ok(self.handle.lock().write(buf)?)
+    }
+
+    /// Moves the position and returns it as an offset from the start.
+    pub fn seek(&mut self, pos: SeekFrom) -> OsResult<u64> {
+	Ok(self.handle.lock().seek(pos)?)
+    }
+
+    /// Takes, converts or releases the advisory lock of the file, shared with
+    /// the descriptors duplicated from this one.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WouldBlock` if another open file holds a conflicting lock.
+    pub fn lock(&mut self, kind: LockKind) -> OsResult<()> {
+	Ok(self.handle.lock().lock(kind)?)
+    }
+
+    /// Moves up to `len` bytes from the current position of the file to
+    /// `output` without passing them through user memory, and returns how
+    /// many were moved. Stops early at the end of the file or when `output`
+    /// takes less than it was given, leaving the position of the file right
+    /// after the bytes moved.
+    ///
+    /// A file read through the page cache is written to `output` straight
+    /// from its cached pages. Other files are copied through a kernel buffer,
+    /// and so is every file sent to a page cached `output`, as writing there
+    /// updates cached pages, maybe the one being read.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if the file is a pipe, whose bytes cannot be
+    /// put back if `output` does not take them, `NoAccess` if the file is
+    /// not open for reading or `output` not for writing, the errors of
+    /// reading, and those of `output.write()` if it took nothing.
+    pub fn send(&mut self, output: &mut OpenFile, len: usize) -> OsResult<usize> {
+	if self.blocking() {
+	    return Err(OsError::InvalidArgument);
+	}
+	if !self.readable || !output.writable {
+	    return Err(OsError::NoAccess);
+	}
+	let in_place = !output.handle.lock().page_cached();
+	let mut buf = Vec::new();
+	let mut sent = 0;
+	while sent < len {
+	    let count = min(len - sent, SENDFILE_CHUNK);
+	    let page = match in_place {
+		true => self.handle.lock().cached_page().transpose()?,
+		false => None,
+	    };
+	    // bytes the position moved past, to be put back if `output` does
+	    // not take them
+	    let (bytes, read): (&[u8], usize) = match &page {
+		Some(page) => (&page[..min(count, page.len())], 0),
+		None => {
+		    buf.resize(count, 0);
+		    let read = self.read(&mut buf)?;
+		    (&buf[..read], read)
+		},
+	    };
+	    if bytes.is_empty() {
+		break;
+	    }
+	    let written = match output.write(bytes) {
+		Ok(written) => written,
+		Err(e) => {
+		    self.seek(SeekFrom::Current(-(read as i64)))?;
+		    return match sent {
+			0 => Err(e),
+			_ => Ok(sent),
+		    };
+		},
+	    };
+	    self.seek(SeekFrom::Current(written as i64 - read as i64))?;
+	    sent += written;
+	    if written < bytes.len() {
+		break;
+	    }
+	}
+	Ok(sent)
+    }
+
+    /// Returns true if reads and writes wait for the other end, as for pipes.
+    pub fn blocking(&self) -> bool {
+	self.handle.lock().blocking()
+    }
+
+    fn sync(&mut self) -> OsResult<()> {
+	if self.writable {
+	    let mut handle = self.handle.lock();
+	    handle.flush()?;
+	    handle.sync()?;
+	}
+	Ok(())
+    }
+}
+
+/// An open descriptor: the file it refers to and its own flags, which the
+/// descriptors duplicated from it do not share.
+#[derive(Clone)]
+struct Descriptor {
+    file: OpenFile,
+    /// closed by `FdTable::close_on_exec()`
+    cloexec: bool,
+}
+
+impl Descriptor {
+    fn new(file: OpenFile) -> Descriptor {
+	Descriptor { file, cloexec: false }
+    }
+}
+
+/// The open files of a process, indexed by file descriptor. Shared by all
+/// threads of the process.
+#[derive(Default)]
+pub struct FdTable {
+    files: Vec<Option<Descriptor>>,
+}
+
+impl FdTable {
+    /// Returns a table with the standard input, output and error, 0 to 2,
+    /// open on the console.
+    pub fn with_console() -> FdTable {
+	let console = || Descriptor::new(OpenFile::new(Box::new(ConsoleFile), true, true));
+	FdTable { files: vec![Some(console()), Some(console()), Some(console())] }
+    }
+
+    /// Returns a table with the standard input open on the console and the
+    /// standard output and error on `output`, sharing it.
+    pub fn with_output(output: OpenFile) -> FdTable {
+	let console = Descriptor::new(OpenFile::new(Box::new(ConsoleFile), true, true));
+	FdTable { files: vec![Some(console), Some(Descriptor::new(output.clone())), Some(Descriptor::new(output))] }
+    }
+
+    /// Adds `file` under the lowest free descriptor and returns it. The
+    /// descriptor is not closed on exec.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoMemory` if `USER_MAX_FILES` files are open already.
+    pub fn insert(&mut self, file: OpenFile) -> OsResult<u64> {
+	let file = Descriptor::new(file);
+	match self.files.iter().position(|slot| slot.is_none()) {
+	    Some(fd) => {
+		self.files[fd] = Some(file);
+		Ok(fd as u64)
+	    },
+	    None if self.files.len() < USER_MAX_FILES => {
+		self.files.push(Some(file));
+		Ok(self.files.len() as u64 - 1)
+	    },
+	    None => Err(OsError::NoMemory),
+	}
+    }
+
+    /// Returns the file open under `fd`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFileDescriptor` if `fd` is not open.
+    pub fn get(&mut self, fd: u64) -> OsResult<&mut OpenFile> {
+	self.descriptor(fd).map(|descriptor| &mut descriptor.file)
+    }
+
+    fn descriptor(&mut self, fd: u64) -> OsResult<&mut Descriptor> {
+	self.files.get_mut(fd as usize)
+	    .and_then(|slot| slot.as_mut())
+	    .ok_or(OsError::InvalidFileDescriptor)
+    }
+
+    /// Returns `true` if `fd` is closed on exec.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFileDescriptor` if `fd` is not open.
+    pub fn cloexec(&mut self, fd: u64) -> OsResult<bool> {
+	Ok(self.descriptor(fd)?.cloexec)
+    }
+
+    /// Sets whether `fd` is closed on exec.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFileDescriptor` if `fd` is not open.
+    pub fn set_cloexec(&mut self, fd: u64, cloexec: bool) -> OsResult<()> {
+	self.descriptor(fd)?.cloexec = cloexec;
+	Ok(())
+    }
+
+    /// Writes back and closes the file open under `fd`. The descriptor is
+    /// freed even if writing back fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFileDescriptor` if `fd` is not open, or the error of
+    /// writing back.
+    pub fn close(&mut self, fd: u64) -> OsResult<()> {
+	let mut descriptor = self.files.get_mut(fd as usize)
+	    .and_then(|slot| slot.take())
+	    .ok_or(OsError::InvalidFileDescriptor)?;
+	while let Some(None) = self.files.last() {
+	    self.files.pop();
+	}
+	descriptor.file.sync()
+    }
+
+    /// Writes back and closes every descriptor, for a process that is gone.
+    /// Errors of writing back are ignored.
+    pub fn close_all(&mut self) {
+	for fd in 0..self.files.len() as u64 {
+	    let _ = self.close(fd);
+	}
+    }
+
+    /// Closes the descriptors marked to be closed on exec, for a process
+    /// about to run another program, so that it does not get hold of files
+    /// that were only meant for the old one. Errors of writing back are
+    /// ignored, as they are when a process exits.
+    pub fn close_on_exec(&mut self) {
+	let closed: Vec<u64> = self.files.iter().enumerate()
+	    .filter(|(_, slot)| slot.as_ref().map_or(false, |descriptor| descriptor.cloexec))
+	    .map(|(fd, _)| fd as u64)
+	    .collect();
+	for fd in closed {
+	    let _ = self.close(fd);
+	}
+    }
+
+    /// Makes the lowest free descriptor refer to the file open under `old`
+    /// and returns it. Both then share the position, but the new descriptor
+    /// is not closed on exec.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFileDescriptor` if `old` is not open and `NoMemory` if
+    /// `USER_MAX_FILES` files are open already.
+    pub fn dup(&mut self, old: u64) -> OsResult<u64> {
+	let file = self.get(old)?.clone();
+	self.insert(file)
+    }
+
+    /// Makes `new` refer to the file open under `old`, closing what `new`
+    /// referred to first. Both then share the position, but `new` is not
+    /// closed on exec.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFileDescriptor` if `old` is not open or `new` is not
+    /// below `USER_MAX_FILES`.
+    pub fn dup2(&mut self, old: u64, new: u64) -> OsResult<u64> {
+	let file = self.get(old)?.clone();
+	if old == new {
+	    return Ok(new);
+	}
+	if new as usize >= USER_MAX_FILES {
+	    return Err(OsError::InvalidFileDescriptor);
+	}
+	if self.get(new).is_ok() {
+	    let _ = self.close(new);
+	}
+	while self.files.len() <= new as usize {
+	    self.files.push(None);
+	}
+	self.files[new as usize] = Some(Descriptor::new(file));
+	Ok(new)
+    }
+
+    /// Returns a table for a child process with the same descriptors open,
+    /// each sharing the open file, and so the position, with this table. The
+    /// descriptors keep their flags; those closed on exec go away only once
+    /// the child runs another program.
+    pub fn inherit(&self) -> FdTable {
+	FdTable { files: self.files.clone() }
+    }
+
+    /// Returns the number of open files.
+    pub fn count(&self) -> usize {
+	self.files.iter().filter(|slot| slot.is_some()).count()
+    }
+}
+
+impl Drop for FdTable {
+    /// Writes back the files the process left open.
+    fn drop(&mut self) {
+	for descriptor in self.files.iter_mut().filter_map(|slot| slot.as_mut()) {
+	    let _ = descriptor.file.sync();
+	}
+    }
+}
+
+impl fmt::Debug for FdTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	f.debug_struct("FdTable").field("open", &self.count()).finish()
+    }
+}
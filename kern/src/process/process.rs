@@ -1,42 +1,77 @@
 use alloc::boxed::Box;
+use alloc::sync::Arc;
+#[cfg(feature = "net")]
 use alloc::vec::Vec;
 use shim::io;
-use shim::io::{Read, Write};
-use shim::path::Path;
+use shim::io::{Read, Seek, SeekFrom, Write};
+use shim::path::{Path, PathBuf};
 use core::mem;
 use core::ptr::Unique;
-use core::ops::Add;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
 
 use aarch64;
 use aarch64::vmsa::*;
+#[cfg(feature = "net")]
 use smoltcp::socket::SocketHandle;
 
+use crate::mutex::Mutex;
 use crate::param::*;
-use crate::process::{Stack, State};
+use crate::process::{elf, ExitHooks, Family, FdTable, Stack, State, ThreadGroup, Usage};
 use crate::traps::TrapFrame;
 use crate::vm::*;
 use kernel_api::{OsError, OsResult};
 
-use fat32::traits::FileSystem;
-use fat32::traits::{Dir, File, Entry};
-
 use crate ::FILESYSTEM;
 
 /// Type alias for the type of a process ID.
 pub type Id = u64;
 
 /// A structure that represents the complete state of a process.
+///
+/// Every thread is scheduled as its own `Process`; the threads of one
+/// process share `vmap`, `threads`, `files`, `cwd` and `perf_counters`.
 #[derive(Debug)]
 pub struct Process {
     /// The saved trap frame of a process.
     pub context: Box<TrapFrame>,
     /// The page table describing the Virtual Memory of the process
-    pub vmap: Box<UserPageTable>,
+    pub vmap: Arc<Mutex<UserPageTable>>,
     /// The scheduling state of the process.
     pub state: State,
     // Lab 5 2.C
     /// Socket handles held by the current process
+    #[cfg(feature = "net")]
     pub sockets: Vec<SocketHandle>,
+    /// ID of this thread, assigned by the scheduler
+    pub tid: Id,
+    /// ID of the process, the `tid` of its first thread
+    pub pid: Id,
+    /// Threads of the process that exited but were not joined
+    pub threads: Arc<Mutex<ThreadGroup>>,
+    /// Open files, starting with the console as standard input, output and
+    /// error
+    pub files: Arc<Mutex<FdTable>>,
+    /// Absolute, normalized working directory, which relative paths given
+    /// to system calls are resolved against
+    pub cwd: Arc<Mutex<PathBuf>>,
+    /// Whether the process may read the cycle counter at EL0
+    pub perf_counters: Arc<AtomicBool>,
+    /// Stack slot of this thread, 0 for the main thread
+    pub stack_slot: usize,
+    /// CPU time, switches and memory of the process so far
+    pub usage: Arc<Mutex<Usage>>,
+    /// When the thread was last switched in, charged to `usage` when it is
+    /// switched out
+    pub ran_at: Duration,
+    /// Nice level of the thread, `NICE_MIN..=NICE_MAX`
+    pub nice: i64,
+    /// Switches the thread was passed over for since it last ran
+    pub age: u64,
+    /// Cleanup subsystems registered for the process
+    pub exit_hooks: Arc<Mutex<ExitHooks>>,
+    /// Parent and children of the process
+    pub family: Family,
 }
 
 impl Process {
@@ -48,18 +83,52 @@ impl Process {
     pub fn new() -> OsResult<Process> {
 	let trap_frame = TrapFrame::default();
 
-	Ok(Process {
+	let process = Process {
 	    context: Box::<TrapFrame>::new(trap_frame),
-	    vmap: Box::new(UserPageTable::new()),
+	    vmap: Arc::new(Mutex::new(UserPageTable::new())),
 	    state: State::Ready,
+	    #[cfg(feature = "net")]
 	    sockets: Vec::new(),
-	})
+	    tid: 0,
+	    pid: 0,
+	    threads: Arc::new(Mutex::new(ThreadGroup::default())),
+	    files: Arc::new(Mutex::new(FdTable::with_console())),
+	    cwd: Arc::new(Mutex::new(PathBuf::from("/"))),
+	    perf_counters: Arc::new(AtomicBool::new(false)),
+	    stack_slot: 0,
+	    usage: Arc::new(Mutex::new(Usage::default())),
+	    ran_at: Duration::default(),
+	    nice: 0,
+	    age: 0,
+	    exit_hooks: Arc::new(Mutex::new(ExitHooks::default())),
+	    family: Family::new(),
+	};
+	process.register_exit_hooks();
+	Ok(process)
+    }
+
+    /// Registers the cleanup of what every process holds: its descriptors
+    /// are closed and the shared memory segments it maps are unmapped once
+    /// it is gone. The hooks refer to the current address space, and are
+    /// registered again when `exec()` replaces it.
+    fn register_exit_hooks(&self) {
+	let mut hooks = self.exit_hooks.lock();
+	let files = Arc::downgrade(&self.files);
+	hooks.add("files", Box::new(move || {
+	    if let Some(files) = files.upgrade() {
+		files.lock().close_all();
+	    }
+	}));
+	let vmap = Arc::downgrade(&self.vmap);
+	hooks.add("shm", Box::new(move || {
+	    if let Some(vmap) = vmap.upgrade() {
+		vmap.lock().unmap_segments();
+	    }
+	}));
     }
     
     /// Load a program stored in the given path by calling `do_load()` method.
     /// Set trapframe `context` corresponding to its page table.
-    /// `sp` - the address of stack top
-    /// `elr` - the address of image base.
     /// `ttbr0` - the base address of kernel page table
     /// `ttbr1` - the base address of user page table
     /// `spsr` - `F`, `A`, `D` bit should be set.
@@ -70,41 +139,128 @@ impl Process {
 
         let mut process = Self::do_load(pn)?;
 
-	process.context.sp = Self::get_stack_top().as_u64();
-	process.context.elr = Self::get_image_base().as_u64();
 	process.context.ttbr0 = VMM.get_baddr().as_u64();
-	process.context.ttbr1 = process.vmap.get_baddr().as_u64();	
+	process.context.ttbr1 = process.vmap.lock().get_baddr().as_u64();
 	process.context.spsr |= aarch64::SPSR_EL1::F | aarch64::SPSR_EL1::A | aarch64::SPSR_EL1::D;
 
         Ok(process)
     }
 
     /// Creates a process and open a file with given path.
-    /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
-    /// permission to load file's contents.
+    /// Reserves a stack of one page that grows on demand and loads the
+    /// program, either an ELF executable or a flat binary. A flat binary is
+    /// reserved with read/write/execute permission at the image base.
+    ///
+    /// Nothing is copied up front: the pages of the stack and the program are
+    /// allocated, and read from the file, as the program first touches them.
+    ///
+    /// Sets `elr` to the program's entry point and `sp` to the stack top. If
+    /// the ELF has a `PT_TLS` segment, the main thread's TLS block is placed
+    /// at the top of the stack and `tpidr` points at it.
     fn do_load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
-	// allocate stack memory
+	// reserve stack memory
 	let mut process = Process::new()?;
-	process.vmap.alloc(Process::get_stack_base(), PagePerm::RW);
-
-	// allocate code memory and read in program
-	let mut program = FILESYSTEM.open_file(pn)?;
-	let mut read_bytes = 0;
-	let mut num_pages = 0;
-	while read_bytes < program.size() {
-	    let mut data = [0u8; PAGE_SIZE];
-	    if let Ok(bytes_returned) = program.read(&mut data) {
-		let vaddr = Process::get_image_base().add(VirtualAddr::from(num_pages * PAGE_SIZE));
-		let page = process.vmap.alloc(vaddr, PagePerm::RWX);
-		page.copy_from_slice(&data);
-	    	read_bytes += bytes_returned as u64;
-	    } else {
-		return Err(OsError::IoError);
+	{
+	    let mut vmap = process.vmap.lock();
+	    vmap.reserve(Region::stack(Process::get_stack_base().as_usize()));
+	    process.context.sp = Self::get_stack_top().as_u64();
+
+	    let mut program = FILESYSTEM.open_file(pn)?;
+	    let mut magic = [0u8; 4];
+	    let is_elf = program.read_exact(&mut magic).is_ok() && magic == elf::ELF_MAGIC;
+	    program.seek(SeekFrom::Start(0))?;
+	    let size = program.size();
+	    let program = Arc::new(Mutex::new(program));
+
+	    if is_elf {
+		let image = elf::load(&program, &mut vmap)?;
+		process.context.elr = image.entry;
+		if let Some(template) = image.tls {
+		    let tp = template.install(&mut vmap, Process::get_stack_base())?;
+		    process.context.tpidr = tp;
+		    process.context.sp = tp;
+		}
+		process.threads.lock().tls = image.tls;
+	    }
+	    else {
+		process.context.elr = Self::get_image_base().as_u64();
+		if size > (USER_VDSO_BASE - USER_IMG_BASE) as u64 {
+		    return Err(OsError::NoVmSpace);
+		}
+		if size > 0 {
+		    vmap.reserve(Region {
+			start: Self::get_image_base().as_usize(),
+			len: size as usize,
+			perm: PagePerm::RWX,
+			backing: Backing::File { file: program, offset: 0, filesz: size },
+			growth: 0,
+		    });
+		}
 	    }
 	}
         Ok(process)
     }
 
+    /// Creates a child of `self`, the running thread with trap frame `tf`.
+    /// The child shares every page of the address space copy-on-write, has
+    /// the open files of `self` with their positions shared, and copies of
+    /// the working directory, the nice level and the cycle counter setting.
+    /// `self` is its parent.
+    /// It has one thread, which continues where `tf` does with `x0` 0 and a
+    /// status of `Ok`; the stacks of the other threads stay reserved.
+    ///
+    /// The process ID is assigned when the child is added to the scheduler.
+    pub fn fork(&self, tf: &TrapFrame) -> Process {
+	let mut context = *tf;
+	context.x[0] = 0;
+	context.x[7] = OsError::Ok as u64;
+
+	let mut threads = ThreadGroup::default();
+	threads.tls = self.threads.lock().tls;
+
+	let child = Process {
+	    context: Box::new(context),
+	    vmap: Arc::new(Mutex::new(self.vmap.lock().duplicate_cow())),
+	    state: State::Ready,
+	    #[cfg(feature = "net")]
+	    sockets: Vec::new(),
+	    tid: 0,
+	    pid: 0,
+	    threads: Arc::new(Mutex::new(threads)),
+	    files: Arc::new(Mutex::new(self.files.lock().inherit())),
+	    cwd: Arc::new(Mutex::new(self.cwd.lock().clone())),
+	    perf_counters: Arc::new(AtomicBool::new(self.perf_counters.load(Ordering::Relaxed))),
+	    stack_slot: self.stack_slot,
+	    usage: Arc::new(Mutex::new(Usage::default())),
+	    ran_at: Duration::default(),
+	    nice: self.nice,
+	    age: 0,
+	    exit_hooks: Arc::new(Mutex::new(ExitHooks::default())),
+	    family: self.family.child(self.pid),
+	};
+	child.register_exit_hooks();
+	child
+    }
+
+    /// Makes `self`, the thread calling exec, run the program `image` was
+    /// loaded with by `load()`: it takes the trap frame, address space and
+    /// thread group of `image`, with its cycle counter access, and becomes
+    /// the main thread. The IDs, family, open files, working directory, nice
+    /// level, usage and exit hooks stay those of `self`.
+    ///
+    /// Returns `image` holding the old address space, which goes away with
+    /// it unless other threads of the process still hold it.
+    pub fn exec(&mut self, mut image: Process) -> Process {
+	mem::swap(&mut self.context, &mut image.context);
+	mem::swap(&mut self.vmap, &mut image.vmap);
+	mem::swap(&mut self.threads, &mut image.threads);
+	mem::swap(&mut self.perf_counters, &mut image.perf_counters);
+	self.stack_slot = 0;
+	// the hooks of `image` refer to the address space `self` took
+	image.exit_hooks.lock().clear();
+	self.register_exit_hooks();
+	image
+    }
 
     /// Returns the highest `VirtualAddr` that is supported by this system.
     pub fn get_max_va() -> VirtualAddr {
@@ -175,3 +331,17 @@ impl Process {
 	(&mut self.context).elr = addr;
     }
 }
+
+impl Drop for Process {
+    /// Releases the stack of a thread. The main thread's stack and the rest
+    /// of the address space go away with the last thread of the process.
+    fn drop(&mut self) {
+	// the last thread runs the exit hooks while the process is whole
+	if Arc::strong_count(&self.exit_hooks) == 1 {
+	    self.exit_hooks.lock().run();
+	}
+	if self.stack_slot != 0 {
+	    self.vmap.lock().release(Process::get_thread_stack_base(self.stack_slot));
+	}
+    }
+}
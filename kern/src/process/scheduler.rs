@@ -1,24 +1,35 @@
 use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+#[cfg(feature = "net")]
 use core::ffi::c_void;
 use core::fmt;
 use core::mem::replace;
 use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
 use core::time::Duration;
 
 use aarch64::*;
-use pi::local_interrupt::LocalInterrupt;
-use smoltcp::time::Instant;
+use pi::local_interrupt::{local_tick_in, LocalController, LocalInterrupt};
 
+use shim::io;
 use shim::path::{Path, PathBuf, Component};
+use kernel_api::{OsError, OsResult, EXIT_KILLED, NICE_MIN};
 use crate::vm::{VirtualAddr, PagePerm};
+use crate::bootargs;
+use crate::bootstat;
+use crate::clock;
+use crate::console;
+use crate::ktrace;
 use crate::mutex::Mutex;
-use crate::net::uspi::TKernelTimerHandle;
+#[cfg(feature = "net")]
+use crate::usb::TKernelTimerHandle;
 use crate::param::*;
-use crate::percore::{get_preemptive_counter, is_mmu_ready, local_irq};
-use crate::process::{Id, Process, State};
+use crate::deferred::{self, Work};
+use crate::percore::{self, get_preemptive_counter, is_mmu_ready, local_irq};
+use crate::process::{ExitHook, Family, FdTable, Id, Process, ProcessInfo, State, INIT_PID};
 use crate::traps::irq::IrqHandlerRegistry;
 use crate::traps::TrapFrame;
 
@@ -28,8 +39,29 @@ use crate::temp_shell;
 
 use pi::interrupt::{Interrupt, Controller};
 use pi::timer::{tick_in, current_time};
+use crate::SCHEDULER;
+#[cfg(feature = "net")]
 use crate::{ETHERNET, USB};
 
+/// Timer interrupts a process runs for before it is preempted
+static QUANTUM_TICKS: AtomicU64 = AtomicU64::new(QUANTUM);
+
+/// Returns the number of timer interrupts a process runs for before the
+/// scheduler switches to the next ready one.
+pub fn quantum() -> u64 {
+    QUANTUM_TICKS.load(Ordering::Relaxed)
+}
+
+/// Changes the quantum from the next switch on. Returns false and leaves it
+/// alone if `ticks` is not within `1..=QUANTUM_MAX`.
+pub fn set_quantum(ticks: u64) -> bool {
+    if ticks == 0 || ticks > QUANTUM_MAX {
+	return false;
+    }
+    QUANTUM_TICKS.store(ticks, Ordering::Relaxed);
+    true
+}
+
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
 pub struct GlobalScheduler(Mutex<Option<Box<Scheduler>>>);
@@ -41,13 +73,20 @@ impl GlobalScheduler {
     }
 
     /// Enters a critical region and execute the provided closure with a mutable
-    /// reference to the inner scheduler.
+    /// reference to the inner scheduler. The threads the closure removed
+    /// from the queue are dropped once the region is left, see `reap()`.
     pub fn critical<F, R>(&self, f: F) -> R
     where
         F: FnOnce(&mut Scheduler) -> R,
     {
-        let mut guard = self.0.lock();
-        f(guard.as_mut().expect("scheduler uninitialized"))
+        let (result, reaped) = {
+            let mut guard = self.0.lock();
+            let scheduler = guard.as_mut().expect("scheduler uninitialized");
+            let result = f(scheduler);
+            (result, mem::replace(&mut scheduler.reaped, Vec::new()))
+        };
+        reap(reaped);
+        result
     }
 
     /// Adds a process to the scheduler's queue and returns that process's ID.
@@ -65,15 +104,33 @@ impl GlobalScheduler {
         self.switch_to(tf)
     }
 
+    /// Charges the running process for a timer interrupt and, once it used
+    /// up its quantum, preempts it for the next ready process. `tf` is the
+    /// full trap frame of the interrupted process, saved and restored as by
+    /// `switch()`.
+    pub fn preempt(&self, tf: &mut TrapFrame) {
+	let expired = self.critical(|scheduler| {
+	    let slice = &mut scheduler.slice[affinity()];
+	    *slice = slice.saturating_sub(1);
+	    *slice == 0
+	});
+	if expired {
+	    self.switch(State::Ready, tf);
+	}
+    }
+
     /// Loops until it finds the next process to schedule.
     /// Call `wfi()` in the loop when no process is ready.
     /// For more details, see the documentation on `Scheduler::switch_to()`.
     ///
     /// Returns the process's ID when a ready process is found.
     pub fn switch_to(&self, tf: &mut TrapFrame) -> Id {
+        let mut idle = false;
         loop {
             let rtn = self.critical(|scheduler| scheduler.switch_to(tf));
             if let Some(id) = rtn {
+                bootstat::mark(bootstat::Phase::FirstProcess);
+                ktrace::record(trace::Kind::Switch, id as u32);
                 trace!(
                     "[core-{}] switch_to {:?}, pc: {:x}, lr: {:x}, x29: {:x}, x28: {:x}, x27: {:x}",
                     affinity(),
@@ -86,27 +143,177 @@ impl GlobalScheduler {
                 );
                 return id;
             }
+            if !idle {
+                ktrace::record(trace::Kind::Idle, 0);
+                idle = true;
+            }
             aarch64::wfi();
         }
     }
 
-    /// Kills currently running process and returns that process's ID.
-    /// For more details, see the documentation on `Scheduler::kill()`.
+    /// Kills currently running process with exit code `code`, switches `tf`
+    /// to the next process, so that the trap never returns into the killed
+    /// one, whose address space is gone, and returns the killed process's
+    /// ID. For more details, see the documentation on `Scheduler::kill()`.
+    pub fn kill_current(&self, code: u64, tf: &mut TrapFrame) -> Option<Id> {
+	let pid = self.critical(|scheduler| scheduler.kill(code, tf));
+	self.switch_to(tf);
+	debug_assert!(
+	    self.critical(|scheduler| scheduler.current().map_or(true, |process| Some(process.pid) != pid)),
+	    "returned into a killed process"
+	);
+	pid
+    }
+
+    /// Kills every thread of the process `pid`, which exits with
+    /// `EXIT_KILLED`, switching `tf` to the next process if the running
+    /// thread is one of them. Returns `false` if there is no such process.
+    pub fn kill_process(&self, pid: Id, tf: &mut TrapFrame) -> bool {
+	let running = self.critical(|scheduler| scheduler.current().map_or(false, |process| process.pid == pid));
+	if running {
+	    return self.kill_current(EXIT_KILLED, tf).is_some();
+	}
+	self.critical(|scheduler| scheduler.kill_process(pid))
+    }
+
+    /// Registers `hook` under `name` to run once every thread of the process
+    /// `pid` is gone, see `ExitHooks::add()`. Returns `hook` back, without
+    /// running it, if there is no such process.
+    pub fn on_exit(&self, pid: Id, name: &'static str, hook: ExitHook) -> Result<(), ExitHook> {
+	self.critical(|scheduler| match scheduler.threads().find(|thread| thread.pid == pid) {
+	    Some(thread) => {
+		thread.exit_hooks.lock().add(name, hook);
+		Ok(())
+	    },
+	    None => Err(hook),
+	})
+    }
+
+    /// Calls `f` with one thread of every process, in queue order, with the
+    /// scheduler locked: `f` sees the queue as it is, but holds up every
+    /// core that schedules meanwhile, so it must not block or take locks
+    /// that may wait on a process. Anything slow is for `snapshot()`.
+    pub fn for_each_process<F: FnMut(&Process)>(&self, f: F) {
+	self.critical(|scheduler| scheduler.processes().for_each(f))
+    }
+
+    /// Returns a snapshot of every process, in queue order. The processes
+    /// may change or exit after it is taken.
+    pub fn snapshot(&self) -> Vec<ProcessInfo> {
+	self.critical(|scheduler| {
+	    let mut processes: Vec<ProcessInfo> = scheduler.processes().map(ProcessInfo::new).collect();
+	    for thread in scheduler.threads() {
+		if let Some(process) = processes.iter_mut().find(|process| process.pid == thread.pid) {
+		    process.add(thread);
+		}
+	    }
+	    processes
+	})
+    }
+
+    /// Returns a snapshot of the process `pid`, `None` if there is no such
+    /// process.
+    pub fn process(&self, pid: Id) -> Option<ProcessInfo> {
+	self.critical(|scheduler| {
+	    let mut process = ProcessInfo::new(scheduler.threads().find(|thread| thread.pid == pid)?);
+	    for thread in scheduler.threads().filter(|thread| thread.pid == pid) {
+		process.add(thread);
+	    }
+	    Some(process)
+	})
+    }
+
+    /// Creates a thread of the current process and adds it to the queue.
+    /// Returns the ID of the new thread. See `Process::spawn_thread()`.
+    pub fn spawn_thread(&self, entry: u64, arg: u64, tls: u64) -> OsResult<Id> {
+	self.critical(|scheduler| {
+	    let thread = scheduler.current().ok_or(OsError::NoEntry)?.spawn_thread(entry, arg, tls)?;
+	    scheduler.add(thread).ok_or(OsError::NoMemory)
+	})
+    }
+
+    /// Creates a child of the current process from its running thread,
+    /// whose trap frame is `tf`, and adds it to the queue. Returns the ID of
+    /// the child. See `Process::fork()`.
+    pub fn fork(&self, tf: &TrapFrame) -> OsResult<Id> {
+	self.critical(|scheduler| {
+	    let (child, children) = {
+		let current = scheduler.current().ok_or(OsError::NoEntry)?;
+		(current.fork(tf), current.family.children.clone())
+	    };
+	    let pid = scheduler.add(child).ok_or(OsError::NoMemory)?;
+	    children.lock().adopt(pid);
+	    Ok(pid)
+	})
+    }
+
+    /// Replaces the program of the current process with the executable at
+    /// `path`, given the arguments `argv` and the environment `envp`, and
+    /// continues `tf` from its entry point. The other threads of the process
+    /// are killed and the descriptors marked close-on-exec are closed. See
+    /// `Process::exec()` and `Process::set_args()`.
+    ///
+    /// The program is loaded before anything is replaced, so on an error the
+    /// process carries on as it was.
+    pub fn exec<P: AsRef<Path>>(&self, path: P, argv: &[&str], envp: &[&str], tf: &mut TrapFrame) -> OsResult<()> {
+	let mut image = Process::load(path)?;
+	image.set_args(argv, envp)?;
+	let files = self.critical(|scheduler| scheduler.exec(image, tf))?;
+	files.lock().close_on_exec();
+	Ok(())
+    }
+
+    /// Exits the currently running thread with `code` and returns its ID.
+    /// For more details, see the documentation on `Scheduler::exit_thread()`.
     #[must_use]
-    pub fn kill(&self, tf: &mut TrapFrame) -> Option<Id> {
-        self.critical(|scheduler| scheduler.kill(tf))
+    pub fn exit_thread(&self, code: u64, tf: &mut TrapFrame) -> Option<Id> {
+        self.critical(|scheduler| scheduler.exit_thread(code, tf))
+    }
+
+    /// Writes a checkpoint of the stopped thread `id` into `w`.
+    /// See `Process::checkpoint()`.
+    ///
+    /// Returns `NoEntry` if there is no such process and `InvalidArgument`
+    /// if it is currently running.
+    pub fn checkpoint<W: io::Write>(&self, id: Id, w: &mut W) -> OsResult<()> {
+	self.critical(|scheduler| {
+	    let process = scheduler.find_id(id).ok_or(OsError::NoEntry)?;
+	    match process.state {
+		State::Running => Err(OsError::InvalidArgument),
+		_ => Ok(process.checkpoint(w)?),
+	    }
+	})
+    }
+
+    /// Restores the checkpoint image at `path` and adds it to the queue.
+    /// Returns the ID of the restored process.
+    pub fn restore<P: AsRef<Path>>(&self, path: P) -> OsResult<Id> {
+	let process = Process::restore_from(path)?;
+	self.add(process).ok_or(OsError::NoMemory)
     }
 
     /// Starts executing processes in user space using timer interrupt based
     /// preemptive scheduling. This method should not return under normal
     /// conditions.
+    ///
+    /// Every core calls this. Core 0 takes the interrupts of the peripherals,
+    /// the system timer among them; the others are preempted by their local
+    /// timers. The timers are set up first so that a core with nothing to
+    /// run yet still wakes up to look again.
     pub fn start(&self) -> ! {
+	let core = affinity();
+	match core {
+	    0 => {
+		self.initialize_global_timer_interrupt();
+		console::enable_read_interrupt();
+	    },
+	    _ => self.initialize_local_timer_interrupt(),
+	}
+
 	let mut trap_frame = TrapFrame::default();
 	self.switch_to(&mut trap_frame);
 	let tf = &trap_frame as *const TrapFrame as u64;
 
-	self.initialize_global_timer_interrupt();
-
 	unsafe{
             asm!("
                 // Call context_restore w/ SP reset to trap frame
@@ -115,7 +322,8 @@ impl GlobalScheduler {
                 bl context_restore
                 " :: "r"(tf) :: "volatile");
 
-            let new_sp = 0x80000; // not ideal to hardcode this, oh well
+            // the top of this core's stack, from where exceptions start
+            let new_sp = KERN_STACK_BASE - core * KERN_STACK_SIZE;
 
             asm!("
                 // Move SP to next page w/out clobbering other registers
@@ -132,7 +340,7 @@ impl GlobalScheduler {
     /// # Lab 4
     /// Initializes the global timer interrupt with `pi::timer`. The timer
     /// should be configured in a way that `Timer1` interrupt fires every
-    /// `TICK` duration, which is defined in `param.rs`.
+    /// `clock::tick()`, `TICK` until the rate is changed.
     ///
     /// # Lab 5
     /// Registers a timer handler with `Usb::start_kernel_timer` which will
@@ -140,24 +348,34 @@ impl GlobalScheduler {
     pub fn initialize_global_timer_interrupt(&self) {
 	GLOBAL_IRQ.register(Interrupt::Timer1, Box::new(systick_handler));
 	Controller::new().enable(Interrupt::Timer1);
-	tick_in(TICK);
+	tick_in(clock::tick());
     }
 
     /// Initializes the per-core local timer interrupt with `pi::local_interrupt`.
-    /// The timer should be configured in a way that `CntpnsIrq` interrupt fires
-    /// every `TICK` duration, which is defined in `param.rs`.
+    /// The timer should be configured in a way that `CntPnsIrq` interrupt fires
+    /// every `clock::tick()`.
     pub fn initialize_local_timer_interrupt(&self) {
-        // Lab 5 2.C
-        unimplemented!("initialize_local_timer_interrupt()")
+	local_irq().register(LocalInterrupt::CntPnsIrq, Box::new(local_tick_handler));
+	let mut controller = LocalController::new(affinity());
+	controller.enable_local_timer();
+	controller.tick_in(clock::tick());
     }
 
     /// Initializes the scheduler and add userspace processes to the Scheduler.
     pub unsafe fn initialize(&self) {
+	if let Some(arg) = bootargs::get("quantum") {
+	    match arg.parse::<u64>().ok().filter(|&ticks| set_quantum(ticks)) {
+		Some(ticks) => info!("scheduler: quantum of {} ticks", ticks),
+		None => warn!("scheduler: ignoring quantum={}, not within 1..={}", arg, QUANTUM_MAX),
+	    }
+	}
 	let locked = &mut self.0.lock();
 	if locked.is_none() {
 	    locked.replace(Scheduler::new());
-	    let process = Process::load(PathBuf::from("/fib.bin")).expect("failed to load user program");
-	    self.add(process).expect("failed to obtain PID");
+	    let mut process = Process::load(PathBuf::from("/fib.bin")).expect("failed to load user program");
+	    process.set_args(&["/fib.bin"], &[]).expect("failed to pass arguments to user program");
+	    let pid = self.add(process).expect("failed to obtain PID");
+	    console::set_foreground(pid);
 	}
     }
 
@@ -181,15 +399,24 @@ impl GlobalScheduler {
 
 /// Poll the ethernet driver and re-register a timer handler using
 /// `Usb::start_kernel_timer`.
+#[cfg(feature = "net")]
 extern "C" fn poll_ethernet(_: TKernelTimerHandle, _: *mut c_void, _: *mut c_void) {
     // Lab 5 2.B
     unimplemented!("poll_ethernet")
 }
 
-/// Internal scheduler struct which is not thread-safe.
+/// Internal scheduler struct which is not thread-safe. The queue is shared
+/// by the cores; each runs a process of it at a time.
 pub struct Scheduler {
     processes: VecDeque<Process>,
     last_id: Option<Id>,
+    /// thread ID of the process running on each core
+    current: [Option<Id>; NCORES],
+    /// timer interrupts left of the quantum of the process on each core
+    slice: [u64; NCORES],
+    /// threads removed from the queue, dropped when the scheduler is
+    /// unlocked
+    reaped: Vec<Process>,
 }
 
 impl Scheduler {
@@ -198,6 +425,9 @@ impl Scheduler {
 	let scheduler = Scheduler {
 	    processes: VecDeque::<Process>::new(),
 	    last_id: Some(0),
+	    current: [None; NCORES],
+	    slice: [0; NCORES],
+	    reaped: Vec::new(),
 	};
 	Box::new(scheduler)
     }
@@ -210,74 +440,242 @@ impl Scheduler {
     }
 
     /// Adds a process to the scheduler's queue and returns that process's ID if
-    /// a new process can be scheduled. The process ID is newly allocated for
-    /// the process and saved in its `trap_frame`. If no further processes can
-    /// be scheduled, returns `None`.
+    /// a new process can be scheduled. The ID is newly allocated and saved as
+    /// the process's `tid`; a process without a `pid` is the first thread of a
+    /// new process and also takes the ID as its `pid`. If no further processes
+    /// can be scheduled, returns `None`.
     ///
     /// It is the caller's responsibility to ensure that the first time `switch`
     /// is called, that process is executing on the CPU.
     fn add(&mut self, mut process: Process) -> Option<Id> {
 	let id = self.next_id()?;
-	process.context.tpidr = id;
+	process.tid = id;
+	if process.pid == 0 {
+	    process.pid = id;
+	}
 	self.processes.push_back(process);
 	Some(id)
     }
 
-    /// Finds the currently running process, sets the current process's state
-    /// to `new_state`, prepares the context switch on `tf` by saving `tf`
-    /// into the current process, and push the current process back to the
-    /// end of `processes` queue.
+    /// Finds the process running on this core, sets the current process's
+    /// state to `new_state`, prepares the context switch on `tf` by saving
+    /// `tf` into the current process, and push the current process back to
+    /// the end of `processes` queue.
     ///
     /// If the `processes` queue is empty or there is no current process,
-    /// returns `false`. Otherwise, returns `true`.
-    fn schedule_out(&mut self, new_state: State, tf: &mut TrapFrame) -> bool {	
-	for index in 0..self.processes.len(){
-	    match self.processes[index].state {
-		State::Running => {
-		    if self.processes[index].context.tpidr == tf.tpidr {
-			let mut process = self.processes.remove(index).expect("removing sheduled out process from queue");
-			process.state = new_state;
-			*(process.context) = tf.clone();
-			self.processes.push_back(process);
-			return true;
-		    }
-		},
-		_ => continue,// TODO: can break after verification
-	    }
-	}	
-	false
+    /// returns `false`. A process another core killed while it ran here is
+    /// dropped instead, and `false` returned too. Otherwise, returns `true`.
+    fn schedule_out(&mut self, new_state: State, tf: &mut TrapFrame) -> bool {
+	let core = affinity();
+	let id = match self.current[core] {
+	    Some(id) => id,
+	    None => return false,
+	};
+	match self.processes.iter().position(|process| process.tid == id) {
+	    Some(index) => {
+		let mut process = self.processes.remove(index).expect("removing sheduled out process from queue");
+		self.current[core] = None;
+		// the last slice of a thread that exits or is killed counts too
+		let ran = current_time().checked_sub(process.ran_at).unwrap_or_default();
+		let (_, peak_pages) = process.vmap.lock().resident_pages();
+		process.usage.lock().switched_out(ran, peak_pages);
+		if let State::Dead = process.state {
+		    self.reaped.push(process);
+		    return false;
+		}
+		process.state = new_state;
+		*(process.context) = tf.clone();
+		self.processes.push_back(process);
+		true
+	    },
+	    None => false,
+	}
     }
     
     /// Finds the next process to switch to, brings the next process to the
     /// front of the `processes` queue, changes the next process's state to
     /// `Running`, and performs context switch by restoring the next process`s
-    /// trap frame into `tf`.
+    /// trap frame into `tf`. `ttbr1` is set to the process's page table with
+    /// the ASID it has now, which may differ from the one it last ran with.
+    /// The process starts a fresh quantum.
+    ///
+    /// The next process is the ready one of the best level, see `level()`,
+    /// and the one nearest the front of the queue among those of that
+    /// level. Every other ready process ages by one switch.
     ///
     /// If there is no process to switch to, returns `None`. Otherwise, returns
     /// `Some` of the next process`s process ID.
     fn switch_to(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-	for index in 0..self.processes.len(){
+	let mut next: Option<(usize, u64)> = None;
+	for index in 0..self.processes.len() {
 	    if self.processes[index].is_ready() {
-		let mut process = self.processes.remove(index).expect("removing sheduled out process from queue");
-		process.state = State::Running;
-		replace(&mut *tf, *process.context);
-		assert_eq!(tf.tpidr, process.context.tpidr);
-		self.processes.push_front(process);
-		return Some(tf.tpidr);
+		let level = Scheduler::level(&self.processes[index]);
+		if next.map_or(true, |(_, best)| level < best) {
+		    next = Some((index, level));
+		}
+	    }
+	}
+	let (index, _) = next?;
+	for (other, process) in self.processes.iter_mut().enumerate() {
+	    if other != index && process.is_ready() {
+		process.age += 1;
 	    }
 	}
-	None
+
+	let mut process = self.processes.remove(index).expect("removing sheduled out process from queue");
+	process.state = State::Running;
+	process.age = 0;
+	replace(&mut *tf, *process.context);
+	tf.ttbr1 = process.vmap.lock().ttbr();
+	process.ran_at = current_time();
+	crate::perf::load(process.perf_counters.load(Ordering::Relaxed));
+	let id = process.tid;
+	self.processes.push_front(process);
+	self.current[affinity()] = Some(id);
+	self.slice[affinity()] = quantum();
+	Some(id)
+    }
+
+    /// Returns the level `process` is scheduled at, the lower the sooner:
+    /// its nice level counted from `NICE_MIN`, less one for every
+    /// `PRIORITY_AGING` switches it was passed over for.
+    fn level(process: &Process) -> u64 {
+	let level = (process.nice - NICE_MIN) as u64;
+	level.saturating_sub(process.age / PRIORITY_AGING)
+    }
+
+    /// Sets the nice level of every thread of the process `pid`. Returns
+    /// `false` if there is no such process.
+    pub fn set_nice(&mut self, pid: Id, nice: i64) -> bool {
+	let mut found = false;
+	for thread in self.processes.iter_mut().filter(|thread| thread.pid == pid) {
+	    thread.nice = nice;
+	    found = true;
+	}
+	found
     }
 
     /// Kills currently running process by scheduling out the current process
     /// as `Dead` state. Releases all process resources held by the process,
-    /// removes the dead process from the queue, drops the dead process's
-    /// instance, and returns the dead process's process ID.
-    fn kill(&mut self, tf: &mut TrapFrame) -> Option<Id> {
+    /// removes the dead process and all of its threads from the queue, drops
+    /// their instances, and returns the dead process's process ID. The
+    /// process exits with `code`, see `exit_process()`.
+    fn kill(&mut self, code: u64, tf: &mut TrapFrame) -> Option<Id> {
 	if self.schedule_out(State::Dead, tf) {
 	    let process = self.processes.pop_back().expect("removing process on kill");
-	    assert_eq!(tf.tpidr, process.context.tpidr);
-	    Some(tf.tpidr)
+	    let pid = process.pid;
+	    self.exit_process(pid, process.family.clone(), code);
+	    self.reaped.push(process);
+	    self.remove_process(pid);
+	    Some(pid)
+	}
+	else {
+	    None
+	}
+    }
+
+    /// Removes every thread of the process `pid` from the queue, which must
+    /// not be the running one: that is for `kill()`. Returns whether there
+    /// were any.
+    fn kill_process(&mut self, pid: Id) -> bool {
+	let family = self.processes.iter()
+	    .find(|thread| thread.pid == pid && !thread.state.is_dead())
+	    .map(|thread| thread.family.clone());
+	if let Some(family) = family {
+	    self.exit_process(pid, family, EXIT_KILLED);
+	}
+	self.remove_process(pid)
+    }
+
+    /// Removes every thread of the process `pid` from the queue but those
+    /// running on other cores, which are marked `Dead` and dropped by their
+    /// core when it switches away: their address space is still in use.
+    /// Returns whether there were any.
+    fn remove_process(&mut self, pid: Id) -> bool {
+	self.remove_threads(pid, None)
+    }
+
+    /// Removes the threads of the process `pid` as `remove_process()` does,
+    /// but leaves the thread `keep` alone.
+    fn remove_threads(&mut self, pid: Id, keep: Option<Id>) -> bool {
+	let mut found = false;
+	let mut index = 0;
+	while index < self.processes.len() {
+	    let thread = &self.processes[index];
+	    if thread.pid != pid || Some(thread.tid) == keep || self.current.contains(&Some(thread.tid)) {
+		index += 1;
+		continue;
+	    }
+	    let thread = self.processes.remove(index).expect("removing thread of killed process");
+	    self.reaped.push(thread);
+	    found = true;
+	}
+	for thread in self.processes.iter_mut().filter(|thread| thread.pid == pid && Some(thread.tid) != keep) {
+	    thread.state = State::Dead;
+	    found = true;
+	}
+	found
+    }
+
+    /// Records that the process `pid`, of family `family`, exited with
+    /// `code`: its parent keeps the code until it waits for `pid`. The
+    /// children of `pid` are handed to the init process, zombies included,
+    /// or have no parent once init itself is gone.
+    fn exit_process(&mut self, pid: Id, family: Family, code: u64) {
+	if let Some(parent) = family.parent {
+	    parent.lock().exit(pid, code);
+	}
+	let init = match pid {
+	    INIT_PID => None,
+	    _ => self.processes.iter()
+		.find(|thread| thread.pid == INIT_PID && !thread.state.is_dead())
+		.map(|thread| thread.family.children.clone()),
+	};
+	for thread in self.processes.iter_mut().filter(|thread| thread.family.ppid == pid) {
+	    thread.family.ppid = if init.is_some() { INIT_PID } else { 0 };
+	    thread.family.parent = init.clone();
+	}
+	if let Some(init) = init {
+	    init.lock().append(&mut family.children.lock());
+	}
+    }
+
+    /// Makes the running thread run the program loaded into `image`,
+    /// restoring its new trap frame into `tf`, and kills the other threads
+    /// of its process. Returns the open files of the process.
+    fn exec(&mut self, image: Process, tf: &mut TrapFrame) -> OsResult<Arc<Mutex<FdTable>>> {
+	let (pid, tid) = match self.current() {
+	    Some(process) => (process.pid, process.tid),
+	    None => return Err(OsError::NoEntry),
+	};
+	self.remove_threads(pid, Some(tid));
+
+	let process = self.current().expect("running thread survives exec");
+	let old = process.exec(image);
+	replace(&mut *tf, *process.context);
+	tf.ttbr1 = process.vmap.lock().ttbr();
+	crate::perf::load(process.perf_counters.load(Ordering::Relaxed));
+	let files = process.files.clone();
+	self.reaped.push(old);
+	Ok(files)
+    }
+
+    /// Exits the currently running thread. `code` is kept for a thread that
+    /// joins it later. The thread is removed from the queue and its stack is
+    /// released; the rest of the process keeps running. Returns the thread ID.
+    ///
+    /// The last thread to exit takes the process with it, and `code` is the
+    /// exit code of the process too.
+    fn exit_thread(&mut self, code: u64, tf: &mut TrapFrame) -> Option<Id> {
+	if self.schedule_out(State::Dead, tf) {
+	    let thread = self.processes.pop_back().expect("removing thread on exit");
+	    thread.threads.lock().exit(thread.tid, code);
+	    if !self.processes.iter().any(|other| other.pid == thread.pid) {
+		self.exit_process(thread.pid, thread.family.clone(), code);
+	    }
+	    let tid = thread.tid;
+	    self.reaped.push(thread);
+	    Some(tid)
 	}
 	else {
 	    None
@@ -290,15 +688,29 @@ impl Scheduler {
         unimplemented!("release_process_resources")
     }
 
-    /// Finds a process corresponding with tpidr saved in a trap frame.
-    /// Panics if the search fails.
-    pub fn find_process(&mut self, tf: &TrapFrame) -> &mut Process {
-        for i in 0..self.processes.len() {
-            if self.processes[i].context.tpidr == tf.tpidr {
-                return &mut self.processes[i];
-            }
-        }
-        panic!("Invalid TrapFrame");
+    /// Finds the process with thread ID `id`.
+    pub fn find_id(&mut self, id: Id) -> Option<&mut Process> {
+	self.processes.iter_mut().find(|process| process.tid == id)
+    }
+
+    /// Returns the process running on this core, if any.
+    pub fn current(&mut self) -> Option<&mut Process> {
+	let id = self.current[affinity()]?;
+	self.find_id(id)
+    }
+
+    /// Returns every thread in the queue.
+    pub fn threads(&self) -> impl Iterator<Item = &Process> {
+	self.processes.iter()
+    }
+
+    /// Returns the first thread of every process in the queue, which stands
+    /// for the parts the threads share.
+    pub fn processes(&self) -> impl Iterator<Item = &Process> {
+	let queue = &self.processes;
+	queue.iter().enumerate()
+	    .filter(move |(index, thread)| !queue.iter().take(*index).any(|other| other.pid == thread.pid))
+	    .map(|(_, thread)| thread)
     }
 }
 
@@ -309,22 +721,68 @@ impl fmt::Debug for Scheduler {
         for i in 0..len {
             write!(
                 f,
-                "    queue[{}]: proc({:3})-{:?} \n",
-                i, self.processes[i].context.tpidr, self.processes[i].state
+                "    queue[{}]: proc({:3}/{:3})-{:?} \n",
+                i, self.processes[i].pid, self.processes[i].tid, self.processes[i].state
             )?;
         }
         Ok(())
     }
 }
 
+/// Threads reaped inside exception handlers, left for `reap_deferred()`
+static DEFERRED_REAP: Mutex<Option<Vec<Process>>> = Mutex::new(None);
+
+/// Drops the threads `reaped` removed from the queue. It is done with the
+/// scheduler unlocked: the last thread of a process takes its open files
+/// with it, which takes the file system lock, and procfs lists the
+/// processes with that lock held. An exception handler leaves them to
+/// `reap_deferred()`, as writing the files back blocks on the disk.
+///
+/// A table about to be freed is first taken out of `TTBR1_EL1` of this
+/// core, which still holds it when the thread running here was reaped.
+fn reap(reaped: Vec<Process>) {
+    if reaped.is_empty() {
+	return;
+    }
+    if percore::in_handler() {
+	DEFERRED_REAP.lock().get_or_insert_with(Vec::new).extend(reaped);
+	deferred::schedule(Work::Reap);
+	return;
+    }
+    for thread in reaped.iter() {
+	VMM.unload_user_table(thread.vmap.lock().get_baddr());
+    }
+    drop(reaped);
+}
+
+/// Drops the threads reaped inside exception handlers, see `reap()`.
+pub fn reap_deferred() {
+    let reaped = DEFERRED_REAP.lock().take();
+    if let Some(reaped) = reaped {
+	reap(reaped);
+    }
+}
+
 // TODO: SYSTICK HANDLER should go where?
 pub fn systick_handler(tf: &mut TrapFrame) {
-    use crate::SCHEDULER;
+    clock::count_tick();
+    #[cfg(feature = "klog")]
+    crate::klog::tick();
+    crate::led::tick();
+    // `INTR` read by a system call waits for the tick to be delivered
+    crate::console::deliver_interrupt(tf);
+
+    SCHEDULER.preempt(tf);
 
-    // if initialized
-    SCHEDULER.switch(State::Ready, tf);
+    // picks up a rate changed since the last tick
+    tick_in(clock::tick());
+}
 
-    tick_in(TICK);
+/// Preempts the process running on cores 1 to 3, which the system timer
+/// does not interrupt.
+pub fn local_tick_handler(tf: &mut TrapFrame) {
+    SCHEDULER.preempt(tf);
+    local_tick_in(affinity(), clock::tick());
 }
 
 pub extern "C" fn  test_user_process() -> ! {
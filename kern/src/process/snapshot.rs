@@ -0,0 +1,52 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::mutex::Mutex;
+use crate::process::{FdTable, Id, Process, Usage};
+use crate::vm::UserPageTable;
+
+/// A thread of a process as of a snapshot
+#[derive(Debug, Copy, Clone)]
+pub struct ThreadInfo {
+    pub tid: Id,
+    /// name of the scheduling state, see `State::name()`
+    pub state: &'static str,
+}
+
+/// A process as of a snapshot, taken with the scheduler locked and read
+/// without it. The shared parts are handles to the process's own: locking
+/// them does not hold up the scheduler, and they stay valid once the process
+/// is gone.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: Id,
+    /// ID of the parent, 0 for a process without one
+    pub ppid: Id,
+    /// nice level of the first thread
+    pub nice: i64,
+    /// every thread, in queue order
+    pub threads: Vec<ThreadInfo>,
+    pub vmap: Arc<Mutex<UserPageTable>>,
+    pub files: Arc<Mutex<FdTable>>,
+    pub usage: Arc<Mutex<Usage>>,
+}
+
+impl ProcessInfo {
+    /// Starts the snapshot of the process of `thread`.
+    pub(super) fn new(thread: &Process) -> ProcessInfo {
+	ProcessInfo {
+	    pid: thread.pid,
+	    ppid: thread.family.ppid,
+	    nice: thread.nice,
+	    threads: Vec::new(),
+	    vmap: thread.vmap.clone(),
+	    files: thread.files.clone(),
+	    usage: thread.usage.clone(),
+	}
+    }
+
+    /// Adds `thread`, one of the process's, to the snapshot.
+    pub(super) fn add(&mut self, thread: &Process) {
+	self.threads.push(ThreadInfo { tid: thread.tid, state: thread.state.name() });
+    }
+}
@@ -23,6 +23,25 @@ pub enum State {
     Dead,
 }
 
+impl State {
+    /// Returns the name of the state as `/proc` shows it.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            State::Ready => "ready",
+            State::Running => "running",
+            State::Waiting(_) => "waiting",
+            State::Dead => "dead",
+        }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        match *self {
+            State::Dead => true,
+            _ => false,
+        }
+    }
+}
+
 impl fmt::Debug for State {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
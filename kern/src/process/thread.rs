@@ -0,0 +1,150 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+#[cfg(feature = "net")]
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::param::{PAGE_MASK, PAGE_SIZE, USER_MAX_THREADS, USER_STACK_BASE, USER_STACK_MAX_PAGES, USER_STACK_SPAN, USER_VDSO_BASE};
+use crate::process::{Id, Process, State, TlsTemplate};
+use crate::vm::{Region, VirtualAddr};
+
+/// Bookkeeping shared by all threads of a process.
+#[derive(Debug, Default)]
+pub struct ThreadGroup {
+    /// exit codes of threads that exited and were not joined yet
+    exited: BTreeMap<Id, u64>,
+    /// thread local storage template of the program, set up for every thread
+    pub tls: Option<TlsTemplate>,
+    /// threads blocked in `futex_wait`, by the address of the futex word;
+    /// a waiter's flag is set when it is woken
+    futexes: BTreeMap<u64, VecDeque<Arc<AtomicBool>>>,
+}
+
+impl ThreadGroup {
+    /// Records that thread `tid` exited with `code`.
+    pub fn exit(&mut self, tid: Id, code: u64) {
+	self.exited.insert(tid, code);
+    }
+
+    /// Returns `true` if thread `tid` exited and was not joined yet.
+    pub fn has_exited(&self, tid: Id) -> bool {
+	self.exited.contains_key(&tid)
+    }
+
+    /// Removes and returns the exit code of thread `tid`, if it exited.
+    pub fn join(&mut self, tid: Id) -> Option<u64> {
+	self.exited.remove(&tid)
+    }
+
+    /// Queues a waiter on the futex word at `addr` and returns the flag that
+    /// `futex_wake` sets to release it.
+    pub fn futex_wait(&mut self, addr: u64) -> Arc<AtomicBool> {
+	let woken = Arc::new(AtomicBool::new(false));
+	self.futexes.entry(addr).or_insert_with(VecDeque::new).push_back(woken.clone());
+	woken
+    }
+
+    /// Releases up to `count` waiters on the futex word at `addr`, oldest
+    /// first. Returns the number of waiters released.
+    pub fn futex_wake(&mut self, addr: u64, count: usize) -> usize {
+	let mut woken = 0;
+	if let Some(waiters) = self.futexes.get_mut(&addr) {
+	    while woken < count {
+		match waiters.pop_front() {
+		    Some(waiter) => waiter.store(true, Ordering::Release),
+		    None => break,
+		}
+		woken += 1;
+	    }
+	    if waiters.is_empty() {
+		self.futexes.remove(&addr);
+	    }
+	}
+	woken
+    }
+}
+
+impl Process {
+    /// Returns the base address of the stack in `slot`, the page it starts
+    /// out with. Slot 0 is the main thread's stack at `USER_STACK_BASE`, the
+    /// stacks of other threads sit `USER_STACK_SPAN` apart below it, leaving
+    /// room to grow and an unmapped guard page between neighbours.
+    pub fn get_thread_stack_base(slot: usize) -> VirtualAddr {
+	VirtualAddr::from(USER_STACK_BASE - slot * USER_STACK_SPAN)
+    }
+
+    /// Returns `true` if `va` lies in the guard page below the furthest a
+    /// stack grows to, which a thread only touches by overflowing its stack.
+    pub fn is_stack_guard(va: VirtualAddr) -> bool {
+	let page = va.as_usize() & PAGE_MASK;
+	page > USER_VDSO_BASE && (USER_STACK_BASE - page) % USER_STACK_SPAN == USER_STACK_MAX_PAGES * PAGE_SIZE
+    }
+
+    /// Creates a new thread of `self`. The thread shares the page table of
+    /// `self`, runs on a one page stack that is allocated when it is first
+    /// touched, and starts at `entry` with `arg` in `x0` and `tls` in
+    /// `TPIDR_EL0`. If `tls` is 0 and the program has a TLS template, a TLS
+    /// block for the thread is set up at the top of its stack instead.
+    ///
+    /// The thread ID is assigned when the thread is added to the scheduler.
+    /// The new thread starts at the nice level of `self`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoVmSpace` if the process already has `USER_MAX_THREADS`
+    /// threads, or the error of `TlsTemplate::install()`.
+    pub fn spawn_thread(&self, entry: u64, arg: u64, tls: u64) -> OsResult<Process> {
+	let template = self.threads.lock().tls;
+	let (slot, sp, tls) = {
+	    let mut vmap = self.vmap.lock();
+	    let slot = (1..USER_MAX_THREADS)
+		.find(|slot| !vmap.is_reserved(Process::get_thread_stack_base(*slot)))
+		.ok_or(OsError::NoVmSpace)?;
+	    let base = Process::get_thread_stack_base(slot);
+	    vmap.reserve(Region::stack(base.as_usize()));
+	    match (tls, template) {
+		(0, Some(template)) => match template.install(&mut vmap, base) {
+		    Ok(tp) => (slot, tp, tp),
+		    Err(e) => {
+			vmap.release(base);
+			return Err(e);
+		    },
+		},
+		_ => (slot, (base.as_usize() + PAGE_SIZE - 16) as u64, tls),
+	    }
+	};
+
+	let mut context = *self.context;
+	context.x = [0; 30];
+	context.x[0] = arg;
+	context.lr = 0;
+	context.elr = entry;
+	context.sp = sp;
+	context.tpidr = tls;
+
+	Ok(Process {
+	    context: Box::new(context),
+	    vmap: self.vmap.clone(),
+	    state: State::Ready,
+	    #[cfg(feature = "net")]
+	    sockets: Vec::new(),
+	    tid: 0,
+	    pid: self.pid,
+	    threads: self.threads.clone(),
+	    files: self.files.clone(),
+	    cwd: self.cwd.clone(),
+	    perf_counters: self.perf_counters.clone(),
+	    stack_slot: slot,
+	    usage: self.usage.clone(),
+	    ran_at: Duration::default(),
+	    nice: self.nice,
+	    age: 0,
+	    exit_hooks: self.exit_hooks.clone(),
+	    family: self.family.clone(),
+	})
+    }
+}
@@ -0,0 +1,63 @@
+use alloc::vec;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::param::PAGE_SIZE;
+use crate::vm::{UserPageTable, VirtualAddr};
+
+/// Size of the thread control block `TPIDR_EL0` points at. AArch64 uses TLS
+/// variant 1: the TLS block follows the TCB, aligned to the block's alignment.
+const TCB_SIZE: usize = 16;
+
+/// The initialization image of a program's thread local storage, described
+/// by its `PT_TLS` segment.
+#[derive(Debug, Copy, Clone)]
+pub struct TlsTemplate {
+    /// address of the `.tdata` image in the loaded program
+    pub vaddr: u64,
+    /// bytes initialized from the image
+    pub filesz: u64,
+    /// size of the TLS block; the bytes past `filesz` are zeroed (`.tbss`)
+    pub memsz: u64,
+    /// alignment of the TLS block, a power of two
+    pub align: u64,
+}
+
+impl TlsTemplate {
+    fn align(&self) -> usize {
+	core::cmp::max(self.align as usize, TCB_SIZE)
+    }
+
+    /// Offset of the TLS block from the thread pointer.
+    fn data_offset(&self) -> usize {
+	(TCB_SIZE + self.align() - 1) & !(self.align() - 1)
+    }
+
+    /// Places a fresh TCB and TLS block at the top of the stack page at
+    /// `stack_base` and initializes the block from the template.
+    ///
+    /// Returns the thread pointer for `TPIDR_EL0`, which is also the new
+    /// 16-byte aligned stack top right below the block.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoMemory` if the block would take more than half of the stack
+    /// and `BadAddress` if the template is not mapped in `vmap`.
+    pub fn install(&self, vmap: &mut UserPageTable, stack_base: VirtualAddr) -> OsResult<u64> {
+	let size = self.data_offset() + self.memsz as usize;
+	if size > PAGE_SIZE / 2 {
+	    return Err(OsError::NoMemory);
+	}
+	let tp = (stack_base.as_usize() + (PAGE_SIZE - size)) & !(self.align() - 1);
+
+	let mut block = vec![0u8; size];
+	let image = self.data_offset()..self.data_offset() + self.filesz as usize;
+	if !vmap.copy_from(VirtualAddr::from(self.vaddr), &mut block[image]) {
+	    return Err(OsError::BadAddress);
+	}
+	if !vmap.copy_to(VirtualAddr::from(tp), &block) {
+	    return Err(OsError::BadAddress);
+	}
+	Ok(tp as u64)
+    }
+}
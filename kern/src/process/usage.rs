@@ -0,0 +1,25 @@
+use core::time::Duration;
+
+/// What the threads of a process used of the machine together, shared by
+/// the threads. Each thread keeps when it was switched in itself, see
+/// `Process::ran_at`, and adds the time it ran here.
+#[derive(Debug, Default)]
+pub struct Usage {
+    /// time the threads ran, in user space and in system calls
+    pub cpu: Duration,
+    /// times a thread was switched out
+    pub switches: u64,
+    /// most pages the address space owned at once, as of the last switch
+    pub peak_pages: usize,
+}
+
+impl Usage {
+    /// Charges a thread switched out, exiting or killed included, for the
+    /// time `ran` it ran since it was switched in. `peak_pages` is the most
+    /// pages the address space owned so far.
+    pub fn switched_out(&mut self, ran: Duration, peak_pages: usize) {
+	self.cpu += ran;
+	self.switches += 1;
+	self.peak_pages = core::cmp::max(self.peak_pages, peak_pages);
+    }
+}
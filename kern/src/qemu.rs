@@ -0,0 +1,83 @@
+//! Hooks for integration tests run under QEMU.
+//!
+//! Kernels built with the `qemu-test` feature talk to QEMU through
+//! semihosting, which `make qemu-test` turns on with `-semihosting`: they
+//! can end the emulator with an exit status and write memory to files on
+//! the host. On hardware there is no debugger to answer the `hlt`
+//! instruction semihosting uses, so other builds make the hooks no-ops.
+
+#[cfg(feature = "qemu-test")]
+use alloc::vec::Vec;
+
+/// Semihosting operation numbers (ref: Arm semihosting specification)
+#[cfg(feature = "qemu-test")]
+mod op {
+    pub const SYS_OPEN: u64 = 0x01;
+    pub const SYS_CLOSE: u64 = 0x02;
+    pub const SYS_WRITE: u64 = 0x05;
+    pub const SYS_EXIT: u64 = 0x18;
+}
+
+/// `SYS_EXIT` reason for a program that ended by itself
+#[cfg(feature = "qemu-test")]
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+/// `SYS_OPEN` mode of `fopen(name, "wb")`
+#[cfg(feature = "qemu-test")]
+const MODE_WB: u64 = 5;
+
+/// Asks the host for semihosting operation `op` with the parameter block at
+/// `param` and returns its result.
+#[cfg(feature = "qemu-test")]
+unsafe fn semihost(op: u64, param: *const u64) -> u64 {
+    let result: u64;
+    asm!("hlt #0xf000"
+         : "={x0}"(result)
+         : "{x0}"(op), "{x1}"(param as u64)
+         : "memory"
+         : "volatile");
+    result
+}
+
+/// Ends QEMU with exit status `code`. Returns on hardware and in builds
+/// without `qemu-test`.
+pub fn test_exit(code: u32) {
+    #[cfg(feature = "qemu-test")]
+    unsafe {
+	let block = [ADP_STOPPED_APPLICATION_EXIT, code as u64];
+	semihost(op::SYS_EXIT, block.as_ptr());
+    }
+    #[cfg(not(feature = "qemu-test"))]
+    let _ = code;
+}
+
+/// Writes the `len` bytes at physical address `addr` to the file `path` on
+/// the host, relative to the directory QEMU runs in. Returns false if the
+/// host refused or the kernel was built without `qemu-test`.
+///
+/// # Safety
+///
+/// The region must be readable memory.
+pub unsafe fn dump(path: &str, addr: usize, len: usize) -> bool {
+    #[cfg(feature = "qemu-test")]
+    {
+	let mut name: Vec<u8> = path.as_bytes().to_vec();
+	name.push(0);
+	let open = [name.as_ptr() as u64, MODE_WB, path.len() as u64];
+	let handle = semihost(op::SYS_OPEN, open.as_ptr());
+	if handle == u64::max_value() {
+	    return false;
+	}
+	// SYS_WRITE returns the number of bytes it did not write
+	let write = [handle, addr as u64, len as u64];
+	let unwritten = semihost(op::SYS_WRITE, write.as_ptr());
+	let close = [handle];
+	semihost(op::SYS_CLOSE, close.as_ptr());
+	unwritten == 0
+    }
+    #[cfg(not(feature = "qemu-test"))]
+    {
+	let _ = (path, addr, len);
+	false
+    }
+}
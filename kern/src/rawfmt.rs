@@ -0,0 +1,91 @@
+//! Text for diagnostics that must not allocate: the panic and allocation
+//! error handlers, early boot and fault handlers. Numbers are formatted by
+//! hand into buffers on the stack, and the text is printed with
+//! `console::print_raw()`, which neither takes the heap nor follows a
+//! redirection of the console into a file.
+
+use core::fmt;
+use core::str;
+
+/// Bytes a `StackBuf` holds; what does not fit is dropped
+pub const STACK_BUF_SIZE: usize = 512;
+
+/// Writes `n` in decimal to the end of `buf` and returns the digits.
+pub fn dec(mut n: u64, buf: &mut [u8; 20]) -> &str {
+    let mut start = buf.len();
+    loop {
+	start -= 1;
+	buf[start] = b'0' + (n % 10) as u8;
+	n /= 10;
+	if n == 0 {
+	    break;
+	}
+    }
+    unsafe { str::from_utf8_unchecked(&buf[start..]) }
+}
+
+/// Writes `n` in hexadecimal with a `0x` prefix and no leading zeroes to the
+/// end of `buf` and returns it.
+pub fn hex(mut n: u64, buf: &mut [u8; 18]) -> &str {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut start = buf.len();
+    loop {
+	start -= 1;
+	buf[start] = DIGITS[(n & 0xf) as usize];
+	n >>= 4;
+	if n == 0 {
+	    break;
+	}
+    }
+    buf[start - 2..start].copy_from_slice(b"0x");
+    unsafe { str::from_utf8_unchecked(&buf[start - 2..]) }
+}
+
+/// A line of text built on the stack, or in a static reserved for it,
+/// truncated at `STACK_BUF_SIZE` bytes.
+pub struct StackBuf {
+    buf: [u8; STACK_BUF_SIZE],
+    len: usize,
+}
+
+impl StackBuf {
+    pub const fn new() -> StackBuf {
+	StackBuf { buf: [0; STACK_BUF_SIZE], len: 0 }
+    }
+
+    /// Appends as much of `s` as fits, up to a character boundary.
+    pub fn push_str(&mut self, s: &str) -> &mut StackBuf {
+	let mut count = core::cmp::min(s.len(), STACK_BUF_SIZE - self.len);
+	while !s.is_char_boundary(count) {
+	    count -= 1;
+	}
+	self.buf[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+	self.len += count;
+	self
+    }
+
+    /// Appends `n` in decimal.
+    pub fn push_dec(&mut self, n: u64) -> &mut StackBuf {
+	self.push_str(dec(n, &mut [0; 20]))
+    }
+
+    /// Appends `n` in hexadecimal with a `0x` prefix.
+    pub fn push_hex(&mut self, n: u64) -> &mut StackBuf {
+	self.push_str(hex(n, &mut [0; 18]))
+    }
+
+    pub fn as_str(&self) -> &str {
+	// only whole characters are ever pushed
+	unsafe { str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+/// For text only `fmt` can produce, such as a panic message. Formatting
+/// into the buffer does not allocate, but runs the `Display` impls of the
+/// arguments.
+impl fmt::Write for StackBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+	self.push_str(s);
+	Ok(())
+    }
+}
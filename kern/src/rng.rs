@@ -0,0 +1,183 @@
+//! The kernel's random numbers, which every consumer draws from: the kernel
+//! image alias of `kaslr`, and processes through the `getrandom` system
+//! call. A xoshiro256** generator produces them, seeded from the hardware
+//! RNG and reseeded from it by `reseed()`.
+//!
+//! Given the `rngseed=N` boot argument, or after `seed()`, the generator is
+//! seeded from `N` instead and never touches the hardware, so that a test
+//! sees the same numbers every run. xoshiro256** is fast and well
+//! distributed but predictable from its output; nothing here is meant for
+//! keys.
+
+use pi::rng as hw;
+use pi::timer::current_time;
+
+use crate::bootargs;
+use crate::mutex::Mutex;
+
+/// Where the generator's state last came from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Seed {
+    /// the hardware RNG
+    Hardware,
+    /// the timer, as the hardware RNG produced no number
+    Timer,
+    /// a fixed seed: the numbers are the same every run
+    Fixed(u64),
+}
+
+struct Generator {
+    state: [u64; 4],
+    seed: Seed,
+}
+
+static GENERATOR: Mutex<Option<Generator>> = Mutex::new(None);
+
+/// Expands `x` into well mixed words, as xoshiro's authors recommend for
+/// seeding it from a single number.
+fn splitmix64(x: &mut u64) -> u64 {
+    *x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Generator {
+    fn fixed(seed: u64) -> Generator {
+	let mut x = seed;
+	let state = [splitmix64(&mut x), splitmix64(&mut x), splitmix64(&mut x), splitmix64(&mut x)];
+	Generator { state, seed: Seed::Fixed(seed) }
+    }
+
+    fn unseeded() -> Generator {
+	Generator { state: [0; 4], seed: Seed::Timer }
+    }
+
+    /// Mixes numbers from the hardware RNG into the state, or the time if
+    /// the hardware produces none.
+    fn mix(&mut self) {
+	let mut words = [0u64; 4];
+	let mut seed = Seed::Hardware;
+	for word in words.iter_mut() {
+	    match (hw::next_u32(), hw::next_u32()) {
+		(Some(high), Some(low)) => *word = (high as u64) << 32 | low as u64,
+		_ => {
+		    seed = Seed::Timer;
+		    break;
+		},
+	    }
+	}
+	if seed == Seed::Timer {
+	    warn!("rng: the hardware RNG produced no number, seeding from the timer");
+	    let mut x = current_time().as_nanos() as u64;
+	    for word in words.iter_mut() {
+		*word = splitmix64(&mut x);
+	    }
+	}
+	for (state, word) in self.state.iter_mut().zip(words.iter()) {
+	    *state ^= word;
+	}
+	// xoshiro never leaves an all zero state
+	if self.state == [0; 4] {
+	    self.state[0] = 1;
+	}
+	self.seed = seed;
+    }
+
+    fn next_u64(&mut self) -> u64 {
+	let s = &mut self.state;
+	let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+	let t = s[1] << 17;
+	s[2] ^= s[0];
+	s[3] ^= s[1];
+	s[1] ^= s[2];
+	s[0] ^= s[3];
+	s[2] ^= t;
+	s[3] = s[3].rotate_left(45);
+	result
+    }
+}
+
+/// Runs `f` on the generator, seeding it first from the hardware if
+/// nothing has seeded it yet.
+fn with<T, F: FnOnce(&mut Generator) -> T>(f: F) -> T {
+    let mut generator = GENERATOR.lock();
+    let generator = generator.get_or_insert_with(|| {
+	let mut generator = Generator::unseeded();
+	generator.mix();
+	generator
+    });
+    f(generator)
+}
+
+/// Seeds the generator, from `N` if the `rngseed=N` boot argument is given
+/// and from the hardware otherwise.
+pub fn initialize() {
+    match bootargs::get("rngseed").map(|arg| (arg, arg.parse::<u64>())) {
+	Some((_, Ok(n))) => {
+	    seed(n);
+	    info!("rng: fixed seed {}", n);
+	},
+	Some((arg, Err(_))) => {
+	    warn!("rng: ignoring rngseed={}, not a number", arg);
+	    reseed();
+	},
+	None => {
+	    reseed();
+	},
+    }
+}
+
+/// Seeds the generator from `n`, after which it produces the same numbers
+/// every time until it is reseeded.
+pub fn seed(n: u64) {
+    *GENERATOR.lock() = Some(Generator::fixed(n));
+}
+
+/// Mixes fresh numbers from the hardware RNG, or the time if it produces
+/// none, into the generator, leaving deterministic mode. Returns where
+/// they came from.
+pub fn reseed() -> Seed {
+    let mut generator = GENERATOR.lock();
+    let generator = generator.get_or_insert_with(Generator::unseeded);
+    generator.mix();
+    generator.seed
+}
+
+/// Returns where the generator's state came from.
+pub fn status() -> Seed {
+    with(|generator| generator.seed)
+}
+
+pub fn next_u64() -> u64 {
+    with(|generator| generator.next_u64())
+}
+
+pub fn next_u32() -> u32 {
+    (next_u64() >> 32) as u32
+}
+
+/// Returns a number in `0..bound`, each equally likely. `bound` must not be
+/// 0.
+pub fn below(bound: u64) -> u64 {
+    assert!(bound != 0);
+    // numbers from `limit` on would favor the low results
+    let limit = core::u64::MAX - core::u64::MAX % bound;
+    with(|generator| loop {
+	let n = generator.next_u64();
+	if n < limit {
+	    return n % bound;
+	}
+    })
+}
+
+/// Fills `buf` with random bytes.
+pub fn fill(buf: &mut [u8]) {
+    with(|generator| {
+	for chunk in buf.chunks_mut(8) {
+	    let bytes = generator.next_u64().to_le_bytes();
+	    chunk.copy_from_slice(&bytes[..chunk.len()]);
+	}
+    })
+}
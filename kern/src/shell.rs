@@ -1,21 +1,27 @@
-use shim::path::{Path, PathBuf, Component};
+use shim::path::PathBuf;
 
 use stack_vec::StackVec;
+use alloc::boxed::Box;
+use alloc::vec;
 use alloc::vec::Vec;
 
 use pi::atags::Atags;
 use pi::interrupt::{Controller, Interrupt};
 
-use fat32::traits::FileSystem;
-use fat32::traits::{Dir, File, Entry};
-
 use kernel_api::*;
 
-use crate::console::{kprint, kprintln, CONSOLE};
+use crate::console::{self, kprint, kprintln, CONSOLE};
 use crate::ALLOCATOR;
+use crate::fileserver;
 use crate::FILESYSTEM;
+use crate::SCHEDULER;
+use crate::param::PAGE_SIZE;
+use crate::process::{ExitHook, FdTable, OpenFile, Process};
+use crate::fs::{MountOptions, Source};
+use fat32::vfat::TimeUpdate;
+use crate::fs::vfs::Handle;
 
-use shim::io::{Read, Write};
+use shim::io::{self, Read, Seek, SeekFrom, Write};
 use core::str;
 use pi::gpio;
 
@@ -48,38 +54,17 @@ impl Shell {
     }
 
     fn change_pwd(&mut self, path: &str) -> bool {
-	let curr_pwd = self.pwd.clone();
-	let p = Path::new(path);
-	
-	for entry in p.components() {
-	    match entry {
-		Component::CurDir => continue,
-		Component::ParentDir => {self.pop();},
-		Component::RootDir => {self.root();},
-		Component::Normal(name) => {
-		    self.pwd.push(name);
-		    if FILESYSTEM.open(self.pwd.as_path()).is_err() { // or is file
-			self.pwd = curr_pwd.clone();
-			return false;
-		    }
-		},
-		_ => unreachable!(),
-	    };
+	let pwd = match fat32::path::resolve(&self.pwd, path) {
+	    Ok(pwd) => pwd,
+	    Err(_) => return false,
+	};
+	if !FILESYSTEM.open(&pwd).map(|entry| entry.attr().directory).unwrap_or(false) {
+	    return false;
 	}
+	self.pwd = pwd;
 	true
     }
 
-    fn pop(&mut self) {
-	if self.pwd.pop() == false {
-	    self.root();
-	}
-    }
-
-    fn root(&mut self) {
-	while self.pwd.pop() {};
-	self.pwd.push("/");
-    } 
-    
     fn new_line(&self, prefix: &str) {
 	kprint!("\n({}) {} ", self.pwd.as_path().display(), prefix);
     }
@@ -118,6 +103,43 @@ impl<'a> Command<'a> {
     }
 }
 
+/// Removes a trailing `> PATH` or `>> PATH` from `cmd` and returns PATH
+/// opened for the output of the command, emptied or to be appended to. A
+/// missing file is created where the file system allows it.
+fn redirection(cmd: &mut Command, shell: &Shell) -> Option<io::Result<Box<dyn Handle>>> {
+    let n = cmd.args.len();
+    if n < 3 {
+	return None;
+    }
+    let append = match cmd.args[n - 2] {
+	">" => false,
+	">>" => true,
+	_ => return None,
+    };
+    let path = cmd.args[n - 1];
+    cmd.args.pop();
+    cmd.args.pop();
+
+    let open = || -> io::Result<Box<dyn Handle>> {
+	let path = fat32::path::resolve(&shell.pwd, path)?;
+	let mut file = match FILESYSTEM.open_file(&path) {
+	    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+		FILESYSTEM.create(&path, false)?;
+		FILESYSTEM.open_file(&path)?
+	    },
+	    result => result?,
+	};
+	match append {
+	    true => {
+		file.seek(SeekFrom::End(0))?;
+	    },
+	    false => file.truncate()?,
+	}
+	Ok(file)
+    };
+    Some(open())
+}
+
 /// fullfills command request if present/valid in Command struct
 fn execute(cmd: &Command, shell: &mut Shell) {
     match cmd.path() {
@@ -128,8 +150,49 @@ fn execute(cmd: &Command, shell: &mut Shell) {
 	"ls" => list_directory(cmd, shell),
 	"pwd" => print_directory(shell),
 	"cat" => concatenate_file(cmd, shell),
+	"attrib" => attrib(cmd, shell),
+	"fragstat" => fragstat(cmd, shell),
+	"readelf" => readelf(cmd, shell),
+	"mkfs" => mkfs(cmd),
+	"mount" => mount(cmd, shell),
+	"umount" => umount(cmd, shell),
+	"touch" => touch(cmd, shell),
+	"mkdir" => make_directory(cmd, shell),
+	"rm" => remove(cmd, shell),
+	"run" => run(cmd, shell),
+	"time" => time(cmd, shell),
+	"dd" => dd(cmd, shell),
+	"backup" => backup(cmd, shell),
+	"ramdisk" => ramdisk(cmd),
+	"iostat" => iostat(cmd),
 	"exit" => exit(shell),
 	"sleep" => sleep(cmd),
+	"watch" => watch(cmd, shell),
+	"dmesg" => dmesg(cmd),
+	"log" => log_sinks(cmd),
+	"ktrace" => ktrace(cmd),
+	"rng" => rng(cmd),
+	"vmstat" => vmstat(cmd),
+	"bootstat" => bootstat(cmd),
+	"vmmap" => vmmap(cmd),
+	"checkpoint" => checkpoint(cmd, shell),
+	"restore" => restore(cmd, shell),
+	"uname" => uname(cmd),
+	"hz" => hz(cmd),
+	"quantum" => quantum(cmd),
+	"renice" => renice(cmd),
+	"led" => led(cmd),
+	"date" => date(cmd),
+	"qemu" => qemu(cmd),
+	"drivers" => drivers(cmd),
+	#[cfg(feature = "gfx")]
+	"fb" => framebuffer(cmd),
+	#[cfg(feature = "sound")]
+	"beep" => beep(cmd),
+	#[cfg(feature = "heap-guard")]
+	"heap" => heap(cmd),
+	// a path, like ./prog, runs the program there
+	path if path.contains('/') => start(cmd.args.as_slice(), shell, false),
 	_ => {
 	    kprint!("\nunknown command");
 	},
@@ -180,58 +243,56 @@ fn change_directory(cmd: &Command, shell: &mut Shell) {
 }
 
 fn list_directory(cmd: &Command, shell: &mut Shell) {
-    use fat32::traits::{Metadata, Timestamp};
     let mut hidden = false;
-    let mut path = shell.pwd.clone();
+    let mut path = ".";
     
     if cmd.args.len() == 3 && cmd.args[1] == "-a" {
 	hidden = true;
-	path.push(cmd.args[2]);
+	path = cmd.args[2];
     }
     else if cmd.args.len() == 2 && cmd.args[1] == "-a"{
 	hidden = true;
     }
     else if cmd.args.len() == 2 {
-	path.push(cmd.args[1]);
-    }
-
-    if let Ok(entry) = FILESYSTEM.open(path.as_path()) {
-	if let Some(dir) = entry.as_dir() {
-	    for entry in dir.entries().unwrap() {
-		if !entry.metadata().hidden() || hidden {
-		    kprintln!("");
-		    
-		    match entry.metadata().read_only() {
-			true => {kprint!("r");},
-			false => {kprint!("w");},
-		    }
-		    
-		    match entry.metadata().hidden() {
-			true => {kprint!("h");},
-			false => {kprint!("-");},
-		    }
-		    
-		    match entry.metadata().system() {
-			true => {kprint!("s");},
-			false => {kprint!("-");},
-		    }
-		    
-		    match entry.metadata().directory() {
-			true => {kprint!("d");},
-			false => {kprint!("f");},
-		    }
-		    
-		    match entry.metadata().archive() {
-			true => {kprint!("a");},
-			false => {kprint!("-");},
-		    }
-		    
-		    kprint!(" {:02}/{:02}/{:04} {:02}:{:02}:{:02} ", entry.metadata().created().day(), entry.metadata().created().month(), entry.metadata().created().year(), entry.metadata().created().hour(), entry.metadata().created().minute(), entry.metadata().created().second());
+	path = cmd.args[1];
+    }
+
+    if let Ok(entries) = FILESYSTEM.open_at(&shell.pwd, path).and_then(|dir| dir.entries()) {
+	for entry in entries.iter() {
+	    let attr = entry.attr();
+	    if !attr.hidden || hidden {
+		kprintln!("");
+		
+		match attr.read_only {
+		    true => {kprint!("r");},
+		    false => {kprint!("w");},
+		}
 		
-		    kprint!("{:02}/{:02}/{:04} {:02}:{:02}:{:02} ", entry.metadata().modified().day(), entry.metadata().modified().month(), entry.metadata().modified().year(), entry.metadata().modified().hour(), entry.metadata().modified().minute(), entry.metadata().modified().second());
+		match attr.hidden {
+		    true => {kprint!("h");},
+		    false => {kprint!("-");},
+		}
 		
-		    kprint!(" {:10} {}", entry.metadata().file_size(), entry.name());
+		match attr.system {
+		    true => {kprint!("s");},
+		    false => {kprint!("-");},
 		}
+		
+		match attr.directory {
+		    true => {kprint!("d");},
+		    false => {kprint!("f");},
+		}
+		
+		match attr.archive {
+		    true => {kprint!("a");},
+		    false => {kprint!("-");},
+		}
+		
+		kprint!(" {:02}/{:02}/{:04} {:02}:{:02}:{:02} ", attr.created.day, attr.created.month, attr.created.year, attr.created.hour, attr.created.minute, attr.created.second);
+		
+		kprint!("{:02}/{:02}/{:04} {:02}:{:02}:{:02} ", attr.modified.day, attr.modified.month, attr.modified.year, attr.modified.hour, attr.modified.minute, attr.modified.second);
+		
+		kprint!(" {:10} {}", attr.size, entry.name());
 	    }
 	}
     }
@@ -251,52 +312,1368 @@ fn concatenate_file(cmd: &Command, shell: &mut Shell) {
 	return;
     }
     
-    let mut file_path = shell.pwd.clone();
-    file_path.push(Path::new(cmd.args[1]));
-	
-    if let Ok(entry) = FILESYSTEM.open(file_path.as_path()) {
-	if let Some(mut file) = entry.into_file() {
-	    kprintln!("");
-	    let mut read_bytes = 0;
-	    let mut data = [0u8; 1024];
-	    while read_bytes < file.size() {
-		if let Ok(bytes_returned) = file.read(&mut data) {
-		    if let Ok(text) = str::from_utf8(&data[0..bytes_returned]) {
-			kprint!("{:?}", text);
-		    }
-		    read_bytes += bytes_returned as u64;
-		}
-		else {
-		    return;
+    if let Ok(mut file) = FILESYSTEM.open_at(&shell.pwd, cmd.args[1]).and_then(|entry| entry.open()) {
+	kprintln!("");
+	let mut read_bytes = 0;
+	let mut data = [0u8; 1024];
+	while read_bytes < file.size() {
+	    if let Ok(bytes_returned) = file.read(&mut data) {
+		if let Ok(text) = str::from_utf8(&data[0..bytes_returned]) {
+		    kprint!("{:?}", text);
 		}
+		read_bytes += bytes_returned as u64;
+	    }
+	    else {
+		return;
 	    }
-	    return;
 	}
+	return;
     }
     kprint!("\n{}: {}: No such file", cmd.args[0], cmd.args[1]);
 }
 
+/// attrib [+r|-r|+h|-h]... PATH
+/// sets or clears the read only and hidden attributes of PATH, then prints
+/// its attributes
+fn attrib(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "attrib");
+    if cmd.args.len() < 2 {
+	kprint!("\nusage: attrib [+r|-r|+h|-h]... PATH");
+	return;
+    }
+    let path = cmd.args[cmd.args.len() - 1];
+    let mut entry = match FILESYSTEM.open_at(&shell.pwd, path) {
+	Ok(entry) => entry,
+	Err(_) => {
+	    kprint!("\n{}: {}: No such file or directory", cmd.args[0], path);
+	    return;
+	},
+    };
+
+    for flag in cmd.args.as_slice()[1..cmd.args.len() - 1].iter() {
+	let result = match *flag {
+	    "+r" => entry.set_read_only(true),
+	    "-r" => entry.set_read_only(false),
+	    "+h" => entry.set_hidden(true),
+	    "-h" => entry.set_hidden(false),
+	    _ => {
+		kprint!("\n{}: invalid flag {}", cmd.args[0], flag);
+		return;
+	    },
+	};
+	if let Err(e) = result {
+	    kprint!("\n{}: {}: {}", cmd.args[0], path, reason(e));
+	    return;
+	}
+    }
+
+    let attr = entry.attr();
+    kprint!("\n{}{}{}{} {}",
+	    if attr.read_only { "r" } else { "-" },
+	    if attr.hidden { "h" } else { "-" },
+	    if attr.system { "s" } else { "-" },
+	    if attr.archive { "a" } else { "-" },
+	    entry.name());
+}
+
 fn exit(shell: &mut Shell) {
     shell.active = false;
 }
 
-fn sleep(cmd: &Command) {   
+/// sleep SECONDS
+/// waits SECONDS, which may have a fraction like 0.5, with the core asleep
+/// while no other interrupt is pending, see `clock::wait()`. a key press
+/// ends the wait early
+fn sleep(cmd: &Command) {
     assert_eq!(cmd.args[0], "sleep");
-    if (cmd.args.len() != 2) {
-	kprint!("\ninvalid argument");
+    let duration = match cmd.args.len() {
+	2 => parse_seconds(cmd.args[1]),
+	_ => None,
+    };
+    match duration {
+	Some(duration) => {
+	    if !crate::clock::wait(duration) {
+		CONSOLE.lock().read_byte();
+	    }
+	},
+	None => kprint!("\nusage: sleep SECONDS"),
+    }
+}
+
+/// watch -n SECONDS COMMAND...
+/// clears the screen and runs the builtin COMMAND every SECONDS, waiting as
+/// sleep does in between, until a key is pressed
+fn watch(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "watch");
+    let interval = match cmd.args.len() {
+	n if n >= 4 && cmd.args[1] == "-n" => parse_seconds(cmd.args[2]).filter(|d| *d > Duration::from_millis(0)),
+	_ => None,
+    };
+    let interval = match interval {
+	Some(interval) => interval,
+	None => {
+	    kprint!("\nusage: watch -n SECONDS COMMAND...");
+	    return;
+	},
+    };
+    let mut backing: [&str; 64] = [""; 64];
+    let mut args = StackVec::new(&mut backing);
+    for arg in cmd.args.as_slice()[3..].iter() {
+	let _ = args.push(*arg);
+    }
+    let command = Command { args };
+
+    loop {
+	// cursor to the top left, then clear the screen
+	kprint!("\x1b[H\x1b[2JEvery {}s:", cmd.args[2]);
+	for arg in command.args.as_slice().iter() {
+	    kprint!(" {}", arg);
+	}
+	kprint!("\n");
+	execute(&command, shell);
+	if !crate::clock::wait(interval) {
+	    CONSOLE.lock().read_byte();
+	    break;
+	}
+    }
+}
+
+fn dmesg(cmd: &Command) {
+    use crate::dmesg::DMESG;
+    use alloc::string::String;
+    assert_eq!(cmd.args[0], "dmesg");
+    let previous = match cmd.args.len() {
+	1 => false,
+	2 if cmd.args[1] == "--previous" => true,
+	_ => {
+	    kprint!("\nusage: dmesg [--previous]");
+	    return;
+	},
+    };
+
+    let guard = DMESG.lock();
+    let log = match guard.as_ref() {
+	Some(log) => log,
+	None => {
+	    kprint!("\n{}: log unavailable", cmd.args[0]);
+	    return;
+	},
+    };
+    let ring = match previous {
+	true => log.previous(),
+	false => Some(log.current()),
+    };
+
+    if let Some(ring) = ring {
+	let (first, second) = ring.contents();
+	kprintln!("");
+	kprint!("{}", String::from_utf8_lossy(first));
+	kprint!("{}", String::from_utf8_lossy(second));
+    }
+    else {
+	kprint!("\n{}: no log from previous boot", cmd.args[0]);
+    }
+}
+
+/// mkfs [-c SECTORS_PER_CLUSTER] [-n LABEL] PARTITION
+/// formats the partition of RAM disk PARTITION (ram0 to ram3) as an empty
+/// FAT32 volume. SD card partitions (1 to 4) are refused: the SD card driver
+/// cannot write
+fn mkfs(cmd: &Command) {
+    use fat32::vfat::{Error, FormatParams};
+    assert_eq!(cmd.args[0], "mkfs");
+    let usage = "\nusage: mkfs [-c SECTORS_PER_CLUSTER] [-n LABEL] PARTITION";
+    let mut params = FormatParams::default();
+    params.volume_id = pi::timer::current_time().as_micros() as u32;
+
+    let args = cmd.args.as_slice();
+    let mut i = 1;
+    while i + 1 < args.len() {
+	match (args[i], args[i + 1]) {
+	    ("-c", count) => match u8::from_str(count) {
+		Ok(count) => params.sectors_per_cluster = count,
+		Err(_) => {
+		    kprint!("{}", usage);
+		    return;
+		},
+	    },
+	    ("-n", label) if label.len() <= 11 => {
+		params.label = [b' '; 11];
+		for (byte, c) in params.label.iter_mut().zip(label.bytes()) {
+		    *byte = c.to_ascii_uppercase();
+		}
+	    },
+	    _ => {
+		kprint!("{}", usage);
+		return;
+	    },
+	}
+	i += 2;
+    }
+    let device = match args.get(i) {
+	Some(device) if i + 1 == args.len() => *device,
+	_ => {
+	    kprint!("{}", usage);
+	    return;
+	},
+    };
+    let ram = parse_ram_disk(device);
+    params.partition = match (ram, usize::from_str(device)) {
+	(Some(_), _) => 0,
+	(None, Ok(partition)) if partition >= 1 && partition <= 4 => partition - 1,
+	_ => {
+	    kprint!("{}", usage);
+	    return;
+	},
+    };
+
+    match FILESYSTEM.format(ram, &params) {
+	Ok(()) => kprint!("\nformatted {}", device),
+	Err(Error::Io(e)) => kprint!("\nmkfs: cannot format {}: {}", device, e),
+	Err(e) => kprint!("\nmkfs: {:?}", e),
+    }
+}
+
+/// mount [DEVICE PATH [-o OPTIONS]]
+/// mounts SD card partition DEVICE (sd1 to sd4, or 1 to 4), RAM disk DEVICE
+/// (ram0 to ram3), or the volume image in file DEVICE, on the directory PATH. OPTIONS is a comma separated
+/// list of ro, rw, fatplus, which reads file sizes over 4 GiB, times=always,
+/// times=relatime or times=never, which set when file times are updated, and
+/// loop, which takes DEVICE as a file even if it looks like a partition.
+/// lists the mounted volumes without arguments
+///
+/// mount -o remount,ro|rw PATH
+/// makes the volume mounted on PATH read only or writable again
+fn mount(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "mount");
+    let usage = "\nusage: mount [DEVICE PATH [-o OPTIONS]]\n       mount -o remount,ro|rw PATH";
+    let args = cmd.args.as_slice();
+    let (options, image) = match args.len() {
+	1 => {
+	    FILESYSTEM.for_each_mount(|m| {
+		let times = match m.options.times {
+		    TimeUpdate::Relatime => "",
+		    TimeUpdate::Always => ",times=always",
+		    TimeUpdate::Never => ",times=never",
+		};
+		kprint!("\n{} on {} type {} ({}{}{})", m.source, m.path.display(), m.fs_type(),
+			if m.read_only() { "ro" } else { "rw" }, if m.options.fat_plus { ",fatplus" } else { "" }, times);
+	    });
+	    return;
+	},
+	4 if args[1] == "-o" => {
+	    let read_only = match args[2] {
+		"remount,ro" | "ro,remount" => true,
+		"remount,rw" | "rw,remount" => false,
+		_ => {
+		    kprint!("{}", usage);
+		    return;
+		},
+	    };
+	    let result = fat32::path::resolve(&shell.pwd, args[3])
+		.and_then(|path| FILESYSTEM.remount(&path, read_only));
+	    if let Err(e) = result {
+		kprint!("\n{}: {}: {}", args[0], args[3], reason(e));
+	    }
+	    return;
+	},
+	3 => (MountOptions::default(), false),
+	5 if args[3] == "-o" => {
+	    let (mut options, mut image) = (MountOptions::default(), false);
+	    for option in args[4].split(',') {
+		match option {
+		    "ro" => options.read_only = true,
+		    "rw" => options.read_only = false,
+		    "fatplus" => options.fat_plus = true,
+		    "times=always" => options.times = TimeUpdate::Always,
+		    "times=relatime" => options.times = TimeUpdate::Relatime,
+		    "times=never" => options.times = TimeUpdate::Never,
+		    "loop" => image = true,
+		    _ => {
+			kprint!("\n{}: unknown option {}", args[0], option);
+			return;
+		    },
+		}
+	    }
+	    (options, image)
+	},
+	_ => {
+	    kprint!("{}", usage);
+	    return;
+	},
+    };
+    let partition = match usize::from_str(args[1].trim_start_matches("sd")) {
+	Ok(partition) if partition >= 1 && partition <= 4 && !image => Some(partition - 1),
+	_ => None,
+    };
+    let source = match (partition, parse_ram_disk(args[1])) {
+	(Some(partition), _) => Source::Partition(partition),
+	(None, Some(n)) if !image => Source::Ram(n),
+	_ => match fat32::path::resolve(&shell.pwd, args[1]) {
+	    Ok(image) => Source::Image(image),
+	    Err(_) => {
+		kprint!("{}", usage);
+		return;
+	    },
+	},
+    };
+    let path = match fat32::path::resolve(&shell.pwd, args[2]) {
+	Ok(path) => path,
+	Err(_) => {
+	    kprint!("{}", usage);
+	    return;
+	},
+    };
+
+    match FILESYSTEM.mount(source, &path, options) {
+	Ok(report) => {
+	    if report.primary_boot_corrupt {
+		kprint!("\n{}: {}: boot sector corrupt, mounted from backup", args[0], args[1]);
+	    }
+	    if report.unclean {
+		kprint!("\n{}: {}: volume was not cleanly unmounted", args[0], args[1]);
+	    }
+	},
+	Err(e) => kprint!("\n{}: {}: {:?}", args[0], args[1], e),
+    }
+}
+
+/// fragstat [-v] PATH...
+/// prints the number of runs of contiguous blocks, clusters on FAT32, holding
+/// each file, and with -v the first block and length of every run
+fn fragstat(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "fragstat");
+    let verbose = cmd.args.len() > 1 && cmd.args[1] == "-v";
+    let paths = &cmd.args.as_slice()[if verbose { 2 } else { 1 }..];
+    if paths.is_empty() {
+	kprint!("\nusage: fragstat [-v] PATH...");
 	return;
     }
-    
-    if let Ok(ms) = u64::from_str(cmd.args[1]) {
-	let dur = Duration::from_millis(ms);
-	if let Ok(duration) = syscall::sleep(dur) {
-	    kprint!("\nslept for {} milliseconds", duration.as_millis());
-	} else {
-	    kprint!("\nan error occurred");
+    for path in paths.iter() {
+	let file = match FILESYSTEM.open_at(&shell.pwd, path).and_then(|entry| entry.open()) {
+	    Ok(file) => file,
+	    Err(_) => {
+		kprint!("\n{}: {}: No such file", cmd.args[0], path);
+		continue;
+	    },
+	};
+	match file.extents() {
+	    Ok(extents) => {
+		let blocks: u64 = extents.iter().map(|&(_, len)| len).sum();
+		kprint!("\n{}: {} blocks in {} extents", path, blocks, extents.len());
+		if verbose {
+		    for (start, len) in extents.iter() {
+			kprint!("\n  {:>10} {:>10}", start, len);
+		    }
+		}
+	    },
+	    Err(e) => kprint!("\n{}: {}: {}", cmd.args[0], path, reason(e)),
+	}
+    }
+}
+
+/// readelf PATH
+/// prints the ELF header and program headers of PATH and whether the loader
+/// accepts it
+fn readelf(cmd: &Command, shell: &mut Shell) {
+    use crate::process::elf::{self, PF_R, PF_W, PF_X};
+
+    assert_eq!(cmd.args[0], "readelf");
+    if cmd.args.len() != 2 {
+	kprint!("\nusage: readelf PATH");
+	return;
+    }
+    let mut file = match FILESYSTEM.open_at(&shell.pwd, cmd.args[1]).and_then(|entry| entry.open()) {
+	Ok(file) => file,
+	Err(_) => {
+	    kprint!("\n{}: {}: No such file", cmd.args[0], cmd.args[1]);
+	    return;
+	},
+    };
+    let elf = match elf::parse(&mut file) {
+	Ok(elf) => elf,
+	Err(e) => {
+	    kprint!("\n{}: {}: not an ELF file ({:?})", cmd.args[0], cmd.args[1], e);
+	    return;
+	},
+    };
+
+    let header = &elf.header;
+    kprint!("\nclass {} data {} type ", header.ident[4], header.ident[5]);
+    match header.kind_name() {
+	Some(name) => kprint!("{}", name),
+	None => kprint!("{:#x}", header.kind),
+    }
+    kprint!(" machine {} entry {:#018x} flags {:#x}", header.machine, header.entry, header.flags);
+    kprint!("\n{} program headers at {:#x}", elf.segments.len(), header.phoff);
+    kprint!("\n  {:<12} {:>10} {:>18} {:>10} {:>10} {} {:>8}", "type", "offset", "vaddr", "filesz", "memsz", "flg", "align");
+    for segment in elf.segments.iter() {
+	match segment.kind_name() {
+	    Some(name) => kprint!("\n  {:<12}", name),
+	    None => kprint!("\n  {:<#12x}", segment.kind),
+	}
+	kprint!(" {:>#10x} {:>#18x} {:>#10x} {:>#10x} {}{}{} {:>#8x}",
+		segment.offset, segment.vaddr, segment.filesz, segment.memsz,
+		if segment.flags & PF_R != 0 { "r" } else { "-" },
+		if segment.flags & PF_W != 0 { "w" } else { "-" },
+		if segment.flags & PF_X != 0 { "x" } else { "-" },
+		segment.align);
+    }
+    match elf.problem() {
+	Some(problem) => kprint!("\nnot loadable: {}", problem),
+	None => kprint!("\nloadable"),
+    }
+}
+
+/// umount PATH
+/// syncs and unmounts the volume mounted on PATH
+fn umount(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "umount");
+    if cmd.args.len() != 2 {
+	kprint!("\nusage: umount PATH");
+	return;
+    }
+    let result = fat32::path::resolve(&shell.pwd, cmd.args[1])
+	.and_then(|path| FILESYSTEM.unmount(&path));
+    if let Err(e) = result {
+	kprint!("\n{}: {}: {}", cmd.args[0], cmd.args[1], reason(e));
+    }
+}
+
+/// touch PATH...
+/// creates each PATH as an empty file unless it exists. only file systems
+/// that can create files, like /tmp, support it
+fn touch(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "touch");
+    if cmd.args.len() < 2 {
+	kprint!("\nusage: touch PATH...");
+	return;
+    }
+    for arg in cmd.args.as_slice()[1..].iter() {
+	let result = fat32::path::resolve(&shell.pwd, arg)
+	    .and_then(|path| FILESYSTEM.create(&path, false));
+	match result {
+	    Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+	    Err(e) => kprint!("\n{}: {}: {}", cmd.args[0], arg, reason(e)),
+	    Ok(()) => {},
+	}
+    }
+}
+
+/// run PROGRAM [ARG...]
+/// starts PROGRAM with the ARGs as a new process working in the shell's
+/// directory, in the foreground, where Ctrl-C on the console kills it; a
+/// PROGRAM given as a path, like ./prog, also runs without `run`
+fn run(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "run");
+    if cmd.args.len() < 2 {
+	kprint!("\nusage: run PROGRAM [ARG...]");
+	return;
+    }
+    start(&cmd.args.as_slice()[1..], shell, false);
+}
+
+/// time PROGRAM [ARG...]
+/// starts PROGRAM like run and, once it exits, prints the time it took, the
+/// CPU time of its threads, how often they were switched out, and the most
+/// memory its address space held
+fn time(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "time");
+    if cmd.args.len() < 2 {
+	kprint!("\nusage: time PROGRAM [ARG...]");
+	return;
+    }
+    start(&cmd.args.as_slice()[1..], shell, true);
+}
+
+/// Starts the program `argv[0]` in the foreground with the arguments `argv`
+/// and `PWD` set in its environment, reporting its usage when it exits if
+/// `timed`. The file the console is redirected to, if any, becomes the
+/// standard output and error of the program rather than of every process.
+fn start(argv: &[&str], shell: &mut Shell, timed: bool) {
+    use alloc::format;
+    let pwd = format!("PWD={}", shell.pwd.as_path().display());
+    let mut output = CONSOLE.lock().redirect(None);
+    let result = fat32::path::resolve(&shell.pwd, argv[0])
+	.map_err(OsError::from)
+	.and_then(Process::load)
+	.and_then(|mut process| {
+	    process.set_args(argv, &[&pwd])?;
+	    *process.cwd.lock() = shell.pwd.clone();
+	    if let Some(file) = output.take() {
+		*process.files.lock() = FdTable::with_output(OpenFile::new(file, false, true));
+	    }
+	    let usage = process.usage.clone();
+	    SCHEDULER.add(process).map(|pid| (pid, usage)).ok_or(OsError::NoMemory)
+	});
+    // the shell writes the file back as usual if the program did not start
+    if output.is_some() {
+	CONSOLE.lock().redirect(output);
+    }
+    match result {
+	Ok((pid, usage)) => {
+	    if timed {
+		let mut command = format!("[{}]", pid);
+		for arg in argv.iter() {
+		    command.push(' ');
+		    command.push_str(arg);
+		}
+		let started = pi::timer::current_time();
+		let report: ExitHook = Box::new(move || {
+		    let real = pi::timer::current_time().checked_sub(started).unwrap_or_default();
+		    let usage = usage.lock();
+		    kprintln!("\n{}: real {}.{:03}s, cpu {}.{:03}s, {} switches, {} KiB max",
+			      command, real.as_secs(), real.subsec_millis(), usage.cpu.as_secs(),
+			      usage.cpu.subsec_millis(), usage.switches, usage.peak_pages * PAGE_SIZE / 1024);
+		});
+		// a program that is done already is reported on right away
+		if let Err(report) = SCHEDULER.on_exit(pid, "time", report) {
+		    report();
+		}
+	    }
+	    console::set_foreground(pid);
+	    kprint!("\n[{}]", pid);
+	},
+	Err(e) => kprint!("\n{}: {}", argv[0], e),
+    }
+}
+
+/// mkdir PATH...
+/// creates each PATH as an empty directory
+fn make_directory(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "mkdir");
+    if cmd.args.len() < 2 {
+	kprint!("\nusage: mkdir PATH...");
+	return;
+    }
+    for arg in cmd.args.as_slice()[1..].iter() {
+	let result = fat32::path::resolve(&shell.pwd, arg)
+	    .and_then(|path| FILESYSTEM.create(&path, true));
+	if let Err(e) = result {
+	    kprint!("\n{}: {}: {}", cmd.args[0], arg, reason(e));
+	}
+    }
+}
+
+/// rm PATH...
+/// removes each PATH, a regular file or an empty directory
+fn remove(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "rm");
+    if cmd.args.len() < 2 {
+	kprint!("\nusage: rm PATH...");
+	return;
+    }
+    for arg in cmd.args.as_slice()[1..].iter() {
+	let result = fat32::path::resolve(&shell.pwd, arg)
+	    .and_then(|path| FILESYSTEM.remove(&path));
+	if let Err(e) = result {
+	    kprint!("\n{}: {}: {}", cmd.args[0], arg, reason(e));
 	}
     }
 }
 
+/// Largest block size `dd` accepts
+const DD_MAX_BLOCK: usize = 1 << 20;
+
+/// dd if=PATH of=PATH [bs=N] [count=N] [skip=N] [seek=N]
+/// copies COUNT blocks of BS bytes (512 by default; K and M suffixes) from
+/// block SKIP of the input to block SEEK of the output, or up to the end of
+/// the input without count. PATH is a file or a device: /dev/sd is the whole
+/// SD card, /dev/sd1 to /dev/sd4 its partitions and /dev/ramN a RAM disk
+fn dd(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "dd");
+    let usage = "\nusage: dd if=PATH of=PATH [bs=N] [count=N] [skip=N] [seek=N]";
+    let (mut input, mut output) = (None, None);
+    let (mut bs, mut count, mut skip, mut seek) = (512, None, 0, 0);
+    for arg in cmd.args.as_slice()[1..].iter() {
+	let mut split = arg.splitn(2, '=');
+	let (key, value) = match (split.next(), split.next()) {
+	    (Some(key), Some(value)) => (key, value),
+	    _ => {
+		kprint!("{}", usage);
+		return;
+	    },
+	};
+	let number = parse_size(value);
+	match (key, number) {
+	    ("if", _) => input = Some(value),
+	    ("of", _) => output = Some(value),
+	    ("bs", Some(n)) if n > 0 && n <= DD_MAX_BLOCK as u64 => bs = n as usize,
+	    ("count", Some(n)) => count = Some(n),
+	    ("skip", Some(n)) => skip = n,
+	    ("seek", Some(n)) => seek = n,
+	    _ => {
+		kprint!("{}", usage);
+		return;
+	    },
+	}
+    }
+    let (input, output) = match (input, output) {
+	(Some(input), Some(output)) => (input, output),
+	_ => {
+	    kprint!("{}", usage);
+	    return;
+	},
+    };
+
+    // devices are files in /dev
+    let open = |path: &str| FILESYSTEM.open_at(&shell.pwd, path).and_then(|entry| entry.open());
+    let mut input = match open(input) {
+	Ok(stream) => stream,
+	Err(e) => {
+	    kprint!("\n{}: {}: {}", cmd.args[0], input, reason(e));
+	    return;
+	},
+    };
+    let mut output = match open(output) {
+	Ok(stream) => stream,
+	Err(e) => {
+	    kprint!("\n{}: {}: {}", cmd.args[0], output, reason(e));
+	    return;
+	},
+    };
+
+    let mut block = vec![0u8; bs];
+    let (mut full, mut partial, mut bytes) = (0u64, 0u64, 0u64);
+    let mut copy = || -> io::Result<()> {
+	input.seek(SeekFrom::Start(skip * bs as u64))?;
+	output.seek(SeekFrom::Start(seek * bs as u64))?;
+	while count.map_or(true, |count| full + partial < count) {
+	    // files and devices may return less than asked for before the end
+	    let mut n = 0;
+	    while n < bs {
+		match input.read(&mut block[n..])? {
+		    0 => break,
+		    read => n += read,
+		}
+	    }
+	    if n == 0 {
+		break;
+	    }
+	    output.write_all(&block[..n])?;
+	    bytes += n as u64;
+	    if n == bs {
+		full += 1;
+	    }
+	    else {
+		partial += 1;
+	    }
+	}
+	output.flush()
+    };
+    let result = copy();
+    kprint!("\n{}+{} records, {} bytes copied", full, partial, bytes);
+    if let Err(e) = result {
+	kprint!("\n{}: {}", cmd.args[0], reason(e));
+    }
+}
+
+/// backup SRC DEST
+/// archives the directory tree SRC, as it is when the command starts, to the
+/// tar file DEST, which may be on another volume, then reads DEST back and
+/// checks the name, size and CRC-32 of every member against those written.
+/// DEST and the volumes mounted below SRC are left out of the archive
+fn backup(cmd: &Command, shell: &mut Shell) {
+    use alloc::string::String;
+    use filexfer::tar::{Kind, Reader, Writer};
+    use crate::fs::walk::walk;
+
+    assert_eq!(cmd.args[0], "backup");
+    if cmd.args.len() != 3 {
+	kprint!("\nusage: backup SRC DEST");
+	return;
+    }
+    let resolve = |arg: &str| fat32::path::resolve(&shell.pwd, arg);
+    let (src, dest) = match (resolve(cmd.args[1]), resolve(cmd.args[2])) {
+	(Ok(src), Ok(dest)) => (src, dest),
+	(Err(e), _) | (_, Err(e)) => {
+	    kprint!("\n{}: {}", cmd.args[0], reason(e));
+	    return;
+	},
+    };
+
+    // name, kind, size and CRC-32 of each member, as written
+    let mut written: Vec<(String, Kind, u64, u32)> = Vec::new();
+    let mut archive = || -> io::Result<()> {
+	let entries = walk(&src)?;
+	let mut file = match FILESYSTEM.open_file(&dest) {
+	    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+		FILESYSTEM.create(&dest, false)?;
+		FILESYSTEM.open_file(&dest)?
+	    },
+	    result => result?,
+	};
+	file.truncate()?;
+
+	let mut tar = Writer::new(file);
+	for entry in entries.iter() {
+	    let path = src.join(&entry.path);
+	    if path == dest {
+		continue;
+	    }
+	    let name = entry.path.to_str()
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "name is not UTF-8"))?;
+	    let mtime = entry.attr.modified.unix();
+	    if entry.attr.directory {
+		tar.append_dir(name, mtime)?;
+		written.push((name.into(), Kind::Directory, 0, 0));
+	    }
+	    else {
+		// the size listed by the walk is archived, even if the file grew
+		let crc = tar.append_file(name, entry.attr.size, mtime, &mut FILESYSTEM.open_file(&path)?)?;
+		written.push((name.into(), Kind::File, entry.attr.size, crc));
+	    }
+	}
+	tar.finish()?.sync()
+    };
+    if let Err(e) = archive() {
+	kprint!("\n{}: {}: {}", cmd.args[0], cmd.args[2], reason(e));
+	return;
+    }
+
+    // returns the first member that differs from what was written
+    let verify = || -> io::Result<Option<String>> {
+	let mut tar = Reader::new(FILESYSTEM.open_file(&dest)?);
+	for (name, kind, size, crc) in written.iter() {
+	    match tar.next_member()? {
+		Some((ref header, read_crc)) if header.name == *name && header.kind == *kind
+		    && header.size == *size && read_crc == *crc => {},
+		_ => return Ok(Some(name.clone())),
+	    }
+	}
+	Ok(tar.next_member()?.map(|(header, _)| header.name))
+    };
+    match verify() {
+	Ok(None) => {
+	    let files = written.iter().filter(|member| member.1 == Kind::File).count();
+	    let bytes: u64 = written.iter().map(|member| member.2).sum();
+	    kprint!("\n{} directories, {} files, {} KiB archived and verified",
+		    written.len() - files, files, (bytes + 1023) / 1024);
+	},
+	Ok(Some(name)) => kprint!("\n{}: {}: {} differs in the archive", cmd.args[0], cmd.args[2], name),
+	Err(e) => kprint!("\n{}: {}: {}", cmd.args[0], cmd.args[2], reason(e)),
+    }
+}
+
+/// ramdisk [create SIZE | destroy N]
+/// creates a RAM disk of SIZE bytes (K and M suffixes) rounded up to whole
+/// sectors, /dev/ramN, with one partition to format with mkfs, or destroys
+/// RAM disk N. lists the RAM disks without arguments
+fn ramdisk(cmd: &Command) {
+    use crate::fs::dev::{RamDisk, MAX_RAM_DISKS};
+    assert_eq!(cmd.args[0], "ramdisk");
+    let usage = "\nusage: ramdisk [create SIZE | destroy N]";
+    match cmd.args.as_slice() {
+	[_] => {
+	    for n in 0..MAX_RAM_DISKS {
+		if let Some(ram) = RamDisk::open(n) {
+		    kprint!("\nram{}: {} sectors ({} KiB)", n, ram.sectors(), ram.sectors() / 2);
+		}
+	    }
+	},
+	[_, "create", size] => {
+	    let sectors = match parse_size(size) {
+		Some(size) => (size + 511) / 512,
+		None => {
+		    kprint!("{}", usage);
+		    return;
+		},
+	    };
+	    match RamDisk::create(sectors) {
+		Ok(ram) => kprint!("\nram{}: {} sectors", ram.number(), sectors),
+		Err(e) => kprint!("\n{}: {}", cmd.args[0], reason(e)),
+	    }
+	},
+	[_, "destroy", n] => {
+	    let ram = match usize::from_str(n).ok().and_then(RamDisk::open) {
+		Some(ram) => ram,
+		None => {
+		    kprint!("\n{}: no RAM disk {}", cmd.args[0], n);
+		    return;
+		},
+	    };
+	    if FILESYSTEM.is_mounted(&Source::Ram(ram.number())) {
+		kprint!("\n{}: ram{} is mounted", cmd.args[0], ram.number());
+		return;
+	    }
+	    ram.destroy();
+	},
+	_ => kprint!("{}", usage),
+    }
+}
+
+/// iostat [-z]
+/// prints the transfers of each block device that was used since boot or the
+/// last iostat -z, which clears them: counts, sizes, errors, the deepest queue
+/// and the latencies that 50, 90 and 99 percent of transfers stayed under
+fn iostat(cmd: &Command) {
+    use crate::fs::iostat::{self, Histogram};
+    assert_eq!(cmd.args[0], "iostat");
+    match cmd.args.len() {
+	1 => {},
+	2 if cmd.args[1] == "-z" => {
+	    iostat::reset();
+	    return;
+	},
+	_ => {
+	    kprint!("\nusage: iostat [-z]");
+	    return;
+	},
+    }
+
+    let latency = |name: &str, histogram: &Histogram| {
+	kprint!("\n  {} latency (us):", name);
+	for &percent in [50, 90, 99].iter() {
+	    match histogram.percentile(percent) {
+		Some(micros) => kprint!(" p{} <{}", percent, micros),
+		None => kprint!(" p{} -", percent),
+	    }
+	}
+    };
+    iostat::for_each(|device, stats| {
+	if stats.is_idle() {
+	    return;
+	}
+	kprint!("\n{}: {} reads ({} KiB), {} writes ({} KiB), {} errors, queue depth {}", device,
+		stats.reads, stats.read_bytes / 1024, stats.writes, stats.written_bytes / 1024,
+		stats.errors, stats.max_queue_depth);
+	latency("read", &stats.read_latency);
+	latency("write", &stats.write_latency);
+    });
+}
+
+/// parses a RAM disk name, ramN
+fn parse_ram_disk(s: &str) -> Option<usize> {
+    match s.starts_with("ram") {
+	true => usize::from_str(&s["ram".len()..]).ok(),
+	false => None,
+    }
+}
+
+/// parses seconds with an optional fraction of up to nine digits, like 2 or
+/// 0.25
+fn parse_seconds(s: &str) -> Option<Duration> {
+    let mut parts = s.splitn(2, '.');
+    let secs = match parts.next()? {
+	"" => 0,
+	secs => u64::from_str(secs).ok()?,
+    };
+    let nanos = match parts.next() {
+	None => 0,
+	Some(frac) => {
+	    if frac.is_empty() || frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	    }
+	    u32::from_str(frac).ok()? * 10u32.pow(9 - frac.len() as u32)
+	},
+    };
+    Some(Duration::new(secs, nanos))
+}
+
+/// parses a decimal number with an optional K (1024) or M (1024 * 1024) suffix
+fn parse_size(s: &str) -> Option<u64> {
+    let (digits, scale) = match s.as_bytes().last() {
+	Some(b'k') | Some(b'K') => (&s[..s.len() - 1], 1 << 10),
+	Some(b'M') => (&s[..s.len() - 1], 1 << 20),
+	_ => (s, 1),
+    };
+    u64::from_str(digits).ok()?.checked_mul(scale)
+}
+
+/// Returns what to print for `e`: the message it carries, such as "file
+/// shrank while archived", or else the description of its `OsError`.
+fn reason(e: io::Error) -> alloc::string::String {
+    match e.get_ref() {
+	Some(message) => message.clone(),
+	None => OsError::from(e).message().into(),
+    }
+}
+
+/// qemu exit CODE | qemu dump ADDR LEN PATH
+/// ends QEMU with exit status CODE, or writes LEN bytes (K and M suffixes)
+/// of memory at the hexadecimal physical address ADDR to PATH on the host.
+/// only kernels built with the qemu-test feature can do either
+fn qemu(cmd: &Command) {
+    assert_eq!(cmd.args[0], "qemu");
+    let args = cmd.args.as_slice();
+    match args {
+	[_, "exit", code] => match u32::from_str(code) {
+	    Ok(code) => {
+		crate::qemu::test_exit(code);
+		kprint!("\nqemu: not built with the qemu-test feature");
+	    },
+	    Err(_) => kprint!("\nqemu: invalid exit code"),
+	},
+	[_, "dump", addr, len, path] => {
+	    let addr = usize::from_str_radix(addr.trim_start_matches("0x"), 16).ok();
+	    match (addr, parse_size(len)) {
+		(Some(addr), Some(len)) => {
+		    if !unsafe { crate::qemu::dump(path, addr, len as usize) } {
+			kprint!("\nqemu: dump failed");
+		    }
+		},
+		_ => kprint!("\nqemu: invalid address or length"),
+	    }
+	},
+	_ => kprint!("\nusage: qemu exit CODE | qemu dump ADDR LEN PATH"),
+    }
+}
+
+/// log [SINK LEVEL|time|notime|color|nocolor]
+/// lists the sinks kernel messages go to, or changes the most verbose level
+/// SINK takes (off, error, warn, info, debug or trace) or how it formats them
+fn log_sinks(cmd: &Command) {
+    use crate::logger;
+    use log::LevelFilter;
+    assert_eq!(cmd.args[0], "log");
+    match cmd.args.len() {
+	1 => {
+	    for sink in logger::sinks() {
+		kprint!("\n{:8} {:5}{}{}", sink.name, sink.level,
+			if sink.format.timestamp { " time" } else { "" },
+			if sink.format.color { " color" } else { "" });
+	    }
+	},
+	3 => {
+	    let sink = match logger::sinks().into_iter().find(|sink| sink.name == cmd.args[1]) {
+		Some(sink) => sink,
+		None => {
+		    kprint!("\nlog: no sink {}", cmd.args[1]);
+		    return;
+		},
+	    };
+	    let (mut level, mut format) = (sink.level, sink.format);
+	    match cmd.args[2] {
+		"time" => format.timestamp = true,
+		"notime" => format.timestamp = false,
+		"color" => format.color = true,
+		"nocolor" => format.color = false,
+		arg => match LevelFilter::from_str(arg) {
+		    Ok(filter) => level = filter,
+		    Err(_) => {
+			kprint!("\nlog: unknown level or format {}", arg);
+			return;
+		    },
+		},
+	    }
+	    logger::configure(sink.name, level, format);
+	},
+	_ => kprint!("\nusage: log [SINK LEVEL|time|notime|color|nocolor]"),
+    }
+}
+
+/// ktrace [on|off|clear|dump]
+/// shows whether thread switches, interrupts and system calls are traced,
+/// starts or stops tracing them, forgets those traced, or dumps them in
+/// binary for tracevis, over the serial line unless redirected to a file
+fn ktrace(cmd: &Command) {
+    use crate::ktrace;
+    assert_eq!(cmd.args[0], "ktrace");
+    match cmd.args.as_slice() {
+	[_] => {
+	    let (enabled, events, dropped) = ktrace::status();
+	    kprint!("\ntracing {}, {} events, {} dropped", if enabled { "on" } else { "off" }, events, dropped);
+	},
+	[_, "on"] => ktrace::start(),
+	[_, "off"] => ktrace::stop(),
+	[_, "clear"] => ktrace::clear(),
+	[_, "dump"] => {
+	    // the dump starts on a line of its own, after the echoed command
+	    kprintln!("");
+	    if let Err(e) = ktrace::dump(&mut *CONSOLE.lock()) {
+		kprint!("\nktrace: {}", reason(e));
+	    }
+	},
+	_ => kprint!("\nusage: ktrace [on|off|clear|dump]"),
+    }
+}
+
+/// rng [reseed|seed N]
+/// shows where the kernel's random numbers are seeded from, reseeds them
+/// from the hardware, or seeds them from N so that they repeat
+fn rng(cmd: &Command) {
+    use crate::rng::{self, Seed};
+    assert_eq!(cmd.args[0], "rng");
+    let seed = match cmd.args.as_slice() {
+	[_] => rng::status(),
+	[_, "reseed"] => rng::reseed(),
+	[_, "seed", n] => match n.parse::<u64>() {
+	    Ok(n) => {
+		rng::seed(n);
+		rng::status()
+	    },
+	    Err(_) => {
+		kprint!("\nrng: {} is not a number", n);
+		return;
+	    },
+	},
+	_ => {
+	    kprint!("\nusage: rng [reseed|seed N]");
+	    return;
+	},
+    };
+    match seed {
+	Seed::Hardware => kprint!("\nseeded from the hardware RNG"),
+	Seed::Timer => kprint!("\nseeded from the timer, the hardware RNG produced nothing"),
+	Seed::Fixed(n) => kprint!("\nfixed seed {}", n),
+    }
+}
+
+/// vmstat [--audit]
+/// prints the pages mapped by the kernel and all processes, and with
+/// --audit the page table entries that break an invariant. debug builds
+/// also check that no released frame was written to
+fn vmstat(cmd: &Command) {
+    use crate::param::PAGE_SIZE;
+    use crate::VMM;
+    assert_eq!(cmd.args[0], "vmstat");
+    let audit = match cmd.args.len() {
+	1 => false,
+	2 if cmd.args[1] == "--audit" => true,
+	_ => {
+	    kprint!("\nusage: vmstat [--audit]");
+	    return;
+	},
+    };
+
+    let stats = VMM.stats();
+    let kib = |pages: usize| pages * PAGE_SIZE / 1024;
+    kprint!("\nkernel pages: {:8} ({} KiB)", stats.kernel_pages, kib(stats.kernel_pages));
+    kprint!("\nuser pages:   {:8} ({} KiB)", stats.user_pages, kib(stats.user_pages));
+    kprint!("\ndevice pages: {:8} ({} KiB)", stats.device_pages, kib(stats.device_pages));
+    kprint!("\nshared pages: {:8} ({} KiB)", stats.shared_pages, kib(stats.shared_pages));
+    kprint!("\ncow pages:    {:8} ({} KiB)", stats.cow_pages, kib(stats.cow_pages));
+    kprint!("\nblock maps:   {:8}", stats.blocks);
+    kprint!("\npage tables:  {:8} ({} KiB)", stats.tables, stats.table_bytes / 1024);
+
+    if audit {
+	// panics if a released frame was written to
+	#[cfg(debug_assertions)]
+	crate::vm::frame::check();
+	let issues = VMM.audit();
+	kprint!("\naudit: {} issues", issues.len());
+	for issue in issues.iter() {
+	    kprint!("\n  {}", issue);
+	}
+    }
+}
+
+/// bootstat
+/// prints when boot reached each of its phases, counted from power-on, with
+/// the time each took, and the extent of each section of the kernel image
+/// and of the whole image, padding included
+fn bootstat(cmd: &Command) {
+    use crate::bootstat::{reached, sections, Phase};
+    assert_eq!(cmd.args[0], "bootstat");
+    if cmd.args.len() != 1 {
+	kprint!("\nusage: bootstat");
+	return;
+    }
+
+    let mut previous: Option<Duration> = None;
+    for phase in Phase::ALL.iter() {
+	match reached(*phase) {
+	    Some(at) => {
+		kprint!("\n{:16} {:5}.{:06}s", phase.name(), at.as_secs(), at.subsec_micros());
+		if let Some(since) = previous.and_then(|previous| at.checked_sub(previous)) {
+		    kprint!("  +{}.{:03}ms", since.as_millis(), since.subsec_micros() % 1000);
+		}
+		previous = Some(at);
+	    },
+	    None => kprint!("\n{:16} not reached", phase.name()),
+	}
+    }
+
+    let sections = sections();
+    for section in sections.iter() {
+	kprint!("\n{:16} {:#010x}..{:#010x} {:8} KiB", section.name, section.start, section.end, section.size() / 1024);
+    }
+    let (start, end) = (sections[0].start, sections[sections.len() - 1].end);
+    kprint!("\n{:16} {:#010x}..{:#010x} {:8} KiB", "image", start, end, (end - start) / 1024);
+}
+
+/// vmmap PID
+/// lists the ranges the page table of process PID maps, with their
+/// permissions and memory attributes
+fn vmmap(cmd: &Command) {
+    use crate::SCHEDULER;
+    assert_eq!(cmd.args[0], "vmmap");
+    let pid = match cmd.args.len() {
+	2 => u64::from_str(cmd.args[1]),
+	_ => {
+	    kprint!("\nusage: vmmap PID");
+	    return;
+	},
+    };
+    match pid.ok().and_then(|pid| SCHEDULER.process(pid)) {
+	Some(process) => kprint!("\n{:?}", *process.vmap.lock()),
+	None => kprint!("\nvmmap: no process {}", cmd.args[1]),
+    }
+}
+
+/// checkpoint PID PATH
+/// saves the registers and memory of the stopped process PID to PATH, from
+/// which restore can resume it on this kernel build. experimental
+fn checkpoint(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "checkpoint");
+    if cmd.args.len() != 3 {
+	kprint!("\nusage: checkpoint PID PATH");
+	return;
+    }
+    let pid = match u64::from_str(cmd.args[1]) {
+	Ok(pid) => pid,
+	Err(_) => {
+	    kprint!("\ncheckpoint: no process {}", cmd.args[1]);
+	    return;
+	},
+    };
+    // the image is taken under the scheduler lock, so it is written out after
+    let mut image = Vec::new();
+    if let Err(e) = SCHEDULER.checkpoint(pid, &mut image) {
+	kprint!("\ncheckpoint: {}", e);
+	return;
+    }
+    let saved = fat32::path::resolve(&shell.pwd, cmd.args[2])
+	.and_then(|path| FILESYSTEM.write_atomic(path, &image));
+    if let Err(e) = saved {
+	kprint!("\ncheckpoint: {}", reason(e));
+    }
+}
+
+/// restore PATH
+/// resumes the process saved to PATH by checkpoint and prints its new ID
+fn restore(cmd: &Command, shell: &mut Shell) {
+    assert_eq!(cmd.args[0], "restore");
+    if cmd.args.len() != 2 {
+	kprint!("\nusage: restore PATH");
+	return;
+    }
+    let restored = fat32::path::resolve(&shell.pwd, cmd.args[1])
+	.map_err(OsError::from)
+	.and_then(|path| SCHEDULER.restore(path));
+    match restored {
+	Ok(pid) => kprint!("\n{}", pid),
+	Err(e) => kprint!("\nrestore: {}", e),
+    }
+}
+
+/// hz [N]
+/// prints the timer interrupts per second and the ticks since boot, or sets
+/// the rate to N
+fn hz(cmd: &Command) {
+    use crate::clock;
+    use crate::param::{HZ_MAX, HZ_MIN};
+    assert_eq!(cmd.args[0], "hz");
+    match cmd.args.len() {
+	1 => kprint!("\n{} Hz, {} ticks since boot", clock::hz(), clock::ticks()),
+	2 => match u64::from_str(cmd.args[1]) {
+	    Ok(hz) if clock::set_hz(hz) => {},
+	    _ => kprint!("\nhz: N must be within {}..={}", HZ_MIN, HZ_MAX),
+	},
+	_ => kprint!("\nusage: hz [N]"),
+    }
+}
+
+/// quantum [N]
+/// prints the timer interrupts a process runs for before it is preempted,
+/// or sets that to N from the next switch on
+fn quantum(cmd: &Command) {
+    use crate::param::QUANTUM_MAX;
+    use crate::process;
+    assert_eq!(cmd.args[0], "quantum");
+    match cmd.args.len() {
+	1 => kprint!("\n{} ticks", process::quantum()),
+	2 => match u64::from_str(cmd.args[1]) {
+	    Ok(ticks) if process::set_quantum(ticks) => {},
+	    _ => kprint!("\nquantum: N must be within 1..={}", QUANTUM_MAX),
+	},
+	_ => kprint!("\nusage: quantum [N]"),
+    }
+}
+
+/// renice NICE PID
+/// sets the nice level of process PID to NICE, from -20, which runs soonest,
+/// to 19
+fn renice(cmd: &Command) {
+    assert_eq!(cmd.args[0], "renice");
+    if cmd.args.len() != 3 {
+	kprint!("\nusage: renice NICE PID");
+	return;
+    }
+    let nice = match i64::from_str(cmd.args[1]) {
+	Ok(nice) if nice >= NICE_MIN && nice <= NICE_MAX => nice,
+	_ => {
+	    kprint!("\nrenice: NICE must be within {}..={}", NICE_MIN, NICE_MAX);
+	    return;
+	},
+    };
+    let found = u64::from_str(cmd.args[2]).ok()
+	.filter(|&pid| pid != 0)
+	.map_or(false, |pid| SCHEDULER.critical(|scheduler| scheduler.set_nice(pid, nice)));
+    if !found {
+	kprint!("\nrenice: no process {}", cmd.args[2]);
+    }
+}
+
+/// led [PATTERN]
+/// prints how the status LED is wired and its heartbeat, or sets the
+/// heartbeat to PATTERN, a 1 for every lit and a 0 for every dark step
+fn led(cmd: &Command) {
+    use crate::led::{self, Pattern};
+    assert_eq!(cmd.args[0], "led");
+    match cmd.args.len() {
+	1 => kprint!("\n{}, heartbeat {}", led::wiring(), led::heartbeat()),
+	2 => match Pattern::parse(cmd.args[1]) {
+	    Some(pattern) => led::set_heartbeat(pattern),
+	    None => kprint!("\nled: PATTERN must be 1 to {} steps of 0 or 1", Pattern::MAX_STEPS),
+	},
+	_ => kprint!("\nusage: led [PATTERN]"),
+    }
+}
+
+/// date [SECONDS]
+/// prints the time in UTC, or sets it to SECONDS since the Unix epoch. files
+/// are only stamped with times once it is set
+fn date(cmd: &Command) {
+    use crate::clock;
+    use fat32::traits::Timestamp as _;
+    use fat32::vfat::Timestamp;
+    assert_eq!(cmd.args[0], "date");
+    match cmd.args.len() {
+	1 => match clock::unix_time() {
+	    Some(secs) => {
+		let ts = Timestamp::from_unix(secs);
+		kprint!("\n{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", ts.year(), ts.month(), ts.day(),
+			secs / 3600 % 24, secs / 60 % 60, secs % 60);
+	    },
+	    None => kprint!("\ndate: time not set"),
+	},
+	2 => match u64::from_str(cmd.args[1]) {
+	    Ok(secs) if secs > 0 => clock::set_unix_time(secs),
+	    _ => kprint!("\ndate: SECONDS must be a positive number"),
+	},
+	_ => kprint!("\nusage: date [SECONDS]"),
+    }
+}
+
+/// uname [-a]
+/// prints the kernel name, or with -a the release, machine, board and
+/// system statistics too
+fn uname(cmd: &Command) {
+    assert_eq!(cmd.args[0], "uname");
+    let all = match cmd.args.len() {
+	1 => false,
+	2 if cmd.args[1] == "-a" => true,
+	_ => {
+	    kprint!("\nusage: uname [-a]");
+	    return;
+	},
+    };
+
+    let name = crate::sysinfo::uname();
+    if !all {
+	kprint!("\n{}", name.sysname());
+	return;
+    }
+    kprint!("\n{} {} {} {} (rev {:06x})", name.sysname(), name.release(), name.machine(), name.board(), name.revision);
+    let info = crate::sysinfo::sysinfo();
+    kprint!("\nup {} s, {} MiB RAM, {} KiB heap, {} KiB user", info.uptime, info.total_ram >> 20, info.heap >> 10, info.user_memory >> 10);
+    kprint!("\n{} processes, {} threads, {}/{} cores online", info.processes, info.threads, info.online_cores, info.cores);
+}
+
+/// drivers
+/// lists the optional drivers built in, with the cargo feature of each and
+/// whether it started
+fn drivers(cmd: &Command) {
+    use crate::driver;
+    assert_eq!(cmd.args[0], "drivers");
+    if cmd.args.len() != 1 {
+	kprint!("\nusage: drivers");
+	return;
+    }
+    let drivers = driver::drivers();
+    if drivers.is_empty() {
+	kprint!("\nno optional drivers built in");
+    }
+    for (driver, status) in drivers {
+	kprint!("\n{:12} {:6} ", driver.name, driver.feature);
+	match status {
+	    None => kprint!("not started"),
+	    Some(Ok(())) => kprint!("started"),
+	    Some(Err(reason)) => kprint!("failed: {}", reason),
+	}
+    }
+}
+
+/// fb [fill RRGGBB]
+/// prints the size, depth and address of the framebuffer, or fills it with
+/// the color RRGGBB in hex
+#[cfg(feature = "gfx")]
+fn framebuffer(cmd: &Command) {
+    use crate::gfx::DISPLAY;
+    assert_eq!(cmd.args[0], "fb");
+    let mut display = DISPLAY.lock();
+    let display = match display.as_mut() {
+	Some(display) => display,
+	None => {
+	    kprint!("\nfb: no framebuffer, see drivers");
+	    return;
+	},
+    };
+    let fb = display.info();
+    match cmd.args.as_slice() {
+	[_] => kprint!("\n{}x{}, {} bits per pixel, {} bytes a row, at {:#x}",
+		       fb.width, fb.height, fb.depth, fb.pitch, fb.addr),
+	[_, "fill", color] => match u32::from_str_radix(color, 16) {
+	    Ok(color) if color <= 0xff_ffff => display.fill(0, 0, fb.width, fb.height, color),
+	    _ => kprint!("\nfb: {} is not a color RRGGBB", color),
+	},
+	_ => kprint!("\nusage: fb [fill RRGGBB]"),
+    }
+}
+
+/// beep [HZ [MILLISECONDS]]
+/// plays a tone of HZ, 880 by default, for MILLISECONDS, 200 by default, on
+/// the headphone jack. a key press ends it early
+#[cfg(feature = "sound")]
+fn beep(cmd: &Command) {
+    use crate::param::{BEEP_HZ, BEEP_TIME};
+    use crate::sound;
+    assert_eq!(cmd.args[0], "beep");
+    let hz = cmd.args.get(1).map_or(Ok(BEEP_HZ), |arg| arg.parse::<u32>());
+    let time = cmd.args.get(2).map_or(Ok(BEEP_TIME), |arg| arg.parse::<u64>().map(Duration::from_millis));
+    match (hz, time) {
+	(Ok(hz), Ok(time)) if cmd.args.len() <= 3 && hz > 0 => {
+	    match sound::beep(hz, time) {
+		Some(true) => {},
+		Some(false) => {
+		    CONSOLE.lock().read_byte();
+		},
+		None => kprint!("\nbeep: no sound, see drivers"),
+	    }
+	},
+	_ => kprint!("\nusage: beep [HZ [MILLISECONDS]]"),
+    }
+}
+
+/// heap [--check]
+/// lists the live heap allocations and their call sites, or with --check
+/// verifies the redzones of all of them
+#[cfg(feature = "heap-guard")]
+fn heap(cmd: &Command) {
+    assert_eq!(cmd.args[0], "heap");
+    kprintln!("");
+    match cmd.args.len() {
+	1 => ALLOCATOR.dump_live(),
+	2 if cmd.args[1] == "--check" => {
+	    if ALLOCATOR.check_live() {
+		kprint!("heap redzones intact");
+	    }
+	},
+	_ => kprint!("usage: heap [--check]"),
+    }
+}
+
 // TODO: THIS IS FOR DEBUGGING AND SHOULD NOT REMAIN
 fn panic() {
     unreachable!();
@@ -311,6 +1688,7 @@ pub fn shell(prefix: &str) {
     let mut buf = StackVec::new(&mut buff_backing);
 
     session.new_line(prefix);
+    crate::bootstat::mark(crate::bootstat::Phase::FirstPrompt);
     
     loop {
 	let mut console = CONSOLE.lock();
@@ -323,8 +1701,20 @@ pub fn shell(prefix: &str) {
 		let mut cmd_backing: [&str; 64] = [""; 64];
 		let command = Command::parse(str::from_utf8(buf.as_slice()).unwrap(),&mut cmd_backing);		
 		match command {
-		    Ok(cmd) => {
-			execute(&cmd, &mut session);
+		    Ok(mut cmd) => {
+			match redirection(&mut cmd, &session) {
+			    None => execute(&cmd, &mut session),
+			    Some(Ok(file)) => {
+				let previous = CONSOLE.lock().redirect(Some(file));
+				execute(&cmd, &mut session);
+				if let Some(mut file) = CONSOLE.lock().redirect(previous) {
+				    if let Err(e) = file.flush().and_then(|_| file.sync()) {
+					kprint!("\n{}: {}", cmd.path(), reason(e));
+				    }
+				}
+			    },
+			    Some(Err(e)) => kprint!("\n{}: {}", cmd.path(), reason(e)),
+			}
 			if !session.active {
 			    break;
 			}
@@ -348,6 +1738,18 @@ pub fn shell(prefix: &str) {
 		}
 	    },
 
+	    // the host asking for the file server, see `fileserver`
+	    byte if byte == filexfer::ESCAPE[0] => {
+		drop(console);
+		match fileserver::serve(&session.pwd) {
+		    true => {
+			buf = StackVec::new(&mut buff_backing);
+			session.new_line(prefix);
+		    },
+		    false => CONSOLE.lock().write_byte(BELL),
+		}
+	    },
+
 	    // non printable char enteered to command line
 	    byte if (byte < 32) => {
 		console.write_byte(BELL);
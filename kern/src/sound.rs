@@ -0,0 +1,32 @@
+//! Tones on the headphone jack, built in by the `sound` feature and started
+//! through the driver registry. The PWM plays square waves only; there is
+//! no sample playback.
+
+use core::time::Duration;
+
+use pi::pwm::Pwm;
+
+use crate::clock;
+use crate::mutex::Mutex;
+
+/// The PWM, once `initialize()` clocked it
+static SPEAKER: Mutex<Option<Pwm>> = Mutex::new(None);
+
+/// Routes the PWM to the headphone jack and clocks it.
+pub fn initialize() -> Result<(), &'static str> {
+    let pwm = Pwm::new().ok_or("the PWM clock did not stop to be set up")?;
+    *SPEAKER.lock() = Some(pwm);
+    Ok(())
+}
+
+/// Plays a tone of `hz` for `duration`, or until a key is pressed, waiting
+/// as `clock::wait()` does. Returns whether the whole `duration` passed, or
+/// `None` if the driver did not start.
+pub fn beep(hz: u32, duration: Duration) -> Option<bool> {
+    SPEAKER.lock().as_mut()?.tone(hz);
+    let done = clock::wait(duration);
+    if let Some(pwm) = SPEAKER.lock().as_mut() {
+	pwm.stop();
+    }
+    Some(done)
+}
@@ -0,0 +1,79 @@
+
+use pi::timer::current_time;
+
+use kernel_api::{Sysinfo, Utsname};
+
+use crate::allocator::memory_map;
+use crate::param::{NCORES, PAGE_SIZE};
+use crate::percore::online_cores;
+use crate::{bootargs, ALLOCATOR, SCHEDULER, VMM};
+
+/// Command line keys the firmware passes the board revision under, one per
+/// SoC generation
+const REVISION_KEYS: [&str; 3] = ["bcm2710.boardrev", "bcm2709.boardrev", "bcm2708.boardrev"];
+
+/// Copies `src` into the NUL padded `dst`, cutting it to fit.
+fn fill(dst: &mut [u8], src: &str) {
+    let len = core::cmp::min(dst.len(), src.len());
+    dst[..len].copy_from_slice(&src.as_bytes()[..len]);
+    for byte in dst[len..].iter_mut() {
+	*byte = 0;
+    }
+}
+
+/// Returns the revision code of the board, 0 if the firmware did not pass one.
+pub fn board_revision() -> u32 {
+    REVISION_KEYS.iter()
+	.filter_map(|key| bootargs::get(key))
+	.filter_map(|rev| u32::from_str_radix(rev.trim_start_matches("0x"), 16).ok())
+	.next()
+	.unwrap_or(0)
+}
+
+/// Returns the model named by a new style revision code.
+pub fn board_model(revision: u32) -> &'static str {
+    if revision & (1 << 23) == 0 {
+	return "Raspberry Pi";
+    }
+    match (revision >> 4) & 0xff {
+	0x04 => "Raspberry Pi 2 Model B",
+	0x08 => "Raspberry Pi 3 Model B",
+	0x0a => "Raspberry Pi Compute Module 3",
+	0x0d => "Raspberry Pi 3 Model B+",
+	0x0e => "Raspberry Pi 3 Model A+",
+	0x10 => "Raspberry Pi Compute Module 3+",
+	0x11 => "Raspberry Pi 4 Model B",
+	0x12 => "Raspberry Pi Zero 2 W",
+	_ => "Raspberry Pi",
+    }
+}
+
+/// Returns the names of the kernel and the board.
+pub fn uname() -> Utsname {
+    let revision = board_revision();
+    let mut name = Utsname::default();
+    fill(&mut name.sysname, "rustOS");
+    fill(&mut name.release, env!("CARGO_PKG_VERSION"));
+    fill(&mut name.machine, "aarch64");
+    fill(&mut name.board, board_model(revision));
+    name.revision = revision;
+    name
+}
+
+/// Returns statistics of the system.
+pub fn sysinfo() -> Sysinfo {
+    let snapshot = SCHEDULER.snapshot();
+    let processes = snapshot.len();
+    let threads: usize = snapshot.iter().map(|process| process.threads.len()).sum();
+    let heap = ALLOCATOR.bounds().map(|(start, end)| end - start).unwrap_or(0);
+    Sysinfo {
+	uptime: current_time().as_secs(),
+	total_ram: memory_map().map(|(_, end)| end).unwrap_or(0) as u64,
+	heap: heap as u64,
+	user_memory: (VMM.stats().user_pages * PAGE_SIZE) as u64,
+	processes: processes as u32,
+	threads: threads as u32,
+	cores: NCORES as u32,
+	online_cores: online_cores() as u32,
+    }
+}
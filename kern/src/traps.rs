@@ -8,10 +8,16 @@ pub use self::frame::TrapFrame;
 use pi::interrupt::{Controller, Interrupt};
 use pi::local_interrupt::{LocalController, LocalInterrupt};
 
-use crate::GLOBAL_IRQ;
+use crate::console::{kprintln_ratelimited, print_raw};
+use crate::deferred;
+use crate::ktrace;
+use crate::process::Process;
+use crate::rawfmt::StackBuf;
+use crate::vm::VirtualAddr;
+use crate::{GLOBAL_IRQ, SCHEDULER};
 use crate::shell::shell;
 
-use self::syndrome::Syndrome;
+use self::syndrome::{Fault, Syndrome};
 use self::syscall::handle_syscall;
 use crate::percore;
 use crate::traps::irq::IrqHandlerRegistry;
@@ -49,17 +55,79 @@ fn handle_synchronous(info: Info, esr: u32, tf: &mut TrapFrame) {
 	    shell("brk]");
 	},
 	Syndrome::Svc(n) => {
+	    ktrace::record(trace::Kind::SyscallEnter, n as u32);
 	    handle_syscall(n, tf);
+	    ktrace::record(trace::Kind::SyscallExit, n as u32);
+	},
+	// a page the program did not touch before is mapped, and the faulting
+	// instruction runs again
+	Syndrome::DataAbort { kind: Fault::Translation, .. } | Syndrome::InstructionAbort { kind: Fault::Translation, .. }
+	    if info.source == Source::LowerAArch64 && fault_in(unsafe { aarch64::FAR_EL1.get() }) => {
+	    tf.elr -= 4;
+	},
+	// a write to a page shared copy-on-write, which is copied first
+	Syndrome::DataAbort { kind: Fault::Permission, .. }
+	    if info.source == Source::LowerAArch64 && copy_on_write(unsafe { aarch64::FAR_EL1.get() }) => {
+	    tf.elr -= 4;
+	},
+	// a thread that overflows its stack would fault again right away
+	Syndrome::DataAbort { kind: Fault::Translation, .. }
+	    if info.source == Source::LowerAArch64 && Process::is_stack_guard(VirtualAddr::from(unsafe { aarch64::FAR_EL1.get() })) => {
+	    print_raw(StackBuf::new().push_str("stack overflow at ").push_hex(tf.elr - 4).push_str(", killing the process\n").as_str());
+	    let _ = SCHEDULER.kill_current(kernel_api::EXIT_KILLED, tf);
+	},
+	// the kernel touching a frame it released, in a debug build
+	#[cfg(debug_assertions)]
+	Syndrome::DataAbort { kind: Fault::Translation, .. }
+	    if info.source != Source::LowerAArch64 && crate::vm::frame::is_quarantined(unsafe { aarch64::FAR_EL1.get() } as usize) => {
+	    crate::vm::frame::touched(unsafe { aarch64::FAR_EL1.get() } as usize, (tf.elr - 4) as usize);
+	},
+	// a fault the kernel cannot handle tends to recur right away
+	syndrome => {
+	    crate::led::fault();
+	    kprintln_ratelimited!("unhandled exception {:?} at {:#x}", syndrome, tf.elr - 4);
 	},
-	_ => {},
     };
 }
 
+/// Maps the page holding `va` if it lies in a region of the current process
+/// that is mapped on demand. Returns `false` if the fault is the program's.
+fn fault_in(va: u64) -> bool {
+    let vmap = SCHEDULER.critical(|scheduler| scheduler.current().map(|process| process.vmap.clone()));
+    match vmap {
+	Some(vmap) => vmap.lock().fault_in(VirtualAddr::from(va)),
+	None => false,
+    }
+}
+
+/// Gives the current process its own copy of the page holding `va` if it is
+/// shared copy-on-write. Returns `false` if the fault is the program's.
+fn copy_on_write(va: u64) -> bool {
+    let vmap = SCHEDULER.critical(|scheduler| scheduler.current().map(|process| process.vmap.clone()));
+    match vmap {
+	Some(vmap) => vmap.lock().copy_on_write(VirtualAddr::from(va)),
+	None => false,
+    }
+}
+
 fn handle_irq(info: Info, esr: u32, tf: &mut TrapFrame) {
-    let controller = Controller::new();
-    for int in Interrupt::iter() {
+    let core = aarch64::affinity();
+    // the interrupts of the peripherals are routed to core 0, but their
+    // pending bits read the same on every core
+    if core == 0 {
+	let controller = Controller::new();
+	for int in Interrupt::iter() {
+	    if controller.is_pending(int) {
+		ktrace::record(trace::Kind::IrqEnter, int as u32);
+		GLOBAL_IRQ.invoke(int, tf);
+		ktrace::record(trace::Kind::IrqExit, int as u32);
+	    }
+	}
+    }
+    let controller = LocalController::new(core);
+    for int in LocalInterrupt::iter() {
 	if controller.is_pending(int) {
-	    GLOBAL_IRQ.invoke(int, tf);
+	    percore::local_irq().invoke(int, tf);
 	}
     }
 }
@@ -73,6 +141,16 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
     let elr = unsafe {aarch64::ELR_EL1.get() as u64};
     assert_eq!(tf.elr, elr);
     
+    // system calls and breakpoints run on behalf of the thread; everything
+    // else interrupts it, possibly while it holds a lock
+    let interrupting = match (info.kind, Syndrome::from(esr)) {
+	(Kind::Synchronous, Syndrome::Svc(_)) | (Kind::Synchronous, Syndrome::Brk(_)) => false,
+	_ => true,
+    };
+    if interrupting {
+	percore::enter_handler();
+    }
+
     match info.kind {
 	Kind::Synchronous => {
 	    handle_synchronous(info, esr, tf);
@@ -84,4 +162,11 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
 	Kind::SError => {}, 
     };
 
+    if interrupting {
+	percore::exit_handler();
+    }
+    // the core holds no lock on the way back to a thread
+    if info.source == Source::LowerAArch64 {
+	deferred::run();
+    }
 }
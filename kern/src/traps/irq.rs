@@ -33,6 +33,7 @@ impl GlobalIrq {
             Mutex::new(None),
             Mutex::new(None),
             Mutex::new(None),
+            Mutex::new(None),
         ])
     }
 }
@@ -76,6 +77,7 @@ impl Index<Interrupt> for GlobalIrq {
             Gpio2 => 5,
             Gpio3 => 6,
             Uart => 7,
+            Aux => 8,
         };
         &self.0[index]
     }
@@ -85,8 +87,7 @@ impl Index<LocalInterrupt> for LocalIrq {
     type Output = IrqHandlerMutex;
 
     fn index(&self, int: LocalInterrupt) -> &IrqHandlerMutex {
-        // Lab 5 1.C
-        unimplemented!("LocalInterrupt Index")
+        &self.0[int as usize]
     }
 }
 
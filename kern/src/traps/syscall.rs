@@ -1,19 +1,36 @@
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::sync::atomic::Ordering;
 use core::time::Duration;
 
 use pi::timer::current_time;
+use shim::io::{self, SeekFrom};
+use shim::path::PathBuf;
+#[cfg(feature = "net")]
 use smoltcp::wire::{IpAddress, IpEndpoint};
 
 use crate::console::{kprint, kprintln, CONSOLE};
-use crate::param::USER_IMG_BASE;
-use crate::process::{Process, State};
+use crate::fs::pipe;
+use crate::fs::vfs::LockKind;
+use crate::mutex::Mutex;
+use crate::param::{PAGE_MASK, PAGE_SIZE, USER_IMG_BASE};
+use crate::perf;
+use crate::process::{FdTable, OpenFile, Process, State};
 use crate::traps::TrapFrame;
-use crate::{ETHERNET, SCHEDULER};
+use crate::vm::{shm, PagePerm, UserPageTable, VirtualAddr};
+use crate::{FILESYSTEM, SCHEDULER};
+#[cfg(feature = "net")]
+use crate::ETHERNET;
 use kernel_api::*;
 
 /// Sleep for `ms` milliseconds.
 ///
 /// This system call takes one parameter: the number of milliseconds to sleep.
+/// Sleepers are woken on timer interrupts, so the sleep lasts up to one
+/// `clock::tick()` longer.
 ///
 /// In addition to the usual status value, this system call returns one
 /// parameter: the approximate true elapsed time from when `sleep` was called to
@@ -58,11 +75,12 @@ pub fn sys_time(tf: &mut TrapFrame) {
     tf.x[7] = OsError::Ok as u64;
 }
 
-/// Kills the current process.
+/// Kills the current process together with all of its threads.
 ///
-/// This system call does not take paramer and does not return any value.
-pub fn sys_exit(tf: &mut TrapFrame) {
-    SCHEDULER.kill(tf);
+/// This system call takes one parameter: the exit code, kept for the parent
+/// until it waits for the process with `waitpid`. It does not return.
+pub fn sys_exit(code: u64, tf: &mut TrapFrame) {
+    let _ = SCHEDULER.kill_current(code, tf);
 }
 
 /// Writes to console.
@@ -82,9 +100,1314 @@ pub fn sys_write(b: u8, tf: &mut TrapFrame) {
 /// In addition to the usual status value, this system call returns a
 /// parameter: the current process's ID.
 pub fn sys_getpid(tf: &mut TrapFrame) {
-    let pid: u64 = tf.tpidr;
-    tf.x[1] = pid;
-    tf.x[7] = OsError::Ok as u64;
+    match SCHEDULER.critical(|scheduler| scheduler.current().map(|process| process.pid)) {
+	Some(pid) => {
+	    tf.x[1] = pid;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	None => tf.x[7] = OsError::NoEntry as u64,
+    }
+}
+
+/// Returns the current thread's ID.
+///
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns a
+/// parameter: the current thread's ID.
+pub fn sys_gettid(tf: &mut TrapFrame) {
+    match SCHEDULER.critical(|scheduler| scheduler.current().map(|process| process.tid)) {
+	Some(tid) => {
+	    tf.x[0] = tid;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	None => tf.x[7] = OsError::NoEntry as u64,
+    }
+}
+
+/// Creates a new thread in the current process.
+///
+/// This system call takes three parameters: the address the thread starts
+/// executing at, an argument passed to the thread in `x0`, and the value of
+/// the thread's `TPIDR_EL0` register (its thread local storage pointer). If
+/// the pointer is 0 and the program has a `PT_TLS` segment, the kernel sets up
+/// a TLS block for the thread.
+///
+/// The thread runs on its own stack and shares the address space of the
+/// current process. It must finish with `thread_exit`; returning from the
+/// entry function faults.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the ID of the new thread.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoVmSpace`: The process has no free thread stack slot.
+/// - `OsError::NoMemory`: The scheduler ran out of thread IDs.
+pub fn sys_thread_create(entry: u64, arg: u64, tls: u64, tf: &mut TrapFrame) {
+    match SCHEDULER.spawn_thread(entry, arg, tls) {
+	Ok(tid) => {
+	    tf.x[0] = tid;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Creates a child process, a copy of the current one.
+///
+/// This system call does not take parameters. The child gets a copy of the
+/// address space, shared copy-on-write, the open files, which share their
+/// positions with the parent's, and the working directory and nice level.
+/// Of the threads only the calling one is copied; the child continues from
+/// the same point.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the process ID of the child in the parent, and 0 in the child.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoMemory`: The scheduler ran out of process IDs.
+pub fn sys_fork(tf: &mut TrapFrame) {
+    match SCHEDULER.fork(tf) {
+	Ok(pid) => {
+	    tf.x[0] = pid;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Replaces the program of the current process with the executable at a
+/// path, an ELF executable or a flat binary.
+///
+/// This system call takes six parameters: the address and the length of the
+/// path, then those of the argument vector and of the environment. Each of
+/// the two is an array of `UserStr`, and the length is the number of
+/// strings. On success it does
+/// not return: the other threads of the process are killed, the descriptors
+/// marked close-on-exec are closed, and the calling thread starts the new
+/// program at its entry point, with a fresh stack holding the arguments and
+/// the environment. See `Process::set_args()`. The process ID, the other
+/// open files and the working directory stay.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path or a string is not UTF-8 encoded,
+///   a string holds a NUL byte, or the file is an ELF file that is not a
+///   statically linked AArch64 executable.
+/// - `OsError::NoMemory`: The arguments and the environment take more than
+///   `ARG_MAX` bytes.
+/// - `OsError::NoEntry`: There is nothing at the path.
+/// - `OsError::NoVmSpace`: The flat binary does not fit below the vDSO page.
+pub fn sys_exec(va: usize, len: usize, argv: (usize, usize), envp: (usize, usize), tf: &mut TrapFrame) {
+    let result = user_path(va, len).and_then(|path| {
+	let argv = user_strings(argv.0, argv.1)?;
+	let envp = user_strings(envp.0, envp.1)?;
+	let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+	let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+	SCHEDULER.exec(path, &argv, &envp, tf)
+    });
+    if let Err(e) = result {
+	tf.x[7] = e as u64;
+    }
+}
+
+/// Copies the `count` strings of the array of `UserStr` at `va` out of user
+/// memory.
+///
+/// Returns `BadAddress` if the array is misaligned or it or a string is not
+/// in user memory, `InvalidArgument` if a string is not UTF-8 encoded, and
+/// `NoMemory` if the strings take more than `ARG_MAX` bytes.
+fn user_strings(va: usize, count: usize) -> OsResult<Vec<String>> {
+    if count == 0 {
+	return Ok(Vec::new());
+    }
+    let size = count.checked_mul(core::mem::size_of::<UserStr>()).ok_or(OsError::BadAddress)?;
+    if size > ARG_MAX {
+	return Err(OsError::NoMemory);
+    }
+    if va % core::mem::align_of::<UserStr>() != 0 {
+	return Err(OsError::BadAddress);
+    }
+    let array = unsafe { to_user_slice(va, size)? };
+    let array = unsafe { core::slice::from_raw_parts(array.as_ptr() as *const UserStr, count) };
+    let mut strings = Vec::with_capacity(count);
+    let mut total: usize = 0;
+    for entry in array {
+	let len = entry.len as usize;
+	total = total.saturating_add(len);
+	if total > ARG_MAX {
+	    return Err(OsError::NoMemory);
+	}
+	if len == 0 {
+	    strings.push(String::new());
+	    continue;
+	}
+	let bytes = unsafe { to_user_slice(entry.ptr as usize, len)? };
+	let s = core::str::from_utf8(bytes).map_err(|_| OsError::InvalidArgument)?;
+	strings.push(String::from(s));
+    }
+    Ok(strings)
+}
+
+/// Waits for a child of the current process to exit.
+///
+/// This system call takes one parameter: the process ID of the child, or 0
+/// for whichever child exits first. A child that exited is a zombie, which
+/// keeps only its exit code, until its parent waits for it; then it is gone.
+/// The children of a process that exits are handed to the init process,
+/// which only keeps those that exit while one of its threads waits.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the process ID of the child and its exit code. That is the
+/// code passed to `exit`, the one the last thread passed to `thread_exit`,
+/// or `EXIT_KILLED` for a process that was killed.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoEntry`: The process is not a child of the current one or
+///   was already waited for, or, for 0, the current process has no children.
+///   Also returned once the child is gone while waiting, waited for by
+///   another thread of the process.
+pub fn sys_waitpid(pid: u64, tf: &mut TrapFrame) {
+    let children = SCHEDULER.critical(|scheduler| {
+	scheduler.current().map(|process| process.family.children.clone())
+    });
+    let waitable = match children {
+	Some(children) => {
+	    let mut children = children.lock();
+	    let waitable = children.waitable(pid);
+	    if waitable {
+		children.wait();
+	    }
+	    waitable
+	},
+	None => false,
+    };
+    if !waitable {
+	tf.x[7] = OsError::NoEntry as u64;
+	return;
+    }
+
+    let waitFn = Box::new(move |process: &mut Process| {
+	let mut children = process.family.children.lock();
+	match children.reap(pid) {
+	    Some((child, code)) => {
+		process.context.x[0] = child;
+		process.context.x[1] = code;
+		process.context.x[7] = OsError::Ok as u64;
+	    },
+	    // another thread reaped the child first
+	    None if !children.waitable(pid) => process.context.x[7] = OsError::NoEntry as u64,
+	    None => return false,
+	}
+	children.done_waiting();
+	true
+    });
+    SCHEDULER.switch(State::Waiting(waitFn), tf);
+}
+
+/// Waits for a thread of the current process to exit.
+///
+/// This system call takes one parameter: the ID of the thread to wait for.
+/// A thread can be joined once; its exit code is discarded afterwards.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the exit code the thread passed to `thread_exit`.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoEntry`: The thread does not exist in the current process or
+///   was already joined.
+/// - `OsError::InvalidArgument`: A thread tried to join itself.
+pub fn sys_thread_join(tid: u64, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| {
+	let (pid, current_tid, threads) = match scheduler.current() {
+	    Some(process) => (process.pid, process.tid, process.threads.clone()),
+	    None => return Err(OsError::NoEntry),
+	};
+	if tid == current_tid {
+	    return Err(OsError::InvalidArgument);
+	}
+	let running = scheduler.find_id(tid).map(|thread| thread.pid == pid).unwrap_or(false);
+	match running || threads.lock().has_exited(tid) {
+	    true => Ok(()),
+	    false => Err(OsError::NoEntry),
+	}
+    });
+    if let Err(e) = result {
+	tf.x[7] = e as u64;
+	return;
+    }
+
+    let joinFn = Box::new(move |process: &mut Process| {
+	match process.threads.lock().join(tid) {
+	    Some(code) => {
+		process.context.x[0] = code;
+		process.context.x[7] = OsError::Ok as u64;
+		true
+	    },
+	    None => false,
+	}
+    });
+    SCHEDULER.switch(State::Waiting(joinFn), tf);
+}
+
+/// Blocks the current thread while the futex word at an address holds an
+/// expected value.
+///
+/// This system call takes two parameters: the address of an aligned `u32`
+/// futex word and the value the caller expects it to hold. The check and the
+/// queueing happen atomically with respect to `futex_wake`, so a wake-up that
+/// follows a change of the word can not be missed.
+///
+/// It only returns the usual status value once another thread of the process
+/// calls `futex_wake` on the same address.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address is unaligned or not mapped.
+/// - `OsError::WouldBlock`: The word does not hold the expected value.
+pub fn sys_futex_wait(va: u64, expected: u32, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| {
+	let process = scheduler.current().ok_or(OsError::NoEntry)?;
+	let mut word = [0u8; 4];
+	if va % 4 != 0 || !process.vmap.lock().copy_from(VirtualAddr::from(va), &mut word) {
+	    return Err(OsError::BadAddress);
+	}
+	match u32::from_le_bytes(word) == expected {
+	    true => Ok(process.threads.lock().futex_wait(va)),
+	    false => Err(OsError::WouldBlock),
+	}
+    });
+    let woken = match result {
+	Ok(woken) => woken,
+	Err(e) => {
+	    tf.x[7] = e as u64;
+	    return;
+	},
+    };
+
+    let wakeFn = Box::new(move |process: &mut Process| {
+	match woken.load(Ordering::Acquire) {
+	    true => {
+		process.context.x[7] = OsError::Ok as u64;
+		true
+	    },
+	    false => false,
+	}
+    });
+    SCHEDULER.switch(State::Waiting(wakeFn), tf);
+}
+
+/// Wakes threads blocked on a futex word.
+///
+/// This system call takes two parameters: the address of the futex word and
+/// the maximum number of threads to wake.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of threads woken.
+pub fn sys_futex_wake(va: u64, count: u64, tf: &mut TrapFrame) {
+    let woken = SCHEDULER.critical(|scheduler| {
+	scheduler.current().map(|process| process.threads.lock().futex_wake(va, count as usize))
+    });
+    match woken {
+	Some(woken) => {
+	    tf.x[0] = woken as u64;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	None => tf.x[7] = OsError::NoEntry as u64,
+    }
+}
+
+/// Exits the current thread.
+///
+/// This system call takes one parameter: the exit code handed to the thread
+/// that joins this one. The other threads of the process keep running, while
+/// `exit` ends the whole process.
+///
+/// It does not return.
+pub fn sys_thread_exit(code: u64, tf: &mut TrapFrame) {
+    let _ = SCHEDULER.exit_thread(code, tf);
+    SCHEDULER.switch_to(tf);
+}
+
+/// Reads the path of `len` bytes at `va` and resolves it against the working
+/// directory of the current process, unless it is absolute.
+///
+/// # Errors
+///
+/// Returns `BadAddress` if the path is not in user memory, and
+/// `InvalidArgument` if it is not UTF-8 encoded.
+fn user_path(va: usize, len: usize) -> OsResult<PathBuf> {
+    let path = unsafe { to_user_slice(va, len) }
+	.and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument))?;
+    let cwd = SCHEDULER.critical(|scheduler| scheduler.current().map(|process| process.cwd.clone()))
+	.ok_or(OsError::NoEntry)?;
+    let cwd = cwd.lock().clone();
+    fat32::path::resolve(cwd, path).map_err(|_| OsError::InvalidArgument)
+}
+
+/// Changes the working directory of the current process, shared by all its
+/// threads.
+///
+/// This system call takes two parameters: the address and the length of the
+/// path of the directory. It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded.
+/// - `OsError::NoEntry`: There is nothing at the path.
+/// - `OsError::IoErrorInvalidInput`: The path is not a directory.
+pub fn sys_chdir(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = user_path(va, len).and_then(|path| {
+	if !FILESYSTEM.open(&path)?.attr().directory {
+	    return Err(OsError::IoErrorInvalidInput);
+	}
+	let cwd = SCHEDULER.critical(|scheduler| scheduler.current().map(|process| process.cwd.clone()))
+	    .ok_or(OsError::NoEntry)?;
+	*cwd.lock() = path;
+	Ok(())
+    });
+
+    match result {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Returns the working directory of the current process.
+///
+/// This system call takes two parameters: the address and the length of the
+/// buffer to copy the absolute path into, which is not NUL terminated.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the length of the path.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The buffer is shorter than the path.
+pub fn sys_getcwd(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| scheduler.current().map(|process| process.cwd.clone()))
+	.ok_or(OsError::NoEntry)
+	.and_then(|cwd| {
+	    let cwd = cwd.lock();
+	    let path = cwd.to_str().ok_or(OsError::InvalidArgument)?.as_bytes();
+	    if path.len() > len {
+		return Err(OsError::InvalidArgument);
+	    }
+	    unsafe { to_user_slice_mut(va, path.len())? }.copy_from_slice(path);
+	    Ok(path.len())
+	});
+
+    match result {
+	Ok(len) => {
+	    tf.x[0] = len as u64;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Changes the protection of pages of the current process.
+///
+/// This system call takes three parameters: the page aligned start address,
+/// the length of the range and the protection, `PROT_READ` combined with
+/// `PROT_WRITE` and `PROT_EXEC` as needed. Pages of the range that were not
+/// touched yet are mapped.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The address is not page aligned or the protection lacks `PROT_READ` or has unknown bits.
+/// - `OsError::BadAddress`: Part of the range is not in the address space of the process, or is the vDSO page.
+pub fn sys_mprotect(va: usize, len: usize, prot: u64, tf: &mut TrapFrame) {
+    if va % PAGE_SIZE != 0 {
+	tf.x[7] = OsError::InvalidArgument as u64;
+	return;
+    }
+    let result = page_perm(prot)
+	.and_then(|perm| match current_vmap()?.lock().protect(VirtualAddr::from(va), len, perm) {
+	    true => Ok(()),
+	    false => Err(OsError::BadAddress),
+	});
+
+    match result {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Maps anonymous memory into the current process.
+///
+/// This system call takes two parameters: the length of the mapping, which
+/// is rounded up to a multiple of the page size, and the protection, as for
+/// `mprotect`. The pages are zeroed and allocated as they are first touched.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the page aligned address of the mapping.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The length is zero or the protection lacks `PROT_READ` or has unknown bits.
+/// - `OsError::NoVmSpace`: There is no free range of addresses that large.
+pub fn sys_mmap(len: usize, prot: u64, tf: &mut TrapFrame) {
+    let result = page_perm(prot).and_then(|perm| {
+	let len = page_round_up(len)?;
+	current_vmap()?.lock().map_anonymous(len, perm).ok_or(OsError::NoVmSpace)
+    });
+
+    match result {
+	Ok(va) => {
+	    tf.x[0] = va.as_u64();
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Unmaps anonymous memory of the current process.
+///
+/// This system call takes two parameters: the page aligned address and the
+/// length of the range to unmap, which is rounded up to a multiple of the
+/// page size. The range may be part of a mapping, which keeps the rest.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The length is zero, or the range does not lie within a single mapping made by `mmap`.
+pub fn sys_munmap(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = page_round_up(len).and_then(|len| {
+	match current_vmap()?.lock().unmap_anonymous(VirtualAddr::from(va), len) {
+	    true => Ok(()),
+	    false => Err(OsError::InvalidArgument),
+	}
+    });
+
+    match result {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Creates a named shared memory segment.
+///
+/// This system call takes three parameters: the address and the length of
+/// the name, and the length of the segment, which is rounded up to a
+/// multiple of the page size. The segment is zeroed.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The name is empty or not UTF-8 encoded, or the length is zero.
+/// - `OsError::FileExists`: There is a segment with the name already.
+/// - `OsError::NoMemory`: There is not enough memory for the segment.
+pub fn sys_shm_create(va: usize, len: usize, size: usize, tf: &mut TrapFrame) {
+    let result = user_name(va, len)
+	.and_then(|name| shm::create(&name, page_round_up(size)?));
+
+    match result {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Maps a named shared memory segment into the current process.
+///
+/// This system call takes three parameters: the address and the length of
+/// the name, and the protection, as for `mprotect`. Every process mapping the
+/// segment sees what the others write to it.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the page aligned address of the mapping.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The name is empty or not UTF-8 encoded, or the protection lacks `PROT_READ` or has unknown bits.
+/// - `OsError::NoEntry`: There is no segment with the name.
+/// - `OsError::NoVmSpace`: There is no free range of addresses as large as the segment.
+pub fn sys_shm_map(va: usize, len: usize, prot: u64, tf: &mut TrapFrame) {
+    let result = user_name(va, len).and_then(|name| {
+	let perm = page_perm(prot)?;
+	let segment = shm::open(&name)?;
+	current_vmap()?.lock().map_segment(segment, perm).ok_or(OsError::NoVmSpace)
+    });
+
+    match result {
+	Ok(va) => {
+	    tf.x[0] = va.as_u64();
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Unmaps a shared memory segment from the current process.
+///
+/// This system call takes one parameter: the address `shm_map` returned. It
+/// only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: No segment is mapped at the address.
+pub fn sys_shm_unmap(va: usize, tf: &mut TrapFrame) {
+    let result = current_vmap().and_then(|vmap| match vmap.lock().unmap_segment(VirtualAddr::from(va)) {
+	true => Ok(()),
+	false => Err(OsError::InvalidArgument),
+    });
+
+    match result {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Removes the name of a shared memory segment.
+///
+/// This system call takes two parameters: the address and the length of the
+/// name. Processes that map the segment keep it until they unmap it. It only
+/// returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The name is empty or not UTF-8 encoded.
+/// - `OsError::NoEntry`: There is no segment with the name.
+pub fn sys_shm_unlink(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = user_name(va, len).and_then(|name| shm::unlink(&name));
+
+    match result {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Fills a buffer with random bytes from the kernel's generator.
+///
+/// This system call takes two parameters: the address and the length of the
+/// buffer. It only returns the usual status value. The bytes are the same
+/// every run while the generator has a fixed seed, see `rng`.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+pub fn sys_getrandom(va: usize, len: usize, tf: &mut TrapFrame) {
+    match unsafe { to_user_slice_mut(va, len) } {
+	Ok(buf) => {
+	    crate::rng::fill(buf);
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Reads the name of a shared memory segment of `len` bytes at `va`.
+///
+/// # Errors
+///
+/// Returns `BadAddress` if the name is not in user memory, and
+/// `InvalidArgument` if it is empty or not UTF-8 encoded.
+fn user_name(va: usize, len: usize) -> OsResult<String> {
+    let name = unsafe { to_user_slice(va, len) }
+	.and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument))?;
+    match name.is_empty() {
+	true => Err(OsError::InvalidArgument),
+	false => Ok(String::from(name)),
+    }
+}
+
+/// Returns the page permission of the `mprotect` and `mmap` protection
+/// `prot`.
+///
+/// # Errors
+/// This functions returns `Err(OsError::InvalidArgument)` if `prot` lacks
+/// `PROT_READ` or has unknown bits.
+fn page_perm(prot: u64) -> OsResult<PagePerm> {
+    match prot & !(PROT_WRITE | PROT_EXEC) {
+	PROT_READ => Ok(PagePerm::new(prot & PROT_WRITE != 0, prot & PROT_EXEC != 0)),
+	_ => Err(OsError::InvalidArgument),
+    }
+}
+
+/// Rounds the nonzero length of a mapping up to a multiple of the page size.
+///
+/// # Errors
+/// This functions returns `Err(OsError::InvalidArgument)` if `len` is zero
+/// or too large to round up.
+fn page_round_up(len: usize) -> OsResult<usize> {
+    match len.checked_add(PAGE_SIZE - 1) {
+	Some(end) if len > 0 => Ok(end & PAGE_MASK),
+	_ => Err(OsError::InvalidArgument),
+    }
+}
+
+/// Returns the page table of the current process.
+fn current_vmap() -> OsResult<Arc<Mutex<UserPageTable>>> {
+    SCHEDULER.critical(|scheduler| scheduler.current().map(|process| process.vmap.clone()))
+	.ok_or(OsError::NoEntry)
+}
+
+/// Returns the open files of the current process.
+fn current_files() -> OsResult<Arc<Mutex<FdTable>>> {
+    SCHEDULER.critical(|scheduler| scheduler.current().map(|process| process.files.clone()))
+	.ok_or(OsError::NoEntry)
+}
+
+/// Opens a regular file.
+///
+/// This system call takes three parameters: the address and the length of
+/// the path of the file, and the flags, one of `O_RDONLY`,
+/// `O_WRONLY` and `O_RDWR`, with `O_CREAT` to create the file if it does not
+/// exist and `O_CLOEXEC` to close the descriptor on exec.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the file descriptor, the lowest one that is free.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded or the flags are unknown.
+/// - `OsError::NoMemory`: The process has `USER_MAX_FILES` files open.
+/// - `OsError::NoEntry`: There is no file at the path, or no directory to create it in.
+/// - `OsError::NoAccess`: The file must be created on a file system that cannot create files.
+/// - `OsError::ReadOnlyFs`: The file must be created on a file system mounted read only.
+/// - `OsError::IoError` and the other I/O errors: The file could not be opened.
+pub fn sys_open(va: usize, len: usize, flags: u64, tf: &mut TrapFrame) {
+    let result = user_path(va, len)
+	.and_then(|path| {
+	    if flags & !(O_ACCMODE | O_CREAT | O_CLOEXEC) != 0 {
+		return Err(OsError::InvalidArgument);
+	    }
+	    let (readable, writable) = match flags & O_ACCMODE {
+		O_RDONLY => (true, false),
+		O_WRONLY => (false, true),
+		O_RDWR => (true, true),
+		_ => return Err(OsError::InvalidArgument),
+	    };
+	    if flags & O_CREAT != 0 {
+		match FILESYSTEM.create(&path, false) {
+		    Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+		    result => result?,
+		}
+	    }
+	    let handle = FILESYSTEM.open_file(&path)?;
+	    let files = current_files()?;
+	    let mut files = files.lock();
+	    let fd = files.insert(OpenFile::new(handle, readable, writable))?;
+	    files.set_cloexec(fd, flags & O_CLOEXEC != 0)?;
+	    Ok(fd)
+	});
+
+    match result {
+	Ok(fd) => {
+	    tf.x[0] = fd;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Creates and opens a temporary file with a name no other file has.
+///
+/// This system call takes six parameters: the address and the length of the
+/// path of the directory to create the file in, of the prefix of its name,
+/// and of a buffer for its path. The file is opened for reading and writing,
+/// and removed once the last descriptor of it is closed, as at exit, unless
+/// it was renamed by then.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the file descriptor, the lowest one that is free, and the
+/// length of the absolute path written to the buffer.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: An address and length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path or prefix is not UTF-8 encoded, the prefix holds a `/`, or the buffer is too short for the path.
+/// - `OsError::NoMemory`: The process has `USER_MAX_FILES` files open.
+/// - `OsError::NoEntry`: There is no directory at the path.
+/// - `OsError::NoAccess`: The directory is on a file system that cannot create files.
+/// - `OsError::IoError` and the other I/O errors: The file could not be created.
+pub fn sys_mktemp(dir_va: usize, dir_len: usize, prefix_va: usize, prefix_len: usize,
+		  out_va: usize, out_len: usize, tf: &mut TrapFrame) {
+    let result = user_path(dir_va, dir_len)
+	.and_then(|dir| {
+	    let prefix = unsafe { to_user_slice(prefix_va, prefix_len) }
+		.and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument))?;
+	    let (path, handle) = FILESYSTEM.create_temp(&dir, prefix)?;
+	    let path = path.to_str().ok_or(OsError::InvalidArgument)?.as_bytes();
+	    if path.len() > out_len {
+		return Err(OsError::InvalidArgument);
+	    }
+	    unsafe { to_user_slice_mut(out_va, path.len())? }.copy_from_slice(path);
+	    let fd = current_files()?.lock().insert(OpenFile::new(handle, true, true))?;
+	    Ok((fd, path.len()))
+	});
+
+    match result {
+	Ok((fd, len)) => {
+	    tf.x[0] = fd;
+	    tf.x[1] = len as u64;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Sets the nice level of a process, which decides how soon its threads run
+/// next to others: the lower, the sooner. Threads passed over for long enough
+/// still run, see `PRIORITY_AGING`.
+///
+/// This system call takes two parameters: the process ID, 0 for the current
+/// process, and the nice level, `NICE_MIN..=NICE_MAX`. The level applies to
+/// every thread of the process and to threads it creates later. It only
+/// returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The nice level is out of range.
+/// - `OsError::NoEntry`: There is no process with the ID.
+pub fn sys_set_priority(pid: u64, nice: i64, tf: &mut TrapFrame) {
+    let result = match nice {
+	NICE_MIN..=NICE_MAX => SCHEDULER.critical(|scheduler| {
+	    let pid = match pid {
+		0 => scheduler.current().ok_or(OsError::NoEntry)?.pid,
+		pid => pid,
+	    };
+	    match scheduler.set_nice(pid, nice) {
+		true => Ok(()),
+		false => Err(OsError::NoEntry),
+	    }
+	}),
+	_ => Err(OsError::InvalidArgument),
+    };
+
+    match result {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Watches a directory for changes to its entries.
+///
+/// This system call takes three parameters: the address and the length of
+/// the path of the directory, and the events to watch, a
+/// combination of `IN_CREATE`, `IN_MODIFY` and `IN_DELETE`. Entries are only
+/// created and removed on volumes that support it, so a directory on a FAT32
+/// volume only ever reports `IN_MODIFY`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: a file descriptor to `read` the events from. Reading it returns
+/// `OsError::WouldBlock` while no event is queued; closing it ends the watch.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded or the events are unknown.
+/// - `OsError::NoMemory`: The process has `USER_MAX_FILES` files open.
+/// - `OsError::NoEntry`: There is no directory at the path.
+/// - `OsError::IoErrorInvalidInput`: The path is not a directory.
+pub fn sys_watch(va: usize, len: usize, mask: u64, tf: &mut TrapFrame) {
+    let result = user_path(va, len)
+	.and_then(|path| {
+	    if mask == 0 || mask & !(IN_CREATE | IN_MODIFY | IN_DELETE) != 0 {
+		return Err(OsError::InvalidArgument);
+	    }
+	    let handle = FILESYSTEM.watch(path, mask)?;
+	    current_files()?.lock().insert(OpenFile::new(handle, true, false))
+	});
+
+    match result {
+	Ok(fd) => {
+	    tf.x[0] = fd;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Removes a regular file or an empty directory.
+///
+/// This system call takes two parameters: the address and the length of the
+/// path. It only returns the usual status value. Descriptors open
+/// on a removed file keep working.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded.
+/// - `OsError::NoEntry`: There is nothing at the path.
+/// - `OsError::NoAccess`: The file system cannot remove files.
+/// - `OsError::ReadOnlyFs`: The file system is read only.
+/// - `OsError::IoErrorInvalidInput`: The path is a mount point.
+/// - `OsError::IoError`: The path is a directory that is not empty.
+pub fn sys_unlink(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = user_path(va, len)
+	.and_then(|path| FILESYSTEM.remove(path).map_err(OsError::from));
+
+    match result {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Creates an empty directory.
+///
+/// This system call takes two parameters: the address and the length of the
+/// path. It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded.
+/// - `OsError::FileExists`: There is a file or directory at the path.
+/// - `OsError::NoEntry`: The directory to create it in does not exist.
+/// - `OsError::NoAccess`: The file system cannot create directories.
+/// - `OsError::ReadOnlyFs`: The file system is read only.
+pub fn sys_mkdir(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = user_path(va, len)
+	.and_then(|path| FILESYSTEM.create(path, true).map_err(OsError::from));
+
+    match result {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Closes a file descriptor, writing back the file.
+///
+/// This system call takes one parameter: the file descriptor.
+///
+/// It only returns the usual status value. The descriptor is closed even if
+/// writing back fails.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The descriptor is not open.
+/// - `OsError::IoError` and the other I/O errors: Writing back failed.
+pub fn sys_close(fd: u64, tf: &mut TrapFrame) {
+    let result = current_files().and_then(|files| files.lock().close(fd));
+    tf.x[7] = match result {
+	Ok(()) => OsError::Ok,
+	Err(e) => e,
+    } as u64;
+}
+
+/// Reads from a file descriptor.
+///
+/// This system call takes the file descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the
+/// buffer as the third parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read, 0 at the end of the file. Reading
+/// an empty pipe waits until there is something to read.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The descriptor is not open.
+/// - `OsError::NoAccess`: The file was opened with `O_WRONLY`.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::WouldBlock`: The descriptor is a watch without queued events.
+/// - `OsError::IoError` and the other I/O errors: Reading failed.
+pub fn sys_read(fd: u64, va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_slice_mut(va, len) }
+	.and_then(|buf| current_files()?.lock().get(fd)?.read(buf));
+
+    match result {
+	Ok(count) => {
+	    tf.x[0] = count as u64;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(OsError::WouldBlock) if is_blocking(fd) => {
+	    let files = match current_files() {
+		Ok(files) => files,
+		Err(e) => {
+		    tf.x[7] = e as u64;
+		    return;
+		},
+	    };
+	    // the thread's pages are not mapped while others run, so the bytes
+	    // go through its page table
+	    let wakeFn = Box::new(move |process: &mut Process| {
+		let mut buf = [0u8; WAIT_CHUNK];
+		let buf = &mut buf[..min(len, WAIT_CHUNK)];
+		let result = files.lock().get(fd).and_then(|file| file.read(buf));
+		match result {
+		    Err(OsError::WouldBlock) => return false,
+		    Ok(count) => match process.vmap.lock().copy_to(VirtualAddr::from(va), &buf[..count]) {
+			true => {
+			    process.context.x[0] = count as u64;
+			    process.context.x[7] = OsError::Ok as u64;
+			},
+			false => process.context.x[7] = OsError::BadAddress as u64,
+		    },
+		    Err(e) => process.context.x[7] = e as u64,
+		}
+		true
+	    });
+	    SCHEDULER.switch(State::Waiting(wakeFn), tf);
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Bytes a blocked `read` or `write` moves at most once it is woken
+const WAIT_CHUNK: usize = 512;
+
+/// Returns true if the file open under `fd` waits instead of failing with
+/// `WouldBlock`.
+fn is_blocking(fd: u64) -> bool {
+    current_files().ok().map_or(false, |files| files.lock().get(fd).map(|file| file.blocking()).unwrap_or(false))
+}
+
+/// Writes to a file descriptor.
+///
+/// This system call takes the file descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the
+/// buffer as the third parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written. A pipe takes what fits, and
+/// writing a full one waits until there is room.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The descriptor is not open.
+/// - `OsError::NoAccess`: The file was opened with `O_RDONLY`.
+/// - `OsError::ReadOnlyFs`: The file is on a file system mounted read only.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::IoError` and the other I/O errors: Writing failed, or the read end of the pipe is closed.
+pub fn sys_fd_write(fd: u64, va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_slice(va, len) }
+	.and_then(|buf| current_files()?.lock().get(fd)?.write(buf));
+
+    match result {
+	Ok(count) => {
+	    tf.x[0] = count as u64;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(OsError::WouldBlock) if is_blocking(fd) => {
+	    let files = match current_files() {
+		Ok(files) => files,
+		Err(e) => {
+		    tf.x[7] = e as u64;
+		    return;
+		},
+	    };
+	    let wakeFn = Box::new(move |process: &mut Process| {
+		let mut buf = [0u8; WAIT_CHUNK];
+		let buf = &mut buf[..min(len, WAIT_CHUNK)];
+		if !process.vmap.lock().copy_from(VirtualAddr::from(va), buf) {
+		    process.context.x[7] = OsError::BadAddress as u64;
+		    return true;
+		}
+		let result = files.lock().get(fd).and_then(|file| file.write(buf));
+		match result {
+		    Err(OsError::WouldBlock) => return false,
+		    Ok(count) => {
+			process.context.x[0] = count as u64;
+			process.context.x[7] = OsError::Ok as u64;
+		    },
+		    Err(e) => process.context.x[7] = e as u64,
+		}
+		true
+	    });
+	    SCHEDULER.switch(State::Waiting(wakeFn), tf);
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Copies bytes from one file descriptor to another within the kernel.
+///
+/// This system call takes three parameters: the descriptor to write to, the
+/// descriptor to read from, which must be a file rather than a pipe, and the
+/// number of bytes to move. The bytes are read from the current position of
+/// the input, which moves past them. Writing to a full pipe waits until there
+/// is room. Bytes of a file read through the page cache are written straight
+/// from its cached pages, see `OpenFile::send()`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes moved, fewer than asked for at the end of
+/// the input or when the output takes fewer.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: A descriptor is not open.
+/// - `OsError::NoAccess`: The input is not open for reading or the output not for writing.
+/// - `OsError::InvalidArgument`: The input is a pipe.
+/// - `OsError::IoError` and the other I/O errors: Reading or writing failed.
+pub fn sys_sendfile(out_fd: u64, in_fd: u64, len: usize, tf: &mut TrapFrame) {
+    let files = match current_files() {
+	Ok(files) => files,
+	Err(e) => {
+	    tf.x[7] = e as u64;
+	    return;
+	},
+    };
+    // both files are taken out of the table, as one may be written to while
+    // the other is read from
+    let send = move |files: &Mutex<FdTable>| -> OsResult<usize> {
+	let (mut input, mut output) = {
+	    let mut files = files.lock();
+	    (files.get(in_fd)?.clone(), files.get(out_fd)?.clone())
+	};
+	input.send(&mut output, len)
+    };
+
+    match send(&files) {
+	Ok(count) => {
+	    tf.x[0] = count as u64;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(OsError::WouldBlock) if is_blocking(out_fd) => {
+	    let wakeFn = Box::new(move |process: &mut Process| {
+		match send(&files) {
+		    Err(OsError::WouldBlock) => return false,
+		    Ok(count) => {
+			process.context.x[0] = count as u64;
+			process.context.x[7] = OsError::Ok as u64;
+		    },
+		    Err(e) => process.context.x[7] = e as u64,
+		}
+		true
+	    });
+	    SCHEDULER.switch(State::Waiting(wakeFn), tf);
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Duplicates a file descriptor onto the lowest free one.
+///
+/// This system call takes one parameter: the open file descriptor. The new
+/// descriptor shares the position with it.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new file descriptor.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The descriptor is not open.
+/// - `OsError::NoMemory`: `USER_MAX_FILES` files are open already.
+pub fn sys_dup(old: u64, tf: &mut TrapFrame) {
+    match current_files().and_then(|files| files.lock().dup(old)) {
+	Ok(fd) => {
+	    tf.x[0] = fd;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Duplicates a file descriptor onto another.
+///
+/// This system call takes two parameters: the open file descriptor and the
+/// descriptor to make refer to the same file. A file open under the second
+/// one is closed first. The two share the position afterwards.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the second file descriptor.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The first descriptor is not open or the second is not below `USER_MAX_FILES`.
+pub fn sys_dup2(old: u64, new: u64, tf: &mut TrapFrame) {
+    match current_files().and_then(|files| files.lock().dup2(old, new)) {
+	Ok(fd) => {
+	    tf.x[0] = fd;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Gets or sets the flags of a file descriptor.
+///
+/// This system call takes three parameters: the file descriptor, the
+/// command, `F_GETFD` or `F_SETFD`, and for `F_SETFD` the new flags, 0 or
+/// `FD_CLOEXEC`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the flags for `F_GETFD`, and 0 for `F_SETFD`.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The descriptor is not open.
+/// - `OsError::InvalidArgument`: The command or the flags are unknown.
+pub fn sys_fcntl(fd: u64, cmd: u64, arg: u64, tf: &mut TrapFrame) {
+    let result = current_files().and_then(|files| {
+	let mut files = files.lock();
+	match cmd {
+	    F_GETFD => Ok(if files.cloexec(fd)? { FD_CLOEXEC } else { 0 }),
+	    F_SETFD if arg & !FD_CLOEXEC == 0 => files.set_cloexec(fd, arg & FD_CLOEXEC != 0).map(|_| 0),
+	    _ => Err(OsError::InvalidArgument),
+	}
+    });
+
+    match result {
+	Ok(value) => {
+	    tf.x[0] = value;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Takes or releases an advisory lock on an open file.
+///
+/// This system call takes two parameters: the file descriptor and the
+/// operation, one of `LOCK_SH`, `LOCK_EX` and `LOCK_UN`, with `LOCK_NB` not
+/// to wait. A lock is shared by the descriptors duplicated from the one it
+/// was taken with, and released when the last of them is closed. Taking a
+/// lock another open file holds in a conflicting way waits until it is
+/// released. It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The descriptor is not open.
+/// - `OsError::InvalidArgument`: The operation is unknown.
+/// - `OsError::WouldBlock`: `LOCK_NB` was given and the lock conflicts with another.
+/// - `OsError::IoError`: The file cannot be locked.
+pub fn sys_flock(fd: u64, op: u64, tf: &mut TrapFrame) {
+    let kind = match op & !LOCK_NB {
+	LOCK_SH => LockKind::Shared,
+	LOCK_EX => LockKind::Exclusive,
+	LOCK_UN => LockKind::Unlock,
+	_ => {
+	    tf.x[7] = OsError::InvalidArgument as u64;
+	    return;
+	},
+    };
+    let files = match current_files() {
+	Ok(files) => files,
+	Err(e) => {
+	    tf.x[7] = e as u64;
+	    return;
+	},
+    };
+
+    let result = files.lock().get(fd).and_then(|file| file.lock(kind));
+    match result {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(OsError::WouldBlock) if op & LOCK_NB == 0 => {
+	    let wakeFn = Box::new(move |process: &mut Process| {
+		let result = files.lock().get(fd).and_then(|file| file.lock(kind));
+		match result {
+		    Err(OsError::WouldBlock) => return false,
+		    Ok(()) => process.context.x[7] = OsError::Ok as u64,
+		    Err(e) => process.context.x[7] = e as u64,
+		}
+		true
+	    });
+	    SCHEDULER.switch(State::Waiting(wakeFn), tf);
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Creates a pipe.
+///
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the file descriptor of the read end and the one of the write
+/// end. Reading an empty pipe waits for a write, and returns 0 bytes once the
+/// write end is closed. Writing a full pipe waits for a read, and fails once
+/// the read end is closed.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoMemory`: The process would have more than `USER_MAX_FILES` files open.
+pub fn sys_pipe(tf: &mut TrapFrame) {
+    let result = current_files().and_then(|files| {
+	let mut files = files.lock();
+	let (reader, writer) = pipe::pipe();
+	let read_fd = files.insert(OpenFile::new(Box::new(reader), true, false))?;
+	match files.insert(OpenFile::new(Box::new(writer), false, true)) {
+	    Ok(write_fd) => Ok((read_fd, write_fd)),
+	    Err(e) => {
+		let _ = files.close(read_fd);
+		Err(e)
+	    },
+	}
+    });
+
+    match result {
+	Ok((read_fd, write_fd)) => {
+	    tf.x[0] = read_fd;
+	    tf.x[1] = write_fd;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Moves the position of a file descriptor.
+///
+/// This system call takes three parameters: the file descriptor, the signed
+/// offset, and where the offset counts from, one of `SEEK_SET`, `SEEK_CUR`
+/// and `SEEK_END`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new position from the start of the file.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The descriptor is not open.
+/// - `OsError::InvalidArgument`: The origin is unknown or the offset from `SEEK_SET` is negative.
+/// - `OsError::IoErrorInvalidInput`: The new position is before the start or past the end of the file.
+pub fn sys_lseek(fd: u64, offset: i64, whence: u64, tf: &mut TrapFrame) {
+    let pos = match whence {
+	SEEK_SET if offset >= 0 => Ok(SeekFrom::Start(offset as u64)),
+	SEEK_CUR => Ok(SeekFrom::Current(offset)),
+	SEEK_END => Ok(SeekFrom::End(offset)),
+	_ => Err(OsError::InvalidArgument),
+    };
+    let result = pos.and_then(|pos| current_files()?.lock().get(fd)?.seek(pos));
+
+    match result {
+	Ok(pos) => {
+	    tf.x[0] = pos;
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
 }
 
 /// Creates a socket and saves the socket handle in the current process's
@@ -92,6 +1415,7 @@ pub fn sys_getpid(tf: &mut TrapFrame) {
 ///
 /// This function does neither take any parameter nor return anything,
 /// except the usual return code that indicates successful syscall execution.
+#[cfg(feature = "net")]
 pub fn sys_sock_create(tf: &mut TrapFrame) {
     // Lab 5 2.D
     unimplemented!("sys_sock_create")
@@ -112,6 +1436,7 @@ pub fn sys_sock_create(tf: &mut TrapFrame) {
 /// # Errors
 /// This function returns `OsError::InvalidSocket` if a socket that corresponds
 /// to the provided descriptor is not found.
+#[cfg(feature = "net")]
 pub fn sys_sock_status(sock_idx: usize, tf: &mut TrapFrame) {
     // Lab 5 2.D
     unimplemented!("sys_sock_status")
@@ -136,6 +1461,7 @@ pub fn sys_sock_status(sock_idx: usize, tf: &mut TrapFrame) {
 /// - `OsError::IllegalSocketOperation`: `connect()` returned `smoltcp::Error::Illegal`.
 /// - `OsError::BadAddress`: `connect()` returned `smoltcp::Error::Unaddressable`.
 /// - `OsError::Unknown`: All the other errors from calling `connect()`.
+#[cfg(feature = "net")]
 pub fn sys_sock_connect(
     sock_idx: usize,
     remote_endpoint: impl Into<IpEndpoint>,
@@ -159,19 +1485,37 @@ pub fn sys_sock_connect(
 /// - `OsError::IllegalSocketOperation`: `listen()` returned `smoltcp::Error::Illegal`.
 /// - `OsError::BadAddress`: `listen()` returned `smoltcp::Error::Unaddressable`.
 /// - `OsError::Unknown`: All the other errors from calling `listen()`.
+#[cfg(feature = "net")]
 pub fn sys_sock_listen(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
     // Lab 5 2.D
     unimplemented!("sys_sock_listen")
 }
 
+/// Maps the pages of `va..va + len` the current process did not touch yet,
+/// and copies those shared copy-on-write if `write` is set, as the kernel
+/// accesses user memory directly rather than by faulting.
+///
+/// # Errors
+/// This functions returns `Err(OsError::BadAddress)` if part of the range is
+/// not in the address space of the process, or, if `write` is set, is not
+/// writable by it.
+fn populate_user(va: usize, len: usize, write: bool) -> OsResult<()> {
+    let populated = current_vmap()?.lock().populate(VirtualAddr::from(va), len, write);
+    match populated {
+	true => Ok(()),
+	false => Err(OsError::BadAddress),
+    }
+}
+
 /// Returns a slice from a virtual address and a legnth.
 ///
 /// # Errors
 /// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
-/// in userspace.
+/// in userspace or not mapped.
 unsafe fn to_user_slice<'a>(va: usize, len: usize) -> OsResult<&'a [u8]> {
     let overflow = va.checked_add(len).is_none();
     if va >= USER_IMG_BASE && !overflow {
+        populate_user(va, len, false)?;
         Ok(core::slice::from_raw_parts(va as *const u8, len))
     } else {
         Err(OsError::BadAddress)
@@ -181,10 +1525,11 @@ unsafe fn to_user_slice<'a>(va: usize, len: usize) -> OsResult<&'a [u8]> {
 ///
 /// # Errors
 /// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
-/// in userspace.
+/// in userspace, not mapped, or not writable by the process.
 unsafe fn to_user_slice_mut<'a>(va: usize, len: usize) -> OsResult<&'a mut [u8]> {
     let overflow = va.checked_add(len).is_none();
     if va >= USER_IMG_BASE && !overflow {
+        populate_user(va, len, true)?;
         Ok(core::slice::from_raw_parts_mut(va as *mut u8, len))
     } else {
         Err(OsError::BadAddress)
@@ -207,6 +1552,7 @@ unsafe fn to_user_slice_mut<'a>(va: usize, len: usize) -> OsResult<&'a mut [u8]>
 /// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
 /// - `OsError::IllegalSocketOperation`: `send_slice()` returned `smoltcp::Error::Illegal`.
 /// - `OsError::Unknown`: All the other errors from smoltcp.
+#[cfg(feature = "net")]
 pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
     // Lab 5 2.D
     unimplemented!("sys_sock_send")
@@ -228,11 +1574,88 @@ pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame)
 /// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
 /// - `OsError::IllegalSocketOperation`: `recv_slice()` returned `smoltcp::Error::Illegal`.
 /// - `OsError::Unknown`: All the other errors from smoltcp.
+#[cfg(feature = "net")]
 pub fn sys_sock_recv(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
     // Lab 5 2.D
     unimplemented!("sys_sock_recv")
 }
 
+/// Copies the raw bytes of `value` to the user buffer at `va`, at most `len`
+/// of them. Programs built against an older, shorter struct get its prefix.
+fn copy_to_user<T: Copy>(value: &T, va: usize, len: usize) -> OsResult<()> {
+    let size = core::cmp::min(len, core::mem::size_of::<T>());
+    let buf = unsafe { to_user_slice_mut(va, size)? };
+    let bytes = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size) };
+    buf.copy_from_slice(bytes);
+    Ok(())
+}
+
+/// Gives the current process read access to the cycle counter at EL0, or
+/// takes it away.
+///
+/// This system call takes one parameter: 1 to allow reading `PMCCNTR_EL0`,
+/// 0 to trap it again. The setting applies to every thread of the process.
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoAccess`: The kernel was not booted with `perf=user`.
+/// - `OsError::InvalidArgument`: The parameter is neither 0 nor 1.
+pub fn sys_perf_enable(enable: u64, tf: &mut TrapFrame) {
+    let result = match enable {
+	_ if !perf::user_allowed() => Err(OsError::NoAccess),
+	0 | 1 => SCHEDULER.critical(|scheduler| {
+	    let process = scheduler.current().ok_or(OsError::NoEntry)?;
+	    process.perf_counters.store(enable == 1, Ordering::Relaxed);
+	    Ok(())
+	}),
+	_ => Err(OsError::InvalidArgument),
+    };
+
+    match result {
+	Ok(()) => {
+	    perf::load(enable == 1);
+	    tf.x[7] = OsError::Ok as u64;
+	},
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Returns the names of the kernel and the board.
+///
+/// This system call takes two parameters: the address and the size of a
+/// `Utsname` to fill in. It does not return any parameter besides the usual
+/// status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the size pair does not form a valid userspace slice.
+pub fn sys_uname(va: usize, len: usize, tf: &mut TrapFrame) {
+    match copy_to_user(&crate::sysinfo::uname(), va, len) {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Returns statistics of the system: uptime, memory, processes and cores.
+///
+/// This system call takes two parameters: the address and the size of a
+/// `Sysinfo` to fill in. It does not return any parameter besides the usual
+/// status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the size pair does not form a valid userspace slice.
+pub fn sys_sysinfo(va: usize, len: usize, tf: &mut TrapFrame) {
+    match copy_to_user(&crate::sysinfo::sysinfo(), va, len) {
+	Ok(()) => tf.x[7] = OsError::Ok as u64,
+	Err(e) => tf.x[7] = e as u64,
+    }
+}
+
 /// Writes a UTF-8 string to the console.
 ///
 /// This system call takes the address of the buffer as the first parameter and
@@ -275,7 +1698,7 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
 	},
 	
 	NR_EXIT => {
-	    sys_exit(tf);
+	    sys_exit(tf.x[0], tf);
 	},
 	
 	NR_WRITE => {
@@ -286,6 +1709,161 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
 	NR_GETPID => {
 	    sys_getpid(tf);
 	},
+
+	NR_GETTID => {
+	    sys_gettid(tf);
+	},
+
+	NR_THREAD_CREATE => {
+	    sys_thread_create(tf.x[0], tf.x[1], tf.x[2], tf);
+	},
+
+	NR_THREAD_JOIN => {
+	    sys_thread_join(tf.x[0], tf);
+	},
+
+	NR_THREAD_EXIT => {
+	    sys_thread_exit(tf.x[0], tf);
+	},
+
+	NR_FUTEX_WAIT => {
+	    sys_futex_wait(tf.x[0], tf.x[1] as u32, tf);
+	},
+
+	NR_FUTEX_WAKE => {
+	    sys_futex_wake(tf.x[0], tf.x[1], tf);
+	},
+
+	NR_OPEN => {
+	    sys_open(tf.x[0] as usize, tf.x[1] as usize, tf.x[2], tf);
+	},
+
+	NR_CLOSE => {
+	    sys_close(tf.x[0], tf);
+	},
+
+	NR_READ => {
+	    sys_read(tf.x[0], tf.x[1] as usize, tf.x[2] as usize, tf);
+	},
+
+	NR_FD_WRITE => {
+	    sys_fd_write(tf.x[0], tf.x[1] as usize, tf.x[2] as usize, tf);
+	},
+
+	NR_LSEEK => {
+	    sys_lseek(tf.x[0], tf.x[1] as i64, tf.x[2], tf);
+	},
+
+	NR_WATCH => {
+	    sys_watch(tf.x[0] as usize, tf.x[1] as usize, tf.x[2], tf);
+	},
+
+	NR_SENDFILE => {
+	    sys_sendfile(tf.x[0], tf.x[1], tf.x[2] as usize, tf);
+	},
+
+	NR_DUP => {
+	    sys_dup(tf.x[0], tf);
+	},
+
+	NR_DUP2 => {
+	    sys_dup2(tf.x[0], tf.x[1], tf);
+	},
+
+	NR_FCNTL => {
+	    sys_fcntl(tf.x[0], tf.x[1], tf.x[2], tf);
+	},
+
+	NR_FLOCK => {
+	    sys_flock(tf.x[0], tf.x[1], tf);
+	},
+
+	NR_PIPE => {
+	    sys_pipe(tf);
+	},
+
+	NR_UNLINK => {
+	    sys_unlink(tf.x[0] as usize, tf.x[1] as usize, tf);
+	},
+
+	NR_MKDIR => {
+	    sys_mkdir(tf.x[0] as usize, tf.x[1] as usize, tf);
+	},
+
+	NR_PERF_ENABLE => {
+	    sys_perf_enable(tf.x[0], tf);
+	},
+
+	NR_UNAME => {
+	    sys_uname(tf.x[0] as usize, tf.x[1] as usize, tf);
+	},
+
+	NR_SYSINFO => {
+	    sys_sysinfo(tf.x[0] as usize, tf.x[1] as usize, tf);
+	},
+
+	NR_CHDIR => {
+	    sys_chdir(tf.x[0] as usize, tf.x[1] as usize, tf);
+	},
+
+	NR_GETCWD => {
+	    sys_getcwd(tf.x[0] as usize, tf.x[1] as usize, tf);
+	},
+
+	NR_MPROTECT => {
+	    sys_mprotect(tf.x[0] as usize, tf.x[1] as usize, tf.x[2], tf);
+	},
+
+	NR_MMAP => {
+	    sys_mmap(tf.x[0] as usize, tf.x[1], tf);
+	},
+
+	NR_MUNMAP => {
+	    sys_munmap(tf.x[0] as usize, tf.x[1] as usize, tf);
+	},
+
+	NR_SHM_CREATE => {
+	    sys_shm_create(tf.x[0] as usize, tf.x[1] as usize, tf.x[2] as usize, tf);
+	},
+
+	NR_SHM_MAP => {
+	    sys_shm_map(tf.x[0] as usize, tf.x[1] as usize, tf.x[2], tf);
+	},
+
+	NR_SHM_UNMAP => {
+	    sys_shm_unmap(tf.x[0] as usize, tf);
+	},
+
+	NR_SHM_UNLINK => {
+	    sys_shm_unlink(tf.x[0] as usize, tf.x[1] as usize, tf);
+	},
+
+	NR_GETRANDOM => {
+	    sys_getrandom(tf.x[0] as usize, tf.x[1] as usize, tf);
+	},
+
+	NR_MKTEMP => {
+	    sys_mktemp(tf.x[0] as usize, tf.x[1] as usize, tf.x[2] as usize, tf.x[3] as usize,
+		       tf.x[4] as usize, tf.x[5] as usize, tf);
+	},
+
+	NR_SET_PRIORITY => {
+	    sys_set_priority(tf.x[0], tf.x[1] as i64, tf);
+	},
+
+	NR_FORK => {
+	    sys_fork(tf);
+	},
+
+	NR_EXEC => {
+	    let argv = (tf.x[2] as usize, tf.x[3] as usize);
+	    let envp = (tf.x[4] as usize, tf.x[5] as usize);
+	    sys_exec(tf.x[0] as usize, tf.x[1] as usize, argv, envp, tf);
+	},
+
+	NR_WAITPID => {
+	    sys_waitpid(tf.x[0], tf);
+	},
 	_ => {
 	    // error code
 	},
@@ -0,0 +1,45 @@
+use aarch64::*;
+use kernel_api::vdso::VdsoData;
+use pi::timer::current_time;
+
+use crate::kaslr;
+use crate::vm::PhysicalAddr;
+
+/// The kernel data page shared read-only with every process at
+/// `USER_VDSO_BASE`.
+#[repr(C)]
+#[repr(align(65536))]
+struct VdsoPage(VdsoData);
+shim::const_assert_size!(VdsoPage, crate::param::PAGE_SIZE);
+
+static mut VDSO_PAGE: VdsoPage = VdsoPage(VdsoData { counter_freq: 0, boot_offset: 0 });
+
+/// Fills in the vDSO page and lets EL0 read the virtual counter. Must run
+/// before the first process is created.
+///
+/// The boot offset is chosen so that the counter converts to the same time
+/// since boot the system timer reports to the `time()` system call.
+pub unsafe fn initialize() {
+    setup();
+
+    let freq = CNTFRQ_EL0.get();
+    let count = CNTVCT_EL0.get();
+    let elapsed = current_time();
+    let elapsed_ticks = elapsed.as_secs() * freq + elapsed.subsec_nanos() as u64 * freq / 1_000_000_000;
+
+    VDSO_PAGE.0 = VdsoData {
+	counter_freq: freq,
+	boot_offset: count.wrapping_sub(elapsed_ticks),
+    };
+}
+
+/// Lets EL0 of the current core read the virtual counter. The counter runs
+/// in step on every core, so one page serves them all.
+pub unsafe fn setup() {
+    CNTKCTL_EL1.set(CNTKCTL_EL1.get() | CNTKCTL_EL1::EL0VCTEN);
+}
+
+/// Returns the physical address of the vDSO page, in the kernel image.
+pub fn page() -> PhysicalAddr {
+    PhysicalAddr::from(kaslr::phys(unsafe { &VDSO_PAGE as *const VdsoPage as usize }))
+}
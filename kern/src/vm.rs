@@ -1,14 +1,23 @@
 mod address;
+mod asid;
+mod demand;
+pub mod frame;
 mod pagetable;
+pub mod shm;
+mod stats;
 
 pub use self::address::{PhysicalAddr, VirtualAddr};
+pub use self::demand::{Backing, Region};
 pub use self::pagetable::*;
+pub use self::stats::{AuditIssue, VmStats};
 
 use aarch64::*;
+use alloc::vec::Vec;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::mutex::Mutex;
-use crate::param::{KERNEL_MASK_BITS, USER_MASK_BITS};
+use crate::param::{KERNEL_MASK_BITS, NCORES, USER_IMG_BASE, USER_MASK_BITS};
+use crate::SCHEDULER;
 use crate::percore::{is_mmu_ready, set_mmu_ready};
 
 use crate::console::{kprint, kprintln, CONSOLE};
@@ -67,12 +76,14 @@ impl VMManager {
         // (ref. D7.2.91: Translation Control Register)
         TCR_EL1.set(
             (0b00 << 37) | // TBI=0, no tagging
+            (0b0  << 36) | // AS=0, 8-bit ASIDs
             (ips  << 32) | // IPS
             (0b11 << 30) | // TG1=64k
             (0b11 << 28) | // SH1=3 inner
             (0b01 << 26) | // ORGN1=1 write back
             (0b01 << 24) | // IRGN1=1 write back
             (0b0  << 23) | // EPD1 enables higher half
+            (0b1  << 22) | // A1=1, TTBR1 holds the ASID
             ((USER_MASK_BITS as u64) << 16) | // T1SZ=34 (1GB)
             (0b01 << 14) | // TG0=64k
             (0b11 << 12) | // SH0=3 inner
@@ -97,6 +108,7 @@ impl VMManager {
         isb();
 	
         set_mmu_ready();
+        self.ready_core_cnt.fetch_add(1, Ordering::AcqRel);
     }
 
     /// Setup MMU for the current core.
@@ -110,12 +122,106 @@ impl VMManager {
 
         info!("MMU is ready for core-{}/@sp={:016x}", affinity(), SP.get());
 
-        // Lab 5 1.B
-        unimplemented!("wait for other cores")
+        while self.ready_core_cnt.load(Ordering::Acquire) < NCORES {
+            nop();
+        }
+    }
+
+    /// Installs the kernel page table in `TTBR1_EL1` of this core, with ASID
+    /// 0, as at boot, if it holds the user page table based at `baddr`. The
+    /// table may then be freed: the core no longer walks it, speculatively or
+    /// not. Whatever this core cached under its ASID is flushed when the ASID
+    /// is handed out again.
+    pub fn unload_user_table(&self, baddr: PhysicalAddr) {
+	unsafe {
+	    let installed = TTBR1_EL1.get() & !(TTBR1_EL1::TTBR_ASID | TTBR1_EL1::TTBR_CNP);
+	    if installed == baddr.as_u64() {
+		TTBR1_EL1.set(self.kern_pt_addr.load(Ordering::Relaxed) as u64);
+		isb();
+	    }
+	}
+    }
+
+    /// Makes everything the kernel page table maps but `start..end`
+    /// execute-never at EL1, on every core. See `kaslr::protect()`.
+    pub fn forbid_exec(&self, start: usize, end: usize) {
+	self.kern_pt.lock().as_mut().expect("vm setup").forbid_exec(start, end);
+	unsafe {
+	    asm!("dsb ishst
+		  tlbi vmalle1is
+		  dsb ish
+		  isb" :::: "volatile");
+	}
+    }
+
+    /// Unmaps the frame of RAM at `addr` from the kernel page table, on
+    /// every core, or maps it again. Does nothing before `initialize()`. See
+    /// `vm::frame`.
+    #[cfg(debug_assertions)]
+    pub fn set_present(&self, addr: usize, present: bool) {
+	match self.kern_pt.lock().as_mut() {
+	    Some(kern_pt) => kern_pt.set_present(addr, present),
+	    None => return,
+	}
+	unsafe {
+	    match present {
+		// the entry was invalid, so no TLB holds it
+		true => asm!("dsb ishst
+			      isb" :::: "volatile"),
+		false => asm!("dsb ishst
+			       tlbi vaae1is, $0
+			       dsb ish
+			       isb" :: "r"(addr >> 12) :: "volatile"),
+	    }
+	}
+    }
+
+    /// Maps `start..end` into the kernel page table 1:1 for a device to
+    /// share with the kernel. Returns `false` if part of it is mapped
+    /// already. See `KernPageTable::map_uncached()`.
+    pub fn map_uncached(&self, start: usize, end: usize) -> bool {
+	let mapped = self.kern_pt.lock().as_mut().expect("vm setup").map_uncached(start, end);
+	// the entries were invalid, so no TLB holds them
+	unsafe {
+	    asm!("dsb ishst
+		  isb" :::: "volatile");
+	}
+	mapped
     }
 
     /// Returns the base address of the kernel page table as `PhysicalAddr`.
     pub fn get_baddr(&self) -> PhysicalAddr {
 	self.kern_pt.lock().as_ref().unwrap().get_baddr()
     }
+
+    /// Returns the pages mapped by the kernel page table and the page tables
+    /// of all processes, with the memory the tables take. The threads of a
+    /// process share one table, which is counted once.
+    pub fn stats(&self) -> VmStats {
+	let mut stats = match self.kern_pt.lock().as_ref() {
+	    Some(kern_pt) => kern_pt.stats(),
+	    None => VmStats::default(),
+	};
+	for process in SCHEDULER.snapshot() {
+	    stats += process.vmap.lock().stats();
+	}
+	stats
+    }
+
+    /// Walks the kernel page table and the page tables of all processes and
+    /// returns every entry that breaks an invariant: misaligned tables or
+    /// pages, a clear access flag, shareability that does not match the
+    /// memory type, pages outside of RAM or the peripherals, or permissions
+    /// that do not match the table.
+    pub fn audit(&self) -> Vec<AuditIssue> {
+	let mut issues = Vec::new();
+	if let Some(kern_pt) = self.kern_pt.lock().as_ref() {
+	    kern_pt.audit(0, false, |va, reason| issues.push(AuditIssue { pid: None, va: va, reason: reason }));
+	}
+	for process in SCHEDULER.snapshot() {
+	    let pid = process.pid;
+	    process.vmap.lock().audit(USER_IMG_BASE, true, |va, reason| issues.push(AuditIssue { pid: Some(pid), va: va, reason: reason }));
+	}
+	issues
+    }
 }
@@ -0,0 +1,169 @@
+//! Address space identifiers. User pages are mapped non-global, so the TLB
+//! tags their translations with the ASID in `TTBR1_EL1` and keeps those of
+//! every process across a context switch. A page table gets an ASID the
+//! first time it is switched to; once all of them are handed out, a new
+//! generation starts and each table gets a fresh ASID when it is switched to
+//! next.
+//!
+//! The other cores keep running with the ASID of the old generation they
+//! switched to last, so a generation starts by reserving the ASID of every
+//! core and leaving each a flush to do before its next switch, as Linux
+//! does: until then a core may cache translations under ASIDs that are
+//! handed out again, but only uses its own, which no other table gets.
+
+use aarch64::affinity;
+
+use crate::mutex::Mutex;
+use crate::param::NCORES;
+
+/// ASIDs are 8 bits wide (`TCR_EL1.AS` clear)
+const ASID_BITS: u64 = 8;
+const ASID_MASK: u64 = (1 << ASID_BITS) - 1;
+
+/// ASID 0 stays with the kernel page table, installed in `TTBR1_EL1` before
+/// any process runs.
+const FIRST_ASID: u64 = 1;
+
+static ASIDS: Mutex<Allocator> = Mutex::new(Allocator {
+    generation: 1,
+    next: FIRST_ASID,
+    active: [0; NCORES],
+    reserved: [0; NCORES],
+    flush_pending: 0,
+});
+
+struct Allocator {
+    generation: u64,
+    next: u64,
+    /// The ASID each core last switched to
+    active: [u64; NCORES],
+    /// The ASIDs the cores held when the generation started, not handed
+    /// out again until the next one: each core keeps running with its own
+    /// until it switches.
+    reserved: [u64; NCORES],
+    /// Bit `n` is set while core `n` has a TLB flush to do before it
+    /// switches to an ASID of the current generation.
+    flush_pending: u64,
+}
+
+/// The ASID of a page table, tagged with the generation it was handed out
+/// in.
+#[derive(Copy, Clone, Debug)]
+pub struct Asid(u64);
+
+impl Asid {
+    /// An ASID that is stale in every generation, for a table that was
+    /// never switched to.
+    pub const NONE: Asid = Asid(0);
+
+    fn generation(self) -> u64 {
+	self.0 >> ASID_BITS
+    }
+
+    /// The ASID itself, as written to `TTBR1_EL1` and `tlbi` operands
+    pub fn value(self) -> u64 {
+	self.0 & ASID_MASK
+    }
+
+    /// Returns the ASID to switch to the table with on this core, handing out
+    /// a new one if it is stale.
+    pub fn activate(&mut self) -> u64 {
+	let core = affinity();
+	let mut asids = ASIDS.lock();
+	if self.generation() != asids.generation {
+	    while asids.next <= ASID_MASK && asids.reserved.contains(&asids.next) {
+		asids.next += 1;
+	    }
+	    if asids.next > ASID_MASK {
+		asids.generation += 1;
+		asids.reserved = asids.active;
+		asids.flush_pending = (1 << NCORES) - 1;
+		asids.next = FIRST_ASID;
+		while asids.reserved.contains(&asids.next) {
+		    asids.next += 1;
+		}
+	    }
+	    *self = Asid(asids.generation << ASID_BITS | asids.next);
+	    asids.next += 1;
+	}
+	if asids.flush_pending & 1 << core != 0 {
+	    asids.flush_pending &= !(1 << core);
+	    flush_local();
+	}
+	asids.active[core] = self.value();
+	self.value()
+    }
+
+    /// Drops the translation of the page at `va` cached for this ASID. A
+    /// table of an old generation may still be running on a core that has
+    /// not switched since, under an ASID that may be handed out again, so
+    /// its page is dropped for every ASID instead.
+    pub fn flush_page(self, va: usize) {
+	let page = (va as u64 >> 12) & ((1 << 44) - 1);
+	match self.state() {
+	    State::Never => {},
+	    State::Current => unsafe {
+		asm!("dsb ishst
+		      tlbi vae1is, $0
+		      dsb ish
+		      isb" :: "r"(self.value() << 48 | page) :: "volatile");
+	    },
+	    State::Stale => unsafe {
+		asm!("dsb ishst
+		      tlbi vaae1is, $0
+		      dsb ish
+		      isb" :: "r"(page) :: "volatile");
+	    },
+	}
+    }
+
+    /// Drops every translation cached for this ASID, or every translation
+    /// of every ASID if it is of an old generation, see `flush_page()`.
+    pub fn flush(self) {
+	match self.state() {
+	    State::Never => {},
+	    State::Current => unsafe {
+		asm!("dsb ishst
+		      tlbi aside1is, $0
+		      dsb ish
+		      isb" :: "r"(self.value() << 48) :: "volatile");
+	    },
+	    State::Stale => unsafe {
+		asm!("dsb ishst
+		      tlbi vmalle1is
+		      dsb ish
+		      isb" :::: "volatile");
+	    },
+	}
+    }
+
+    fn state(self) -> State {
+	if self.0 == Asid::NONE.0 {
+	    State::Never
+	}
+	else if self.generation() == ASIDS.lock().generation {
+	    State::Current
+	}
+	else {
+	    State::Stale
+	}
+    }
+}
+
+/// Whether an ASID was never handed out, so that nothing is cached under
+/// it, is of the current generation, or of an old one.
+enum State {
+    Never,
+    Current,
+    Stale,
+}
+
+/// Drops every translation in the TLB of this core, of every ASID.
+fn flush_local() {
+    unsafe {
+	asm!("dsb nshst
+	      tlbi vmalle1
+	      dsb nsh
+	      isb" :::: "volatile");
+    }
+}
@@ -0,0 +1,110 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::cmp::{max, min};
+
+use shim::io::{self, Read, Seek, SeekFrom};
+
+use crate::fs::vfs::Handle;
+use crate::mutex::Mutex;
+use crate::param::{PAGE_SIZE, USER_STACK_MAX_PAGES};
+use crate::vm::shm::Segment;
+use crate::vm::{PagePerm, PhysicalAddr};
+
+/// Where the bytes of a page mapped on first touch come from
+#[derive(Clone)]
+pub enum Backing {
+    /// nowhere, the page starts out zeroed
+    Zero,
+    /// `filesz` bytes of `file` starting at `offset`, placed at the start of
+    /// the region; the rest of the region is zero
+    File { file: Arc<Mutex<Box<dyn Handle>>>, offset: u64, filesz: u64 },
+    /// the pages of a shared memory segment as long as the region, mapped
+    /// rather than copied
+    Shared(Arc<Segment>),
+}
+
+/// A range of user addresses whose pages are allocated when they are first
+/// touched rather than when the range is set up
+#[derive(Clone)]
+pub struct Region {
+    pub start: usize,
+    /// length in bytes, not zero; the region may end at the very top of the
+    /// address space, as the main thread's stack does
+    pub len: usize,
+    pub perm: PagePerm,
+    pub backing: Backing,
+    /// bytes the region may still grow downwards by when the page right
+    /// below it is touched, as stacks do; 0 for regions that do not grow
+    pub growth: usize,
+}
+
+impl Region {
+    /// Returns a stack of one zeroed, read/write page at `base`, which grows
+    /// to `USER_STACK_MAX_PAGES` pages.
+    pub fn stack(base: usize) -> Region {
+	Region {
+	    start: base,
+	    len: PAGE_SIZE,
+	    perm: PagePerm::RW,
+	    backing: Backing::Zero,
+	    growth: (USER_STACK_MAX_PAGES - 1) * PAGE_SIZE,
+	}
+    }
+
+    /// Address of the last byte of the region
+    pub fn last(&self) -> usize {
+	self.start + (self.len - 1)
+    }
+
+    /// Returns `true` if the page at `page` overlaps the region.
+    pub fn overlaps(&self, page: usize) -> bool {
+	page <= self.last() && page + (PAGE_SIZE - 1) >= self.start
+    }
+
+    /// Returns `true` if the region can grow down to the page at `page`,
+    /// which lies below it.
+    pub fn can_grow_to(&self, page: usize) -> bool {
+	page < self.start && self.start - page <= self.growth
+    }
+
+    /// Extends the region down to the page at `page`, which it can grow to.
+    /// Only the zeroed part of a region can be below its start, so the
+    /// region must be backed by zeroes.
+    pub fn grow_to(&mut self, page: usize) {
+	debug_assert!(self.can_grow_to(page));
+	let by = self.start - page;
+	self.start = page;
+	self.len += by;
+	self.growth -= by;
+    }
+
+    /// Returns the page of the segment backing the region that is mapped at
+    /// `page`, or `None` if the region is not backed by a segment.
+    pub fn shared_frame(&self, page: usize) -> Option<PhysicalAddr> {
+	match &self.backing {
+	    Backing::Shared(segment) => Some(segment.frame(page - self.start)),
+	    _ => None,
+	}
+    }
+
+    /// Copies the part of the file backing the region that falls into the
+    /// page at `page` into `frame`, which is zeroed.
+    pub fn fill(&self, page: usize, frame: &mut [u8]) -> io::Result<()> {
+	let (file, offset, filesz) = match &self.backing {
+	    Backing::Zero | Backing::Shared(_) => return Ok(()),
+	    Backing::File { file, offset, filesz } => (file, *offset, *filesz as usize),
+	};
+	if filesz == 0 {
+	    return Ok(());
+	}
+	// inclusive, so that neither end overflows at the top of memory
+	let first = max(page, self.start);
+	let last = min(page + (PAGE_SIZE - 1), self.start + (filesz - 1));
+	if first > last {
+	    return Ok(());
+	}
+	let mut file = file.lock();
+	file.seek(SeekFrom::Start(offset + (first - self.start) as u64))?;
+	file.read_exact(&mut frame[first - page..=last - page])
+    }
+}
@@ -0,0 +1,267 @@
+//! Physical page frames, with a count of the owners of each. User pages,
+//! shared memory segments and the page cache take their memory a frame at a
+//! time from here rather than from the heap directly; the frames themselves
+//! still come out of the heap. A frame mapped copy-on-write by several page
+//! tables has one reference for each of them, and goes back to the heap
+//! once the last one lets it go.
+//!
+//! Debug builds track where each frame was allocated and released. A
+//! released frame is poisoned, unmapped from the kernel and held back in a
+//! quarantine of the last `FRAME_QUARANTINE` frames rather than handed to
+//! the heap at once. The kernel touching a frame in quarantine through a
+//! stale pointer faults on the spot, and the kernel panics naming both
+//! sites and the instruction. A process can still write to one through a
+//! stale mapping of its own; that shows as changed poison when the frame
+//! leaves quarantine or `check()` looks, and the kernel panics the same way.
+
+use core::alloc::{GlobalAlloc, Layout};
+
+use crate::allocator;
+use crate::mutex::Mutex;
+use crate::param::{FRAME_QUARANTINE, IO_BASE, PAGE_SIZE};
+use crate::vm::PhysicalAddr;
+use crate::ALLOCATOR;
+#[cfg(debug_assertions)]
+use crate::VMM;
+
+/// Frames RAM holds, all of it lying below the peripherals
+const NR_FRAMES: usize = IO_BASE / PAGE_SIZE;
+
+/// Byte released frames are filled with in debug builds, so that a stale
+/// mapping reads an obvious pattern instead of plausible zeros
+const FRAME_POISON: u8 = 0x6b;
+
+/// References to each frame by its number, 0 for frames not allocated here
+static REFS: Mutex<[u16; NR_FRAMES]> = Mutex::new([0; NR_FRAMES]);
+
+/// Return address of the `alloc()` call of each allocated frame by its
+/// number. Kernel code lies below 4 GiB, the alias of `kaslr` included.
+#[cfg(debug_assertions)]
+static SITES: Mutex<[u32; NR_FRAMES]> = Mutex::new([0; NR_FRAMES]);
+
+/// A released frame waiting to go back to the heap.
+#[cfg(debug_assertions)]
+#[derive(Copy, Clone)]
+struct Released {
+    frame: PhysicalAddr,
+    allocated_at: usize,
+    released_at: usize,
+}
+
+/// The frames released last, oldest at `next` once full
+#[cfg(debug_assertions)]
+struct Quarantine {
+    frames: [Option<Released>; FRAME_QUARANTINE],
+    next: usize,
+}
+
+#[cfg(debug_assertions)]
+static QUARANTINE: Mutex<Quarantine> = Mutex::new(Quarantine { frames: [None; FRAME_QUARANTINE], next: 0 });
+
+/// Counts of the frames handed out, as listed in `/proc/meminfo`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameStats {
+    /// frames allocated
+    pub frames: usize,
+    /// frames with more than one owner
+    pub shared: usize,
+    /// references to all frames together
+    pub refs: usize,
+}
+
+fn layout() -> Layout {
+    unsafe { Layout::from_size_align_unchecked(PAGE_SIZE, PAGE_SIZE) }
+}
+
+/// Returns the number of `frame`.
+///
+/// # Panics
+///
+/// Panics if `frame` is not the address of a frame in RAM.
+fn number(frame: PhysicalAddr) -> usize {
+    let addr = frame.as_usize();
+    assert!(addr % PAGE_SIZE == 0 && addr < IO_BASE, "not a frame: {:?}", frame);
+    addr / PAGE_SIZE
+}
+
+/// Returns the address the function this is inlined into returns to. It is
+/// only right before that function makes a call of its own.
+#[cfg(all(debug_assertions, target_arch = "aarch64"))]
+#[inline(always)]
+fn return_address() -> usize {
+    let lr: usize;
+    unsafe {
+	asm!("mov $0, x30" : "=r"(lr) ::: "volatile");
+    }
+    lr
+}
+
+#[cfg(all(debug_assertions, not(target_arch = "aarch64")))]
+fn return_address() -> usize {
+    0
+}
+
+/// Allocates a zeroed frame with a single reference. Returns `None` if
+/// memory is exhausted.
+#[inline(never)]
+pub fn alloc() -> Option<PhysicalAddr> {
+    #[cfg(debug_assertions)]
+    let caller = return_address();
+    let ptr = unsafe { ALLOCATOR.alloc_zeroed(layout()) };
+    if ptr.is_null() {
+	return None;
+    }
+    let frame = PhysicalAddr::from(ptr);
+    REFS.lock()[number(frame)] = 1;
+    #[cfg(debug_assertions)]
+    {
+	SITES.lock()[number(frame)] = caller as u32;
+    }
+    Some(frame)
+}
+
+/// Adds a reference to `frame`.
+///
+/// # Panics
+///
+/// Panics if `frame` was not allocated by `alloc()` or is not allocated any
+/// longer.
+pub fn share(frame: PhysicalAddr) {
+    let mut refs = REFS.lock();
+    let count = &mut refs[number(frame)];
+    assert!(*count != 0, "sharing free frame {:?}", frame);
+    *count += 1;
+}
+
+/// Drops a reference to `frame`, and clears it and returns it to the heap if
+/// it was the last one: the kernel cannot tell which frames held secrets, so
+/// none of them reach the next owner with their contents. Debug builds
+/// poison the frame and quarantine it instead, returning the oldest frame
+/// in quarantine to the heap. Returns `true` if the frame was freed.
+///
+/// # Panics
+///
+/// Panics if `frame` was not allocated by `alloc()` or is not allocated any
+/// longer, or in debug builds if the frame leaving quarantine was written to.
+#[inline(never)]
+pub fn release(frame: PhysicalAddr) -> bool {
+    #[cfg(debug_assertions)]
+    let caller = return_address();
+    {
+	let mut refs = REFS.lock();
+	let count = &mut refs[number(frame)];
+	assert!(*count != 0, "releasing free frame {:?}", frame);
+	*count -= 1;
+	if *count != 0 {
+	    return false;
+	}
+    }
+    #[cfg(debug_assertions)]
+    quarantine(frame, caller);
+    #[cfg(not(debug_assertions))]
+    free(frame);
+    true
+}
+
+/// Clears `frame` and returns it to the heap.
+#[cfg(not(debug_assertions))]
+fn free(mut frame: PhysicalAddr) {
+    unsafe {
+	allocator::zeroize(frame.as_mut_ptr(), PAGE_SIZE);
+	ALLOCATOR.dealloc(frame.as_mut_ptr(), layout());
+    }
+}
+
+/// Poisons `frame`, released from `caller`, unmaps it and puts it in
+/// quarantine in place of the oldest frame there, which is mapped again and
+/// goes back to the heap once its poison is checked.
+#[cfg(debug_assertions)]
+fn quarantine(mut frame: PhysicalAddr, caller: usize) {
+    let allocated_at = core::mem::replace(&mut SITES.lock()[number(frame)], 0) as usize;
+    unsafe { allocator::poison(frame.as_mut_ptr(), PAGE_SIZE, FRAME_POISON) };
+    let released = Released { frame, allocated_at, released_at: caller };
+    let oldest = {
+	let mut quarantine = QUARANTINE.lock();
+	let next = quarantine.next;
+	quarantine.next = (next + 1) % FRAME_QUARANTINE;
+	VMM.set_present(frame.as_usize(), false);
+	let oldest = core::mem::replace(&mut quarantine.frames[next], Some(released));
+	if let Some(oldest) = oldest.as_ref() {
+	    VMM.set_present(oldest.frame.as_usize(), true);
+	}
+	oldest
+    };
+    if let Some(mut oldest) = oldest {
+	verify(&oldest);
+	unsafe { ALLOCATOR.dealloc(oldest.frame.as_mut_ptr(), layout()) };
+    }
+}
+
+/// Panics with where `released` was allocated and released if its poison
+/// has changed since. The frame must be mapped.
+#[cfg(debug_assertions)]
+fn verify(released: &Released) {
+    let bytes = unsafe { core::slice::from_raw_parts(released.frame.as_ptr(), PAGE_SIZE) };
+    if let Some(offset) = bytes.iter().position(|&byte| byte != FRAME_POISON) {
+	panic!("frame {:?} written after it was released: byte {:#x} is {:#x}, \
+		allocated at {:#x}, released at {:#x}",
+	       released.frame, offset, bytes[offset], released.allocated_at, released.released_at);
+    }
+}
+
+/// Checks the poison of every frame in quarantine, mapping each for the
+/// time it takes.
+///
+/// # Panics
+///
+/// Panics with where a frame was allocated and released if it was written
+/// to since.
+#[cfg(debug_assertions)]
+pub fn check() {
+    let quarantine = QUARANTINE.lock();
+    for released in quarantine.frames.iter().flatten() {
+	VMM.set_present(released.frame.as_usize(), true);
+	verify(released);
+	VMM.set_present(released.frame.as_usize(), false);
+    }
+}
+
+/// Returns true if `va`, the address a kernel access faulted on, lies in a
+/// frame in quarantine.
+#[cfg(debug_assertions)]
+pub fn is_quarantined(va: usize) -> bool {
+    find(va).is_some()
+}
+
+/// Panics with where the frame in quarantine holding `va` was allocated and
+/// released, for a kernel access to it by the instruction at `pc`.
+#[cfg(debug_assertions)]
+pub fn touched(va: usize, pc: usize) -> ! {
+    match find(va) {
+	Some(released) => panic!("frame {:?} touched at {:#x} by the instruction at {:#x} after it was \
+				  released, allocated at {:#x}, released at {:#x}",
+				 released.frame, va, pc, released.allocated_at, released.released_at),
+	None => panic!("{:#x} touched by the instruction at {:#x} is not in a released frame", va, pc),
+    }
+}
+
+#[cfg(debug_assertions)]
+fn find(va: usize) -> Option<Released> {
+    let frame = va & !(PAGE_SIZE - 1);
+    QUARANTINE.lock().frames.iter().flatten().find(|released| released.frame.as_usize() == frame).cloned()
+}
+
+/// Returns the number of references to `frame`, 0 if it is not allocated.
+pub fn refs(frame: PhysicalAddr) -> usize {
+    REFS.lock()[number(frame)] as usize
+}
+
+pub fn stats() -> FrameStats {
+    let refs = REFS.lock();
+    refs.iter().filter(|&&count| count != 0).fold(FrameStats::default(), |mut stats, &count| {
+	stats.frames += 1;
+	stats.shared += (count > 1) as usize;
+	stats.refs += count as usize;
+	stats
+    })
+}
@@ -3,21 +3,46 @@ use core::ops::{Deref, DerefMut};
 use core::slice::Iter;
 
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::fmt;
+use alloc::sync::Arc;
 use alloc::vec;
-use core::alloc::{GlobalAlloc, Layout};
+use alloc::vec::Vec;
 use core::mem::size_of;
 
 use crate::allocator;
+use crate::dma;
+use crate::dtb;
+use crate::kaslr;
 use crate::param::*;
-use crate::vm::{PhysicalAddr, VirtualAddr};
-use crate::ALLOCATOR;
+use crate::vdso;
+use crate::vm::asid::Asid;
+use crate::vm::frame;
+use crate::vm::shm::Segment;
+use crate::vm::{Backing, PhysicalAddr, Region, VirtualAddr, VmStats};
 
 use aarch64::vmsa::*;
 use shim::const_assert_size;
 
 const TABLE_SIZE: usize = PAGE_SIZE / size_of::<u64>();
 
+/// Value of the software bits of an L3 entry mapping a page the table does
+/// not own, such as the vDSO page.
+const SW_SHARED: u64 = 0b0001;
+
+/// Value of the software bits of an L3 entry mapping a page read-only that
+/// the table shares with the tables it was duplicated from or into, until
+/// one of them writes to it.
+const SW_COW: u64 = 0b0010;
+
+/// Value of the software bits of an L3 entry mapping a page shared like
+/// `SW_COW` that is not writable itself, so that writing to it faults.
+const SW_COW_RO: u64 = 0b0100;
+
+/// Bits of a page or block descriptor that tell mappings apart in the
+/// `fmt::Debug` output of a table
+const DUMP_MASK: u64 = RawL3Entry::SW | RawL3Entry::UXN | RawL3Entry::PXN | RawL3Entry::AP | RawL3Entry::ATTR;
+
 #[repr(C)]
 pub struct Page([u8; PAGE_SIZE]);
 const_assert_size!(Page, PAGE_SIZE);
@@ -25,10 +50,6 @@ const_assert_size!(Page, PAGE_SIZE);
 impl Page {
     pub const SIZE: usize = PAGE_SIZE;
     pub const ALIGN: usize = PAGE_SIZE;
-
-    fn layout() -> Layout {
-        unsafe { Layout::from_size_align_unchecked(Self::SIZE, Self::ALIGN) }
-    }
 }
 
 #[repr(C)]
@@ -65,6 +86,27 @@ impl L3Entry {
 	self.0.get_value(RawL2Entry::VALID) != 0
     }
 
+    /// Returns `true` if the L3Entry maps a page the table does not own.
+    fn is_shared(&self) -> bool {
+	self.is_valid() && self.0.get_value(RawL3Entry::SW) == SW_SHARED
+    }
+
+    /// Returns `true` if the L3Entry maps a page shared copy-on-write.
+    fn is_cow(&self) -> bool {
+	match self.0.get_value(RawL3Entry::SW) {
+	    SW_COW | SW_COW_RO => self.is_valid(),
+	    _ => false,
+	}
+    }
+
+    /// Returns the permission the page is mapped with, counting a page shared
+    /// copy-on-write as writable if writing to it copies it.
+    fn perm(&self) -> PagePerm {
+	let writable = self.0.get_value(RawL3Entry::AP) == EntryPerm::USER_RW
+	    || self.0.get_value(RawL3Entry::SW) == SW_COW;
+	PagePerm::new(writable, self.0.get_value(RawL3Entry::UXN) == 0)
+    }
+
     /// Extracts `ADDR` field of the L3Entry and returns as a `PhysicalAddr`
     /// if valid. Otherwise, return `None`.
     fn get_page_addr(&self) -> Option<PhysicalAddr> {
@@ -98,7 +140,9 @@ impl L3PageTable {
 #[repr(align(65536))]
 pub struct PageTable {
     pub l2: L2PageTable,
-    pub l3: [Box<L3PageTable>; 3],
+    /// The L3 table of each of the first L2 entries, or `None` where the
+    /// entry maps a block instead, as only the kernel table's do
+    pub l3: [Option<Box<L3PageTable>>; 3],
 }
 
 impl PageTable {
@@ -108,12 +152,13 @@ impl PageTable {
 
 	let mut table = Box::new(PageTable {
             l2: L2PageTable::new(),
-            l3: [Box::new(L3PageTable::new()), Box::new(L3PageTable::new()), Box::new(L3PageTable::new())],
+            l3: [Some(Box::new(L3PageTable::new())), Some(Box::new(L3PageTable::new())), Some(Box::new(L3PageTable::new()))],
         });
 
-	for (index, l3) in table.l3.iter().enumerate() {
+	for index in 0..table.l3.len() {
+	    let l3 = table.l3_table(index).as_ptr();
 	    let entry = &mut table.l2.entries[index];
-	    entry.set_value(l3.as_ptr().as_u64() >> PAGE_ALIGN, RawL2Entry::ADDR);	    
+	    entry.set_value(l3.as_u64() >> PAGE_ALIGN, RawL2Entry::ADDR);
 	    entry.set_value(1, RawL2Entry::AF);
 	    entry.set_value(EntrySh::ISh, RawL2Entry::SH);
 	    entry.set_value(perm, RawL2Entry::AP);
@@ -126,7 +171,8 @@ impl PageTable {
     }
 
     /// Returns the (L2index, L3index) extracted from the given virtual address.
-    /// L2index should be smaller than the number of L3PageTable.
+    /// L2index should be smaller than the number of L3PageTable. User
+    /// addresses are located from `USER_IMG_BASE`, kernel ones from 0.
     ///
     /// # Panics
     ///
@@ -134,20 +180,40 @@ impl PageTable {
     /// Panics if extracted L2index exceeds the number of L3PageTable.
     fn locate(va: VirtualAddr) -> (usize, usize) {
 	assert_eq!(va.as_u64() as usize % PAGE_SIZE, 0);
-	let num_l3 = 2;
-	let il3 = (va.as_u64() >> 16) & 0x1FFF;
-	let il2 = ((va.as_u64() >> 16) >> 13) & 0x01;
+	let num_l3 = 3;
+	let offset = match va.as_usize() >= USER_IMG_BASE {
+	    true => va.as_usize() - USER_IMG_BASE,
+	    false => va.as_usize(),
+	};
+	let il3 = (offset >> 16) & 0x1FFF;
+	let il2 = (offset >> 16) >> 13;
 
 	assert!(il2 < num_l3);
 
-	(il2 as usize, il3 as usize)
+	(il2, il3)
+    }
+
+    /// Returns the L3 table of the L2 entry at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the L2 entry maps a block.
+    fn l3_table(&self, index: usize) -> &L3PageTable {
+	self.l3[index].as_ref().expect("L2 entry maps a block")
+    }
+
+    fn l3_table_mut(&mut self, index: usize) -> &mut L3PageTable {
+	self.l3[index].as_mut().expect("L2 entry maps a block")
     }
 
     /// Returns `true` if the L3entry indicated by the given virtual address is valid.
-    /// Otherwise, `false` is returned.
+    /// Otherwise, `false` is returned. An address within a block is valid.
     pub fn is_valid(&self, va: VirtualAddr) -> bool {
         let (l2, l3) = PageTable::locate(va);
-        self.l3[l2].entries[l3].is_valid()
+	match &self.l3[l2] {
+	    Some(table) => table.entries[l3].is_valid(),
+	    None => self.l2.entries[l2].get_value(RawL2Entry::VALID) == EntryValid::Valid,
+	}
     }
 
     /// Returns `true` if the L3entry indicated by the given virtual address is invalid.
@@ -160,18 +226,18 @@ impl PageTable {
     /// address.
     pub fn set_entry(&mut self, va: VirtualAddr, entry: RawL3Entry) -> &mut Self {
         let (l2, l3) = PageTable::locate(va);
-        self.l3[l2].entries[l3].0 = entry;
+        self.l3_table_mut(l2).entries[l3].0 = entry;
         self
     }
 
     pub fn get_entry(&self, va: VirtualAddr) -> &L3Entry {
         let (l2, l3) = PageTable::locate(va);
-        &self.l3[l2].entries[l3]
+        &self.l3_table(l2).entries[l3]
     }
 
     pub fn get_entry_mut(&mut self, va: VirtualAddr) -> &mut L3Entry {
         let (l2, l3) = PageTable::locate(va);
-        &mut self.l3[l2].entries[l3]
+        &mut self.l3_table_mut(l2).entries[l3]
     }
 
     /// Returns a base address of the pagetable. The returned `PhysicalAddr` value
@@ -179,98 +245,526 @@ impl PageTable {
     pub fn get_baddr(&self) -> PhysicalAddr {
         self.l2.as_ptr()
     }
+
+    /// Returns the number of pages of each kind the table maps and the memory
+    /// taken by the table itself.
+    pub fn stats(&self) -> VmStats {
+	let mut stats = VmStats {
+	    tables: 1,
+	    table_bytes: (1 + self.l3.iter().flatten().count()) * PAGE_SIZE,
+	    ..VmStats::default()
+	};
+	for index in (0..self.l3.len()).filter(|&index| self.l3[index].is_none()) {
+	    let block = self.l2.entries[index];
+	    if block.get_value(RawL2Entry::VALID) == EntryValid::Valid {
+		stats.blocks += 1;
+		match block.get_value(RawL2Entry::ATTR) {
+		    EntryAttr::Dev => stats.device_pages += TABLE_SIZE,
+		    _ => stats.kernel_pages += TABLE_SIZE,
+		}
+	    }
+	}
+	for l3 in self.l3.iter().flatten() {
+	    for entry in l3.entries.iter().filter(|entry| entry.is_valid()) {
+		if entry.is_shared() {
+		    stats.shared_pages += 1;
+		}
+		else if entry.is_cow() {
+		    stats.user_pages += 1;
+		    stats.cow_pages += 1;
+		}
+		else if entry.0.get_value(RawL3Entry::ATTR) == EntryAttr::Dev {
+		    stats.device_pages += 1;
+		}
+		else {
+		    match entry.0.get_value(RawL3Entry::AP) {
+			EntryPerm::USER_RW | EntryPerm::USER_RO => stats.user_pages += 1,
+			_ => stats.kernel_pages += 1,
+		    }
+		}
+	    }
+	}
+	stats
+    }
+
+    /// Walks the table and calls `report` with the virtual address and a
+    /// description of every entry that breaks an invariant. `base` is the
+    /// virtual address the first L3 entry translates; `user` is set for user
+    /// page tables, whose pages must be accessible from EL0.
+    pub fn audit<F: FnMut(VirtualAddr, &'static str)>(&self, base: usize, user: bool, mut report: F) {
+	let va = |index: usize| VirtualAddr::from(base.wrapping_add(index * PAGE_SIZE));
+
+	if self.l2.as_ptr().as_usize() % PAGE_SIZE != 0 {
+	    report(va(0), "L2 table is not page aligned");
+	}
+	for (index, entry) in self.l2.entries.iter().enumerate() {
+	    let valid = entry.get_value(RawL2Entry::VALID) == EntryValid::Valid;
+	    if index >= self.l3.len() {
+		if valid {
+		    report(va(index * TABLE_SIZE), "L2 entry without an L3 table is valid");
+		}
+		continue;
+	    }
+	    let l3 = match &self.l3[index] {
+		Some(l3) => l3.as_ptr().as_u64(),
+		None => {
+		    if user {
+			report(va(index * TABLE_SIZE), "user table maps a block");
+		    }
+		    else if valid && entry.get_value(RawL2Entry::TYPE) != EntryType::Block {
+			report(va(index * TABLE_SIZE), "L2 entry without an L3 table is not a block descriptor");
+		    }
+		    else if valid {
+			// bits [28:16] of the output address are RES0 for a 512MiB block
+			if entry.get() & ((TABLE_SIZE * PAGE_SIZE - 1) as u64) & !0xFFFF != 0 {
+			    report(va(index * TABLE_SIZE), "output address is not block aligned");
+			}
+			PageTable::audit_output(va(index * TABLE_SIZE), RawL3Entry::new(entry.get()), TABLE_SIZE * PAGE_SIZE, user, &mut report);
+		    }
+		    continue;
+		},
+	    };
+	    if l3 as usize % PAGE_SIZE != 0 {
+		report(va(index * TABLE_SIZE), "L3 table is not page aligned");
+	    }
+	    if !valid {
+		report(va(index * TABLE_SIZE), "L2 entry of an L3 table is invalid");
+	    }
+	    else if entry.get_value(RawL2Entry::TYPE) != EntryType::Table {
+		report(va(index * TABLE_SIZE), "L2 entry is not a table descriptor");
+	    }
+	    else if entry.get_value(RawL2Entry::ADDR) != l3 >> PAGE_ALIGN {
+		report(va(index * TABLE_SIZE), "L2 entry does not point at its L3 table");
+	    }
+	}
+
+	for (table, l3) in self.l3.iter().enumerate() {
+	    let l3 = match l3 {
+		Some(l3) => l3,
+		None => continue,
+	    };
+	    for (index, entry) in l3.entries.iter().enumerate().filter(|(_, entry)| entry.is_valid()) {
+		let va = va(table * TABLE_SIZE + index);
+		let raw = entry.0;
+		if raw.get_value(RawL3Entry::TYPE) != PageType::Page {
+		    report(va, "L3 entry is not a page descriptor");
+		}
+		// bits [15:12] of the output address are RES0 with a 64KiB granule
+		if raw.get() & 0xF000 != 0 {
+		    report(va, "output address is not page aligned");
+		}
+		PageTable::audit_output(va, raw, PAGE_SIZE, user, &mut report);
+	    }
+	}
+    }
+
+    /// Writes one line for every run of pages mapped with the same
+    /// permissions and attributes, with the addresses counted from `base`,
+    /// the virtual address the first L3 entry translates.
+    fn fmt_mappings(&self, f: &mut fmt::Formatter, base: usize) -> fmt::Result {
+	// the first page, end and attribute bits of the run being merged
+	let mut run: Option<(usize, usize, u64)> = None;
+	for (table, l3) in self.l3.iter().enumerate() {
+	    // a block is a single descriptor with the same bits as a page's
+	    let (count, len) = match l3 {
+		Some(_) => (TABLE_SIZE, PAGE_SIZE),
+		None => (1, TABLE_SIZE * PAGE_SIZE),
+	    };
+	    let mut offset = table * TABLE_SIZE * PAGE_SIZE;
+	    for index in 0..count {
+		let raw = match l3 {
+		    Some(l3) => l3.entries[index].0,
+		    None => RawL3Entry::new(self.l2.entries[table].get()),
+		};
+		let bits = match raw.get_value(RawL3Entry::VALID) == EntryValid::Valid {
+		    true => Some(raw.get() & DUMP_MASK),
+		    false => None,
+		};
+		run = match (run, bits) {
+		    (Some((start, _, current)), Some(bits)) if current == bits => Some((start, offset + len, bits)),
+		    (previous, bits) => {
+			if let Some((start, end, current)) = previous {
+			    PageTable::fmt_run(f, base.wrapping_add(start), base.wrapping_add(end - 1), current)?;
+			}
+			bits.map(|bits| (offset, offset + len, bits))
+		    },
+		};
+		offset += len;
+	    }
+	}
+	if let Some((start, end, current)) = run {
+	    PageTable::fmt_run(f, base.wrapping_add(start), base.wrapping_add(end - 1), current)?;
+	}
+	Ok(())
+    }
+
+    /// Writes the line of `fmt_mappings()` for `first..=last` mapped with the
+    /// attribute bits `bits`.
+    fn fmt_run(f: &mut fmt::Formatter, first: usize, last: usize, bits: u64) -> fmt::Result {
+	let raw = RawL3Entry::new(bits);
+	let ap = raw.get_value(RawL3Entry::AP);
+	let user = ap == EntryPerm::USER_RW || ap == EntryPerm::USER_RO;
+	let writable = ap == EntryPerm::USER_RW || ap == EntryPerm::KERN_RW;
+	let executable = match user {
+	    true => raw.get_value(RawL3Entry::UXN) == 0,
+	    false => raw.get_value(RawL3Entry::PXN) == 0,
+	};
+	let memory = match raw.get_value(RawL3Entry::ATTR) {
+	    EntryAttr::Mem => "Normal",
+	    EntryAttr::Dev => "Device",
+	    EntryAttr::Nc => "NonCacheable",
+	    _ => "?",
+	};
+	write!(f, "{:#018x} - {:#018x} {} {} {} {}", first, last,
+	       if writable { "RW" } else { "RO" }, if executable { "X " } else { "NX" },
+	       if user { "user  " } else { "kernel" }, memory)?;
+	match raw.get_value(RawL3Entry::SW) {
+	    SW_SHARED => write!(f, " shared")?,
+	    SW_COW | SW_COW_RO => write!(f, " cow")?,
+	    _ => {},
+	}
+	writeln!(f)
+    }
+
+    /// Checks the attributes of a valid page or block descriptor mapping
+    /// `len` bytes at `va`, which are at the same bits in both, for
+    /// `audit()`.
+    fn audit_output<F: FnMut(VirtualAddr, &'static str)>(va: VirtualAddr, raw: RawL3Entry, len: usize, user: bool, report: &mut F) {
+	let mem_end = allocator::memory_map().map(|(_, end)| end).unwrap_or(0);
+	let (io_start, io_end) = io_range();
+	let pa = (raw.get_value(RawL3Entry::ADDR) as usize) << PAGE_ALIGN;
+	if raw.get_value(RawL3Entry::AF) == 0 {
+	    report(va, "access flag is clear");
+	}
+	match raw.get_value(RawL3Entry::ATTR) {
+	    EntryAttr::Mem => {
+		if raw.get_value(RawL3Entry::SH) != EntrySh::ISh {
+		    report(va, "normal memory is not inner shareable");
+		}
+		if pa + len > mem_end {
+		    report(va, "normal memory page is outside of RAM");
+		}
+	    },
+	    EntryAttr::Dev => {
+		if raw.get_value(RawL3Entry::SH) != EntrySh::OSh {
+		    report(va, "device memory is not outer shareable");
+		}
+		if pa < io_start || pa + len > io_end {
+		    report(va, "device page is outside of the peripheral range");
+		}
+	    },
+	    EntryAttr::Nc => {
+		// or memory between RAM and the peripherals the kernel shares
+		// with a device, see `KernPageTable::map_uncached()`
+		let in_zone = dma::zone().map_or(false, |(start, end)| pa >= start && pa + len <= end);
+		let in_hole = pa >= mem_end && pa + len <= io_start;
+		if user || !(in_zone || in_hole) {
+		    report(va, "non-cacheable page is outside of the DMA zone");
+		}
+	    },
+	    _ => report(va, "unexpected memory attribute"),
+	}
+	let user_page = match raw.get_value(RawL3Entry::AP) {
+	    EntryPerm::USER_RW | EntryPerm::USER_RO => true,
+	    _ => false,
+	};
+	if user && !user_page {
+	    report(va, "user table maps a kernel-only page");
+	}
+	else if !user && user_page {
+	    report(va, "kernel table maps a page accessible from EL0");
+	}
+    }
 }
 
+/// Iterates over the L3 entries of every L3 table in address order, so the
+/// `n`th entry translates the `n`th page from the base of the table. Panics
+/// for a table mapping blocks, which has no L3 entries for them.
 impl<'a> IntoIterator for &'a PageTable {
     type Item = &'a L3Entry;    
-    type IntoIter = Chain<Iter<'a, L3Entry>, Iter<'a, L3Entry>>;
+    type IntoIter = Chain<Chain<Iter<'a, L3Entry>, Iter<'a, L3Entry>>, Iter<'a, L3Entry>>;
     
     fn into_iter(self) -> Self::IntoIter {
-	self.l3[0].entries.iter().chain(self.l3[1].entries.iter())
+	self.l3_table(0).entries.iter().chain(self.l3_table(1).entries.iter()).chain(self.l3_table(2).entries.iter())
     }
 }
 
+/// Returns the (start address, end address) of the peripherals, from the
+/// device tree if the firmware passed one.
+fn io_range() -> (usize, usize) {
+    dtb::peripherals().unwrap_or((IO_BASE, IO_BASE_END))
+}
+
 pub struct KernPageTable(Box<PageTable>);
 
 impl KernPageTable {
     /// Returns a new `KernPageTable`. `KernPageTable` should have a `Pagetable`
     /// created with `KERN_RW` permission.
     ///
-    /// Set L3entry of ARM physical address starting at 0x00000000 for RAM and
-    /// physical address range from `IO_BASE` to `IO_BASE_END` for peripherals.
-    /// Each L3 entry should have correct value for lower attributes[10:0] as well
-    /// as address[47:16]. Refer to the definition of `RawL3Entry` in `vmsa.rs` for
-    /// more details.
+    /// Maps RAM from physical address 0x00000000 and the peripherals the
+    /// device tree names, or `IO_BASE` to `IO_BASE_END` without one, 1:1,
+    /// and the DMA zone non-cacheable. Where an L2 entry covers only RAM
+    /// outside the DMA zone or only peripherals, it maps a 512MiB block and
+    /// its L3 table is dropped; the rest is mapped page by page. Refer to
+    /// the definitions of
+    /// `RawL2Entry` and `RawL3Entry` in `vmsa.rs` for the attribute bits.
     pub fn new() -> KernPageTable {
 	
 	let mut kpt: Box<PageTable> = PageTable::new(EntryPerm::KERN_RW);
-	let (mut mem_start, mut mem_end) = allocator::memory_map().unwrap();
-	let mem_start = 0;
+	let (_, mem_end) = allocator::memory_map().unwrap();
 	let mem_end = mem_end >> PAGE_ALIGN;
-	let io_start = IO_BASE >> PAGE_ALIGN;
-	let io_end = IO_BASE_END >> PAGE_ALIGN;
+	let (io_start, io_end) = io_range();
+	let (io_start, io_end) = (io_start >> PAGE_ALIGN, io_end >> PAGE_ALIGN);
+	let (dma_start, dma_end) = dma::zone().unwrap();
+	let (dma_start, dma_end) = (dma_start >> PAGE_ALIGN, dma_end >> PAGE_ALIGN);
 	
-	assert!(mem_end <= IO_BASE);
+	assert!(mem_end <= io_start);
 	assert!(kpt.l3.len() * TABLE_SIZE >= io_end);
-	
-	// kernel memory is mapped 1:1
-	for i in mem_start..mem_end {
-	    let index_l3 = i / TABLE_SIZE;
-	    let index_entry = i % TABLE_SIZE;
-	    
-	    let entry: &mut RawL3Entry = &mut kpt.l3[index_l3].entries[index_entry].0;
-	    
-	    entry.set_value(i as u64, RawL2Entry::ADDR);
-	    entry.set_value(1, RawL2Entry::AF);
-	    entry.set_value(EntrySh::ISh, RawL2Entry::SH);
-	    entry.set_value(EntryPerm::KERN_RW, RawL2Entry::AP);
-	    entry.set_value(1, RawL2Entry::NS);
-	    entry.set_value(EntryAttr::Mem, RawL2Entry::ATTR);
-	    entry.set_value(PageType::Page, RawL2Entry::TYPE);
-	    entry.set_value(EntryValid::Valid, RawL2Entry::VALID);
+
+	for index in 0..kpt.l3.len() {
+	    let (first, end) = (index * TABLE_SIZE, (index + 1) * TABLE_SIZE);
+	    // debug builds map RAM page by page, so that a frame in quarantine
+	    // can be unmapped, see `vm::frame`
+	    let ram = end <= mem_end && (end <= dma_start || first >= dma_end) && !cfg!(debug_assertions);
+	    let attr = match (ram, first >= io_start && end <= io_end) {
+		(true, _) => EntryAttr::Mem,
+		(_, true) => EntryAttr::Dev,
+		_ => continue,
+	    };
+	    let mut block = RawL2Entry::new(KernPageTable::entry(first, attr).get());
+	    block.set_value(EntryType::Block, RawL2Entry::TYPE);
+	    kpt.l2.entries[index] = block;
+	    kpt.l3[index] = None;
 	}
 
-	// kernel i/o is mapped 1:1
-	for i in io_start..io_end {
-	    let index_l3 = i / TABLE_SIZE;
-	    let index_entry = i % TABLE_SIZE;
-	    let entry: &mut RawL3Entry = &mut kpt.l3[index_l3].entries[index_entry].0;
-	    
-	    entry.set_value(i as u64, RawL2Entry::ADDR);
-	    entry.set_value(1, RawL2Entry::AF);
-	    entry.set_value(EntrySh::OSh, RawL2Entry::SH);
-	    entry.set_value(EntryPerm::KERN_RW, RawL2Entry::AP);
-	    entry.set_value(1, RawL2Entry::NS);
-	    entry.set_value(EntryAttr::Dev, RawL2Entry::ATTR);
-	    entry.set_value(PageType::Page, RawL2Entry::TYPE);
-	    entry.set_value(EntryValid::Valid, RawL2Entry::VALID);
+	// the alias of the kernel image goes in the hole between RAM and i/o
+	if let Some(offset) = kaslr::choose(mem_end << PAGE_ALIGN, io_start << PAGE_ALIGN) {
+	    let (start, end) = kaslr::image();
+	    for page in start >> PAGE_ALIGN..end >> PAGE_ALIGN {
+		let alias = page + (offset >> PAGE_ALIGN);
+		kpt.l3_table_mut(alias / TABLE_SIZE).entries[alias % TABLE_SIZE].0 = KernPageTable::entry(page, EntryAttr::Mem);
+	    }
+	}
+
+	// kernel memory and i/o are mapped 1:1
+	let ram = (0..mem_end).map(|page| match page >= dma_start && page < dma_end {
+	    true => (page, EntryAttr::Nc),
+	    false => (page, EntryAttr::Mem),
+	});
+	for (page, attr) in ram.chain((io_start..io_end).map(|page| (page, EntryAttr::Dev))) {
+	    if let Some(l3) = kpt.l3[page / TABLE_SIZE].as_mut() {
+		l3.entries[page % TABLE_SIZE].0 = KernPageTable::entry(page, attr);
+	    }
 	}
 	KernPageTable(kpt)
     }
 
+    /// Returns a page descriptor mapping page number `page` 1:1 with the
+    /// memory attribute `attr`, one of `EntryAttr`.
+    fn entry(page: usize, attr: u64) -> RawL3Entry {
+	let sh = match attr {
+	    EntryAttr::Dev => EntrySh::OSh,
+	    _ => EntrySh::ISh,
+	};
+	let mut entry = RawL3Entry::new(0);
+	entry.set_value(page as u64, RawL3Entry::ADDR);
+	entry.set_value(1, RawL3Entry::AF);
+	entry.set_value(sh, RawL3Entry::SH);
+	entry.set_value(EntryPerm::KERN_RW, RawL3Entry::AP);
+	entry.set_value(1, RawL3Entry::NS);
+	entry.set_value(attr, RawL3Entry::ATTR);
+	entry.set_value(PageType::Page, RawL3Entry::TYPE);
+	entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
+	entry
+    }
+
     pub fn get_baddr(&self) -> PhysicalAddr {
         self.0.get_baddr()
     }
 
+    /// Makes everything the table maps execute-never at EL1 but the virtual
+    /// addresses `start..end`, the alias of the kernel image. Blocks only
+    /// map RAM and peripherals 1:1, so each of them is. Pages unmapped by
+    /// `set_present()` are too, for when they are mapped again.
+    pub fn forbid_exec(&mut self, start: usize, end: usize) {
+	let table = &mut self.0;
+	for index in 0..table.l3.len() {
+	    match table.l3[index].as_mut() {
+		Some(l3) => for (page, entry) in l3.entries.iter_mut().enumerate() {
+		    let va = (index * TABLE_SIZE + page) * PAGE_SIZE;
+		    if entry.0.get() != 0 && (va < start || va >= end) {
+			entry.0.set_value(1, RawL3Entry::PXN);
+		    }
+		},
+		None => {
+		    table.l2.entries[index].set_value(1, RawL2Entry::PXN);
+		},
+	    }
+	}
+    }
+
+    /// Unmaps the page of RAM at `addr`, so that the kernel faults when it
+    /// touches it, or maps it again as it was. Only debug builds map RAM
+    /// page by page, see `vm::frame`.
+    #[cfg(debug_assertions)]
+    pub fn set_present(&mut self, addr: usize, present: bool) {
+	let page = addr >> PAGE_ALIGN;
+	let entry = &mut self.0.l3_table_mut(page / TABLE_SIZE).entries[page % TABLE_SIZE].0;
+	assert!(entry.get() != 0, "page {:#x} is not mapped", addr);
+	entry.set_value(match present {
+	    true => EntryValid::Valid,
+	    false => EntryValid::Invalid,
+	}, RawL3Entry::VALID);
+    }
+
+    /// Maps the pages of `start..end` 1:1, non-cacheable and execute-never,
+    /// for memory outside of RAM the kernel shares with a device, like the
+    /// framebuffer. Returns `false`, mapping nothing, if a page of the range
+    /// is mapped already, as RAM, the peripherals and the alias of the
+    /// kernel image are.
+    pub fn map_uncached(&mut self, start: usize, end: usize) -> bool {
+	let pages = start >> PAGE_ALIGN..(end + PAGE_SIZE - 1) >> PAGE_ALIGN;
+	let table = &mut self.0;
+	if pages.end > table.l3.len() * TABLE_SIZE
+	    || pages.clone().any(|page| table.is_valid(VirtualAddr::from(page << PAGE_ALIGN))) {
+	    return false;
+	}
+	for page in pages {
+	    let mut entry = KernPageTable::entry(page, EntryAttr::Nc);
+	    entry.set_value(1, RawL3Entry::PXN);
+	    table.l3_table_mut(page / TABLE_SIZE).entries[page % TABLE_SIZE].0 = entry;
+	}
+	true
+    }
 }
 
+/// What user code may do with a page, which it can always read. The kernel
+/// never executes a user page, and does not write to one on behalf of user
+/// code that may not.
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum PagePerm {
     RW,
     RO,
+    RX,
     RWX,
 }
 
-pub struct UserPageTable(Box<PageTable>);
+impl PagePerm {
+    pub fn new(writable: bool, executable: bool) -> PagePerm {
+	match (writable, executable) {
+	    (false, false) => PagePerm::RO,
+	    (false, true) => PagePerm::RX,
+	    (true, false) => PagePerm::RW,
+	    (true, true) => PagePerm::RWX,
+	}
+    }
+
+    pub fn writable(self) -> bool {
+	self == PagePerm::RW || self == PagePerm::RWX
+    }
+
+    pub fn executable(self) -> bool {
+	self == PagePerm::RX || self == PagePerm::RWX
+    }
+
+    /// Returns the permission that allows what either `self` or `other`
+    /// does, for a page two regions with different permissions overlap.
+    pub fn union(self, other: PagePerm) -> PagePerm {
+	PagePerm::new(self.writable() || other.writable(), self.executable() || other.executable())
+    }
+
+    /// Sets the access permission and execute-never bits of `entry`.
+    fn apply(self, entry: &mut RawL3Entry) {
+	let ap = match self.writable() {
+	    true => EntryPerm::USER_RW,
+	    false => EntryPerm::USER_RO,
+	};
+	entry.set_value(ap, RawL3Entry::AP);
+	entry.set_value(!self.executable() as u64, RawL3Entry::UXN);
+	entry.set_value(1, RawL3Entry::PXN);
+    }
+}
+
+/// Pages a `UserPageTable` owns and the most it owned at once
+#[derive(Debug, Default, Copy, Clone)]
+struct Footprint {
+    pages: usize,
+    peak: usize,
+}
+
+/// The page table of a process and the state of its address space.
+pub struct UserPageTable {
+    table: Box<PageTable>,
+    /// the regions mapped page by page as they are touched
+    regions: Vec<Region>,
+    /// the mappings made by `mmap` and `shm_map`, their lengths by their
+    /// start addresses
+    shared: BTreeMap<usize, usize>,
+    /// the ASID the translations of the table are cached under
+    asid: Asid,
+    /// the pages the table owns
+    footprint: Footprint,
+}
 
 impl UserPageTable {
     /// Returns a new `UserPageTable` containing a `PageTable` created with
-    /// `USER_RW` permission.
+    /// `USER_RW` permission. The vDSO page is mapped at `USER_VDSO_BASE`.
     pub fn new() -> UserPageTable {
-	UserPageTable(PageTable::new(EntryPerm::USER_RW))
+	let mut table = UserPageTable {
+	    table: PageTable::new(EntryPerm::USER_RW),
+	    regions: Vec::new(),
+	    shared: BTreeMap::new(),
+	    asid: Asid::NONE,
+	    footprint: Footprint::default(),
+	};
+	table.map_shared(VirtualAddr::from(USER_VDSO_BASE), vdso::page(), PagePerm::RO);
+	table
+    }
+
+    /// Returns the value of `TTBR1_EL1` that switches to the table: its base
+    /// address tagged with its ASID, which it gets here if it has none in
+    /// the current generation.
+    pub fn ttbr(&mut self) -> u64 {
+	self.table.get_baddr().as_u64() | self.asid.activate() << 48
+    }
+
+    /// Maps the page at `pa`, which the table does not own, at `va` with
+    /// permission `perm`. The page is not freed when it is unmapped or the
+    /// table is dropped, and it is not copied when the table is duplicated.
+    fn map_shared(&mut self, va: VirtualAddr, pa: PhysicalAddr, perm: PagePerm) {
+	let mut entry: RawL3Entry = RawL3Entry::new(0);
+	entry.set_value(pa.as_u64() >> PAGE_ALIGN, RawL3Entry::ADDR);
+	entry.set_value(SW_SHARED, RawL3Entry::SW);
+	entry.set_value(1, RawL3Entry::NG);
+	entry.set_value(1, RawL3Entry::AF);
+	entry.set_value(EntrySh::ISh, RawL3Entry::SH);
+	perm.apply(&mut entry);
+	entry.set_value(1, RawL3Entry::NS);
+	entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
+	entry.set_value(PageType::Page, RawL3Entry::TYPE);
+	entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
+	self.table.set_entry(va, entry);
+    }
+
+    /// Returns an L3 entry mapping the page at `pa`, owned by the table,
+    /// with permission `perm` from EL0.
+    fn page_entry(pa: PhysicalAddr, perm: PagePerm) -> RawL3Entry {
+	let mut entry: RawL3Entry = RawL3Entry::new(0);
+	entry.set_value(pa.as_u64() >> PAGE_ALIGN, RawL3Entry::ADDR);
+	entry.set_value(1, RawL3Entry::NG);
+	entry.set_value(1, RawL2Entry::AF);
+	entry.set_value(EntrySh::ISh, RawL3Entry::SH);
+	perm.apply(&mut entry);
+	entry.set_value(1, RawL2Entry::NS);
+	entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
+	entry.set_value(PageType::Page, RawL3Entry::TYPE);
+	entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
+	entry
     }
 
     /// Allocates a page and set an L3 entry translates given virtual address to the
-    /// physical address of the allocated page. Returns the allocated page.
+    /// physical address of the allocated page, with permission `perm` from
+    /// EL0. Returns the allocated page.
     ///
     /// # Panics
     /// Panics if the virtual address is lower than `USER_IMG_BASE`.
@@ -278,38 +772,449 @@ impl UserPageTable {
     /// Panics if allocator fails to allocate a page.
     ///
     /// TODO. use Result<T> and make it failurable
-    /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> &mut [u8] {
 	assert!(va.as_usize() >= USER_IMG_BASE);
 
 	// retrieve entry
-	if self.0.is_valid(va) {
+	if self.table.is_valid(va) {
 	    panic!("attempt to reallocate virtual address");
 	}
-	let phys_page: *mut u8 = unsafe{
-	    ALLOCATOR.alloc(Page::layout())
-	};
+	let mut frame = frame::alloc().expect("out of memory for user page");
+	let phys_page = frame.as_mut_ptr();
 
-	let phys_addr = (phys_page as u64) >> PAGE_ALIGN;	    
-	let mut entry: RawL3Entry = RawL3Entry::new(0);
-	entry.set_value(phys_addr, RawL3Entry::ADDR);
-	entry.set_value(1, RawL2Entry::AF);
-	entry.set_value(EntrySh::ISh, RawL3Entry::SH);
-	entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
-	entry.set_value(1, RawL2Entry::NS);
-	entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
-	entry.set_value(PageType::Page, RawL3Entry::TYPE);
-	entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
-	self.0.set_entry(va, entry);
+	self.table.set_entry(va, UserPageTable::page_entry(frame, perm));
+	self.footprint.pages += 1;
+	self.footprint.peak = core::cmp::max(self.footprint.peak, self.footprint.pages);
 
 	unsafe{
 	    core::slice::from_raw_parts_mut(phys_page, PAGE_SIZE)
 	}
     }
 
+    /// Unmaps the page at the given virtual address and drops the table's
+    /// reference to it, unless the table does not own it. Does nothing if
+    /// the address is not mapped.
+    pub fn dealloc(&mut self, va: VirtualAddr) {
+	let entry = *self.table.get_entry(va);
+	let page = match entry.get_page_addr() {
+	    Some(page) => page,
+	    None => return,
+	};
+	self.table.set_entry(va, RawL3Entry::new(0));
+	self.asid.flush_page(va.as_usize());
+	if !entry.is_shared() {
+	    frame::release(page);
+	    self.footprint.pages -= 1;
+	}
+    }
+
+    /// Returns a copy of the table that shares every page the table owns
+    /// copy-on-write: the pages are mapped read-only in both tables, and
+    /// the first table to write to one gets a copy of its own. Regions not
+    /// touched yet are set up in the copy as well, so they are read in for
+    /// each table separately.
+    pub fn duplicate_cow(&mut self) -> UserPageTable {
+	let mut copy = UserPageTable::new();
+	copy.regions = self.regions.clone();
+	copy.shared = self.shared.clone();
+	copy.footprint = Footprint { pages: self.footprint.pages, peak: self.footprint.pages };
+	for (l3, copy_l3) in self.table.l3.iter_mut().flatten().zip(copy.table.l3.iter_mut().flatten()) {
+	    for (entry, copy_entry) in l3.entries.iter_mut().zip(copy_l3.entries.iter_mut()) {
+		let page = match entry.get_page_addr() {
+		    Some(page) if !entry.is_shared() => page,
+		    _ => continue,
+		};
+		if !entry.is_cow() {
+		    let sw = match entry.perm().writable() {
+			true => SW_COW,
+			false => SW_COW_RO,
+		    };
+		    entry.0.set_value(sw, RawL3Entry::SW);
+		    entry.0.set_value(EntryPerm::USER_RO, RawL3Entry::AP);
+		}
+		frame::share(page);
+		*copy_entry = *entry;
+	    }
+	}
+	// the pages this table could write to are read-only now
+	self.asid.flush();
+	copy
+    }
+
+    /// Gives the table its own writable copy of the copy-on-write page
+    /// holding `va`, or takes the page over if no other table shares it any
+    /// longer. Returns `false` if the page is not copy-on-write or not
+    /// writable, in which case a write fault on it is the program's.
+    pub fn copy_on_write(&mut self, va: VirtualAddr) -> bool {
+	let va = VirtualAddr::from(va.as_usize() & PAGE_MASK);
+	if va.as_usize() < USER_IMG_BASE || self.table.get_entry(va).0.get_value(RawL3Entry::SW) != SW_COW {
+	    return false;
+	}
+	let perm = self.table.get_entry(va).perm();
+	let page = self.get_page(va);
+	if frame::refs(page) == 1 {
+	    self.table.set_entry(va, UserPageTable::page_entry(page, perm));
+	    self.asid.flush_page(va.as_usize());
+	    return true;
+	}
+	let mut copy = match frame::alloc() {
+	    Some(copy) => copy,
+	    None => return false,
+	};
+	unsafe { core::ptr::copy_nonoverlapping(page.as_ptr(), copy.as_mut_ptr(), PAGE_SIZE) };
+	frame::release(page);
+	self.table.set_entry(va, UserPageTable::page_entry(copy, perm));
+	self.asid.flush_page(va.as_usize());
+	true
+    }
+
+    /// Sets up `region`, whose pages are allocated, and read in from its
+    /// backing file, the first time they are touched. The pages of a region
+    /// may overlap those of others or of pages already mapped, as the
+    /// segments of a program sharing a page do; a page is filled from every
+    /// region it overlaps.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the region is empty, starts below `USER_IMG_BASE` or wraps
+    /// around.
+    pub fn reserve(&mut self, region: Region) {
+	assert!(region.len > 0 && region.start >= USER_IMG_BASE);
+	assert!(region.start.checked_add(region.len - 1).is_some());
+	self.regions.push(region);
+    }
+
+    /// Sets up a mapping of `len` bytes, a nonzero multiple of `PAGE_SIZE`, of
+    /// zeroed memory with permission `perm` at the highest free addresses
+    /// below the vDSO page, and returns its address. Returns `None` if there
+    /// is no gap that large.
+    pub fn map_anonymous(&mut self, len: usize, perm: PagePerm) -> Option<VirtualAddr> {
+	self.map_region(len, perm, Backing::Zero)
+    }
+
+    /// Maps `segment` with permission `perm` the way `map_anonymous()` maps
+    /// zeroed memory, and returns its address. Other tables mapping the
+    /// segment see what is written to it through this one.
+    pub fn map_segment(&mut self, segment: Arc<Segment>, perm: PagePerm) -> Option<VirtualAddr> {
+	self.map_region(segment.len(), perm, Backing::Shared(segment))
+    }
+
+    fn map_region(&mut self, len: usize, perm: PagePerm, backing: Backing) -> Option<VirtualAddr> {
+	assert!(len > 0 && len % PAGE_SIZE == 0);
+	// the end of the free run of pages the search is in
+	let mut end = USER_VDSO_BASE;
+	let mut page = USER_VDSO_BASE;
+	while page > USER_IMG_BASE {
+	    page -= PAGE_SIZE;
+	    if self.is_reserved(VirtualAddr::from(page)) {
+		end = page;
+	    }
+	    else if end - page == len {
+		self.reserve(Region { start: page, len, perm, backing, growth: 0 });
+		self.shared.insert(page, len);
+		return Some(VirtualAddr::from(page));
+	    }
+	}
+	None
+    }
+
+    /// Removes `va..va + len`, a nonzero multiple of `PAGE_SIZE` long, from
+    /// the mapping made by `map_anonymous()` it lies in, and frees the pages
+    /// of it that were touched. What is left of the mapping on either side
+    /// stays mapped. Returns `false` if the range does not lie within a
+    /// single mapping, such as one unmapped already, or lies in a mapping of
+    /// a segment.
+    pub fn unmap_anonymous(&mut self, va: VirtualAddr, len: usize) -> bool {
+	assert!(len > 0 && len % PAGE_SIZE == 0);
+	let start = va.as_usize();
+	let (map_start, map_len) = match self.shared.range(..=start).next_back() {
+	    Some((&map_start, &map_len)) => (map_start, map_len),
+	    None => return false,
+	};
+	if start % PAGE_SIZE != 0 || start - map_start >= map_len || len > map_len - (start - map_start) {
+	    return false;
+	}
+	let (end, map_end) = (start + len, map_start + map_len);
+	let position = self.regions.iter().position(|region| region.start == map_start && region.len == map_len)
+	    .expect("mapping has a region");
+	if let Backing::Shared(_) = self.regions[position].backing {
+	    return false;
+	}
+
+	self.shared.remove(&map_start);
+	let region = self.regions.remove(position);
+	if start > map_start {
+	    self.shared.insert(map_start, start - map_start);
+	    self.reserve(Region { start: map_start, len: start - map_start, ..region.clone() });
+	}
+	if end < map_end {
+	    self.shared.insert(end, map_end - end);
+	    self.reserve(Region { start: end, len: map_end - end, ..region });
+	}
+	self.each_page(start, end - 1, |table, page| {
+	    table.dealloc(page);
+	    true
+	});
+	true
+    }
+
+    /// Unmaps the segment mapped at `va` by `map_segment()`. Returns `false`
+    /// if no segment is mapped there.
+    pub fn unmap_segment(&mut self, va: VirtualAddr) -> bool {
+	let start = va.as_usize();
+	let position = self.regions.iter().position(|region| match region.backing {
+	    Backing::Shared(_) => region.start == start && self.shared.contains_key(&start),
+	    _ => false,
+	});
+	let region = match position {
+	    Some(position) => self.regions.remove(position),
+	    None => return false,
+	};
+	self.shared.remove(&start);
+	self.each_page(start, region.last(), |table, page| {
+	    table.dealloc(page);
+	    true
+	});
+	true
+    }
+
+    /// Unmaps every shared memory segment, as `unmap_segment()` does.
+    pub fn unmap_segments(&mut self) {
+	let starts: Vec<usize> = self.regions.iter()
+	    .filter(|region| match region.backing {
+		Backing::Shared(_) => true,
+		_ => false,
+	    })
+	    .map(|region| region.start)
+	    .collect();
+	for start in starts {
+	    self.unmap_segment(VirtualAddr::from(start));
+	}
+    }
+
+    /// Returns the number of pages the table owns and the most it owned at
+    /// once. Pages shared copy-on-write count for every table sharing them.
+    pub fn resident_pages(&self) -> (usize, usize) {
+	(self.footprint.pages, self.footprint.peak)
+    }
+
+    /// Returns the number of mappings made by `map_anonymous()` and
+    /// `map_segment()` and the bytes they span.
+    pub fn anonymous(&self) -> (usize, usize) {
+	(self.shared.len(), self.shared.values().sum())
+    }
+
+    /// Calls `f` with every page from the one holding `va` to the one holding
+    /// `last`, until it returns `false`. Returns `false` if it did.
+    fn each_page<F: FnMut(&mut Self, VirtualAddr) -> bool>(&mut self, va: usize, last: usize, mut f: F) -> bool {
+	let mut page = va & PAGE_MASK;
+	loop {
+	    if !f(self, VirtualAddr::from(page)) {
+		return false;
+	    }
+	    if page == last & PAGE_MASK {
+		return true;
+	    }
+	    page += PAGE_SIZE;
+	}
+    }
+
+    /// Drops the regions overlapping the page at `va`, such as a stack however
+    /// far it grew, and frees the pages of them that were touched, along with
+    /// the page at `va` itself.
+    pub fn release(&mut self, va: VirtualAddr) {
+	let page = va.as_usize() & PAGE_MASK;
+	let (released, kept): (Vec<Region>, Vec<Region>) = self.regions.drain(..).partition(|region| region.overlaps(page));
+	self.regions = kept;
+	self.dealloc(va);
+	for region in released.iter() {
+	    self.each_page(region.start, region.last(), |table, page| {
+		table.dealloc(page);
+		true
+	    });
+	}
+    }
+
+    /// Returns `true` if the page at `va` is mapped or belongs to a region
+    /// that maps it when touched.
+    pub fn is_reserved(&self, va: VirtualAddr) -> bool {
+	let page = va.as_usize() & PAGE_MASK;
+	self.table.is_valid(VirtualAddr::from(page)) || self.regions.iter().any(|region| region.overlaps(page))
+    }
+
+    /// Maps the page holding `va` if a region covers it and it was not
+    /// touched before, growing a stack down to the page if it lies within
+    /// reach below one. Returns `true` if the page is mapped now, and `false`
+    /// if no region covers it or its contents could not be read, in which
+    /// case the fault is the program's.
+    pub fn fault_in(&mut self, va: VirtualAddr) -> bool {
+	let page = va.as_usize() & PAGE_MASK;
+	if va.as_usize() < USER_IMG_BASE {
+	    return false;
+	}
+	if self.table.is_valid(VirtualAddr::from(page)) {
+	    return true;
+	}
+	if !self.regions.iter().any(|region| region.overlaps(page)) {
+	    match self.regions.iter_mut().find(|region| region.can_grow_to(page)) {
+		Some(stack) => stack.grow_to(page),
+		None => return false,
+	    }
+	}
+	// a page shared by segments with different permissions allows what
+	// either of them does
+	let mut perms = self.regions.iter().filter(|region| region.overlaps(page)).map(|region| region.perm);
+	let perm = match perms.next() {
+	    Some(first) => perms.fold(first, PagePerm::union),
+	    None => return false,
+	};
+	if let Some(frame) = self.regions.iter().filter(|region| region.overlaps(page)).find_map(|region| region.shared_frame(page)) {
+	    self.map_shared(VirtualAddr::from(page), frame, perm);
+	    return true;
+	}
+	let frame = self.alloc(VirtualAddr::from(page), perm).as_mut_ptr();
+	let frame = unsafe { core::slice::from_raw_parts_mut(frame, PAGE_SIZE) };
+	let filled = self.regions.iter()
+	    .filter(|region| region.overlaps(page))
+	    .all(|region| region.fill(page, frame).is_ok());
+	if !filled {
+	    self.dealloc(VirtualAddr::from(page));
+	}
+	filled
+    }
+
+    /// Maps every page from `va` to `va + len` that is not mapped yet, as if
+    /// the range was touched, so that the kernel can access it directly.
+    /// With `write` set, copy-on-write pages of the range are copied as well,
+    /// as the kernel writes to them without faulting. Returns `false` if part
+    /// of the range is neither mapped nor reserved, or, with `write` set, is
+    /// read-only.
+    pub fn populate(&mut self, va: VirtualAddr, len: usize, write: bool) -> bool {
+	if len == 0 {
+	    return true;
+	}
+	match va.as_usize().checked_add(len - 1) {
+	    Some(last) => self.each_page(va.as_usize(), last, |table, page| table.touch(page, write)),
+	    None => false,
+	}
+    }
+
+    /// Sets the permission of every page from `va` to `va + len` to `perm`.
+    /// Pages not touched yet are mapped first, so that the permission is not
+    /// lost to that of their region when they are. Returns `false`, leaving
+    /// the permissions as they were, if part of the range is neither mapped
+    /// nor reserved or is the vDSO page, and also if a page could not be read
+    /// in, after which only some pages may have been mapped. Pages of shared
+    /// memory segments change only for this table.
+    pub fn protect(&mut self, va: VirtualAddr, len: usize, perm: PagePerm) -> bool {
+	let last = match len.checked_sub(1).and_then(|len| va.as_usize().checked_add(len)) {
+	    Some(last) => last,
+	    None => return len == 0,
+	};
+	let reserved = self.each_page(va.as_usize(), last, |table, page| {
+	    page.as_usize() >= USER_IMG_BASE && page.as_usize() != USER_VDSO_BASE && table.is_reserved(page)
+	});
+	if !reserved || !self.populate(va, len, false) {
+	    return false;
+	}
+	self.each_page(va.as_usize(), last, |table, page| {
+	    let entry = &mut table.table.get_entry_mut(page).0;
+	    match entry.get_value(RawL3Entry::SW) {
+		SW_COW | SW_COW_RO => {
+		    // stays read-only until it is copied, as `perm()` tells
+		    let sw = match perm.writable() {
+			true => SW_COW,
+			false => SW_COW_RO,
+		    };
+		    entry.set_value(sw, RawL3Entry::SW);
+		    entry.set_value(!perm.executable() as u64, RawL3Entry::UXN);
+		},
+		_ => perm.apply(entry),
+	    }
+	    table.asid.flush_page(page.as_usize());
+	    true
+	})
+    }
+
+    /// Maps every page of every region that was not touched yet.
+    pub fn populate_all(&mut self) -> bool {
+	let ranges: Vec<(usize, usize)> = self.regions.iter().map(|region| (region.start, region.len)).collect();
+	ranges.into_iter().all(|(start, len)| self.populate(VirtualAddr::from(start), len, false))
+    }
+
+    /// Maps the page at `va` as `fault_in()` does and, for a write, gives the
+    /// table its own copy if it is shared copy-on-write. Returns `false` for
+    /// a write to a page EL0 may not write to, copy-on-write or not.
+    fn touch(&mut self, va: VirtualAddr, write: bool) -> bool {
+	if !self.fault_in(va) {
+	    return false;
+	}
+	let entry = self.table.get_entry(va);
+	!write || entry.perm().writable() && (!entry.is_cow() || self.copy_on_write(va))
+    }
+
+    /// Returns the bytes from `va` to the end of its page, or `None` if the
+    /// page is not mapped and no region covers it, or is read-only and
+    /// `write` is set. A page a region covers is mapped on
+    /// the way, and a copy-on-write page is copied for a write.
+    fn page_from(&mut self, va: usize, write: bool) -> Option<&mut [u8]> {
+	let page = va & PAGE_MASK;
+	if va < USER_IMG_BASE || !self.touch(VirtualAddr::from(page), write) {
+	    return None;
+	}
+	let offset = va - page;
+	let phys = self.get_page(VirtualAddr::from(page));
+	unsafe {
+	    Some(core::slice::from_raw_parts_mut((phys.as_usize() + offset) as *mut u8, PAGE_SIZE - offset))
+	}
+    }
+
+    /// Copies `buf` into user memory at `va`. Returns `false` if part of the
+    /// range is not mapped or not writable, in which case only the prefix
+    /// before it is written.
+    pub fn copy_to(&mut self, va: VirtualAddr, buf: &[u8]) -> bool {
+	let mut copied = 0;
+	while copied < buf.len() {
+	    let page = match self.page_from(va.as_usize() + copied, true) {
+		Some(page) => page,
+		None => return false,
+	    };
+	    let count = core::cmp::min(page.len(), buf.len() - copied);
+	    page[..count].copy_from_slice(&buf[copied..copied + count]);
+	    copied += count;
+	}
+	true
+    }
+
+    /// Fills `buf` from user memory at `va`. Returns `false` if part of the
+    /// range is not mapped.
+    pub fn copy_from(&mut self, va: VirtualAddr, buf: &mut [u8]) -> bool {
+	let mut copied = 0;
+	while copied < buf.len() {
+	    let page = match self.page_from(va.as_usize() + copied, false) {
+		Some(page) => page,
+		None => return false,
+	    };
+	    let count = core::cmp::min(page.len(), buf.len() - copied);
+	    buf[copied..copied + count].copy_from_slice(&page[..count]);
+	    copied += count;
+	}
+	true
+    }
+
+    /// Returns an iterator over every user page owned by the table as the
+    /// virtual address, the physical address and the permission of the page.
+    /// A page shared copy-on-write counts as writable if writing copies it.
+    pub fn mapped<'a>(&'a self) -> impl Iterator<Item = (VirtualAddr, PhysicalAddr, PagePerm)> + 'a {
+	self.table.into_iter().enumerate().filter(|(_, entry)| !entry.is_shared()).filter_map(|(index, entry)| {
+	    let va = VirtualAddr::from(USER_IMG_BASE.wrapping_add(index * PAGE_SIZE));
+	    entry.get_page_addr().map(|pa| (va, pa, entry.perm()))
+	})
+    }
+
     pub fn get_page(&mut self, va: VirtualAddr) -> PhysicalAddr {
 	let (l2, l3) = PageTable::locate(va);
-        let entry: L3Entry = self.l3[l2].entries[l3];
+        let entry: L3Entry = self.l3_table(l2).entries[l3];
 	let addr = entry.get_page_addr().unwrap();
 	return addr;
     }
@@ -327,7 +1232,7 @@ impl Deref for UserPageTable {
     type Target = PageTable;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.table
     }
 }
 
@@ -339,36 +1244,72 @@ impl DerefMut for KernPageTable {
 
 impl DerefMut for UserPageTable {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.table
     }
 }
 
-// FIXME: Implement `Drop` for `UserPageTable`.
+/// Drops the table's reference to every page it owns. Debug builds check
+/// that no page is mapped twice, which would drop a reference too many.
 impl Drop for UserPageTable {
     fn drop(&mut self) {
-	for entry in self.0.into_iter() {
-	    if let Some(mut phys_addr) = entry.get_page_addr() {
-		unsafe{
-		    ALLOCATOR.dealloc(phys_addr.as_mut_ptr(), Page::layout());
-		};
-	    }
+	let pages = self.table.into_iter()
+	    .filter(|entry| !entry.is_shared())
+	    .filter_map(|entry| entry.get_page_addr());
+	if cfg!(debug_assertions) {
+	    let mut freed: Vec<usize> = pages.clone().map(|page| page.as_usize()).collect();
+	    freed.sort();
+	    debug_assert!(freed.windows(2).all(|pair| pair[0] != pair[1]), "user page mapped twice");
+	}
+	for page in pages {
+	    frame::release(page);
 	}
     }
 }
 
-// FIXME: Implement `fmt::Debug` as you need.
+/// Lists the mapped ranges, merged where neighbouring pages share their
+/// permissions and attributes, one per line, such as
+/// `0x000000003f000000 - 0x000000003fffffff RW NX kernel Device`. The
+/// addresses are offsets from the start of the range the table translates;
+/// those of `KernPageTable` and `UserPageTable` are virtual addresses.
+impl fmt::Debug for PageTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	self.fmt_mappings(f, 0)
+    }
+}
+
 impl fmt::Debug for UserPageTable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("User Page Table")
-            .field("base address", &self.get_baddr())
-            .finish()
+	writeln!(f, "user page table at {:?}", self.get_baddr())?;
+	self.table.fmt_mappings(f, USER_IMG_BASE)
     }
 }
 
 impl fmt::Debug for KernPageTable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("Kernel Page Table")
-            .field("base address", &self.get_baddr())
-            .finish()
+	writeln!(f, "kernel page table at {:?}", self.get_baddr())?;
+	self.0.fmt_mappings(f, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An entry mapping a page with permission `perm`, marked `sw`
+    fn entry(perm: PagePerm, sw: u64) -> L3Entry {
+	let mut raw = UserPageTable::page_entry(PhysicalAddr::from(0x10_0000), perm);
+	raw.set_value(sw, RawL3Entry::SW);
+	L3Entry(raw)
+    }
+
+    /// `touch()` lets the kernel write to a page only if EL0 may
+    #[test]
+    fn test_write_needs_writable_page() {
+	assert!(entry(PagePerm::RW, 0).perm().writable());
+	assert!(!entry(PagePerm::RO, 0).perm().writable());
+	assert!(!entry(PagePerm::RX, 0).perm().writable());
+	// shared copy-on-write: read-only in the table, writable once copied
+	assert!(entry(PagePerm::RO, SW_COW).perm().writable());
+	assert!(!entry(PagePerm::RO, SW_COW_RO).perm().writable());
     }
 }
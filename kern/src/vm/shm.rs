@@ -0,0 +1,98 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::mutex::Mutex;
+use crate::param::PAGE_SIZE;
+use crate::vm::{frame, PhysicalAddr};
+
+/// Segments created by `shm_create` and not unlinked yet, by name
+static SEGMENTS: Mutex<Option<BTreeMap<String, Arc<Segment>>>> = Mutex::new(None);
+
+/// Pages of memory that processes map by name to share them. The pages are
+/// freed once the segment is unlinked and no process maps it any longer.
+pub struct Segment {
+    frames: Vec<PhysicalAddr>,
+}
+
+impl Segment {
+    /// Returns a segment of `len` bytes, a nonzero multiple of `PAGE_SIZE`,
+    /// of zeroed memory, or `None` if there is not enough memory.
+    fn new(len: usize) -> Option<Segment> {
+	let mut segment = Segment { frames: Vec::with_capacity(len / PAGE_SIZE) };
+	for _ in 0..len / PAGE_SIZE {
+	    // the frames allocated so far go with the segment
+	    segment.frames.push(frame::alloc()?);
+	}
+	Some(segment)
+    }
+
+    /// Length of the segment in bytes
+    pub fn len(&self) -> usize {
+	self.frames.len() * PAGE_SIZE
+    }
+
+    /// Returns the physical address of the page at `offset` bytes into the
+    /// segment.
+    pub fn frame(&self, offset: usize) -> PhysicalAddr {
+	self.frames[offset / PAGE_SIZE]
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+	for frame in self.frames.drain(..) {
+	    frame::release(frame);
+	}
+    }
+}
+
+/// Creates a segment of `len` bytes, a nonzero multiple of `PAGE_SIZE`,
+/// named `name`.
+///
+/// # Errors
+///
+/// Returns `FileExists` if there is a segment named `name` already and
+/// `NoMemory` if there is not enough memory for the segment.
+pub fn create(name: &str, len: usize) -> OsResult<()> {
+    let mut guard = SEGMENTS.lock();
+    let segments = guard.get_or_insert_with(BTreeMap::new);
+    if segments.contains_key(name) {
+	return Err(OsError::FileExists);
+    }
+    let segment = Segment::new(len).ok_or(OsError::NoMemory)?;
+    segments.insert(String::from(name), Arc::new(segment));
+    Ok(())
+}
+
+/// Returns the segment named `name`.
+///
+/// # Errors
+///
+/// Returns `NoEntry` if there is no segment named `name`.
+pub fn open(name: &str) -> OsResult<Arc<Segment>> {
+    SEGMENTS.lock().as_ref()
+	.and_then(|segments| segments.get(name).cloned())
+	.ok_or(OsError::NoEntry)
+}
+
+/// Removes the name of the segment named `name`. The processes that map the
+/// segment keep it until they unmap it.
+///
+/// # Errors
+///
+/// Returns `NoEntry` if there is no segment named `name`.
+pub fn unlink(name: &str) -> OsResult<()> {
+    SEGMENTS.lock().as_mut()
+	.and_then(|segments| segments.remove(name))
+	.map(|_| ())
+	.ok_or(OsError::NoEntry)
+}
+
+/// Returns the bytes the named segments take, as listed in `/proc/meminfo`.
+pub fn stats() -> usize {
+    SEGMENTS.lock().as_ref().map_or(0, |segments| segments.values().map(|segment| segment.len()).sum())
+}
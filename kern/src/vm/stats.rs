@@ -0,0 +1,64 @@
+use core::fmt;
+use core::ops::AddAssign;
+
+use crate::process::Id;
+use crate::vm::VirtualAddr;
+
+/// Counts of the pages mapped by one or more page tables and of the memory
+/// the tables themselves take.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VmStats {
+    /// normal memory pages only accessible from EL1
+    pub kernel_pages: usize,
+    /// normal memory pages accessible from EL0 and owned by their table
+    pub user_pages: usize,
+    /// pages mapped with the device memory attribute
+    pub device_pages: usize,
+    /// pages mapped into a table that does not own them, like the vDSO page
+    pub shared_pages: usize,
+    /// user pages shared copy-on-write with another table, also counted in
+    /// `user_pages` of each table
+    pub cow_pages: usize,
+    /// L2 entries mapping a block rather than an L3 table, whose pages are
+    /// counted with the kernel or device pages
+    pub blocks: usize,
+    /// number of page tables counted
+    pub tables: usize,
+    /// bytes taken by the L2 and L3 tables
+    pub table_bytes: usize,
+}
+
+impl AddAssign for VmStats {
+    fn add_assign(&mut self, other: VmStats) {
+	self.kernel_pages += other.kernel_pages;
+	self.user_pages += other.user_pages;
+	self.device_pages += other.device_pages;
+	self.shared_pages += other.shared_pages;
+	self.cow_pages += other.cow_pages;
+	self.blocks += other.blocks;
+	self.tables += other.tables;
+	self.table_bytes += other.table_bytes;
+    }
+}
+
+/// A page table entry that breaks an invariant, found by
+/// `VMManager::audit()`.
+#[derive(Debug, Copy, Clone)]
+pub struct AuditIssue {
+    /// process owning the table, `None` for the kernel page table
+    pub pid: Option<Id>,
+    /// virtual address the entry translates; for L2 entries the first
+    /// address of the range the entry covers
+    pub va: VirtualAddr,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for AuditIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self.pid {
+	    Some(pid) => write!(f, "pid {}", pid)?,
+	    None => write!(f, "kernel")?,
+	}
+	write!(f, " {:016x}: {}", self.va.as_usize(), self.reason)
+    }
+}
@@ -28,6 +28,13 @@ pub fn isb() {
     unsafe { asm!("isb" :::: "volatile") };
 }
 
+/// Data Memory Barrier: memory accesses before it are observed before
+/// accesses after it
+#[inline(always)]
+pub fn dmb() {
+    unsafe { asm!("dmb sy" :::: "volatile") };
+}
+
 /// Set Event
 #[inline(always)]
 pub fn sev() {
@@ -103,3 +110,30 @@ pub fn set_interrupt_mask(mask: u64) {
          : "volatile");
     }
 }
+
+/// Size in bytes of a data cache line on the Cortex-A53
+pub const CACHE_LINE_SIZE: usize = 64;
+
+/// Cleans the data cache lines covering `[start, start + len)` to the point
+/// of coherency so the data survives a reset.
+pub unsafe fn clean_dcache_range(start: usize, len: usize) {
+    let mut addr = start & !(CACHE_LINE_SIZE - 1);
+    while addr < start + len {
+        asm!("dc cvac, $0" :: "r"(addr) :: "volatile");
+        addr += CACHE_LINE_SIZE;
+    }
+    asm!("dsb sy" :::: "volatile");
+}
+
+/// Invalidates the data cache lines covering `[start, start + len)`, so that
+/// reads see what another bus master, such as the GPU, wrote to memory.
+/// Lines the CPU dirtied in the range must have been cleaned first, or
+/// their data is lost.
+pub unsafe fn invalidate_dcache_range(start: usize, len: usize) {
+    let mut addr = start & !(CACHE_LINE_SIZE - 1);
+    while addr < start + len {
+        asm!("dc ivac, $0" :: "r"(addr) :: "volatile");
+        addr += CACHE_LINE_SIZE;
+    }
+    asm!("dsb sy" :::: "volatile");
+}
@@ -238,6 +238,9 @@ defreg!(CNTVOFF_EL2);
 // (ref: D7.5.1 Counter-timer Frequency Register)
 defreg!(CNTFRQ_EL0);
 
+// (ref: D7.5.20 Counter-timer Virtual Count Register)
+defreg!(CNTVCT_EL0);
+
 // (ref: D7.5.9 Counter-timer Kernel Control Register)
 defreg!(
     CNTKCTL_EL1,
@@ -267,3 +270,47 @@ defreg!(CNTP_CVAL_EL0);
 
 // (ref: D7.5.12 Counter-timer Physical Timer TimerValue Register)
 defreg!(CNTP_TVAL_EL0, [TVAL[31 - 00],]);
+
+// (ref: D7.10.4 Performance Monitors Control Register)
+defreg!(
+    PMCR_EL0,
+    [
+        N[15 - 11],  // Number of event counters implemented
+        LC[06 - 06], // Long cycle counter enable
+        DP[05 - 05], // Disable cycle counter when event counting is prohibited
+        X[04 - 04],  // Enable export of events
+        D[03 - 03],  // Clock divider: count every 64th cycle
+        C[02 - 02],  // Cycle counter reset
+        P[01 - 01],  // Event counter reset
+        E[00 - 00],  // Enable all counters
+    ]
+);
+
+// (ref: D7.10.18 Performance Monitors User Enable Register)
+defreg!(
+    PMUSERENR_EL0,
+    [
+        ER[03 - 03], // Event counter read enable
+        CR[02 - 02], // Cycle counter read enable
+        SW[01 - 01], // Software increment write enable
+        EN[00 - 00], // Traps EL0 accesses to the performance monitors to EL1 when 0
+    ]
+);
+
+// (ref: D7.10.3 Performance Monitors Count Enable Set register)
+defreg!(PMCNTENSET_EL0, [C[31 - 31],]);
+
+// (ref: D7.10.2 Performance Monitors Cycle Count Filter Register)
+defreg!(
+    PMCCFILTR_EL0,
+    [
+        P[31 - 31],   // Do not count cycles in EL1
+        U[30 - 30],   // Do not count cycles in EL0
+        NSK[29 - 29], // Non-secure EL1 filtering
+        NSU[28 - 28], // Non-secure EL0 filtering
+        NSH[27 - 27], // Count cycles in EL2
+    ]
+);
+
+// (ref: D7.10.1 Performance Monitors Cycle Count Register)
+defreg!(PMCCNTR_EL0);
@@ -47,6 +47,8 @@ pub mod EntryAttr {
 defbit!(
     RawL2Entry,
     [
+        UXN[54 - 54], // Unprivileged execute-never, of a block
+        PXN[53 - 53], // Privileged execute-never, of a block
         ADDR[47 - 16],
         AF[10 - 10],
         SH[09 - 08],
@@ -61,7 +63,11 @@ defbit!(
 defbit!(
     RawL3Entry,
     [
+        SW[58 - 55],  // Reserved for software use, ignored by hardware
+        UXN[54 - 54], // Unprivileged execute-never
+        PXN[53 - 53], // Privileged execute-never
         ADDR[47 - 16],
+        NG[11 - 11],  // Not global: matches only the ASID it was cached for
         AF[10 - 10],
         SH[09 - 08],
         AP[07 - 06],
@@ -94,7 +100,7 @@ defreg!(TCR_EL1);
 defreg!(TTBR0_EL1, [TTBR_CNP[00 - 00],]);
 
 // (ref: D7.2.102: Translation Table Base Register 1)
-defreg!(TTBR1_EL1, [TTBR_CNP[00 - 00],]);
+defreg!(TTBR1_EL1, [TTBR_ASID[63 - 48], TTBR_CNP[00 - 00],]);
 
 // (ref: D7.2.43: AArch64 Memory Model Feature Register 0)
 defreg!(
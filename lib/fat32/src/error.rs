@@ -0,0 +1,144 @@
+use core::fmt;
+
+use shim::io;
+
+/// What the file system was doing when an error occurred
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// loading a sector into the cache
+    ReadSector,
+    /// writing a dirty sector back to the device
+    WriteSector,
+    ReadFat,
+    WriteFat,
+    ReadCluster,
+    WriteCluster,
+    FollowChain,
+    AllocCluster,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	f.write_str(match self {
+	    Op::ReadSector => "reading sector",
+	    Op::WriteSector => "writing sector",
+	    Op::ReadFat => "reading FAT entry",
+	    Op::WriteFat => "writing FAT entry",
+	    Op::ReadCluster => "reading cluster",
+	    Op::WriteCluster => "writing cluster",
+	    Op::FollowChain => "following cluster chain",
+	    Op::AllocCluster => "allocating cluster",
+	})
+    }
+}
+
+/// An error of the file system, with enough context to find where on the
+/// device it happened. Converts into an `io::Error` of a matching kind whose
+/// message carries the context.
+#[derive(Debug)]
+pub enum FatError {
+    /// the device failed to transfer SECTOR, an absolute device sector,
+    /// during OP on CLUSTER
+    Device { op: Op, sector: u64, cluster: Option<u32>, error: io::Error },
+    /// CLUSTER is not a data cluster of the volume
+    InvalidCluster { op: Op, cluster: u32 },
+    /// the FAT entry of CLUSTER, which is part of a chain, is free, reserved
+    /// or bad
+    BrokenChain { cluster: u32, entry: u32 },
+    /// the chain starting at cluster START loops
+    ChainCycle { start: u32 },
+    /// CLUSTER is the last cluster of its chain
+    EndOfChain { cluster: u32 },
+    NoFreeClusters,
+    /// a read of SECTOR returned LEN bytes, fewer than needed
+    ShortRead { sector: u64, len: usize },
+    /// a buffer of LEN bytes was passed for a sector of NEEDED bytes
+    ShortBuffer { len: usize, needed: u64 },
+    /// SECTOR was accessed through the cache without being loaded
+    Uncached { sector: u64 },
+}
+
+impl FatError {
+    /// Attributes a device error to OP on CLUSTER. Other errors are returned
+    /// unchanged.
+    pub fn during(self, op: Op, cluster: u32) -> FatError {
+	match self {
+	    FatError::Device { sector, error, .. } => FatError::Device { op, sector, cluster: Some(cluster), error },
+	    error => error,
+	}
+    }
+
+    pub fn kind(&self) -> io::ErrorKind {
+	match self {
+	    FatError::Device { error, .. } => error.kind(),
+	    FatError::InvalidCluster { .. } | FatError::ShortBuffer { .. } => io::ErrorKind::InvalidInput,
+	    FatError::BrokenChain { .. } | FatError::ChainCycle { .. } => io::ErrorKind::InvalidData,
+	    FatError::EndOfChain { .. } => io::ErrorKind::Interrupted,
+	    FatError::ShortRead { .. } => io::ErrorKind::UnexpectedEof,
+	    FatError::NoFreeClusters | FatError::Uncached { .. } => io::ErrorKind::Other,
+	}
+    }
+}
+
+impl fmt::Display for FatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    FatError::Device { op, sector, cluster: Some(cluster), error } =>
+		write!(f, "device error {} {} at sector {}: {}", op, cluster, sector, error),
+	    FatError::Device { op, sector, cluster: None, error } =>
+		write!(f, "device error {} {}: {}", op, sector, error),
+	    FatError::InvalidCluster { op, cluster } =>
+		write!(f, "invalid cluster {} while {}", cluster, op),
+	    FatError::BrokenChain { cluster, entry } =>
+		write!(f, "cluster chain broken at cluster {}: FAT entry {:#010x}", cluster, entry),
+	    FatError::ChainCycle { start } =>
+		write!(f, "cycle in cluster chain starting at cluster {}", start),
+	    FatError::EndOfChain { cluster } =>
+		write!(f, "cluster {} ends its chain", cluster),
+	    FatError::NoFreeClusters => f.write_str("no free clusters"),
+	    FatError::ShortRead { sector, len } =>
+		write!(f, "short read of sector {}: {} bytes", sector, len),
+	    FatError::ShortBuffer { len, needed } =>
+		write!(f, "buffer of {} bytes too small for {} byte sector", len, needed),
+	    FatError::Uncached { sector } =>
+		write!(f, "attempted to read uncached sector {}", sector),
+	}
+    }
+}
+
+impl From<FatError> for io::Error {
+    fn from(error: FatError) -> io::Error {
+	io::Error::new(error.kind(), format!("{}", error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_error_context() {
+	let error = FatError::Device {
+	    op: Op::ReadSector,
+	    sector: 2050,
+	    cluster: None,
+	    error: io::Error::new(io::ErrorKind::TimedOut, "SD card controller timed out"),
+	};
+	assert_eq!(error.to_string(), "device error reading sector 2050: SD card controller timed out");
+
+	let error = error.during(Op::ReadFat, 9);
+	assert_eq!(error.to_string(), "device error reading FAT entry 9 at sector 2050: SD card controller timed out");
+
+	let error = io::Error::from(error);
+	assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+	assert!(error.to_string().contains("sector 2050"));
+    }
+
+    #[test]
+    fn test_error_kinds() {
+	assert_eq!(io::Error::from(FatError::EndOfChain { cluster: 3 }).kind(), io::ErrorKind::Interrupted);
+	assert_eq!(io::Error::from(FatError::ChainCycle { start: 3 }).kind(), io::ErrorKind::InvalidData);
+	assert_eq!(io::Error::from(FatError::InvalidCluster { op: Op::ReadCluster, cluster: 1 }).kind(),
+		   io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,393 @@
+//! FAT32 volumes built in memory for tests.
+//!
+//! `ImageBuilder` formats a partition with `vfat::format()` and lays out a
+//! tree of files and directories on it by hand, without going through the
+//! file system's own write paths, so reads can be checked against what was
+//! put on the disk. `Image` then damages the volume the way a failing card
+//! or an interrupted write does.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use crate::vfat::{format, BiosParameterBlock, FormatParams};
+
+const SECTOR_SIZE: usize = 512;
+const ENTRY_SIZE: usize = 32;
+
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LFN: u8 = 0x0F;
+
+/// sequence number flag of the last (first on disk) LFN entry of a name
+const LAST_LFN: u8 = 0x40;
+
+/// UTF-16 code units in an LFN entry
+const LFN_UNITS: usize = 13;
+
+/// byte offsets of the name characters in an LFN entry
+const LFN_OFFSETS: [usize; LFN_UNITS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+const EOC: u32 = 0x0FFF_FFFF;
+
+/// A file or a directory of an image
+#[derive(Debug, Clone)]
+pub enum Node {
+    File { name: String, data: Vec<u8> },
+    Dir { name: String, children: Vec<Node> },
+}
+
+impl Node {
+    pub fn file(name: &str, data: &[u8]) -> Node {
+	Node::File { name: name.to_string(), data: data.to_vec() }
+    }
+
+    pub fn dir(name: &str, children: Vec<Node>) -> Node {
+	Node::Dir { name: name.to_string(), children: children }
+    }
+
+    pub fn name(&self) -> &str {
+	match self {
+	    Node::File { name, .. } | Node::Dir { name, .. } => name,
+	}
+    }
+}
+
+/// Builds an image of an MBR with one FAT32 partition starting at sector 1.
+///
+/// Every entry gets an LFN and an 8.3 name of the form `NNNNNN~1`, so long
+/// names must not contain `~` to stay apart from the short ones.
+#[derive(Debug, Clone)]
+pub struct ImageBuilder {
+    sectors: u32,
+    sectors_per_cluster: u8,
+    scatter: Option<u64>,
+    root: Vec<Node>,
+}
+
+impl ImageBuilder {
+    /// An empty volume of SECTORS 512 byte sectors with 1 sector clusters.
+    pub fn new(sectors: u32) -> ImageBuilder {
+	ImageBuilder { sectors: sectors, sectors_per_cluster: 1, scatter: None, root: Vec::new() }
+    }
+
+    pub fn sectors_per_cluster(mut self, sectors_per_cluster: u8) -> ImageBuilder {
+	self.sectors_per_cluster = sectors_per_cluster;
+	self
+    }
+
+    /// Hands out free clusters in an order shuffled by SEED instead of in
+    /// sequence, so that chains are fragmented and interleaved.
+    pub fn scatter(mut self, seed: u64) -> ImageBuilder {
+	self.scatter = Some(seed);
+	self
+    }
+
+    /// Adds NODES to the root directory.
+    pub fn nodes(mut self, nodes: Vec<Node>) -> ImageBuilder {
+	self.root.extend(nodes);
+	self
+    }
+
+    /// Builds the image.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tree does not fit on the volume.
+    pub fn build(&self) -> Image {
+	let mut bytes = vec![0u8; SECTOR_SIZE * (1 + self.sectors as usize)];
+	bytes[450] = 0x0C;
+	bytes[454..458].copy_from_slice(&1u32.to_le_bytes());
+	bytes[458..462].copy_from_slice(&self.sectors.to_le_bytes());
+	bytes[510] = 0x55;
+	bytes[511] = 0xAA;
+
+	let params = FormatParams { sectors_per_cluster: self.sectors_per_cluster, ..FormatParams::default() };
+	let mut device = Cursor::new(bytes);
+	format(&mut device, &params).expect("failed to format image");
+	let ebpb = BiosParameterBlock::from(&mut device, 1).expect("formatted image has no boot sector");
+
+	// the same bounds the file system puts on the number of clusters
+	let start = SECTOR_SIZE;
+	let fat_size = ebpb.num_sectors_per_fat() as usize * SECTOR_SIZE;
+	let data_sector = ebpb.fat_start() + ebpb.num_sectors_per_fat() * ebpb.num_fats();
+	let data_clusters = (ebpb.num_logical_sectors() - data_sector) / ebpb.logical_per_cluster();
+	let fat_clusters = (fat_size / 4 - 2) as u32;
+	let mut image = Image {
+	    bytes: device.into_inner(),
+	    start: start,
+	    fat_start: start + ebpb.fat_start() as usize * SECTOR_SIZE,
+	    fat_size: fat_size,
+	    num_fats: ebpb.num_fats() as usize,
+	    data_start: start + data_sector as usize * SECTOR_SIZE,
+	    cluster_size: ebpb.cluster_size() as usize,
+	    num_clusters: data_clusters.min(fat_clusters),
+	    chains: HashMap::new(),
+	    entries: HashMap::new(),
+	};
+
+	let mut free: Vec<u32> = (ebpb.root_cluster() + 1..image.num_clusters + 2).collect();
+	if let Some(seed) = self.scatter {
+	    shuffle(&mut free, seed);
+	}
+	free.reverse();
+
+	let mut layout = Layout { image: &mut image, free: free };
+	let clusters = layout.dir_clusters(&self.root, false);
+	let mut chain = vec![ebpb.root_cluster()];
+	chain.extend(layout.alloc(clusters - 1));
+	layout.write_dir("/", &self.root, chain, None);
+	image
+    }
+}
+
+/// A built image and where things are on it. Offsets are in bytes from the
+/// start of the image.
+#[derive(Debug, Clone)]
+pub struct Image {
+    bytes: Vec<u8>,
+    start: usize,
+    fat_start: usize,
+    fat_size: usize,
+    num_fats: usize,
+    data_start: usize,
+    cluster_size: usize,
+    num_clusters: u32,
+    chains: HashMap<String, Vec<u32>>,
+    /// offsets of the short entries of files and directories
+    entries: HashMap<String, usize>,
+}
+
+impl Image {
+    /// A device holding a copy of the image.
+    pub fn device(&self) -> Cursor<Vec<u8>> {
+	Cursor::new(self.bytes.clone())
+    }
+
+    /// The clusters of the file or directory at PATH, `/` being the root.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the image has nothing at PATH.
+    pub fn chain(&self, path: &str) -> &[u32] {
+	match self.chains.get(path) {
+	    Some(chain) => chain,
+	    None => panic!("no file or directory at {} in image", path),
+	}
+    }
+
+    /// Every cluster in use by a file or a directory
+    pub fn used_clusters(&self) -> Vec<u32> {
+	let mut used: Vec<u32> = self.chains.values().flat_map(|chain| chain.iter().cloned()).collect();
+	used.sort();
+	used
+    }
+
+    /// The 32 byte short directory entry of the file or directory at PATH.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the image has nothing at PATH other than the root.
+    pub fn entry_mut(&mut self, path: &str) -> &mut [u8] {
+	let offset = match self.entries.get(path) {
+	    Some(&offset) => offset,
+	    None => panic!("no directory entry for {} in image", path),
+	};
+	&mut self.bytes[offset..offset + ENTRY_SIZE]
+    }
+
+    pub fn fat_entry(&self, cluster: u32) -> u32 {
+	let offset = self.fat_start + cluster as usize * 4;
+	let mut entry = [0u8; 4];
+	entry.copy_from_slice(&self.bytes[offset..offset + 4]);
+	u32::from_le_bytes(entry)
+    }
+
+    /// Sets the entry of CLUSTER in every FAT to VALUE, all 32 bits of it.
+    pub fn set_fat_entry(&mut self, cluster: u32, value: u32) {
+	for fat in 0..self.num_fats {
+	    let offset = self.fat_start + fat * self.fat_size + cluster as usize * 4;
+	    self.bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+	}
+    }
+
+    /// Links the last cluster of the chain of PATH back to its first.
+    pub fn link_cycle(&mut self, path: &str) {
+	let (first, last) = self.ends(path);
+	self.set_fat_entry(last, first);
+    }
+
+    /// Marks the Nth cluster of the chain of PATH free while the cluster
+    /// before it still links to it.
+    pub fn free_in_chain(&mut self, path: &str, n: usize) {
+	let cluster = self.chain(path)[n];
+	self.set_fat_entry(cluster, 0);
+    }
+
+    /// Links the first cluster of the chain of PATH to a cluster past the end
+    /// of the volume.
+    pub fn link_past_end(&mut self, path: &str) {
+	let (first, _) = self.ends(path);
+	let past_end = self.num_clusters + 2 + 16;
+	self.set_fat_entry(first, past_end);
+    }
+
+    /// Zeroes the primary boot sector, leaving the backup.
+    pub fn wipe_boot_sector(&mut self) {
+	for byte in self.bytes[self.start..self.start + SECTOR_SIZE].iter_mut() {
+	    *byte = 0;
+	}
+    }
+
+    fn ends(&self, path: &str) -> (u32, u32) {
+	let chain = self.chain(path);
+	match (chain.first(), chain.last()) {
+	    (Some(&first), Some(&last)) => (first, last),
+	    _ => panic!("{} has no clusters", path),
+	}
+    }
+
+    fn write_chain(&mut self, chain: &[u32], data: &[u8]) {
+	for (i, &cluster) in chain.iter().enumerate() {
+	    let part = &data[i * self.cluster_size..((i + 1) * self.cluster_size).min(data.len())];
+	    let offset = self.data_start + (cluster as usize - 2) * self.cluster_size;
+	    self.bytes[offset..offset + part.len()].copy_from_slice(part);
+	}
+	for pair in chain.windows(2) {
+	    self.set_fat_entry(pair[0], pair[1]);
+	}
+	if let Some(&last) = chain.last() {
+	    self.set_fat_entry(last, EOC);
+	}
+    }
+}
+
+/// Places a tree on an image, handing out clusters from FREE
+struct Layout<'a> {
+    image: &'a mut Image,
+    /// free clusters, the next one last
+    free: Vec<u32>,
+}
+
+impl<'a> Layout<'a> {
+    fn alloc(&mut self, count: usize) -> Vec<u32> {
+	if count > self.free.len() {
+	    panic!("image too small: {} clusters needed, {} free", count, self.free.len());
+	}
+	let at = self.free.len() - count;
+	let mut clusters = self.free.split_off(at);
+	clusters.reverse();
+	clusters
+    }
+
+    /// Number of clusters a directory of CHILDREN takes, at least one.
+    fn dir_clusters(&self, children: &[Node], dots: bool) -> usize {
+	let dot_entries = if dots { 2 } else { 0 };
+	let entries: usize = children.iter().map(|node| lfn_entries(node.name()) + 1).sum();
+	let bytes = (dot_entries + entries) * ENTRY_SIZE;
+	((bytes + self.image.cluster_size - 1) / self.image.cluster_size).max(1)
+    }
+
+    /// Writes the directory at PATH, holding CHILDREN, into the clusters of
+    /// CHAIN, and everything below it. PARENT is the first cluster of the
+    /// directory containing it, `None` for the root.
+    fn write_dir(&mut self, path: &str, children: &[Node], chain: Vec<u32>, parent: Option<u32>) {
+	let mut entries: Vec<u8> = Vec::new();
+	if let Some(parent) = parent {
+	    // the root is cluster 0 in ".."
+	    let parent = if parent == self.image.chain("/")[0] { 0 } else { parent };
+	    entries.extend_from_slice(&short_entry(*b".          ", ATTR_DIRECTORY, chain[0], 0));
+	    entries.extend_from_slice(&short_entry(*b"..         ", ATTR_DIRECTORY, parent, 0));
+	}
+	self.image.chains.insert(path.to_string(), chain.clone());
+
+	let mut locations = Vec::new();
+	for (i, node) in children.iter().enumerate() {
+	    let child_path = match path {
+		"/" => format!("/{}", node.name()),
+		_ => format!("{}/{}", path, node.name()),
+	    };
+	    let mut short_name = [b' '; 11];
+	    short_name[..8].copy_from_slice(format!("{:06X}~1", i).as_bytes());
+
+	    let entry = match node {
+		Node::File { data, .. } => {
+		    let count = (data.len() + self.image.cluster_size - 1) / self.image.cluster_size;
+		    let file_chain = self.alloc(count);
+		    self.image.write_chain(&file_chain, data);
+		    self.image.chains.insert(child_path.clone(), file_chain.clone());
+		    short_entry(short_name, 0, file_chain.first().cloned().unwrap_or(0), data.len() as u32)
+		},
+		Node::Dir { children, .. } => {
+		    let count = self.dir_clusters(children, true);
+		    let dir_chain = self.alloc(count);
+		    self.write_dir(&child_path, children, dir_chain.clone(), Some(chain[0]));
+		    short_entry(short_name, ATTR_DIRECTORY, dir_chain[0], 0)
+		},
+	    };
+	    entries.extend(lfn_entry_bytes(node.name(), &short_name));
+	    locations.push((child_path, entries.len()));
+	    entries.extend_from_slice(&entry);
+	}
+
+	// the rest of the last cluster is zero, which ends the directory
+	self.image.write_chain(&chain, &entries);
+	let cluster_size = self.image.cluster_size;
+	for (child_path, offset) in locations {
+	    let cluster = chain[offset / cluster_size];
+	    let location = self.image.data_start + (cluster as usize - 2) * cluster_size + offset % cluster_size;
+	    self.image.entries.insert(child_path, location);
+	}
+    }
+}
+
+fn short_entry(name: [u8; 11], attributes: u8, cluster: u32, size: u32) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[..11].copy_from_slice(&name);
+    entry[11] = attributes;
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
+
+fn lfn_entries(name: &str) -> usize {
+    (name.encode_utf16().count() + LFN_UNITS - 1) / LFN_UNITS
+}
+
+/// The LFN entries of NAME in the order they are on the disk, the last part
+/// of the name first. The name is terminated by 0x0000 and padded with
+/// 0xFFFF unless it fills its last entry.
+fn lfn_entry_bytes(name: &str, short_name: &[u8; 11]) -> Vec<u8> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = lfn_entries(name);
+    if units.len() < count * LFN_UNITS {
+	units.push(0x0000);
+	units.resize(count * LFN_UNITS, 0xFFFF);
+    }
+
+    let checksum = short_name.iter().fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte));
+    let mut bytes = Vec::new();
+    for sequence in (1..=count).rev() {
+	let mut entry = [0u8; ENTRY_SIZE];
+	entry[0] = sequence as u8 | if sequence == count { LAST_LFN } else { 0 };
+	entry[11] = ATTR_LFN;
+	entry[13] = checksum;
+	let part = &units[(sequence - 1) * LFN_UNITS..sequence * LFN_UNITS];
+	for (&offset, unit) in LFN_OFFSETS.iter().zip(part) {
+	    entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+	}
+	bytes.extend_from_slice(&entry);
+    }
+    bytes
+}
+
+/// Fisher-Yates shuffle driven by xorshift64, so images are reproducible
+/// from their seed.
+fn shuffle(clusters: &mut [u32], seed: u64) {
+    let mut state = seed | 1;
+    for i in (1..clusters.len()).rev() {
+	state ^= state << 13;
+	state ^= state >> 7;
+	state ^= state << 17;
+	clusters.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+}
@@ -10,12 +10,17 @@ extern crate alloc;
 #[cfg(not(target_endian = "little"))]
 compile_error!("only little endian platforms supported");
 
+mod error;
+#[cfg(test)]
+mod image;
 mod mbr;
 #[cfg(test)]
 mod tests;
 mod util;
 
+pub mod path;
 pub mod traits;
 pub mod vfat;
 
+pub use crate::error::{FatError, Op};
 pub use crate::mbr::*;
@@ -3,6 +3,7 @@ use core::mem::{size_of, transmute};
 use shim::const_assert_size;
 use shim::io;
 
+use crate::error::FatError;
 use crate::traits::BlockDevice;
 
 const MBR_SECTOR: u64 = 0;
@@ -150,7 +151,7 @@ impl MasterBootRecord {
 
 	// cast sector_data to struct MasterBootRecord
 	if read_size != MBR_SIZE {
-	    return Err(Error::Io(io::Error::new(io::ErrorKind::Other, "MasterBootRecord size is invalid")));
+	    return Err(Error::Io(FatError::ShortRead { sector: MBR_SECTOR, len: read_size }.into()));
 	}
 
 	let mbr_ptr = data.as_ptr() as *const MasterBootRecord;
@@ -185,6 +186,17 @@ impl MasterBootRecord {
 	Ok(mbr)
     }
 
+    /// partition table entry INDEX, 0 to 3
+    pub fn pte(&self, index: usize) -> Option<PartitionEntry> {
+	match index {
+	    0 => Some(self.pte_first),
+	    1 => Some(self.pte_second),
+	    2 => Some(self.pte_third),
+	    3 => Some(self.pte_fourth),
+	    _ => None,
+	}
+    }
+
     pub fn first_pte(&self) -> PartitionEntry {
 	self.pte_first
     }
@@ -0,0 +1,74 @@
+use shim::io;
+use shim::path::{Component, Path, PathBuf};
+
+/// Resolves `path` against the working directory `cwd` and returns the
+/// equivalent absolute path without `.` or `..` components.
+///
+/// Repeated separators are collapsed and `..` at the root stays at the root.
+/// Resolution is purely lexical; nothing is looked up on disk. `cwd` is only
+/// used when `path` is relative.
+///
+/// # Errors
+///
+/// Returns `InvalidInput` if `path` is relative and `cwd` is not absolute, or
+/// if either path holds a prefix component.
+pub fn resolve<P: AsRef<Path>, Q: AsRef<Path>>(cwd: P, path: Q) -> io::Result<PathBuf> {
+    let (cwd, path) = (cwd.as_ref(), path.as_ref());
+    let mut resolved = PathBuf::from("/");
+    if !path.has_root() {
+	if !cwd.has_root() {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "working directory is not absolute"));
+	}
+	push_components(&mut resolved, cwd)?;
+    }
+    push_components(&mut resolved, path)?;
+    Ok(resolved)
+}
+
+fn push_components(resolved: &mut PathBuf, path: &Path) -> io::Result<()> {
+    for component in path.components() {
+	match component {
+	    Component::RootDir => {
+		while resolved.pop() {}
+	    },
+	    Component::CurDir => {},
+	    Component::ParentDir => {
+		resolved.pop();
+	    },
+	    Component::Normal(name) => resolved.push(name),
+	    Component::Prefix(_) => {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "encountered invalid path component"));
+	    },
+	}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(cwd: &str, path: &str, expected: &str) {
+	assert_eq!(resolve(cwd, path).unwrap(), PathBuf::from(expected), "resolve({:?}, {:?})", cwd, path);
+    }
+
+    #[test]
+    fn test_resolve() {
+	check("/", "/a/b", "/a/b");
+	check("/x/y", "/a/b", "/a/b");
+	check("/", "a//b/", "/a/b");
+	check("/x", "a/./b", "/x/a/b");
+	check("/x/y", "..", "/x");
+	check("/x/y", "../../..", "/");
+	check("/x/y", ".", "/x/y");
+	check("/x/y", "", "/x/y");
+	check("/", "/a/../../b/.", "/b");
+	check("//x//y/", "z", "/x/y/z");
+    }
+
+    #[test]
+    fn test_resolve_relative_cwd() {
+	assert_eq!(resolve("x", "y").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+	check("x", "/y", "/y");
+    }
+}
@@ -3,19 +3,23 @@ extern crate rand;
 use std::fmt::{self, Debug};
 use std::io;
 use std::io::prelude::*;
-use std::io::Cursor;
+use std::io::{Cursor, SeekFrom};
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
+use proptest::prelude::*;
+use proptest::{prop_oneof, proptest};
+
+use crate::image::{Image, ImageBuilder, Node};
 use crate::mbr;
 use crate::traits::*;
 use crate::vfat;
 
 use mbr::{MasterBootRecord, PartitionEntry, CHS};
-use vfat::{BiosParameterBlock, VFat, VFatHandle};
+use vfat::{AllocStrategy, BiosParameterBlock, SpinLock, VFat, VFatHandle};
 
 #[derive(Clone)]
-struct StdVFatHandle(Arc<Mutex<VFat<Self>>>);
+struct StdVFatHandle(Arc<VFat<Self>>);
 
 impl Debug for StdVFatHandle {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -24,12 +28,14 @@ impl Debug for StdVFatHandle {
 }
 
 impl VFatHandle for StdVFatHandle {
+    type Lock = SpinLock;
+
     fn new(val: VFat<StdVFatHandle>) -> Self {
-        StdVFatHandle(Arc::new(Mutex::new(val)))
+        StdVFatHandle(Arc::new(val))
     }
 
-    fn lock<R>(&self, f: impl FnOnce(&mut VFat<StdVFatHandle>) -> R) -> R {
-        f(&mut self.0.lock().expect("all okay"))
+    fn with<R>(&self, f: impl FnOnce(&VFat<StdVFatHandle>) -> R) -> R {
+        f(&self.0)
     }
 }
 
@@ -398,6 +404,36 @@ fn test_mock4_files_recursive() {
     assert_hash_eq!("mock 4 file hashes", hash, hash_for!("files-2-3-4"));
 }
 
+#[test]
+fn test_open_normalized_paths() {
+    let vfat = vfat_from_resource!("mock1.fat32.img");
+
+    let entry = vfat.open("//NOTES/./LEC1/../LEC2/").expect("directory exists");
+    assert_eq!(entry.name(), "LEC2");
+    let entry = vfat.open("/../../NOTES").expect("directory exists");
+    assert_eq!(entry.name(), "NOTES");
+
+    expect_variant!(vfat.open("NOTES"), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+    expect_variant!(vfat.open("/NOTES/LEC1/SLIDES.PDF/x"), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_open_at_relative_paths() {
+    let vfat = vfat_from_resource!("mock1.fat32.img");
+
+    let entry = vfat.open_at("/NOTES", "LEC2/CODE").expect("directory exists");
+    assert_eq!(entry.name(), "CODE");
+    let file = vfat.open_at("/NOTES/LEC1", "../LEC2/./PAPER.PDF").expect("file exists");
+    assert!(file.is_file());
+    let entry = vfat.open_at("/NOTES/LEC1", "/NOTES").expect("directory exists");
+    assert_eq!(entry.name(), "NOTES");
+    let root = vfat.open_at("/NOTES", "../..").expect("root exists");
+    assert!(root.is_dir());
+
+    expect_variant!(vfat.open_at("/NOTES", "LEC6"), Err(ref e) if e.kind() == io::ErrorKind::NotFound);
+    expect_variant!(vfat.open_at("NOTES", "LEC1"), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+}
+
 struct Shuffle<T: BlockDevice> {
     device: T,
     swap_address: u64,
@@ -474,3 +510,314 @@ fn shuffle_test() {
     let hash = hash_files_recursive_from(vfat, "/");
     assert_hash_eq!("mock 1 file hashes", hash, hash_for!("files-1"));
 }
+
+/// Checks that the directory at DIR holds exactly NODES, recursively.
+fn check_tree(vfat: &StdVFatHandle, dir: &Path, nodes: &[Node]) {
+    let mut names: Vec<String> = vfat
+        .open_dir(dir)
+        .expect("directory exists")
+        .entries()
+        .expect("entries iterator")
+        .map(|entry| entry.name().to_string())
+        .filter(|name| name != "." && name != "..")
+        .collect();
+    let mut expected: Vec<String> = nodes.iter().map(|node| node.name().to_string()).collect();
+    names.sort();
+    expected.sort();
+    assert_eq!(names, expected, "entries of {:?}", dir);
+
+    for node in nodes {
+        let path = dir.join(node.name());
+        match node {
+            Node::File { data, .. } => {
+                let mut file = vfat.open_file(&path).expect("file exists");
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents).expect("read file");
+                assert!(contents == *data, "contents of {:?} differ", path);
+            }
+            Node::Dir { children, .. } => check_tree(vfat, &path, children),
+        }
+    }
+}
+
+/// Visits everything reachable from DIR down to DEPTH levels, ignoring
+/// errors. Corrupt chains can make a directory its own descendant.
+fn walk_tree(vfat: &StdVFatHandle, dir: &Path, depth: usize) {
+    let entries = match vfat.open_dir(dir).and_then(|dir| dir.entries()) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries {
+        let name = entry.name().to_string();
+        if let Some(mut file) = entry.into_file() {
+            let mut contents = Vec::new();
+            let _ = file.read_to_end(&mut contents);
+        } else if depth > 0 && name != "." && name != ".." {
+            walk_tree(vfat, &dir.join(name), depth - 1);
+        }
+    }
+}
+
+#[test]
+fn test_image_lfn_edge_cases() {
+    let names = [
+        "a",
+        "exactly13char",
+        "exactly 26 characters long",
+        "two.dots.in.name",
+        "  spaces  ",
+        "ÿ is not a terminator",
+        "Ωmega ßtraße 中文",
+        "twelve chars😀 straddles two entries",
+    ];
+    let long_name: String = ::std::iter::repeat('x').take(255).collect();
+    let mut nodes: Vec<Node> = names.iter().map(|name| Node::file(name, name.as_bytes())).collect();
+    nodes.push(Node::file(&long_name, b"longest"));
+    nodes.push(Node::file("empty", b""));
+    nodes.push(Node::dir("nested", vec![Node::dir("deeper", vec![Node::file("leaf", &[7; 3000])])]));
+
+    let image = ImageBuilder::new(4096).nodes(nodes.clone()).build();
+    let vfat = VFat::<StdVFatHandle>::from(image.device()).expect("failed to mount built image");
+    check_tree(&vfat, Path::new("/"), &nodes);
+}
+
+#[test]
+fn test_image_corrupt_chains() {
+    let files: Vec<Node> = (0..20).map(|i| Node::file(&format!("file {}", i), &[i as u8; 700])).collect();
+    let nodes = vec![Node::dir("big", files), Node::file("data", &[1; 2048])];
+    let image = ImageBuilder::new(4096).nodes(nodes).scatter(7).build();
+    assert!(image.chain("/big").len() >= 3);
+
+    let mut cyclic = image.clone();
+    cyclic.link_cycle("/big");
+    let vfat = VFat::<StdVFatHandle>::from(cyclic.device()).expect("mount");
+    let error = vfat.open_dir("/big").and_then(|dir| dir.entries()).err().expect("cycle detected");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+    let mut broken = image.clone();
+    broken.free_in_chain("/data", 2);
+    let vfat = VFat::<StdVFatHandle>::from(broken.device()).expect("mount");
+    let mut contents = Vec::new();
+    assert!(vfat.open_file("/data").expect("file exists").read_to_end(&mut contents).is_err());
+    broken.free_in_chain("/big", 1);
+    let vfat = VFat::<StdVFatHandle>::from(broken.device()).expect("mount");
+    let error = vfat.open_dir("/big").and_then(|dir| dir.entries()).err().expect("broken chain detected");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+    let mut past_end = image.clone();
+    past_end.link_past_end("/data");
+    let vfat = VFat::<StdVFatHandle>::from(past_end.device()).expect("mount");
+    let mut contents = Vec::new();
+    let error = vfat.open_file("/data").expect("file exists").read_to_end(&mut contents).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    let mut wiped = image.clone();
+    wiped.wipe_boot_sector();
+    let (vfat, report) = VFat::<StdVFatHandle>::mount(wiped.device(), vfat::LookupMode::default())
+        .expect("mount from backup boot sector");
+    assert!(report.primary_boot_corrupt);
+    assert_eq!(image.fat_entry(image.chain("/data")[0]), image.chain("/data")[1]);
+    let mut contents = Vec::new();
+    vfat.open_file("/data").expect("file exists").read_to_end(&mut contents).expect("read file");
+    assert_eq!(contents, vec![1; 2048]);
+}
+
+#[test]
+fn test_image_fat_plus_sizes() {
+    let mut image = ImageBuilder::new(4096).nodes(vec![Node::file("big", &[3; 2048])]).build();
+    // size bit 32 in bit 5 and bit 35 in bit 0, with the case bits 3 and 4 set
+    image.entry_mut("/big")[12] = 0x39;
+    let size = 2048 + (1 << 32) + (1 << 35);
+
+    let vfat = VFat::<StdVFatHandle>::from(image.device()).expect("mount");
+    assert_eq!(vfat.open_file("/big").expect("file exists").size(), 2048);
+
+    vfat.with(|v| v.set_fat_plus(true));
+    let mut file = vfat.open_file("/big").expect("file exists");
+    assert_eq!(file.size(), size);
+    let mut contents = [0; 1024];
+    file.read_exact(&mut contents).expect("read start of file");
+    assert_eq!(&contents[..], &[3; 1024][..]);
+    // the chain of the truncated image ends long before the size
+    assert!(file.seek(SeekFrom::Start(1 << 32)).is_err());
+    file.seek(SeekFrom::Start(0)).expect("seek to start");
+    assert_eq!(file.write(b"large").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_image_file_extents() {
+    let nodes = vec![Node::file("data", &[5; 4000]), Node::file("empty", b"")];
+    let image = ImageBuilder::new(4096).nodes(nodes.clone()).build();
+    let vfat = VFat::<StdVFatHandle>::from(image.device()).expect("mount");
+    let extents = vfat.open_file("/data").expect("file exists").extents().expect("extents");
+    assert_eq!(extents.len(), 1);
+    assert_eq!((extents[0].0.number(), extents[0].1), (image.chain("/data")[0], 8));
+    assert!(vfat.open_file("/empty").expect("file exists").extents().expect("extents").is_empty());
+
+    let scattered = ImageBuilder::new(4096).nodes(nodes).scatter(3).build();
+    let vfat = VFat::<StdVFatHandle>::from(scattered.device()).expect("mount");
+    let extents = vfat.open_file("/data").expect("file exists").extents().expect("extents");
+    assert!(extents.len() > 1);
+    let clusters: Vec<u32> = extents.iter().flat_map(|&(start, len)| start.number()..start.number() + len).collect();
+    assert_eq!(clusters, scattered.chain("/data"));
+
+    let mut broken = image.clone();
+    broken.free_in_chain("/data", 4);
+    let vfat = VFat::<StdVFatHandle>::from(broken.device()).expect("mount");
+    let error = vfat.open_file("/data").expect("file exists").extents().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_image_file_truncate() {
+    let nodes = vec![Node::file("data", &[5; 4000]), Node::file("other", &[6; 1000])];
+    let image = ImageBuilder::new(4096).nodes(nodes).build();
+    let vfat = VFat::<StdVFatHandle>::from(image.device()).expect("mount");
+    let chain = image.chain("/data");
+
+    let mut file = vfat.open_file("/data").expect("file exists");
+    file.truncate().expect("truncate");
+    assert_eq!(file.size(), 0);
+    file.write_all(b"fresh").expect("write after truncate");
+    assert!(vfat.with(|v| chain[1..].iter().all(|&n| v.next_cluster(vfat::Cluster::from(n)).is_err())));
+
+    let mut contents = Vec::new();
+    vfat.open_file("/data").expect("file exists").read_to_end(&mut contents).expect("read file");
+    assert_eq!(contents, b"fresh");
+    let mut contents = Vec::new();
+    vfat.open_file("/other").expect("file exists").read_to_end(&mut contents).expect("read file");
+    assert_eq!(contents, vec![6; 1000]);
+}
+
+#[test]
+fn test_image_entry_refresh() {
+    let nodes = vec![Node::file("data", &[5; 1000]), Node::dir("dir", vec![])];
+    let image = ImageBuilder::new(4096).nodes(nodes).build();
+    let vfat = VFat::<StdVFatHandle>::from(image.device()).expect("mount");
+
+    let mut stale = vfat.open("/data").expect("file exists");
+    let mut file = vfat.open_file("/data").expect("file exists");
+    file.seek(SeekFrom::End(0)).expect("seek to end");
+    file.write_all(&[6; 3000]).expect("write");
+    assert_eq!(stale.as_file().expect("a file").size(), 1000);
+    stale.refresh().expect("refresh");
+    assert_eq!(stale.as_file().expect("a file").size(), 4000);
+
+    let mut dir = vfat.open("/dir").expect("dir exists");
+    dir.refresh().expect("refresh");
+    assert!(dir.as_dir().is_some());
+    vfat.open("/").expect("root").refresh().expect("root is always current");
+}
+
+/// Names the builder and the lookup can tell apart: no `~`, which the
+/// generated short names use, and no `/`.
+fn arb_name() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9 ._!#$%&'(),;=@^{}ÿéßΩ中😀-]{1,40}".prop_filter("reserved name", |name| name != "." && name != "..")
+}
+
+/// Drops nodes whose names equal an earlier one ignoring case.
+fn unique(nodes: Vec<Node>) -> Vec<Node> {
+    let mut seen = ::std::collections::HashSet::new();
+    nodes.into_iter().filter(|node| seen.insert(node.name().to_lowercase())).collect()
+}
+
+fn arb_tree() -> impl Strategy<Value = Vec<Node>> {
+    let file = (arb_name(), prop::collection::vec(any::<u8>(), 0..5000))
+        .prop_map(|(name, data)| Node::File { name: name, data: data });
+    let node = file.prop_recursive(3, 32, 6, |inner| {
+        (arb_name(), prop::collection::vec(inner, 0..6))
+            .prop_map(|(name, children)| Node::Dir { name: name, children: unique(children) })
+    });
+    prop::collection::vec(node, 0..8).prop_map(unique)
+}
+
+fn arb_sectors_per_cluster() -> impl Strategy<Value = u8> {
+    prop_oneof![Just(1u8), Just(2), Just(4), Just(8)]
+}
+
+fn build(tree: &[Node], sectors_per_cluster: u8, seed: Option<u64>) -> Image {
+    let builder = ImageBuilder::new(8192)
+        .sectors_per_cluster(sectors_per_cluster)
+        .nodes(tree.to_vec());
+    match seed {
+        Some(seed) => builder.scatter(seed).build(),
+        None => builder.build(),
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn prop_image_round_trip(
+        tree in arb_tree(),
+        sectors_per_cluster in arb_sectors_per_cluster(),
+        seed in any::<Option<u64>>()
+    ) {
+        let image = build(&tree, sectors_per_cluster, seed);
+        let vfat = VFat::<StdVFatHandle>::from(image.device()).expect("failed to mount built image");
+        check_tree(&vfat, Path::new("/"), &tree);
+    }
+
+    #[test]
+    fn prop_corrupt_fat_never_panics(
+        tree in arb_tree(),
+        sectors_per_cluster in arb_sectors_per_cluster(),
+        seed in any::<u64>(),
+        corruptions in prop::collection::vec(
+            (any::<prop::sample::Index>(), prop_oneof![Just(0u32), Just(1), Just(0x0FFF_FFF7), Just(0x0FFF_FFFF), 2u32..64, any::<u32>()]),
+            1..8
+        )
+    ) {
+        let mut image = build(&tree, sectors_per_cluster, Some(seed));
+        let used = image.used_clusters();
+        for (index, value) in corruptions {
+            let cluster = match used.is_empty() {
+                true => 2,
+                false => used[index.index(used.len())],
+            };
+            image.set_fat_entry(cluster, value);
+        }
+        let vfat = VFat::<StdVFatHandle>::from(image.device()).expect("failed to mount built image");
+        walk_tree(&vfat, Path::new("/"), 4);
+    }
+
+    #[test]
+    fn prop_file_writes_round_trip(
+        tree in arb_tree(),
+        sectors_per_cluster in arb_sectors_per_cluster(),
+        seed in any::<Option<u64>>(),
+        alloc in prop_oneof![Just(AllocStrategy::FirstFit), Just(AllocStrategy::NextFree), Just(AllocStrategy::Contiguous)],
+        writes in prop::collection::vec(
+            (any::<prop::sample::Index>(), any::<prop::sample::Index>(), prop::collection::vec(any::<u8>(), 1..6000)),
+            1..6
+        )
+    ) {
+        let mut tree = tree;
+        tree.retain(|node| node.name().to_lowercase() != "target");
+        tree.push(Node::file("target", b""));
+        let image = build(&tree, sectors_per_cluster, seed);
+        let vfat = VFat::<StdVFatHandle>::from(image.device()).expect("failed to mount built image");
+        vfat.with(|v| v.set_alloc_strategy(alloc));
+
+        // overwrite and append to files at offsets up to their end, keeping
+        // a model of their contents
+        let files: Vec<usize> = (0..tree.len()).filter(|&i| match tree[i] {
+            Node::File { .. } => true,
+            Node::Dir { .. } => false,
+        }).collect();
+        for (file, offset, bytes) in writes {
+            let i = files[file.index(files.len())];
+            if let Node::File { name, data } = &mut tree[i] {
+                let offset = offset.index(data.len() + 1);
+                let mut handle = vfat.open_file(Path::new("/").join(&*name)).expect("file exists");
+                handle.seek(SeekFrom::Start(offset as u64)).expect("seek within file");
+                handle.write_all(&bytes).expect("write file");
+
+                let end = ::std::cmp::min(data.len(), offset + bytes.len());
+                data.splice(offset..end, bytes.iter().cloned());
+            }
+        }
+        check_tree(&vfat, Path::new("/"), &tree);
+    }
+}
@@ -6,6 +6,9 @@ use shim::io;
 /// granularities.
 pub trait BlockDevice: Send {
     /// Sector size in bytes. Must be a multiple of 512 >= 512. Defaults to 512.
+    ///
+    /// Callers must not assume 512 byte sectors: devices such as 4K eMMC
+    /// report their native size here and file systems adapt to it.
     fn sector_size(&self) -> u64 {
         512
     }
@@ -56,9 +59,24 @@ pub trait BlockDevice: Send {
     /// error of `UnexpectedEof` if the length of `buf` is less than
     /// `self.sector_size()`.
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize>;
+
+    /// Write barrier: returns once every sector written before the call has
+    /// reached stable storage. Defaults to doing nothing, which is correct for
+    /// devices that write through.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device fails to commit buffered writes.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
+    fn sector_size(&self) -> u64 {
+        (**self).sector_size()
+    }
+
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         (*self).read_sector(n, buf)
     }
@@ -66,6 +84,10 @@ impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
         (*self).write_sector(n, buf)
     }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (*self).flush()
+    }
 }
 
 macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
@@ -87,6 +109,10 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
             self.write_all(&buf[..to_write])?;
             Ok(to_write)
         }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Write::flush(self)
+        }
     }
 }
 
@@ -76,7 +76,8 @@ pub trait FileSystem: Sized {
     /// The type of directory entries in this file system.
     type Entry: Entry<File = Self::File, Dir = Self::Dir>;
 
-    /// Opens the entry at `path`. `path` must be absolute.
+    /// Opens the entry at `path`. `path` must be absolute; `.` and `..`
+    /// components and repeated separators are allowed.
     ///
     /// # Errors
     ///
@@ -90,6 +91,17 @@ pub trait FileSystem: Sized {
     /// All other error values are implementation defined.
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry>;
 
+    /// Opens the entry at `path`, resolving a relative `path` against the
+    /// absolute working directory `cwd`. See `path::resolve()`.
+    ///
+    /// # Errors
+    ///
+    /// If `path` is relative and `cwd` is not absolute, an error kind of
+    /// `InvalidInput` is returned. Otherwise the errors of `open()` apply.
+    fn open_at<P: AsRef<Path>, Q: AsRef<Path>>(self, cwd: P, path: Q) -> io::Result<Self::Entry> {
+        self.open(crate::path::resolve(cwd, path)?)
+    }
+
     /// Opens the file at `path`. `path` must be absolute.
     ///
     /// # Errors
@@ -5,6 +5,7 @@ use hashbrown::HashMap;
 use shim::io;
 use core::cmp;
 
+use crate::error::{FatError, Op};
 use crate::traits::BlockDevice;
 
 #[derive(Debug)]
@@ -39,19 +40,14 @@ impl CachedPartition {
     /// translated to physical sector `partition.start`. Virtual sectors of
     /// sector number `[0, num_sectors)` are accessible.
     ///
-    /// `partition.sector_size` must be an integer multiple of
-    /// `device.sector_size()`.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the partition's sector size is < the device's sector size.
+    /// `partition.start` is given in device sectors. The logical sector size
+    /// and `device.sector_size()` may differ in either direction: a logical
+    /// sector may span several device sectors, or several logical sectors may
+    /// share one device sector (e.g. 512 byte FAT sectors on a 4K eMMC).
     pub fn new<T>(device: T, partition: Partition) -> CachedPartition
     where
         T: BlockDevice + 'static,
     {
-        assert!(partition.sector_size >= device.sector_size());
-	assert!(partition.sector_size % device.sector_size() == 0);
-
         CachedPartition {
             device: Box::new(device),
             cache: HashMap::new(),
@@ -59,22 +55,16 @@ impl CachedPartition {
         }
     }
 
-    /// Returns the number of physical sectors that corresponds to
-    /// one logical sector.
-    fn factor(&self) -> u64 {
-        self.partition.sector_size / self.device.sector_size()
-    }
-
-    /// Maps a user's request for a sector `virt` to the physical sector.
-    /// Returns `None` if the virtual sector number is out of range.
-    fn virtual_to_physical(&self, virt: u64) -> Option<u64> {
+    /// Maps a user's request for a sector `virt` to the byte offset on the
+    /// device where the sector begins. Returns `None` if the virtual sector
+    /// number is out of range.
+    fn virtual_to_byte(&self, virt: u64) -> Option<u64> {
         if virt >= self.partition.num_sectors {
             return None;
         }
 
-        let physical_offset = virt * self.factor();
-        let physical_sector = self.partition.start + physical_offset;
-        Some(physical_sector)
+        let partition_start = self.partition.start * self.device.sector_size();
+        Some(partition_start + virt * self.partition.sector_size)
     }
 
     /// Returns a mutable reference to the cached sector `sector`. If the sector
@@ -87,7 +77,7 @@ impl CachedPartition {
     /// # Errors
     ///
     /// Returns an error if there is an error reading the sector from the disk.
-    pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
+    pub fn get_mut(&mut self, sector: u64) -> Result<&mut [u8], FatError> {
         self.get(sector)?;
 	let entry = self.cache.get_mut(&sector).unwrap();
 	entry.dirty = true;
@@ -100,20 +90,11 @@ impl CachedPartition {
     /// # Errors
     ///
     /// Returns an error if there is an error reading the sector from the disk.
-    pub fn get(&mut self, sector: u64) -> io::Result<&[u8]> {
+    pub fn get(&mut self, sector: u64) -> Result<&[u8], FatError> {
         if !self.cache.contains_key(&sector) {
-	    let physical_sector = self.virtual_to_physical(sector).expect("attempted to cache invalid sector");	    
-	    let num_physical = self.factor();
-	    let logical_size: usize = self.partition.sector_size as usize;
-	    let physical_size = self.device.sector_size();
-
-	    let mut data = vec![0u8; logical_size];
-	    for n in 0..num_physical {
-		self.device.read_sector(
-		    physical_sector + n,
-		    &mut data[(physical_size * n) as usize..],
-		)?;
-	    }
+	    let byte_start = self.virtual_to_byte(sector).expect("attempted to cache invalid sector");
+	    let mut data = vec![0u8; self.partition.sector_size as usize];
+	    read_bytes(&mut *self.device, byte_start, &mut data)?;
 	    self.cache.insert(sector, CacheEntry {
 		data: data,
 		dirty: false,
@@ -121,6 +102,85 @@ impl CachedPartition {
 	}
 	Ok(&self.cache[&sector].data)
     }
+
+    /// Writes every dirty sector back to the disk and marks it clean, then
+    /// issues a write barrier on the device.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error writing a sector to the disk. Sectors
+    /// that were not yet written remain dirty.
+    pub fn flush(&mut self) -> Result<(), FatError> {
+	let first_sector = self.partition.start;
+	let partition_start = first_sector * self.device.sector_size();
+	let sector_size = self.partition.sector_size;
+	let device = &mut self.device;
+
+	for (sector, entry) in self.cache.iter_mut().filter(|(_, entry)| entry.dirty) {
+	    write_bytes(&mut **device, partition_start + sector * sector_size, &entry.data)?;
+	    entry.dirty = false;
+	}
+	device.flush().map_err(write_error(first_sector))
+    }
+}
+
+/// Reads `buf.len()` bytes starting at byte `start` of `device`, which need
+/// not be aligned to the device's sectors.
+fn read_bytes(device: &mut dyn BlockDevice, start: u64, buf: &mut [u8]) -> Result<(), FatError> {
+    let physical_size = device.sector_size();
+    let mut bounce = Vec::new();
+    let mut done: u64 = 0;
+    while done < buf.len() as u64 {
+	let physical_sector = (start + done) / physical_size;
+	let offset = (start + done) % physical_size;
+	let count = cmp::min(physical_size - offset, buf.len() as u64 - done);
+	let range = done as usize..(done + count) as usize;
+
+	if offset == 0 && count == physical_size {
+	    device.read_sector(physical_sector, &mut buf[range]).map_err(read_error(physical_sector))?;
+	}
+	else {
+	    bounce.resize(physical_size as usize, 0);
+	    device.read_sector(physical_sector, &mut bounce).map_err(read_error(physical_sector))?;
+	    buf[range].copy_from_slice(&bounce[offset as usize..(offset + count) as usize]);
+	}
+	done += count;
+    }
+    Ok(())
+}
+
+/// Writes `buf` starting at byte `start` of `device`. Device sectors that are
+/// only partially covered by `buf` are read, patched, and written back.
+fn write_bytes(device: &mut dyn BlockDevice, start: u64, buf: &[u8]) -> Result<(), FatError> {
+    let physical_size = device.sector_size();
+    let mut bounce = Vec::new();
+    let mut done: u64 = 0;
+    while done < buf.len() as u64 {
+	let physical_sector = (start + done) / physical_size;
+	let offset = (start + done) % physical_size;
+	let count = cmp::min(physical_size - offset, buf.len() as u64 - done);
+	let range = done as usize..(done + count) as usize;
+
+	if offset == 0 && count == physical_size {
+	    device.write_sector(physical_sector, &buf[range]).map_err(write_error(physical_sector))?;
+	}
+	else {
+	    bounce.resize(physical_size as usize, 0);
+	    device.read_sector(physical_sector, &mut bounce).map_err(read_error(physical_sector))?;
+	    bounce[offset as usize..(offset + count) as usize].copy_from_slice(&buf[range]);
+	    device.write_sector(physical_sector, &bounce).map_err(write_error(physical_sector))?;
+	}
+	done += count;
+    }
+    Ok(())
+}
+
+fn read_error(sector: u64) -> impl FnOnce(io::Error) -> FatError {
+    move |error| FatError::Device { op: Op::ReadSector, sector, cluster: None, error }
+}
+
+fn write_error(sector: u64) -> impl FnOnce(io::Error) -> FatError {
+    move |error| FatError::Device { op: Op::WriteSector, sector, cluster: None, error }
 }
 
 // Implement `BlockDevice` for `CacheDevice`. The `read_sector` and
@@ -132,11 +192,11 @@ impl BlockDevice for CachedPartition {
 
     fn read_sector(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {    
 	if (buf.len() as u64) < self.partition.sector_size {
-	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer too small to read sector"));
+	    return Err(FatError::ShortBuffer { len: buf.len(), needed: self.partition.sector_size }.into());
 	}
 
 	if !self.cache.contains_key(&sector) {
-	    Err(io::Error::new(io::ErrorKind::Other, "attempted to read uncached sector"))
+	    Err(FatError::Uncached { sector }.into())
 	}
 	else {
 	    let entry = &self.cache[&sector].data;
@@ -146,7 +206,18 @@ impl BlockDevice for CachedPartition {
     }
 
     fn write_sector(&mut self, sector: u64, buf: &[u8]) -> io::Result<usize> {
-        unimplemented!()
+	if (buf.len() as u64) < self.partition.sector_size {
+	    return Err(FatError::ShortBuffer { len: buf.len(), needed: self.partition.sector_size }.into());
+	}
+
+	let sector_size = self.partition.sector_size as usize;
+	let entry = self.get_mut(sector)?;
+	entry.copy_from_slice(&buf[0..sector_size]);
+	Ok(sector_size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	Ok(CachedPartition::flush(self)?)
     }
 }
 
@@ -327,4 +398,61 @@ mod tests {
 	}
 	Ok(())
     }
+
+    /// a device with 4K sectors that exposes its backing storage to the test
+    struct BigSectorDevice(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl BlockDevice for BigSectorDevice {
+	fn sector_size(&self) -> u64 {
+	    4096
+	}
+
+	fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+	    let disk = self.0.lock().unwrap();
+	    let start = n as usize * 4096;
+	    let count = cmp::min(4096, buf.len());
+	    buf[..count].copy_from_slice(&disk[start..start + count]);
+	    Ok(count)
+	}
+
+	fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+	    let mut disk = self.0.lock().unwrap();
+	    let start = n as usize * 4096;
+	    let count = cmp::min(4096, buf.len());
+	    disk[start..start + count].copy_from_slice(&buf[..count]);
+	    Ok(count)
+	}
+    }
+
+    #[test]
+    fn test_cache_small_logical_sectors() {
+	let disk = std::sync::Arc::new(std::sync::Mutex::new(vec![0u8; 4096 * 4]));
+	{
+	    let mut bytes = disk.lock().unwrap();
+	    // partition starts at device sector 1, logical sector 9 = byte 4096 + 9*512
+	    bytes[4096 + 9*512] = 0x11;
+	    bytes[4096 + 9*512 + 511] = 0x22;
+	}
+
+	let partition = Partition {
+	    start: 1,
+	    num_sectors: 24,
+	    sector_size: 512,
+	};
+	let mut cache = CachedPartition::new(BigSectorDevice(disk.clone()), partition);
+
+	let sector = cache.get(9).expect("failed to read");
+	assert_eq!(sector.len(), 512);
+	assert_eq!(sector[0], 0x11);
+	assert_eq!(sector[511], 0x22);
+
+	cache.get_mut(10).expect("failed to read")[0] = 0x33;
+	cache.flush().expect("failed to flush");
+
+	let bytes = disk.lock().unwrap();
+	assert_eq!(bytes[4096 + 10*512], 0x33);
+	// neighbouring logical sectors in the same device sector are preserved
+	assert_eq!(bytes[4096 + 9*512], 0x11);
+	assert_eq!(bytes[4096 + 9*512 + 511], 0x22);
+    }
 }
@@ -0,0 +1,137 @@
+use alloc::vec::Vec;
+
+use shim::io;
+
+use crate::error::FatError;
+use crate::vfat::fat::EOC_MARKER;
+use crate::vfat::{Cluster, VFatHandle};
+
+/// A chain of clusters linked through the FAT.
+///
+/// `ClusterChain` manipulates the FAT directly, without a directory entry, so
+/// callers that manage their own storage (a swap file, the page cache) can
+/// grow, shrink, and split chains. An empty chain has no start cluster.
+#[derive(Debug)]
+pub struct ClusterChain<HANDLE: VFatHandle> {
+    vfat: HANDLE,
+    start: Option<Cluster>,
+}
+
+impl<HANDLE: VFatHandle> ClusterChain<HANDLE> {
+    /// Returns the existing chain that begins at `start`.
+    pub fn new(vfat: HANDLE, start: Cluster) -> ClusterChain<HANDLE> {
+	ClusterChain { vfat: vfat, start: Some(start) }
+    }
+
+    /// Returns a chain without any clusters.
+    pub fn empty(vfat: HANDLE) -> ClusterChain<HANDLE> {
+	ClusterChain { vfat: vfat, start: None }
+    }
+
+    /// Allocates a new chain of `count` clusters.
+    pub fn alloc(vfat: HANDLE, count: usize) -> io::Result<ClusterChain<HANDLE>> {
+	let mut chain = ClusterChain::empty(vfat);
+	chain.extend(count)?;
+	Ok(chain)
+    }
+
+    /// The first cluster of the chain, `None` if the chain is empty.
+    pub fn start(&self) -> Option<Cluster> {
+	self.start
+    }
+
+    /// Returns every cluster of the chain in order.
+    pub fn clusters(&self) -> io::Result<Vec<Cluster>> {
+	let mut clusters = Vec::new();
+	let mut current = match self.start {
+	    Some(start) => start,
+	    None => return Ok(clusters),
+	};
+	self.vfat.with(|v| {
+	    let limit = v.num_clusters as usize;
+	    loop {
+		clusters.push(current);
+		if clusters.len() > limit {
+		    return Err(FatError::ChainCycle { start: clusters[0].number() }.into());
+		}
+		match v.next_cluster(current) {
+		    Ok(next) => current = next,
+		    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+		    Err(e) => return Err(e),
+		}
+	    }
+	})?;
+	Ok(clusters)
+    }
+
+    /// Number of clusters in the chain.
+    pub fn len(&self) -> io::Result<usize> {
+	Ok(self.clusters()?.len())
+    }
+
+    /// Appends `count` newly allocated clusters to the end of the chain.
+    /// Returns the first cluster that was added. The clusters are chosen by
+    /// the file system's allocation strategy.
+    pub fn extend(&mut self, count: usize) -> io::Result<Option<Cluster>> {
+	if count == 0 {
+	    return Ok(None);
+	}
+	let mut last = self.clusters()?.last().cloned();
+	let mut first = None;
+	for added in 0..count {
+	    let cluster = self.vfat.with(|v| -> io::Result<Cluster> {
+		let cluster = v.alloc_cluster_after(last, count - added)?;
+		if let Some(last) = last {
+		    v.set_fat_entry(last, cluster.number())?;
+		}
+		Ok(cluster)
+	    })?;
+	    if self.start.is_none() {
+		self.start = Some(cluster);
+	    }
+	    if first.is_none() {
+		first = Some(cluster);
+	    }
+	    last = Some(cluster);
+	}
+	Ok(first)
+    }
+
+    /// Keeps the first `count` clusters of the chain and frees the rest.
+    /// Truncating to zero frees the whole chain.
+    pub fn truncate(&mut self, count: usize) -> io::Result<()> {
+	let clusters = self.clusters()?;
+	if count >= clusters.len() {
+	    return Ok(());
+	}
+	self.vfat.with(|v| -> io::Result<()> {
+	    if count > 0 {
+		v.set_fat_entry(clusters[count - 1], EOC_MARKER)?;
+	    }
+	    for cluster in &clusters[count..] {
+		v.free_cluster(*cluster)?;
+	    }
+	    Ok(())
+	})?;
+	if count == 0 {
+	    self.start = None;
+	}
+	Ok(())
+    }
+
+    /// Splits the chain after its first `count` clusters. `self` keeps the
+    /// head and the remaining clusters are returned as a new chain.
+    pub fn split(&mut self, count: usize) -> io::Result<ClusterChain<HANDLE>> {
+	let clusters = self.clusters()?;
+	if count >= clusters.len() {
+	    return Ok(ClusterChain::empty(self.vfat.clone()));
+	}
+	if count == 0 {
+	    let tail = ClusterChain { vfat: self.vfat.clone(), start: self.start };
+	    self.start = None;
+	    return Ok(tail);
+	}
+	self.vfat.with(|v| v.set_fat_entry(clusters[count - 1], EOC_MARKER))?;
+	Ok(ClusterChain::new(self.vfat.clone(), clusters[count]))
+    }
+}
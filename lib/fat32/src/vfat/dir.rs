@@ -15,15 +15,87 @@ use crate::util::VecExt;
 use crate::vfat::{Attributes, Date, Metadata, Time, Timestamp};
 use crate::vfat::{Cluster, Entry, File, VFatHandle};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Dir<HANDLE: VFatHandle> {
     pub vfat: HANDLE,
     pub cluster: Cluster,
     pub metadata: Metadata,
     pub short_name: String,
     pub long_name: String,
+    pub location: Option<EntryLocation>,
 }
 
+/// Where the regular directory entry of a file or directory is stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EntryLocation {
+    /// first cluster of the parent directory
+    pub dir_cluster: Cluster,
+    /// byte offset of the entry in the parent directory's cluster chain
+    pub offset: usize,
+}
+
+impl EntryLocation {
+    /// Rewrites the attribute byte of the entry at this location.
+    pub(super) fn write_attributes<HANDLE: VFatHandle>(&self, vfat: &HANDLE, attributes: Attributes) -> io::Result<()> {
+	vfat.with(|v| {
+	    let _dir = v.lock_dir(self.dir_cluster);
+	    let cluster = v.offset_cluster(self.dir_cluster, self.offset)?;
+	    let offset = self.offset % v.cluster_size() as usize + ATTRIBUTES_OFFSET;
+	    v.write_cluster(cluster, offset, &[attributes.bits()])?;
+	    Ok(())
+	})
+    }
+
+    /// Reads the short name and the metadata of the entry at this location,
+    /// `None` if the slot is free.
+    pub(super) fn read<HANDLE: VFatHandle>(&self, vfat: &HANDLE) -> io::Result<Option<(String, Metadata)>> {
+	let entry = vfat.with(|v| -> io::Result<VFatRegularDirEntry> {
+	    let _dir = v.lock_dir(self.dir_cluster);
+	    let cluster = v.offset_cluster(self.dir_cluster, self.offset)?;
+	    let mut raw = [0u8; 32];
+	    v.read_cluster(cluster, self.offset % v.cluster_size() as usize, &mut raw)?;
+	    Ok(unsafe { transmute::<[u8; 32], VFatRegularDirEntry>(raw) })
+	})?;
+	match entry.file_name[0] {
+	    0x00 | DELETED => Ok(None),
+	    _ => Ok(Some((entry.name(), entry.metadata))),
+	}
+    }
+
+    /// Rewrites the access date of the entry at this location, and nothing
+    /// else, so that reading a file never writes back a stale size.
+    pub(super) fn write_access_date<HANDLE: VFatHandle>(&self, vfat: &HANDLE, metadata: &Metadata) -> io::Result<()> {
+	let field = Metadata::ACCESS_DATE_OFFSET;
+	vfat.with(|v| {
+	    let _dir = v.lock_dir(self.dir_cluster);
+	    let cluster = v.offset_cluster(self.dir_cluster, self.offset)?;
+	    let offset = self.offset % v.cluster_size() as usize + ATTRIBUTES_OFFSET + field;
+	    v.write_cluster(cluster, offset, &metadata.as_bytes()[field..field + size_of::<Date>()])?;
+	    Ok(())
+	})
+    }
+
+    /// Rewrites everything but the name of the entry at this location.
+    pub(super) fn write_metadata<HANDLE: VFatHandle>(&self, vfat: &HANDLE, metadata: &Metadata) -> io::Result<()> {
+	vfat.with(|v| {
+	    let _dir = v.lock_dir(self.dir_cluster);
+	    let cluster = v.offset_cluster(self.dir_cluster, self.offset)?;
+	    let offset = self.offset % v.cluster_size() as usize + ATTRIBUTES_OFFSET;
+	    v.write_cluster(cluster, offset, metadata.as_bytes())?;
+	    Ok(())
+	})
+    }
+}
+
+/// offset of the attribute byte in a regular directory entry
+const ATTRIBUTES_OFFSET: usize = 11;
+
+/// first byte of a deleted entry
+const DELETED: u8 = 0xE5;
+
+/// LFN entries a name of up to 255 characters takes
+const MAX_LFN_ENTRIES: usize = 20;
+
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
 pub struct VFatRegularDirEntry {
@@ -42,8 +114,6 @@ impl VFatRegularDirEntry {
 	if let Some(term_index) = name.find(0x20 as char){
 	    name.truncate(term_index);
 	}
-	assert!(name.len() > 0);
-	
 	let mut extension = String::from(String::from_utf8_lossy(&self.file_extension)); // get extension
 	// truncate any null terminators
 	if let Some(term_index) = extension.find(0x00 as char){
@@ -78,21 +148,20 @@ pub struct VFatLfnDirEntry {
 }
 
 impl VFatLfnDirEntry {
-    fn name(&self) -> String {
+    /// Returns this entry's part of the name as UTF-16 without the terminator
+    /// and the padding after it. A surrogate pair can straddle two entries,
+    /// so the parts are only decoded once joined.
+    fn name_units(&self) -> Vec<u16> {
 	let mut name: Vec<u16> = Vec::new();
 	name.extend_from_slice(&self.name_chars);
 	name.extend_from_slice(&self.name_chars_second);
 	name.extend_from_slice(&self.name_chars_third);
-	let mut name_string = String::from_utf16(&name).unwrap();
 
 	// check for termination characters
-	if let Some(term_index) = name_string.find(0x00 as char){
-	    name_string.truncate(term_index);
-	}
-	if let Some(term_index) = name_string.find(0xFF as char){
-	    name_string.truncate(term_index);
+	if let Some(term_index) = name.iter().position(|&c| c == 0x0000 || c == 0xFFFF) {
+	    name.truncate(term_index);
 	}
-	return name_string;
+	name
     }
 }
 
@@ -132,8 +201,8 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
 	}
     }
     
-    /// Finds the entry named `name` in `self` and returns it. Comparison is
-    /// case-insensitive.
+    /// Finds the entry named `name` in `self` and returns it. Comparison
+    /// follows the lookup mode the file system was mounted with.
     ///
     /// # Errors
     ///
@@ -143,15 +212,16 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
     /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
     /// is returned.
     pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry<HANDLE>> {
-	use traits::{Dir, Entry};
-	let lowercase_name = {
+	use traits::Dir;
+	let name = {
 	    match name.as_ref().to_str() {
-		Some(name) => name.to_lowercase(),
+		Some(name) => name,
 		None => {return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid name"))},
 	    }
 	};
-	for entry in self.entries()? {    
-	    if entry.name().to_lowercase() == lowercase_name {
+	let lookup = self.vfat.with(|v| v.lookup_mode());
+	for entry in self.entries()? {
+	    if lookup.matches(name, entry.short_name(), entry.long_name()) {
 		return Ok(entry);
 	    }
 	}
@@ -174,18 +244,22 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
     pub fn root(vfat: &HANDLE) -> Entry<HANDLE> {
 	Entry::_Dir(Dir {
 	    vfat: vfat.clone(),
-	    cluster: vfat.lock(|v| v.root_cluster()),
+	    cluster: vfat.with(|v| v.root_cluster()),
 	    metadata: Metadata::root(),
 	    short_name: String::new(),
 	    long_name: String::new(),
+	    location: None,
 	})
     }
 }
 
 pub struct DirIterator<HANDLE: VFatHandle> {
     vfat: HANDLE,
+    cluster: Cluster,
     entries: Vec::<VFatDirEntry>,
     entry_offset: usize,
+    /// file sizes include the FAT+ bits
+    fat_plus: bool,
 }
 
 impl <HANDLE: VFatHandle> DirIterator<HANDLE> {
@@ -194,33 +268,34 @@ impl <HANDLE: VFatHandle> DirIterator<HANDLE> {
     /// Returns the associated type (File or Directory)
     fn parse_lfn(&mut self) -> Option<Entry<HANDLE>> {
 
-	let mut vec_name: Vec<String> = Vec::new();
+	let mut vec_name: Vec<Vec<u16>> = Vec::new();
 
 	// iterate through all LFN entries
-	while (unsafe {self.entries[self.entry_offset].unknown.attributes.lfn()}) {
+	while self.entry_offset < self.entries.len() && unsafe {self.entries[self.entry_offset].unknown.attributes.lfn()} {
 	    
-	    let mut lfn_entry: &VFatLfnDirEntry = unsafe {&self.entries[self.entry_offset].long_filename};
+	    let lfn_entry: &VFatLfnDirEntry = unsafe {&self.entries[self.entry_offset].long_filename};
+	    self.entry_offset += 1;
 
-	    // sequence: 0 ... 19
-	    let seq_num: usize = ((lfn_entry.sequence_number & 0x1F) - 1) as usize;
-	    assert!(seq_num < 20);
+	    // sequence: 1 ... 20. parts of deleted entries and parts with
+	    // corrupt sequence numbers are dropped
+	    let seq_num: usize = (lfn_entry.sequence_number & 0x1F) as usize;
+	    if lfn_entry.sequence_number == DELETED || seq_num == 0 || seq_num > MAX_LFN_ENTRIES {
+		continue;
+	    }
 
 	    // extend vec_name to hold all lfn entries
-	    if seq_num >= vec_name.len() {
-		vec_name.resize(seq_num + 1, String::from(""));
+	    if seq_num > vec_name.len() {
+		vec_name.resize(seq_num, Vec::new());
 	    }
-
-	    vec_name.insert(seq_num, lfn_entry.name());
-
-	    // go to next entry
-	    self.entry_offset += 1;
+	    vec_name[seq_num - 1] = lfn_entry.name_units();
 	}
-	let mut name = String::new();
-	for n in vec_name {
-	    name.push_str(&n);
+
+	// a directory ending in LFN entries has no entry for them
+	if self.entry_offset == self.entries.len() {
+	    return None;
 	}
-	
-	self.parse_reg(name)
+	let units: Vec<u16> = vec_name.into_iter().flatten().collect();
+	self.parse_reg(String::from_utf16_lossy(&units))
     }
 
     /// Parses a regular directory entry and returns the associated type (File or Directory)
@@ -237,11 +312,16 @@ impl <HANDLE: VFatHandle> DirIterator<HANDLE> {
 	    return None;
 	}
     
+	let location = EntryLocation {
+	    dir_cluster: self.cluster,
+	    offset: self.entry_offset * size_of::<VFatDirEntry>(),
+	};
+
 	// increment iterator
 	self.entry_offset += 1;	
 
 	// deleted entry
-	if (entry.file_name[0] == 0xE5 || entry.file_name[0] == 0x00) {
+	if (entry.file_name[0] == DELETED || entry.file_name[0] == 0x00) {
 	    return None;
 	}
 	
@@ -254,6 +334,7 @@ impl <HANDLE: VFatHandle> DirIterator<HANDLE> {
 		metadata: entry.metadata,
 		short_name: entry.name(),
 		long_name: long_name,
+		location: Some(location),
 	    });
 	    return Some(dir_entry);
 	}
@@ -263,10 +344,14 @@ impl <HANDLE: VFatHandle> DirIterator<HANDLE> {
 		cluster: Cluster::from(entry.metadata.cluster()),
 		current_cluster: Cluster::from(entry.metadata.cluster()),
 		position: 0,
-		size: entry.metadata.file_size(),
+		size: match self.fat_plus {
+		    true => entry.metadata.fat_plus_size(),
+		    false => entry.metadata.file_size() as u64,
+		},
 		metadata: entry.metadata,
 		short_name: entry.name(),
 		long_name: long_name,
+		location: Some(location),
 	    });
 	    return Some(file_entry);
 	}
@@ -314,7 +399,10 @@ impl <HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
     fn entries(&self) -> io::Result<Self::Iter> {
 	// read in all of directory
 	let mut data: Vec<u8> = Vec::new();
-	let size = self.vfat.lock(|v| v.read_chain(self.cluster, &mut data))?;
+	let (size, fat_plus) = self.vfat.with(|v| {
+	    let _dir = v.lock_dir(self.cluster);
+	    v.read_chain(self.cluster, &mut data).map(|size| (size, v.fat_plus()))
+	})?;
 	
 	// unsafe cast to Vec::<VFatDirEntry>
 	let num_entries: usize = data.len() / size_of::<VFatDirEntry>();
@@ -326,7 +414,7 @@ impl <HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
 		num_entries * size_of::<VFatDirEntry>());
 	}
 
-	Ok(DirIterator::<HANDLE>{ vfat: self.vfat.clone(), entries: entries, entry_offset: 0})
+	Ok(DirIterator::<HANDLE>{ vfat: self.vfat.clone(), cluster: self.cluster, entries: entries, entry_offset: 0, fat_plus: fat_plus })
     }
 }
 
@@ -337,7 +425,8 @@ mod tests {
     use shim::path::Path;
     use shim::io::Cursor;
 
-    use std::sync::{Arc, Mutex};
+    use std::sync::Arc;
+    use crate::vfat::SpinLock;
     use std::fmt::{self, Debug};
 
     use crate::traits::{BlockDevice, FileSystem};
@@ -347,7 +436,7 @@ mod tests {
     static mut data: [u8; 1024*14] = [0; 1024*14];
 
     #[derive(Clone)]
-    struct StdVFatHandle(Arc<Mutex<VFat<Self>>>);
+    struct StdVFatHandle(Arc<VFat<Self>>);
 
     impl Debug for StdVFatHandle {
 	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -356,12 +445,14 @@ mod tests {
     }
 
     impl VFatHandle for StdVFatHandle {
+	type Lock = SpinLock;
+
 	fn new(val: VFat<StdVFatHandle>) -> Self {
-            StdVFatHandle(Arc::new(Mutex::new(val)))
+            StdVFatHandle(Arc::new(val))
 	}
-	
-	fn lock<R>(&self, f: impl FnOnce(&mut VFat<StdVFatHandle>) -> R) -> R {
-            f(&mut self.0.lock().expect("all okay"))
+
+	fn with<R>(&self, f: impl FnOnce(&VFat<StdVFatHandle>) -> R) -> R {
+            f(&self.0)
 	}
     }
 
@@ -653,17 +744,108 @@ mod tests {
 	Ok(())
     }
     
+    #[test]
+    fn test_entry_set_attributes() {
+	use traits::Entry;
+	let vfat = VFat::<StdVFatHandle>::from(get_block()).expect("failed to initialize VFAT from image");
+	let root = Dir::root(&vfat);
+	let root_dir = root.as_dir().unwrap();
+
+	let mut file = root_dir.find("hello.txt").unwrap();
+	assert!(file.metadata().read_only());
+	file.set_read_only(false).unwrap();
+	file.set_hidden(true).unwrap();
+	assert!(!file.metadata().read_only());
+	assert!(file.metadata().hidden());
+
+	// the change is on disk, and only the entry's attribute byte changed
+	let file = root_dir.find("hello.txt").unwrap();
+	assert!(!file.metadata().read_only());
+	assert!(file.metadata().hidden());
+	assert!(!file.metadata().directory());
+	assert_eq!(file.metadata().cluster(), 4);
+	assert_eq!(file.metadata().file_size(), 4096);
+	let lfn_file = root_dir.find("abcdefghijklmnopqrstuvwxyz").unwrap();
+	assert!(lfn_file.metadata().read_only());
+	assert!(!lfn_file.metadata().hidden());
+
+	let mut file = root_dir.find("hello.txt").unwrap();
+	let mut attributes = file.metadata().attributes();
+	attributes.set_archive(true);
+	file.set_attributes(attributes).unwrap();
+	assert!(root_dir.find("hello.txt").unwrap().metadata().archive());
+
+	let mut root = Dir::root(&vfat);
+	assert_eq!(root.set_hidden(true).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_file_write() {
+	use shim::io::{Read, Seek, SeekFrom, Write};
+	use traits::{Entry, Metadata};
+	// a private copy that covers the whole partition, so the file can grow
+	let mut image = get_block().into_inner().to_vec();
+	image.resize(512 + 127 * 1024, 0);
+	let vfat = VFat::<StdVFatHandle>::from(Cursor::new(image)).expect("failed to initialize VFAT from image");
+	let root = Dir::root(&vfat);
+	let root_dir = root.as_dir().unwrap();
+
+	// overwrite in place, then append past the end of the file's only cluster
+	let mut file = root_dir.find("NO.txt").unwrap().into_file().unwrap();
+	file.write_all(&[1, 2, 3, 4]).unwrap();
+	file.seek(SeekFrom::End(0)).unwrap();
+	let tail: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+	file.write_all(&tail).unwrap();
+	assert_eq!(file.size, 2048 + 3000);
+
+	let mut file = root_dir.find("NO.txt").unwrap().into_file().unwrap();
+	assert_eq!(file.metadata.file_size(), 2048 + 3000);
+	assert_eq!(file.metadata.cluster(), 5);
+	let mut contents = Vec::new();
+	file.read_to_end(&mut contents).unwrap();
+	assert_eq!(contents[..4], [1, 2, 3, 4]);
+	assert_eq!(contents[1024..1028], [33, 5, 5, 5]);
+	assert_eq!(contents[2048..], tail[..]);
+    }
+
+    /// What the kernel's `write_atomic()` falls back to on FAT32, which
+    /// cannot rename: the file is emptied, written and synced in place.
+    #[test]
+    fn test_file_rewrite() {
+	use shim::io::{Read, Write};
+	use traits::{Entry, File, Metadata};
+	let mut image = get_block().into_inner().to_vec();
+	image.resize(512 + 127 * 1024, 0);
+	let vfat = VFat::<StdVFatHandle>::from(Cursor::new(image)).expect("failed to initialize VFAT from image");
+	let root = Dir::root(&vfat);
+	let root_dir = root.as_dir().unwrap();
+
+	let mut file = root_dir.find("NO.txt").unwrap().into_file().unwrap();
+	file.truncate().unwrap();
+	assert_eq!(file.size, 0);
+	file.write_all(b"new contents").unwrap();
+	file.flush().unwrap();
+	file.sync().unwrap();
+
+	let mut file = root_dir.find("NO.txt").unwrap().into_file().unwrap();
+	assert_eq!(file.metadata.file_size(), 12);
+	let mut contents = Vec::new();
+	file.read_to_end(&mut contents).unwrap();
+	assert_eq!(contents, b"new contents");
+    }
+
     #[test]
     fn test_dir_mock_parsing() -> Result<(), String> {
 	use traits::Entry;
+	use crate::traits::Dir as _;
 	let block_device = get_block();
 	let vfat = VFat::<StdVFatHandle>::from(block_device).expect("failed to initialize VFAT from image");
 	let _root = Dir::root(&vfat);
 	let root_dir = _root.as_dir().unwrap();
-	let mut iter = root_dir.get_iter();
+	let mut iter = root_dir.entries().unwrap();
 
 	// Regular Entry
-	let mut file = root_dir.get_next(&mut iter).unwrap();
+	let mut file = iter.next().unwrap();
 	assert_eq!(file.name(), String::from("hello.txt"));
 	assert_eq!(file.metadata().cluster(), 4);
 	assert_eq!(file.metadata().file_size(), 4096);
@@ -671,7 +853,7 @@ mod tests {
 	println!("\nfirst file: {}", file.name());
 
 	// Regular Entry
-	file = root_dir.get_next(&mut iter).unwrap();
+	file = iter.next().unwrap();
 	assert_eq!(file.name(), String::from("NO.txt"));
 	assert_eq!(file.metadata().cluster(), 5);
 	assert_eq!(file.metadata().file_size(), 2048);
@@ -679,7 +861,7 @@ mod tests {
 	println!("\nsecond file: {}", file.name());
 
 	// Long File Name Entry
-	file = root_dir.get_next(&mut iter).unwrap();
+	file = iter.next().unwrap();
 	assert_eq!(file.metadata().cluster(), 6);
 	assert_eq!(file.metadata().file_size(), 2048);
 	assert!(file.is_file());
@@ -713,9 +895,10 @@ mod tests {
     // recursively traversed file system and prints the tree structure
     fn map_dir(mut dir: &Dir<StdVFatHandle>, indent: String) {
 	use traits::Entry;
-	let mut iter = dir.get_iter();
+	use crate::traits::Dir as _;
+	let mut iter = dir.entries().unwrap();
 	loop {
-	    match dir.get_next(&mut iter) {
+	    match iter.next() {
 		Some(entry) => {
 		    if let Some(sub_dir) = entry.as_dir() {
 			print!("-d-");
@@ -767,26 +950,27 @@ mod tests {
     #[test]
     fn test_img1() -> Result<(), String> {
 	use traits::Entry;
+	use crate::traits::Dir as _;
 	let vfat = vfat_from_resource!("mock1.fat32.img");
 
-	let bytes_per_sector = vfat.lock(|v| v.bytes_per_sector);
+	let bytes_per_sector = vfat.with(|v| v.bytes_per_sector);
 	assert_eq!(bytes_per_sector, 512);
 
-	let sectors_per_cluster = vfat.lock(|v| v.sectors_per_cluster);
+	let sectors_per_cluster = vfat.with(|v| v.sectors_per_cluster);
 	assert_eq!(sectors_per_cluster, 1);
 
-	let sectors_per_fat = vfat.lock(|v| v.sectors_per_fat);
+	let sectors_per_fat = vfat.with(|v| v.sectors_per_fat);
 	assert_eq!(sectors_per_fat, 0x0BD1);
 
-	let fat_start_sector = vfat.lock(|v| v.fat_start_sector);
+	let fat_start_sector = vfat.with(|v| v.fat_start_sector);
 	assert_eq!(fat_start_sector, 32);
 
-	let data_start_sector = vfat.lock(|v| v.data_start_sector);
+	let data_start_sector = vfat.with(|v| v.data_start_sector);
 	assert_eq!(data_start_sector, 6082);
 	
 	let _root = Dir::root(&vfat);
 	let root_dir = _root.as_dir().unwrap();
-	let mut iter = root_dir.get_iter();
+	let mut iter = root_dir.entries().unwrap();
 	
 	// expected valued
 	let names = vec!["CS140E", "rpi3-docs", "solutions", "NOTES"];
@@ -794,7 +978,7 @@ mod tests {
 	
 	let mut count = 0;
 	loop {
-	    match root_dir.get_next(&mut iter) {
+	    match iter.next() {
 		Some(entry) => {
 		    assert_eq!(entry.name(), names[count]);
 		    assert_eq!(entry.is_dir(), is_dir[count]);
@@ -815,9 +999,9 @@ mod tests {
 
 	// lets search a sub directory
 	let mut sub_dir = root_dir.find("rpi3-docs").expect("failed to find rpi3_docs in root directory").into_dir().unwrap();	
-	iter = sub_dir.get_iter();
+	iter = sub_dir.entries().unwrap();
 	loop {
-	    match sub_dir.get_next(&mut iter) {
+	    match iter.next() {
 		Some(entry) => {
 		    if let Some(dir) = entry.as_dir() {
 			println!("\ndir: {}", entry.name());
@@ -858,16 +1042,16 @@ mod tests {
 	let start_cluster = file.cluster;
 	let mut offset_cluster = file.cluster;
 	let mut cmp_cluster = file.cluster;
-	let cluster_size = vfat.lock(|v| v.cluster_size()) as usize;
+	let cluster_size = vfat.with(|v| v.cluster_size()) as usize;
 	let mut current_cluster = offset / cluster_size;
 	
 	while (offset as u64) < file.size() {
 	    if offset / cluster_size > current_cluster {
 		current_cluster = offset / cluster_size;
-		cmp_cluster = vfat.lock(|v| v.next_cluster(cmp_cluster)).unwrap();
+		cmp_cluster = vfat.with(|v| v.next_cluster(cmp_cluster)).unwrap();
 	    }
 	    
-	    offset_cluster = vfat.lock(|v| v.offset_cluster(start_cluster, offset)).unwrap();
+	    offset_cluster = vfat.with(|v| v.offset_cluster(start_cluster, offset)).unwrap();
 	    assert_eq!(offset_cluster, cmp_cluster);
 	    
 	    offset += 10;
@@ -903,7 +1087,7 @@ mod tests {
 		position = file.seek(SeekFrom::Current(seek_size)).unwrap();
 		seek_position += seek_size as u64;
 		assert_eq!(file.position as u64, seek_position);
-		let cluster = vfat.lock(|v| v.offset_cluster(file.cluster, file.position as usize)).unwrap();
+		let cluster = vfat.with(|v| v.offset_cluster(file.cluster, file.position as usize)).unwrap();
 		assert_eq!(file.current_cluster, cluster);
 	    }
 	    println!("{}", seek_size);
@@ -916,8 +1100,8 @@ mod tests {
 	file.current_cluster = file.cluster;
 	position = file.seek(SeekFrom::Current(seek_size)).unwrap();
 	assert_eq!(file.position as u64, position);
-	assert_eq!(file.position, seek_size as u32);
-	let cluster = vfat.lock(|v| v.offset_cluster(file.cluster, file.position as usize)).unwrap();
+	assert_eq!(file.position, seek_size as u64);
+	let cluster = vfat.with(|v| v.offset_cluster(file.cluster, file.position as usize)).unwrap();
 	assert_eq!(file.current_cluster, cluster);
 
 	// longest seek possible
@@ -926,8 +1110,8 @@ mod tests {
 	file.current_cluster = file.cluster;
 	position = file.seek(SeekFrom::Current(seek_size)).unwrap();
 	assert_eq!(file.position as u64, position);
-	assert_eq!(file.position, seek_size as u32);
-	let cluster = vfat.lock(|v| v.offset_cluster(file.cluster, file.position as usize - 1)).unwrap();
+	assert_eq!(file.position, seek_size as u64);
+	let cluster = vfat.with(|v| v.offset_cluster(file.cluster, file.position as usize - 1)).unwrap();
 	assert_eq!(file.current_cluster, cluster);
 	
 	// seeking past end of file
@@ -4,6 +4,7 @@ use core::mem::{size_of, transmute};
 
 use crate::traits::BlockDevice;
 use crate::vfat::Error;
+use crate::vfat::format::{Geometry, MEDIA_FIXED};
 
 const EBPB_SIZE: usize = size_of::<BiosParameterBlock>();
 const VALID_SIG_1: u8 = 0x28;
@@ -76,6 +77,41 @@ impl BiosParameterBlock {
 	Ok(ebpb)
     }
 
+    /// Returns the boot sector of a new FAT32 volume laid out as GEOMETRY.
+    pub(crate) fn new(geometry: &Geometry, root_cluster: u32, fsinfo: u16, backup_boot: u16, volume_id: u32, label: [u8; 11]) -> BiosParameterBlock {
+	BiosParameterBlock {
+	    jmp_short_xx_nop: [0xEB, 0x58, 0x90],
+	    oem_ID: *b"rustOS  ",
+	    bytes_per_sector: geometry.bytes_per_sector.to_le_bytes(),
+	    sector_per_cluster: geometry.sectors_per_cluster,
+	    reserved_sectors: geometry.reserved_sectors.to_le_bytes(),
+	    num_FAT: geometry.num_fats,
+	    max_dir_entry: [0; 2],
+	    total_logical_sectors: [0; 2],
+	    FAT_ID: MEDIA_FIXED,
+	    sectors_per_FAT: [0; 2],
+	    sector_per_track: 63u16.to_le_bytes(),
+	    num_heads: 255u16.to_le_bytes(),
+	    num_hidden_sector: geometry.hidden_sectors.to_le_bytes(),
+	    total_logical_sectors_alt: geometry.num_sectors.to_le_bytes(),
+	    sectors_per_FAT_alt: geometry.sectors_per_fat.to_le_bytes(),
+	    flags: [0; 2],
+	    FAT_version: [0; 2],
+	    root_cluster: root_cluster.to_le_bytes(),
+	    FSInfo: fsinfo.to_le_bytes(),
+	    backup_boot: backup_boot.to_le_bytes(),
+	    reserved: [0; 12],
+	    drive_number: 0x80,
+	    winNT_flags: 0,
+	    signature: VALID_SIG_2,
+	    volume_ID: volume_id.to_le_bytes(),
+	    volume_label: label,
+	    system_ID: *b"FAT32   ",
+	    boot_code: [0; 420],
+	    boot_signature: BOOT_SIG.to_le_bytes(),
+	}
+    }
+
     /// byte size of logical sectors for partition
     pub fn logical_sector_size(&self) -> u32 {
 	u16::from_le_bytes(self.bytes_per_sector) as u32
@@ -128,6 +164,15 @@ impl BiosParameterBlock {
 	u32::from_le_bytes(self.root_cluster)
     }
 
+    /// offset in logical sectors from start of partition to the FSInfo sector,
+    /// `None` if the volume has none
+    pub fn fsinfo_sector(&self) -> Option<u32> {
+	match u16::from_le_bytes(self.FSInfo) {
+	    0 | 0xFFFF => None,
+	    sector => Some(sector as u32),
+	}
+    }
+
     /// returns true if EBPB signature is valid
     pub fn signature(&self) -> bool {
 	if self.signature == VALID_SIG_1 || self.signature == VALID_SIG_2 {
@@ -1,17 +1,123 @@
 
 use crate::traits;
-use crate::vfat::{Dir, File, Metadata, VFatHandle};
+use crate::vfat::{Attributes, Dir, File, Metadata, VFatHandle};
 use core::fmt;
 use crate::vfat;
+use shim::io;
 
 // You can change this definition if you want
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Entry<HANDLE: VFatHandle> {
     _File(File<HANDLE>),
     _Dir(Dir<HANDLE>),
 }
 
-// TODO: Implement any useful helper methods on `Entry`.
+impl <HANDLE: VFatHandle> Entry<HANDLE> {
+    /// The 8.3 name of the entry
+    pub fn short_name(&self) -> &str {
+	match self {
+	    &Entry::_File(ref file) => &file.short_name,
+	    &Entry::_Dir(ref dir) => &dir.short_name,
+	}
+    }
+
+    /// The long file name of the entry, empty if the entry has none
+    pub fn long_name(&self) -> &str {
+	match self {
+	    &Entry::_File(ref file) => &file.long_name,
+	    &Entry::_Dir(ref dir) => &dir.long_name,
+	}
+    }
+
+    /// Replaces the attributes of the entry and rewrites its directory entry
+    /// on disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if `attributes` would turn the entry into a
+    /// different kind of entry (directory, volume ID or LFN), or if the entry
+    /// is the root directory, which has no directory entry.
+    pub fn set_attributes(&mut self, attributes: Attributes) -> io::Result<()> {
+	let (vfat, location, metadata) = match self {
+	    Entry::_File(file) => (&file.vfat, file.location, &mut file.metadata),
+	    Entry::_Dir(dir) => (&dir.vfat, dir.location, &mut dir.metadata),
+	};
+	let current = metadata.attributes;
+	if attributes.lfn()
+	    || attributes.directory() != current.directory()
+	    || attributes.volume_id() != current.volume_id() {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot change the kind of an entry"));
+	}
+	let location = match location {
+	    Some(location) => location,
+	    None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "root directory has no attributes")),
+	};
+	location.write_attributes(vfat, attributes)?;
+	metadata.attributes = attributes;
+	Ok(())
+    }
+
+    /// Sets or clears the read only attribute of the entry on disk.
+    pub fn set_read_only(&mut self, read_only: bool) -> io::Result<()> {
+	let mut attributes = self.attributes();
+	attributes.set_read_only(read_only);
+	self.set_attributes(attributes)
+    }
+
+    /// Sets or clears the hidden attribute of the entry on disk.
+    pub fn set_hidden(&mut self, hidden: bool) -> io::Result<()> {
+	let mut attributes = self.attributes();
+	attributes.set_hidden(hidden);
+	self.set_attributes(attributes)
+    }
+
+    /// Rereads the directory entry of a copy that may be stale, so that its
+    /// metadata and, for a file, its size and first cluster are current. A
+    /// file is rewound. The root directory has no entry and is always
+    /// current.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if the slot of the entry was freed or holds another
+    /// entry now, and the error of reading the directory.
+    pub fn refresh(&mut self) -> io::Result<()> {
+	use crate::traits::Metadata as _;
+	let (vfat, location, short_name, directory) = match self {
+	    Entry::_File(file) => (&file.vfat, file.location, &file.short_name, false),
+	    Entry::_Dir(dir) => (&dir.vfat, dir.location, &dir.short_name, true),
+	};
+	let location = match location {
+	    Some(location) => location,
+	    None => return Ok(()),
+	};
+	let metadata = location.read(vfat)?
+	    .filter(|(name, metadata)| name == short_name && metadata.attributes.directory() == directory)
+	    .map(|(_, metadata)| metadata)
+	    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "entry was removed"))?;
+	match self {
+	    Entry::_File(file) => {
+		let cluster = vfat::Cluster::from(metadata.cluster());
+		file.size = match file.vfat.with(|v| v.fat_plus()) {
+		    true => metadata.fat_plus_size(),
+		    false => metadata.file_size() as u64,
+		};
+		file.cluster = cluster;
+		file.current_cluster = cluster;
+		file.position = 0;
+		file.metadata = metadata;
+	    },
+	    Entry::_Dir(dir) => dir.metadata = metadata,
+	}
+	Ok(())
+    }
+
+    fn attributes(&self) -> Attributes {
+	match self {
+	    &Entry::_File(ref file) => file.metadata.attributes,
+	    &Entry::_Dir(ref dir) => dir.metadata.attributes,
+	}
+    }
+}
 
 /// Trait implemented by directory entries in a file system.
 ///
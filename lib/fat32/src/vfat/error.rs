@@ -1,5 +1,6 @@
 use shim::io;
 
+use crate::error::FatError;
 use crate::mbr;
 
 #[derive(Debug)]
@@ -21,3 +22,9 @@ impl From<io::Error> for Error {
         Error::Io(error)
     }
 }
+
+impl From<FatError> for Error {
+    fn from(error: FatError) -> Error {
+        Error::Io(error.into())
+    }
+}
@@ -19,6 +19,15 @@ pub enum Status {
     Eoc(u32),
 }
 
+/// The low 28 bits of a FAT entry hold the cluster number
+pub const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+
+/// Value written to mark the last cluster of a chain
+pub const EOC_MARKER: u32 = 0x0FFF_FFFF;
+
+/// Bit of FAT[1] that is set when the volume was cleanly unmounted
+pub const CLEAN_SHUTDOWN: u32 = 0x0800_0000;
+
 #[repr(C, packed)]
 pub struct FatEntry(pub u32);
 
@@ -54,6 +63,21 @@ impl FatEntry {
 
 	unreachable!()
     }
+
+    /// Returns true if the clean shutdown bit is set. Only meaningful for FAT[1].
+    pub fn is_clean(&self) -> bool {
+	self.0 & CLEAN_SHUTDOWN != 0
+    }
+
+    /// Sets or clears the clean shutdown bit. Only meaningful for FAT[1].
+    pub fn set_clean(&mut self, clean: bool) {
+	if clean {
+	    self.0 = self.0 | CLEAN_SHUTDOWN;
+	}
+	else {
+	    self.0 = self.0 & !CLEAN_SHUTDOWN;
+	}
+    }
 }
 
 impl fmt::Debug for FatEntry {
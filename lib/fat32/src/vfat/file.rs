@@ -1,21 +1,23 @@
 use alloc::string::String;
+use alloc::vec::Vec;
 
 use shim::io::{self, SeekFrom};
-use core::cmp::{max, min};
+use core::cmp::min;
 
 use crate::traits;
-use crate::vfat::{Cluster, Entry, Metadata, VFatHandle};
+use crate::vfat::{Cluster, Entry, EntryLocation, Metadata, TimeUpdate, VFatHandle};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct File<HANDLE: VFatHandle> {
     pub vfat: HANDLE,
     pub cluster: Cluster,
     pub current_cluster: Cluster,
-    pub position: u32,
-    pub size: u32,
+    pub position: u64,
+    pub size: u64,
     pub metadata: Metadata,
     pub short_name: String,
     pub long_name: String,
+    pub location: Option<EntryLocation>,
 }
 
 impl <HANDLE:VFatHandle> File<HANDLE> {
@@ -36,6 +38,113 @@ impl <HANDLE:VFatHandle> File<HANDLE> {
 	    &self.long_name
 	}
     }
+
+    /// The runs of contiguous clusters holding the file's data, in file order,
+    /// as the first cluster of each run and its length in clusters. An empty
+    /// file has none.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UnexpectedEof` if the cluster chain ends before the end of the
+    /// file, and the error of the file system if the chain is broken.
+    pub fn extents(&self) -> io::Result<Vec<(Cluster, u32)>> {
+	let cluster_size = self.vfat.with(|v| v.cluster_size()) as u64;
+	let mut remaining = (self.size + cluster_size - 1) / cluster_size;
+	let mut extents: Vec<(Cluster, u32)> = Vec::new();
+	let mut cluster = self.cluster;
+	while remaining > 0 {
+	    match extents.last_mut() {
+		Some((start, len)) if start.number() + *len == cluster.number() => *len += 1,
+		_ => extents.push((cluster, 1)),
+	    }
+	    remaining -= 1;
+	    if remaining > 0 {
+		cluster = self.vfat.with(|v| v.next_cluster(cluster)).map_err(|e| match e.kind() {
+		    io::ErrorKind::Interrupted => io::Error::new(io::ErrorKind::UnexpectedEof, "cluster chain ends before end of file"),
+		    _ => e,
+		})?;
+	    }
+	}
+	Ok(extents)
+    }
+
+    /// Records a read in the access date as the volume's time policy asks,
+    /// writing only the date so that a stale copy of the size is never
+    /// written back. A read is not failed for it.
+    fn touch_access(&mut self) {
+	use crate::traits::Metadata as _;
+	let location = match self.location {
+	    Some(location) => location,
+	    None => return,
+	};
+	let (policy, now) = self.vfat.with(|v| (v.time_update(), v.now()));
+	let today = match now {
+	    Some(now) => now.date,
+	    None => return,
+	};
+	let accessed = self.metadata.access_date();
+	if accessed == today {
+	    return;
+	}
+	let update = match policy {
+	    TimeUpdate::Always => true,
+	    TimeUpdate::Relatime => accessed.days() <= self.metadata.modified().date.days() || today.days() - accessed.days() > 1,
+	    TimeUpdate::Never => false,
+	};
+	if update {
+	    self.metadata.set_access_date(today);
+	    let _ = location.write_access_date(&self.vfat, &self.metadata);
+	}
+    }
+
+    /// Reads the page of the file starting at byte `offset` straight into
+    /// `page`, one cluster at a time without an intermediate buffer. The page
+    /// is as large as the caller's pages are. The part of the page past the
+    /// end of the file is zeroed. The file position is
+    /// not changed, but a read at or after the current position walks the
+    /// cluster chain from the current cluster instead of the first one.
+    ///
+    /// Returns the number of bytes read from the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if `offset` is beyond the end of the file.
+    pub fn read_into_page(&mut self, offset: u64, page: &mut [u8]) -> io::Result<usize> {
+	if offset > self.size {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot read after end of file"));
+	}
+	let (first_cluster, current_cluster, position) = (self.cluster, self.current_cluster, self.position);
+	let bytes_to_read = min(page.len() as u64, self.size - offset) as usize;
+	if bytes_to_read == 0 {
+	    for byte in page.iter_mut() {
+		*byte = 0;
+	    }
+	    return Ok(0);
+	}
+
+	let bytes_read = self.vfat.with(|v| -> io::Result<usize> {
+	    let bytes_per_cluster = v.cluster_size() as u64;
+	    let mut cluster = match offset / bytes_per_cluster >= position / bytes_per_cluster {
+		true => v.offset_cluster(current_cluster, (offset - position + position % bytes_per_cluster) as usize)?,
+		false => v.offset_cluster(first_cluster, offset as usize)?,
+	    };
+	    let mut cluster_offset = (offset % bytes_per_cluster) as usize;
+	    let mut bytes_read = 0;
+	    while bytes_read < bytes_to_read {
+		if bytes_read > 0 {
+		    cluster = v.next_cluster(cluster)?;
+		}
+		bytes_read += v.read_cluster(cluster, cluster_offset, &mut page[bytes_read..bytes_to_read])?;
+		cluster_offset = 0;
+	    }
+	    Ok(bytes_read)
+	})?;
+
+	for byte in page[bytes_read..].iter_mut() {
+	    *byte = 0;
+	}
+	Ok(bytes_read)
+    }
 }
 
 // FIXME: Implement `traits::File` (and its supertraits) for `File`.
@@ -47,32 +156,30 @@ impl <HANDLE:VFatHandle> traits::File for File<HANDLE> {
 
     /// Returns the size of the file in bytes.
     fn size(&self) -> u64 {
-	self.size as u64
-    }
-}
-
-impl <HANDLE:VFatHandle> io::Write for File<HANDLE> {
-    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
-	unimplemented!("read only file system")
-    }
-    fn flush(&mut self) -> io::Result<()> {
-	Ok(())
+	self.size
     }
 }
 
 impl <HANDLE:VFatHandle> io::Read for File<HANDLE> {   
     fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
 	use io::Seek;
-	let bytes_per_cluster: u32 = self.vfat.lock(|v| v.cluster_size()) as u32;
+	let bytes_per_cluster: u64 = self.vfat.with(|v| v.cluster_size()) as u64;
 	let mut bytes_read: usize = 0;
-	let bytes_to_read: u32 = min(_buf.len() as u32, (self.size - self.position));
+	let bytes_to_read: u64 = min(_buf.len() as u64, (self.size - self.position));
 
-	while (bytes_read as u32) < bytes_to_read {
+	while (bytes_read as u64) < bytes_to_read {
 	    let offset = (self.position % bytes_per_cluster);
-	    let new_bytes = self.vfat.lock(|v| v.read_cluster(self.current_cluster, offset as usize, &mut _buf[bytes_read..bytes_to_read as usize]))?;
-	    self.seek(SeekFrom::Current(new_bytes as i64));
+	    let new_bytes = self.vfat.with(|v| v.read_cluster(self.current_cluster, offset as usize, &mut _buf[bytes_read..bytes_to_read as usize]))?;
+	    self.seek(SeekFrom::Current(new_bytes as i64)).map_err(|e| match e.kind() {
+		// a retry would read the same bytes again
+		io::ErrorKind::Interrupted => io::Error::new(io::ErrorKind::UnexpectedEof, "cluster chain ends before the file"),
+		_ => e,
+	    })?;
 	    bytes_read += new_bytes;
 	}
+	if bytes_read > 0 {
+	    self.touch_access();
+	}
 	Ok(bytes_read as usize)
     }
 }
@@ -94,43 +201,45 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
 	let mut long_pos: u64 = 0;
 
-	// safely convert to 32 bit (FAT32) file offset
 	match _pos {
 	    SeekFrom::Start(offset) => {long_pos = offset;},
-	    SeekFrom::End(offset) => {long_pos = add_signed_unsigned(self.size as u64, offset);},
-	    SeekFrom::Current(offset) => {long_pos = add_signed_unsigned(self.position as u64, offset);},
+	    SeekFrom::End(offset) => {long_pos = add_signed_unsigned(self.size, offset);},
+	    SeekFrom::Current(offset) => {long_pos = add_signed_unsigned(self.position, offset);},
 	}
 
-	if long_pos > self.size as u64 {
+	if long_pos > self.size {
 	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot seek after end of file"));
 	}
-	let pos = long_pos as u32;
+	let pos = long_pos;
 
 	// maintain current cluster
-	let bytes_per_cluster = self.vfat.lock(|v| v.cluster_size());
+	let bytes_per_cluster = self.vfat.with(|v| v.cluster_size()) as u64;
 	let start_of_current_cluster = self.position - (self.position % bytes_per_cluster);
 	let start_of_next_cluster = self.position + (bytes_per_cluster - (self.position % bytes_per_cluster));
 	let end_of_next_cluster = start_of_next_cluster + bytes_per_cluster - 1;
-	if pos == self.size {
+	if self.size == 0 {
+	    // empty file, no chain to walk
+	}
+	else if pos == self.size {
 	    // end of file
-	    self.current_cluster = self.vfat.lock(|v| v.offset_cluster(self.cluster, pos as usize - 1))?;
+	    self.current_cluster = self.vfat.with(|v| v.offset_cluster(self.cluster, pos as usize - 1))?;
 	}
 	else if start_of_current_cluster <= pos && pos < start_of_next_cluster {
 	    // same cluster
 	}
 	else if start_of_next_cluster <= pos && pos <= end_of_next_cluster {
 	    // if next cluster in sequence, do a fast get
-	    self.current_cluster = self.vfat.lock(|v| v.next_cluster(self.current_cluster))?;
+	    self.current_cluster = self.vfat.with(|v| v.next_cluster(self.current_cluster))?;
 	}
 	else {
 	    // if not, linear lookup of cluster
-	    self.current_cluster = self.vfat.lock(|v| v.offset_cluster(self.cluster, pos as usize))?;
+	    self.current_cluster = self.vfat.with(|v| v.offset_cluster(self.cluster, pos as usize))?;
 	}
 
 	// update file byte offset
 	self.position = pos;
 
-	Ok(pos as u64)
+	Ok(pos)
     }
 }
 
@@ -0,0 +1,226 @@
+use core::mem::size_of;
+use core::slice;
+
+use shim::io;
+
+use crate::mbr::MasterBootRecord;
+use crate::traits::BlockDevice;
+use crate::vfat::fat::{EOC_MARKER, FAT_ENTRY_MASK};
+use crate::vfat::{BiosParameterBlock, Error, FsInfo};
+
+/// Sector of the FSInfo structure, relative to the start of the partition
+pub const FSINFO_SECTOR: u16 = 1;
+
+/// Sector of the backup boot sector; the backup FSInfo follows it
+pub const BACKUP_BOOT_SECTOR: u16 = 6;
+
+/// Cluster of the root directory
+const ROOT_CLUSTER: u32 = 2;
+
+/// Media descriptor of fixed disks, repeated in the low byte of FAT[0]
+pub(crate) const MEDIA_FIXED: u8 = 0xF8;
+
+/// Attribute of the directory entry holding the volume label
+const ATTR_VOLUME_ID: u8 = 0x08;
+
+/// Largest number of clusters a FAT32 volume can address
+const MAX_CLUSTERS: u32 = 0x0FFF_FFF5 - 2;
+
+/// Label of volumes that have none
+const NO_NAME: [u8; 11] = *b"NO NAME    ";
+
+/// Parameters of a volume created by `format()`
+#[derive(Debug, Copy, Clone)]
+pub struct FormatParams {
+    /// MBR partition to format, 0 to 3
+    pub partition: usize,
+    /// sectors per cluster, a power of two up to 128; 0 picks a cluster size
+    /// from the size of the partition
+    pub sectors_per_cluster: u8,
+    /// number of FAT copies
+    pub num_fats: u8,
+    /// sectors in front of the first FAT; at least 8, so that the FSInfo and
+    /// the backup boot sector fit
+    pub reserved_sectors: u16,
+    /// volume serial number
+    pub volume_id: u32,
+    /// volume label, padded with spaces
+    pub label: [u8; 11],
+}
+
+impl Default for FormatParams {
+    fn default() -> FormatParams {
+	FormatParams {
+	    partition: 0,
+	    sectors_per_cluster: 0,
+	    num_fats: 2,
+	    reserved_sectors: 32,
+	    volume_id: 0,
+	    label: NO_NAME,
+	}
+    }
+}
+
+/// Layout of a volume about to be formatted
+pub(crate) struct Geometry {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub num_fats: u8,
+    /// first sector of the partition on the device
+    pub hidden_sectors: u32,
+    pub num_sectors: u32,
+    pub sectors_per_fat: u32,
+}
+
+impl Geometry {
+    /// Lays out a FAT32 volume on a partition of NUM_SECTORS sectors.
+    fn new(params: &FormatParams, bytes_per_sector: u16, hidden_sectors: u32, num_sectors: u32) -> io::Result<Geometry> {
+	let sectors_per_cluster = match params.sectors_per_cluster {
+	    0 => default_sectors_per_cluster(bytes_per_sector, num_sectors),
+	    n => n,
+	};
+	if !sectors_per_cluster.is_power_of_two() || params.num_fats == 0 || params.reserved_sectors < BACKUP_BOOT_SECTOR + 2 {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid format parameters"));
+	}
+
+	// the FATs need one entry for each data cluster plus the two reserved
+	// ones, and take away from the sectors left for data
+	let entries_per_sector = bytes_per_sector as u64 / size_of::<u32>() as u64;
+	let spc = sectors_per_cluster as u64;
+	let available = (num_sectors as u64).saturating_sub(params.reserved_sectors as u64);
+	let sectors_per_fat = (available + 2 * spc + entries_per_sector * spc + params.num_fats as u64 - 1)
+	    / (entries_per_sector * spc + params.num_fats as u64);
+	let data_sectors = available.saturating_sub(sectors_per_fat * params.num_fats as u64);
+	let clusters = data_sectors / spc;
+	if clusters < 1 {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "partition too small for FAT32"));
+	}
+	if clusters > MAX_CLUSTERS as u64 {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "partition too large for cluster size"));
+	}
+
+	Ok(Geometry {
+	    bytes_per_sector: bytes_per_sector,
+	    sectors_per_cluster: sectors_per_cluster,
+	    reserved_sectors: params.reserved_sectors,
+	    num_fats: params.num_fats,
+	    hidden_sectors: hidden_sectors,
+	    num_sectors: num_sectors,
+	    sectors_per_fat: sectors_per_fat as u32,
+	})
+    }
+
+    fn data_start(&self) -> u32 {
+	self.reserved_sectors as u32 + self.sectors_per_fat * self.num_fats as u32
+    }
+
+    fn num_clusters(&self) -> u32 {
+	(self.num_sectors - self.data_start()) / self.sectors_per_cluster as u32
+    }
+}
+
+/// Cluster size Windows picks for a FAT32 volume of this size
+fn default_sectors_per_cluster(bytes_per_sector: u16, num_sectors: u32) -> u8 {
+    let mib = num_sectors as u64 * bytes_per_sector as u64 >> 20;
+    let cluster_size = match mib {
+	0..=260 => 512,
+	261..=8192 => 4096,
+	8193..=16384 => 8192,
+	16385..=32768 => 16384,
+	_ => 32768,
+    };
+    core::cmp::max(1, cluster_size / bytes_per_sector as u32) as u8
+}
+
+/// Formats a partition of DEVICE as an empty FAT32 volume.
+///
+/// Writes the boot sector, FSInfo and their backups, empty FATs and an empty
+/// root directory in cluster 2, holding only the volume label if PARAMS has
+/// one. Logical sectors are the device's sectors.
+///
+/// # Errors
+///
+/// Returns an error if the MBR cannot be read, if the partition does not
+/// exist or is too small or large for the parameters, or if writing fails.
+pub fn format<T: BlockDevice>(mut device: T, params: &FormatParams) -> Result<(), Error> {
+    let mbr = MasterBootRecord::from(&mut device)?;
+    let pte = match mbr.pte(params.partition) {
+	Some(pte) if pte.num_sectors() > 0 => pte,
+	_ => return Err(Error::NotFound),
+    };
+
+    let bytes_per_sector = device.sector_size();
+    if bytes_per_sector < 512 || bytes_per_sector > 4096 || !bytes_per_sector.is_power_of_two() {
+	return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "unsupported sector size")));
+    }
+    let geometry = Geometry::new(params, bytes_per_sector as u16, pte.start_sector(), pte.num_sectors())?;
+
+    let start = pte.start_sector() as u64;
+    let mut sector = vec![0u8; bytes_per_sector as usize];
+
+    // clear the reserved sectors, then the FATs with their reserved entries
+    // and the end of the root directory's chain
+    for n in 0..geometry.data_start() as u64 {
+	let reserved = geometry.reserved_sectors as u64;
+	if n >= reserved && (n - reserved) % geometry.sectors_per_fat as u64 == 0 {
+	    write_fat_head(&mut sector);
+	}
+	device.write_sector(start + n, &sector)?;
+	zero(&mut sector);
+    }
+
+    // root directory
+    let root_start = start + geometry.data_start() as u64;
+    for n in 0..geometry.sectors_per_cluster as u64 {
+	if n == 0 && params.label != NO_NAME {
+	    sector[..11].copy_from_slice(&params.label);
+	    sector[11] = ATTR_VOLUME_ID;
+	}
+	device.write_sector(root_start + n, &sector)?;
+	zero(&mut sector);
+    }
+
+    // the FSInfo and boot sector go last so an interrupted format does not
+    // leave a volume that mounts
+    let fsinfo = FsInfo::new(geometry.num_clusters() - 1, ROOT_CLUSTER + 1);
+    copy_struct(&mut sector, &fsinfo);
+    device.write_sector(start + FSINFO_SECTOR as u64, &sector)?;
+    device.write_sector(start + BACKUP_BOOT_SECTOR as u64 + 1, &sector)?;
+    zero(&mut sector);
+
+    let ebpb = BiosParameterBlock::new(&geometry, ROOT_CLUSTER, FSINFO_SECTOR, BACKUP_BOOT_SECTOR, params.volume_id, params.label);
+    copy_struct(&mut sector, &ebpb);
+    device.write_sector(start + BACKUP_BOOT_SECTOR as u64, &sector)?;
+    device.write_sector(start, &sector)?;
+
+    device.flush()?;
+    Ok(())
+}
+
+/// Sets the reserved entries FAT[0] and FAT[1] in the first sector of a FAT,
+/// and marks the root directory's cluster as the end of its chain.
+fn write_fat_head(sector: &mut [u8]) {
+    let entries = [
+	FAT_ENTRY_MASK & !0xFF | MEDIA_FIXED as u32,
+	FAT_ENTRY_MASK,
+	EOC_MARKER,
+    ];
+    for (i, entry) in entries.iter().enumerate() {
+	sector[i * 4..(i + 1) * 4].copy_from_slice(&entry.to_le_bytes());
+    }
+}
+
+fn zero(sector: &mut [u8]) {
+    for byte in sector.iter_mut() {
+	*byte = 0;
+    }
+}
+
+/// Copies the plain-old-data structure VAL to the front of SECTOR.
+fn copy_struct<S>(sector: &mut [u8], val: &S) {
+    let bytes = unsafe {
+	slice::from_raw_parts(val as *const S as *const u8, size_of::<S>())
+    };
+    sector[..bytes.len()].copy_from_slice(bytes);
+}
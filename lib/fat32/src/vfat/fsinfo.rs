@@ -0,0 +1,62 @@
+use shim::const_assert_size;
+
+const LEAD_SIG: u32 = 0x4161_5252;
+const STRUCT_SIG: u32 = 0x6141_7272;
+const TRAIL_SIG: u32 = 0xAA55_0000;
+
+/// Value of the free count and next free fields when they are not known
+pub const UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// The FAT32 FSInfo sector, which caches the number of free clusters and a
+/// hint where to start looking for the next free cluster. Both are hints only
+/// and may be stale.
+#[repr(C, packed)]
+pub struct FsInfo {
+    lead_signature: u32,
+    reserved: [u8; 480],
+    struct_signature: u32,
+    free_count: u32,
+    next_free: u32,
+    reserved_2: [u8; 12],
+    trail_signature: u32,
+}
+
+const_assert_size!(FsInfo, 512);
+
+impl FsInfo {
+    /// returns a valid FSInfo sector with the given free count and next free hint
+    pub fn new(free_count: u32, next_free: u32) -> FsInfo {
+	FsInfo {
+	    lead_signature: LEAD_SIG,
+	    reserved: [0; 480],
+	    struct_signature: STRUCT_SIG,
+	    free_count: free_count,
+	    next_free: next_free,
+	    reserved_2: [0; 12],
+	    trail_signature: TRAIL_SIG,
+	}
+    }
+
+    /// returns true if all three signatures of the sector are valid
+    pub fn is_valid(&self) -> bool {
+	self.lead_signature == LEAD_SIG
+	    && self.struct_signature == STRUCT_SIG
+	    && self.trail_signature == TRAIL_SIG
+    }
+
+    /// cluster number the last allocation ended at, if known
+    pub fn next_free(&self) -> Option<u32> {
+	match self.next_free {
+	    UNKNOWN => None,
+	    next => Some(next),
+	}
+    }
+
+    pub fn set_next_free(&mut self, next: u32) {
+	self.next_free = next;
+    }
+
+    pub fn set_free_count(&mut self, count: u32) {
+	self.free_count = count;
+    }
+}
@@ -0,0 +1,85 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{spin_loop_hint, AtomicBool, Ordering};
+
+/// A lock without data, supplied by the platform through `VFatHandle::Lock`.
+/// `lock()` returns once the caller holds the lock; `unlock()` is only called
+/// by the holder. The file system never takes a lock it already holds.
+pub trait RawLock: Default + Send + Sync {
+    fn lock(&self);
+    fn unlock(&self);
+}
+
+/// A spin lock for platforms with working atomics.
+#[derive(Debug, Default)]
+pub struct SpinLock(AtomicBool);
+
+impl RawLock for SpinLock {
+    fn lock(&self) {
+	while self.0.compare_and_swap(false, true, Ordering::Acquire) {
+	    spin_loop_hint();
+	}
+    }
+
+    fn unlock(&self) {
+	self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Data guarded by a `RawLock`.
+pub struct Lock<R: RawLock, T> {
+    raw: R,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<R: RawLock, T: Send> Send for Lock<R, T> {}
+unsafe impl<R: RawLock, T: Send> Sync for Lock<R, T> {}
+
+impl<R: RawLock, T> Lock<R, T> {
+    pub fn new(data: T) -> Lock<R, T> {
+	Lock { raw: R::default(), data: UnsafeCell::new(data) }
+    }
+
+    /// Blocks until the lock is held and returns a guard that releases it
+    /// when dropped.
+    pub fn lock(&self) -> LockGuard<R, T> {
+	self.raw.lock();
+	LockGuard { lock: self }
+    }
+
+    /// Access without locking, which `&mut self` makes safe.
+    pub fn get_mut(&mut self) -> &mut T {
+	unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<R: RawLock, T> fmt::Debug for Lock<R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	f.write_str("Lock { .. }")
+    }
+}
+
+pub struct LockGuard<'a, R: RawLock, T> {
+    lock: &'a Lock<R, T>,
+}
+
+impl<'a, R: RawLock, T> Deref for LockGuard<'a, R, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+	unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, R: RawLock, T> DerefMut for LockGuard<'a, R, T> {
+    fn deref_mut(&mut self) -> &mut T {
+	unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, R: RawLock, T> Drop for LockGuard<'a, R, T> {
+    fn drop(&mut self) {
+	self.lock.raw.unlock();
+    }
+}
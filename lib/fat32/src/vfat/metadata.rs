@@ -67,6 +67,38 @@ impl Attributes {
     pub fn lfn(&self) -> bool {
 	self.0 == attr::LFN as u8
     }
+
+    /// Sets or clears the read only bit.
+    pub fn set_read_only(&mut self, read_only: bool) {
+	self.set(attr::READ_ONLY, read_only);
+    }
+
+    /// Sets or clears the hidden bit.
+    pub fn set_hidden(&mut self, hidden: bool) {
+	self.set(attr::HIDDEN, hidden);
+    }
+
+    /// Sets or clears the system bit.
+    pub fn set_system(&mut self, system: bool) {
+	self.set(attr::SYSTEM, system);
+    }
+
+    /// Sets or clears the archive bit.
+    pub fn set_archive(&mut self, archive: bool) {
+	self.set(attr::ARCHIVE, archive);
+    }
+
+    /// The raw attribute byte as stored on disk.
+    pub(super) fn bits(&self) -> u8 {
+	self.0
+    }
+
+    fn set(&mut self, bit: attr, value: bool) {
+	match value {
+	    true => self.0 |= bit as u8,
+	    false => self.0 &= !(bit as u8),
+	}
+    }
 }
 
 /// A structure containing a date and time.
@@ -104,6 +136,57 @@ fn truncate_bits(val: u16, least_sigbit: u16, num_bits: u16) -> u16 {
     masked_val
 }
 
+/// Days from 1970-01-01 to YEAR-MONTH-DAY of the proleptic Gregorian calendar.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy as i64;
+    era * 146097 + doe - 719468
+}
+
+/// The date DAYS after 1970-01-01 as (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl Date {
+    /// Days from 1970-01-01 to this date.
+    pub(super) fn days(&self) -> i64 {
+	use crate::traits::Timestamp as _;
+	let ts = Timestamp { date: *self, time: Time(0) };
+	days_from_civil(ts.year() as i64, ts.month() as u32, ts.day() as u32)
+    }
+}
+
+impl Timestamp {
+    /// Converts SECS seconds since the Unix epoch, in UTC, to a timestamp.
+    /// Times before 1980 or after 2107, which FAT cannot store, are clamped
+    /// to the first or the last timestamp it can.
+    pub fn from_unix(secs: u64) -> Timestamp {
+	let (year, month, day) = civil_from_days((secs / 86400) as i64);
+	let time = secs % 86400;
+	match year {
+	    y if y < 1980 => Timestamp { date: Date(1 << 5 | 1), time: Time(0) },
+	    y if y > 2107 => Timestamp { date: Date(127 << 9 | 12 << 5 | 31), time: Time(23 << 11 | 59 << 5 | 29) },
+	    _ => Timestamp {
+		date: Date(((year - 1980) as u16) << 9 | (month as u16) << 5 | day as u16),
+		time: Time(((time / 3600) as u16) << 11 | ((time / 60 % 60) as u16) << 5 | (time % 60 / 2) as u16),
+	    },
+	}
+    }
+}
+
 // FIXME: Implement `traits::Timestamp` for `Timestamp`.
 impl traits::Timestamp for Timestamp {
 
@@ -223,6 +306,52 @@ impl traits::Metadata for Metadata {
 }
 
 impl Metadata {
+    /// The attributes of the entry.
+    pub fn attributes(&self) -> Attributes {
+	self.attributes
+    }
+
+    pub(super) fn set_cluster(&mut self, cluster: u32) {
+	self.cluster_high = (cluster >> 16) as u16;
+	self.cluster_low = cluster as u16;
+    }
+
+    /// The file's size with bits 32 to 37 that the FAT+ extension keeps in
+    /// the reserved byte: size bits 32 to 34 in its bits 5 to 7, and 35 to 37
+    /// in its bits 0 to 2. Bits 3 and 4 are Windows NT's case flags.
+    pub fn fat_plus_size(&self) -> u64 {
+	let high = ((self.reserved >> 5) & 0x7) as u64 | ((self.reserved & 0x7) as u64) << 3;
+	self.file_size as u64 | high << 32
+    }
+
+    pub(super) fn set_file_size(&mut self, size: u32) {
+	self.file_size = size;
+    }
+
+    /// The date of the last access; FAT does not record its time.
+    pub(super) fn access_date(&self) -> Date {
+	self.access_date
+    }
+
+    pub(super) fn set_access_date(&mut self, date: Date) {
+	self.access_date = date;
+    }
+
+    pub(super) fn set_modified(&mut self, timestamp: Timestamp) {
+	self.modified_date = timestamp.date;
+	self.modified_time = timestamp.time;
+    }
+
+    /// Offset of the access date in the metadata as stored.
+    pub(super) const ACCESS_DATE_OFFSET: usize = 7;
+
+    /// The metadata as stored in a directory entry.
+    pub(super) fn as_bytes(&self) -> &[u8] {
+	unsafe {
+	    core::slice::from_raw_parts(self as *const Metadata as *const u8, core::mem::size_of::<Metadata>())
+	}
+    }
+
     pub fn root () -> Metadata {
 	Metadata {
 	    attributes: Attributes(attr::DIRECTORY as u8),
@@ -293,4 +422,21 @@ mod tests {
 	
 	
     }
+    #[test]
+    fn test_timestamp_from_unix() {
+	use crate::traits::Timestamp as _;
+
+	// 2019-07-01 12:34:56
+	let ts = Timestamp::from_unix(1561984496);
+	assert_eq!((ts.year(), ts.month(), ts.day()), (2019, 7, 1));
+	assert_eq!((ts.hour(), ts.minute(), ts.second()), (12, 34, 56));
+
+	// 2000-02-29, a leap day
+	let ts = Timestamp::from_unix(951782400);
+	assert_eq!((ts.year(), ts.month(), ts.day()), (2000, 2, 29));
+	assert_eq!(ts.date.days(), 951782400 / 86400);
+
+	let ts = Timestamp::from_unix(0);
+	assert_eq!((ts.year(), ts.month(), ts.day(), ts.hour()), (1980, 1, 1, 0));
+    }
 }
@@ -1,4 +1,5 @@
 pub(crate) mod cache;
+pub(crate) mod chain;
 pub(crate) mod cluster;
 pub(crate) mod dir;
 pub(crate) mod ebpb;
@@ -6,17 +7,25 @@ pub(crate) mod entry;
 pub(crate) mod error;
 pub(crate) mod fat;
 pub(crate) mod file;
+pub(crate) mod format;
+pub(crate) mod fsinfo;
+pub(crate) mod lock;
 pub(crate) mod metadata;
 pub(crate) mod vfat;
+pub(crate) mod write;
 
-pub use self::dir::Dir;
+pub use self::chain::ClusterChain;
+pub use self::cluster::Cluster;
+pub use self::dir::{Dir, EntryLocation};
 pub use self::ebpb::BiosParameterBlock;
 pub use self::entry::Entry;
 pub use self::error::Error;
 pub use self::file::File;
-pub use self::metadata::{Attributes, Date, Metadata, Time, Timestamp};
-pub use self::vfat::{VFat, VFatHandle};
+pub use self::format::{format, FormatParams};
+pub use self::lock::{Lock, LockGuard, RawLock, SpinLock};
+pub use self::metadata::{days_from_civil, Attributes, Date, Metadata, Time, Timestamp};
+pub use self::vfat::{AllocStrategy, LookupMode, MountReport, TimeUpdate, VFat, VFatHandle};
 
 pub(crate) use self::cache::{CachedPartition, Partition};
-pub(crate) use self::cluster::Cluster;
 pub(crate) use self::fat::{FatEntry, Status};
+pub(crate) use self::fsinfo::FsInfo;
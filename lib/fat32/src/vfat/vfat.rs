@@ -12,82 +12,372 @@ use shim::path;
 use shim::path::Path;
 use shim::path::Component;
 
+use crate::error::{FatError, Op};
 use crate::mbr::MasterBootRecord;
 use crate::traits::{BlockDevice, FileSystem};
 use crate::util::SliceExt;
 use crate::vfat::{BiosParameterBlock, CachedPartition, Partition};
-use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Status};
-
-/// A generic trait that handles a critical section as a closure
+use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, File, FsInfo, Status, Timestamp};
+use crate::vfat::{Lock, LockGuard, RawLock};
+use crate::vfat::fat::{EOC_MARKER, FAT_ENTRY_MASK};
+use crate::vfat::format::BACKUP_BOOT_SECTOR;
+use crate::vfat::fsinfo;
+
+/// A shared handle to a mounted file system. `VFat` does its own locking, so
+/// any number of handles can use it at the same time.
 pub trait VFatHandle: Clone + Debug + Send + Sync {
+    /// the lock guarding each independently locked part of the file system
+    type Lock: RawLock;
+
     fn new(val: VFat<Self>) -> Self;
-    fn lock<R>(&self, f: impl FnOnce(&mut VFat<Self>) -> R) -> R;
+    fn with<R>(&self, f: impl FnOnce(&VFat<Self>) -> R) -> R;
+}
+
+/// Number of locks directory changes are spread over
+const DIR_LOCKS: usize = 8;
+
+/// How path components are compared against directory entry names
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LookupMode {
+    /// match only against the 8.3 short name, ignoring ASCII case
+    Strict83,
+    /// match against the long or short name ignoring case (Windows semantics)
+    CaseInsensitive,
+    /// match exactly against the long name, or the short name if there is no long name
+    CaseSensitive,
+}
+
+impl Default for LookupMode {
+    fn default() -> LookupMode {
+	LookupMode::CaseInsensitive
+    }
+}
+
+impl LookupMode {
+    /// returns true if NAME refers to an entry with SHORT_NAME and LONG_NAME
+    pub fn matches(&self, name: &str, short_name: &str, long_name: &str) -> bool {
+	match self {
+	    LookupMode::Strict83 => short_name.eq_ignore_ascii_case(name),
+	    LookupMode::CaseInsensitive => {
+		let name = name.to_lowercase();
+		(!long_name.is_empty() && long_name.to_lowercase() == name)
+		    || short_name.to_lowercase() == name
+	    },
+	    LookupMode::CaseSensitive => {
+		if long_name.is_empty() {
+		    short_name == name
+		}
+		else {
+		    long_name == name
+		}
+	    },
+	}
+    }
 }
 
+/// How free clusters are chosen when a chain grows
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AllocStrategy {
+    /// take the lowest numbered free cluster
+    FirstFit,
+    /// take the first free cluster after the previous allocation, starting
+    /// from the FSInfo next free hint at mount
+    NextFree,
+    /// keep chains in consecutive clusters where possible: take the cluster
+    /// right after the end of the chain, or else the start of a run of free
+    /// clusters large enough for the rest of the write
+    Contiguous,
+}
+
+impl Default for AllocStrategy {
+    fn default() -> AllocStrategy {
+	AllocStrategy::FirstFit
+    }
+}
+
+/// When the access and modification times of files are updated. Nothing is
+/// updated until the volume is given a clock, or on a read-only volume.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimeUpdate {
+    /// the modification time on every write, the access date on every read
+    Always,
+    /// the modification time on every write, the access date on a read only
+    /// if it is not after the modification date or is more than a day old
+    Relatime,
+    /// neither: timestamps stay as they are, and reads never write
+    Never,
+}
+
+impl Default for TimeUpdate {
+    fn default() -> TimeUpdate {
+	TimeUpdate::Relatime
+    }
+}
+
+/// What was found wrong with a volume when it was mounted, and worked around
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct MountReport {
+    /// the boot sector failed its signature check, so the volume was mounted
+    /// from the backup boot sector
+    pub primary_boot_corrupt: bool,
+    /// the volume was not cleanly unmounted
+    pub unclean: bool,
+}
+
+/// Allocation state, guarded by the FAT lock
+#[derive(Debug)]
+struct FatState {
+    alloc: AllocStrategy,
+    next_free: u32,
+    dirty: bool,
+    read_only: bool,
+}
+
+/// A mounted FAT32 volume.
+///
+/// Its state is split over several locks so that unrelated operations do not
+/// wait for each other: the sector cache, the FAT (held while clusters are
+/// allocated, freed or linked), and the directories, where the lock of a
+/// directory is held while its entries are read or rewritten. Directory locks
+/// are striped by first cluster, so directories may share one. Locks are taken
+/// in the order directory, FAT, cache.
 #[derive(Debug)]
 pub struct VFat<HANDLE: VFatHandle> {
     phantom: PhantomData<HANDLE>,
-    device: CachedPartition,
+    device: Lock<HANDLE::Lock, CachedPartition>,
+    fat: Lock<HANDLE::Lock, FatState>,
+    dirs: [Lock<HANDLE::Lock, ()>; DIR_LOCKS],
+    lookup: Lock<HANDLE::Lock, LookupMode>,
+    fat_plus: Lock<HANDLE::Lock, bool>,
+    times: Lock<HANDLE::Lock, TimeUpdate>,
+    /// seconds since the Unix epoch, `None` while the time is not known
+    clock: Lock<HANDLE::Lock, Option<fn() -> Option<u64>>>,
     pub bytes_per_sector: u16,
     pub sectors_per_cluster: u8,
     pub sectors_per_fat: u32,
     pub fat_start_sector: u64,
     pub data_start_sector: u64,
+    pub num_fats: u8,
+    pub num_clusters: u32,
     root: Cluster,
+    fsinfo_sector: Option<u64>,
+    report: MountReport,
 }
 
 impl<HANDLE: VFatHandle> VFat<HANDLE> {
-    pub fn from<T>(mut device: T) -> Result<HANDLE, Error>
+    pub fn from<T>(device: T) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+	VFat::from_with_lookup(device, LookupMode::default())
+    }
+
+    /// mounts DEVICE, comparing path components according to LOOKUP
+    pub fn from_with_lookup<T>(device: T, lookup: LookupMode) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+	VFat::mount(device, lookup).map(|(vfat, _)| vfat)
+    }
+
+    /// Mounts DEVICE like `from_with_lookup()`, and returns what was found
+    /// wrong with the volume along with it.
+    ///
+    /// If the boot sector fails its signature check, the backup boot sector
+    /// at sector 6 of the partition is used instead. The primary is not
+    /// repaired.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if neither boot sector is valid, and the errors
+    /// of `MasterBootRecord::from()` or of reading the volume otherwise.
+    pub fn mount<T>(device: T, lookup: LookupMode) -> Result<(HANDLE, MountReport), Error>
+    where
+        T: BlockDevice + 'static,
+    {
+	VFat::mount_partition(device, 0, lookup)
+    }
+
+    /// `mount()` for the volume on MBR partition PARTITION, 0 to 3.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if the partition does not exist, and the errors of
+    /// `mount()` otherwise.
+    pub fn mount_partition<T>(mut device: T, partition: usize, lookup: LookupMode) -> Result<(HANDLE, MountReport), Error>
     where
         T: BlockDevice + 'static,
     {
 	let mbr = MasterBootRecord::from(&mut device)?;
-	let pte = mbr.first_pte();
-	let ebpb = BiosParameterBlock::from(&mut device, pte.start_sector() as u64)?;
+	let pte = match mbr.pte(partition) {
+	    Some(pte) if pte.num_sectors() > 0 => pte,
+	    _ => return Err(Error::NotFound),
+	};
+	VFat::mount_volume(device, pte.start_sector() as u64, lookup)
+    }
+
+    /// `mount()` for the volume whose boot sector is sector START of DEVICE,
+    /// for devices without a partition table such as the image of a single
+    /// volume.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if neither boot sector is valid, and the errors
+    /// of reading the volume otherwise.
+    pub fn mount_volume<T>(mut device: T, start: u64, lookup: LookupMode) -> Result<(HANDLE, MountReport), Error>
+    where
+        T: BlockDevice + 'static,
+    {
+	let mut report = MountReport::default();
+	let ebpb = match BiosParameterBlock::from(&mut device, start) {
+	    Err(Error::BadSignature) => {
+		report.primary_boot_corrupt = true;
+		BiosParameterBlock::from(&mut device, start + BACKUP_BOOT_SECTOR as u64)?
+	    },
+	    result => result?,
+	};
 	
 	let partition = Partition {
-	    start: pte.start_sector() as u64,
+	    start: start,
 	    num_sectors: ebpb.num_logical_sectors() as u64,
 	    sector_size: ebpb.logical_sector_size() as u64,
 	};
 	
 	let cache = CachedPartition::new(device, partition);
 	
-	let vfat: VFat<HANDLE> = VFat {
+	let mut vfat: VFat<HANDLE> = VFat {
 	    phantom: PhantomData,
-	    device: cache,
+	    device: Lock::new(cache),
+	    fat: Lock::new(FatState { alloc: AllocStrategy::default(), next_free: 2, dirty: false, read_only: false }),
+	    dirs: [Lock::new(()), Lock::new(()), Lock::new(()), Lock::new(()),
+		   Lock::new(()), Lock::new(()), Lock::new(()), Lock::new(())],
+	    lookup: Lock::new(lookup),
+	    fat_plus: Lock::new(false),
+	    times: Lock::new(TimeUpdate::default()),
+	    clock: Lock::new(None),
 	    bytes_per_sector: ebpb.logical_sector_size() as u16,
 	    sectors_per_cluster: ebpb.logical_per_cluster() as u8,
 	    sectors_per_fat: ebpb.num_sectors_per_fat(),
 	    fat_start_sector: ebpb.fat_start() as u64,
 	    data_start_sector:  ebpb.fat_start() as u64 + ebpb.num_sectors_per_fat() as u64 * ebpb.num_fats() as u64,
+	    num_fats: ebpb.num_fats() as u8,
+	    num_clusters: 0,
 	    root: Cluster::from(ebpb.root_cluster()),
+	    fsinfo_sector: None,
+	    report: report,
 	};
+	vfat.report.unclean = !vfat.volume_flags()?.is_clean();
+
+	// the data region and the FAT itself both bound the number of clusters
+	let data_clusters = (ebpb.num_logical_sectors() as u64 - vfat.data_start_sector) / vfat.sectors_per_cluster as u64;
+	let fat_clusters = vfat.sectors_per_fat as u64 * vfat.bytes_per_sector as u64 / size_of::<FatEntry>() as u64 - 2;
+	vfat.num_clusters = cmp::min(data_clusters, fat_clusters) as u32;
 
-	Ok(VFatHandle::new(vfat))
+	if let Some(sector) = ebpb.fsinfo_sector() {
+	    if (sector as u64) < vfat.fat_start_sector {
+		vfat.read_fsinfo(sector as u64)?;
+	    }
+	}
+
+	let report = vfat.report;
+	Ok((VFatHandle::new(vfat), report))
     }
 
     /// Size of a cluster in bytes
-    pub fn cluster_size(&mut self) -> u32 {
+    pub fn cluster_size(&self) -> u32 {
 	self.sectors_per_cluster as u32 * self.bytes_per_sector as u32
     }
 
-    pub fn root_cluster(&mut self) -> Cluster {
+    pub fn root_cluster(&self) -> Cluster {
 	self.root
     }
 
+    /// Mode used to match path components against entry names
+    pub fn lookup_mode(&self) -> LookupMode {
+	*self.lookup.lock()
+    }
+
+    pub fn set_lookup_mode(&self, lookup: LookupMode) {
+	*self.lookup.lock() = lookup;
+    }
+
+    /// Returns true if file sizes are read with the FAT+ extension
+    pub fn fat_plus(&self) -> bool {
+	*self.fat_plus.lock()
+    }
+
+    /// Reads the sizes of files of 4 GiB and more, up to 256 GiB, that the
+    /// FAT+ extension stores in a reserved byte of their directory entries.
+    /// Applies to entries read after the call. Such files cannot be written.
+    pub fn set_fat_plus(&self, fat_plus: bool) {
+	*self.fat_plus.lock() = fat_plus;
+    }
+
+    /// When the timestamps of files are updated
+    pub fn time_update(&self) -> TimeUpdate {
+	*self.times.lock()
+    }
+
+    pub fn set_time_update(&self, times: TimeUpdate) {
+	*self.times.lock() = times;
+    }
+
+    /// Takes the current time from CLOCK, which returns the seconds since the
+    /// Unix epoch, or `None` while the time is not known.
+    pub fn set_clock(&self, clock: fn() -> Option<u64>) {
+	*self.clock.lock() = Some(clock);
+    }
+
+    /// Returns the current time to stamp files with, or `None` if timestamps
+    /// are not to be updated.
+    pub(crate) fn now(&self) -> Option<Timestamp> {
+	if self.time_update() == TimeUpdate::Never || self.read_only() {
+	    return None;
+	}
+	let clock = (*self.clock.lock())?;
+	clock().map(Timestamp::from_unix)
+    }
+
+    /// Strategy used to choose free clusters
+    pub fn alloc_strategy(&self) -> AllocStrategy {
+	self.fat.lock().alloc
+    }
+
+    pub fn set_alloc_strategy(&self, alloc: AllocStrategy) {
+	self.fat.lock().alloc = alloc;
+    }
+
+    /// Returns true if writes to the volume are refused
+    pub fn read_only(&self) -> bool {
+	self.fat.lock().read_only
+    }
+
+    /// Refuses or allows writes to the volume. Writes then fail with
+    /// `PermissionDenied` before anything reaches the cache.
+    pub fn set_read_only(&self, read_only: bool) {
+	self.fat.lock().read_only = read_only;
+    }
+
+    /// Locks the directory starting at CLUSTER against concurrent changes to
+    /// its entries until the guard is dropped.
+    pub(crate) fn lock_dir(&self, cluster: Cluster) -> LockGuard<HANDLE::Lock, ()> {
+	self.dirs[cluster.number() as usize % DIR_LOCKS].lock()
+    }
+
     /// returns the next cluster in the chain. If cluster if last in chain return Err
-    pub fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Cluster> {
+    /// of `Interrupted`; a free, reserved or bad entry is `InvalidData`.
+    pub fn next_cluster(&self, cluster: Cluster) -> io::Result<Cluster> {
 	let fat_entry = self.fat_entry(cluster)?;
 	match fat_entry.status() {
 	    Status::Data(next) => Ok(next),
-	    _ => Err(io::Error::new(io::ErrorKind::Interrupted, "no next cluster")),
+	    Status::Eoc(_) => Err(FatError::EndOfChain { cluster: cluster.number() }.into()),
+	    _ => Err(FatError::BrokenChain { cluster: cluster.number(), entry: fat_entry.0 }.into()),
 	}
     }
     
     /// offsets a number of bytes into a chain from the start of CLUSTER
     /// return error if offset is beyond the end of the cluster chain
-    pub fn offset_cluster(&mut self, cluster: Cluster, offset: usize) -> io::Result<Cluster> {
+    pub fn offset_cluster(&self, cluster: Cluster, offset: usize) -> io::Result<Cluster> {
 	use crate::traits::{Entry, Metadata};
 	let distance = offset / self.cluster_size() as usize;
 	let mut current_cluster = cluster;
@@ -99,9 +389,9 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
 
     //  * A method to read from an offset of a cluster into a buffer.
     //
-    pub fn read_cluster(&mut self, cluster: Cluster, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
-	if !cluster.is_valid() {
-	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid cluster request into FAT table"));
+    pub fn read_cluster(&self, cluster: Cluster, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
+	if !self.in_volume(cluster) {
+	    return Err(FatError::InvalidCluster { op: Op::ReadCluster, cluster: cluster.number() }.into());
 	}
 	let bytes_remaining: usize = cmp::min(
 	    self.bytes_per_sector as usize * self.sectors_per_cluster as usize - offset,
@@ -112,8 +402,9 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
 	//sector = self.data_start_sector + offset as u64 / self.bytes_per_sector as u64;
 	let mut byte_offset: usize = offset % self.bytes_per_sector as usize;
 	let mut bytes_read = 0;
+	let mut device = self.device.lock();
 	while bytes_read < bytes_remaining {
-	    let data = self.device.get(sector)?;
+	    let data = device.get(sector).map_err(|e| e.during(Op::ReadCluster, cluster.number()))?;
 	    let read_size = cmp::min(self.bytes_per_sector as usize - byte_offset, buf.len() - bytes_read);
 	    buf[bytes_read..bytes_read + read_size].copy_from_slice(&data[byte_offset..byte_offset + read_size]);
 	    bytes_read += read_size;
@@ -123,11 +414,253 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
 	Ok(bytes_read)
     }
 
+    /// writes BUF into CLUSTER starting OFFSET bytes into the cluster.
+    /// the volume is marked dirty before the first write of a mount.
+    pub fn write_cluster(&self, cluster: Cluster, offset: usize, buf: &[u8]) -> io::Result<usize> {
+	if !self.in_volume(cluster) {
+	    return Err(FatError::InvalidCluster { op: Op::WriteCluster, cluster: cluster.number() }.into());
+	}
+	self.mark_dirty()?;
+
+	let bytes_remaining: usize = cmp::min(
+	    self.bytes_per_sector as usize * self.sectors_per_cluster as usize - offset,
+	    buf.len(),
+	);
+	let mut sector: u64 = self.data_start_sector + cluster.index() as u64 * self.sectors_per_cluster as u64 + offset as u64 / self.bytes_per_sector as u64;
+	let mut byte_offset: usize = offset % self.bytes_per_sector as usize;
+	let mut bytes_written = 0;
+	let mut device = self.device.lock();
+	while bytes_written < bytes_remaining {
+	    let bytes_per_sector = self.bytes_per_sector as usize;
+	    let data = device.get_mut(sector).map_err(|e| e.during(Op::WriteCluster, cluster.number()))?;
+	    let write_size = cmp::min(bytes_per_sector - byte_offset, bytes_remaining - bytes_written);
+	    data[byte_offset..byte_offset + write_size].copy_from_slice(&buf[bytes_written..bytes_written + write_size]);
+	    bytes_written += write_size;
+	    sector += 1;
+	    byte_offset = 0;
+	}
+	Ok(bytes_written)
+    }
+
+    /// sets the FAT entry of CLUSTER to VALUE in every FAT copy. the reserved
+    /// top 4 bits of the entry are preserved.
+    pub fn set_fat_entry(&self, cluster: Cluster, value: u32) -> io::Result<()> {
+	self.write_fat_entry(&mut self.fat.lock(), cluster, value)
+    }
+
+    /// `set_fat_entry` for callers holding the FAT lock
+    fn write_fat_entry(&self, fat: &mut FatState, cluster: Cluster, value: u32) -> io::Result<()> {
+	if !self.in_volume(cluster) {
+	    return Err(FatError::InvalidCluster { op: Op::WriteFat, cluster: cluster.number() }.into());
+	}
+	self.set_dirty(fat)?;
+
+	let bytes_from_start: usize = cluster.number() as usize * size_of::<FatEntry>();
+	let byte_offset: usize = bytes_from_start % self.bytes_per_sector as usize;
+	let sector_offset_into_fat: u64 = (bytes_from_start / self.bytes_per_sector as usize) as u64;
+	let mut device = self.device.lock();
+	for copy in 0..self.num_fats as u64 {
+	    let fat_sector = self.fat_start_sector + copy * self.sectors_per_fat as u64 + sector_offset_into_fat;
+	    let fat_data = device.get_mut(fat_sector).map_err(|e| e.during(Op::WriteFat, cluster.number()))?;
+	    let fat_entry: &mut [FatEntry] = unsafe {
+		fat_data.cast_mut()
+	    };
+	    let entry = &mut fat_entry[byte_offset / size_of::<FatEntry>()];
+	    entry.0 = (entry.0 & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK);
+	}
+	Ok(())
+    }
+
+    /// finds a free cluster, marks it as the end of a chain, and returns it
+    pub fn alloc_cluster(&self) -> io::Result<Cluster> {
+	self.alloc_cluster_after(None, 1)
+    }
+
+    /// like `alloc_cluster`, for a cluster that is appended to a chain ending
+    /// at PREV which still needs REMAINING clusters, this one included. the
+    /// contiguous strategy uses both to keep the chain in consecutive clusters.
+    /// the new cluster is not linked to PREV.
+    pub fn alloc_cluster_after(&self, prev: Option<Cluster>, remaining: usize) -> io::Result<Cluster> {
+	let mut fat = self.fat.lock();
+	let cluster = match (fat.alloc, prev) {
+	    (AllocStrategy::FirstFit, _) => self.find_free(2, 1)?,
+	    (AllocStrategy::NextFree, _) => self.find_free(fat.next_free, 1)?,
+	    (AllocStrategy::Contiguous, Some(prev)) if self.is_free(prev.number() + 1)? => Cluster::from(prev.number() + 1),
+	    (AllocStrategy::Contiguous, _) => self.find_free(fat.next_free, remaining)?,
+	};
+	self.write_fat_entry(&mut fat, cluster, EOC_MARKER)?;
+	fat.next_free = match cluster.number() + 1 {
+	    next if next < self.num_clusters + 2 => next,
+	    _ => 2,
+	};
+	Ok(cluster)
+    }
+
+    /// scans the FAT from cluster START, wrapping around at the end, for the
+    /// first run of RUN free clusters and returns its first cluster. if there
+    /// is no such run the start of the longest run is returned.
+    fn find_free(&self, start: u32, run: usize) -> io::Result<Cluster> {
+	let (mut run_start, mut run_len) = (0, 0);
+	let (mut best_start, mut best_len) = (0, 0);
+	for i in 0..self.num_clusters {
+	    let number = 2 + (start - 2 + i) % self.num_clusters;
+	    if number == 2 {
+		// a run does not continue across the end of the FAT
+		run_len = 0;
+	    }
+	    if !self.is_free(number)? {
+		run_len = 0;
+		continue;
+	    }
+	    if run_len == 0 {
+		run_start = number;
+	    }
+	    run_len += 1;
+	    if run_len >= run {
+		return Ok(Cluster::from(run_start));
+	    }
+	    if run_len > best_len {
+		best_start = run_start;
+		best_len = run_len;
+	    }
+	}
+	match best_len {
+	    0 => Err(FatError::NoFreeClusters.into()),
+	    _ => Ok(Cluster::from(best_start)),
+	}
+    }
+
+    /// returns true if CLUSTER is a data cluster of the volume. corrupt FATs
+    /// and directory entries can name clusters past its end.
+    fn in_volume(&self, cluster: Cluster) -> bool {
+	cluster.is_valid() && cluster.number() < self.num_clusters + 2
+    }
+
+    /// returns true if cluster NUMBER exists and is free
+    fn is_free(&self, number: u32) -> io::Result<bool> {
+	if number < 2 || number >= self.num_clusters + 2 {
+	    return Ok(false);
+	}
+	Ok(self.fat_entry(Cluster::from(number))?.status() == Status::Free)
+    }
+
+    /// marks CLUSTER as free
+    pub fn free_cluster(&self, cluster: Cluster) -> io::Result<()> {
+	self.set_fat_entry(cluster, 0)
+    }
+
+    /// Returns true if the volume was not cleanly unmounted before this mount
+    pub fn was_dirty(&self) -> bool {
+	self.report.unclean
+    }
+
+    /// clears the clean shutdown bit of FAT[1] and writes it to disk
+    /// immediately, so that a crash before `sync()` is detected on the next mount
+    pub fn mark_dirty(&self) -> io::Result<()> {
+	self.set_dirty(&mut self.fat.lock())
+    }
+
+    /// `mark_dirty` for callers holding the FAT lock
+    fn set_dirty(&self, fat: &mut FatState) -> io::Result<()> {
+	if fat.read_only {
+	    return Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only file system"));
+	}
+	if fat.dirty {
+	    return Ok(());
+	}
+	self.set_volume_clean(false)?;
+	self.device.lock().flush()?;
+	fat.dirty = true;
+	Ok(())
+    }
+
+    /// Writes all cached changes to disk, then sets the clean shutdown bit
+    pub fn sync(&self) -> io::Result<()> {
+	let mut fat = self.fat.lock();
+	if fat.dirty {
+	    self.write_fsinfo(fat.next_free)?;
+	}
+	self.device.lock().flush()?;
+	if fat.dirty {
+	    self.set_volume_clean(true)?;
+	    self.device.lock().flush()?;
+	    fat.dirty = false;
+	}
+	Ok(())
+    }
+
+    /// Syncs the volume so that it is recorded as cleanly unmounted
+    pub fn unmount(&self) -> io::Result<()> {
+	self.sync()
+    }
+
+    /// uses the FSInfo sector at SECTOR if it is valid, taking its next free
+    /// hint as the place to start allocating
+    fn read_fsinfo(&mut self, sector: u64) -> io::Result<()> {
+	let num_clusters = self.num_clusters;
+	let device = self.device.get_mut();
+	let data = device.get(sector)?;
+	let fsinfo: &FsInfo = unsafe {
+	    &data.cast::<FsInfo>()[0]
+	};
+	if !fsinfo.is_valid() {
+	    return Ok(());
+	}
+	if let Some(next) = fsinfo.next_free() {
+	    if next >= 2 && next < num_clusters + 2 {
+		self.fat.get_mut().next_free = next;
+	    }
+	}
+	self.fsinfo_sector = Some(sector);
+	Ok(())
+    }
+
+    /// stores NEXT_FREE as the next free hint in the FSInfo sector. the free
+    /// count is not tracked, so it is marked unknown.
+    fn write_fsinfo(&self, next_free: u32) -> io::Result<()> {
+	let sector = match self.fsinfo_sector {
+	    Some(sector) => sector,
+	    None => return Ok(()),
+	};
+	let mut device = self.device.lock();
+	let data = device.get_mut(sector)?;
+	let fsinfo: &mut FsInfo = unsafe {
+	    &mut data.cast_mut::<FsInfo>()[0]
+	};
+	fsinfo.set_next_free(next_free);
+	fsinfo.set_free_count(fsinfo::UNKNOWN);
+	Ok(())
+    }
+
+    /// FAT[1] of the first FAT, which holds the volume flags
+    fn volume_flags(&self) -> io::Result<FatEntry> {
+	let mut device = self.device.lock();
+	let fat_data = device.get(self.fat_start_sector)?;
+	let fat_entry: &[FatEntry] = unsafe {
+	    fat_data.cast()
+	};
+	Ok(FatEntry(fat_entry[1].0))
+    }
+
+    /// updates the clean shutdown bit in FAT[1] of every FAT copy
+    fn set_volume_clean(&self, clean: bool) -> io::Result<()> {
+	let mut device = self.device.lock();
+	for fat in 0..self.num_fats as u64 {
+	    let sector = self.fat_start_sector + fat * self.sectors_per_fat as u64;
+	    let fat_data = device.get_mut(sector)?;
+	    let fat_entry: &mut [FatEntry] = unsafe {
+		fat_data.cast_mut()
+	    };
+	    fat_entry[1].set_clean(clean);
+	}
+	Ok(())
+    }
+
     //
     //  * A method to read all of the clusters chained from a starting cluster
     //    into a vector.
     //
-    pub fn read_chain(&mut self, start: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
+    pub fn read_chain(&self, start: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
 	let cluster_size: usize = self.bytes_per_sector as usize * self.sectors_per_cluster as usize;
 	let mut tortoise = start;
 	let mut hare: io::Result<Option<Cluster>> = Ok(Some(start));
@@ -157,7 +690,7 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
 	    if let Ok(option) = hare {
 		if let Some(cluster) = option {
 		    if cluster == tortoise {
-			return Err(io::Error::new(io::ErrorKind::InvalidData, "cycle is present in cluster chain"));
+			return Err(FatError::ChainCycle { start: start.number() }.into());
 		    }
 		}
 	    }
@@ -165,7 +698,7 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
 	unreachable!();
     }
 
-    fn chain_check_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+    fn chain_check_cluster(&self, cluster: Cluster) -> io::Result<Option<Cluster>> {
 	let entry = self.fat_entry(cluster)?;
 	match entry.status() {
 	    Status::Data(next_cluster) => {
@@ -174,14 +707,8 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
 	    Status::Eoc(_) => {
 		Ok(None)
 	    },
-	    Status::Free => {
-		Err(io::Error::new(io::ErrorKind::InvalidData, "encountered free cluster"))
-	    },
-	    Status::Reserved => {
-		Err(io::Error::new(io::ErrorKind::InvalidData, "encountered reserved cluster"))
-	    },
-	    Status::Bad => {
-		Err(io::Error::new(io::ErrorKind::InvalidData, "encounterd bad cluster"))
+	    Status::Free | Status::Reserved | Status::Bad => {
+		Err(FatError::BrokenChain { cluster: cluster.number(), entry: entry.0 }.into())
 	    },
 	    _ => unreachable!(),
 	}
@@ -190,21 +717,22 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
     //  * A method to return a reference to a `FatEntry` for a cluster where the
     //    reference points directly into a cached sector.
     //
-    fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
-	if !cluster.is_valid() {
-	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid cluster request into FAT table"));
+    fn fat_entry(&self, cluster: Cluster) -> io::Result<FatEntry> {
+	if !self.in_volume(cluster) {
+	    return Err(FatError::InvalidCluster { op: Op::ReadFat, cluster: cluster.number() }.into());
 	}
 
 	let bytes_from_start: usize = cluster.number() as usize * size_of::<FatEntry>() as usize;
 	let byte_offset: usize = bytes_from_start % self.bytes_per_sector as usize;
 	let sector_offset_into_fat: usize = bytes_from_start / self.bytes_per_sector as usize;
 	let fat_sector = self.fat_start_sector as u64 + sector_offset_into_fat as u64;
-	let fat_data = self.device.get(fat_sector)?;	
+	let mut device = self.device.lock();
+	let fat_data = device.get(fat_sector).map_err(|e| e.during(Op::ReadFat, cluster.number()))?;
 	let fat_entry: &[FatEntry] = unsafe {
 	    fat_data.cast()
 	};
 
-	Ok(&fat_entry[byte_offset / size_of::<FatEntry>()])
+	Ok(FatEntry(fat_entry[byte_offset / size_of::<FatEntry>()].0))
     }
 }
 
@@ -215,42 +743,25 @@ impl<'a, HANDLE: VFatHandle> FileSystem for &'a HANDLE {
 
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
 	use crate::traits::Entry;
-	let mut entries = Vec::new();
+	if !path.as_ref().has_root() {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"));
+	}
+	let path = crate::path::resolve("/", path)?;
+	let mut entry = Dir::root(self);
 
-	for component in path.as_ref().components() {
+	for component in path.components() {
 	    match component {
-		Component::RootDir => {
-		    entries.truncate(0);
-		    entries.push(Dir::root(self));
-		},
-		Component::CurDir => {},
-		Component::ParentDir => {
-		    entries.pop();
-		    if entries.len() == 0 {
-			entries.push(Dir::root(self));
-		    }
-		},
+		Component::RootDir => {},
 		Component::Normal(name) => {
-		    if let Some(directory) = entries.last().expect("empty path").as_dir() {
-			let entry = directory.find(name)?;
-			entries.push(entry);
-		    }
-		    else {
-			return Err(io::Error::new(io::ErrorKind::InvalidInput, "no file specified"));
-		    }
-		},
-		_ => {
-		    return Err(io::Error::new(io::ErrorKind::InvalidInput, "encountered invalid path component"));
+		    entry = match entry.as_dir() {
+			Some(directory) => directory.find(name)?,
+			None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a directory")),
+		    };
 		},
+		_ => unreachable!("path is normalized"),
 	    }
 	}
-
-	if let Some(last_entry) = entries.into_iter().last() {
-	    Ok(last_entry)
-	}
-	else {
-	    Err(io::Error::from(io::ErrorKind::NotFound))
-	}
+	Ok(entry)
     }
 }
 
@@ -263,13 +774,14 @@ mod tests {
     use crate::vfat::VFat;
     
 
-    use std::sync::{Arc, Mutex};
+    use std::sync::Arc;
+    use crate::vfat::SpinLock;
     use std::fmt::{self, Debug};
 
     static mut data: [u8; 1024*9] = [0; 1024*9];
 
     #[derive(Clone)]
-    struct StdVFatHandle(Arc<Mutex<VFat<Self>>>);
+    struct StdVFatHandle(Arc<VFat<Self>>);
 
     impl Debug for StdVFatHandle {
 	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -278,12 +790,14 @@ mod tests {
     }
 
     impl VFatHandle for StdVFatHandle {
+	type Lock = SpinLock;
+
 	fn new(val: VFat<StdVFatHandle>) -> Self {
-            StdVFatHandle(Arc::new(Mutex::new(val)))
+            StdVFatHandle(Arc::new(val))
 	}
-	
-	fn lock<R>(&self, f: impl FnOnce(&mut VFat<StdVFatHandle>) -> R) -> R {
-            f(&mut self.0.lock().expect("all okay"))
+
+	fn with<R>(&self, f: impl FnOnce(&VFat<StdVFatHandle>) -> R) -> R {
+            f(&self.0)
 	}
     }
 
@@ -474,12 +988,12 @@ mod tests {
 
 	let vfat = VFat::<StdVFatHandle>::from(block_device).expect("failed to initialize VFAT from image");
 
-	let bytes_per_sector = vfat.lock(|v| v.bytes_per_sector);
-	let sectors_per_cluster = vfat.lock(|v| v.sectors_per_cluster);
-	let sectors_per_fat = vfat.lock(|v| v.sectors_per_fat);
-	let fat_start_sector = vfat.lock(|v| v.fat_start_sector);
-	let data_start_sector = vfat.lock(|v| v.data_start_sector);
-	let root = vfat.lock(|v| v.root);
+	let bytes_per_sector = vfat.with(|v| v.bytes_per_sector);
+	let sectors_per_cluster = vfat.with(|v| v.sectors_per_cluster);
+	let sectors_per_fat = vfat.with(|v| v.sectors_per_fat);
+	let fat_start_sector = vfat.with(|v| v.fat_start_sector);
+	let data_start_sector = vfat.with(|v| v.data_start_sector);
+	let root = vfat.with(|v| v.root);
 
 	assert_eq!(bytes_per_sector, 1024);
 	assert_eq!(sectors_per_cluster, 2);
@@ -496,36 +1010,36 @@ mod tests {
 	let block_device = get_block();
 
 	let vfat = VFat::<StdVFatHandle>::from(block_device).expect("failed to initialize VFAT from image");
-	let bytes_per_sector = vfat.lock(|v| v.bytes_per_sector) as usize;
-	let sectors_per_cluster = vfat.lock(|v| v.sectors_per_cluster) as usize;
+	let bytes_per_sector = vfat.with(|v| v.bytes_per_sector) as usize;
+	let sectors_per_cluster = vfat.with(|v| v.sectors_per_cluster) as usize;
 
 	let mut buf = vec![0u8; 2048];
 	
 	let mut cluster = Cluster::from(2);
-	let mut read = vfat.lock(|v| v.read_cluster(cluster, 0, buf.as_mut_slice())).unwrap();
+	let mut read = vfat.with(|v| v.read_cluster(cluster, 0, buf.as_mut_slice())).unwrap();
 	assert_eq!(buf[0..4], [99,2,2,2]);
 	assert_eq!(buf[100..108], [3,4,5,6,7,8,9,10]);
 	assert_eq!(buf[1024..1028], [33,2,2,2]);
 	assert_eq!(read, bytes_per_sector * sectors_per_cluster);
 	
 	cluster = Cluster::from(2);
-	read = vfat.lock(|v| v.read_cluster(cluster, 100, buf.as_mut_slice())).unwrap();
+	read = vfat.with(|v| v.read_cluster(cluster, 100, buf.as_mut_slice())).unwrap();
 	assert_eq!(buf[0..8], [3,4,5,6,7,8,9,10]);
 	assert_eq!(read, bytes_per_sector * sectors_per_cluster - 100);
 
 	cluster = Cluster::from(3);
-	read = vfat.lock(|v| v.read_cluster(cluster, 0, buf.as_mut_slice())).unwrap();
+	read = vfat.with(|v| v.read_cluster(cluster, 0, buf.as_mut_slice())).unwrap();
 	assert_eq!(buf[0..4], [99,3,3,3]);
 	assert_eq!(buf[1024..1028], [33,3,3,3]);
 	assert_eq!(read, bytes_per_sector * sectors_per_cluster);
 
 	cluster = Cluster::from(3);
-	read = vfat.lock(|v| v.read_cluster(cluster, 1024, buf.as_mut_slice())).unwrap();
+	read = vfat.with(|v| v.read_cluster(cluster, 1024, buf.as_mut_slice())).unwrap();
 	assert_eq!(buf[0..4], [33,3,3,3]);
 	assert_eq!(read, bytes_per_sector * sectors_per_cluster - 1024);
 
 	cluster = Cluster::from(4);
-	read = vfat.lock(|v| v.read_cluster(cluster, 0, buf.as_mut_slice())).unwrap();
+	read = vfat.with(|v| v.read_cluster(cluster, 0, buf.as_mut_slice())).unwrap();
 	assert_eq!(buf[0..4], [99,4,4,4]);
 	assert_eq!(buf[1024..1028], [33,4,4,4]);
 	assert_eq!(read, bytes_per_sector * sectors_per_cluster);
@@ -538,13 +1052,13 @@ mod tests {
 	let block_device = get_block();
 
 	let vfat = VFat::<StdVFatHandle>::from(block_device).expect("failed to initialize VFAT from image");
-	let bytes_per_sector = vfat.lock(|v| v.bytes_per_sector) as usize;
-	let sectors_per_cluster = vfat.lock(|v| v.sectors_per_cluster) as usize;
+	let bytes_per_sector = vfat.with(|v| v.bytes_per_sector) as usize;
+	let sectors_per_cluster = vfat.with(|v| v.sectors_per_cluster) as usize;
 
 	let mut buf: Vec<u8> = Vec::new();
 	
 	let mut cluster = Cluster::from(2);
-	let mut read = vfat.lock(|v| v.read_chain(cluster, &mut buf)).unwrap();
+	let mut read = vfat.with(|v| v.read_chain(cluster, &mut buf)).unwrap();
 
 	assert_eq!(buf[0..4], [99,2,2,2]);
 	assert_eq!(buf[100..108], [3,4,5,6,7,8,9,10]);
@@ -566,24 +1080,341 @@ mod tests {
 	let block_device = get_block();
 
 	let vfat = VFat::<StdVFatHandle>::from(block_device).expect("failed to initialize VFAT from image");
-	let bytes_per_sector = vfat.lock(|v| v.bytes_per_sector) as usize;
-	let sectors_per_cluster = vfat.lock(|v| v.sectors_per_cluster) as usize;
+	let bytes_per_sector = vfat.with(|v| v.bytes_per_sector) as usize;
+	let sectors_per_cluster = vfat.with(|v| v.sectors_per_cluster) as usize;
 
 	let mut buf: Vec<u8> = Vec::new();
 	
-	let mut cluster = vfat.lock(|v| v.root_cluster());
+	let mut cluster = vfat.with(|v| v.root_cluster());
 	assert_eq!(cluster.number(), 2);
 	
-	cluster = vfat.lock(|v| v.next_cluster(cluster).unwrap());
+	cluster = vfat.with(|v| v.next_cluster(cluster).unwrap());
 	assert_eq!(cluster.number(), 4);
 
-	cluster = vfat.lock(|v| v.next_cluster(cluster).unwrap());
+	cluster = vfat.with(|v| v.next_cluster(cluster).unwrap());
 	assert_eq!(cluster.number(), 3);
 
-	if let Ok(no_cluster) = vfat.lock(|v| v.next_cluster(cluster)) {
+	if let Ok(no_cluster) = vfat.with(|v| v.next_cluster(cluster)) {
 	    panic!("should have returned None as cluster chain has ended");
 	} 
 
 	Ok(())
     }
+
+    #[test]
+    fn test_file_read_into_page() {
+	use crate::vfat::{File, Metadata};
+
+	let vfat = VFat::<StdVFatHandle>::from(get_block()).expect("failed to initialize VFAT from image");
+	// the chain 2 -> 4 -> 3 holds a 5000 byte file
+	let mut file = File {
+	    vfat: vfat.clone(),
+	    cluster: Cluster::from(2),
+	    current_cluster: Cluster::from(2),
+	    position: 0,
+	    size: 5000,
+	    metadata: Metadata::default(),
+	    short_name: String::new(),
+	    long_name: String::new(),
+	    location: None,
+	};
+	let mut page = vec![0xAAu8; 64 * 1024];
+
+	assert_eq!(file.read_into_page(0, &mut page).unwrap(), 5000);
+	assert_eq!(page[0..4], [99,2,2,2]);
+	assert_eq!(page[1024..1028], [33,2,2,2]);
+	assert_eq!(page[2048..2052], [99,4,4,4]);
+	assert_eq!(page[4096..4100], [99,3,3,3]);
+	assert!(page[5000..].iter().all(|byte| *byte == 0));
+
+	assert_eq!(file.read_into_page(3072, &mut page).unwrap(), 1928);
+	assert_eq!(page[0..4], [33,4,4,4]);
+	assert_eq!(page[1024..1028], [99,3,3,3]);
+
+	// reads after the position start from the current cluster
+	use shim::io::{Seek, SeekFrom};
+	file.seek(SeekFrom::Start(2500)).unwrap();
+	assert_eq!(file.read_into_page(4096, &mut page).unwrap(), 904);
+	assert_eq!(page[0..4], [99,3,3,3]);
+	assert_eq!(file.position, 2500);
+
+	assert_eq!(file.read_into_page(5000, &mut page).unwrap(), 0);
+	assert!(file.read_into_page(5001, &mut page).is_err());
+    }
+
+    #[test]
+    fn test_lookup_mode() {
+	let strict = LookupMode::Strict83;
+	assert!(strict.matches("readme.txt", "README.TXT", "ReadMe.txt"));
+	assert!(!strict.matches("a long name.txt", "ALONGN~1.TXT", "a long name.txt"));
+
+	let insensitive = LookupMode::CaseInsensitive;
+	assert!(insensitive.matches("readme.TXT", "README.TXT", "ReadMe.txt"));
+	assert!(insensitive.matches("A LONG NAME.txt", "ALONGN~1.TXT", "a long name.txt"));
+	assert!(insensitive.matches("alongn~1.txt", "ALONGN~1.TXT", "a long name.txt"));
+	assert!(!insensitive.matches("other.txt", "README.TXT", "ReadMe.txt"));
+
+	let sensitive = LookupMode::CaseSensitive;
+	assert!(sensitive.matches("ReadMe.txt", "README.TXT", "ReadMe.txt"));
+	assert!(!sensitive.matches("readme.txt", "README.TXT", "ReadMe.txt"));
+	assert!(!sensitive.matches("README.TXT", "README.TXT", "ReadMe.txt"));
+	assert!(sensitive.matches("FIB.BIN", "FIB.BIN", ""));
+	assert!(!sensitive.matches("fib.bin", "FIB.BIN", ""));
+
+	let vfat = VFat::<StdVFatHandle>::from(get_block()).expect("failed to initialize VFAT from image");
+	assert_eq!(vfat.with(|v| v.lookup_mode()), LookupMode::CaseInsensitive);
+	vfat.with(|v| v.set_lookup_mode(LookupMode::Strict83));
+	assert_eq!(vfat.with(|v| v.lookup_mode()), LookupMode::Strict83);
+    }
+
+    static mut dirty_data: [u8; 1024*9] = [0; 1024*9];
+
+    fn get_dirty_block() -> Cursor<&'static mut [u8]> {
+	unsafe {
+	    dirty_data.copy_from_slice(get_block().into_inner());
+	    Cursor::new(&mut dirty_data[..])
+	}
+    }
+
+    #[test]
+    fn test_vfat_dirty_bit() {
+	let fat_flags = 512 + 1024 + 4;
+	let cluster_three = 512 + 2*1024 + 2*1024;
+
+	let vfat = VFat::<StdVFatHandle>::from(get_dirty_block()).expect("failed to initialize VFAT from image");
+	assert!(!vfat.with(|v| v.was_dirty()));
+
+	// the dirty flag reaches the disk on the first write, data only on sync
+	vfat.with(|v| v.write_cluster(Cluster::from(3), 0, &[7, 7, 7, 7])).unwrap();
+	unsafe {
+	    assert_eq!(dirty_data[fat_flags + 3] & 0x08, 0);
+	    assert_eq!(dirty_data[cluster_three..cluster_three+4], [99,3,3,3]);
+	}
+	drop(vfat);
+
+	let vfat = VFat::<StdVFatHandle>::from(unsafe { Cursor::new(&mut dirty_data[..]) }).expect("failed to initialize VFAT from image");
+	assert!(vfat.with(|v| v.was_dirty()));
+
+	vfat.with(|v| v.write_cluster(Cluster::from(3), 0, &[7, 7, 7, 7])).unwrap();
+	vfat.with(|v| v.unmount()).unwrap();
+	unsafe {
+	    assert_eq!(dirty_data[fat_flags + 3] & 0x08, 0x08);
+	    assert_eq!(dirty_data[cluster_three..cluster_three+4], [7,7,7,7]);
+	}
+	drop(vfat);
+
+	let vfat = VFat::<StdVFatHandle>::from(unsafe { Cursor::new(&mut dirty_data[..]) }).expect("failed to initialize VFAT from image");
+	assert!(!vfat.with(|v| v.was_dirty()));
+    }
+
+    fn get_owned_block() -> Cursor<Vec<u8>> {
+	Cursor::new(get_block().into_inner().to_vec())
+    }
+
+    /// An MBR with a single 2 MiB partition starting at sector 1, for
+    /// `format()` to lay a volume on.
+    fn get_unformatted_block() -> Cursor<Vec<u8>> {
+	let mut image = vec![0u8; 512 * 4097];
+	image[450] = 0x0C;
+	image[454..458].copy_from_slice(&1u32.to_le_bytes());
+	image[458..462].copy_from_slice(&4096u32.to_le_bytes());
+	image[510] = 0x55;
+	image[511] = 0xAA;
+	Cursor::new(image)
+    }
+
+    #[test]
+    fn test_vfat_read_only() {
+	let vfat = VFat::<StdVFatHandle>::from(get_owned_block()).expect("failed to initialize VFAT from image");
+	vfat.with(|v| v.set_read_only(true));
+	let error = vfat.with(|v| v.write_cluster(Cluster::from(3), 0, &[7])).unwrap_err();
+	assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+	assert!(vfat.with(|v| v.alloc_cluster()).is_err());
+
+	vfat.with(|v| v.set_read_only(false));
+	vfat.with(|v| v.write_cluster(Cluster::from(3), 0, &[7])).expect("write after remount failed");
+    }
+
+    #[test]
+    fn test_vfat_cluster_chain() {
+	use crate::vfat::ClusterChain;
+
+	let vfat = VFat::<StdVFatHandle>::from(get_owned_block()).expect("failed to initialize VFAT from image");
+	let numbers = |chain: &ClusterChain<StdVFatHandle>| -> Vec<u32> {
+	    chain.clusters().unwrap().iter().map(|c| c.number()).collect()
+	};
+
+	let mut chain = ClusterChain::new(vfat.clone(), Cluster::from(2));
+	assert_eq!(numbers(&chain), [2, 4, 3]);
+
+	assert_eq!(chain.extend(2).unwrap(), Some(Cluster::from(5)));
+	assert_eq!(numbers(&chain), [2, 4, 3, 5, 6]);
+	assert_eq!(vfat.with(|v| v.next_cluster(Cluster::from(3)).unwrap()), Cluster::from(5));
+
+	let mut tail = chain.split(3).unwrap();
+	assert_eq!(numbers(&chain), [2, 4, 3]);
+	assert_eq!(numbers(&tail), [5, 6]);
+
+	tail.truncate(1).unwrap();
+	assert_eq!(numbers(&tail), [5]);
+	assert_eq!(vfat.with(|v| v.fat_entry(Cluster::from(6)).unwrap().status()), Status::Free);
+
+	tail.truncate(0).unwrap();
+	assert_eq!(tail.start(), None);
+	assert_eq!(tail.len().unwrap(), 0);
+	assert_eq!(vfat.with(|v| v.fat_entry(Cluster::from(5)).unwrap().status()), Status::Free);
+
+	let fresh = ClusterChain::alloc(vfat.clone(), 1).unwrap();
+	assert_eq!(numbers(&fresh), [5]);
+    }
+
+    #[test]
+    fn test_alloc_strategies() {
+	use crate::vfat::{AllocStrategy, ClusterChain};
+
+	let numbers = |chain: &ClusterChain<StdVFatHandle>| -> Vec<u32> {
+	    chain.clusters().unwrap().iter().map(|c| c.number()).collect()
+	};
+	// clusters 2, 3, 4 and 6 are in use, 5 and everything from 7 on is free
+	let mount = |alloc: AllocStrategy| {
+	    let vfat = VFat::<StdVFatHandle>::from(get_owned_block()).expect("failed to initialize VFAT from image");
+	    vfat.with(|v| v.set_fat_entry(Cluster::from(6), EOC_MARKER)).unwrap();
+	    vfat.with(|v| v.set_alloc_strategy(alloc));
+	    vfat
+	};
+
+	let vfat = mount(AllocStrategy::FirstFit);
+	assert_eq!(numbers(&ClusterChain::alloc(vfat.clone(), 3).unwrap()), [5, 7, 8]);
+	vfat.with(|v| v.free_cluster(Cluster::from(5))).unwrap();
+	assert_eq!(vfat.with(|v| v.alloc_cluster()).unwrap(), Cluster::from(5));
+
+	let vfat = mount(AllocStrategy::NextFree);
+	assert_eq!(vfat.with(|v| v.alloc_cluster()).unwrap(), Cluster::from(5));
+	vfat.with(|v| v.free_cluster(Cluster::from(5))).unwrap();
+	assert_eq!(vfat.with(|v| v.alloc_cluster()).unwrap(), Cluster::from(7));
+
+	// the single free cluster 5 is skipped for a run of three
+	let vfat = mount(AllocStrategy::Contiguous);
+	let mut chain = ClusterChain::alloc(vfat.clone(), 3).unwrap();
+	assert_eq!(numbers(&chain), [7, 8, 9]);
+	chain.extend(2).unwrap();
+	assert_eq!(numbers(&chain), [7, 8, 9, 10, 11]);
+	// a new chain starts searching at the hint, past the free cluster 5
+	assert_eq!(numbers(&ClusterChain::alloc(vfat.clone(), 1).unwrap()), [12]);
+
+	// without a run large enough, the longest one is used and the chain
+	// continues wherever there is space
+	let free = vfat.with(|v| v.num_clusters) as usize - 10;
+	let rest = numbers(&ClusterChain::alloc(vfat.clone(), free).unwrap());
+	assert_eq!(rest.len(), free);
+	assert_eq!(rest[0], 13);
+	assert_eq!(rest[free - 1], 5);
+	assert!(vfat.with(|v| v.alloc_cluster()).is_err());
+    }
+
+    #[test]
+    fn test_concurrent_access() {
+	use crate::vfat::ClusterChain;
+
+	let vfat = VFat::<StdVFatHandle>::from(get_owned_block()).expect("failed to initialize VFAT from image");
+	let mut expected = Vec::new();
+	vfat.with(|v| v.read_chain(Cluster::from(2), &mut expected)).unwrap();
+
+	// readers of the same chain run alongside allocations by the others
+	let threads: Vec<_> = (0..4).map(|_| {
+	    let (vfat, expected) = (vfat.clone(), expected.clone());
+	    std::thread::spawn(move || {
+		for _ in 0..50 {
+		    let mut read = Vec::new();
+		    vfat.with(|v| v.read_chain(Cluster::from(2), &mut read)).unwrap();
+		    assert_eq!(read, expected);
+		}
+		ClusterChain::alloc(vfat, 2).unwrap().clusters().unwrap()
+	    })
+	}).collect();
+
+	let mut allocated: Vec<u32> = threads.into_iter()
+	    .flat_map(|thread| thread.join().unwrap())
+	    .map(|cluster| cluster.number())
+	    .collect();
+	allocated.sort();
+	allocated.dedup();
+	assert_eq!(allocated.len(), 8);
+    }
+
+    #[test]
+    fn test_format() {
+	use crate::vfat::{format, AllocStrategy, FormatParams};
+
+	let params = FormatParams { label: *b"SCRATCH    ", volume_id: 0x1234_5678, ..FormatParams::default() };
+	let mut device = get_unformatted_block();
+	format(&mut device, &params).expect("format failed");
+	let missing = FormatParams { partition: 1, ..params };
+	assert!(format(&mut device, &missing).is_err());
+
+	// 32 reserved sectors and two FATs of 32 sectors leave 4000 clusters
+	let image = device.into_inner();
+	assert_eq!(image[512..1024], image[7 * 512..8 * 512]);
+	assert_eq!(image[2 * 512..3 * 512], image[8 * 512..9 * 512]);
+	assert_eq!(&image[97 * 512..97 * 512 + 11], b"SCRATCH    ");
+
+	let vfat = VFat::<StdVFatHandle>::from(Cursor::new(image)).expect("failed to mount formatted volume");
+	assert!(!vfat.with(|v| v.was_dirty()));
+	assert_eq!(vfat.with(|v| v.num_clusters), 4000);
+	assert_eq!(vfat.with(|v| v.fat_entry(Cluster::from(2)).unwrap().status()), Status::Eoc(EOC_MARKER));
+	assert_eq!(vfat.with(|v| v.fat_entry(Cluster::from(3)).unwrap().status()), Status::Free);
+
+	// the FSInfo hint points past the root directory
+	vfat.with(|v| v.set_alloc_strategy(AllocStrategy::NextFree));
+	assert_eq!(vfat.with(|v| v.alloc_cluster()).unwrap(), Cluster::from(3));
+    }
+
+    #[test]
+    fn test_backup_boot_sector() {
+	use crate::vfat::{format, FormatParams};
+
+	let mut device = get_unformatted_block();
+	format(&mut device, &FormatParams::default()).expect("format failed");
+	let mut image = device.into_inner();
+
+	let (_, report) = VFat::<StdVFatHandle>::mount(Cursor::new(image.clone()), LookupMode::default())
+	    .expect("failed to mount formatted volume");
+	assert_eq!(report, MountReport::default());
+
+	// a wiped boot sector falls back to the backup
+	for byte in image[512..1024].iter_mut() {
+	    *byte = 0;
+	}
+	let (vfat, report) = VFat::<StdVFatHandle>::mount(Cursor::new(image.clone()), LookupMode::default())
+	    .expect("failed to mount from backup boot sector");
+	assert!(report.primary_boot_corrupt);
+	assert!(!report.unclean);
+	assert_eq!(vfat.with(|v| v.num_clusters), 4000);
+	assert_eq!(vfat.with(|v| v.fat_entry(Cluster::from(2)).unwrap().status()), Status::Eoc(EOC_MARKER));
+
+	for byte in image[7 * 512..8 * 512].iter_mut() {
+	    *byte = 0;
+	}
+	match VFat::<StdVFatHandle>::mount(Cursor::new(image), LookupMode::default()) {
+	    Err(Error::BadSignature) => {},
+	    _ => panic!("mounted a volume without a valid boot sector"),
+	}
+    }
+
+    #[test]
+    fn test_mount_volume() {
+	use crate::vfat::{format, FormatParams};
+
+	let mut device = get_unformatted_block();
+	format(&mut device, &FormatParams::default()).expect("format failed");
+
+	// the partition on its own, as a volume image would hold it
+	let volume = device.into_inner()[512..].to_vec();
+	assert!(VFat::<StdVFatHandle>::mount(Cursor::new(volume.clone()), LookupMode::default()).is_err());
+	let (vfat, report) = VFat::<StdVFatHandle>::mount_volume(Cursor::new(volume), 0, LookupMode::default())
+	    .expect("failed to mount volume without a partition table");
+	assert_eq!(report, MountReport::default());
+	assert_eq!(vfat.with(|v| v.num_clusters), 4000);
+	assert_eq!(vfat.with(|v| v.fat_entry(Cluster::from(2)).unwrap().status()), Status::Eoc(EOC_MARKER));
+    }
 }
@@ -0,0 +1,145 @@
+//! Writing to files: the `io::Write` implementation of `File`, which grows
+//! a file cluster by cluster and records its size and modification time in
+//! its directory entry, and truncation.
+
+use core::cmp::{max, min};
+
+use shim::io;
+
+use crate::vfat::{Cluster, ClusterChain, File, VFatHandle};
+
+impl <HANDLE:VFatHandle> File<HANDLE> {
+    /// Empties the file and frees its clusters.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if the file has no directory entry.
+    pub fn truncate(&mut self) -> io::Result<()> {
+	let location = match self.location {
+	    Some(location) => location,
+	    None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "file has no directory entry")),
+	};
+	if self.cluster.is_valid() {
+	    ClusterChain::new(self.vfat.clone(), self.cluster).truncate(0)?;
+	}
+	self.cluster = Cluster::from(0);
+	self.current_cluster = self.cluster;
+	self.position = 0;
+	self.size = 0;
+	self.metadata.set_cluster(0);
+	self.metadata.set_file_size(0);
+	if let Some(now) = self.vfat.with(|v| v.now()) {
+	    self.metadata.set_modified(now);
+	}
+	location.write_metadata(&self.vfat, &self.metadata)
+    }
+
+    /// Writes BUF at the current position, which WRITTEN follows, growing the
+    /// file when the write passes its end.
+    fn write_clusters(&mut self, buf: &[u8], written: &mut usize) -> io::Result<()> {
+	let cluster_size = self.vfat.with(|v| v.cluster_size()) as u64;
+	while *written < buf.len() {
+	    let offset = self.position % cluster_size;
+	    if offset == 0 && self.position == self.size {
+		let remaining = (buf.len() - *written + cluster_size as usize - 1) / cluster_size as usize;
+		self.grow(remaining)?;
+	    }
+	    let current_cluster = self.current_cluster;
+	    let bytes = self.vfat.with(|v| v.write_cluster(current_cluster, offset as usize, &buf[*written..]))?;
+	    *written += bytes;
+	    self.position += bytes as u64;
+	    self.size = max(self.size, self.position);
+
+	    // like `seek()`, keep the last cluster as the current one at the end
+	    // of the file
+	    if self.position % cluster_size == 0 && self.position < self.size {
+		self.current_cluster = self.vfat.with(|v| v.next_cluster(current_cluster))?;
+	    }
+	}
+	Ok(())
+    }
+
+    /// Makes the cluster following the end of the file the current one,
+    /// allocating it if the chain ends there. REMAINING is the number of
+    /// clusters the write still needs.
+    fn grow(&mut self, remaining: usize) -> io::Result<()> {
+	if self.size == 0 && self.cluster.is_valid() {
+	    self.current_cluster = self.cluster;
+	    return Ok(());
+	}
+	let last = match self.size {
+	    0 => None,
+	    _ => Some(self.current_cluster),
+	};
+	let cluster = self.vfat.with(|v| -> io::Result<Cluster> {
+	    let last = match last {
+		Some(last) => last,
+		None => return v.alloc_cluster_after(None, remaining),
+	    };
+	    match v.next_cluster(last) {
+		Ok(next) => Ok(next),
+		Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+		    let cluster = v.alloc_cluster_after(Some(last), remaining)?;
+		    v.set_fat_entry(last, cluster.number())?;
+		    Ok(cluster)
+		},
+		Err(e) => Err(e),
+	    }
+	})?;
+	if last.is_none() {
+	    self.cluster = cluster;
+	}
+	self.current_cluster = cluster;
+	Ok(())
+    }
+}
+
+impl <HANDLE:VFatHandle> io::Write for File<HANDLE> {
+    /// Writes `buf` at the current position. A write past the end of the file
+    /// grows it, allocating clusters by the file system's allocation strategy,
+    /// and records the new size in the file's directory entry, along with the
+    /// modification time unless the volume's time policy is `Never`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if the file would grow past 4 GiB or already is
+    /// larger, as FAT+ files are, and the error of the file system if nothing
+    /// could be written.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	let location = match self.location {
+	    Some(location) => location,
+	    None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "file has no directory entry")),
+	};
+	if self.size > u32::max_value() as u64 {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "FAT+ files larger than 4 GiB are read-only"));
+	}
+	let len = min(buf.len() as u64, (u32::max_value() as u64).saturating_sub(self.position)) as usize;
+	if len == 0 && !buf.is_empty() {
+	    return Err(io::Error::new(io::ErrorKind::InvalidInput, "file too large for FAT32"));
+	}
+
+	let (cluster, size) = (self.cluster, self.size);
+	let mut written = 0;
+	let result = self.write_clusters(&buf[..len], &mut written);
+	let mut touched = false;
+	if written > 0 {
+	    if let Some(now) = self.vfat.with(|v| v.now()) {
+		use crate::traits::Metadata as _;
+		touched = self.metadata.modified() != now;
+		self.metadata.set_modified(now);
+	    }
+	}
+	if touched || self.cluster != cluster || self.size != size {
+	    self.metadata.set_cluster(self.cluster.number());
+	    self.metadata.set_file_size(self.size as u32);
+	    location.write_metadata(&self.vfat, &self.metadata)?;
+	}
+	match (result, written) {
+	    (Err(e), 0) => Err(e),
+	    _ => Ok(written),
+	}
+    }
+    fn flush(&mut self) -> io::Result<()> {
+	Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+//! The framed protocol of the kernel's serial file server, shared by the
+//! kernel and `rustos-dev`.
+//!
+//! The host switches the shell into file server mode by sending `ESCAPE`,
+//! then sends requests, each a frame answered by one or more frames:
+//!
+//! - `List` with a path: `Data` frames of lines of the form `d NAME` for a
+//!   directory or `f SIZE NAME` for a file, then `End`.
+//! - `Get` with a path: `Data` frames holding the file, then `End`.
+//! - `Put` with a path: `Ok` once the file is created and emptied, after
+//!   which the host sends `Data` frames holding the file, then `End`,
+//!   answered by `Ok` once the file is written.
+//! - `Quit`: `Ok`, after which the shell takes the console back.
+//!
+//! Any request may be answered by `Error` with a message instead. A frame
+//! is `SYNC`, the op, the payload length as a little endian `u32`, the
+//! payload of at most `MAX_PAYLOAD` bytes, and the CRC-32 of the op, length
+//! and payload as a little endian `u32`.
+//!
+//! The `tar` module holds the ustar archives the shell's `backup` writes.
+
+#![cfg_attr(feature = "no_std", no_std)]
+
+extern crate alloc;
+
+use shim::io;
+use shim::ioerr;
+
+pub mod tar;
+
+#[cfg(test)] mod tests;
+
+/// Bytes that switch the shell into file server mode: DLE and `RFS`
+pub const ESCAPE: [u8; 4] = [0x10, b'R', b'F', b'S'];
+
+/// Byte every frame starts with
+pub const SYNC: u8 = 0x7e;
+
+/// Most bytes a frame carries
+pub const MAX_PAYLOAD: usize = 4096;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Op {
+    List = 1,
+    Get = 2,
+    Put = 3,
+    Quit = 4,
+    Ok = 0x80,
+    Data = 0x81,
+    End = 0x82,
+    Error = 0x83,
+}
+
+impl Op {
+    fn from_u8(value: u8) -> Option<Op> {
+        match value {
+            1 => Some(Op::List),
+            2 => Some(Op::Get),
+            3 => Some(Op::Put),
+            4 => Some(Op::Quit),
+            0x80 => Some(Op::Ok),
+            0x81 => Some(Op::Data),
+            0x82 => Some(Op::End),
+            0x83 => Some(Op::Error),
+            _ => None,
+        }
+    }
+}
+
+/// CRC-32 (IEEE 802.3), computed a byte at a time so that no table is kept.
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32(!0)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+/// Returns the CRC-32 of `bytes`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+/// Writes a frame of `op` carrying `payload`, which must not be longer than
+/// `MAX_PAYLOAD`.
+pub fn write_frame<W: io::Write>(w: &mut W, op: Op, payload: &[u8]) -> io::Result<()> {
+    assert!(payload.len() <= MAX_PAYLOAD);
+    let mut head = [0u8; 6];
+    head[0] = SYNC;
+    head[1] = op as u8;
+    head[2..6].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    let mut crc = Crc32::new();
+    crc.update(&head[1..]);
+    crc.update(payload);
+    w.write_all(&head)?;
+    w.write_all(payload)?;
+    w.write_all(&crc.finish().to_le_bytes())?;
+    w.flush()
+}
+
+/// Reads a frame into `buf`, skipping bytes up to the next `SYNC`, and
+/// returns its op and the length of its payload.
+///
+/// # Errors
+///
+/// Returns `InvalidData` if the frame is of an unknown op, too long or
+/// fails its CRC.
+pub fn read_frame<R: io::Read>(r: &mut R, buf: &mut [u8; MAX_PAYLOAD]) -> io::Result<(Op, usize)> {
+    let mut byte = [0u8; 1];
+    while byte[0] != SYNC {
+        r.read_exact(&mut byte)?;
+    }
+    let mut head = [0u8; 5];
+    r.read_exact(&mut head)?;
+    let len = u32::from_le_bytes([head[1], head[2], head[3], head[4]]) as usize;
+    if len > MAX_PAYLOAD {
+        return ioerr!(InvalidData, "frame too long");
+    }
+    r.read_exact(&mut buf[..len])?;
+    let mut sum = [0u8; 4];
+    r.read_exact(&mut sum)?;
+
+    let mut crc = Crc32::new();
+    crc.update(&head);
+    crc.update(&buf[..len]);
+    if crc.finish() != u32::from_le_bytes(sum) {
+        return ioerr!(InvalidData, "frame fails its CRC");
+    }
+    match Op::from_u8(head[0]) {
+        Some(op) => Ok((op, len)),
+        None => ioerr!(InvalidData, "unknown op"),
+    }
+}
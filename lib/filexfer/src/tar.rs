@@ -0,0 +1,276 @@
+//! Reading and writing ustar archives, with the CRC-32 of every file's
+//! contents so an archive can be checked against what was written.
+
+use alloc::string::String;
+use alloc::vec;
+use core::cmp::min;
+use core::str;
+
+use crate::Crc32;
+use shim::io;
+
+/// Size of a tar block: a header takes one, and the contents of a file are
+/// padded to a whole number of them.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Largest size of a file, held in 11 octal digits
+const MAX_SIZE: u64 = (1 << 33) - 1;
+
+/// Offsets and lengths of the header fields this module uses
+const NAME: (usize, usize) = (0, 100);
+const MODE: (usize, usize) = (100, 8);
+const UID: (usize, usize) = (108, 8);
+const GID: (usize, usize) = (116, 8);
+const SIZE: (usize, usize) = (124, 12);
+const MTIME: (usize, usize) = (136, 12);
+const CHKSUM: (usize, usize) = (148, 8);
+const TYPEFLAG: usize = 156;
+const MAGIC: (usize, usize) = (257, 6);
+const VERSION: (usize, usize) = (263, 2);
+const PREFIX: (usize, usize) = (345, 155);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Directory,
+}
+
+/// A member of an archive, as its ustar header describes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// path of the member, relative, without the trailing `/` of directories
+    pub name: String,
+    pub kind: Kind,
+    /// size of the contents in bytes, 0 for a directory
+    pub size: u64,
+    /// time of the last modification, in seconds since the Unix epoch
+    pub mtime: u64,
+}
+
+fn field(block: &mut [u8; BLOCK_SIZE], (offset, len): (usize, usize)) -> &mut [u8] {
+    &mut block[offset..offset + len]
+}
+
+/// Writes `value` in octal to `field`, zero-padded, with a NUL at the end.
+fn put_octal(field: &mut [u8], mut value: u64) {
+    let digits = field.len() - 1;
+    for byte in field[..digits].iter_mut().rev() {
+        *byte = b'0' + (value & 7) as u8;
+        value >>= 3;
+    }
+    field[digits] = 0;
+}
+
+/// Reads the octal number in `field`, which may be padded with spaces and
+/// NULs.
+fn get_octal(field: &[u8]) -> io::Result<u64> {
+    let mut value: u64 = 0;
+    for &byte in field.iter().skip_while(|&&b| b == b' ') {
+        match byte {
+            b'0'..=b'7' => value = value << 3 | (byte - b'0') as u64,
+            b' ' | 0 => break,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid number in tar header")),
+        }
+    }
+    Ok(value)
+}
+
+/// Returns the string in `field`, which ends at the first NUL.
+fn get_str(field: &[u8]) -> io::Result<&str> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    str::from_utf8(&field[..len])
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid name in tar header"))
+}
+
+/// Returns the sum of the bytes of `block`, the checksum field counted as
+/// spaces.
+fn checksum(block: &[u8; BLOCK_SIZE]) -> u64 {
+    let (offset, len) = CHKSUM;
+    block.iter().enumerate().map(|(i, &b)| match i >= offset && i < offset + len {
+        true => b' ' as u64,
+        false => b as u64,
+    }).sum()
+}
+
+impl Header {
+    /// Returns the header block of the member.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if the name does not fit the 100 bytes of the
+    /// name field, or the 155 of the prefix field before a `/` and 100
+    /// after, or if the file is too large for the size field.
+    pub fn encode(&self) -> io::Result<[u8; BLOCK_SIZE]> {
+        let mut name = String::from(self.name.as_str());
+        if self.kind == Kind::Directory {
+            name.push('/');
+        }
+        let (prefix, name) = match name.len() <= NAME.1 {
+            true => ("", name.as_str()),
+            false => {
+                let split = name.char_indices()
+                    .filter(|&(i, c)| c == '/' && i > 0 && i <= PREFIX.1 && name.len() - i - 1 <= NAME.1)
+                    .map(|(i, _)| i)
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "name too long for a tar header"))?;
+                (&name[..split], &name[split + 1..])
+            }
+        };
+        if self.size > MAX_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "file too large for a tar header"));
+        }
+
+        let mut block = [0u8; BLOCK_SIZE];
+        field(&mut block, NAME)[..name.len()].copy_from_slice(name.as_bytes());
+        field(&mut block, PREFIX)[..prefix.len()].copy_from_slice(prefix.as_bytes());
+        put_octal(field(&mut block, MODE), match self.kind {
+            Kind::File => 0o644,
+            Kind::Directory => 0o755,
+        });
+        put_octal(field(&mut block, UID), 0);
+        put_octal(field(&mut block, GID), 0);
+        put_octal(field(&mut block, SIZE), self.size);
+        put_octal(field(&mut block, MTIME), self.mtime);
+        block[TYPEFLAG] = match self.kind {
+            Kind::File => b'0',
+            Kind::Directory => b'5',
+        };
+        field(&mut block, MAGIC).copy_from_slice(b"ustar\0");
+        field(&mut block, VERSION).copy_from_slice(b"00");
+
+        let sum = checksum(&block);
+        let chksum = field(&mut block, CHKSUM);
+        put_octal(&mut chksum[..7], sum);
+        chksum[7] = b' ';
+        Ok(block)
+    }
+
+    /// Returns the member described by the header `block`, or `None` for the
+    /// zero block that ends an archive.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if the checksum does not match, if the block is
+    /// not a ustar header, or if it is not one of a file or a directory.
+    pub fn decode(block: &[u8; BLOCK_SIZE]) -> io::Result<Option<Header>> {
+        if block.iter().all(|&b| b == 0) {
+            return Ok(None);
+        }
+        let (offset, len) = CHKSUM;
+        if get_octal(&block[offset..offset + len])? != checksum(block) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad tar header checksum"));
+        }
+        if &block[MAGIC.0..MAGIC.0 + 5] != b"ustar" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a ustar header"));
+        }
+
+        let mut name = String::from(get_str(&block[PREFIX.0..PREFIX.0 + PREFIX.1])?);
+        if !name.is_empty() {
+            name.push('/');
+        }
+        name.push_str(get_str(&block[NAME.0..NAME.0 + NAME.1])?);
+        let kind = match block[TYPEFLAG] {
+            b'0' | 0 => Kind::File,
+            b'5' => Kind::Directory,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported tar member type")),
+        };
+        if kind == Kind::Directory && name.ends_with('/') {
+            name.pop();
+        }
+        Ok(Some(Header {
+            name,
+            kind,
+            size: get_octal(&block[SIZE.0..SIZE.0 + SIZE.1])?,
+            mtime: get_octal(&block[MTIME.0..MTIME.0 + MTIME.1])?,
+        }))
+    }
+}
+
+/// Returns the number of padding bytes after contents of `size` bytes.
+fn padding(size: u64) -> usize {
+    (BLOCK_SIZE - (size % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE
+}
+
+/// Writes a ustar archive to a stream, one member after the other.
+pub struct Writer<W: io::Write> {
+    inner: W,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn new(inner: W) -> Writer<W> {
+        Writer { inner }
+    }
+
+    /// Appends the directory `name`.
+    pub fn append_dir(&mut self, name: &str, mtime: u64) -> io::Result<()> {
+        let header = Header { name: name.into(), kind: Kind::Directory, size: 0, mtime };
+        self.inner.write_all(&header.encode()?)
+    }
+
+    /// Appends the file `name` with the `size` bytes read from `contents`,
+    /// and returns their CRC-32.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UnexpectedEof` if `contents` ends before `size` bytes, the
+    /// archive being left with a partial member.
+    pub fn append_file<R: io::Read>(&mut self, name: &str, size: u64, mtime: u64, contents: &mut R) -> io::Result<u32> {
+        let header = Header { name: name.into(), kind: Kind::File, size, mtime };
+        self.inner.write_all(&header.encode()?)?;
+
+        let mut crc = Crc32::new();
+        let mut buf = vec![0u8; 8 * BLOCK_SIZE];
+        let mut left = size;
+        while left > 0 {
+            let want = min(left, buf.len() as u64) as usize;
+            let read = contents.read(&mut buf[..want])?;
+            if read == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while archived"));
+            }
+            crc.update(&buf[..read]);
+            self.inner.write_all(&buf[..read])?;
+            left -= read as u64;
+        }
+        self.inner.write_all(&[0u8; BLOCK_SIZE][..padding(size)])?;
+        Ok(crc.finish())
+    }
+
+    /// Ends the archive with two zero blocks and returns the stream.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0u8; 2 * BLOCK_SIZE])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reads the members of a ustar archive from a stream.
+pub struct Reader<R: io::Read> {
+    inner: R,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn new(inner: R) -> Reader<R> {
+        Reader { inner }
+    }
+
+    /// Reads the next member and returns its header with the CRC-32 of its
+    /// contents, or `None` at the end of the archive.
+    pub fn next_member(&mut self) -> io::Result<Option<(Header, u32)>> {
+        let mut block = [0u8; BLOCK_SIZE];
+        self.inner.read_exact(&mut block)?;
+        let header = match Header::decode(&block)? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+
+        let mut crc = Crc32::new();
+        let mut left = header.size;
+        while left > 0 {
+            self.inner.read_exact(&mut block)?;
+            let used = min(left, BLOCK_SIZE as u64) as usize;
+            crc.update(&block[..used]);
+            left -= used as u64;
+        }
+        Ok(Some((header, crc.finish())))
+    }
+}
@@ -0,0 +1,126 @@
+use crate::*;
+use std::io::Cursor;
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(b""), 0);
+}
+
+#[test]
+fn frame_round_trip() {
+    let mut wire = Vec::new();
+    write_frame(&mut wire, Op::Get, b"/boot/config.txt").expect("write to memory");
+    write_frame(&mut wire, Op::End, b"").expect("write to memory");
+
+    let mut r = Cursor::new(wire);
+    let mut buf = [0u8; MAX_PAYLOAD];
+    assert_eq!(read_frame(&mut r, &mut buf).expect("valid frame"), (Op::Get, 16));
+    assert_eq!(&buf[..16], b"/boot/config.txt");
+    assert_eq!(read_frame(&mut r, &mut buf).expect("valid frame"), (Op::End, 0));
+}
+
+#[test]
+fn read_frame_skips_to_sync() {
+    let mut wire = b"\r\n> ".to_vec();
+    write_frame(&mut wire, Op::Ok, b"").expect("write to memory");
+    let mut buf = [0u8; MAX_PAYLOAD];
+    assert_eq!(read_frame(&mut Cursor::new(wire), &mut buf).expect("valid frame"), (Op::Ok, 0));
+}
+
+#[test]
+fn read_frame_rejects_corruption() {
+    let mut wire = Vec::new();
+    write_frame(&mut wire, Op::Data, b"hello").expect("write to memory");
+    wire[7] ^= 0x20;
+    let mut buf = [0u8; MAX_PAYLOAD];
+    let e = read_frame(&mut Cursor::new(wire), &mut buf).expect_err("corrupt frame");
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn read_frame_rejects_long_frames() {
+    let mut wire = vec![SYNC, Op::Data as u8];
+    wire.extend_from_slice(&(MAX_PAYLOAD as u32 + 1).to_le_bytes());
+    let mut buf = [0u8; MAX_PAYLOAD];
+    assert!(read_frame(&mut Cursor::new(wire), &mut buf).is_err());
+}
+
+#[test]
+fn tar_header_round_trip() {
+    use crate::tar::{Header, Kind};
+
+    let file = Header { name: "boot/config.txt".into(), kind: Kind::File, size: 1234, mtime: 1_500_000_000 };
+    let block = file.encode().expect("short name");
+    assert_eq!(&block[257..262], b"ustar");
+    assert_eq!(Header::decode(&block).expect("valid header"), Some(file));
+
+    let dir = Header { name: "boot/overlays".into(), kind: Kind::Directory, size: 0, mtime: 0 };
+    let block = dir.encode().expect("short name");
+    assert_eq!(&block[..14], b"boot/overlays/");
+    assert_eq!(Header::decode(&block).expect("valid header"), Some(dir));
+}
+
+#[test]
+fn tar_header_long_name_uses_prefix() {
+    use crate::tar::{Header, Kind};
+
+    let name = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+    let file = Header { name, kind: Kind::File, size: 0, mtime: 0 };
+    let block = file.encode().expect("name fits prefix and name");
+    assert_eq!(&block[..90], "f".repeat(90).as_bytes());
+    assert_eq!(Header::decode(&block).expect("valid header"), Some(file));
+
+    let file = Header { name: "x".repeat(101), kind: Kind::File, size: 0, mtime: 0 };
+    assert_eq!(file.encode().err().map(|e| e.kind()), Some(io::ErrorKind::InvalidInput));
+}
+
+#[test]
+fn tar_header_rejects_bad_checksum() {
+    use crate::tar::{Header, Kind};
+
+    let file = Header { name: "a".into(), kind: Kind::File, size: 1, mtime: 0 };
+    let mut block = file.encode().expect("short name");
+    block[0] = b'b';
+    assert_eq!(Header::decode(&block).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    assert_eq!(Header::decode(&[0u8; tar::BLOCK_SIZE]).expect("end block"), None);
+}
+
+#[test]
+fn tar_archive_round_trip() {
+    use crate::tar::{Kind, Reader, Writer, BLOCK_SIZE};
+
+    let small = b"hello, world\n".to_vec();
+    let large: Vec<u8> = (0..3 * BLOCK_SIZE as u32 + 7).map(|i| i as u8).collect();
+
+    let mut w = Writer::new(Vec::new());
+    w.append_dir("etc", 10).expect("write to memory");
+    let small_crc = w.append_file("etc/motd", small.len() as u64, 20, &mut Cursor::new(&small)).expect("write to memory");
+    let large_crc = w.append_file("etc/blob", large.len() as u64, 30, &mut Cursor::new(&large)).expect("write to memory");
+    w.append_file("etc/empty", 0, 40, &mut Cursor::new(b"")).expect("write to memory");
+    let archive = w.finish().expect("write to memory");
+    assert_eq!(archive.len() % BLOCK_SIZE, 0);
+    assert_eq!(small_crc, crc32(&small));
+    assert_eq!(large_crc, crc32(&large));
+
+    let mut r = Reader::new(Cursor::new(archive));
+    let mut members = Vec::new();
+    while let Some((header, crc)) = r.next_member().expect("valid archive") {
+        members.push((header.name, header.kind, header.size, header.mtime, crc));
+    }
+    assert_eq!(members, vec![
+        ("etc".into(), Kind::Directory, 0, 10, crc32(b"")),
+        ("etc/motd".into(), Kind::File, small.len() as u64, 20, small_crc),
+        ("etc/blob".into(), Kind::File, large.len() as u64, 30, large_crc),
+        ("etc/empty".into(), Kind::File, 0, 40, crc32(b"")),
+    ]);
+}
+
+#[test]
+fn tar_append_file_short_contents() {
+    use crate::tar::Writer;
+
+    let mut w = Writer::new(Vec::new());
+    let err = w.append_file("f", 10, 0, &mut Cursor::new(b"short")).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
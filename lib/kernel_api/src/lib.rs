@@ -2,14 +2,19 @@
 #![no_std]
 
 use core::fmt;
+use core::marker::PhantomData;
 
 use shim::io;
 
 #[cfg(feature = "user-space")]
 pub mod syscall;
+pub mod vdso;
 
 pub type OsResult<T> = core::result::Result<T, OsError>;
 
+/// Errors of system calls. The numbers are part of the system call ABI,
+/// shared with user programs through `x7`: a variant keeps its number for
+/// good, and new ones take numbers not used before.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum OsError {
     Unknown = 0,
@@ -19,9 +24,12 @@ pub enum OsError {
     NoMemory = 20,
     NoVmSpace = 30,
     NoAccess = 40,
+    ReadOnlyFs = 41,
     BadAddress = 50,
     FileExists = 60,
     InvalidArgument = 70,
+    WouldBlock = 80,
+    InvalidFileDescriptor = 90,
 
     IoError = 101,
     IoErrorEof = 102,
@@ -42,14 +50,18 @@ impl core::convert::From<u64> for OsError {
             20 => OsError::NoMemory,
             30 => OsError::NoVmSpace,
             40 => OsError::NoAccess,
+            41 => OsError::ReadOnlyFs,
             50 => OsError::BadAddress,
             60 => OsError::FileExists,
             70 => OsError::InvalidArgument,
+            80 => OsError::WouldBlock,
+            90 => OsError::InvalidFileDescriptor,
 
             101 => OsError::IoError,
             102 => OsError::IoErrorEof,
             103 => OsError::IoErrorInvalidData,
             104 => OsError::IoErrorInvalidInput,
+            105 => OsError::IoErrorTimedOut,
 
             200 => OsError::InvalidSocket,
             201 => OsError::IllegalSocketOperation,
@@ -59,14 +71,70 @@ impl core::convert::From<u64> for OsError {
     }
 }
 
+impl OsError {
+    /// Returns the description of the error printed to users.
+    pub fn message(&self) -> &'static str {
+        match self {
+            OsError::Unknown => "Unknown error",
+            OsError::Ok => "Success",
+
+            OsError::NoEntry => "No such file or directory",
+            OsError::NoMemory => "Out of memory",
+            OsError::NoVmSpace => "No virtual memory space",
+            OsError::NoAccess => "Permission denied",
+            OsError::ReadOnlyFs => "Read-only file system",
+            OsError::BadAddress => "Bad address",
+            OsError::FileExists => "File exists",
+            OsError::InvalidArgument => "Invalid argument",
+            OsError::WouldBlock => "Resource temporarily unavailable",
+            OsError::InvalidFileDescriptor => "Bad file descriptor",
+
+            OsError::IoError => "Input/output error",
+            OsError::IoErrorEof => "Unexpected end of file",
+            OsError::IoErrorInvalidData => "Invalid data",
+            OsError::IoErrorInvalidInput => "Invalid input",
+            OsError::IoErrorTimedOut => "Timed out",
+
+            OsError::InvalidSocket => "Not a socket",
+            OsError::IllegalSocketOperation => "Operation not supported on socket",
+        }
+    }
+}
+
+/// Returns the description of the error numbered `code`, as a system call
+/// returns it in `x7`.
+pub fn strerror(code: u64) -> &'static str {
+    OsError::from(code).message()
+}
+
+impl fmt::Display for OsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+/// Carries `e` in an `io::Error`, for the errors no `io::ErrorKind` stands
+/// for, such as `ReadOnlyFs`. Converting the `io::Error` back gives `e`.
+impl core::convert::From<OsError> for io::Error {
+    fn from(e: OsError) -> Self {
+        io::Error::from_raw_os_error(e as i32)
+    }
+}
+
 impl core::convert::From<io::Error> for OsError {
     fn from(e: io::Error) -> Self {
+        if let Some(code) = e.raw_os_error() {
+            return OsError::from(code as u64);
+        }
         match e.kind() {
             io::ErrorKind::UnexpectedEof => OsError::IoErrorEof,
             io::ErrorKind::InvalidData => OsError::IoErrorInvalidData,
             io::ErrorKind::InvalidInput => OsError::IoErrorInvalidInput,
             io::ErrorKind::TimedOut => OsError::IoErrorTimedOut,
             io::ErrorKind::NotFound => OsError::NoEntry,
+            io::ErrorKind::WouldBlock => OsError::WouldBlock,
+            io::ErrorKind::AlreadyExists => OsError::FileExists,
+            io::ErrorKind::PermissionDenied => OsError::NoAccess,
             _ => OsError::IoError,
         }
     }
@@ -78,6 +146,182 @@ pub const NR_EXIT: usize = 3;
 pub const NR_WRITE: usize = 4;
 pub const NR_GETPID: usize = 5;
 pub const NR_WRITE_STR: usize = 6;
+pub const NR_GETTID: usize = 7;
+pub const NR_THREAD_CREATE: usize = 8;
+pub const NR_THREAD_JOIN: usize = 9;
+pub const NR_THREAD_EXIT: usize = 10;
+pub const NR_FUTEX_WAIT: usize = 11;
+pub const NR_FUTEX_WAKE: usize = 12;
+pub const NR_OPEN: usize = 13;
+pub const NR_CLOSE: usize = 14;
+pub const NR_READ: usize = 15;
+/// Writes to a file descriptor; `NR_WRITE` writes a byte to the console.
+pub const NR_FD_WRITE: usize = 16;
+pub const NR_LSEEK: usize = 17;
+pub const NR_WATCH: usize = 18;
+pub const NR_PIPE: usize = 19;
+pub const NR_UNLINK: usize = 28;
+pub const NR_MKDIR: usize = 29;
+pub const NR_PERF_ENABLE: usize = 30;
+pub const NR_DUP2: usize = 31;
+pub const NR_FLOCK: usize = 32;
+pub const NR_CHDIR: usize = 33;
+pub const NR_GETCWD: usize = 34;
+pub const NR_DUP: usize = 35;
+pub const NR_MPROTECT: usize = 36;
+pub const NR_FCNTL: usize = 37;
+pub const NR_SENDFILE: usize = 38;
+pub const NR_MMAP: usize = 39;
+pub const NR_MUNMAP: usize = 40;
+pub const NR_SHM_CREATE: usize = 41;
+pub const NR_SHM_MAP: usize = 42;
+pub const NR_SHM_UNMAP: usize = 43;
+pub const NR_SHM_UNLINK: usize = 44;
+pub const NR_GETRANDOM: usize = 45;
+pub const NR_MKTEMP: usize = 46;
+pub const NR_SET_PRIORITY: usize = 47;
+pub const NR_FORK: usize = 48;
+pub const NR_EXEC: usize = 49;
+pub const NR_WAITPID: usize = 50;
+
+/// Most bytes the arguments and the environment of a program may take on its
+/// stack, strings and pointers together
+pub const ARG_MAX: usize = 16 * 1024;
+
+/// A string passed by address and length in the arrays `exec` takes. The
+/// layout is part of the system call ABI, unlike that of `&str`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct UserStr<'a> {
+    pub ptr: u64,
+    pub len: u64,
+    _str: PhantomData<&'a str>,
+}
+
+impl<'a> From<&'a str> for UserStr<'a> {
+    fn from(s: &'a str) -> UserStr<'a> {
+        UserStr { ptr: s.as_ptr() as u64, len: s.len() as u64, _str: PhantomData }
+    }
+}
+
+/// Exit code `waitpid` reports for a process the kernel killed, as shells
+/// report one killed by `SIGKILL`
+pub const EXIT_KILLED: u64 = 137;
+
+/// Range of the nice level `set_priority` takes; the lower, the sooner a
+/// process runs. Processes start at 0.
+pub const NICE_MIN: i64 = -20;
+pub const NICE_MAX: i64 = 19;
+
+/// Flags of `open`: exactly one of the access modes, optionally combined
+/// with `O_CREAT` and `O_CLOEXEC`.
+pub const O_RDONLY: u64 = 0;
+pub const O_WRONLY: u64 = 1;
+pub const O_RDWR: u64 = 2;
+pub const O_ACCMODE: u64 = 3;
+/// Creates the file if it does not exist.
+pub const O_CREAT: u64 = 0o100;
+/// Closes the descriptor when the process runs another program.
+pub const O_CLOEXEC: u64 = 0o2000000;
+
+/// Commands of `fcntl`: `F_GETFD` returns the flags of the descriptor and
+/// `F_SETFD` sets them to its argument.
+pub const F_GETFD: u64 = 1;
+pub const F_SETFD: u64 = 2;
+/// Descriptor flag set on descriptors closed when the process runs another
+/// program. Descriptors made by `dup` and `dup2` start out without it.
+pub const FD_CLOEXEC: u64 = 1;
+
+/// Origins of `lseek`
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+/// Operations of `flock`: one of `LOCK_SH`, `LOCK_EX` and `LOCK_UN`, with
+/// `LOCK_NB` to fail instead of waiting for a conflicting lock.
+pub const LOCK_SH: u64 = 1;
+pub const LOCK_EX: u64 = 2;
+pub const LOCK_NB: u64 = 4;
+pub const LOCK_UN: u64 = 8;
+
+/// Protections of `mprotect` and `mmap`: `PROT_READ`, optionally combined
+/// with `PROT_WRITE` and `PROT_EXEC`. Pages cannot be made inaccessible.
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+/// Events of `watch` on the entries of a directory. Only volumes that can
+/// create entries, like the tmpfs on `/tmp`, report `IN_CREATE` and
+/// `IN_DELETE`; FAT32 volumes never do.
+///
+/// An entry was created by `open` with `O_CREAT`, `mkdir` or `mktemp`, or
+/// renamed into the directory.
+pub const IN_CREATE: u64 = 1;
+/// A file was written or truncated.
+pub const IN_MODIFY: u64 = 2;
+/// An entry was removed by `unlink`, or renamed out of the directory.
+pub const IN_DELETE: u64 = 4;
+/// Queued in place of the events a watch had no room for; always reported.
+pub const IN_Q_OVERFLOW: u64 = 8;
+
+/// A file opened by `open` or `watch`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fd(u64);
+
+impl Fd {
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Standard input, output and error, open on the console when a process
+/// starts
+pub const STDIN: Fd = Fd(0);
+pub const STDOUT: Fd = Fd(1);
+pub const STDERR: Fd = Fd(2);
+
+/// An event read from a `watch` descriptor. Reads return whole events, each
+/// the event bit and the length of the name as little endian `u16`s followed
+/// by the name of the entry in the watched directory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatchEvent<'a> {
+    pub mask: u64,
+    pub name: &'a str,
+}
+
+impl<'a> WatchEvent<'a> {
+    pub const HEADER_SIZE: usize = 4;
+
+    /// Length of the encoded event
+    pub fn encoded_len(&self) -> usize {
+        Self::HEADER_SIZE + self.name.len()
+    }
+
+    /// Writes the event to the start of `buf` and returns its length, or
+    /// `None` if it does not fit.
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let len = self.encoded_len();
+        if buf.len() < len {
+            return None;
+        }
+        buf[0..2].copy_from_slice(&(self.mask as u16).to_le_bytes());
+        buf[2..4].copy_from_slice(&(self.name.len() as u16).to_le_bytes());
+        buf[4..len].copy_from_slice(self.name.as_bytes());
+        Some(len)
+    }
+
+    /// Reads the event at the start of `buf` and returns it with its length,
+    /// or `None` if `buf` does not start with a whole event.
+    pub fn decode(buf: &'a [u8]) -> Option<(WatchEvent<'a>, usize)> {
+        if buf.len() < Self::HEADER_SIZE {
+            return None;
+        }
+        let mask = u16::from_le_bytes([buf[0], buf[1]]) as u64;
+        let len = Self::HEADER_SIZE + u16::from_le_bytes([buf[2], buf[3]]) as usize;
+        let name = core::str::from_utf8(buf.get(Self::HEADER_SIZE..len)?).ok()?;
+        Some((WatchEvent { mask, name }, len))
+    }
+}
 
 #[derive(Clone, Copy, Debug)]
 pub struct SocketDescriptor(u64);
@@ -127,3 +371,81 @@ pub const NR_SOCK_CONNECT: usize = 22;
 pub const NR_SOCK_LISTEN: usize = 23;
 pub const NR_SOCK_SEND: usize = 24;
 pub const NR_SOCK_RECV: usize = 25;
+
+pub const NR_UNAME: usize = 26;
+pub const NR_SYSINFO: usize = 27;
+
+/// Names the kernel and the board, filled in by `uname`. Strings are UTF-8
+/// padded with NUL bytes.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Utsname {
+    pub sysname: [u8; 16],
+    pub release: [u8; 16],
+    pub machine: [u8; 16],
+    pub board: [u8; 32],
+    /// revision code of the board, 0 if the firmware did not pass one
+    pub revision: u32,
+}
+
+/// Returns the string stored in `field`, up to the first NUL.
+fn nul_padded(field: &[u8]) -> &str {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).unwrap_or("")
+}
+
+impl Utsname {
+    pub fn sysname(&self) -> &str {
+        nul_padded(&self.sysname)
+    }
+
+    pub fn release(&self) -> &str {
+        nul_padded(&self.release)
+    }
+
+    pub fn machine(&self) -> &str {
+        nul_padded(&self.machine)
+    }
+
+    pub fn board(&self) -> &str {
+        nul_padded(&self.board)
+    }
+}
+
+impl Default for Utsname {
+    fn default() -> Utsname {
+        Utsname { sysname: [0; 16], release: [0; 16], machine: [0; 16], board: [0; 32], revision: 0 }
+    }
+}
+
+impl fmt::Debug for Utsname {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Utsname")
+            .field("sysname", &self.sysname())
+            .field("release", &self.release())
+            .field("machine", &self.machine())
+            .field("board", &self.board())
+            .field("revision", &self.revision)
+            .finish()
+    }
+}
+
+/// System statistics filled in by `sysinfo`. Fields are only ever added at
+/// the end, so older programs keep working.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sysinfo {
+    /// seconds since boot
+    pub uptime: u64,
+    /// bytes of RAM
+    pub total_ram: u64,
+    /// bytes of the kernel heap
+    pub heap: u64,
+    /// bytes of memory mapped by user processes
+    pub user_memory: u64,
+    pub processes: u32,
+    pub threads: u32,
+    pub cores: u32,
+    /// cores the kernel runs on
+    pub online_cores: u32,
+}
@@ -51,11 +51,13 @@ pub fn time() -> Duration {
     Duration::new(seconds, nano as u32)
 }
 
-pub fn exit() -> ! {
+/// Ends the process, every thread of it, with exit code `code`, which the
+/// parent gets from `waitpid`.
+pub fn exit(code: u64) -> ! {
     unsafe {
         asm!("svc $0"
              :
-	     : "i"(NR_EXIT)
+	     : "i"(NR_EXIT), "{x0}"(code)
              : "memory"
              : "volatile");
     }
@@ -101,6 +103,668 @@ pub fn getpid() -> u64 {
     pid
 }
 
+pub fn gettid() -> u64 {
+    let mut tid: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(tid), "={x7}"(ecode)
+             : "i"(NR_GETTID)
+             : "x0", "x7", "memory"
+             : "volatile");
+    }
+
+    tid
+}
+
+/// Starts a new thread of the current process running `entry(arg)` on its own
+/// stack, with `tls` as its thread pointer (`TPIDR_EL0`). With `tls` 0 the
+/// kernel gives the thread a TLS block initialized from the program's `PT_TLS`
+/// segment. Returns the ID of the new thread. The thread must end with
+/// `thread_exit`.
+pub fn thread_create(entry: extern "C" fn(u64) -> !, arg: u64, tls: u64) -> OsResult<u64> {
+    let mut tid: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(tid), "={x7}"(ecode)
+             : "i"(NR_THREAD_CREATE), "{x0}"(entry as u64), "{x1}"(arg), "{x2}"(tls)
+             : "x0", "x1", "x2", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, tid)
+}
+
+/// Waits for thread `tid` to exit and returns its exit code.
+pub fn thread_join(tid: u64) -> OsResult<u64> {
+    let mut code: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(code), "={x7}"(ecode)
+             : "i"(NR_THREAD_JOIN), "{x0}"(tid)
+             : "x0", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, code)
+}
+
+pub fn thread_exit(code: u64) -> ! {
+    unsafe {
+        asm!("svc $0"
+             :
+             : "i"(NR_THREAD_EXIT), "{x0}"(code)
+             : "memory"
+             : "volatile");
+    }
+    loop {};
+}
+
+/// Returns the thread pointer of the current thread as set by `thread_create`.
+pub fn thread_pointer() -> u64 {
+    let tp: u64;
+    unsafe {
+        asm!("mrs $0, TPIDR_EL0"
+             : "=r"(tp)
+             :
+             :
+             : "volatile");
+    }
+    tp
+}
+
+/// Blocks while the `u32` at `addr` equals `expected`, until another thread
+/// calls `futex_wake` on `addr`. Returns `WouldBlock` right away if the value
+/// differs.
+pub fn futex_wait(addr: *const u32, expected: u32) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_FUTEX_WAIT), "{x0}"(addr as u64), "{x1}"(expected as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Wakes up to `count` threads blocked on `addr` and returns how many were
+/// woken.
+pub fn futex_wake(addr: *const u32, count: u32) -> OsResult<usize> {
+    let mut woken: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(woken), "={x7}"(ecode)
+             : "i"(NR_FUTEX_WAKE), "{x0}"(addr as u64), "{x1}"(count as u64)
+             : "x0", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, woken as usize)
+}
+
+/// Opens the regular file at `path` with `flags`, one of
+/// `O_RDONLY`, `O_WRONLY` and `O_RDWR`, at its start. With `O_CREAT` in
+/// `flags` a missing file is created empty.
+pub fn open(path: &str, flags: u64) -> OsResult<Fd> {
+    let mut fd: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(fd), "={x7}"(ecode)
+             : "i"(NR_OPEN), "{x0}"(path.as_ptr() as u64), "{x1}"(path.len() as u64), "{x2}"(flags)
+             : "x0", "x1", "x2", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, Fd(fd))
+}
+
+/// Creates a temporary file in the directory `dir`, named `prefix` followed
+/// by random characters, and opens it for reading and writing. Returns the
+/// descriptor and the absolute path of the file, copied into `buf`. The file
+/// is removed once it is closed, as at exit, unless it was renamed by then.
+/// Fails with `InvalidArgument` if `buf` is too short.
+pub fn mktemp<'a>(dir: &str, prefix: &str, buf: &'a mut [u8]) -> OsResult<(Fd, &'a str)> {
+    let mut fd: u64;
+    let mut len: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $3"
+             : "={x0}"(fd), "={x1}"(len), "={x7}"(ecode)
+             : "i"(NR_MKTEMP), "{x0}"(dir.as_ptr() as u64), "{x1}"(dir.len() as u64),
+               "{x2}"(prefix.as_ptr() as u64), "{x3}"(prefix.len() as u64),
+               "{x4}"(buf.as_mut_ptr() as u64), "{x5}"(buf.len() as u64)
+             : "x0", "x1", "x2", "x3", "x4", "x5", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())?;
+    let path = core::str::from_utf8(&buf[..len as usize]).map_err(|_| OsError::InvalidArgument)?;
+    Ok((Fd(fd), path))
+}
+
+/// Creates a pipe and returns its read end and its write end.
+pub fn pipe() -> OsResult<(Fd, Fd)> {
+    let mut read_fd: u64;
+    let mut write_fd: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $3"
+             : "={x0}"(read_fd), "={x1}"(write_fd), "={x7}"(ecode)
+             : "i"(NR_PIPE)
+             : "x0", "x1", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, (Fd(read_fd), Fd(write_fd)))
+}
+
+/// Copies up to `len` bytes from the file open under `input` to `output`
+/// without passing them through user memory, and returns how many were
+/// copied. `input` must be a file; `output` may be a pipe.
+pub fn sendfile(output: Fd, input: Fd, len: usize) -> OsResult<usize> {
+    let mut count: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(count), "={x7}"(ecode)
+             : "i"(NR_SENDFILE), "{x0}"(output.raw()), "{x1}"(input.raw()), "{x2}"(len as u64)
+             : "x0", "x1", "x2", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, count as usize)
+}
+
+/// Returns the lowest free descriptor, made to refer to the file open under
+/// `old`. The two share the position, so a saved `dup(STDOUT)` can be put
+/// back with `dup2` after a redirection.
+pub fn dup(old: Fd) -> OsResult<Fd> {
+    let mut fd: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(fd), "={x7}"(ecode)
+             : "i"(NR_DUP), "{x0}"(old.raw())
+             : "x0", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, Fd(fd))
+}
+
+/// Makes `new` refer to the file open under `old`, closing it first if it
+/// is open, and returns `new`. The two share the position, so redirecting
+/// `STDOUT` to a file is `dup2(file, STDOUT)`.
+pub fn dup2(old: Fd, new: Fd) -> OsResult<Fd> {
+    let mut fd: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(fd), "={x7}"(ecode)
+             : "i"(NR_DUP2), "{x0}"(old.raw()), "{x1}"(new.raw())
+             : "x0", "x1", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, Fd(fd))
+}
+
+/// Gets or sets the flags of the descriptor `fd`, as `cmd` says; `F_GETFD`
+/// returns them and `F_SETFD` sets them to `arg`, returning 0.
+pub fn fcntl(fd: Fd, cmd: u64, arg: u64) -> OsResult<u64> {
+    let mut value: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(value), "={x7}"(ecode)
+             : "i"(NR_FCNTL), "{x0}"(fd.raw()), "{x1}"(cmd), "{x2}"(arg)
+             : "x0", "x1", "x2", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, value)
+}
+
+/// Takes a shared or exclusive advisory lock on the file open under `fd`, or
+/// releases it, as `op` says. Waits for a conflicting lock to go away unless
+/// `op` has `LOCK_NB`, in which case it fails with `WouldBlock`.
+pub fn flock(fd: Fd, op: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_FLOCK), "{x0}"(fd.raw()), "{x1}"(op)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Removes the regular file or empty directory at `path`.
+pub fn unlink(path: &str) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_UNLINK), "{x0}"(path.as_ptr() as u64), "{x1}"(path.len() as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Creates an empty directory at `path`.
+pub fn mkdir(path: &str) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_MKDIR), "{x0}"(path.as_ptr() as u64), "{x1}"(path.len() as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Changes the working directory, which relative paths are resolved against,
+/// to the directory at `path`.
+pub fn chdir(path: &str) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_CHDIR), "{x0}"(path.as_ptr() as u64), "{x1}"(path.len() as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Copies the absolute path of the working directory into `buf` and returns
+/// it. Fails with `InvalidArgument` if `buf` is too short.
+pub fn getcwd(buf: &mut [u8]) -> OsResult<&str> {
+    let mut len: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(len), "={x7}"(ecode)
+             : "i"(NR_GETCWD), "{x0}"(buf.as_mut_ptr() as u64), "{x1}"(buf.len() as u64)
+             : "x0", "x1", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())?;
+    core::str::from_utf8(&buf[..len as usize]).map_err(|_| OsError::InvalidArgument)
+}
+
+/// Sets the protection of the pages from `addr`, which is page aligned, to
+/// `addr + len` to `prot`, `PROT_READ` combined with `PROT_WRITE` and
+/// `PROT_EXEC` as needed.
+pub fn mprotect(addr: usize, len: usize, prot: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_MPROTECT), "{x0}"(addr as u64), "{x1}"(len as u64), "{x2}"(prot)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Maps `len` bytes, rounded up to whole pages, of zeroed memory with
+/// protection `prot` and returns their address.
+pub fn mmap(len: usize, prot: u64) -> OsResult<usize> {
+    let mut addr: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(addr), "={x7}"(ecode)
+             : "i"(NR_MMAP), "{x0}"(len as u64), "{x1}"(prot)
+             : "x0", "x1", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, addr as usize)
+}
+
+/// Unmaps the pages from `addr`, which is page aligned, to `addr + len` of
+/// memory mapped by `mmap`. Fails with `InvalidArgument` if the range does
+/// not lie within a single mapping, such as one unmapped already.
+pub fn munmap(addr: usize, len: usize) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_MUNMAP), "{x0}"(addr as u64), "{x1}"(len as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Creates a shared memory segment of `len` bytes, rounded up to whole
+/// pages, named `name`. Fails with `FileExists` if the name is taken.
+pub fn shm_create(name: &str, len: usize) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_SHM_CREATE), "{x0}"(name.as_ptr() as u64), "{x1}"(name.len() as u64), "{x2}"(len as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Maps the shared memory segment named `name` with protection `prot` and
+/// returns its address. Processes mapping the same segment share its pages.
+pub fn shm_map(name: &str, prot: u64) -> OsResult<usize> {
+    let mut addr: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(addr), "={x7}"(ecode)
+             : "i"(NR_SHM_MAP), "{x0}"(name.as_ptr() as u64), "{x1}"(name.len() as u64), "{x2}"(prot)
+             : "x0", "x1", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, addr as usize)
+}
+
+/// Unmaps the shared memory segment `shm_map` mapped at `addr`.
+pub fn shm_unmap(addr: usize) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_SHM_UNMAP), "{x0}"(addr as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Removes the name of the shared memory segment `name`. The segment goes
+/// away once no process maps it.
+pub fn shm_unlink(name: &str) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_SHM_UNLINK), "{x0}"(name.as_ptr() as u64), "{x1}"(name.len() as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Fills `buf` with random bytes. They are the same every run if the kernel
+/// was booted with a fixed `rngseed`.
+pub fn getrandom(buf: &mut [u8]) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_GETRANDOM), "{x0}"(buf.as_mut_ptr() as u64), "{x1}"(buf.len() as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Closes `fd`, writing back what was written to it.
+pub fn close(fd: Fd) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_CLOSE), "{x0}"(fd.raw())
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Reads from `fd` into `buf` and returns the number of bytes read, 0 at the
+/// end of the file.
+pub fn read(fd: Fd, buf: &mut [u8]) -> OsResult<usize> {
+    let mut count: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(count), "={x7}"(ecode)
+             : "i"(NR_READ), "{x0}"(fd.raw()), "{x1}"(buf.as_mut_ptr() as u64), "{x2}"(buf.len() as u64)
+             : "x0", "x1", "x2", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, count as usize)
+}
+
+/// Writes `buf` to `fd` and returns the number of bytes written.
+pub fn fd_write(fd: Fd, buf: &[u8]) -> OsResult<usize> {
+    let mut count: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(count), "={x7}"(ecode)
+             : "i"(NR_FD_WRITE), "{x0}"(fd.raw()), "{x1}"(buf.as_ptr() as u64), "{x2}"(buf.len() as u64)
+             : "x0", "x1", "x2", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, count as usize)
+}
+
+/// Moves the position of `fd` to `offset` from `whence`, one of `SEEK_SET`,
+/// `SEEK_CUR` and `SEEK_END`, and returns the new position.
+pub fn lseek(fd: Fd, offset: i64, whence: u64) -> OsResult<u64> {
+    let mut pos: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(pos), "={x7}"(ecode)
+             : "i"(NR_LSEEK), "{x0}"(fd.raw()), "{x1}"(offset as u64), "{x2}"(whence)
+             : "x0", "x1", "x2", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, pos)
+}
+
+/// Watches the directory at `path` for the events in `mask`, a
+/// combination of `IN_CREATE`, `IN_MODIFY` and `IN_DELETE`. Events are read
+/// from the returned descriptor with `read` and `WatchEvent::decode`; `read`
+/// returns `WouldBlock` while none are queued. Closing it ends the watch.
+pub fn watch(path: &str, mask: u64) -> OsResult<Fd> {
+    let mut fd: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(fd), "={x7}"(ecode)
+             : "i"(NR_WATCH), "{x0}"(path.as_ptr() as u64), "{x1}"(path.len() as u64), "{x2}"(mask)
+             : "x0", "x1", "x2", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, Fd(fd))
+}
+
+/// Lets the threads of this process read the cycle counter `PMCCNTR_EL0`
+/// if `enabled`, or takes it away again. The kernel must have been booted
+/// with `perf=user`.
+pub fn perf_enable(enabled: bool) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_PERF_ENABLE), "{x0}"(enabled as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Sets the nice level of the process `pid`, or of this process if `pid` is
+/// 0, to `nice`, within `NICE_MIN..=NICE_MAX`. The lower it is, the sooner
+/// the process runs next to others.
+pub fn set_priority(pid: u64, nice: i64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_SET_PRIORITY), "{x0}"(pid), "{x1}"(nice as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Creates a child process, a copy of this one with a single thread, the
+/// caller, that continues from here too. Returns the child's process ID in
+/// this process and 0 in the child.
+pub fn fork() -> OsResult<u64> {
+    let mut pid: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $2"
+             : "={x0}"(pid), "={x7}"(ecode)
+             : "i"(NR_FORK)
+             : "x0", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, pid)
+}
+
+/// Replaces the program of this process with the executable at `path`,
+/// started from its entry point with the arguments `argv`, `argv[0]` being
+/// the program's name by convention, and the environment `envp`, strings of
+/// the form `NAME=value`. The other threads of the process are killed.
+/// Returns only if the program could not be loaded.
+///
+/// The strings are made with `UserStr::from()`, e.g.
+/// `exec("/fib", &["/fib".into(), "20".into()], &[])`.
+pub fn exec(path: &str, argv: &[UserStr], envp: &[UserStr]) -> OsError {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_EXEC), "{x0}"(path.as_ptr() as u64), "{x1}"(path.len() as u64),
+               "{x2}"(argv.as_ptr() as u64), "{x3}"(argv.len() as u64),
+               "{x4}"(envp.as_ptr() as u64), "{x5}"(envp.len() as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    OsError::from(ecode)
+}
+
+/// Waits for the child `pid` to exit, or for any child if `pid` is 0, and
+/// returns its process ID and exit code. A child is gone once waited for.
+pub fn waitpid(pid: u64) -> OsResult<(u64, u64)> {
+    let mut child: u64;
+    let mut code: u64;
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $3"
+             : "={x0}"(child), "={x1}"(code), "={x7}"(ecode)
+             : "i"(NR_WAITPID), "{x0}"(pid)
+             : "x0", "x1", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, (child, code))
+}
+
+/// Returns the names of the kernel and the board.
+pub fn uname() -> OsResult<Utsname> {
+    let mut name = Utsname::default();
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_UNAME), "{x0}"(&mut name as *mut Utsname as u64), "{x1}"(core::mem::size_of::<Utsname>() as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, name)
+}
+
+/// Returns statistics of the system.
+pub fn sysinfo() -> OsResult<Sysinfo> {
+    let mut info = Sysinfo::default();
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1"
+             : "={x7}"(ecode)
+             : "i"(NR_SYSINFO), "{x0}"(&mut info as *mut Sysinfo as u64), "{x1}"(core::mem::size_of::<Sysinfo>() as u64)
+             : "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, info)
+}
+
 pub fn sock_create() -> SocketDescriptor {
     // Lab 5 2.D
     unimplemented!("sock_create")
@@ -0,0 +1,29 @@
+use core::time::Duration;
+
+/// Address of the read-only page of kernel data mapped into every process.
+/// It sits right below the stacks of the highest thread slots.
+pub const VDSO_BASE: u64 = 0xffff_ffff_fedf_0000;
+
+/// Layout of the data at `VDSO_BASE`. The kernel fills it in once at boot.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct VdsoData {
+    /// frequency of the virtual counter `CNTVCT_EL0` in Hz
+    pub counter_freq: u64,
+    /// counter value at the time `time()` reports as zero
+    pub boot_offset: u64,
+}
+
+impl VdsoData {
+    /// Converts the counter value `count` to the time since boot, the same
+    /// clock the `time()` system call reads.
+    pub fn time_at(&self, count: u64) -> Duration {
+        if self.counter_freq == 0 {
+            return Duration::from_secs(0);
+        }
+        let ticks = count.wrapping_sub(self.boot_offset);
+        let secs = ticks / self.counter_freq;
+        let nanos = (ticks % self.counter_freq) * 1_000_000_000 / self.counter_freq;
+        Duration::new(secs, nanos as u32)
+    }
+}
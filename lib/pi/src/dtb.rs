@@ -0,0 +1,315 @@
+//! A reader of the flattened device tree blob newer firmware hands the
+//! kernel in `x0` instead of ATAGs. Only lookups of properties by path are
+//! supported, which is all the kernel needs to find memory and peripherals.
+
+use core::slice;
+use core::str;
+
+/// `magic` of a device tree header
+pub const FDT_MAGIC: u32 = 0xd00d_feed;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// Size of the header, up to `size_dt_struct`
+const HEADER_SIZE: usize = 40;
+
+/// Most bytes of a blob `Fdt::from_addr()` accepts, so that a bad header
+/// cannot make it read through all of memory
+pub const MAX_SIZE: usize = 1 << 20;
+
+/// Reads the big endian word at `offset` of `bytes`.
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let word = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+/// Rounds `n` up to a multiple of 4, the alignment of every token.
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// Returns the NUL terminated string at the start of `bytes`.
+fn c_str(bytes: &[u8]) -> Option<&str> {
+    let len = bytes.iter().position(|&b| b == 0)?;
+    str::from_utf8(&bytes[..len]).ok()
+}
+
+/// Returns true if the node `name` is what the path component `component`
+/// names: the same name, or the same name without its unit address if the
+/// component has none, so that `memory` finds `memory@0`.
+fn matches(name: &str, component: &str) -> bool {
+    name == component || (!component.contains('@') && name.split('@').next() == Some(component))
+}
+
+/// Reads a number of `cells` big endian words from the start of `bytes`,
+/// returning it and the rest of `bytes`.
+pub fn read_cells(bytes: &[u8], cells: u32) -> Option<(u64, &[u8])> {
+    let len = cells as usize * 4;
+    if cells > 2 || bytes.len() < len {
+        return None;
+    }
+    let mut value = 0u64;
+    for i in 0..cells as usize {
+        value = value << 32 | be32(bytes, i * 4)? as u64;
+    }
+    Some((value, &bytes[len..]))
+}
+
+/// A flattened device tree
+#[derive(Debug, Copy, Clone)]
+pub struct Fdt<'a> {
+    blob: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Fdt<'a> {
+    /// Returns the tree held by `blob`, `None` if its header is not that of
+    /// a device tree or points outside of it.
+    pub fn new(blob: &'a [u8]) -> Option<Fdt<'a>> {
+        if be32(blob, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let total = be32(blob, 4)? as usize;
+        let (structs, strings) = (be32(blob, 8)? as usize, be32(blob, 12)? as usize);
+        let (strings_size, structs_size) = (be32(blob, 32)? as usize, be32(blob, 36)? as usize);
+        let blob = blob.get(..total)?;
+        Some(Fdt {
+            blob,
+            structs: blob.get(structs..structs.checked_add(structs_size)?)?,
+            strings: blob.get(strings..strings.checked_add(strings_size)?)?,
+        })
+    }
+
+    /// Returns the tree at `addr`, `None` if there is none.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be 0, which gives `None`, or point to readable memory of
+    /// at least `HEADER_SIZE` bytes that stays unchanged while the tree is
+    /// used, and as many as the header claims if it starts with
+    /// `FDT_MAGIC`.
+    pub unsafe fn from_addr(addr: usize) -> Option<Fdt<'static>> {
+        if addr == 0 || addr % 4 != 0 {
+            return None;
+        }
+        let header = slice::from_raw_parts(addr as *const u8, HEADER_SIZE);
+        if be32(header, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let total = be32(header, 4)? as usize;
+        if total < HEADER_SIZE || total > MAX_SIZE {
+            return None;
+        }
+        Fdt::new(slice::from_raw_parts(addr as *const u8, total))
+    }
+
+    /// Size in bytes of the blob
+    pub fn size(&self) -> usize {
+        self.blob.len()
+    }
+
+    /// Returns the value of the property `name` of the node at the absolute
+    /// `path`, such as `/memory` or `/` for the root. A path component
+    /// without a unit address matches nodes with any; the first match wins.
+    pub fn property(&self, path: &str, name: &str) -> Option<&'a [u8]> {
+        let components = || path.split('/').filter(|c| !c.is_empty());
+        let total = components().count();
+        // depth of the node the cursor is in, the root being 1, and how many
+        // components the nodes from the root down to it match
+        let (mut depth, mut matched) = (0usize, 0usize);
+        let mut offset = 0;
+        loop {
+            let token = be32(self.structs, offset)?;
+            offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let node = c_str(self.structs.get(offset..)?)?;
+                    offset += align4(node.len() + 1);
+                    depth += 1;
+                    if depth >= 2 && depth - 1 == matched + 1 && matched < total
+                        && matches(node, components().nth(matched)?) {
+                        matched += 1;
+                    }
+                },
+                FDT_END_NODE => {
+                    if depth == 0 {
+                        return None;
+                    }
+                    if matched > 0 && depth - 1 == matched {
+                        matched -= 1;
+                    }
+                    depth -= 1;
+                },
+                FDT_PROP => {
+                    let len = be32(self.structs, offset)? as usize;
+                    let name_offset = be32(self.structs, offset + 4)? as usize;
+                    let value = self.structs.get(offset + 8..(offset + 8).checked_add(len)?)?;
+                    offset += 8 + align4(len);
+                    if depth >= 1 && depth - 1 == matched && matched == total
+                        && c_str(self.strings.get(name_offset..)?)? == name {
+                        return Some(value);
+                    }
+                },
+                FDT_NOP => {},
+                FDT_END => return None,
+                // an unknown token: the tree is malformed
+                _ => return None,
+            }
+        }
+    }
+
+    /// Returns the property `name` of the node at `path` as a number of
+    /// one cell.
+    pub fn u32_property(&self, path: &str, name: &str) -> Option<u32> {
+        be32(self.property(path, name)?, 0)
+    }
+
+    /// Returns the property `name` of the node at `path` as a string.
+    pub fn str_property(&self, path: &str, name: &str) -> Option<&'a str> {
+        let value = self.property(path, name)?;
+        let value = match value.last() {
+            Some(0) => &value[..value.len() - 1],
+            _ => value,
+        };
+        str::from_utf8(value).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Builds a blob node by node, in the order the structure block holds
+    /// them.
+    struct Builder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn new() -> Builder {
+            Builder { structs: Vec::new(), strings: Vec::new() }
+        }
+
+        fn word(&mut self, word: u32) -> &mut Builder {
+            self.structs.extend_from_slice(&word.to_be_bytes());
+            self
+        }
+
+        fn pad(&mut self) {
+            while self.structs.len() % 4 != 0 {
+                self.structs.push(0);
+            }
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Builder {
+            self.word(FDT_BEGIN_NODE);
+            self.structs.extend_from_slice(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+            self
+        }
+
+        fn end(&mut self) -> &mut Builder {
+            self.word(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Builder {
+            let name_offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self.word(FDT_PROP).word(value.len() as u32).word(name_offset);
+            self.structs.extend_from_slice(value);
+            self.pad();
+            self
+        }
+
+        fn cells(&mut self, name: &str, cells: &[u32]) -> &mut Builder {
+            let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes().to_vec()).collect();
+            self.prop(name, &value)
+        }
+
+        fn build(&mut self) -> Vec<u8> {
+            self.word(FDT_END);
+            let structs = HEADER_SIZE;
+            let strings = structs + self.structs.len();
+            let total = strings + self.strings.len();
+            let mut blob = Vec::new();
+            for word in [FDT_MAGIC, total as u32, structs as u32, strings as u32, 0, 17, 16, 0,
+                         self.strings.len() as u32, self.structs.len() as u32].iter() {
+                blob.extend_from_slice(&word.to_be_bytes());
+            }
+            blob.extend_from_slice(&self.structs);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    fn pi3() -> Vec<u8> {
+        Builder::new()
+            .begin("")
+                .cells("#address-cells", &[1])
+                .cells("#size-cells", &[1])
+                .begin("chosen")
+                    .prop("bootargs", b"console=ttyS0 rngseed=7\0")
+                .end()
+                .begin("soc")
+                    .cells("ranges", &[0x7e00_0000, 0x3f00_0000, 0x0100_0000, 0x4000_0000, 0x4000_0000, 0x1000])
+                    .begin("serial@7e215040")
+                        .prop("status", b"okay\0")
+                    .end()
+                .end()
+                .begin("memory@0")
+                    .cells("reg", &[0, 0x3b40_0000])
+                .end()
+            .end()
+            .build()
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let mut blob = pi3();
+        blob[0] = 0;
+        assert!(Fdt::new(&blob).is_none());
+        let blob = pi3();
+        assert!(Fdt::new(&blob[..20]).is_none());
+    }
+
+    #[test]
+    fn finds_properties() {
+        let blob = pi3();
+        let fdt = Fdt::new(&blob).expect("valid tree");
+        assert_eq!(fdt.size(), blob.len());
+        assert_eq!(fdt.u32_property("/", "#address-cells"), Some(1));
+        assert_eq!(fdt.str_property("/chosen", "bootargs"), Some("console=ttyS0 rngseed=7"));
+        assert_eq!(fdt.str_property("/soc/serial", "status"), Some("okay"));
+        assert_eq!(fdt.str_property("/soc/serial@7e215040", "status"), Some("okay"));
+        assert_eq!(fdt.property("/serial", "status"), None);
+        assert_eq!(fdt.property("/chosen", "ranges"), None);
+        assert_eq!(fdt.property("/soc/serial@7e201000", "status"), None);
+
+        let reg = fdt.property("/memory", "reg").expect("memory has reg");
+        let (start, rest) = read_cells(reg, 1).unwrap();
+        let (size, rest) = read_cells(rest, 1).unwrap();
+        assert_eq!((start, size), (0, 0x3b40_0000));
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn reads_cells() {
+        let bytes = [0, 0, 0, 1, 0, 0, 0, 2, 0xff];
+        assert_eq!(read_cells(&bytes, 2), Some(((1 << 32) | 2, &bytes[8..])));
+        assert_eq!(read_cells(&bytes, 0), Some((0, &bytes[..])));
+        assert_eq!(read_cells(&bytes[..7], 2), None);
+        assert_eq!(read_cells(&bytes, 3), None);
+    }
+}
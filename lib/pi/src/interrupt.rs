@@ -10,6 +10,8 @@ pub enum Interrupt {
     Timer1 = 1,
     Timer3 = 3,
     Usb = 9,
+    /// the auxiliary peripherals, the mini UART among them
+    Aux = 29,
     Gpio0 = 49,
     Gpio1 = 50,
     Gpio2 = 51,
@@ -18,11 +20,11 @@ pub enum Interrupt {
 }
 
 impl Interrupt {
-    pub const MAX: usize = 8;
+    pub const MAX: usize = 9;
 
     pub fn iter() -> impl Iterator<Item = Interrupt> {
         use Interrupt::*;
-        [Timer1, Timer3, Usb, Gpio0, Gpio1, Gpio2, Gpio3, Uart]
+        [Timer1, Timer3, Usb, Gpio0, Gpio1, Gpio2, Gpio3, Uart, Aux]
             .iter()
             .map(|int| *int)
     }
@@ -38,6 +40,7 @@ impl Interrupt {
             Gpio2 => 5,
             Gpio3 => 6,
             Uart => 7,
+            Aux => 8,
         }
     }
 
@@ -52,6 +55,7 @@ impl Interrupt {
             5 => Gpio2,
             6 => Gpio3,
             7 => Uart,
+            8 => Aux,
             _ => panic!("Unknown interrupt: {}", i),
         }
     }
@@ -64,6 +68,7 @@ impl From<usize> for Interrupt {
             1 => Timer1,
             3 => Timer3,
             9 => Usb,
+            29 => Aux,
             49 => Gpio0,
             50 => Gpio1,
             51 => Gpio2,
@@ -134,6 +139,21 @@ impl Controller {
 	irq_disable.write(0b1 << shift_mask);
     }
 
+    /// Returns the enabled interrupts as bit masks of the two banks, to be
+    /// given back to `restore()`.
+    pub fn save(&self) -> (u32, u32) {
+	(self.registers.IRQ_ENBL_1.read(), self.registers.IRQ_ENBL_2.read())
+    }
+
+    /// Enables exactly the interrupts of `masks`, as `save()` returned
+    /// them, and disables the rest.
+    pub fn restore(&mut self, masks: (u32, u32)) {
+	self.registers.IRQ_DSBL_1.write(!masks.0);
+	self.registers.IRQ_DSBL_2.write(!masks.1);
+	self.registers.IRQ_ENBL_1.write(masks.0);
+	self.registers.IRQ_ENBL_2.write(masks.1);
+    }
+
     /// Returns `true` if `int` is pending. Otherwise, returns `false`.
     pub fn is_pending(&self, int: Interrupt) -> bool {
 	let irq_index = int as u32;
@@ -8,8 +8,14 @@
 
 pub mod atags;
 pub mod common;
+pub mod dtb;
 pub mod gpio;
 pub mod interrupt;
 pub mod local_interrupt;
+pub mod mbox;
+pub mod mmio;
+pub mod pm;
+pub mod pwm;
+pub mod rng;
 pub mod timer;
 pub mod uart;
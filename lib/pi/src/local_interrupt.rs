@@ -1,15 +1,26 @@
 use core::time::Duration;
 
+use aarch64::*;
 use volatile::prelude::*;
-use volatile::Volatile;
+use volatile::{ReadVolatile, Volatile};
 
 const INT_BASE: usize = 0x40000000;
 
 /// Core interrupt sources (QA7: 4.10)
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum LocalInterrupt {
-    // Lab 5 1.C
-    // FIXME: please fill in the definition
+    CntPsIrq = 0,
+    CntPnsIrq = 1,
+    CntHpIrq = 2,
+    CntVIrq = 3,
+    Mailbox0 = 4,
+    Mailbox1 = 5,
+    Mailbox2 = 6,
+    Mailbox3 = 7,
+    Gpu = 8,
+    Pmu = 9,
+    AxiOutstanding = 10,
+    LocalTimer = 11,
 }
 
 impl LocalInterrupt {
@@ -22,8 +33,22 @@ impl LocalInterrupt {
 
 impl From<usize> for LocalInterrupt {
     fn from(irq: usize) -> LocalInterrupt {
-        // Lab 5 1.C
-        unimplemented!("LocalInterrupt")
+        use LocalInterrupt::*;
+        match irq {
+            0 => CntPsIrq,
+            1 => CntPnsIrq,
+            2 => CntHpIrq,
+            3 => CntVIrq,
+            4 => Mailbox0,
+            5 => Mailbox1,
+            6 => Mailbox2,
+            7 => Mailbox3,
+            8 => Gpu,
+            9 => Pmu,
+            10 => AxiOutstanding,
+            11 => LocalTimer,
+            _ => panic!("Unknown local irq: {}", irq),
+        }
     }
 }
 
@@ -31,8 +56,26 @@ impl From<usize> for LocalInterrupt {
 #[repr(C)]
 #[allow(non_snake_case)]
 struct Registers {
-    // Lab 5 1.C
-    // FIXME: please fill in the definition
+    CONTROL: Volatile<u32>,
+    _unused0: Volatile<u32>,
+    CORE_TIMER_PRESCALER: Volatile<u32>,
+    GPU_INT_ROUTING: Volatile<u32>,
+    PMU_INT_ROUTING_SET: Volatile<u32>,
+    PMU_INT_ROUTING_CLR: Volatile<u32>,
+    _unused1: Volatile<u32>,
+    CORE_TIMER_LS: Volatile<u32>,
+    CORE_TIMER_MS: Volatile<u32>,
+    LOCAL_INT_ROUTING: Volatile<u32>,
+    _unused2: Volatile<u32>,
+    AXI_OUTSTANDING_COUNTERS: Volatile<u32>,
+    AXI_OUTSTANDING_IRQ: Volatile<u32>,
+    LOCAL_TIMER_CONTROL: Volatile<u32>,
+    LOCAL_TIMER_WRITE_FLAGS: Volatile<u32>,
+    _unused3: Volatile<u32>,
+    CORE_TIMER_INT_CONTROL: [Volatile<u32>; 4],
+    CORE_MAILBOX_INT_CONTROL: [Volatile<u32>; 4],
+    CORE_IRQ_SOURCE: [ReadVolatile<u32>; 4],
+    CORE_FIQ_SOURCE: [ReadVolatile<u32>; 4],
 }
 
 pub struct LocalController {
@@ -49,20 +92,29 @@ impl LocalController {
         }
     }
 
+    /// Enables the EL1 physical timer of the calling core and routes its
+    /// non-secure interrupt to that core's IRQ. `core` must be the calling
+    /// core: the timer is one of its system registers.
     pub fn enable_local_timer(&mut self) {
-        // Lab 5 1.C
-        unimplemented!("LocalInterrupt")
+        unsafe {
+            CNTP_CTL_EL0.set(CNTP_CTL_EL0::ENABLE);
+        }
+        let control = &mut self.registers.CORE_TIMER_INT_CONTROL[self.core];
+        control.write(control.read() | (1 << LocalInterrupt::CntPnsIrq as u32));
     }
 
     pub fn is_pending(&self, int: LocalInterrupt) -> bool {
-        // Lab 5 1.C
-        unimplemented!("LocalInterrupt")
+        self.registers.CORE_IRQ_SOURCE[self.core].read() & (1 << int as u32) != 0
     }
 
+    /// Sets up the timer of the calling core to fire `t` duration from now,
+    /// which also clears an interrupt it raised before. (timer: 3.1 to 3.3)
     pub fn tick_in(&mut self, t: Duration) {
-        // Lab 5 1.C
-        // See timer: 3.1 to 3.3
-        unimplemented!("LocalInterrupt")
+        let frequency = unsafe { CNTFRQ_EL0.get() };
+        let ticks = frequency * t.as_micros() as u64 / 1_000_000;
+        unsafe {
+            CNTP_TVAL_EL0.set(ticks);
+        }
     }
 }
 
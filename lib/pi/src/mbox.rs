@@ -0,0 +1,146 @@
+use aarch64::asm::{clean_dcache_range, invalidate_dcache_range};
+use core::mem::size_of;
+
+use crate::common::IO_BASE;
+use crate::mmio::reg;
+
+/// The base address of the mailbox the ARM core talks to the VideoCore
+/// firmware through.
+const MBOX_REG_BASE: usize = IO_BASE + 0xB880;
+
+reg!(READ: u32 = MBOX_REG_BASE + 0x00);
+reg!(STATUS: u32 = MBOX_REG_BASE + 0x18);
+reg!(WRITE: u32 = MBOX_REG_BASE + 0x20);
+
+const STATUS_FULL: u32 = 1 << 31;
+const STATUS_EMPTY: u32 = 1 << 30;
+
+/// The channel of property tag requests, from the ARM to the VideoCore
+const CHANNEL_PROPERTY: u32 = 8;
+
+const REQUEST: u32 = 0;
+const RESPONSE_SUCCESS: u32 = 0x8000_0000;
+
+const TAG_ALLOCATE_BUFFER: u32 = 0x0004_0001;
+const TAG_GET_PITCH: u32 = 0x0004_0008;
+const TAG_SET_PHYSICAL_SIZE: u32 = 0x0004_8003;
+const TAG_SET_VIRTUAL_SIZE: u32 = 0x0004_8004;
+const TAG_SET_DEPTH: u32 = 0x0004_8005;
+const TAG_SET_PIXEL_ORDER: u32 = 0x0004_8006;
+const TAG_SET_GPIO_STATE: u32 = 0x0003_8041;
+const TAG_END: u32 = 0;
+
+/// The firmware hands out addresses as the VideoCore sees them, with the
+/// cache alias in the top two bits
+const BUS_ADDR_MASK: u32 = 0x3fff_ffff;
+
+/// Pixel order with blue in the lowest byte, so that a 32 bit pixel reads
+/// 0xAARRGGBB
+const PIXEL_ORDER_BGR: u32 = 0;
+
+/// Times the mailbox is polled before a call is given up, so that firmware
+/// that does not answer cannot hang the caller
+const MBOX_SPINS: usize = 1_000_000;
+
+/// A property message: its size, the request or response code, the tags and
+/// the end tag. The firmware takes the address of the buffer with the
+/// channel in its low 4 bits, so it is 16 byte aligned.
+#[repr(C, align(16))]
+struct Message<A>(A);
+
+/// Sends `message` on the property channel and waits for the firmware to
+/// answer. Returns `true` if it processed the request.
+fn call<A: AsMut<[u32]>>(message: &mut Message<A>) -> bool {
+    let addr = message as *mut Message<A> as usize;
+    // the firmware reads and writes memory behind the ARM's data cache
+    unsafe { clean_dcache_range(addr, size_of::<Message<A>>()) };
+
+    let mut spins = 0;
+    while STATUS.read() & STATUS_FULL != 0 {
+	spins += 1;
+	if spins == MBOX_SPINS {
+	    return false;
+	}
+    }
+    WRITE.write(addr as u32 | CHANNEL_PROPERTY);
+
+    loop {
+	while STATUS.read() & STATUS_EMPTY != 0 {
+	    spins += 1;
+	    if spins == MBOX_SPINS {
+		return false;
+	    }
+	}
+	if READ.read() == addr as u32 | CHANNEL_PROPERTY {
+	    break;
+	}
+    }
+    unsafe { invalidate_dcache_range(addr, size_of::<Message<A>>()) };
+    message.0.as_mut()[1] == RESPONSE_SUCCESS
+}
+
+/// Drives pin `pin` of the GPIO expander the firmware controls, which the
+/// ACT and PWR LEDs of some boards hang off, high if `on` is set and low
+/// otherwise. Returns `false` if the firmware did not do it.
+pub fn set_gpio_state(pin: u32, on: bool) -> bool {
+    let mut message = Message([
+	size_of::<Message<[u32; 8]>>() as u32,
+	REQUEST,
+	TAG_SET_GPIO_STATE,
+	8,
+	0,
+	pin,
+	on as u32,
+	TAG_END,
+    ]);
+    call(&mut message)
+}
+
+/// A framebuffer the firmware allocated, which it scans out to the HDMI
+/// output.
+#[derive(Copy, Clone, Debug)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    /// bits per pixel
+    pub depth: u32,
+    /// bytes from the start of a row to the start of the next
+    pub pitch: u32,
+    /// physical address of the first pixel
+    pub addr: usize,
+    /// size in bytes
+    pub size: usize,
+}
+
+/// Asks the firmware for a framebuffer of `width` by `height` pixels of
+/// `depth` bits, in BGR order. Returns `None` if it did not allocate one, as without a
+/// display attached on some firmware. The firmware may pick another size
+/// or depth than the one asked for; the returned one is what it picked.
+pub fn allocate_framebuffer(width: u32, height: u32, depth: u32) -> Option<Framebuffer> {
+    let mut message = Message([
+	size_of::<Message<[u32; 30]>>() as u32,
+	REQUEST,
+	TAG_SET_PHYSICAL_SIZE, 8, 0, width, height,
+	TAG_SET_VIRTUAL_SIZE, 8, 0, width, height,
+	TAG_SET_DEPTH, 4, 0, depth,
+	TAG_SET_PIXEL_ORDER, 4, 0, PIXEL_ORDER_BGR,
+	TAG_ALLOCATE_BUFFER, 8, 0, 4096, 0,
+	TAG_GET_PITCH, 4, 0, 0,
+	TAG_END,
+    ]);
+    if !call(&mut message) {
+	return None;
+    }
+    let m = &message.0;
+    match m[23] != 0 && m[24] != 0 {
+	true => Some(Framebuffer {
+	    width: m[5],
+	    height: m[6],
+	    depth: m[15],
+	    pitch: m[28],
+	    addr: (m[23] & BUS_ADDR_MASK) as usize,
+	    size: m[24] as usize,
+	}),
+	false => None,
+    }
+}
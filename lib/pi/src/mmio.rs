@@ -0,0 +1,101 @@
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::ptr;
+
+use aarch64::asm::dmb;
+
+use crate::common::{IO_BASE, IO_BASE_END};
+
+/// A memory mapped peripheral register holding a `T`.
+///
+/// The BCM2837 does not keep accesses to different peripherals in order, so
+/// every write is preceded and every read followed by a data memory barrier
+/// (BCM2835 ARM Peripherals, 1.3). Define registers with `reg!`, which checks
+/// the address when the crate is built.
+pub struct Reg<T> {
+    addr: usize,
+    _value: PhantomData<T>,
+}
+
+impl<T> Clone for Reg<T> {
+    fn clone(&self) -> Reg<T> {
+	Reg { addr: self.addr, _value: PhantomData }
+    }
+}
+
+impl<T> Copy for Reg<T> {}
+
+impl<T: Copy> Reg<T> {
+    /// Returns the register at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be a register of size `T` in the peripheral window.
+    pub const unsafe fn at(addr: usize) -> Reg<T> {
+	Reg { addr: addr, _value: PhantomData }
+    }
+
+    /// The physical address of the register.
+    pub fn addr(&self) -> usize {
+	self.addr
+    }
+
+    pub fn read(&self) -> T {
+	let value = unsafe { ptr::read_volatile(self.addr as *const T) };
+	dmb();
+	value
+    }
+
+    pub fn write(&self, value: T) {
+	dmb();
+	unsafe { ptr::write_volatile(self.addr as *mut T, value) };
+    }
+
+    /// Writes back the result of `f` applied to the current value.
+    pub fn modify<F: FnOnce(T) -> T>(&self, f: F) {
+	self.write(f(self.read()));
+    }
+}
+
+/// Returns `true` if a register of `size` bytes at `addr` is naturally aligned
+/// and lies entirely within the peripheral window.
+pub const fn is_valid(addr: usize, size: usize) -> bool {
+    (addr >= IO_BASE) & (addr <= IO_BASE_END - size) & (addr % size == 0)
+}
+
+/// Returns `true` if `T` can be accessed in one load or store.
+pub const fn is_register_sized<T>() -> bool {
+    (size_of::<T>() == 1) | (size_of::<T>() == 2) | (size_of::<T>() == 4) | (size_of::<T>() == 8)
+}
+
+/// Defines a constant `Reg<T>` named `NAME` at `ADDR`. The build fails if the
+/// address is misaligned or outside the peripheral window.
+///
+/// ```rust,ignore
+/// reg!(CLO: u32 = IO_BASE + 0x3004);
+/// ```
+pub macro reg($vis:vis $name:ident: $T:ty = $addr:expr) {
+    $vis const $name: $crate::mmio::Reg<$T> = unsafe { $crate::mmio::Reg::at($addr) };
+    const _: () = {
+	let _ = [(); 0 - (!($crate::mmio::is_register_sized::<$T>()
+			     & $crate::mmio::is_valid($addr, ::core::mem::size_of::<$T>())) as usize)];
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid() {
+	assert!(is_valid(IO_BASE, 4));
+	assert!(is_valid(IO_BASE + 0x3004, 4));
+	assert!(is_valid(IO_BASE_END - 4, 4));
+	assert!(!is_valid(IO_BASE - 4, 4));
+	assert!(!is_valid(IO_BASE_END, 4));
+	assert!(!is_valid(IO_BASE_END - 2, 4));
+	assert!(!is_valid(IO_BASE + 0x3002, 4));
+	assert!(is_register_sized::<u32>());
+	assert!(!is_register_sized::<[u32; 3]>());
+    }
+}
@@ -0,0 +1,60 @@
+use crate::common::IO_BASE;
+use crate::mmio::reg;
+use core::time::Duration;
+
+/// The base address for the power management (and watchdog) registers.
+const PM_REG_BASE: usize = IO_BASE + 0x100000;
+
+/// Every write to a PM register must carry this password.
+const PM_PASSWORD: u32 = 0x5a00_0000;
+
+const PM_RSTC_WRCFG_CLR: u32 = 0xffff_ffcf;
+const PM_RSTC_WRCFG_FULL_RESET: u32 = 0x0000_0020;
+const PM_RSTC_RESET: u32 = 0x0000_0102;
+
+/// The watchdog counts in units of 1/65536 seconds and has a 20 bit counter.
+const PM_WDOG_TICKS_PER_SEC: u64 = 65536;
+const PM_WDOG_TIME_MASK: u32 = 0x000f_ffff;
+
+reg!(RSTC: u32 = PM_REG_BASE + 0x1c);
+reg!(WDOG: u32 = PM_REG_BASE + 0x24);
+
+/// The Raspberry Pi hardware watchdog. Once started, the board is reset
+/// unless the watchdog is stopped or restarted before the timeout expires.
+pub struct Watchdog {
+    _private: (),
+}
+
+impl Watchdog {
+    /// Returns a new instance of `Watchdog`.
+    pub fn new() -> Watchdog {
+        Watchdog { _private: () }
+    }
+
+    /// The longest timeout the watchdog supports (just under 16 seconds).
+    pub fn max_timeout() -> Duration {
+	Duration::from_micros(PM_WDOG_TIME_MASK as u64 * 1_000_000 / PM_WDOG_TICKS_PER_SEC)
+    }
+
+    /// Arms the watchdog to perform a full reset after `timeout`. Timeouts
+    /// longer than `max_timeout()` are clamped.
+    pub fn start(&mut self, timeout: Duration) {
+	let ticks = timeout.as_micros() as u64 * PM_WDOG_TICKS_PER_SEC / 1_000_000;
+	let ticks = core::cmp::min(ticks, PM_WDOG_TIME_MASK as u64) as u32;
+
+	let rstc = RSTC.read() & PM_RSTC_WRCFG_CLR;
+	WDOG.write(PM_PASSWORD | ticks);
+	RSTC.write(PM_PASSWORD | rstc | PM_RSTC_WRCFG_FULL_RESET);
+    }
+
+    /// Disarms the watchdog.
+    pub fn stop(&mut self) {
+	RSTC.write(PM_PASSWORD | PM_RSTC_RESET);
+    }
+
+    /// Returns the time left before the watchdog resets the board.
+    pub fn remaining(&self) -> Duration {
+	let ticks = (WDOG.read() & PM_WDOG_TIME_MASK) as u64;
+	Duration::from_micros(ticks * 1_000_000 / PM_WDOG_TICKS_PER_SEC)
+    }
+}
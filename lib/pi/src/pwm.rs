@@ -0,0 +1,91 @@
+use crate::common::IO_BASE;
+use crate::gpio::{Function, Gpio};
+use crate::mmio::reg;
+
+/// The base address of the clock manager, which clocks the PWM among
+/// others.
+const CM_REG_BASE: usize = IO_BASE + 0x101000;
+
+/// The base address of the PWM controller.
+const PWM_REG_BASE: usize = IO_BASE + 0x20C000;
+
+reg!(CM_PWMCTL: u32 = CM_REG_BASE + 0xa0);
+reg!(CM_PWMDIV: u32 = CM_REG_BASE + 0xa4);
+
+reg!(CTL: u32 = PWM_REG_BASE + 0x00);
+reg!(RNG1: u32 = PWM_REG_BASE + 0x10);
+reg!(DAT1: u32 = PWM_REG_BASE + 0x14);
+reg!(RNG2: u32 = PWM_REG_BASE + 0x20);
+reg!(DAT2: u32 = PWM_REG_BASE + 0x24);
+
+/// Every write to a clock manager register must carry this password.
+const CM_PASSWORD: u32 = 0x5a00_0000;
+
+const CM_SRC_OSCILLATOR: u32 = 1;
+const CM_ENAB: u32 = 1 << 4;
+const CM_KILL: u32 = 1 << 5;
+const CM_BUSY: u32 = 1 << 7;
+
+/// Channel enable and mark-space mode bits of `CTL`: in mark-space mode a
+/// channel is high for `DAT` clock cycles out of every `RNG`
+const CTL_PWEN1: u32 = 1 << 0;
+const CTL_MSEN1: u32 = 1 << 7;
+const CTL_PWEN2: u32 = 1 << 8;
+const CTL_MSEN2: u32 = 1 << 15;
+
+/// The 19.2MHz crystal oscillator, divided by 2
+const CM_DIVISOR: u32 = 2;
+pub const CLOCK_HZ: u32 = 19_200_000 / CM_DIVISOR;
+
+/// The pins the left and right channels of the headphone jack of the
+/// Raspberry Pi 3 hang off, each PWM in its alternate function 0
+const LEFT_PIN: u8 = 40;
+const RIGHT_PIN: u8 = 45;
+
+/// Times the clock manager is polled for the clock to stop, so that a clock
+/// that never does cannot hang the caller
+const CM_SPINS: usize = 1_000_000;
+
+/// The PWM controller, driving the headphone jack.
+pub struct Pwm {
+    _private: (),
+}
+
+impl Pwm {
+    /// Routes both PWM channels to the headphone jack, silent, and clocks
+    /// the controller at `CLOCK_HZ`. Returns `None` if the clock manager
+    /// does not stop the PWM clock to change its divisor.
+    pub fn new() -> Option<Pwm> {
+	Gpio::new(LEFT_PIN).into_alt(Function::Alt0);
+	Gpio::new(RIGHT_PIN).into_alt(Function::Alt0);
+	CTL.write(0);
+
+	CM_PWMCTL.write(CM_PASSWORD | CM_KILL);
+	let mut spins = 0;
+	while CM_PWMCTL.read() & CM_BUSY != 0 {
+	    spins += 1;
+	    if spins == CM_SPINS {
+		return None;
+	    }
+	}
+	CM_PWMDIV.write(CM_PASSWORD | CM_DIVISOR << 12);
+	CM_PWMCTL.write(CM_PASSWORD | CM_ENAB | CM_SRC_OSCILLATOR);
+	Some(Pwm { _private: () })
+    }
+
+    /// Plays a square wave of `hz` on both channels until `stop()` is
+    /// called. Frequencies are clamped to 1Hz to half of `CLOCK_HZ`.
+    pub fn tone(&mut self, hz: u32) {
+	let range = CLOCK_HZ / hz.max(1).min(CLOCK_HZ / 2);
+	RNG1.write(range);
+	DAT1.write(range / 2);
+	RNG2.write(range);
+	DAT2.write(range / 2);
+	CTL.write(CTL_PWEN1 | CTL_MSEN1 | CTL_PWEN2 | CTL_MSEN2);
+    }
+
+    /// Silences both channels.
+    pub fn stop(&mut self) {
+	CTL.write(0);
+    }
+}
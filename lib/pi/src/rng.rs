@@ -0,0 +1,44 @@
+use crate::common::IO_BASE;
+use crate::mmio::reg;
+
+/// The base address of the hardware random number generator.
+const RNG_REG_BASE: usize = IO_BASE + 0x104000;
+
+reg!(CTRL: u32 = RNG_REG_BASE + 0x00);
+reg!(STATUS: u32 = RNG_REG_BASE + 0x04);
+reg!(DATA: u32 = RNG_REG_BASE + 0x08);
+reg!(INT_MASK: u32 = RNG_REG_BASE + 0x10);
+
+const CTRL_ENABLE: u32 = 1;
+const INT_OFF: u32 = 1;
+
+/// Numbers the generator throws away after it is enabled, as its first
+/// output is poorly distributed
+const WARMUP_COUNT: u32 = 0x40000;
+
+/// Times the status is polled for a number before giving up, so that a
+/// generator that never produces one, like QEMU's, cannot hang the caller
+const RNG_SPINS: usize = 1_000_000;
+
+/// Enables the generator unless it is running already. It produces its
+/// first number once it has warmed up, which takes a while.
+pub fn initialize() {
+    if CTRL.read() & CTRL_ENABLE == 0 {
+	STATUS.write(WARMUP_COUNT);
+	INT_MASK.modify(|mask| mask | INT_OFF);
+	CTRL.modify(|ctrl| ctrl | CTRL_ENABLE);
+    }
+}
+
+/// Returns a random number from the hardware generator, enabling it first
+/// if necessary, or `None` if it does not produce one.
+pub fn next_u32() -> Option<u32> {
+    initialize();
+    for _ in 0..RNG_SPINS {
+	// the top byte counts the words available
+	if STATUS.read() >> 24 != 0 {
+	    return Some(DATA.read());
+	}
+    }
+    None
+}
@@ -46,6 +46,19 @@ impl Timer {
 	self.registers.COMPARE[1].write(next_tick);
 	self.registers.CS.write(0b0010);
     }
+
+    /// Sets up a match in timer 3 to occur `t` duration from now, clearing
+    /// an earlier one. Timer 1 is left to `tick_in()`.
+    pub fn wake_in(&mut self, t: Duration) {
+        let current_time = self.registers.CLO.read();
+        self.registers.CS.write(0b1000);
+        self.registers.COMPARE[3].write(current_time.wrapping_add(t.as_micros() as u32));
+    }
+
+    /// Clears a match in timer 3.
+    pub fn clear_wake(&mut self) {
+        self.registers.CS.write(0b1000);
+    }
 }
 
 /// Returns current time.
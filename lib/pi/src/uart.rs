@@ -99,6 +99,20 @@ impl MiniUart {
         }
     }
 
+    /// Returns the mini UART as an earlier `new()` set it up, without
+    /// touching its configuration or waiting for it to settle.
+    ///
+    /// # Safety
+    ///
+    /// `new()` must have been called before. Bytes written through several
+    /// handles at once interleave.
+    pub unsafe fn attach() -> MiniUart {
+        MiniUart {
+            registers: &mut *(MU_REG_BASE as *mut Registers),
+            timeout: None,
+        }
+    }
+
     /// Set the read timeout to `t` duration.
     pub fn set_read_timeout(&mut self, t: Duration) {
         self.timeout = Some(t)
@@ -111,12 +125,32 @@ impl MiniUart {
         self.registers.AUX_MU_IO_REG.write(byte);
     }
 
+    /// Writes the byte `byte` if there is space available in the output
+    /// FIFO and returns whether it did. This method does not block.
+    pub fn try_write_byte(&mut self, byte: u8) -> bool {
+        if !self.registers.AUX_MU_LSR_REG.has_mask(LsrStatus::TxAvailable as u8) {
+            return false;
+        }
+        self.registers.AUX_MU_IO_REG.write(byte);
+        true
+    }
+
     /// Returns `true` if there is at least one byte ready to be sent. If this
     /// return immediately. This method does not block.
     pub fn sent_byte(&self) -> bool {
         !self.registers.AUX_MU_LSR_REG.has_mask(LsrStatus::TxEmpty as u8)
     }
 
+    /// Raises the `Aux` interrupt while a byte is ready to be read, or stops
+    /// raising it.
+    pub fn set_read_interrupt(&mut self, enable: bool) {
+        // the datasheet swaps the receive and transmit bits; bit 0 is receive
+        match enable {
+            true => self.registers.AUX_MU_IER_REG.or_mask(0b1),
+            false => self.registers.AUX_MU_IER_REG.and_mask(!0b1),
+        }
+    }
+
     /// Returns `true` if there is at least one byte ready to be read. If this
     /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
     /// return immediately. This method does not block.
@@ -0,0 +1,134 @@
+//! The format of a dump of the kernel's trace buffer, shared by the kernel,
+//! which writes it, and `tracevis`, which reads it.
+//!
+//! A dump is a `Header` followed by the number of `Event`s it announces,
+//! oldest first, each encoded in `EVENT_SIZE` bytes. Integers are little
+//! endian. A dump sent over the serial line may be preceded by console
+//! output, so readers look for `MAGIC` before decoding the header.
+
+#![no_std]
+
+#[cfg(test)] mod tests;
+
+/// First bytes of every dump
+pub const MAGIC: [u8; 4] = *b"RTRC";
+
+/// Version of the format described here
+pub const VERSION: u16 = 1;
+
+/// Size of an encoded `Header`
+pub const HEADER_SIZE: usize = 16;
+
+/// Size of an encoded `Event`
+pub const EVENT_SIZE: usize = 16;
+
+/// Reasons a dump cannot be decoded.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// the dump does not start with `MAGIC`
+    BadMagic,
+    /// the dump is of a version this crate does not know
+    BadVersion(u16),
+    /// an event is of an unknown kind
+    BadKind(u8),
+}
+
+/// The start of a dump.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Header {
+    /// events following the header
+    pub events: u32,
+    /// events the kernel's exception handlers skipped because the buffer
+    /// was busy, since it was last cleared; the oldest events overwritten
+    /// once the buffer is full are not counted
+    pub dropped: u32,
+}
+
+impl Header {
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0; HEADER_SIZE];
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.events.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.dropped.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8; HEADER_SIZE]) -> Result<Header, Error> {
+        if buf[0..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let version = u16::from_le_bytes([buf[4], buf[5]]);
+        if version != VERSION {
+            return Err(Error::BadVersion(version));
+        }
+        Ok(Header {
+            events: u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]),
+            dropped: u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]),
+        })
+    }
+}
+
+/// What an event records. The meaning of `Event::arg` depends on it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    /// the core switched to thread `arg`
+    Switch = 1,
+    /// the core found no thread to run and waits for an interrupt
+    Idle = 2,
+    /// the core started handling interrupt number `arg`
+    IrqEnter = 3,
+    /// the core is done handling interrupt number `arg`
+    IrqExit = 4,
+    /// the running thread made system call number `arg`
+    SyscallEnter = 5,
+    /// system call number `arg` returned to the running thread
+    SyscallExit = 6,
+}
+
+impl Kind {
+    fn from_u8(value: u8) -> Result<Kind, Error> {
+        match value {
+            1 => Ok(Kind::Switch),
+            2 => Ok(Kind::Idle),
+            3 => Ok(Kind::IrqEnter),
+            4 => Ok(Kind::IrqExit),
+            5 => Ok(Kind::SyscallEnter),
+            6 => Ok(Kind::SyscallExit),
+            _ => Err(Error::BadKind(value)),
+        }
+    }
+}
+
+/// Something that happened on a core.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Event {
+    /// microseconds since boot
+    pub time: u64,
+    /// core it happened on
+    pub core: u8,
+    pub kind: Kind,
+    pub arg: u32,
+}
+
+impl Event {
+    pub fn encode(&self) -> [u8; EVENT_SIZE] {
+        let mut buf = [0; EVENT_SIZE];
+        buf[0..8].copy_from_slice(&self.time.to_le_bytes());
+        buf[8] = self.kind as u8;
+        buf[9] = self.core;
+        buf[12..16].copy_from_slice(&self.arg.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8; EVENT_SIZE]) -> Result<Event, Error> {
+        let mut time = [0; 8];
+        time.copy_from_slice(&buf[0..8]);
+        Ok(Event {
+            time: u64::from_le_bytes(time),
+            kind: Kind::from_u8(buf[8])?,
+            core: buf[9],
+            arg: u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]),
+        })
+    }
+}
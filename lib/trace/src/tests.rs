@@ -0,0 +1,29 @@
+use crate::*;
+
+#[test]
+fn header_round_trip() {
+    let header = Header { events: 4096, dropped: 17 };
+    assert_eq!(Header::decode(&header.encode()), Ok(header));
+}
+
+#[test]
+fn header_rejects_garbage() {
+    let mut buf = Header { events: 1, dropped: 0 }.encode();
+    buf[4] = 2;
+    assert_eq!(Header::decode(&buf), Err(Error::BadVersion(2)));
+    buf[0] = b'X';
+    assert_eq!(Header::decode(&buf), Err(Error::BadMagic));
+}
+
+#[test]
+fn event_round_trip() {
+    let event = Event { time: 0x1234_5678_9abc, core: 3, kind: Kind::IrqEnter, arg: 57 };
+    assert_eq!(Event::decode(&event.encode()), Ok(event));
+}
+
+#[test]
+fn event_rejects_unknown_kind() {
+    let mut buf = Event { time: 0, core: 0, kind: Kind::Idle, arg: 0 }.encode();
+    buf[8] = 0;
+    assert_eq!(Event::decode(&buf), Err(Error::BadKind(0)));
+}
@@ -0,0 +1,113 @@
+//! Chrome trace-event JSON, as loaded by `chrome://tracing` and Perfetto.
+//! Each core gets three tracks: the threads it ran, the interrupts it
+//! handled and the system calls it served, each a slice from the event that
+//! starts it to the one that ends it.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use trace::{Event, Header, Kind};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Track {
+    Threads = 0,
+    Irqs = 1,
+    Syscalls = 2,
+}
+
+impl Track {
+    fn name(self) -> &'static str {
+	match self {
+	    Track::Threads => "threads",
+	    Track::Irqs => "irqs",
+	    Track::Syscalls => "syscalls",
+	}
+    }
+}
+
+/// Returns the name of interrupt number `irq` of the BCM2837.
+fn irq_name(irq: u32) -> String {
+    match irq {
+	1 => "Timer1".to_string(),
+	3 => "Timer3".to_string(),
+	9 => "Usb".to_string(),
+	49..=52 => format!("Gpio{}", irq - 49),
+	57 => "Uart".to_string(),
+	_ => format!("irq {}", irq),
+    }
+}
+
+/// Writes the slices as complete (`X`) events, a comma before every one but
+/// the first.
+struct Slices<'a, W: Write> {
+    w: &'a mut W,
+    /// open slices with their start time and name, by core and track
+    open: BTreeMap<(u8, Track), (u64, String)>,
+    tracks: Vec<(u8, Track)>,
+    first: bool,
+}
+
+impl<'a, W: Write> Slices<'a, W> {
+    fn tid(core: u8, track: Track) -> u32 {
+	core as u32 * 3 + track as u32
+    }
+
+    fn write(&mut self, json: &str) -> io::Result<()> {
+	if !self.first {
+	    self.w.write_all(b",\n")?;
+	}
+	self.first = false;
+	self.w.write_all(json.as_bytes())
+    }
+
+    fn open(&mut self, core: u8, track: Track, time: u64, name: String) -> io::Result<()> {
+	self.close(core, track, time)?;
+	if !self.tracks.contains(&(core, track)) {
+	    self.tracks.push((core, track));
+	}
+	self.open.insert((core, track), (time, name));
+	Ok(())
+    }
+
+    fn close(&mut self, core: u8, track: Track, time: u64) -> io::Result<()> {
+	if let Some((start, name)) = self.open.remove(&(core, track)) {
+	    let json = format!(r#"{{"name":"{}","ph":"X","ts":{},"dur":{},"pid":0,"tid":{}}}"#,
+			       name, start, time.saturating_sub(start), Slices::<W>::tid(core, track));
+	    self.write(&json)?;
+	}
+	Ok(())
+    }
+}
+
+/// Writes `events`, oldest first, as a JSON object of trace events to `w`.
+/// Slices still open at the last event end there.
+pub fn export<W: Write>(header: &Header, events: &[Event], w: &mut W) -> io::Result<()> {
+    w.write_all(b"{\"traceEvents\":[\n")?;
+    let mut slices = Slices { w, open: BTreeMap::new(), tracks: Vec::new(), first: true };
+    for event in events {
+	let (core, time) = (event.core, event.time);
+	match event.kind {
+	    Kind::Switch => slices.open(core, Track::Threads, time, format!("thread {}", event.arg))?,
+	    Kind::Idle => slices.close(core, Track::Threads, time)?,
+	    Kind::IrqEnter => slices.open(core, Track::Irqs, time, irq_name(event.arg))?,
+	    Kind::IrqExit => slices.close(core, Track::Irqs, time)?,
+	    Kind::SyscallEnter => slices.open(core, Track::Syscalls, time, format!("syscall {}", event.arg))?,
+	    Kind::SyscallExit => slices.close(core, Track::Syscalls, time)?,
+	}
+    }
+
+    let end = events.last().map_or(0, |event| event.time);
+    let open: Vec<(u8, Track)> = slices.open.keys().cloned().collect();
+    for (core, track) in open {
+	slices.close(core, track, end)?;
+    }
+    slices.write(r#"{"name":"process_name","ph":"M","pid":0,"args":{"name":"rustOS"}}"#)?;
+    let mut tracks = slices.tracks.clone();
+    tracks.sort();
+    for (core, track) in tracks {
+	let json = format!(r#"{{"name":"thread_name","ph":"M","pid":0,"tid":{},"args":{{"name":"core {} {}"}}}}"#,
+			   Slices::<W>::tid(core, track), core, track.name());
+	slices.write(&json)?;
+    }
+    write!(slices.w, "\n],\"displayTimeUnit\":\"ms\",\"otherData\":{{\"dropped\":\"{}\"}}}}\n", header.dropped)
+}
@@ -0,0 +1,112 @@
+use structopt;
+use structopt_derive::StructOpt;
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+use serial::core::{CharSize, BaudRate, StopBits, FlowControl};
+
+use ttywrite::parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate};
+use ttywrite::tty::{self, SerialConfig};
+
+mod chrome;
+#[cfg(test)] mod tests;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "tracevis",
+            about = "Convert a dump of the rustOS trace buffer to Chrome trace-event JSON.")]
+struct Opt {
+    #[structopt(short = "i", help = "Dump written by `ktrace dump > FILE` (defaults to stdin if neither this nor --tty is set)",
+                parse(from_os_str))]
+    input: Option<PathBuf>,
+
+    #[structopt(long = "tty", help = "Ask the shell on this TTY for a dump and read it from there", parse(from_os_str))]
+    tty_path: Option<PathBuf>,
+
+    #[structopt(short = "o", help = "Output file (defaults to stdout if not set)", parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    #[structopt(short = "b", long = "baud", parse(try_from_str = "parse_baud_rate"),
+                help = "Set baud rate", default_value = "115200")]
+    baud_rate: BaudRate,
+
+    #[structopt(short = "t", long = "timeout", parse(try_from_str),
+                help = "Set timeout in seconds", default_value = "10")]
+    timeout: u64,
+
+    #[structopt(short = "w", long = "width", parse(try_from_str = "parse_width"),
+                help = "Set data character width in bits", default_value = "8")]
+    char_width: CharSize,
+
+    #[structopt(short = "f", long = "flow-control", parse(try_from_str = "parse_flow_control"),
+                help = "Enable flow control ('hardware' or 'software')", default_value = "none")]
+    flow_control: FlowControl,
+
+    #[structopt(short = "s", long = "stop-bits", parse(try_from_str = "parse_stop_bits"),
+                help = "Set number of stop bits", default_value = "1")]
+    stop_bits: StopBits,
+}
+
+fn invalid_data(e: trace::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
+}
+
+/// Reads a dump from `r`, skipping whatever comes before `trace::MAGIC`,
+/// such as the shell echoing the command that asked for it.
+fn read_dump<R: Read>(r: &mut R) -> io::Result<(trace::Header, Vec<trace::Event>)> {
+    let mut header = [0u8; trace::HEADER_SIZE];
+    let mut byte = [0u8; 1];
+    let mut matched = 0;
+    while matched < trace::MAGIC.len() {
+	r.read_exact(&mut byte)?;
+	matched = match byte[0] {
+	    b if b == trace::MAGIC[matched] => matched + 1,
+	    b if b == trace::MAGIC[0] => 1,
+	    _ => 0,
+	};
+    }
+    header[..matched].copy_from_slice(&trace::MAGIC);
+    r.read_exact(&mut header[matched..])?;
+    let header = trace::Header::decode(&header).map_err(invalid_data)?;
+
+    let mut events = Vec::with_capacity(header.events as usize);
+    let mut buf = [0u8; trace::EVENT_SIZE];
+    for _ in 0..header.events {
+	r.read_exact(&mut buf)?;
+	events.push(trace::Event::decode(&buf).map_err(invalid_data)?);
+    }
+    Ok((header, events))
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    let (header, events) = match (opt.tty_path, opt.input) {
+	(Some(tty_path), _) => {
+	    let config = SerialConfig {
+		baud_rate: opt.baud_rate,
+		timeout: opt.timeout,
+		char_width: opt.char_width,
+		flow_control: opt.flow_control,
+		stop_bits: opt.stop_bits,
+	    };
+	    let mut serial = tty::open(&tty_path, &config).expect("open and configure TTY device");
+	    serial.write_all(b"ktrace dump\r").expect("asking the shell for a dump");
+	    read_dump(&mut serial)
+	},
+	(None, Some(input)) => read_dump(&mut BufReader::new(File::open(input).expect("opening input file"))),
+	(None, None) => read_dump(&mut io::stdin().lock()),
+    }.expect("reading trace dump");
+
+    if header.dropped > 0 {
+	eprintln!("tracevis: the kernel dropped {} events", header.dropped);
+    }
+
+    let mut output: Box<dyn Write> = match opt.output {
+	Some(path) => Box::new(BufWriter::new(File::create(path).expect("creating output file"))),
+	None => Box::new(io::stdout()),
+    };
+    chrome::export(&header, &events, &mut output).expect("writing trace events");
+}
@@ -0,0 +1,59 @@
+use std::io::Cursor;
+
+use trace::{Event, Header, Kind};
+
+use crate::chrome;
+use crate::read_dump;
+
+fn event(time: u64, core: u8, kind: Kind, arg: u32) -> Event {
+    Event { time, core, kind, arg }
+}
+
+fn dump(header: &Header, events: &[Event], noise: &[u8]) -> Vec<u8> {
+    let mut bytes = noise.to_vec();
+    bytes.extend_from_slice(&header.encode());
+    for event in events {
+        bytes.extend_from_slice(&event.encode());
+    }
+    bytes
+}
+
+#[test]
+fn read_dump_skips_console_output() {
+    let events = [event(10, 0, Kind::Switch, 1), event(20, 0, Kind::Idle, 0)];
+    let header = Header { events: 2, dropped: 0 };
+    let bytes = dump(&header, &events, b"> ktrace dump\r\nRR");
+    let (read_header, read_events) = read_dump(&mut Cursor::new(bytes)).expect("valid dump");
+    assert_eq!(read_header, header);
+    assert_eq!(read_events, events);
+}
+
+#[test]
+fn read_dump_fails_on_truncated_dump() {
+    let events = [event(10, 0, Kind::Switch, 1)];
+    let mut bytes = dump(&Header { events: 2, dropped: 0 }, &events, b"");
+    bytes.pop();
+    assert!(read_dump(&mut Cursor::new(bytes)).is_err());
+}
+
+#[test]
+fn export_pairs_events_into_slices() {
+    let events = [
+        event(100, 0, Kind::Switch, 2),
+        event(110, 0, Kind::IrqEnter, 1),
+        event(115, 0, Kind::IrqExit, 1),
+        event(150, 1, Kind::Switch, 3),
+        event(200, 0, Kind::Switch, 4),
+        event(300, 1, Kind::Idle, 0),
+    ];
+    let mut out = Vec::new();
+    chrome::export(&Header { events: 6, dropped: 1 }, &events, &mut out).expect("writing to memory");
+    let json = String::from_utf8(out).expect("utf-8 JSON");
+    assert!(json.contains(r#"{"name":"Timer1","ph":"X","ts":110,"dur":5,"pid":0,"tid":1}"#));
+    assert!(json.contains(r#"{"name":"thread 2","ph":"X","ts":100,"dur":100,"pid":0,"tid":0}"#));
+    assert!(json.contains(r#"{"name":"thread 3","ph":"X","ts":150,"dur":150,"pid":0,"tid":3}"#));
+    // still running when the trace ends
+    assert!(json.contains(r#"{"name":"thread 4","ph":"X","ts":200,"dur":100,"pid":0,"tid":0}"#));
+    assert!(json.contains(r#""args":{"name":"core 1 threads"}"#));
+    assert!(json.ends_with("\"otherData\":{\"dropped\":\"1\"}}\n"));
+}
@@ -0,0 +1,169 @@
+use structopt;
+use structopt_derive::StructOpt;
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use structopt::StructOpt;
+use serial::core::{CharSize, BaudRate, StopBits, FlowControl};
+
+use ttywrite::parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate};
+use ttywrite::files::Session;
+use ttywrite::tty::{self, SerialConfig};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "rustos-dev", about = "Flash, reset, talk to, and copy files to and from a rustOS board over a TTY.")]
+struct Opt {
+    #[structopt(short = "b", long = "baud", parse(try_from_str = "parse_baud_rate"),
+                help = "Set baud rate", default_value = "115200")]
+    baud_rate: BaudRate,
+
+    #[structopt(short = "t", long = "timeout", parse(try_from_str),
+                help = "Set timeout in seconds", default_value = "10")]
+    timeout: u64,
+
+    #[structopt(short = "w", long = "width", parse(try_from_str = "parse_width"),
+                help = "Set data character width in bits", default_value = "8")]
+    char_width: CharSize,
+
+    #[structopt(help = "Path to TTY device", parse(from_os_str))]
+    tty_path: PathBuf,
+
+    #[structopt(short = "f", long = "flow-control", parse(try_from_str = "parse_flow_control"),
+                help = "Enable flow control ('hardware' or 'software')", default_value = "none")]
+    flow_control: FlowControl,
+
+    #[structopt(short = "s", long = "stop-bits", parse(try_from_str = "parse_stop_bits"),
+                help = "Set number of stop bits", default_value = "1")]
+    stop_bits: StopBits,
+
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    #[structopt(name = "flash", about = "Send a kernel image to the bootloader")]
+    Flash {
+        #[structopt(short = "i", help = "Input file (defaults to stdin if not set)", parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        #[structopt(short = "r", long = "raw", help = "Disable XMODEM")]
+        raw: bool,
+    },
+
+    #[structopt(name = "console", about = "Attach stdin/stdout to the TTY")]
+    Console,
+
+    #[structopt(name = "reset", about = "Reset the board by pulsing DTR")]
+    Reset,
+
+    #[structopt(name = "run", about = "Reset the board, flash an image, then attach the console")]
+    Run {
+        #[structopt(short = "i", help = "Input file (defaults to stdin if not set)", parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        #[structopt(short = "r", long = "raw", help = "Disable XMODEM")]
+        raw: bool,
+
+        #[structopt(long = "no-reset", help = "Don't pulse DTR before flashing")]
+        no_reset: bool,
+    },
+
+    #[structopt(name = "ls", about = "List a directory through the shell's file server")]
+    Ls {
+        #[structopt(help = "Directory on the board, relative to the shell's", default_value = ".")]
+        path: String,
+    },
+
+    #[structopt(name = "get", about = "Copy files from the board through the shell's file server")]
+    Get {
+        #[structopt(short = "d", help = "Local directory to copy into", default_value = ".", parse(from_os_str))]
+        dir: PathBuf,
+
+        #[structopt(help = "Files on the board, relative to the shell's directory")]
+        paths: Vec<String>,
+    },
+
+    #[structopt(name = "put", about = "Copy files to the board through the shell's file server")]
+    Put {
+        #[structopt(short = "d", help = "Directory on the board to copy into", default_value = ".")]
+        dir: String,
+
+        #[structopt(help = "Local files", parse(from_os_str))]
+        paths: Vec<PathBuf>,
+    },
+}
+
+/// Runs `transfer` in a file server session with the shell at the other end
+/// of `serial`, then gives the console back.
+fn files<F>(mut serial: serial::SystemPort, transfer: F) -> io::Result<()>
+    where F: FnOnce(&mut Session<&mut serial::SystemPort>) -> io::Result<()>
+{
+    tty::drain(&mut serial)?;
+    let mut session = Session::start(&mut serial)?;
+    transfer(&mut session)?;
+    session.quit()
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let config = SerialConfig {
+	baud_rate: opt.baud_rate,
+	timeout: opt.timeout,
+	char_width: opt.char_width,
+	flow_control: opt.flow_control,
+	stop_bits: opt.stop_bits,
+    };
+    let mut serial = tty::open(&opt.tty_path, &config).expect("open and configure TTY device");
+
+    match opt.cmd {
+	Command::Flash { input, raw } => {
+	    tty::transmit(input, &mut serial, raw).expect("writing input file");
+	},
+	Command::Console => {
+	    tty::console(serial).expect("attaching console");
+	},
+	Command::Reset => {
+	    tty::reset(&mut serial).expect("resetting board");
+	},
+	Command::Run { input, raw, no_reset } => {
+	    if !no_reset {
+		tty::reset(&mut serial).expect("resetting board");
+	    }
+	    tty::transmit(input, &mut serial, raw).expect("writing input file");
+	    tty::console(serial).expect("attaching console");
+	},
+	Command::Ls { path } => {
+	    files(serial, |session| {
+		print!("{}", session.list(&path)?);
+		Ok(())
+	    }).expect("listing directory");
+	},
+	Command::Get { dir, paths } => {
+	    files(serial, |session| {
+		for path in paths.iter() {
+		    let name = Path::new(path).file_name()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+		    let mut file = File::create(dir.join(name))?;
+		    let size = session.get(path, &mut file)?;
+		    println!("{}: {} bytes", path, size);
+		}
+		Ok(())
+	    }).expect("copying files from the board");
+	},
+	Command::Put { dir, paths } => {
+	    files(serial, |session| {
+		for path in paths.iter() {
+		    let name = path.file_name().and_then(|name| name.to_str())
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+		    let remote = format!("{}/{}", dir.trim_end_matches('/'), name);
+		    let size = session.put(&remote, &mut File::open(path)?)?;
+		    println!("{}: {} bytes", remote, size);
+		}
+		Ok(())
+	    }).expect("copying files to the board");
+	},
+    }
+}
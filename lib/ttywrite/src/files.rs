@@ -0,0 +1,98 @@
+use filexfer::{read_frame, write_frame, Op, ESCAPE, MAX_PAYLOAD};
+
+use std::io::{self, Read, Write};
+
+/// A session with the kernel's file server, which has the console until
+/// the session is dropped.
+pub struct Session<T: Read + Write> {
+    serial: T,
+    buf: Box<[u8; MAX_PAYLOAD]>,
+}
+
+impl<T: Read + Write> Session<T> {
+    /// Switches the shell on the other end of `serial` into file server
+    /// mode.
+    pub fn start(mut serial: T) -> io::Result<Session<T>> {
+	serial.write_all(&ESCAPE)?;
+	serial.flush()?;
+	Ok(Session { serial, buf: Box::new([0u8; MAX_PAYLOAD]) })
+    }
+
+    /// Returns the entries of the directory `path` as lines of `d NAME` or
+    /// `f SIZE NAME`.
+    pub fn list(&mut self, path: &str) -> io::Result<String> {
+	let mut lines = Vec::new();
+	self.request(Op::List, path)?;
+	self.receive(&mut lines)?;
+	String::from_utf8(lines).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "listing is not UTF-8"))
+    }
+
+    /// Copies the file `path` to `out`, returning its size.
+    pub fn get<W: Write>(&mut self, path: &str, out: &mut W) -> io::Result<u64> {
+	self.request(Op::Get, path)?;
+	self.receive(out)
+    }
+
+    /// Replaces the file `path`, creating it if needed, with what `input`
+    /// holds, returning its size.
+    pub fn put<R: Read>(&mut self, path: &str, input: &mut R) -> io::Result<u64> {
+	self.request(Op::Put, path)?;
+	self.expect_ok()?;
+	let mut size = 0;
+	loop {
+	    let read = input.read(&mut self.buf[..])?;
+	    if read == 0 {
+		break;
+	    }
+	    write_frame(&mut self.serial, Op::Data, &self.buf[..read])?;
+	    size += read as u64;
+	}
+	write_frame(&mut self.serial, Op::End, b"")?;
+	self.expect_ok()?;
+	Ok(size)
+    }
+
+    /// Ends the session, giving the console back to the shell.
+    pub fn quit(mut self) -> io::Result<()> {
+	write_frame(&mut self.serial, Op::Quit, b"")?;
+	self.expect_ok()
+    }
+
+    fn request(&mut self, op: Op, path: &str) -> io::Result<()> {
+	write_frame(&mut self.serial, op, path.as_bytes())
+    }
+
+    /// Reads a frame, turning an `Error` from the server into an error.
+    fn reply(&mut self) -> io::Result<(Op, usize)> {
+	match read_frame(&mut self.serial, &mut self.buf)? {
+	    (Op::Error, len) => {
+		let message = String::from_utf8_lossy(&self.buf[..len]).into_owned();
+		Err(io::Error::new(io::ErrorKind::Other, message))
+	    },
+	    frame => Ok(frame),
+	}
+    }
+
+    fn expect_ok(&mut self) -> io::Result<()> {
+	match self.reply()? {
+	    (Op::Ok, _) => Ok(()),
+	    (op, _) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected Ok, got {:?}", op))),
+	}
+    }
+
+    /// Writes `Data` frames to `out` up to `End`, returning how many bytes
+    /// they held.
+    fn receive<W: Write>(&mut self, out: &mut W) -> io::Result<u64> {
+	let mut size = 0;
+	loop {
+	    match self.reply()? {
+		(Op::Data, len) => {
+		    out.write_all(&self.buf[..len])?;
+		    size += len as u64;
+		},
+		(Op::End, _) => return Ok(size),
+		(op, _) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected Data, got {:?}", op))),
+	    }
+	}
+    }
+}
@@ -0,0 +1,3 @@
+pub mod files;
+pub mod parsers;
+pub mod tty;
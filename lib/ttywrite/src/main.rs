@@ -1,17 +1,13 @@
-mod parsers;
-
-use serial;
 use structopt;
 use structopt_derive::StructOpt;
-use xmodem::{Progress, Xmodem};
 
 use std::path::PathBuf;
-use std::time::Duration;
 
 use structopt::StructOpt;
-use serial::core::{CharSize, BaudRate, StopBits, FlowControl, SerialDevice, SerialPortSettings};
+use serial::core::{CharSize, BaudRate, StopBits, FlowControl};
 
-use parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate};
+use ttywrite::parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate};
+use ttywrite::tty::{self, SerialConfig};
 
 #[derive(StructOpt, Debug)]
 #[structopt(about = "Write to TTY using the XMODEM protocol by default.")]
@@ -46,44 +42,16 @@ struct Opt {
     raw: bool,
 }
 
-fn progress_fn(progress: Progress) {
-    println!("Progress: {:?}", progress);
-}
-
 fn main() {
-    use std::fs::File;
-    use std::io::{self, BufReader};
-
     let opt = Opt::from_args();
-    let mut serial = serial::open(&opt.tty_path).expect("path points to invalid TTY");
-    let mut settings = serial.read_settings().expect("get TTY device settings");
-
-    // configure TTY device
-    serial.set_timeout(Duration::from_secs(opt.timeout)).expect("set TTY timeout");
-    settings.set_baud_rate(opt.baud_rate).expect("set TTY baud rate");
-    settings.set_char_size(opt.char_width);
-    settings.set_flow_control(opt.flow_control);
-    settings.set_stop_bits(opt.stop_bits);    
-    serial.write_settings(&settings).expect("set TTY device settings");
-
-    match opt.input {
-	Some(f) => {
-	    let file = File::open(f).expect("open input file");
-	    let mut reader = BufReader::new(file);
-	    match opt.raw {
-		true => {io::copy(&mut reader, &mut serial).expect("writing input file as bit stream");},
-		false => {Xmodem::transmit_with_progress(reader, serial, progress_fn).expect("writing input file as XMODEM");},
-	    }
-	},
-	
-	None => {
-	    let stdin = io::stdin();
-	    let mut reader = BufReader::new(stdin);
-	    match opt.raw {
-		true => {io::copy(&mut reader, &mut serial).expect("writing input file as bit stream");},
-		false => {Xmodem::transmit_with_progress(reader, serial, progress_fn).expect("writing input file as XMODEM");},
-	    }
-	},
-    }
-
+    let config = SerialConfig {
+	baud_rate: opt.baud_rate,
+	timeout: opt.timeout,
+	char_width: opt.char_width,
+	flow_control: opt.flow_control,
+	stop_bits: opt.stop_bits,
+    };
+    let mut serial = tty::open(&opt.tty_path, &config).expect("path points to invalid TTY");
+
+    tty::transmit(opt.input, &mut serial, opt.raw).expect("writing input file");
 }
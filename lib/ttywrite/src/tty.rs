@@ -0,0 +1,117 @@
+use serial;
+use serial::core::{CharSize, BaudRate, StopBits, FlowControl, SerialDevice, SerialPortSettings};
+use xmodem::{Progress, Xmodem};
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Serial line settings shared by every tool talking to the Pi.
+#[derive(Debug, Clone)]
+pub struct SerialConfig {
+    pub baud_rate: BaudRate,
+    pub timeout: u64,
+    pub char_width: CharSize,
+    pub flow_control: FlowControl,
+    pub stop_bits: StopBits,
+}
+
+/// Opens the TTY at `path` and configures it according to `config`.
+pub fn open(path: &Path, config: &SerialConfig) -> serial::Result<serial::SystemPort> {
+    let mut serial = serial::open(path)?;
+    let mut settings = serial.read_settings()?;
+
+    serial.set_timeout(Duration::from_secs(config.timeout))?;
+    settings.set_baud_rate(config.baud_rate)?;
+    settings.set_char_size(config.char_width);
+    settings.set_flow_control(config.flow_control);
+    settings.set_stop_bits(config.stop_bits);
+    serial.write_settings(&settings)?;
+    Ok(serial)
+}
+
+pub fn progress_fn(progress: Progress) {
+    println!("Progress: {:?}", progress);
+}
+
+/// Writes `input` (stdin if `None`) to `serial`, using XMODEM unless `raw`.
+pub fn transmit<T: SerialDevice>(input: Option<PathBuf>, serial: &mut T, raw: bool) -> io::Result<()> {
+    let mut reader: Box<dyn Read> = match input {
+	Some(f) => Box::new(BufReader::new(File::open(f)?)),
+	None => Box::new(BufReader::new(io::stdin())),
+    };
+
+    match raw {
+	true => {io::copy(&mut reader, serial)?;},
+	false => {Xmodem::transmit_with_progress(reader, serial, progress_fn)?;},
+    }
+    Ok(())
+}
+
+/// Resets the board by pulsing DTR low. This requires DTR of the USB to TTL
+/// adapter to be wired to the RUN header of the Pi.
+pub fn reset<T: SerialDevice>(serial: &mut T) -> serial::Result<()> {
+    serial.set_dtr(false)?;
+    thread::sleep(Duration::from_millis(100));
+    serial.set_dtr(true)?;
+    thread::sleep(Duration::from_millis(100));
+    Ok(())
+}
+
+/// Throws away what `serial` has received so far, like the output of the
+/// shell, waiting until it has been quiet for a moment.
+pub fn drain<T: SerialDevice>(serial: &mut T) -> io::Result<()> {
+    let timeout = serial.timeout();
+    serial.set_timeout(Duration::from_millis(100))?;
+    let mut buf = [0u8; 256];
+    let result = loop {
+	match serial.read(&mut buf) {
+	    Ok(_) => continue,
+	    Err(ref e) if e.kind() == io::ErrorKind::TimedOut => break Ok(()),
+	    Err(e) => break Err(e),
+	}
+    };
+    serial.set_timeout(timeout)?;
+    result
+}
+
+/// Attaches stdin/stdout to `serial` until stdin is closed.
+pub fn console<T: SerialDevice + Send + 'static>(mut serial: T) -> io::Result<()> {
+    // short timeout so the reader releases the port for the writer
+    serial.set_timeout(Duration::from_millis(10))?;
+    let serial = Arc::new(Mutex::new(serial));
+
+    let reader = serial.clone();
+    thread::spawn(move || {
+	let mut buf = [0u8; 256];
+	let stdout = io::stdout();
+	loop {
+	    let read = reader.lock().unwrap().read(&mut buf);
+	    match read {
+		Ok(n) => {
+		    let mut out = stdout.lock();
+		    let _ = out.write_all(&buf[..n]);
+		    let _ = out.flush();
+		},
+		Err(ref e) if e.kind() == io::ErrorKind::TimedOut => thread::yield_now(),
+		Err(e) => {
+		    eprintln!("console: {}", e);
+		    return;
+		},
+	    }
+	}
+    });
+
+    let stdin = io::stdin();
+    let mut buf = [0u8; 256];
+    loop {
+	let n = stdin.lock().read(&mut buf)?;
+	if n == 0 {
+	    return Ok(());
+	}
+	serial.lock().unwrap().write_all(&buf[..n])?;
+    }
+}
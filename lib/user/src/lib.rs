@@ -0,0 +1,8 @@
+#![feature(asm)]
+#![no_std]
+
+//! Support library for user programs.
+
+pub mod perf;
+pub mod sync;
+pub mod time;
@@ -0,0 +1,14 @@
+//! Blocking synchronization primitives built on the kernel's futex calls.
+//!
+//! Each primitive keeps its state in a single `AtomicU32` futex word. The
+//! uncontended paths are a single atomic operation; system calls are only
+//! made when a thread has to sleep or another thread has to be woken.
+
+mod condvar;
+mod futex;
+mod mutex;
+mod rwlock;
+
+pub use self::condvar::Condvar;
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
@@ -0,0 +1,61 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::futex;
+use super::mutex::MutexGuard;
+
+/// A condition variable used together with a `Mutex`.
+///
+/// The futex word is a sequence number bumped by every notification. A
+/// waiter samples it while still holding the mutex, so a notification sent
+/// between unlocking and going to sleep changes the word and the sleep
+/// returns immediately.
+///
+/// Wake-ups may be spurious; wait in a loop or use `wait_while()`.
+#[derive(Debug, Default)]
+pub struct Condvar {
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar { seq: AtomicU32::new(0) }
+    }
+
+    /// Releases the lock held by `guard`, sleeps until notified, and
+    /// reacquires the lock.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        let seq = self.seq.load(Ordering::Relaxed);
+        drop(guard);
+
+        futex::wait(&self.seq, seq);
+
+        // other waiters may be woken by `notify_all` at the same time, so take
+        // the lock as contended to make sure its unlock wakes the next one
+        mutex.lock_contended();
+        MutexGuard::new(mutex)
+    }
+
+    /// Waits until `condition` returns `false`.
+    pub fn wait_while<'a, T: ?Sized, F>(&self, mut guard: MutexGuard<'a, T>, mut condition: F) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wakes one waiting thread.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        futex::wake_one(&self.seq);
+    }
+
+    /// Wakes all waiting threads.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        futex::wake_all(&self.seq);
+    }
+}
@@ -0,0 +1,19 @@
+use core::sync::atomic::AtomicU32;
+
+use kernel_api::syscall;
+
+/// Sleeps while `word` holds `expected`. May return spuriously, so callers
+/// re-check their condition in a loop.
+pub fn wait(word: &AtomicU32, expected: u32) {
+    let _ = syscall::futex_wait(word as *const AtomicU32 as *const u32, expected);
+}
+
+/// Wakes one thread sleeping on `word`.
+pub fn wake_one(word: &AtomicU32) {
+    let _ = syscall::futex_wake(word as *const AtomicU32 as *const u32, 1);
+}
+
+/// Wakes every thread sleeping on `word`.
+pub fn wake_all(word: &AtomicU32) {
+    let _ = syscall::futex_wake(word as *const AtomicU32 as *const u32, core::u32::MAX);
+}
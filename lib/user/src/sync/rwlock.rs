@@ -0,0 +1,193 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::futex;
+
+/// set while a writer holds the lock
+const WRITER: u32 = 1 << 31;
+/// set while threads may be sleeping on the lock
+const WAITING: u32 = 1 << 30;
+/// the number of readers holding the lock
+const READERS: u32 = WAITING - 1;
+
+/// A reader-writer lock that puts waiting threads to sleep.
+///
+/// The futex word holds the number of readers, a `WRITER` bit, and a
+/// `WAITING` bit. A thread that has to wait sets `WAITING` before sleeping;
+/// the release that leaves the lock free clears it and wakes every sleeper,
+/// which then compete for the lock again. Readers are preferred: a reader is
+/// admitted whenever no writer holds the lock.
+pub struct RwLock<T: ?Sized> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+/// Releases shared access when dropped.
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+/// Releases exclusive access when dropped.
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+unsafe impl<'a, T: ?Sized + Sync> Sync for RwLockReadGuard<'a, T> {}
+unsafe impl<'a, T: ?Sized + Sync> Sync for RwLockWriteGuard<'a, T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(val: T) -> RwLock<T> {
+        RwLock {
+            state: AtomicU32::new(0),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Acquires shared access, sleeping while a writer holds the lock.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & WRITER == 0 {
+                assert!(state & READERS != READERS, "too many readers");
+                if self.state.compare_and_swap(state, state + 1, Ordering::Acquire) == state {
+                    return RwLockReadGuard { lock: self };
+                }
+            }
+            else {
+                self.sleep(state);
+            }
+        }
+    }
+
+    /// Acquires exclusive access, sleeping while readers or a writer hold the
+    /// lock.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & (WRITER | READERS) == 0 {
+                if self.state.compare_and_swap(state, state | WRITER, Ordering::Acquire) == state {
+                    return RwLockWriteGuard { lock: self };
+                }
+            }
+            else {
+                self.sleep(state);
+            }
+        }
+    }
+
+    /// Acquires shared access if no writer holds the lock.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & WRITER != 0 || state & READERS == READERS {
+            return None;
+        }
+        match self.state.compare_and_swap(state, state + 1, Ordering::Acquire) == state {
+            true => Some(RwLockReadGuard { lock: self }),
+            false => None,
+        }
+    }
+
+    /// Acquires exclusive access if the lock is free.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & (WRITER | READERS) != 0 {
+            return None;
+        }
+        match self.state.compare_and_swap(state, state | WRITER, Ordering::Acquire) == state {
+            true => Some(RwLockWriteGuard { lock: self }),
+            false => None,
+        }
+    }
+
+    /// Marks the lock as waited on and sleeps until it changes from `state`.
+    fn sleep(&self, state: u32) {
+        let waiting = state | WAITING;
+        if state == waiting || self.state.compare_and_swap(state, waiting, Ordering::Relaxed) == state {
+            futex::wait(&self.state, waiting);
+        }
+    }
+
+    fn read_unlock(&self) {
+        let state = self.state.fetch_sub(1, Ordering::Release) - 1;
+        if state == WAITING {
+            self.wake();
+        }
+    }
+
+    fn write_unlock(&self) {
+        if self.state.swap(0, Ordering::Release) & WAITING != 0 {
+            futex::wake_all(&self.state);
+        }
+    }
+
+    /// Clears `WAITING` on a free lock and wakes all sleepers.
+    fn wake(&self) {
+        if self.state.compare_and_swap(WAITING, 0, Ordering::Relaxed) == WAITING {
+            futex::wake_all(&self.state);
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> RwLock<T> {
+        RwLock::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => f.debug_struct("RwLock").field("data", &&*guard).finish(),
+            None => f.debug_struct("RwLock").field("data", &"<locked>").finish(),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
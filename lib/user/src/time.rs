@@ -0,0 +1,35 @@
+//! Time since boot, read without entering the kernel.
+//!
+//! The kernel maps a read-only page at `VDSO_BASE` into every process that
+//! holds the frequency of the virtual counter and its value at boot. Reading
+//! `CNTVCT_EL0` from EL0 is allowed, so `now()` is a register read and some
+//! arithmetic instead of a `time()` system call.
+
+use core::time::Duration;
+
+use kernel_api::vdso::{VdsoData, VDSO_BASE};
+
+/// Returns the time since boot. Agrees with `kernel_api::syscall::time()`.
+pub fn now() -> Duration {
+    vdso().time_at(counter())
+}
+
+/// Returns the kernel data page shared with every process.
+fn vdso() -> &'static VdsoData {
+    unsafe { &*(VDSO_BASE as *const VdsoData) }
+}
+
+/// Reads the virtual counter. The `isb` keeps the read from being
+/// speculated ahead of earlier instructions.
+fn counter() -> u64 {
+    let count: u64;
+    unsafe {
+        asm!("isb
+              mrs $0, CNTVCT_EL0"
+             : "=r"(count)
+             :
+             : "memory"
+             : "volatile");
+    }
+    count
+}
@@ -13,7 +13,7 @@ use kernel_api::{print, println, OsResult};
 fn main() {
     let result = main_inner();
     if let Err(error) = result {
-        println!("Terminating with error: {:?}", error);
+        println!("Terminating with error: {}", error);
     }
 }
 
@@ -18,10 +18,11 @@ fn fib(n: u64) -> u64 {
 
 fn main() {
     let pid = getpid();
+    let n = cr0::args().nth(1).and_then(|arg| arg.parse().ok()).unwrap_or(40);
     let beg = time();
     println!("[{:02}] Started: {:?}", pid, beg);
     
-    let rtn = fib(40);
+    let rtn = fib(n);
     
     let end = time();
     println!("[{:02}] Ended: {:?}", pid, end);
@@ -1,6 +1,10 @@
+// a program uses only some of the accessors
+#![allow(dead_code)]
+
 use core::mem::zeroed;
 use core::panic::PanicInfo;
 use core::ptr::write_volatile;
+use core::{slice, str};
 
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
@@ -22,9 +26,55 @@ unsafe fn zeros_bss() {
     }
 }
 
+/// `argc`, `argv` and `envp` as the kernel passed them, set before `main`
+static mut ARGC: usize = 0;
+static mut ARGV: *const *const u8 = 0 as *const *const u8;
+static mut ENVP: *const *const u8 = 0 as *const *const u8;
+
+/// Returns the NUL-terminated string at `s`, which the kernel copied from a
+/// `&str`.
+unsafe fn c_str(s: *const u8) -> &'static str {
+    let mut len = 0;
+    while *s.add(len) != 0 {
+        len += 1;
+    }
+    str::from_utf8_unchecked(slice::from_raw_parts(s, len))
+}
+
+/// Returns the arguments the program was started with, its name first.
+pub fn args() -> impl Iterator<Item = &'static str> {
+    let (argc, argv) = unsafe { (ARGC, ARGV) };
+    (0..argc).map(move |i| unsafe { c_str(*argv.add(i)) })
+}
+
+/// Returns the environment of the program as `(name, value)` pairs.
+pub fn vars() -> impl Iterator<Item = (&'static str, &'static str)> {
+    let envp = unsafe { ENVP };
+    (0..)
+        .map(move |i| unsafe { if envp.is_null() { 0 as *const u8 } else { *envp.add(i) } })
+        .take_while(|s| !s.is_null())
+        .map(|s| {
+            let var = unsafe { c_str(s) };
+            match var.find('=') {
+                Some(at) => (&var[..at], &var[at + 1..]),
+                None => (var, ""),
+            }
+        })
+}
+
+/// Returns the value of the environment variable `name`, if it is set.
+pub fn var(name: &str) -> Option<&'static str> {
+    vars().find(|(var, _)| *var == name).map(|(_, value)| value)
+}
+
+/// The entry point. The kernel passes `argc`, `argv` and `envp`, which also
+/// sit at the top of the stack in the System V layout.
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
+pub unsafe extern "C" fn _start(argc: usize, argv: *const *const u8, envp: *const *const u8) -> ! {
     zeros_bss();
+    ARGC = argc;
+    ARGV = argv;
+    ENVP = envp;
     crate::main();
-    kernel_api::syscall::exit();
+    kernel_api::syscall::exit(0);
 }